@@ -0,0 +1,417 @@
+use crate::common_circuits::{grover_operator, phase_estimation};
+use crate::errors::CircuitError;
+use crate::{run_local, OpBuilder, Register, UnitaryBuilder};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+/// An estimate of the probability `a` that a prepared state is good, along with a confidence
+/// interval.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AmplitudeEstimate {
+    /// The estimated probability.
+    pub estimate: f64,
+    /// The lower end of the confidence interval.
+    pub lower: f64,
+    /// The upper end of the confidence interval.
+    pub upper: f64,
+}
+
+impl AmplitudeEstimate {
+    /// Check if `a` lies within the confidence interval.
+    pub fn contains(&self, a: f64) -> bool {
+        self.lower <= a && a <= self.upper
+    }
+}
+
+/// Estimate the probability that `state_prep` applied to `|0...0>` on `n` qubits is in a good
+/// state, as marked by `oracle` flipping its phase, using phase estimation of the Grover operator
+/// with `m` precision qubits. The most frequent of `shots` samples `y` gives the estimate
+/// `sin^2(pi y / 2^m)`, the interval is the standard bound
+/// `2 pi sqrt(a (1 - a)) / 2^m + pi^2 / 4^m` which holds with probability at least `8 / pi^2`.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::amplitude_estimation::canonical_amplitude_estimation;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// // Prepare |1> with probability 1/4.
+/// let estimate = canonical_amplitude_estimation(
+///     1,
+///     4,
+///     |b, r| Ok(b.ry(r, std::f64::consts::PI / 3.0)),
+///     |b, r| Ok(b.z(r)),
+///     100,
+/// )?;
+/// assert!(estimate.contains(0.25));
+/// # Ok(())
+/// # }
+/// ```
+pub fn canonical_amplitude_estimation<A, O>(
+    n: u64,
+    m: u64,
+    state_prep: A,
+    oracle: O,
+    shots: usize,
+) -> Result<AmplitudeEstimate, CircuitError>
+where
+    A: Fn(&mut dyn UnitaryBuilder, Register) -> Result<Register, CircuitError>,
+    O: Fn(&mut dyn UnitaryBuilder, Register) -> Result<Register, CircuitError>,
+{
+    let mut b = OpBuilder::new();
+    let precision = b.register(m)?;
+    let target = b.register(n)?;
+    let target = state_prep(&mut b, target)?;
+    let (precision, target) = phase_estimation(&mut b, precision, target, |b, r, power| {
+        (0..power).try_fold(r, |r, _| grover_operator(b, r, &state_prep, &oracle))
+    })?;
+    let indices = precision.indices.clone();
+    let r = b.merge(vec![precision, target])?;
+
+    let (state, _) = run_local::<f64>(&r)?;
+    let counts = state.sample_measurements(&indices, shots);
+    let y = most_frequent(&counts);
+
+    let scale = (1u64 << m) as f64;
+    let estimate = (PI * y as f64 / scale).sin().powi(2);
+    let error = 2.0 * PI * (estimate * (1.0 - estimate)).sqrt() / scale + (PI / scale).powi(2);
+    Ok(AmplitudeEstimate {
+        estimate,
+        lower: (estimate - error).max(0.0),
+        upper: (estimate + error).min(1.0),
+    })
+}
+
+/// Estimate the number of the `2^n` inputs marked by `oracle`, which should flip the phase of the
+/// marked states, with quantum counting: phase estimation with `precision` qubits of the Grover
+/// operator for the uniform superposition. The circuit is built in `b` and sampled once, and a
+/// measured `y` gives the estimate `2^n sin^2(pi y / 2^precision)`. For `M` marked inputs this is
+/// within `2 pi sqrt(M (2^n - M)) / 2^precision + pi^2 2^n / 4^precision` of `M` with probability
+/// at least `8 / pi^2`.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::amplitude_estimation::quantum_counting;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// // Mark the 4 of 8 inputs with the lowest bit set.
+/// let mut b = OpBuilder::new();
+/// let count = quantum_counting(&mut b, |b, r| phase_oracle(b, r, |x| x & 1 == 1), 3, 4)?;
+/// assert!((count - 4.0).abs() < 1e-10);
+/// # Ok(())
+/// # }
+/// ```
+pub fn quantum_counting<O>(
+    b: &mut OpBuilder,
+    oracle: O,
+    n: u64,
+    precision: u64,
+) -> Result<f64, CircuitError>
+where
+    O: Fn(&mut dyn UnitaryBuilder, Register) -> Result<Register, CircuitError>,
+{
+    let hadamard = |b: &mut dyn UnitaryBuilder, r: Register| Ok(b.hadamard(r));
+    let p = b.register(precision)?;
+    let target = b.register(n)?;
+    let target = b.hadamard(target);
+    let (p, target) = phase_estimation(b, p, target, |b, r, power| {
+        (0..power).try_fold(r, |r, _| grover_operator(b, r, hadamard, &oracle))
+    })?;
+    let (p, m) = b.measure(p);
+    let r = b.merge(vec![p, target])?;
+
+    let (_, measured) = run_local::<f64>(&r)?;
+    let y = match measured.get_measurement(&m) {
+        Some((y, _)) => y,
+        None => return CircuitError::make_str_err("Counting register was not measured."),
+    };
+    let scale = (1u64 << precision) as f64;
+    Ok((1u64 << n) as f64 * (PI * y as f64 / scale).sin().powi(2))
+}
+
+/// Estimate the probability that `state_prep` applied to `|0...0>` on `n` qubits is in a good
+/// state using iterative amplitude estimation (Grinko et al.), which avoids phase estimation
+/// entirely. Each round samples `shots` measurements of `Q^k A|0>` for a chosen number of Grover
+/// rounds `k`, with `is_good` classifying the measured values of the register, and narrows the
+/// interval on `theta` until the interval on `a` has a width of at most `2 epsilon`. The interval
+/// holds with probability at least `1 - alpha`.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::amplitude_estimation::iterative_amplitude_estimation;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// // Prepare |1> with probability 0.3.
+/// let theta = 0.3f64.sqrt().asin();
+/// let estimate = iterative_amplitude_estimation(
+///     1,
+///     |b, r| Ok(b.ry(r, 2.0 * theta)),
+///     |b, r| Ok(b.z(r)),
+///     |x| x == 1,
+///     0.01,
+///     0.001,
+///     100,
+/// )?;
+/// assert!((estimate.estimate - 0.3).abs() < 0.05);
+/// # Ok(())
+/// # }
+/// ```
+pub fn iterative_amplitude_estimation<A, O, G>(
+    n: u64,
+    state_prep: A,
+    oracle: O,
+    is_good: G,
+    epsilon: f64,
+    alpha: f64,
+    shots: usize,
+) -> Result<AmplitudeEstimate, CircuitError>
+where
+    A: Fn(&mut dyn UnitaryBuilder, Register) -> Result<Register, CircuitError>,
+    O: Fn(&mut dyn UnitaryBuilder, Register) -> Result<Register, CircuitError>,
+    G: Fn(u64) -> bool,
+{
+    if epsilon <= 0.0 || alpha <= 0.0 || alpha >= 1.0 || shots == 0 {
+        return CircuitError::make_str_err(
+            "Amplitude estimation requires epsilon > 0, 0 < alpha < 1, and shots > 0.",
+        );
+    }
+    // Maximum number of rounds, used to split alpha between the confidence intervals.
+    let rounds = (PI / (8.0 * epsilon)).log2().ceil().max(1.0);
+    let max_rounds = 1000;
+
+    let (mut theta_l, mut theta_u) = (0.0, PI / 2.0);
+    let (mut k, mut upper_half) = (0, true);
+    let (mut good, mut total) = (0usize, 0usize);
+    let mut round = 0;
+    while lower_upper(theta_l, theta_u).1 - lower_upper(theta_l, theta_u).0 > 2.0 * epsilon
+        && round < max_rounds
+    {
+        round += 1;
+        let (next_k, next_upper_half) = find_next_k(k, upper_half, theta_l, theta_u);
+        if next_k != k {
+            good = 0;
+            total = 0;
+        }
+        k = next_k;
+        upper_half = next_upper_half;
+
+        let mut b = OpBuilder::new();
+        let r = b.register(n)?;
+        let r = state_prep(&mut b, r)?;
+        let r = (0..k).try_fold(r, |r, _| grover_operator(&mut b, r, &state_prep, &oracle))?;
+        let (state, _) = run_local::<f64>(&r)?;
+        let counts = state.sample_measurements(&r.indices, shots);
+        good += counts
+            .iter()
+            .filter(|(x, _)| is_good(**x))
+            .map(|(_, c)| c)
+            .sum::<usize>();
+        total += shots;
+
+        // Chernoff-Hoeffding bound on the probability of measuring a good state.
+        let p = good as f64 / total as f64;
+        let p_error = ((2.0 * rounds / alpha).ln() / (2.0 * total as f64)).sqrt();
+        let p_min = (p - p_error).max(0.0);
+        let p_max = (p + p_error).min(1.0);
+
+        // The probability is (1 - cos(K theta)) / 2 for K = 4k + 2.
+        let scale = (4 * k + 2) as f64;
+        let (phi_min, phi_max) = if upper_half {
+            ((1.0 - 2.0 * p_min).acos(), (1.0 - 2.0 * p_max).acos())
+        } else {
+            (
+                2.0 * PI - (1.0 - 2.0 * p_max).acos(),
+                2.0 * PI - (1.0 - 2.0 * p_min).acos(),
+            )
+        };
+        // Use the middle of the interval to find its period, the ends may sit on a boundary.
+        let middle = scale * (theta_l + theta_u) / 2.0;
+        let offset = (middle / (2.0 * PI)).floor() * 2.0 * PI;
+        theta_l = (offset + phi_min) / scale;
+        theta_u = (offset + phi_max) / scale;
+    }
+
+    let (lower, upper) = lower_upper(theta_l, theta_u);
+    Ok(AmplitudeEstimate {
+        estimate: ((theta_l + theta_u) / 2.0).sin().powi(2),
+        lower,
+        upper,
+    })
+}
+
+fn most_frequent(counts: &HashMap<u64, usize>) -> u64 {
+    counts
+        .iter()
+        .max_by_key(|(x, c)| (**c, std::cmp::Reverse(**x)))
+        .map(|(x, _)| *x)
+        .unwrap_or(0)
+}
+
+fn lower_upper(theta_l: f64, theta_u: f64) -> (f64, f64) {
+    (theta_l.sin().powi(2), theta_u.sin().powi(2))
+}
+
+/// Find the largest `K = 4k + 2` (with at least double the current one) such that
+/// `[K theta_l, K theta_u]` lies entirely within one half of the circle, returning `k` and whether
+/// it was the upper half.
+fn find_next_k(k: u64, upper_half: bool, theta_l: f64, theta_u: f64) -> (u64, bool) {
+    let current = 4 * k + 2;
+    let width = theta_u - theta_l;
+    if width <= 0.0 {
+        return (k, upper_half);
+    }
+    let max_scale = (PI / width).floor() as u64;
+    if max_scale < 2 {
+        return (k, upper_half);
+    }
+    let mut scale = max_scale - (max_scale - 2) % 4;
+    while scale >= 2 * current {
+        let low = (scale as f64 * theta_l) % (2.0 * PI);
+        let high = (scale as f64 * theta_u) % (2.0 * PI);
+        if low <= PI && high <= PI && low <= high {
+            return ((scale - 2) / 4, true);
+        }
+        if low >= PI && high >= PI && low <= high {
+            return ((scale - 2) / 4, false);
+        }
+        scale -= 4;
+    }
+    (k, upper_half)
+}
+
+#[cfg(test)]
+mod amplitude_estimation_tests {
+    use super::*;
+    use crate::phase_oracle;
+
+    fn prep(
+        a: f64,
+    ) -> impl Fn(&mut dyn UnitaryBuilder, Register) -> Result<Register, CircuitError> {
+        let theta = a.sqrt().asin();
+        move |b, r| Ok(b.ry(r, 2.0 * theta))
+    }
+
+    #[test]
+    fn test_canonical_exact() -> Result<(), CircuitError> {
+        // a = sin^2(pi / 8) is exactly representable with 3 precision qubits.
+        let a = (PI / 8.0).sin().powi(2);
+        let estimate = canonical_amplitude_estimation(1, 3, prep(a), |b, r| Ok(b.z(r)), 50)?;
+        assert!((estimate.estimate - a).abs() < 1e-10);
+        assert!(estimate.contains(a));
+        Ok(())
+    }
+
+    #[test]
+    fn test_canonical_multi_qubit() -> Result<(), CircuitError> {
+        // Uniform superposition over 2 qubits, good state |11> has a = 1/4.
+        let estimate = canonical_amplitude_estimation(
+            2,
+            4,
+            |b, r| Ok(b.hadamard(r)),
+            |b, r| {
+                let (q, t) = b.split(r, &[0])?;
+                let (q, t) = b.cz(q, t.unwrap());
+                b.merge(vec![q, t])
+            },
+            100,
+        )?;
+        // theta = pi / 6 is closest to y = 3 or y = 13 out of 16.
+        assert!((estimate.estimate - (3.0 * PI / 16.0).sin().powi(2)).abs() < 1e-10);
+        assert!(estimate.contains(0.25));
+        Ok(())
+    }
+
+    #[test]
+    fn test_iterative() -> Result<(), CircuitError> {
+        for a in &[0.1, 0.42, 0.9] {
+            let estimate = iterative_amplitude_estimation(
+                1,
+                prep(*a),
+                |b, r| Ok(b.z(r)),
+                |x| x == 1,
+                0.005,
+                1e-6,
+                200,
+            )?;
+            assert!(estimate.contains(*a), "{:?} for {}", estimate, a);
+            assert!(estimate.upper - estimate.lower <= 0.01);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_iterative_period_boundary() -> Result<(), CircuitError> {
+        // Some of these seeds put a bound exactly on the edge of a period.
+        for seed in 40..50 {
+            let estimate = crate::rng::with_seed(seed, || {
+                iterative_amplitude_estimation(
+                    1,
+                    prep(0.9),
+                    |b, r| Ok(b.z(r)),
+                    |x| x == 1,
+                    0.005,
+                    1e-6,
+                    200,
+                )
+            })?;
+            assert!(estimate.contains(0.9), "{:?} for seed {}", estimate, seed);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_iterative_bad_arguments() {
+        let result = iterative_amplitude_estimation(
+            1,
+            prep(0.5),
+            |b, r| Ok(b.z(r)),
+            |x| x == 1,
+            0.0,
+            0.1,
+            10,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_quantum_counting_exact() -> Result<(), CircuitError> {
+        // Counts of 0, half and all of the inputs are exact with 2 precision qubits.
+        let mut b = OpBuilder::new();
+        let count = quantum_counting(&mut b, |b, r| phase_oracle(b, r, |_| false), 2, 2)?;
+        assert!(count.abs() < 1e-10);
+        let mut b = OpBuilder::new();
+        let count = quantum_counting(&mut b, |b, r| phase_oracle(b, r, |x| x >= 2), 2, 2)?;
+        assert!((count - 2.0).abs() < 1e-10);
+        let mut b = OpBuilder::new();
+        let count = quantum_counting(&mut b, |b, r| phase_oracle(b, r, |_| true), 2, 2)?;
+        assert!((count - 4.0).abs() < 1e-10);
+        Ok(())
+    }
+
+    #[test]
+    fn test_quantum_counting() -> Result<(), CircuitError> {
+        // 3 of 8 marked, with 5 precision qubits the bound is about 0.84.
+        let marked = |x: u64| x == 1 || x == 4 || x == 6;
+        let bound = 2.0 * PI * 15f64.sqrt() / 32.0 + PI * PI * 8.0 / 1024.0;
+        let within = (0..10)
+            .filter(|seed| {
+                let count = crate::rng::with_seed(*seed, || {
+                    let mut b = OpBuilder::new();
+                    quantum_counting(&mut b, |b, r| phase_oracle(b, r, marked), 3, 5)
+                })
+                .unwrap();
+                (count - 3.0).abs() <= bound
+            })
+            .count();
+        assert!(within >= 8, "{:?}", within);
+        Ok(())
+    }
+
+    #[test]
+    fn test_quantum_counting_bad_arguments() {
+        let mut b = OpBuilder::new();
+        assert!(quantum_counting(&mut b, |b, r| phase_oracle(b, r, |_| true), 2, 0).is_err());
+    }
+}
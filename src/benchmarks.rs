@@ -0,0 +1,325 @@
+use crate::density_state::DensityMatrixState;
+use crate::errors::CircuitError;
+use crate::noise::NoiseModel;
+use crate::pipeline::{run_local, run_with_noise, sample_from_probs, QuantumState};
+use crate::rng::{gaussian, random};
+use crate::state_ops::from_tuples;
+use crate::{Complex, OpBuilder, Register, UnitaryBuilder};
+use num::Zero;
+use std::collections::HashMap;
+
+/// A width passes when the mean heavy output probability is above this by two standard errors.
+const HEAVY_OUTPUT_THRESHOLD: f64 = 2.0 / 3.0;
+
+/// The heavy output probabilities of the model circuits of one width, from `quantum_volume`.
+#[derive(Debug, Clone)]
+pub struct WidthResult {
+    /// Number of qubits in the model circuits, which also have this many layers.
+    pub width: u64,
+    /// Probability of measuring a heavy output for each model circuit under the noise model.
+    pub heavy_output_probabilities: Vec<f64>,
+    /// Whether the mean heavy output probability was above two thirds with two sigma confidence.
+    pub passed: bool,
+}
+
+impl WidthResult {
+    /// Get the mean heavy output probability over the model circuits.
+    pub fn mean(&self) -> f64 {
+        let n = self.heavy_output_probabilities.len() as f64;
+        self.heavy_output_probabilities.iter().sum::<f64>() / n
+    }
+
+    /// Get the standard error of the mean, treating each model circuit as a bernoulli trial.
+    pub fn standard_error(&self) -> f64 {
+        let n = self.heavy_output_probabilities.len() as f64;
+        let mean = self.mean();
+        (mean * (1.0 - mean) / n).sqrt()
+    }
+}
+
+/// The results of `quantum_volume`.
+#[derive(Debug, Clone)]
+pub struct QuantumVolume {
+    /// `2^m` for the largest width `m` such that every width from 2 to `m` passed, or 1 if none did.
+    pub quantum_volume: u64,
+    /// The results for each width tried, in increasing order.
+    pub widths: Vec<WidthResult>,
+}
+
+/// Build a quantum volume model circuit on `r` with `depth` layers. Each layer applies a random
+/// permutation to the qubits and then a Haar random two qubit unitary (named `"SU(4)"`) to each
+/// consecutive pair, leaving one qubit idle if `r` has an odd number of qubits.
+///
+/// Random choices use the generator from `rng::with_seed` when there is one.
+pub fn model_circuit(b: &mut OpBuilder, r: Register, depth: u64) -> Result<Register, CircuitError> {
+    let n = r.n() as usize;
+    let mut qubits: Vec<Option<Register>> = b.split_all(r).into_iter().map(Some).collect();
+    for _ in 0..depth {
+        let mut order: Vec<usize> = (0..n).collect();
+        (1..n).rev().for_each(|i| {
+            let j = (random::<u64>() % (i as u64 + 1)) as usize;
+            order.swap(i, j);
+        });
+        for pair in order.chunks(2).filter(|pair| pair.len() == 2) {
+            let q = qubits[pair[0]].take().unwrap();
+            let r = qubits[pair[1]].take().unwrap();
+            let qr = b.merge(vec![q, r])?;
+            let qr = b.mat("SU(4)", qr, random_unitary(4))?;
+            let (q, r) = b.split(qr, &[0])?;
+            qubits[pair[0]] = Some(q);
+            qubits[pair[1]] = r;
+        }
+    }
+    b.merge(qubits.into_iter().map(Option::unwrap).collect())
+}
+
+/// Get the probability of measuring a heavy output of the circuit `r` when run under `noise`.
+/// The heavy outputs are those with a probability above the median in the noiseless circuit.
+/// Both probabilities are computed exactly, using a density matrix for the noisy circuit.
+pub fn heavy_output_probability(r: &Register, noise: &NoiseModel) -> Result<f64, CircuitError> {
+    let ideal = ideal_probabilities(r)?;
+    let mut sorted = ideal.clone();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mid = sorted.len() / 2;
+    let median = if sorted.len() & 1 == 1 {
+        sorted[mid]
+    } else {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    };
+    let noisy = noisy_probabilities(r, noise)?;
+    Ok(ideal
+        .iter()
+        .zip(noisy.iter())
+        .filter(|(p, _)| **p > median)
+        .map(|(_, p)| p)
+        .sum())
+}
+
+/// Get the probability of measuring each value of `r` at the end of the noiseless circuit, with
+/// `r.indices[0]` as the least significant bit.
+pub fn ideal_probabilities(r: &Register) -> Result<Vec<f64>, CircuitError> {
+    let (mut state, _) = run_local::<f64>(r)?;
+    Ok(state.stochastic_measure(&r.indices, 0.0))
+}
+
+/// Get the probability of measuring each value of `r` at the end of the circuit run under
+/// `noise`, as with `ideal_probabilities`. The circuit is run on a density matrix so the
+/// probabilities include every outcome of the noise.
+pub fn noisy_probabilities(r: &Register, noise: &NoiseModel) -> Result<Vec<f64>, CircuitError> {
+    let (mut state, _) = run_with_noise::<f64, DensityMatrixState<f64>>(r, noise)?;
+    Ok(state.stochastic_measure(&r.indices, 0.0))
+}
+
+/// Sample `shots` values of `r` from the circuit run under `noise`, giving the number of times
+/// each value was measured (as for `run_and_sample`). These stand in for samples from a device
+/// in `linear_xeb_fidelity`.
+pub fn noisy_samples(
+    r: &Register,
+    noise: &NoiseModel,
+    shots: usize,
+) -> Result<HashMap<u64, usize>, CircuitError> {
+    let probs = noisy_probabilities(r, noise)?;
+    Ok(sample_from_probs(&probs, shots))
+}
+
+/// Run the quantum volume benchmark under `noise`, for square model circuits with widths from 2
+/// up to `max_width` and `num_circuits` random circuits of each width. A width passes when the
+/// mean heavy output probability is above two thirds by two standard errors.
+///
+/// Random choices use the generator from `rng::with_seed` when there is one.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::benchmarks::quantum_volume;
+/// use qip::noise::NoiseModel;
+/// use qip::rng::with_seed;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// // Noiseless circuits have a heavy output probability of around 0.85.
+/// let result = with_seed(1, || quantum_volume(3, 100, &NoiseModel::new()))?;
+/// assert_eq!(result.quantum_volume, 8);
+///
+/// // While fully depolarizing every qubit leaves a probability of one half.
+/// let mut noise = NoiseModel::new();
+/// noise.set_default_probability(1.0)?;
+/// let result = with_seed(1, || quantum_volume(3, 100, &noise))?;
+/// assert_eq!(result.quantum_volume, 1);
+/// # Ok(())
+/// # }
+/// ```
+pub fn quantum_volume(
+    max_width: u64,
+    num_circuits: usize,
+    noise: &NoiseModel,
+) -> Result<QuantumVolume, CircuitError> {
+    if max_width < 2 {
+        let message = format!(
+            "Quantum volume needs a width of at least 2, found {:?}",
+            max_width
+        );
+        return CircuitError::make_err(message);
+    }
+    if num_circuits == 0 {
+        return CircuitError::make_str_err("Quantum volume needs at least one model circuit.");
+    }
+    let widths = (2..=max_width)
+        .map(|width| {
+            let heavy_output_probabilities = (0..num_circuits)
+                .map(|_| {
+                    let mut b = OpBuilder::new();
+                    let r = b.register(width)?;
+                    let r = model_circuit(&mut b, r, width)?;
+                    heavy_output_probability(&r, noise)
+                })
+                .collect::<Result<Vec<_>, CircuitError>>()?;
+            let mut result = WidthResult {
+                width,
+                heavy_output_probabilities,
+                passed: false,
+            };
+            result.passed = result.mean() - 2.0 * result.standard_error() > HEAVY_OUTPUT_THRESHOLD;
+            Ok(result)
+        })
+        .collect::<Result<Vec<_>, CircuitError>>()?;
+    let achieved = widths
+        .iter()
+        .take_while(|w| w.passed)
+        .last()
+        .map(|w| w.width)
+        .unwrap_or(0);
+    Ok(QuantumVolume {
+        quantum_volume: 1 << achieved,
+        widths,
+    })
+}
+
+/// Build a random circuit for cross entropy benchmarking on `r` with `depth` cycles. Each cycle
+/// applies one of `sqrt(X)`, `sqrt(Y)` and `sqrt(W)` (with `W = (X + Y) / sqrt(2)`) to every
+/// qubit, never repeating the previous gate on a qubit, followed by CZ gates between neighbouring
+/// qubits of `r`, alternating between pairs starting at even and odd positions.
+///
+/// Random choices use the generator from `rng::with_seed` when there is one.
+pub fn xeb_circuit(b: &mut OpBuilder, r: Register, depth: u64) -> Result<Register, CircuitError> {
+    let mut qubits: Vec<Option<Register>> = b.split_all(r).into_iter().map(Some).collect();
+    let mut previous: Vec<Option<u64>> = vec![None; qubits.len()];
+    for cycle in 0..depth {
+        for (q, previous) in qubits.iter_mut().zip(previous.iter_mut()) {
+            // Pick uniformly from the gates other than the previous one.
+            let gate = match *previous {
+                None => random::<u64>() % 3,
+                Some(p) => (p + 1 + random::<u64>() % 2) % 3,
+            };
+            *previous = Some(gate);
+            let (name, mat) = sqrt_pauli(gate);
+            *q = Some(b.mat(name, q.take().unwrap(), mat)?);
+        }
+        let start = (cycle & 1) as usize;
+        for i in (start..qubits.len().saturating_sub(1)).step_by(2) {
+            let q = qubits[i].take().unwrap();
+            let r = qubits[i + 1].take().unwrap();
+            let (q, r) = b.cz(q, r);
+            qubits[i] = Some(q);
+            qubits[i + 1] = Some(r);
+        }
+    }
+    b.merge(qubits.into_iter().map(Option::unwrap).collect())
+}
+
+/// Estimate the fidelity of the circuit which produced `samples` with the linear cross entropy
+/// benchmark `2^n <p(x)> - 1`, where `p(x)` is the ideal probability of each sampled value `x`
+/// given by `ideal` (from `ideal_probabilities`) and the mean is over samples. This is near 1 for
+/// samples from the ideal random circuit and near 0 for uniformly random samples.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::benchmarks::{ideal_probabilities, linear_xeb_fidelity, noisy_samples, xeb_circuit};
+/// use qip::noise::NoiseModel;
+/// use qip::rng::with_seed;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let r = b.register(5)?;
+/// let r = with_seed(1, || xeb_circuit(&mut b, r, 10))?;
+/// let ideal = ideal_probabilities(&r)?;
+///
+/// let samples = with_seed(2, || run_and_sample::<f64>(&r, 5000))?;
+/// let fidelity = linear_xeb_fidelity(&ideal, &samples)?;
+/// assert!((fidelity - 1.0).abs() < 0.2);
+///
+/// let mut noise = NoiseModel::new();
+/// noise.set_default_probability(0.05)?;
+/// let samples = with_seed(2, || noisy_samples(&r, &noise, 5000))?;
+/// assert!(linear_xeb_fidelity(&ideal, &samples)? < fidelity);
+/// # Ok(())
+/// # }
+/// ```
+pub fn linear_xeb_fidelity(
+    ideal: &[f64],
+    samples: &HashMap<u64, usize>,
+) -> Result<f64, CircuitError> {
+    let total: usize = samples.values().sum();
+    if total == 0 {
+        return CircuitError::make_str_err("Cross entropy benchmarking needs at least one sample.");
+    }
+    let sum = samples
+        .iter()
+        .try_fold(0.0, |acc, (x, count)| match ideal.get(*x as usize) {
+            Some(p) => Ok(acc + p * *count as f64),
+            None => CircuitError::make_err(format!(
+                "Sampled value {:?} is outside of the {:?} ideal probabilities",
+                x,
+                ideal.len()
+            )),
+        })?;
+    Ok(ideal.len() as f64 * sum / total as f64 - 1.0)
+}
+
+/// Get the name and matrix of the square root of X, Y or W for `gate` 0, 1 or 2.
+fn sqrt_pauli(gate: u64) -> (&'static str, Vec<Complex<f64>>) {
+    let h = std::f64::consts::FRAC_1_SQRT_2;
+    // sqrt(P) = ((1 + i) I + (1 - i) P) / 2
+    match gate {
+        0 => (
+            "sqrt(X)",
+            from_tuples(&[(0.5, 0.5), (0.5, -0.5), (0.5, -0.5), (0.5, 0.5)]),
+        ),
+        1 => (
+            "sqrt(Y)",
+            from_tuples(&[(0.5, 0.5), (-0.5, -0.5), (0.5, 0.5), (0.5, 0.5)]),
+        ),
+        _ => (
+            "sqrt(W)",
+            from_tuples(&[(0.5, 0.5), (0.0, -h), (h, 0.0), (0.5, 0.5)]),
+        ),
+    }
+}
+
+/// Draw a Haar random `n` by `n` unitary, in row major order, by orthonormalizing the columns
+/// of a matrix of complex gaussian entries.
+fn random_unitary(n: usize) -> Vec<Complex<f64>> {
+    let mut columns: Vec<Vec<Complex<f64>>> = vec![];
+    while columns.len() < n {
+        let mut v: Vec<Complex<f64>> = (0..n)
+            .map(|_| Complex::new(gaussian(), gaussian()))
+            .collect();
+        columns.iter().for_each(|u| {
+            let overlap = u
+                .iter()
+                .zip(v.iter())
+                .fold(Complex::zero(), |acc: Complex<f64>, (a, b)| {
+                    acc + a.conj() * b
+                });
+            v.iter_mut()
+                .zip(u.iter())
+                .for_each(|(b, a)| *b -= overlap * a);
+        });
+        let norm = v.iter().map(|c| c.norm_sqr()).sum::<f64>().sqrt();
+        // A nearly dependent vector loses too much precision, try again.
+        if norm > 1e-6 {
+            columns.push(v.into_iter().map(|c| c / norm).collect());
+        }
+    }
+    (0..n * n).map(|i| columns[i % n][i / n]).collect()
+}
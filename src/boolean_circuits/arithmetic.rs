@@ -335,6 +335,364 @@ pub fn exp_mod(
 }
 wrap_and_invert!(pub exp_mod_op, pub exp_mod_inv, (exp_mod), ra, rb, rm, rp, re);
 
+/// Add together ra and rb using a temporary carry register, result is in rb.
+/// Maps `|a>|b>` to `|a>|(a + b) mod 2^(n+1)>` where ra has n qubits and rb has n+1 qubits.
+pub fn ripple_carry_add(
+    b: &mut dyn UnitaryBuilder,
+    ra: Register,
+    rb: Register,
+) -> Result<(Register, Register), CircuitError> {
+    if ra.n() + 1 != rb.n() {
+        CircuitError::make_err(format!(
+            "Expected ra[n] and rb[n+1], but got ({},{})",
+            ra.n(),
+            rb.n()
+        ))
+    } else {
+        b.push_name_scope("ripple_carry_add");
+        let rc = b.get_temp_register(ra.n(), false);
+        let result = add(b, rc, ra, rb);
+        b.pop_name_scope();
+        let (rc, ra, rb) = result?;
+        b.return_temp_register(rc, false);
+        Ok((ra, rb))
+    }
+}
+wrap_and_invert!(pub ripple_carry_add_op, pub ripple_carry_add_inv, (ripple_carry_add), ra, rb);
+
+/// Add together ra and rb with carries computed in logarithmic depth by a Kogge-Stone prefix tree,
+/// xoring the sum into rs. Maps `|a>|b>|s>` to `|a>|b>|s ^ (a + b)>` where ra and rb have n qubits
+/// and rs has n+1 qubits, all temporary qubits are returned to `|0>`.
+pub fn carry_lookahead_add(
+    b: &mut dyn UnitaryBuilder,
+    ra: Register,
+    rb: Register,
+    rs: Register,
+) -> Result<(Register, Register, Register), CircuitError> {
+    let n = ra.n() as usize;
+    if rb.n() != ra.n() || rs.n() != ra.n() + 1 {
+        return CircuitError::make_err(format!(
+            "Expected ra[n] rb[n] and rs[n+1], but got ({},{},{})",
+            ra.n(),
+            rb.n(),
+            rs.n()
+        ));
+    }
+    b.push_name_scope("carry_lookahead_add");
+    // Qubit layout: a, b, s, then temporaries g (generate), p (propagate), and the propagate
+    // values for each larger block size.
+    let (a_off, b_off, s_off, g_off, p_off) = (0, n, 2 * n, 3 * n + 1, 4 * n + 1);
+    let mut next_temp = 5 * n + 1;
+    let mut gates: Vec<(Vec<usize>, usize)> = vec![];
+    (0..n).for_each(|i| {
+        gates.push((vec![a_off + i, b_off + i], g_off + i));
+        gates.push((vec![a_off + i], p_off + i));
+        gates.push((vec![b_off + i], p_off + i));
+    });
+    // After the steps with offset dist, g[i] holds the generate for the bits (i - 2 dist, i].
+    let mut props: Vec<usize> = (0..n).map(|i| p_off + i).collect();
+    let mut dist = 1;
+    while dist < n {
+        (dist..n).rev().for_each(|i| {
+            gates.push((vec![props[i], g_off + i - dist], g_off + i));
+        });
+        if 2 * dist < n {
+            let mut next_props = props.clone();
+            (2 * dist..n).for_each(|i| {
+                gates.push((vec![props[i], props[i - dist]], next_temp));
+                next_props[i] = next_temp;
+                next_temp += 1;
+            });
+            props = next_props;
+        }
+        dist *= 2;
+    }
+
+    let temps = b.get_temp_register((next_temp - g_off) as u64, false);
+    let mut qs: Vec<Option<Register>> = b
+        .split_all(ra)
+        .into_iter()
+        .chain(b.split_all(rb))
+        .chain(b.split_all(rs))
+        .chain(b.split_all(temps))
+        .map(Some)
+        .collect();
+
+    let result = gates
+        .iter()
+        .try_for_each(|(cs, t)| apply_controlled_not(b, &mut qs, cs, *t))
+        .and_then(|_| {
+            // The sum bit i is p[i] ^ c[i], where the carry c[i] is the generate of (0, i - 1].
+            (0..n).try_for_each(|i| apply_controlled_not(b, &mut qs, &[p_off + i], s_off + i))?;
+            (1..=n).try_for_each(|i| apply_controlled_not(b, &mut qs, &[g_off + i - 1], s_off + i))
+        })
+        .and_then(|_| {
+            gates
+                .iter()
+                .rev()
+                .try_for_each(|(cs, t)| apply_controlled_not(b, &mut qs, cs, *t))
+        })
+        .and_then(|_| {
+            let mut qs = qs.into_iter().map(|q| q.unwrap());
+            let ra = b.merge(qs.by_ref().take(n).collect())?;
+            let rb = b.merge(qs.by_ref().take(n).collect())?;
+            let rs = b.merge(qs.by_ref().take(n + 1).collect())?;
+            let temps = b.merge(qs.collect())?;
+            b.return_temp_register(temps, false);
+            Ok((ra, rb, rs))
+        });
+    b.pop_name_scope();
+    result
+}
+wrap_and_invert!(pub carry_lookahead_add_op, pub carry_lookahead_add_inv, (carry_lookahead_add), ra, rb, rs);
+
+/// Apply a not to `qs[target]` controlled on all of `qs[controls]`.
+fn apply_controlled_not(
+    b: &mut dyn UnitaryBuilder,
+    qs: &mut [Option<Register>],
+    controls: &[usize],
+    target: usize,
+) -> Result<(), CircuitError> {
+    let cr = b.merge(controls.iter().map(|i| qs[*i].take().unwrap()).collect())?;
+    let (cr, t) = b.cnot(cr, qs[target].take().unwrap());
+    qs[target] = Some(t);
+    b.split_all(cr)
+        .into_iter()
+        .zip(controls.iter())
+        .for_each(|(q, i)| qs[*i] = Some(q));
+    Ok(())
+}
+
+/// Add a constant to r, mapping `|x>` to `|(x + c) mod 2^n>` for n qubits.
+pub fn add_constant(
+    b: &mut dyn UnitaryBuilder,
+    r: Register,
+    c: u64,
+) -> Result<Register, CircuitError> {
+    b.push_name_scope(&format!("add_{}", c));
+    let n = r.n();
+    let result = if n == 1 {
+        Ok(if c & 1 == 1 { b.not(r) } else { r })
+    } else {
+        // Adding 2^(n-1) only flips the highest bit, add the rest with a temporary register.
+        let low = c & ((1 << (n - 1)) - 1);
+        let rc = b.get_temp_register(n - 1, false);
+        let rc = xor_constant(b, rc, low)?;
+        let (rc, r) = ripple_carry_add(b, rc, r)?;
+        let rc = xor_constant(b, rc, low)?;
+        // Merge back so that the ops resetting rc are in the history of r.
+        let r = b.merge(vec![r, rc])?;
+        let (r, rc) = b.split(r, &(0..n).collect::<Vec<_>>())?;
+        b.return_temp_register(rc.unwrap(), false);
+        xor_constant(b, r, (c >> (n - 1) & 1) << (n - 1))
+    };
+    b.pop_name_scope();
+    result
+}
+
+/// Apply a not to each qubit of r for which the corresponding bit of c is set.
+fn xor_constant(b: &mut dyn UnitaryBuilder, r: Register, c: u64) -> Result<Register, CircuitError> {
+    let qs: Vec<Register> = b
+        .split_all(r)
+        .into_iter()
+        .enumerate()
+        .map(|(i, q)| if (c >> i) & 1 == 1 { b.not(q) } else { q })
+        .collect();
+    b.merge(qs)
+}
+
+/// Maps `|x>` to `|(x + c) mod m>` for `x < m`. r has n+1 qubits where `m <= 2^n`, the highest
+/// qubit is used as scratch space and must be `|0>`.
+pub fn mod_add_const(
+    b: &mut dyn UnitaryBuilder,
+    r: Register,
+    c: u64,
+    m: u64,
+) -> Result<Register, CircuitError> {
+    let n = r.n();
+    if n < 2 || m == 0 || m > 1 << (n - 1) {
+        return CircuitError::make_err(format!(
+            "Expected 0 < m <= 2^(r.n - 1) but found m={} with r.n={}",
+            m, n
+        ));
+    }
+    b.push_name_scope(&format!("add_{}_mod_{}", c % m, m));
+    let c = c % m;
+    let neg = |v: u64| ((1u64 << n) - v) & ((1u64 << n) - 1);
+    let t = b.get_temp_register(1, false);
+    let result = add_constant(b, r, c)
+        .and_then(|r| add_constant(b, r, neg(m)))
+        .and_then(|r| {
+            // The highest bit is set if x + c < m, in which case m is added back.
+            let (t, r) = cnot_from_highest(b, r, t)?;
+            let (t, r) = try_condition(b, t, r, |b, r| add_constant(b, r, m))?;
+            // Now t is set exactly when the result is at least c, uncompute it.
+            let r = add_constant(b, r, neg(c))?;
+            let t = b.not(t);
+            let (t, r) = cnot_from_highest(b, r, t)?;
+            let r = add_constant(b, r, c)?;
+            b.return_temp_register(t, false);
+            Ok(r)
+        });
+    b.pop_name_scope();
+    result
+}
+
+/// Apply a not to t controlled on the highest qubit of r.
+fn cnot_from_highest(
+    b: &mut dyn UnitaryBuilder,
+    r: Register,
+    t: Register,
+) -> Result<(Register, Register), CircuitError> {
+    let n = r.n();
+    let (top, rest) = b.split(r, &[n - 1])?;
+    let (top, t) = b.cnot(top, t);
+    let r = b.merge(vec![rest.unwrap(), top])?;
+    Ok((t, r))
+}
+
+/// Maps `|x>|y>` to `|x>|(y + a x) mod m>` for `y < m`. ry has n+1 qubits where `m <= 2^n`, the
+/// highest qubit is used as scratch space and must be `|0>`.
+pub fn mod_mul_add_const(
+    b: &mut dyn UnitaryBuilder,
+    rx: Register,
+    ry: Register,
+    a: u64,
+    m: u64,
+) -> Result<(Register, Register), CircuitError> {
+    b.push_name_scope(&format!("mul_add_{}_mod_{}", a, m));
+    let result = b
+        .split_all(rx)
+        .into_iter()
+        .enumerate()
+        .try_fold((vec![], ry), |(mut xs, ry), (i, x)| {
+            let c = mul_mod(a, pow_mod(2, i as u64, m), m);
+            let (x, ry) = try_condition(b, x, ry, |b, ry| mod_add_const(b, ry, c, m))?;
+            xs.push(x);
+            Ok((xs, ry))
+        })
+        .and_then(|(xs, ry)| Ok((b.merge(xs)?, ry)));
+    b.pop_name_scope();
+    result
+}
+
+/// Maps `|x>` to `|a x mod m>` for `x < m`, using a temporary register of n+1 qubits. r has n
+/// qubits where `m <= 2^n` and a must be coprime to m so that the map is reversible.
+///
+/// This can be given to `phase_estimation` as `U^power` by multiplying by `a^power mod m`, which
+/// is the core of Shor's algorithm.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::boolean_circuits::arithmetic::mod_mul_const;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let r = b.register(3)?;
+/// let handle = r.handle();
+/// let r = mod_mul_const(&mut b, r, 3, 7)?;
+/// let (r, m) = b.measure(r);
+///
+/// let (_, measured) = run_local_with_init::<f64>(&r, &[handle.make_init_from_index(4)?])?;
+/// assert_eq!(measured.get_measurement(&m).unwrap().0, (3 * 4) % 7);
+/// # Ok(())
+/// # }
+/// ```
+pub fn mod_mul_const(
+    b: &mut dyn UnitaryBuilder,
+    r: Register,
+    a: u64,
+    m: u64,
+) -> Result<Register, CircuitError> {
+    let n = r.n();
+    if m == 0 || m > 1 << n {
+        return CircuitError::make_err(format!(
+            "Expected 0 < m <= 2^r.n but found m={} with r.n={}",
+            m, n
+        ));
+    }
+    let a_inv = match mod_inverse(a, m) {
+        Some(a_inv) => a_inv,
+        None => {
+            return CircuitError::make_err(format!("Expected a={} to be coprime to m={}", a, m));
+        }
+    };
+    b.push_name_scope(&format!("mul_{}_mod_{}", a % m, m));
+    let ry = b.get_temp_register(n + 1, false);
+    // |x>|0> -> |x>|ax> -> |ax>|x> -> |ax>|x - a^-1 ax> = |ax>|0>
+    let result = mod_mul_add_const(b, r, ry, a, m).and_then(|(r, ry)| {
+        let (ry, top) = b.split(ry, &(0..n).collect::<Vec<_>>())?;
+        let (r, ry) = b.swap(r, ry)?;
+        let ry = b.merge(vec![ry, top.unwrap()])?;
+        let (r, ry) = mod_mul_add_const(b, r, ry, m - a_inv, m)?;
+        b.return_temp_register(ry, false);
+        Ok(r)
+    });
+    b.pop_name_scope();
+    result
+}
+
+/// Maps `|e>|x>` to `|e>|x a^e mod m>` for `x < m` by applying `mod_mul_const` with `a^(2^j)`
+/// conditioned on each qubit j of re. rx has n qubits where `m <= 2^n` and a must be coprime to m.
+pub fn mod_exp(
+    b: &mut dyn UnitaryBuilder,
+    re: Register,
+    rx: Register,
+    a: u64,
+    m: u64,
+) -> Result<(Register, Register), CircuitError> {
+    b.push_name_scope(&format!("exp_{}_mod_{}", a, m));
+    let result = b
+        .split_all(re)
+        .into_iter()
+        .enumerate()
+        .try_fold((vec![], rx), |(mut es, rx), (j, e)| {
+            let power = pow_mod(a, 1 << j, m);
+            let (e, rx) = try_condition(b, e, rx, |b, rx| mod_mul_const(b, rx, power, m))?;
+            es.push(e);
+            Ok((es, rx))
+        })
+        .and_then(|(es, rx)| Ok((b.merge(es)?, rx)));
+    b.pop_name_scope();
+    result
+}
+
+fn mul_mod(a: u64, b: u64, m: u64) -> u64 {
+    ((a as u128 * b as u128) % m as u128) as u64
+}
+
+pub(crate) fn pow_mod(a: u64, e: u64, m: u64) -> u64 {
+    let (mut result, mut base, mut e) = (1 % m, a % m, e);
+    while e > 0 {
+        if e & 1 == 1 {
+            result = mul_mod(result, base, m);
+        }
+        base = mul_mod(base, base, m);
+        e >>= 1;
+    }
+    result
+}
+
+fn mod_inverse(a: u64, m: u64) -> Option<u64> {
+    let (mut old_r, mut r) = ((a % m) as i128, m as i128);
+    let (mut old_s, mut s) = (1i128, 0i128);
+    while r != 0 {
+        let q = old_r / r;
+        let tmp = old_r - q * r;
+        old_r = r;
+        r = tmp;
+        let tmp = old_s - q * s;
+        old_s = s;
+        s = tmp;
+    }
+    if old_r == 1 || m == 1 {
+        Some(old_s.rem_euclid(m as i128) as u64)
+    } else {
+        None
+    }
+}
+
 #[cfg(test)]
 mod arithmetic_tests {
     use super::*;
@@ -846,5 +1204,178 @@ mod arithmetic_tests {
         Ok(())
     }
 
+    #[test]
+    fn test_ripple_carry_add() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let n = 3;
+        let ra = b.register(n)?;
+        let rb = b.register(n + 1)?;
+
+        assert_on_registers(
+            &mut b,
+            vec![ra, rb],
+            ripple_carry_add_op,
+            |befores, afters, full| {
+                assert_eq!(afters[0], befores[0]);
+                assert_eq!(afters[1], (befores[0] + befores[1]) % (1 << (n + 1)));
+                assert_eq!(full >> (2 * n + 1), 0);
+            },
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_carry_lookahead_add() -> Result<(), CircuitError> {
+        for n in 1..=3 {
+            let mut b = OpBuilder::new();
+            let ra = b.register(n)?;
+            let rb = b.register(n)?;
+            let rs = b.register(n + 1)?;
+
+            assert_on_registers(
+                &mut b,
+                vec![ra, rb, rs],
+                carry_lookahead_add_op,
+                |befores, afters, full| {
+                    assert_eq!(afters[0], befores[0]);
+                    assert_eq!(afters[1], befores[1]);
+                    assert_eq!(afters[2], befores[2] ^ (befores[0] + befores[1]));
+                    assert_eq!(full >> (3 * n + 1), 0);
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_carry_lookahead_add_inv() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let n = 2;
+        let ra = b.register(n)?;
+        let rb = b.register(n)?;
+        let rs = b.register(n + 1)?;
+
+        assert_on_registers(
+            &mut b,
+            vec![ra, rb, rs],
+            |b, rs| {
+                let rs = carry_lookahead_add_op(b, rs)?;
+                carry_lookahead_add_inv(b, rs)
+            },
+            |befores, afters, full| {
+                assert_eq!(afters, befores);
+                assert_eq!(full >> (3 * n + 1), 0);
+            },
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_constant() -> Result<(), CircuitError> {
+        for n in 1..=3 {
+            for c in 0..(1 << n) + 2 {
+                let mut b = OpBuilder::new();
+                let r = b.register(n)?;
+
+                assert_on_registers(
+                    &mut b,
+                    vec![r],
+                    |b, mut rs| Ok(vec![add_constant(b, rs.pop().unwrap(), c)?]),
+                    |befores, afters, full| {
+                        assert_eq!(afters[0], (befores[0] + c) % (1 << n));
+                        assert_eq!(full >> n, 0);
+                    },
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_mod_add_const() -> Result<(), CircuitError> {
+        let n = 3;
+        for (c, m) in &[(0, 5), (3, 5), (7, 5), (6, 7), (5, 8)] {
+            let (c, m) = (*c, *m);
+            let mut b = OpBuilder::new();
+            let r = b.register(n + 1)?;
+
+            assert_on_registers_and_filter(
+                &mut b,
+                vec![r],
+                |b, mut rs| Ok(vec![mod_add_const(b, rs.pop().unwrap(), c, m)?]),
+                |befores, afters, full| {
+                    assert_eq!(afters[0], (befores[0] + c) % m);
+                    assert_eq!(full >> (n + 1), 0);
+                },
+                |befores| befores[0] < m,
+            )?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_mod_mul_const() -> Result<(), CircuitError> {
+        let n = 3;
+        for (a, m) in &[(1, 5), (3, 5), (2, 7), (6, 7), (11, 8)] {
+            let (a, m) = (*a, *m);
+            let mut b = OpBuilder::new();
+            let r = b.register(n)?;
+
+            assert_on_registers_and_filter(
+                &mut b,
+                vec![r],
+                |b, mut rs| Ok(vec![mod_mul_const(b, rs.pop().unwrap(), a, m)?]),
+                |befores, afters, full| {
+                    assert_eq!(afters[0], (befores[0] * a) % m);
+                    assert_eq!(full >> n, 0);
+                },
+                |befores| befores[0] < m,
+            )?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_mod_mul_const_not_coprime() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let r = b.register(3)?;
+        assert!(mod_mul_const(&mut b, r, 2, 6).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mod_exp() -> Result<(), CircuitError> {
+        let (a, m) = (2, 5);
+        let mut b = OpBuilder::new();
+        let re = b.register(2)?;
+        let rx = b.register(3)?;
+
+        assert_on_registers_and_filter(
+            &mut b,
+            vec![re, rx],
+            |b, mut rs| {
+                let rx = rs.pop().unwrap();
+                let re = rs.pop().unwrap();
+                let (re, rx) = mod_exp(b, re, rx, a, m)?;
+                Ok(vec![re, rx])
+            },
+            |befores, afters, full| {
+                assert_eq!(afters[0], befores[0]);
+                assert_eq!(afters[1], (befores[1] * pow_mod(a, befores[0], m)) % m);
+                assert_eq!(full >> 5, 0);
+            },
+            |befores| befores[1] < m,
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_mod_inverse() {
+        assert_eq!(mod_inverse(3, 7), Some(5));
+        assert_eq!(mod_inverse(10, 7), Some(5));
+        assert_eq!(mod_inverse(4, 8), None);
+        assert_eq!(mod_inverse(5, 1), Some(0));
+    }
+
     // The n=k=2 case takes too long to test completely.
 }
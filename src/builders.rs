@@ -1,16 +1,43 @@
+use crate::classical::ClassicalRegister;
 use crate::errors::CircuitError;
+use crate::loops::ClassicalLoop;
+use crate::macros::inverter::{inverter, remap_indices};
+use crate::named_gates::{check_gate_name, GateDefinition};
+use crate::parameters::{Parameter, ParameterizedMatFn};
 use crate::pipeline::*;
+use crate::pipeline_debug::DebugLog;
 use crate::qubits::*;
 use crate::state_ops::*;
+use crate::types::Endianness;
+use crate::utils::flip_bits;
 use crate::Complex;
-use num::Zero;
+use num::{One, Zero};
+use std::collections::HashMap;
 use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
 
 /// A function which takes a builder, a Register, and a set of measured values, and constructs a
 /// circuit, outputting the resulting Register.
 type SingleRegisterSideChannelFn =
     dyn Fn(&mut dyn UnitaryBuilder, Register, &[u64]) -> Result<Register, CircuitError>;
 
+/// A function which takes a builder and a Register and constructs a circuit, used for circuits which
+/// are only applied for some classical measured values.
+type ClassicalIfFn = dyn Fn(&mut dyn UnitaryBuilder, Register) -> Result<Register, CircuitError>;
+
+/// A function which takes a builder and a Register and constructs a circuit, used for circuits
+/// whose adjoint is applied instead.
+type DaggerFn = dyn Fn(&mut dyn UnitaryBuilder, Register) -> Result<Register, CircuitError>;
+
+/// A function which takes a builder, a Register, and a Register of ancilla qubits, and constructs a
+/// circuit, outputting both Registers. Used by `with_ancilla`.
+type AncillaFn = dyn Fn(
+    &mut dyn UnitaryBuilder,
+    Register,
+    Register,
+) -> Result<(Register, Register), CircuitError>;
+
 /// A function which takes a builder, a vec of Register, and a set of measured values, and constructs a
 /// circuit, outputting the resulting Registers.
 type SideChannelFn =
@@ -74,6 +101,15 @@ pub trait UnitaryBuilder {
     /// Build a builder which uses `r` as a condition.
     fn with_condition(&mut self, r: Register) -> ConditionalContextBuilder;
 
+    /// Build a builder which uses `r` as an anticondition: ops only act on the parts of the state
+    /// where every qubit of `r` is `|0>`, the register is flipped before and after the ops.
+    fn with_anticondition(&mut self, r: Register) -> ConditionalContextBuilder<'_> {
+        let r = self.not(r);
+        let mut b = self.with_condition(r);
+        b.anticondition = true;
+        b
+    }
+
     /// Add a name scope.
     fn push_name_scope(&mut self, name: &str);
 
@@ -103,6 +139,60 @@ pub trait UnitaryBuilder {
         mat: Vec<Complex<f64>>,
     ) -> Result<Register, CircuitError>;
 
+    /// Build a matrix op whose matrix is given by `f` for the value of `param` when the circuit is
+    /// run, apply to `r`, if `r` is multiple indices and the matrix is 2x2, apply to each index,
+    /// otherwise returns an error if the matrix is not the correct size for the number of indices
+    /// in `r` (mat.len() == 2^(2n)).
+    fn parameterized_mat(
+        &mut self,
+        name: &str,
+        r: Register,
+        param: &Parameter,
+        f: Box<ParameterizedMatFn>,
+    ) -> Result<Register, CircuitError> {
+        let size = f(0.0).len();
+        if r.n() > 1 && size == 2 * 2 {
+            let f: Rc<ParameterizedMatFn> = f.into();
+            let rs = self.split_all(r);
+            let rs = rs
+                .into_iter()
+                .map(|r| {
+                    let f = f.clone();
+                    self.parameterized_mat(name, r, param, Box::new(move |theta| f(theta)))
+                })
+                .collect::<Result<Vec<_>, CircuitError>>()?;
+            return self.merge(rs);
+        }
+        let expected = 1 << (2 * r.n());
+        if size != expected {
+            let message = format!(
+                "Matrix data has {:?} entries versus expected 2^2*{:?}",
+                size,
+                r.n()
+            );
+            return CircuitError::make_err(message);
+        }
+        let indices = r.indices.clone();
+        self.merge_with_parameterized_op(
+            vec![r],
+            name.to_string(),
+            param.clone(),
+            Box::new(move |theta| {
+                let mat = f(theta);
+                if mat.len() == expected {
+                    Ok(UnitaryOp::Matrix(indices.clone(), mat))
+                } else {
+                    let message = format!(
+                        "Matrix data has {:?} entries versus expected {:?}",
+                        mat.len(),
+                        expected
+                    );
+                    CircuitError::make_err(message)
+                }
+            }),
+        )
+    }
+
     /// Build a matrix op from real numbers, apply to `r`, if `r` is multiple indices and
     /// mat is 2x2, apply to each index, otherwise returns an error if the matrix is not the correct
     /// size for the number of indices in `r` (mat.len() == 2^(2n)).
@@ -111,8 +201,8 @@ pub trait UnitaryBuilder {
     }
 
     /// Build a sparse matrix op, apply to `r`, if `r` is multiple indices and
-    /// mat is 2x2, apply to each index, otherwise returns an error if the matrix is not the correct
-    /// size for the number of indices in `r` (mat.len() == 2^n).
+    /// mat is 2x2 (two rows), apply to each index, otherwise returns an error if the matrix is not
+    /// the correct size for the number of indices in `r` (mat.len() == 2^n).
     fn sparse_mat(
         &mut self,
         name: &str,
@@ -137,8 +227,8 @@ pub trait UnitaryBuilder {
     }
 
     /// Build a sparse matrix op from real numbers, apply to `r`, if `r` is multiple indices and
-    /// mat is 2x2, apply to each index, otherwise returns an error if the matrix is not the correct
-    /// size for the number of indices in `r` (mat.len() == 2^n).
+    /// mat is 2x2 (two rows), apply to each index, otherwise returns an error if the matrix is not
+    /// the correct size for the number of indices in `r` (mat.len() == 2^n).
     fn real_sparse_mat(
         &mut self,
         name: &str,
@@ -158,6 +248,18 @@ pub trait UnitaryBuilder {
         self.sparse_mat(name, r, mat, natural_order)
     }
 
+    /// Apply a unitary to `r` which takes `|0...0>` to the state given by `amplitudes`, where
+    /// `amplitudes[x]` is the amplitude of `r` having the value `x`. Returns an error if
+    /// `amplitudes` does not have `2^n` entries or is not normalized.
+    fn prepare_state(
+        &mut self,
+        r: Register,
+        amplitudes: &[Complex<f64>],
+    ) -> Result<Register, CircuitError> {
+        let mat = make_state_preparation_matrix(r.n(), amplitudes)?;
+        self.mat("PrepareState", r, mat)
+    }
+
     /// Apply NOT to `r`, if `r` is multiple indices, apply to each
     fn not(&mut self, r: Register) -> Register {
         self.real_mat("not", r, &[0.0, 1.0, 1.0, 0.0]).unwrap()
@@ -171,14 +273,13 @@ pub trait UnitaryBuilder {
     /// Apply Rx to `r`, if `r` is multiple indices, apply to each
     /// * `theta` - the angle to rotate around the x axis of the Bloch sphere
     fn rx(&mut self, r: Register, theta: f64) -> Register {
-        let theta_2 = theta / 2.0;
-        let (sin, cos) = theta_2.sin_cos();
-        self.mat(
-            "Rx",
-            r,
-            from_tuples(&[(cos, 0.0), (0.0, -sin), (0.0, -sin), (cos, 0.0)]),
-        )
-        .unwrap()
+        self.mat("Rx", r, rx_matrix(theta)).unwrap()
+    }
+
+    /// Apply Rx to `r` with an angle given by the value of `param` when run, if `r` is multiple
+    /// indices, apply to each.
+    fn rx_param(&mut self, r: Register, param: &Parameter) -> Result<Register, CircuitError> {
+        self.parameterized_mat("Rx", r, param, Box::new(rx_matrix))
     }
 
     /// Apply Y to `r`, if `r` is multiple indices, apply to each
@@ -194,9 +295,13 @@ pub trait UnitaryBuilder {
     /// Apply Ry to `r`, if `r` is multiple indices, apply to each
     /// * `theta` - the angle to rotate around the y axis of the Bloch sphere
     fn ry(&mut self, r: Register, theta: f64) -> Register {
-        let theta_2 = theta / 2.0;
-        let (sin, cos) = theta_2.sin_cos();
-        self.real_mat("Ry", r, &[cos, -sin, sin, cos]).unwrap()
+        self.mat("Ry", r, ry_matrix(theta)).unwrap()
+    }
+
+    /// Apply Ry to `r` with an angle given by the value of `param` when run, if `r` is multiple
+    /// indices, apply to each.
+    fn ry_param(&mut self, r: Register, param: &Parameter) -> Result<Register, CircuitError> {
+        self.parameterized_mat("Ry", r, param, Box::new(ry_matrix))
     }
 
     /// Apply Z to `r`, if `r` is multiple indices, apply to each
@@ -207,32 +312,72 @@ pub trait UnitaryBuilder {
     /// Apply Rz to `r`, if `r` is multiple indices, apply to each
     /// * `theta` - the angle to rotate around the z axis of the Bloch sphere
     fn rz(&mut self, r: Register, theta: f64) -> Register {
-        let theta_2 = theta / 2.0;
-        let phase_plus = Complex {
+        self.mat("Rz", r, rz_matrix(theta)).unwrap()
+    }
+
+    /// Apply Rz to `r` with an angle given by the value of `param` when run, if `r` is multiple
+    /// indices, apply to each.
+    fn rz_param(&mut self, r: Register, param: &Parameter) -> Result<Register, CircuitError> {
+        self.parameterized_mat("Rz", r, param, Box::new(rz_matrix))
+    }
+
+    /// Apply H to `r`, if `r` is multiple indices, apply to each
+    fn hadamard(&mut self, r: Register) -> Register {
+        let inv_sqrt = 1.0f64 / 2.0f64.sqrt();
+        self.real_mat("H", r, &[inv_sqrt, inv_sqrt, inv_sqrt, -inv_sqrt])
+            .unwrap()
+    }
+
+    /// Apply S to `r`, if `r` is multiple indices, apply to each
+    fn s(&mut self, r: Register) -> Register {
+        self.mat(
+            "S",
+            r,
+            from_tuples(&[(1.0, 0.0), (0.0, 0.0), (0.0, 0.0), (0.0, 1.0)]),
+        )
+        .unwrap()
+    }
+
+    /// Apply S-dagger to `r`, if `r` is multiple indices, apply to each
+    fn sdagger(&mut self, r: Register) -> Register {
+        self.mat(
+            "Sdag",
+            r,
+            from_tuples(&[(1.0, 0.0), (0.0, 0.0), (0.0, 0.0), (0.0, -1.0)]),
+        )
+        .unwrap()
+    }
+
+    /// Apply T to `r`, if `r` is multiple indices, apply to each
+    fn t(&mut self, r: Register) -> Register {
+        let phase = Complex {
             re: 0.0,
-            im: theta_2,
+            im: std::f64::consts::FRAC_PI_4,
         }
         .exp();
-        let phase_minus = Complex {
+        self.mat(
+            "T",
+            r,
+            vec![Complex::one(), Complex::zero(), Complex::zero(), phase],
+        )
+        .unwrap()
+    }
+
+    /// Apply T-dagger to `r`, if `r` is multiple indices, apply to each
+    fn tdagger(&mut self, r: Register) -> Register {
+        let phase = Complex {
             re: 0.0,
-            im: -theta_2,
+            im: -std::f64::consts::FRAC_PI_4,
         }
         .exp();
         self.mat(
-            "Rz",
+            "Tdag",
             r,
-            vec![phase_minus, Complex::zero(), Complex::zero(), phase_plus],
+            vec![Complex::one(), Complex::zero(), Complex::zero(), phase],
         )
         .unwrap()
     }
 
-    /// Apply H to `r`, if `r` is multiple indices, apply to each
-    fn hadamard(&mut self, r: Register) -> Register {
-        let inv_sqrt = 1.0f64 / 2.0f64.sqrt();
-        self.real_mat("H", r, &[inv_sqrt, inv_sqrt, inv_sqrt, -inv_sqrt])
-            .unwrap()
-    }
-
     /// Transforms `|psi>` to `e^{i*theta}|psi>`
     fn phase(&mut self, r: Register, theta: f64) -> Register {
         let phase = Complex { re: 0.0, im: theta }.exp();
@@ -244,6 +389,71 @@ pub trait UnitaryBuilder {
         .unwrap()
     }
 
+    /// Apply the general single qubit gate `U3(theta, phi, lambda)` to `r`, with matrix
+    /// `[[cos(theta/2), -e^{i lambda} sin(theta/2)], [e^{i phi} sin(theta/2), e^{i (phi + lambda)}
+    /// cos(theta/2)]]`. This is `Rz(phi) Ry(theta) Rz(lambda)` up to a phase, and `u3_angles`
+    /// recovers the angles from any 2x2 unitary. If `r` is multiple indices, apply to each.
+    fn u3(&mut self, r: Register, theta: f64, phi: f64, lambda: f64) -> Register {
+        self.mat("U3", r, u3_matrix(theta, phi, lambda)).unwrap()
+    }
+
+    /// Apply `exp(-i theta/2 P)` to `r` for the Pauli string `P` made of `I`, `X`, `Y` and `Z`,
+    /// where the character at position `j` acts on qubit `j` of `r`. Each `X` and `Y` is rotated
+    /// into `Z`, then a ladder of cnots computes the parity onto the last qubit for an `Rz`.
+    fn exp_pauli(
+        &mut self,
+        r: Register,
+        pauli: &str,
+        theta: f64,
+    ) -> Result<Register, CircuitError> {
+        let paulis = parse_pauli_string(pauli, r.n())?;
+
+        self.push_name_scope("ExpPauli");
+        let mut qs: Vec<Option<Register>> = self.split_all(r).into_iter().map(Some).collect();
+        if paulis.is_empty() {
+            // Only a global phase, which still matters if this is conditioned.
+            qs[0] = Some(self.phase(qs[0].take().unwrap(), -theta / 2.0));
+        }
+        paulis.iter().for_each(|(i, c)| {
+            let q = qs[*i].take().unwrap();
+            qs[*i] = Some(match c {
+                'X' => self.hadamard(q),
+                'Y' => {
+                    let q = self.sdagger(q);
+                    self.hadamard(q)
+                }
+                _ => q,
+            });
+        });
+        let ladder: Vec<(usize, usize)> = paulis.windows(2).map(|w| (w[0].0, w[1].0)).collect();
+        let apply_cnot = |s: &mut Self, qs: &mut Vec<Option<Register>>, (c, t): (usize, usize)| {
+            let (cq, tq) = s.cnot(qs[c].take().unwrap(), qs[t].take().unwrap());
+            qs[c] = Some(cq);
+            qs[t] = Some(tq);
+        };
+        ladder.iter().for_each(|ct| apply_cnot(self, &mut qs, *ct));
+        if let Some((last, _)) = paulis.last() {
+            qs[*last] = Some(self.rz(qs[*last].take().unwrap(), theta));
+        }
+        ladder
+            .iter()
+            .rev()
+            .for_each(|ct| apply_cnot(self, &mut qs, *ct));
+        paulis.iter().for_each(|(i, c)| {
+            let q = qs[*i].take().unwrap();
+            qs[*i] = Some(match c {
+                'X' => self.hadamard(q),
+                'Y' => {
+                    let q = self.hadamard(q);
+                    self.s(q)
+                }
+                _ => q,
+            });
+        });
+        self.pop_name_scope();
+        self.merge(qs.into_iter().map(|q| q.unwrap()).collect())
+    }
+
     /// Apply SWAP to `ra` and `rb`
     fn swap(&mut self, ra: Register, rb: Register) -> Result<(Register, Register), CircuitError> {
         let op = self.make_swap_op(&ra, &rb)?;
@@ -253,6 +463,113 @@ pub trait UnitaryBuilder {
         Ok((ra, rb.unwrap()))
     }
 
+    /// Apply iSWAP to the qubits `ra` and `rb`, which swaps `|01>` and `|10>` with a phase of `i`.
+    fn iswap(&mut self, ra: Register, rb: Register) -> Result<(Register, Register), CircuitError> {
+        let (o, z, i) = (Complex::one(), Complex::zero(), Complex::i());
+        let mat = vec![o, z, z, z, z, z, i, z, z, i, z, z, z, z, z, o];
+        two_qubit_mat(self, "iSWAP", ra, rb, mat)
+    }
+
+    /// Apply the square root of iSWAP to the qubits `ra` and `rb`, taking `|01>` to
+    /// `(|01> + i|10>)/sqrt(2)` and `|10>` to `(i|01> + |10>)/sqrt(2)`.
+    fn sqrt_iswap(
+        &mut self,
+        ra: Register,
+        rb: Register,
+    ) -> Result<(Register, Register), CircuitError> {
+        let (o, z) = (Complex::one(), Complex::zero());
+        let c = Complex::new(std::f64::consts::FRAC_1_SQRT_2, 0.0);
+        let s = Complex::new(0.0, std::f64::consts::FRAC_1_SQRT_2);
+        let mat = vec![o, z, z, z, z, c, s, z, z, s, c, z, z, z, z, o];
+        two_qubit_mat(self, "sqrtiSWAP", ra, rb, mat)
+    }
+
+    /// Apply the fermionic simulation gate to the qubits `ra` and `rb`, which rotates `|01>` and
+    /// `|10>` into each other by `theta` and applies a phase of `e^{-i phi}` to `|11>`:
+    /// `|01> -> cos(theta)|01> - i sin(theta)|10>`. `fsim(-pi/2, 0)` is iSWAP, and
+    /// `fsim(pi/2, pi/6)` is the Sycamore gate.
+    fn fsim(
+        &mut self,
+        ra: Register,
+        rb: Register,
+        theta: f64,
+        phi: f64,
+    ) -> Result<(Register, Register), CircuitError> {
+        let (o, z) = (Complex::one(), Complex::zero());
+        let c = Complex::new(theta.cos(), 0.0);
+        let s = Complex::new(0.0, -theta.sin());
+        let p = Complex::from_polar(&1.0, &-phi);
+        let mat = vec![o, z, z, z, z, c, s, z, z, s, c, z, z, z, z, p];
+        two_qubit_mat(self, "fSim", ra, rb, mat)
+    }
+
+    /// Apply a Givens rotation by `theta` to the qubits `ra` and `rb`, a real rotation of `|01>`
+    /// and `|10>` into each other which conserves the number of excitations:
+    /// `|01> -> cos(theta)|01> + sin(theta)|10>` and `|10> -> cos(theta)|10> - sin(theta)|01>`,
+    /// where `ra` is the first qubit of each label.
+    fn givens(
+        &mut self,
+        ra: Register,
+        rb: Register,
+        theta: f64,
+    ) -> Result<(Register, Register), CircuitError> {
+        let (o, z) = (Complex::one(), Complex::zero());
+        let c = Complex::new(theta.cos(), 0.0);
+        let s = Complex::new(theta.sin(), 0.0);
+        let mat = vec![o, z, z, z, z, c, -s, z, z, s, c, z, z, z, z, o];
+        two_qubit_mat(self, "Givens", ra, rb, mat)
+    }
+
+    /// Apply `exp(-i theta/2 X X)` to the qubits `ra` and `rb`, the XX interaction.
+    fn rxx(
+        &mut self,
+        ra: Register,
+        rb: Register,
+        theta: f64,
+    ) -> Result<(Register, Register), CircuitError> {
+        two_qubit_mat(self, "RXX", ra, rb, rxx_matrix(theta))
+    }
+
+    /// Apply `exp(-i theta/2 Y Y)` to the qubits `ra` and `rb`, the YY interaction.
+    fn ryy(
+        &mut self,
+        ra: Register,
+        rb: Register,
+        theta: f64,
+    ) -> Result<(Register, Register), CircuitError> {
+        let z = Complex::zero();
+        let c = Complex::new((theta / 2.0).cos(), 0.0);
+        let s = Complex::new(0.0, -(theta / 2.0).sin());
+        let mat = vec![c, z, z, -s, z, c, s, z, z, s, c, z, -s, z, z, c];
+        two_qubit_mat(self, "RYY", ra, rb, mat)
+    }
+
+    /// Apply `exp(-i theta/2 Z Z)` to the qubits `ra` and `rb`, the ZZ interaction.
+    fn rzz(
+        &mut self,
+        ra: Register,
+        rb: Register,
+        theta: f64,
+    ) -> Result<(Register, Register), CircuitError> {
+        let z = Complex::zero();
+        let p = Complex::from_polar(&1.0, &(-theta / 2.0));
+        let m = p.conj();
+        let mat = vec![p, z, z, z, z, m, z, z, z, z, m, z, z, z, z, p];
+        two_qubit_mat(self, "RZZ", ra, rb, mat)
+    }
+
+    /// Apply the Mølmer–Sørensen gate `exp(-i theta X X)` to the qubits `ra` and `rb`, the native
+    /// entangling gate of trapped ions. This is `rxx(2 theta)`, and `ms(pi/4)` takes `|00>` to
+    /// `(|00> - i|11>)/sqrt(2)`.
+    fn ms(
+        &mut self,
+        ra: Register,
+        rb: Register,
+        theta: f64,
+    ) -> Result<(Register, Register), CircuitError> {
+        two_qubit_mat(self, "MS", ra, rb, rxx_matrix(2.0 * theta))
+    }
+
     /// Make an operation from the boxed function `f`. This maps c|`r_in`>|`r_out`> to
     /// c*e^i`theta`|`r_in`>|`r_out` ^ `indx`> where `indx` and `theta` are the outputs from the
     /// function `f(x) = (indx, theta)`
@@ -264,6 +581,61 @@ pub trait UnitaryBuilder {
         f: Box<dyn Fn(u64) -> (u64, f64) + Send + Sync>,
     ) -> Result<(Register, Register), CircuitError>;
 
+    /// Apply the oracle `|x>|y> -> |x>|y ^ f(x)>` for a classical function `f`, where `x` and
+    /// `y` use the first qubit of `r_in` and `r_out` as their least significant bits. The oracle
+    /// is applied to the state as a permutation of indices, without building its matrix. Bits of
+    /// `f(x)` beyond the size of `r_out` are ignored.
+    ///
+    /// # Example
+    /// ```
+    /// use qip::*;
+    /// # fn main() -> Result<(), CircuitError> {
+    ///
+    /// let mut b = OpBuilder::new();
+    /// let r_in = b.register(3)?;
+    /// let r_out = b.register(3)?;
+    /// let r_in = b.hadamard(r_in);
+    /// let (r_in, r_out) = b.classical_oracle(r_in, r_out, Box::new(|x| (x * 5) % 8))?;
+    /// let (r_in, m_in) = b.measure(r_in);
+    /// let (r_out, m_out) = b.measure(r_out);
+    ///
+    /// let (_, measured) = run_local::<f64>(&b.merge(vec![r_in, r_out])?)?;
+    /// let (x, _) = measured.get_measurement(&m_in).unwrap();
+    /// let (y, _) = measured.get_measurement(&m_out).unwrap();
+    /// assert_eq!(y, (x * 5) % 8);
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn classical_oracle(
+        &mut self,
+        r_in: Register,
+        r_out: Register,
+        f: Box<dyn Fn(u64) -> u64 + Send + Sync>,
+    ) -> Result<(Register, Register), CircuitError> {
+        let mask = 1u64
+            .checked_shl(r_out.n() as u32)
+            .map_or(u64::MAX, |m| m - 1);
+        self.apply_function("oracle", r_in, r_out, Box::new(move |x| (f(x) & mask, 0.0)))
+    }
+
+    /// Apply the oracle `|x> -> -|x>` for each `x` where `f(x)` is true and `|x> -> |x>`
+    /// otherwise, where `x` uses the first qubit of `r` as its least significant bit. The oracle
+    /// is a diagonal op, so it is applied to the state in place.
+    fn phase_oracle(
+        &mut self,
+        r: Register,
+        f: Box<dyn Fn(u64) -> bool + Send + Sync>,
+    ) -> Result<Register, CircuitError> {
+        let mat = (0..1 << r.n())
+            .map(|x| {
+                let sign = if f(x) { -1.0 } else { 1.0 };
+                vec![(x, Complex::new(sign, 0.0))]
+            })
+            .collect();
+        let op = self.make_sparse_mat_op(&r, mat, true)?;
+        self.merge_with_op(vec![r], Some(("phase_oracle".to_string(), op)))
+    }
+
     /// A controlled x, using `cr` as control and `r` as input.
     fn cx(&mut self, cr: Register, r: Register) -> (Register, Register) {
         let mut b = self.with_condition(cr);
@@ -285,6 +657,71 @@ pub trait UnitaryBuilder {
         let cr = b.release_register();
         (cr, r)
     }
+    /// A controlled rx, using `cr` as control and `r` as input.
+    fn crx(&mut self, cr: Register, r: Register, theta: f64) -> (Register, Register) {
+        let mut b = self.with_condition(cr);
+        let r = b.rx(r, theta);
+        let cr = b.release_register();
+        (cr, r)
+    }
+    /// A controlled ry, using `cr` as control and `r` as input.
+    fn cry(&mut self, cr: Register, r: Register, theta: f64) -> (Register, Register) {
+        let mut b = self.with_condition(cr);
+        let r = b.ry(r, theta);
+        let cr = b.release_register();
+        (cr, r)
+    }
+    /// A controlled rz, using `cr` as control and `r` as input.
+    fn crz(&mut self, cr: Register, r: Register, theta: f64) -> (Register, Register) {
+        let mut b = self.with_condition(cr);
+        let r = b.rz(r, theta);
+        let cr = b.release_register();
+        (cr, r)
+    }
+    /// A controlled phase, using `cr` as control and `r` as input: when `cr` is `|1>` each qubit
+    /// of `r` has `|1>` multiplied by `e^{i theta}`. Unlike a controlled `phase` this only acts on
+    /// `|1>`, so for single qubits it is symmetric in `cr` and `r`.
+    fn cphase(&mut self, cr: Register, r: Register, theta: f64) -> (Register, Register) {
+        let phase = Complex::from_polar(&1.0, &theta);
+        let mut b = self.with_condition(cr);
+        let r = b
+            .mat(
+                "P",
+                r,
+                vec![Complex::one(), Complex::zero(), Complex::zero(), phase],
+            )
+            .unwrap();
+        let cr = b.release_register();
+        (cr, r)
+    }
+    /// A controlled s, using `cr` as control and `r` as input.
+    fn cs(&mut self, cr: Register, r: Register) -> (Register, Register) {
+        let mut b = self.with_condition(cr);
+        let r = b.s(r);
+        let cr = b.release_register();
+        (cr, r)
+    }
+    /// A controlled s-dagger, using `cr` as control and `r` as input.
+    fn csdagger(&mut self, cr: Register, r: Register) -> (Register, Register) {
+        let mut b = self.with_condition(cr);
+        let r = b.sdagger(r);
+        let cr = b.release_register();
+        (cr, r)
+    }
+    /// A controlled t, using `cr` as control and `r` as input.
+    fn ct(&mut self, cr: Register, r: Register) -> (Register, Register) {
+        let mut b = self.with_condition(cr);
+        let r = b.t(r);
+        let cr = b.release_register();
+        (cr, r)
+    }
+    /// A controlled t-dagger, using `cr` as control and `r` as input.
+    fn ctdagger(&mut self, cr: Register, r: Register) -> (Register, Register) {
+        let mut b = self.with_condition(cr);
+        let r = b.tdagger(r);
+        let cr = b.release_register();
+        (cr, r)
+    }
     /// A controlled not, using `cr` as control and `r` as input.
     fn cnot(&mut self, cr: Register, r: Register) -> (Register, Register) {
         let mut b = self.with_condition(cr);
@@ -292,6 +729,34 @@ pub trait UnitaryBuilder {
         let cr = b.release_register();
         (cr, r)
     }
+    /// An anticontrolled x, using `cr` as anticontrol and `r` as input.
+    fn ncx(&mut self, cr: Register, r: Register) -> (Register, Register) {
+        let mut b = self.with_anticondition(cr);
+        let r = b.x(r);
+        let cr = b.release_register();
+        (cr, r)
+    }
+    /// An anticontrolled y, using `cr` as anticontrol and `r` as input.
+    fn ncy(&mut self, cr: Register, r: Register) -> (Register, Register) {
+        let mut b = self.with_anticondition(cr);
+        let r = b.y(r);
+        let cr = b.release_register();
+        (cr, r)
+    }
+    /// An anticontrolled z, using `cr` as anticontrol and `r` as input.
+    fn ncz(&mut self, cr: Register, r: Register) -> (Register, Register) {
+        let mut b = self.with_anticondition(cr);
+        let r = b.z(r);
+        let cr = b.release_register();
+        (cr, r)
+    }
+    /// An anticontrolled not, using `cr` as anticontrol and `r` as input.
+    fn ncnot(&mut self, cr: Register, r: Register) -> (Register, Register) {
+        let mut b = self.with_anticondition(cr);
+        let r = b.not(r);
+        let cr = b.release_register();
+        (cr, r)
+    }
     /// Swap `ra` and `rb` controlled by `cr`.
     fn cswap(
         &mut self,
@@ -304,6 +769,57 @@ pub trait UnitaryBuilder {
         let cr = b.release_register();
         Ok((cr, ra, rb))
     }
+    /// Apply iSWAP to the qubits `ra` and `rb` controlled by `cr`.
+    fn ciswap(
+        &mut self,
+        cr: Register,
+        ra: Register,
+        rb: Register,
+    ) -> Result<(Register, Register, Register), CircuitError> {
+        let mut b = self.with_condition(cr);
+        let (ra, rb) = b.iswap(ra, rb)?;
+        let cr = b.release_register();
+        Ok((cr, ra, rb))
+    }
+    /// Apply the square root of iSWAP to the qubits `ra` and `rb` controlled by `cr`.
+    fn csqrt_iswap(
+        &mut self,
+        cr: Register,
+        ra: Register,
+        rb: Register,
+    ) -> Result<(Register, Register, Register), CircuitError> {
+        let mut b = self.with_condition(cr);
+        let (ra, rb) = b.sqrt_iswap(ra, rb)?;
+        let cr = b.release_register();
+        Ok((cr, ra, rb))
+    }
+    /// Apply `fsim(theta, phi)` to the qubits `ra` and `rb` controlled by `cr`.
+    fn cfsim(
+        &mut self,
+        cr: Register,
+        ra: Register,
+        rb: Register,
+        theta: f64,
+        phi: f64,
+    ) -> Result<(Register, Register, Register), CircuitError> {
+        let mut b = self.with_condition(cr);
+        let (ra, rb) = b.fsim(ra, rb, theta, phi)?;
+        let cr = b.release_register();
+        Ok((cr, ra, rb))
+    }
+    /// Apply a Givens rotation by `theta` to the qubits `ra` and `rb` controlled by `cr`.
+    fn cgivens(
+        &mut self,
+        cr: Register,
+        ra: Register,
+        rb: Register,
+        theta: f64,
+    ) -> Result<(Register, Register, Register), CircuitError> {
+        let mut b = self.with_condition(cr);
+        let (ra, rb) = b.givens(ra, rb, theta)?;
+        let cr = b.release_register();
+        Ok((cr, ra, rb))
+    }
     /// Apply a unitary matrix to the register. If mat is 2x2 then can broadcast to all qubits.
     fn cmat(
         &mut self,
@@ -444,6 +960,15 @@ pub trait UnitaryBuilder {
         named_operator: Option<(String, UnitaryOp)>,
     ) -> Result<Register, CircuitError>;
 
+    /// Merge Registers using an op which depends on the value of `param` when the circuit is run.
+    fn merge_with_parameterized_op(
+        &mut self,
+        rs: Vec<Register>,
+        name: String,
+        param: Parameter,
+        f: Box<ParameterizedOpFn>,
+    ) -> Result<Register, CircuitError>;
+
     /// Merge a set of qubits into a given qubit at a set of indices
     fn merge_with_indices(
         &mut self,
@@ -535,6 +1060,179 @@ pub trait UnitaryBuilder {
         .unwrap()
     }
 
+    /// Apply the circuit portion `f` to `r` only if every qubit measured for `handle` was `|1>`.
+    /// This is the classical analog of `with_condition`, letting mid-circuit measurements feed
+    /// forward into later gates.
+    ///
+    /// # Example
+    /// ```
+    /// use qip::*;
+    /// # fn main() -> Result<(), CircuitError> {
+    ///
+    /// let mut b = OpBuilder::new();
+    /// let q = b.qubit();
+    /// let r = b.qubit();
+    /// let q = b.hadamard(q);
+    /// let (q, m) = b.measure(q);
+    /// // Copy the measured value onto r.
+    /// let r = b.classical_if(r, &m, Box::new(|b, r| Ok(b.not(r))));
+    ///
+    /// let r = b.merge(vec![q, r])?;
+    /// let (state, measured) = run_local::<f64>(&r)?;
+    /// let (m, _) = measured.get_measurement(&m).unwrap();
+    /// let expected = m | (m << 1);
+    /// assert_eq!(state.get_state(true)[expected as usize].re, 1.0);
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn classical_if(
+        &mut self,
+        r: Register,
+        handle: &MeasurementHandle,
+        f: Box<ClassicalIfFn>,
+    ) -> Register {
+        let n = handle.clone_register().n();
+        let all_ones = u64::MAX >> (64 - n);
+        self.c_if(r, handle, all_ones, f).unwrap()
+    }
+
+    /// Apply the circuit portion `f` to `r` only if the value measured for `handle` is `value`,
+    /// read in the same bit order as `get_measurement`. The value is checked separately for each
+    /// run of the circuit, so each shot takes its own branch. Returns an error if `value` does not
+    /// fit in the measured qubits.
+    ///
+    /// # Example
+    /// ```
+    /// use qip::*;
+    /// # fn main() -> Result<(), CircuitError> {
+    ///
+    /// let mut b = OpBuilder::new();
+    /// let q = b.register(2)?;
+    /// let r = b.qubit();
+    /// let q = b.hadamard(q);
+    /// let (q, m) = b.measure(q);
+    /// // Flip r only if the measurement gave 0b10.
+    /// let r = b.c_if(r, &m, 0b10, Box::new(|b, r| Ok(b.not(r))))?;
+    ///
+    /// let r = b.merge(vec![q, r])?;
+    /// let (state, measured) = run_local::<f64>(&r)?;
+    /// let (m, _) = measured.get_measurement(&m).unwrap();
+    /// let flipped = if m == 0b10 { 1 } else { 0 };
+    /// assert_eq!(state.get_state(true)[(m | (flipped << 2)) as usize].re, 1.0);
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn c_if(
+        &mut self,
+        r: Register,
+        handle: &MeasurementHandle,
+        value: u64,
+        f: Box<ClassicalIfFn>,
+    ) -> Result<Register, CircuitError> {
+        let n = handle.clone_register().n();
+        if n < 64 && value >> n != 0 {
+            let message = format!("Value {} does not fit in {} measured qubits", value, n);
+            return CircuitError::make_err(message);
+        }
+        Ok(self.single_register_classical_sidechannel(
+            r,
+            std::slice::from_ref(handle),
+            Box::new(
+                move |b, r, measured| {
+                    if measured[0] == value {
+                        f(b, r)
+                    } else {
+                        Ok(r)
+                    }
+                },
+            ),
+        ))
+    }
+
+    /// Apply the circuit portion `f` to `r` only if the value of the classical register `cr` is
+    /// nonzero, such as a single bit condition from comparing registers.
+    ///
+    /// # Example
+    /// ```
+    /// use qip::*;
+    /// use qip::classical::ClassicalRegister;
+    /// # fn main() -> Result<(), CircuitError> {
+    ///
+    /// let mut b = OpBuilder::new();
+    /// let ra = b.qubit();
+    /// let rb = b.qubit();
+    /// let q = b.qubit();
+    /// let ra = b.hadamard(ra);
+    /// let rb = b.hadamard(rb);
+    /// let (ra, ma) = b.measure(ra);
+    /// let (rb, mb) = b.measure(rb);
+    /// // Flip q if the measurements disagree.
+    /// let differ = ClassicalRegister::from(&ma) ^ ClassicalRegister::from(&mb);
+    /// let q = b.classical_register_if(q, &differ, Box::new(|b, q| Ok(b.not(q))));
+    ///
+    /// let r = b.merge(vec![ra, rb, q])?;
+    /// let (state, measured) = run_local::<f64>(&r)?;
+    /// let differ = measured.get_classical(&differ).unwrap();
+    /// let (a, _) = measured.get_measurement(&ma).unwrap();
+    /// let (b, _) = measured.get_measurement(&mb).unwrap();
+    /// let expected = a | (b << 1) | (differ << 2);
+    /// assert_eq!(state.get_state(true)[expected as usize].re, 1.0);
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn classical_register_if(
+        &mut self,
+        r: Register,
+        cr: &ClassicalRegister,
+        f: Box<ClassicalIfFn>,
+    ) -> Register {
+        let handles = cr.handles();
+        let ids: Vec<u64> = handles.iter().map(|handle| handle.get_id()).collect();
+        let cr = cr.clone();
+        self.single_register_classical_sidechannel(
+            r,
+            &handles,
+            Box::new(move |b, r, measured| {
+                let measured = ids.iter().cloned().zip(measured.iter().cloned()).collect();
+                match cr.evaluate(&measured) {
+                    Some(0) => Ok(r),
+                    Some(_) => f(b, r),
+                    None => {
+                        CircuitError::make_str_err("Missing measurement for classical register")
+                    }
+                }
+            }),
+        )
+    }
+
+    /// Apply the adjoint of the circuit portion `f` to `r`: the ops built by `f` are applied in
+    /// reverse order with each one inverted. Any temporary qubits borrowed by `f` are borrowed and
+    /// returned here as well. Returns an error if `f` contains measurements, channels, or classical
+    /// side channels, since these cannot be inverted.
+    ///
+    /// # Example
+    /// ```
+    /// use qip::*;
+    /// # fn main() -> Result<(), CircuitError> {
+    ///
+    /// let mut b = OpBuilder::new();
+    /// let r = b.register(3)?;
+    /// let r = qft(&mut b, r)?;
+    /// // Uncompute the qft.
+    /// let r = b.dagger(r, Box::new(|b, r| qft(b, r)))?;
+    ///
+    /// let (state, _) = run_local::<f64>(&r)?;
+    /// assert!((state.get_state(true)[0].re - 1.0).abs() < 1e-10);
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn dagger(&mut self, r: Register, f: Box<DaggerFn>) -> Result<Register, CircuitError> {
+        let mut rs = inverter(self, vec![r], |b, mut rs| {
+            Ok(vec![f(b, rs.pop().unwrap())?])
+        })?;
+        Ok(rs.pop().unwrap())
+    }
+
     /// Create a circuit portion which depends on the classical results of measuring some Registers.
     fn classical_sidechannel(
         &mut self,
@@ -582,6 +1280,45 @@ pub trait UnitaryBuilder {
         Ok(rs.pop().unwrap())
     }
 
+    /// Print the probability of each value of `r` as `label: [p0, p1, ...]` when this point in the
+    /// circuit is run, without changing the state.
+    fn debug_print(&mut self, r: Register, label: &str) -> Result<Register, CircuitError> {
+        let label = label.to_string();
+        self.debug(r, Box::new(move |probs| println!("{}: {:?}", label, probs)))
+    }
+
+    /// Record the probability of each value of `r` in `log` under `label` when this point in the
+    /// circuit is run, without changing the state.
+    ///
+    /// # Example
+    /// ```
+    /// use qip::*;
+    /// use qip::pipeline_debug::DebugLog;
+    /// # fn main() -> Result<(), CircuitError> {
+    ///
+    /// let log = DebugLog::new();
+    /// let mut b = OpBuilder::new();
+    /// let q = b.qubit();
+    /// let q = b.debug_record(q, "before", &log)?;
+    /// let q = b.hadamard(q);
+    /// let q = b.debug_record(q, "after", &log)?;
+    /// run_local::<f64>(&q)?;
+    ///
+    /// assert_eq!(log.get("before"), vec![vec![1.0, 0.0]]);
+    /// assert!((log.get("after")[0][1] - 0.5).abs() < 1e-10);
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn debug_record(
+        &mut self,
+        r: Register,
+        label: &str,
+        log: &DebugLog,
+    ) -> Result<Register, CircuitError> {
+        let (label, log) = (label.to_string(), log.clone());
+        self.debug(r, Box::new(move |probs| log.record(&label, probs)))
+    }
+
     /// Debug a vec of registers using a function `f` run during circuit execution on each state of `r`
     fn debug_registers(
         &mut self,
@@ -590,6 +1327,32 @@ pub trait UnitaryBuilder {
     ) -> Result<Vec<Register>, CircuitError>;
 }
 
+/// Check that none of `modifiers` change the basis values of the ancillas at `anc_indices`.
+fn check_ancillas_preserved(
+    modifiers: &[&StateModifier],
+    anc_indices: &[u64],
+) -> Result<(), CircuitError> {
+    modifiers.iter().try_for_each(|modifier| {
+        let preserved = match &modifier.modifier {
+            StateModifierType::UnitaryOp(op) => preserves_basis_values(op, anc_indices),
+            StateModifierType::ParameterizedOp(_, f) => {
+                preserves_basis_values(&f(0.0)?, anc_indices)
+            }
+            StateModifierType::MeasureState(_, indices, _)
+            | StateModifierType::Channel(indices, _) => {
+                !indices.iter().any(|i| anc_indices.contains(i))
+            }
+            _ => true,
+        };
+        if preserved {
+            Ok(())
+        } else {
+            let message = format!("Op {:?} changes the ancilla qubits", modifier.name);
+            CircuitError::make_err(message)
+        }
+    })
+}
+
 /// Helper function for Boxing static functions and applying using the given UnitaryBuilder.
 pub fn apply_function<F: 'static + Fn(u64) -> (u64, f64) + Send + Sync>(
     b: &mut dyn UnitaryBuilder,
@@ -600,6 +1363,122 @@ pub fn apply_function<F: 'static + Fn(u64) -> (u64, f64) + Send + Sync>(
     b.apply_function("f", r_in, r_out, Box::new(f))
 }
 
+/// Helper function for Boxing static functions and applying them as a `classical_oracle` using
+/// the given UnitaryBuilder.
+pub fn classical_oracle<F: 'static + Fn(u64) -> u64 + Send + Sync>(
+    b: &mut dyn UnitaryBuilder,
+    r_in: Register,
+    r_out: Register,
+    f: F,
+) -> Result<(Register, Register), CircuitError> {
+    b.classical_oracle(r_in, r_out, Box::new(f))
+}
+
+/// Get the position and letter of each non identity term of the Pauli string `pauli` acting on
+/// `n` qubits. Returns an error if the string is longer than `n` or contains anything other than
+/// `I`, `X`, `Y` and `Z`.
+pub(crate) fn parse_pauli_string(pauli: &str, n: u64) -> Result<Vec<(usize, char)>, CircuitError> {
+    if pauli.len() as u64 > n {
+        let message = format!(
+            "Pauli string {:?} is longer than the register ({:?} qubits)",
+            pauli, n
+        );
+        return CircuitError::make_err(message);
+    }
+    pauli
+        .chars()
+        .enumerate()
+        .try_fold(vec![], |mut acc, (i, c)| match c {
+            'X' | 'Y' | 'Z' => {
+                acc.push((i, c));
+                Ok(acc)
+            }
+            'I' => Ok(acc),
+            c => CircuitError::make_err(format!(
+                "Pauli strings may only contain I, X, Y and Z, found {:?}",
+                c
+            )),
+        })
+}
+
+/// Move the unitary op `modifiers[i]`, built on the qubits `0..n`, onto `indices` where qubit `k`
+/// becomes `indices[k]`. Parameterized ops keep a reference to `modifiers` so they can call the
+/// original function. Returns an error for ops which are not unitary.
+fn remap_modifier(
+    modifiers: &Rc<Vec<StateModifier>>,
+    i: usize,
+    indices: &[u64],
+) -> Result<StateModifier, CircuitError> {
+    let remap =
+        |inner: &[u64]| -> Vec<u64> { inner.iter().map(|k| indices[*k as usize]).collect() };
+    let name = modifiers[i].name.clone();
+    match &modifiers[i].modifier {
+        StateModifierType::UnitaryOp(op) => Ok(StateModifier::new_unitary(
+            name,
+            remap_indices(op.clone(), indices),
+        )),
+        StateModifierType::ParameterizedOp(param, _) => {
+            let (modifiers, indices) = (modifiers.clone(), indices.to_vec());
+            let f = Box::new(move |theta| match &modifiers[i].modifier {
+                StateModifierType::ParameterizedOp(_, f) => Ok(remap_indices(f(theta)?, &indices)),
+                _ => unreachable!(),
+            });
+            Ok(StateModifier::new_parameterized(name, param.clone(), f))
+        }
+        StateModifierType::Gate(def, inner) => {
+            Ok(StateModifier::new_gate(name, def.clone(), remap(inner)))
+        }
+        StateModifierType::Loop(body, inner) => {
+            Ok(StateModifier::new_loop(name, body.clone(), remap(inner)))
+        }
+        StateModifierType::Subcircuit(inner) => {
+            let remapped = (0..inner.len())
+                .map(|k| remap_modifier(inner, k, indices))
+                .collect::<Result<Vec<_>, CircuitError>>()?;
+            Ok(StateModifier::new_subcircuit(name, Rc::new(remapped)))
+        }
+        _ => {
+            let message = format!("Cannot repeat non-unitary op {:?}", name);
+            CircuitError::make_err(message)
+        }
+    }
+}
+
+/// Apply the 4x4 `mat` to the single qubits `ra` and `rb`, with `ra` as the most significant bit
+/// of the matrix index.
+fn two_qubit_mat<B: UnitaryBuilder + ?Sized>(
+    b: &mut B,
+    name: &str,
+    ra: Register,
+    rb: Register,
+    mat: Vec<Complex<f64>>,
+) -> Result<(Register, Register), CircuitError> {
+    if ra.n() != 1 || rb.n() != 1 {
+        let message = format!(
+            "{} acts on single qubits, found registers of {:?} and {:?} qubits",
+            name,
+            ra.n(),
+            rb.n()
+        );
+        return CircuitError::make_err(message);
+    }
+    let ra_indices = ra.indices.clone();
+    let r = b.merge(vec![ra, rb])?;
+    let r = b.mat(name, r, mat)?;
+    let (ra, rb) = b.split_absolute(r, &ra_indices)?;
+    Ok((ra, rb.unwrap()))
+}
+
+/// Helper function for Boxing static functions and applying them as a `phase_oracle` using the
+/// given UnitaryBuilder.
+pub fn phase_oracle<F: 'static + Fn(u64) -> bool + Send + Sync>(
+    b: &mut dyn UnitaryBuilder,
+    r: Register,
+    f: F,
+) -> Result<Register, CircuitError> {
+    b.phase_oracle(r, Box::new(f))
+}
+
 /// Helper function for Boxing static functions and building sparse mats using the given
 /// UnitaryBuilder.
 pub fn apply_sparse_function<F: 'static + Fn(u64) -> (u64, f64) + Send + Sync>(
@@ -619,6 +1498,8 @@ pub struct OpBuilder {
     temp_zero_qubits: Vec<Register>,
     temp_one_qubits: Vec<Register>,
     names: Vec<String>,
+    endianness: Endianness,
+    gates: HashMap<String, Rc<GateDefinition>>,
 }
 
 impl OpBuilder {
@@ -627,6 +1508,37 @@ impl OpBuilder {
         OpBuilder::default()
     }
 
+    /// Set the bit order of the values given by measurements built after this call. With
+    /// `Endianness::Big` the first qubit of a measured register is the most significant bit.
+    /// Measurements exported by `qasm::to_qasm` write their classical bits in the same order.
+    ///
+    /// # Example
+    /// ```
+    /// use qip::*;
+    /// # fn main() -> Result<(), CircuitError> {
+    ///
+    /// let mut b = OpBuilder::new();
+    /// b.set_endianness(Endianness::Big);
+    /// let q = b.qubit();
+    /// let r = b.qubit();
+    /// let q = b.not(q);
+    /// let qr = b.merge(vec![q, r])?;
+    /// let (qr, m) = b.measure(qr);
+    ///
+    /// let (_, measured) = run_local::<f64>(&qr)?;
+    /// assert_eq!(measured.get_measurement(&m).unwrap().0, 0b10);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_endianness(&mut self, endianness: Endianness) {
+        self.endianness = endianness;
+    }
+
+    /// The bit order used for measurements, see `set_endianness`.
+    pub fn endianness(&self) -> Endianness {
+        self.endianness
+    }
+
     /// Add a measure op to the pipeline for `r` and return a reference which can
     /// later be used to access the measured value from the results of `pipeline::run`.
     pub fn measure(&mut self, r: Register) -> (Register, MeasurementHandle) {
@@ -639,7 +1551,7 @@ impl OpBuilder {
         let modifier = StateModifier::new_measurement_basis(
             String::from("measure"),
             id,
-            r.indices.clone(),
+            self.endianness.qubit_order(&r.indices),
             angle,
         );
         let modifier = Some(modifier);
@@ -647,6 +1559,411 @@ impl OpBuilder {
         Register::make_measurement_handle(self.get_op_id(), r)
     }
 
+    /// Apply the quantum channel with kraus operators `kraus_ops` to `r`. Each operator is a
+    /// matrix with the same layout as `mat`, if `r` is multiple indices and the operators are
+    /// 2x2 then the channel is applied to each index independently. Returns an error if the
+    /// operators are the wrong size or do not satisfy `sum_k K_k^dagger K_k = I`.
+    pub fn channel(
+        &mut self,
+        name: &str,
+        r: Register,
+        kraus_ops: Vec<Vec<Complex<f64>>>,
+    ) -> Result<Register, CircuitError> {
+        if kraus_ops.is_empty() {
+            return CircuitError::make_str_err("Channel must have at least one kraus operator");
+        }
+        if r.n() > 1 && kraus_ops.iter().all(|k| k.len() == 4) {
+            let rs = self.split_all(r);
+            let rs = rs
+                .into_iter()
+                .map(|r| self.channel(name, r, kraus_ops.clone()))
+                .collect::<Result<Vec<_>, CircuitError>>()?;
+            return self.merge(rs);
+        }
+        let size = 1usize << r.n();
+        if let Some(k) = kraus_ops.iter().find(|k| k.len() != size * size) {
+            let message = format!(
+                "Kraus operators for {:?} qubits must have {:?} entries, found {:?}",
+                r.n(),
+                size * size,
+                k.len()
+            );
+            return CircuitError::make_err(message);
+        }
+        let complete = (0..size).all(|row| {
+            (0..size).all(|col| {
+                let v: Complex<f64> = kraus_ops
+                    .iter()
+                    .map(|k| {
+                        (0..size)
+                            .map(|i| k[i * size + row].conj() * k[i * size + col])
+                            .sum::<Complex<f64>>()
+                    })
+                    .sum();
+                let expected = if row == col { 1.0 } else { 0.0 };
+                (v - expected).norm() < 1e-10
+            })
+        });
+        if !complete {
+            return CircuitError::make_str_err(
+                "Kraus operators must satisfy sum_k K_k^dagger K_k = I",
+            );
+        }
+        let modifier =
+            StateModifier::new_channel(self.get_full_name(name), r.indices.clone(), kraus_ops);
+        Register::merge_with_modifier(self.get_op_id(), vec![r], Some(modifier))
+    }
+
+    /// Reset each qubit of `r` to `|0>`, as though it were measured and flipped if found in `|1>`.
+    /// Since the measured value is discarded this is applied as the channel with kraus operators
+    /// `|0><0|` and `|0><1|`, so states which can represent mixtures reset exactly.
+    ///
+    /// # Example
+    /// ```
+    /// use qip::*;
+    /// # fn main() -> Result<(), CircuitError> {
+    ///
+    /// let mut b = OpBuilder::new();
+    /// let r = b.register(2)?;
+    /// let r = b.hadamard(r);
+    /// let r = b.reset(r);
+    ///
+    /// let (state, _) = run_local::<f64>(&r)?;
+    /// assert_eq!(state.get_state(true)[0].norm(), 1.0);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn reset(&mut self, r: Register) -> Register {
+        let kraus_ops = vec![
+            from_reals(&[1.0, 0.0, 0.0, 0.0]),
+            from_reals(&[0.0, 1.0, 0.0, 0.0]),
+        ];
+        self.channel("reset", r, kraus_ops).unwrap()
+    }
+
+    /// Apply the circuit portion `f` to `rs` a total of `k` times, such as for Grover iterations
+    /// or Trotter steps. The circuit is only built once, every repetition shares the same
+    /// underlying ops rather than copying their matrices. Gates defined on this builder can be
+    /// used inside `f`. Returns an error if `f` allocates or drops any qubits, or contains
+    /// measurements, channels, or classical side channels.
+    ///
+    /// # Example
+    /// ```
+    /// use qip::*;
+    /// # fn main() -> Result<(), CircuitError> {
+    ///
+    /// let mut b = OpBuilder::new();
+    /// let r = b.register(2)?;
+    /// // Four applications of S is the identity.
+    /// let r = b.repeat(4, vec![r], |b, mut rs| Ok(vec![b.s(rs.pop().unwrap())]))?;
+    ///
+    /// let r = b.merge(r)?;
+    /// let (state, _) = run_local::<f64>(&r)?;
+    /// assert_eq!(state.get_state(true)[0].re, 1.0);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn repeat<F: Fn(&mut OpBuilder, Vec<Register>) -> Result<Vec<Register>, CircuitError>>(
+        &mut self,
+        k: u64,
+        rs: Vec<Register>,
+        f: F,
+    ) -> Result<Vec<Register>, CircuitError> {
+        if k == 0 {
+            return Ok(rs);
+        }
+        let original_indices: Vec<_> = rs.iter().map(|r| r.indices.clone()).collect();
+        let flat_indices: Vec<_> = original_indices.iter().flatten().cloned().collect();
+
+        let widths: Vec<u64> = original_indices.iter().map(|r| r.len() as u64).collect();
+        let modifiers = self.build_sub_circuit(&widths, f, || {
+            "Cannot repeat circuits which allocate or drop qubits".to_string()
+        })?;
+        let modifiers = Rc::new(modifiers);
+        let modifiers = (0..modifiers.len())
+            .map(|i| remap_modifier(&modifiers, i, &flat_indices))
+            .collect::<Result<Vec<_>, CircuitError>>()?;
+        let modifiers = Rc::new(modifiers);
+
+        let name = self.get_full_name("repeat");
+        let r = self.merge(rs)?;
+        let r = (0..k).try_fold(r, |r, _| {
+            let modifier = StateModifier::new_subcircuit(name.clone(), modifiers.clone());
+            Register::merge_with_modifier(self.get_op_id(), vec![r], Some(modifier))
+        })?;
+        let (rs, _) = self.split_absolute_many(r, &original_indices)?;
+        Ok(rs)
+    }
+
+    /// Apply the circuit portion `f` to `rs` once for each value of `variable` in `range`, in
+    /// order. The body is only built once, with ops which depend on the loop variable given by
+    /// `variable` as a parameter, such as angle schedules built with `parameterized_mat`. The loop
+    /// is stored as a single op and only unrolled when the circuit is run or exported. Other
+    /// parameters used in the body are bound when the circuit is run, and loops may be nested.
+    /// Returns an error if `f` allocates or drops any qubits, or contains ops which are not
+    /// unitary.
+    ///
+    /// # Example
+    /// ```
+    /// use qip::*;
+    /// use qip::parameters::Parameter;
+    /// use std::f64::consts::PI;
+    /// # fn main() -> Result<(), CircuitError> {
+    ///
+    /// let k = Parameter::new("k");
+    /// let mut b = OpBuilder::new();
+    /// let q = b.qubit();
+    /// let q = b.hadamard(q);
+    /// // Phases of pi/2, pi/4 and pi/8.
+    /// let mut rs = b.for_loop(&k, 1..4, vec![q], |b, mut rs| {
+    ///     let q = rs.pop().unwrap();
+    ///     let phase = |k: f64| {
+    ///         let one = Complex::new(1.0, 0.0);
+    ///         let zero = Complex::new(0.0, 0.0);
+    ///         vec![one, zero, zero, Complex::from_polar(&1.0, &(PI / 2f64.powf(k)))]
+    ///     };
+    ///     Ok(vec![b.parameterized_mat("R", q, &k, Box::new(phase))?])
+    /// })?;
+    /// let q = rs.pop().unwrap();
+    ///
+    /// let (state, _) = run_local::<f64>(&q)?;
+    /// let phase = state.get_state(true)[1].arg();
+    /// assert!((phase - 7.0 * PI / 8.0).abs() < 1e-10);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn for_loop<F: Fn(&mut OpBuilder, Vec<Register>) -> Result<Vec<Register>, CircuitError>>(
+        &mut self,
+        variable: &Parameter,
+        range: Range<i64>,
+        rs: Vec<Register>,
+        f: F,
+    ) -> Result<Vec<Register>, CircuitError> {
+        let original_indices: Vec<_> = rs.iter().map(|r| r.indices.clone()).collect();
+        let flat_indices: Vec<_> = original_indices.iter().flatten().cloned().collect();
+
+        let widths: Vec<u64> = original_indices.iter().map(|r| r.len() as u64).collect();
+        let modifiers = self.build_sub_circuit(&widths, f, || {
+            "Loops must return exactly the qubits they are given".to_string()
+        })?;
+        let n = flat_indices.len() as u64;
+        let body = ClassicalLoop::new(variable.clone(), range, n, modifiers)?;
+
+        let name = self.get_full_name(&format!("for({})", variable.name()));
+        let modifier = StateModifier::new_loop(name, Rc::new(body), flat_indices);
+        let r = self.merge(rs)?;
+        let r = Register::merge_with_modifier(self.get_op_id(), vec![r], Some(modifier))?;
+        let (rs, _) = self.split_absolute_many(r, &original_indices)?;
+        Ok(rs)
+    }
+
+    /// Register the composite gate `name` acting on registers of `widths` qubits, built by `f`
+    /// from fresh registers of those sizes. The gate can then be applied any number of times with
+    /// `apply_gate`, each application sharing the same ops rather than copying them, and is
+    /// exported as a `gate` declaration by `qasm::to_qasm`. Gates already defined on this builder
+    /// can be used inside `f`.
+    ///
+    /// Names must start with a lowercase letter followed by letters, digits and underscores.
+    /// Returns an error if the name is invalid or already defined, or if `f` allocates or drops
+    /// any qubits, or contains ops which are not unitary.
+    ///
+    /// # Example
+    /// ```
+    /// use qip::*;
+    /// # fn main() -> Result<(), CircuitError> {
+    ///
+    /// let mut b = OpBuilder::new();
+    /// b.define_gate("bell", &[1, 1], |b, mut rs| {
+    ///     let rb = rs.pop().unwrap();
+    ///     let ra = b.hadamard(rs.pop().unwrap());
+    ///     let (ra, rb) = b.cnot(ra, rb);
+    ///     Ok(vec![ra, rb])
+    /// })?;
+    ///
+    /// let ra = b.qubit();
+    /// let rb = b.qubit();
+    /// let rs = b.apply_gate("bell", vec![ra, rb])?;
+    /// let r = b.merge(rs)?;
+    /// let (state, _) = run_local::<f64>(&r)?;
+    /// let x = std::f64::consts::FRAC_1_SQRT_2;
+    /// assert!((state.get_state(true)[0b11].re - x).abs() < 1e-10);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn define_gate<
+        F: Fn(&mut OpBuilder, Vec<Register>) -> Result<Vec<Register>, CircuitError>,
+    >(
+        &mut self,
+        name: &str,
+        widths: &[u64],
+        f: F,
+    ) -> Result<(), CircuitError> {
+        check_gate_name(name)?;
+        if self.gates.contains_key(name) {
+            let message = format!("Gate {:?} is already defined", name);
+            return CircuitError::make_err(message);
+        }
+        if widths.is_empty() || widths.contains(&0) {
+            let message = format!(
+                "Gate {:?} needs nonempty registers, found {:?}",
+                name, widths
+            );
+            return CircuitError::make_err(message);
+        }
+
+        let modifiers = self.build_sub_circuit(widths, f, || {
+            format!("Gate {:?} must return exactly the qubits it is given", name)
+        })?;
+        let def = GateDefinition::new(name.to_string(), widths.to_vec(), modifiers)?;
+        self.gates.insert(name.to_string(), Rc::new(def));
+        Ok(())
+    }
+
+    /// Build `f` on a new builder which shares the gates defined on this one, starting from fresh
+    /// registers of `widths` qubits. Returns the ops `f` built, acting on the qubits `0..n` in the
+    /// order of the registers, with debug ops removed. If `f` allocates qubits or doesn't return
+    /// exactly the qubits it was given, returns an error with the message from `message`.
+    fn build_sub_circuit<
+        F: FnOnce(&mut OpBuilder, Vec<Register>) -> Result<Vec<Register>, CircuitError>,
+        M: FnOnce() -> String,
+    >(
+        &self,
+        widths: &[u64],
+        f: F,
+        message: M,
+    ) -> Result<Vec<StateModifier>, CircuitError> {
+        let mut sub_builder = OpBuilder::new();
+        sub_builder.gates = self.gates.clone();
+        let sub_rs = widths
+            .iter()
+            .map(|n| sub_builder.register(*n))
+            .collect::<Result<Vec<_>, CircuitError>>()?;
+        let n = sub_builder.get_qubit_count();
+        let sub_rs = f(&mut sub_builder, sub_rs)?;
+        let sub_r = sub_builder.merge(sub_rs)?;
+        if sub_builder.get_qubit_count() != n || sub_r.n() != n {
+            return CircuitError::make_err(message());
+        }
+        Ok(get_owned_opfns(sub_r)
+            .into_iter()
+            .filter(|modifier| !matches!(modifier.modifier, StateModifierType::Debug(_, _)))
+            .collect())
+    }
+
+    /// Apply the gate `name` registered with `define_gate` to `rs`, which must match the widths
+    /// of the definition.
+    pub fn apply_gate(
+        &mut self,
+        name: &str,
+        rs: Vec<Register>,
+    ) -> Result<Vec<Register>, CircuitError> {
+        let def = match self.gates.get(name) {
+            Some(def) => def.clone(),
+            None => {
+                let message = format!("No gate named {:?} is defined", name);
+                return CircuitError::make_err(message);
+            }
+        };
+        let widths: Vec<u64> = rs.iter().map(|r| r.n()).collect();
+        if widths != def.widths() {
+            let message = format!(
+                "Gate {:?} expects registers of {:?} qubits, found {:?}",
+                name,
+                def.widths(),
+                widths
+            );
+            return CircuitError::make_err(message);
+        }
+        let original_indices: Vec<_> = rs.iter().map(|r| r.indices.clone()).collect();
+        let flat_indices: Vec<_> = original_indices.iter().flatten().cloned().collect();
+
+        let modifier = StateModifier::new_gate(self.get_full_name(name), def, flat_indices);
+        let r = self.merge(rs)?;
+        let r = Register::merge_with_modifier(self.get_op_id(), vec![r], Some(modifier))?;
+        let (rs, _) = self.split_absolute_many(r, &original_indices)?;
+        Ok(rs)
+    }
+
+    /// Borrow `n` ancilla qubits in `|0>`, then apply `compute` to `r` and the ancillas, followed by
+    /// `apply`, followed by the adjoint of `compute` which returns the ancillas to `|0>` so they
+    /// can be reused. `apply` may use the ancillas as controls or apply phases to them but must not
+    /// otherwise change them, and both functions must return the ancillas they were given, or an
+    /// error is returned.
+    ///
+    /// # Example
+    /// ```
+    /// use qip::*;
+    /// # fn main() -> Result<(), CircuitError> {
+    ///
+    /// let mut b = OpBuilder::new();
+    /// let r = b.register(3)?;
+    /// let r = b.not(r);
+    /// // Compute the AND of the first two qubits into an ancilla and copy it onto the third.
+    /// let r = b.with_ancilla(
+    ///     1,
+    ///     r,
+    ///     Box::new(|b, r, anc| {
+    ///         let (r, out) = b.split(r, &[0, 1])?;
+    ///         let (r, anc) = b.cnot(r, anc);
+    ///         Ok((b.merge(vec![r, out.unwrap()])?, anc))
+    ///     }),
+    ///     Box::new(|b, r, anc| {
+    ///         let (r, out) = b.split(r, &[0, 1])?;
+    ///         let (anc, out) = b.cnot(anc, out.unwrap());
+    ///         Ok((b.merge(vec![r, out])?, anc))
+    ///     }),
+    /// )?;
+    ///
+    /// let (state, _) = run_local::<f64>(&r)?;
+    /// // The third qubit was flipped back to |0>, the ancilla (qubit 3) is |0> again.
+    /// assert!((state.get_state(true)[0b0011].re - 1.0).abs() < 1e-10);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_ancilla(
+        &mut self,
+        n: u64,
+        r: Register,
+        compute: Box<AncillaFn>,
+        apply: Box<AncillaFn>,
+    ) -> Result<Register, CircuitError> {
+        let anc = self.get_temp_register(n, false);
+        let anc_indices = anc.indices.clone();
+        let check_returned = |anc: &Register| {
+            if anc.indices == anc_indices {
+                Ok(())
+            } else {
+                let message = format!(
+                    "Ancilla qubits {:?} were not returned, found {:?}",
+                    anc_indices, anc.indices
+                );
+                CircuitError::make_err(message)
+            }
+        };
+
+        self.push_name_scope("Ancilla");
+        let (r, anc) = compute(self, r, anc)?;
+        check_returned(&anc)?;
+        let first_id = self.op_id;
+        let (r, anc) = apply(self, r, anc)?;
+        check_returned(&anc)?;
+        let mut applied = get_opfns_since(&r, first_id);
+        applied.extend(get_opfns_since(&anc, first_id));
+        check_ancillas_preserved(&applied, &anc_indices)?;
+        let mut rs = inverter(self, vec![r, anc], |b, mut rs| {
+            let anc = rs.pop().unwrap();
+            let r = rs.pop().unwrap();
+            let (r, anc) = compute(b, r, anc)?;
+            Ok(vec![r, anc])
+        })?;
+        self.pop_name_scope();
+
+        let anc = rs.pop().unwrap();
+        let r = rs.pop().unwrap();
+        self.return_temp_register(anc, false);
+        Ok(r)
+    }
+
     /// Get the current count of created qubits.
     pub fn get_qubit_count(&self) -> u64 {
         self.qubit_index
@@ -671,11 +1988,35 @@ impl OpBuilder {
         (zeros, ones)
     }
 
-    fn get_op_id(&mut self) -> u64 {
+    /// Take all the temps currently in holding.
+    pub(crate) fn take_temp_registers(&mut self) -> Vec<Register> {
+        let mut temps: Vec<_> = self.temp_zero_qubits.drain(..).collect();
+        temps.append(&mut self.temp_one_qubits);
+        temps
+    }
+
+    pub(crate) fn get_op_id(&mut self) -> u64 {
         let tmp = self.op_id;
         self.op_id += 1;
         tmp
     }
+
+    /// Apply `modifier` to the qubits at `indices` within `r`, returning the selected qubits
+    /// followed by the rest of `r`.
+    pub(crate) fn apply_modifier_to_indices(
+        &mut self,
+        r: Register,
+        indices: &[u64],
+        modifier: StateModifier,
+    ) -> Result<Register, CircuitError> {
+        let (sel, rest) = self.split_absolute(r, indices)?;
+        let id = self.get_op_id();
+        let sel = Register::merge_with_modifier(id, vec![sel], Some(modifier))?;
+        match rest {
+            Some(rest) => self.merge(vec![sel, rest]),
+            None => Ok(sel),
+        }
+    }
 }
 
 impl UnitaryBuilder for OpBuilder {
@@ -755,6 +2096,7 @@ impl UnitaryBuilder for OpBuilder {
         ConditionalContextBuilder {
             parent_builder: self,
             conditioned_register: Some(r),
+            anticondition: false,
         }
     }
 
@@ -798,7 +2140,7 @@ impl UnitaryBuilder for OpBuilder {
         natural_order: bool,
     ) -> Result<Register, CircuitError> {
         // Special case for broadcasting ops
-        if r.indices.len() > 1 && mat.len() == (2 * 2) {
+        if r.indices.len() > 1 && mat.len() == 2 {
             let rs = self.split_all(r);
             let rs = rs
                 .into_iter()
@@ -847,12 +2189,23 @@ impl UnitaryBuilder for OpBuilder {
         Register::merge_with_modifier(self.get_op_id(), rs, modifier)
     }
 
+    fn merge_with_parameterized_op(
+        &mut self,
+        rs: Vec<Register>,
+        name: String,
+        param: Parameter,
+        f: Box<ParameterizedOpFn>,
+    ) -> Result<Register, CircuitError> {
+        let modifier = StateModifier::new_parameterized(self.get_full_name(&name), param, f);
+        Register::merge_with_modifier(self.get_op_id(), rs, Some(modifier))
+    }
+
     fn stochastic_measure(&mut self, r: Register) -> (Register, u64) {
         let id = self.get_op_id();
         let modifier = StateModifier::new_stochastic_measurement(
             String::from("stochastic"),
             id,
-            r.indices.clone(),
+            self.endianness.qubit_order(&r.indices),
         );
         let modifier = Some(modifier);
         let r = Register::merge_with_modifier(id, vec![r], modifier).unwrap();
@@ -908,6 +2261,7 @@ impl UnitaryBuilder for OpBuilder {
 pub struct ConditionalContextBuilder<'a> {
     parent_builder: &'a mut dyn UnitaryBuilder,
     conditioned_register: Option<Register>,
+    anticondition: bool,
 }
 
 impl<'a> fmt::Debug for ConditionalContextBuilder<'a> {
@@ -923,9 +2277,14 @@ impl<'a> fmt::Debug for ConditionalContextBuilder<'a> {
 impl<'a> ConditionalContextBuilder<'a> {
     /// Release the Register used to build this builder
     pub fn release_register(self: Self) -> Register {
-        match self.conditioned_register {
+        let r = match self.conditioned_register {
             Some(r) => r,
             None => panic!("Conditional context builder failed to populate register."),
+        };
+        if self.anticondition {
+            self.parent_builder.not(r)
+        } else {
+            r
         }
     }
 
@@ -959,6 +2318,7 @@ impl<'a> UnitaryBuilder for ConditionalContextBuilder<'a> {
         ConditionalContextBuilder {
             parent_builder: self,
             conditioned_register: Some(r),
+            anticondition: false,
         }
     }
 
@@ -1003,7 +2363,7 @@ impl<'a> UnitaryBuilder for ConditionalContextBuilder<'a> {
         natural_order: bool,
     ) -> Result<Register, CircuitError> {
         // Special case for applying mat to each Register in collection.
-        if r.indices.len() > 1 && mat.len() == (2 * 2) {
+        if r.indices.len() > 1 && mat.len() == 2 {
             let rs = self.split_all(r);
             let rs = rs
                 .into_iter()
@@ -1105,6 +2465,27 @@ impl<'a> UnitaryBuilder for ConditionalContextBuilder<'a> {
         }
     }
 
+    fn merge_with_parameterized_op(
+        &mut self,
+        mut rs: Vec<Register>,
+        name: String,
+        param: Parameter,
+        f: Box<ParameterizedOpFn>,
+    ) -> Result<Register, CircuitError> {
+        let cr = self.get_conditional_register();
+        let cr_indices = cr.indices.clone();
+        let name = format!("C({})", name);
+        let f_cr_indices = cr_indices.clone();
+        let f = Box::new(move |theta| make_control_op(f_cr_indices.clone(), f(theta)?));
+        rs.insert(0, cr);
+        let r = self
+            .parent_builder
+            .merge_with_parameterized_op(rs, name, param, f)?;
+        let (cr, r) = self.split_absolute(r, &cr_indices)?;
+        self.set_conditional_register(cr);
+        Ok(r.unwrap())
+    }
+
     fn stochastic_measure(&mut self, r: Register) -> (Register, u64) {
         self.parent_builder.stochastic_measure(r)
     }
@@ -1193,6 +2574,34 @@ where
     (r, rs)
 }
 
+/// Anticondition a circuit defined by `f` using `cr`, so it only acts when `cr` is `|0n>`.
+///
+/// # Example
+/// ```
+/// use qip::*;
+///
+/// let mut b = qip::OpBuilder::new();
+/// let qa = b.qubit();
+/// let qb = b.qubit();
+///
+/// // Flip qb only if qa is |0>, the same as b.ncnot(qa, qb).
+/// let (qa, qb) = anticondition(&mut b, qa, qb, |b, q| b.not(q));
+/// ```
+pub fn anticondition<F, RS, OS>(
+    b: &mut dyn UnitaryBuilder,
+    cr: Register,
+    rs: RS,
+    f: F,
+) -> (Register, OS)
+where
+    F: FnOnce(&mut dyn UnitaryBuilder, RS) -> OS,
+{
+    let mut c = b.with_anticondition(cr);
+    let rs = f(&mut c, rs);
+    let r = c.release_register();
+    (r, rs)
+}
+
 /// Condition a circuit defined by `f` using `cr`, better supports Result types.
 ///
 /// # Example
@@ -1227,3 +2636,160 @@ where
     let r = c.release_register();
     Ok((r, rs))
 }
+
+/// Make a unitary whose first column is `amplitudes`, using the householder reflection which maps
+/// `|0...0>` to the state up to the phase of its first amplitude.
+fn make_state_preparation_matrix(
+    n: u64,
+    amplitudes: &[Complex<f64>],
+) -> Result<Vec<Complex<f64>>, CircuitError> {
+    let size = 1usize << n;
+    if amplitudes.len() != size {
+        return CircuitError::make_err(format!(
+            "Expected {} amplitudes for {} qubits, found {}",
+            size,
+            n,
+            amplitudes.len()
+        ));
+    }
+    let norm: f64 = amplitudes.iter().map(|c| c.norm_sqr()).sum();
+    if (norm - 1.0).abs() > 1e-10 {
+        return CircuitError::make_err(format!("Expected normalized amplitudes, found {}", norm));
+    }
+    let phase = if amplitudes[0].norm() > 1e-10 {
+        amplitudes[0] / amplitudes[0].norm()
+    } else {
+        Complex::one()
+    };
+    // The reflection I - 2ww^dagger/|w|^2 with w = phase|0> - psi takes phase|0> to psi.
+    let w: Vec<Complex<f64>> = amplitudes
+        .iter()
+        .enumerate()
+        .map(|(i, a)| if i == 0 { phase - a } else { -a })
+        .collect();
+    let w_norm: f64 = w.iter().map(|c| c.norm_sqr()).sum();
+    let mut mat = vec![Complex::zero(); size * size];
+    (0..size).for_each(|row| {
+        (0..size).for_each(|col| {
+            let mut v = if row == col {
+                Complex::one()
+            } else {
+                Complex::zero()
+            };
+            if w_norm > 1e-20 {
+                v -= w[row] * w[col].conj() * 2.0 / w_norm;
+            }
+            if col == 0 {
+                v *= phase;
+            }
+            // Matrices are indexed with the first qubit as the most significant bit.
+            let mat_row = flip_bits(n as usize, row as u64) as usize;
+            let mat_col = flip_bits(n as usize, col as u64) as usize;
+            mat[mat_row * size + mat_col] = v;
+        })
+    });
+    Ok(mat)
+}
+
+/// Find `(theta, phi, lambda)` with `mat = e^{i alpha} U3(theta, phi, lambda)` for some global
+/// phase `alpha`, see `UnitaryBuilder::u3`, for a 2x2 unitary `mat` given row major. Angles are in
+/// `(-pi, pi]`, with `theta` in `[0, pi]`. When `theta` is `0` only `phi + lambda` matters and
+/// `phi` is `0`, when it is `pi` only `phi - lambda` matters and `lambda` is `0`. Returns an error
+/// if `mat` is not a 2x2 unitary.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let h = std::f64::consts::FRAC_1_SQRT_2;
+/// let hadamard = [h, h, h, -h].iter().map(|x| Complex::new(*x, 0.0)).collect::<Vec<_>>();
+/// let (theta, phi, lambda) = u3_angles(&hadamard)?;
+/// assert!((theta - std::f64::consts::FRAC_PI_2).abs() < 1e-10);
+/// assert!(phi.abs() < 1e-10);
+/// assert!((lambda - std::f64::consts::PI).abs() < 1e-10);
+/// # Ok(())
+/// # }
+/// ```
+pub fn u3_angles(mat: &[Complex<f64>]) -> Result<(f64, f64, f64), CircuitError> {
+    if mat.len() != 4 {
+        let message = format!("Expected a 2x2 matrix, found {:?} entries", mat.len());
+        return CircuitError::make_err(message);
+    }
+    let unitary = (0..2).all(|i| {
+        (0..2).all(|j| {
+            let dot = mat[2 * i] * mat[2 * j].conj() + mat[2 * i + 1] * mat[2 * j + 1].conj();
+            let expected = if i == j { 1.0 } else { 0.0 };
+            (dot - Complex::new(expected, 0.0)).norm() < 1e-8
+        })
+    });
+    if !unitary {
+        return CircuitError::make_str_err("Matrix is not unitary.");
+    }
+    let wrap = |angle: f64| {
+        let angle = angle.rem_euclid(2.0 * std::f64::consts::PI);
+        if angle > std::f64::consts::PI {
+            angle - 2.0 * std::f64::consts::PI
+        } else {
+            angle
+        }
+    };
+    let (cos, sin) = (mat[0].norm(), mat[2].norm());
+    let theta = 2.0 * sin.atan2(cos);
+    let epsilon = 1e-10;
+    let (phi, lambda) = if sin < epsilon {
+        // Diagonal, the phase of the top left is the global phase.
+        (0.0, (mat[3] / mat[0]).arg())
+    } else if cos < epsilon {
+        // Anti-diagonal, the phase of the top right is the global phase.
+        ((-mat[2] / mat[1]).arg(), 0.0)
+    } else {
+        let alpha = mat[0].arg();
+        (mat[2].arg() - alpha, (-mat[1]).arg() - alpha)
+    };
+    Ok((theta, wrap(phi), wrap(lambda)))
+}
+
+/// The matrix of `U3(theta, phi, lambda)`, see `UnitaryBuilder::u3`.
+pub(crate) fn u3_matrix(theta: f64, phi: f64, lambda: f64) -> Vec<Complex<f64>> {
+    let (sin, cos) = (theta / 2.0).sin_cos();
+    vec![
+        Complex::new(cos, 0.0),
+        -Complex::from_polar(&sin, &lambda),
+        Complex::from_polar(&sin, &phi),
+        Complex::from_polar(&cos, &(phi + lambda)),
+    ]
+}
+
+/// The matrix of `exp(-i theta/2 X X)`, used by `rxx` and `ms`.
+fn rxx_matrix(theta: f64) -> Vec<Complex<f64>> {
+    let z = Complex::zero();
+    let c = Complex::new((theta / 2.0).cos(), 0.0);
+    let s = Complex::new(0.0, -(theta / 2.0).sin());
+    vec![c, z, z, s, z, c, s, z, z, s, c, z, s, z, z, c]
+}
+
+fn rx_matrix(theta: f64) -> Vec<Complex<f64>> {
+    let (sin, cos) = (theta / 2.0).sin_cos();
+    from_tuples(&[(cos, 0.0), (0.0, -sin), (0.0, -sin), (cos, 0.0)])
+}
+
+fn ry_matrix(theta: f64) -> Vec<Complex<f64>> {
+    let (sin, cos) = (theta / 2.0).sin_cos();
+    from_reals(&[cos, -sin, sin, cos])
+}
+
+fn rz_matrix(theta: f64) -> Vec<Complex<f64>> {
+    let theta_2 = theta / 2.0;
+    let phase_plus = Complex {
+        re: 0.0,
+        im: theta_2,
+    }
+    .exp();
+    let phase_minus = Complex {
+        re: 0.0,
+        im: -theta_2,
+    }
+    .exp();
+    vec![phase_minus, Complex::zero(), Complex::zero(), phase_plus]
+}
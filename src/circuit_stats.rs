@@ -0,0 +1,174 @@
+use crate::pipeline::{
+    get_opfns_and_frontier, get_required_state_size_from_frontier, StateModifier, StateModifierType,
+};
+use crate::state_ops::{get_index, num_indices, UnitaryOp};
+use crate::Register;
+use std::collections::HashMap;
+
+/// Summary statistics about the circuit which produces a Register.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct CircuitStats {
+    /// Number of qubits used by the circuit.
+    pub n: u64,
+    /// Number of gates, measurements and channels of each type, keyed by name with scopes removed
+    /// (for example `H`, `C(X)`, `measure`).
+    pub gate_counts: HashMap<String, usize>,
+    /// Number of unitary ops which act on exactly two qubits.
+    pub two_qubit_gates: usize,
+    /// Number of unitary ops which act on more than two qubits.
+    pub multi_qubit_gates: usize,
+    /// Number of classical side channels, the ops they produce depend on measured values and are
+    /// not included in the other statistics.
+    pub side_channels: usize,
+    /// Length of the longest chain of ops where each depends on a qubit touched by the previous.
+    pub depth: usize,
+}
+
+impl CircuitStats {
+    /// Compute the statistics of the circuit which produces `r`.
+    pub fn new(r: &Register) -> CircuitStats {
+        let (frontier, ops) = get_opfns_and_frontier(r);
+        let n = get_required_state_size_from_frontier(&frontier);
+        let mut stats = CircuitStats {
+            n,
+            ..Default::default()
+        };
+        let mut layers = vec![0; n as usize];
+        ops.into_iter()
+            .for_each(|modifier| stats.add_modifier(&mut layers, modifier));
+        stats
+    }
+
+    /// Add `modifier` to the statistics, `layers` holds the depth so far of each qubit.
+    fn add_modifier(&mut self, layers: &mut [usize], modifier: &StateModifier) {
+        let indices: Vec<u64> = match &modifier.modifier {
+            StateModifierType::UnitaryOp(op) => self.add_unitary_op(op),
+            // The qubits an op acts on don't depend on the parameter value.
+            StateModifierType::ParameterizedOp(_, f) => match f(0.0) {
+                Ok(op) => self.add_unitary_op(&op),
+                Err(_) => return,
+            },
+            StateModifierType::MeasureState(_, indices, _)
+            | StateModifierType::StochasticMeasureState(_, indices, _)
+            | StateModifierType::Channel(indices, _) => indices.clone(),
+            StateModifierType::Subcircuit(modifiers) => {
+                modifiers
+                    .iter()
+                    .for_each(|modifier| self.add_modifier(layers, modifier));
+                return;
+            }
+            StateModifierType::Gate(def, indices) => {
+                def.instantiate(indices)
+                    .iter()
+                    .for_each(|modifier| self.add_modifier(layers, modifier));
+                return;
+            }
+            // The qubits each op acts on don't depend on the values of other parameters.
+            StateModifierType::Loop(body, indices) => {
+                let params = body
+                    .free_parameters()
+                    .into_iter()
+                    .map(|param| (param.name().to_string(), 0.0))
+                    .collect();
+                if let Ok(ops) = body.unroll(indices, Some(&params)) {
+                    ops.iter()
+                        .for_each(|modifier| self.add_modifier(layers, modifier));
+                }
+                return;
+            }
+            StateModifierType::SideChannelModifiers(_, _) => {
+                self.side_channels += 1;
+                return;
+            }
+            StateModifierType::Debug(_, _) => return,
+        };
+        *self
+            .gate_counts
+            .entry(gate_type(&modifier.name))
+            .or_insert(0) += 1;
+        let layer = indices
+            .iter()
+            .map(|indx| layers[*indx as usize])
+            .max()
+            .unwrap_or(0)
+            + 1;
+        indices
+            .iter()
+            .for_each(|indx| layers[*indx as usize] = layer);
+        self.depth = self.depth.max(layer);
+    }
+
+    /// Count `op` by the number of qubits it acts on, return its indices.
+    fn add_unitary_op(&mut self, op: &UnitaryOp) -> Vec<u64> {
+        let indices: Vec<u64> = (0..num_indices(op)).map(|i| get_index(op, i)).collect();
+        match indices.len() {
+            0 | 1 => {}
+            2 => self.two_qubit_gates += 1,
+            _ => self.multi_qubit_gates += 1,
+        }
+        indices
+    }
+
+    /// Total number of gates, measurements and channels in the circuit.
+    pub fn total_gates(&self) -> usize {
+        self.gate_counts.values().sum()
+    }
+}
+
+/// Remove name scopes from an op name, keeping the `C(...)` wrappers added by conditions.
+fn gate_type(name: &str) -> String {
+    let op_start = name.find('(').unwrap_or(name.len());
+    let name = match name[..op_start].rfind('/') {
+        Some(i) => &name[i + 1..],
+        None => name,
+    };
+    if name.starts_with("C(") && name.ends_with(')') {
+        format!("C({})", gate_type(&name[2..name.len() - 1]))
+    } else {
+        name.to_string()
+    }
+}
+
+#[cfg(test)]
+mod circuit_stats_tests {
+    use super::*;
+    use crate::{CircuitError, OpBuilder, UnitaryBuilder};
+
+    #[test]
+    fn test_gate_type() {
+        assert_eq!(gate_type("H"), "H");
+        assert_eq!(gate_type("a/b/H"), "H");
+        assert_eq!(gate_type("a/C(b/X)"), "C(X)");
+        assert_eq!(gate_type("C(C(a/Z))"), "C(C(Z))");
+    }
+
+    #[test]
+    fn test_stats() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let q = b.qubit();
+        let r = b.qubit();
+        let s = b.qubit();
+        let q = b.hadamard(q);
+        let r = b.hadamard(r);
+        let (q, r) = b.cnot(q, r);
+        let (q, s) = b.cnot(q, s);
+        let qr = b.merge(vec![q, r])?;
+        let (qr, s) = b.cnot(qr, s);
+        let s = b.x(s);
+        let (s, _) = b.measure(s);
+        let qrs = b.merge(vec![qr, s])?;
+
+        let stats = CircuitStats::new(&qrs);
+        assert_eq!(stats.n, 3);
+        assert_eq!(stats.gate_counts.get("H"), Some(&2));
+        assert_eq!(stats.gate_counts.get("C(not)"), Some(&3));
+        assert_eq!(stats.gate_counts.get("X"), Some(&1));
+        assert_eq!(stats.gate_counts.get("measure"), Some(&1));
+        assert_eq!(stats.total_gates(), 7);
+        assert_eq!(stats.two_qubit_gates, 2);
+        assert_eq!(stats.multi_qubit_gates, 1);
+        assert_eq!(stats.side_channels, 0);
+        assert_eq!(stats.depth, 6);
+        Ok(())
+    }
+}
@@ -0,0 +1,284 @@
+use crate::pipeline::{MeasuredResults, MeasurementHandle};
+use crate::Precision;
+use std::collections::HashMap;
+use std::ops::{BitAnd, BitOr, BitXor, Not};
+use std::rc::Rc;
+
+/// The operations a `ClassicalRegister` is built from.
+#[derive(Debug)]
+enum ClassicalExpr {
+    Measurement(MeasurementHandle),
+    Constant(u64),
+    And(ClassicalRegister, ClassicalRegister),
+    Or(ClassicalRegister, ClassicalRegister),
+    Xor(ClassicalRegister, ClassicalRegister),
+    Not(ClassicalRegister),
+    Concat(ClassicalRegister, ClassicalRegister),
+    Bit(ClassicalRegister, u64),
+    Parity(ClassicalRegister),
+    Compare(ClassicalRegister, ClassicalRegister, Comparison),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A classical value of `n` bits computed from the results of measurements, such as a syndrome
+/// assembled from several stabilizer measurements. Registers are combined with `&`, `|`, `^` and
+/// `!` (or with constants) and compared to give single bit conditions, which feed conditional ops
+/// through `UnitaryBuilder::classical_register_if` and are read from the results of a run with
+/// `MeasuredResults::get_classical`.
+///
+/// Bits are in the same order as `MeasuredResults::get_measurement`, and operations on registers
+/// of different sizes give a register as large as the larger one.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::classical::ClassicalRegister;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let ra = b.qubit();
+/// let rb = b.qubit();
+/// let ra = b.hadamard(ra);
+/// let rb = b.hadamard(rb);
+/// let (ra, ma) = b.measure(ra);
+/// let (rb, mb) = b.measure(rb);
+/// let both = ClassicalRegister::from(&ma) & ClassicalRegister::from(&mb);
+///
+/// let r = b.merge(vec![ra, rb])?;
+/// let (_, measured) = run_local::<f64>(&r)?;
+/// let (a, _) = measured.get_measurement(&ma).unwrap();
+/// let (b, _) = measured.get_measurement(&mb).unwrap();
+/// assert_eq!(measured.get_classical(&both), Some(a & b));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClassicalRegister {
+    n: u64,
+    expr: Rc<ClassicalExpr>,
+}
+
+impl ClassicalRegister {
+    fn new(n: u64, expr: ClassicalExpr) -> ClassicalRegister {
+        ClassicalRegister {
+            n,
+            expr: Rc::new(expr),
+        }
+    }
+
+    /// The register holding the outcome of the measurement `handle`.
+    pub fn from_measurement(handle: &MeasurementHandle) -> ClassicalRegister {
+        let n = handle.clone_register().n();
+        ClassicalRegister::new(n, ClassicalExpr::Measurement(handle.clone()))
+    }
+
+    /// A register of `n` bits holding `value`, truncated to fit.
+    pub fn constant(value: u64, n: u64) -> ClassicalRegister {
+        let n = n.clamp(1, 64);
+        ClassicalRegister::new(n, ClassicalExpr::Constant(value & mask(n)))
+    }
+
+    /// The number of bits in the register.
+    pub fn n(&self) -> u64 {
+        self.n
+    }
+
+    /// Get a register with the bits of `self` followed by the bits of `other` above them.
+    pub fn concat(&self, other: &ClassicalRegister) -> ClassicalRegister {
+        let n = (self.n + other.n).min(64);
+        ClassicalRegister::new(n, ClassicalExpr::Concat(self.clone(), other.clone()))
+    }
+
+    /// Get the single bit register holding bit `i` of `self`.
+    pub fn bit(&self, i: u64) -> ClassicalRegister {
+        ClassicalRegister::new(1, ClassicalExpr::Bit(self.clone(), i))
+    }
+
+    /// Get the single bit register holding the parity of the bits of `self`.
+    pub fn parity(&self) -> ClassicalRegister {
+        ClassicalRegister::new(1, ClassicalExpr::Parity(self.clone()))
+    }
+
+    fn compare<T: Into<ClassicalRegister>>(&self, other: T, cmp: Comparison) -> ClassicalRegister {
+        ClassicalRegister::new(1, ClassicalExpr::Compare(self.clone(), other.into(), cmp))
+    }
+
+    /// Single bit register which is `1` when `self == other`.
+    pub fn equals<T: Into<ClassicalRegister>>(&self, other: T) -> ClassicalRegister {
+        self.compare(other, Comparison::Eq)
+    }
+
+    /// Single bit register which is `1` when `self != other`.
+    pub fn not_equals<T: Into<ClassicalRegister>>(&self, other: T) -> ClassicalRegister {
+        self.compare(other, Comparison::Ne)
+    }
+
+    /// Single bit register which is `1` when `self < other`.
+    pub fn less_than<T: Into<ClassicalRegister>>(&self, other: T) -> ClassicalRegister {
+        self.compare(other, Comparison::Lt)
+    }
+
+    /// Single bit register which is `1` when `self <= other`.
+    pub fn less_equal<T: Into<ClassicalRegister>>(&self, other: T) -> ClassicalRegister {
+        self.compare(other, Comparison::Le)
+    }
+
+    /// Single bit register which is `1` when `self > other`.
+    pub fn greater_than<T: Into<ClassicalRegister>>(&self, other: T) -> ClassicalRegister {
+        self.compare(other, Comparison::Gt)
+    }
+
+    /// Single bit register which is `1` when `self >= other`.
+    pub fn greater_equal<T: Into<ClassicalRegister>>(&self, other: T) -> ClassicalRegister {
+        self.compare(other, Comparison::Ge)
+    }
+
+    /// The measurements the value of the register depends on, ordered by id and without repeats.
+    pub fn handles(&self) -> Vec<MeasurementHandle> {
+        let mut handles = vec![];
+        self.collect_handles(&mut handles);
+        handles.sort();
+        handles.dedup();
+        handles
+    }
+
+    fn collect_handles(&self, handles: &mut Vec<MeasurementHandle>) {
+        match &*self.expr {
+            ClassicalExpr::Measurement(handle) => handles.push(handle.clone()),
+            ClassicalExpr::Constant(_) => {}
+            ClassicalExpr::Not(a) | ClassicalExpr::Bit(a, _) | ClassicalExpr::Parity(a) => {
+                a.collect_handles(handles)
+            }
+            ClassicalExpr::And(a, b)
+            | ClassicalExpr::Or(a, b)
+            | ClassicalExpr::Xor(a, b)
+            | ClassicalExpr::Concat(a, b)
+            | ClassicalExpr::Compare(a, b, _) => {
+                a.collect_handles(handles);
+                b.collect_handles(handles);
+            }
+        }
+    }
+
+    /// Compute the value of the register given the measured value for each handle id, returns
+    /// None if a measurement it depends on is missing.
+    pub(crate) fn evaluate(&self, measured: &HashMap<u64, u64>) -> Option<u64> {
+        let value = match &*self.expr {
+            ClassicalExpr::Measurement(handle) => *measured.get(&handle.get_id())?,
+            ClassicalExpr::Constant(value) => *value,
+            ClassicalExpr::And(a, b) => a.evaluate(measured)? & b.evaluate(measured)?,
+            ClassicalExpr::Or(a, b) => a.evaluate(measured)? | b.evaluate(measured)?,
+            ClassicalExpr::Xor(a, b) => a.evaluate(measured)? ^ b.evaluate(measured)?,
+            ClassicalExpr::Not(a) => !a.evaluate(measured)?,
+            ClassicalExpr::Concat(a, b) => {
+                let high = b.evaluate(measured)?;
+                let high = if a.n < 64 { high << a.n } else { 0 };
+                a.evaluate(measured)? | high
+            }
+            ClassicalExpr::Bit(a, i) => {
+                let value = a.evaluate(measured)?;
+                if *i < 64 {
+                    (value >> i) & 1
+                } else {
+                    0
+                }
+            }
+            ClassicalExpr::Parity(a) => u64::from(a.evaluate(measured)?.count_ones()) & 1,
+            ClassicalExpr::Compare(a, b, cmp) => {
+                let (a, b) = (a.evaluate(measured)?, b.evaluate(measured)?);
+                let result = match cmp {
+                    Comparison::Eq => a == b,
+                    Comparison::Ne => a != b,
+                    Comparison::Lt => a < b,
+                    Comparison::Le => a <= b,
+                    Comparison::Gt => a > b,
+                    Comparison::Ge => a >= b,
+                };
+                u64::from(result)
+            }
+        };
+        Some(value & mask(self.n))
+    }
+}
+
+/// The mask for the lowest `n` bits.
+fn mask(n: u64) -> u64 {
+    if n >= 64 {
+        !0
+    } else {
+        (1 << n) - 1
+    }
+}
+
+impl From<&MeasurementHandle> for ClassicalRegister {
+    fn from(handle: &MeasurementHandle) -> Self {
+        ClassicalRegister::from_measurement(handle)
+    }
+}
+
+/// Constants are as wide as the bits needed to hold them.
+impl From<u64> for ClassicalRegister {
+    fn from(value: u64) -> Self {
+        ClassicalRegister::constant(value, u64::from(64 - value.leading_zeros()))
+    }
+}
+
+impl<T: Into<ClassicalRegister>> BitAnd<T> for ClassicalRegister {
+    type Output = ClassicalRegister;
+
+    fn bitand(self, other: T) -> ClassicalRegister {
+        let other = other.into();
+        ClassicalRegister::new(self.n.max(other.n), ClassicalExpr::And(self, other))
+    }
+}
+
+impl<T: Into<ClassicalRegister>> BitOr<T> for ClassicalRegister {
+    type Output = ClassicalRegister;
+
+    fn bitor(self, other: T) -> ClassicalRegister {
+        let other = other.into();
+        ClassicalRegister::new(self.n.max(other.n), ClassicalExpr::Or(self, other))
+    }
+}
+
+impl<T: Into<ClassicalRegister>> BitXor<T> for ClassicalRegister {
+    type Output = ClassicalRegister;
+
+    fn bitxor(self, other: T) -> ClassicalRegister {
+        let other = other.into();
+        ClassicalRegister::new(self.n.max(other.n), ClassicalExpr::Xor(self, other))
+    }
+}
+
+impl Not for ClassicalRegister {
+    type Output = ClassicalRegister;
+
+    fn not(self) -> ClassicalRegister {
+        ClassicalRegister::new(self.n, ClassicalExpr::Not(self))
+    }
+}
+
+impl<P: Precision> MeasuredResults<P> {
+    /// Get the value of a classical register from these results, or None if a measurement it
+    /// depends on was not made.
+    pub fn get_classical(&self, cr: &ClassicalRegister) -> Option<u64> {
+        let measured = cr
+            .handles()
+            .iter()
+            .map(|handle| {
+                self.get_measurement(handle)
+                    .map(|(value, _)| (handle.get_id(), value))
+            })
+            .collect::<Option<HashMap<_, _>>>()?;
+        cr.evaluate(&measured)
+    }
+}
@@ -0,0 +1,267 @@
+use crate::errors::CircuitError;
+use crate::rng::random;
+use crate::{Register, UnitaryBuilder};
+
+/// Gates making up a `CliffordCircuit`, acting on qubits given by their position in the Register
+/// the circuit is applied to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CliffordGate {
+    /// Hadamard.
+    H(u64),
+    /// Phase by `i` on `|1>`.
+    S(u64),
+    /// Inverse of `S`.
+    Sdag(u64),
+    /// Pauli X.
+    X(u64),
+    /// Pauli Z.
+    Z(u64),
+    /// Not on the second qubit controlled by the first.
+    CNOT(u64, u64),
+    /// Phase by `-1` on `|11>`.
+    CZ(u64, u64),
+    /// Swap of two qubits.
+    Swap(u64, u64),
+}
+
+impl CliffordGate {
+    /// Get the inverse of the gate.
+    pub fn inverse(self) -> CliffordGate {
+        match self {
+            CliffordGate::S(i) => CliffordGate::Sdag(i),
+            CliffordGate::Sdag(i) => CliffordGate::S(i),
+            gate => gate,
+        }
+    }
+
+    /// Get the qubits the gate acts on.
+    pub fn qubits(self) -> Vec<u64> {
+        match self {
+            CliffordGate::H(i)
+            | CliffordGate::S(i)
+            | CliffordGate::Sdag(i)
+            | CliffordGate::X(i)
+            | CliffordGate::Z(i) => vec![i],
+            CliffordGate::CNOT(i, j) | CliffordGate::CZ(i, j) | CliffordGate::Swap(i, j) => {
+                vec![i, j]
+            }
+        }
+    }
+}
+
+/// A circuit of Clifford gates on `n` qubits which can be applied to a Register with a builder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CliffordCircuit {
+    n: u64,
+    gates: Vec<CliffordGate>,
+}
+
+impl CliffordCircuit {
+    /// Make a circuit on `n` qubits from `gates`, applied in order. Returns an error if a gate
+    /// acts on a qubit outside of the circuit or uses the same qubit twice.
+    pub fn new(n: u64, gates: Vec<CliffordGate>) -> Result<Self, CircuitError> {
+        for gate in &gates {
+            let qubits = gate.qubits();
+            if qubits.iter().any(|q| *q >= n) {
+                let message = format!("Gate {:?} acts outside of {:?} qubits", gate, n);
+                return CircuitError::make_err(message);
+            }
+            if qubits.len() == 2 && qubits[0] == qubits[1] {
+                let message = format!("Gate {:?} uses the same qubit twice", gate);
+                return CircuitError::make_err(message);
+            }
+        }
+        Ok(CliffordCircuit { n, gates })
+    }
+
+    /// Sample a Clifford on `n` qubits uniformly at random, following the canonical form of
+    /// Bravyi and Maslov: every Clifford can be written as `F1 H P F2` for hadamard free
+    /// Cliffords `F1`, `F2` (made of S, CZ and CNOT gates), a layer of Hadamards `H` and a
+    /// permutation `P`. The Hadamards and permutation are drawn from the quantum Mallows
+    /// distribution, weighting each by the number of Cliffords which share it, then `F1`, `F2`
+    /// and a random Pauli are drawn uniformly.
+    ///
+    /// Random choices use the generator from `rng::with_seed` when there is one.
+    ///
+    /// # Example
+    /// ```
+    /// use qip::*;
+    /// use qip::clifford::CliffordCircuit;
+    /// # fn main() -> Result<(), CircuitError> {
+    ///
+    /// let clifford = CliffordCircuit::random(3);
+    ///
+    /// // A random Clifford followed by its inverse does nothing.
+    /// let mut b = OpBuilder::new();
+    /// let r = b.register(3)?;
+    /// let r = clifford.apply(&mut b, r)?;
+    /// let r = clifford.inverse().apply(&mut b, r)?;
+    /// let (r, m) = b.measure(r);
+    /// let (_, measured) = run_local::<f64>(&r)?;
+    /// assert_eq!(measured.get_measurement(&m).map(|(v, _)| v), Some(0));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn random(n: u64) -> Self {
+        let (hadamards, permutation) = sample_quantum_mallows(n as usize);
+        let mut gates = random_hadamard_free(n);
+        hadamards
+            .iter()
+            .enumerate()
+            .filter(|(_, h)| **h)
+            .for_each(|(i, _)| gates.push(CliffordGate::H(i as u64)));
+        gates.extend(permutation_swaps(&permutation));
+        gates.extend(random_hadamard_free(n));
+        (0..n).for_each(|i| {
+            if random::<bool>() {
+                gates.push(CliffordGate::X(i));
+            }
+            if random::<bool>() {
+                gates.push(CliffordGate::Z(i));
+            }
+        });
+        CliffordCircuit { n, gates }
+    }
+
+    /// Get the number of qubits the circuit acts on.
+    pub fn n(&self) -> u64 {
+        self.n
+    }
+
+    /// Get the gates of the circuit, in the order they are applied.
+    pub fn gates(&self) -> &[CliffordGate] {
+        &self.gates
+    }
+
+    /// Get the circuit which undoes this one.
+    pub fn inverse(&self) -> CliffordCircuit {
+        CliffordCircuit {
+            n: self.n,
+            gates: self.gates.iter().rev().map(|g| g.inverse()).collect(),
+        }
+    }
+
+    /// Get the circuit which applies this one followed by `other`. Returns an error if they act
+    /// on different numbers of qubits.
+    pub fn then(&self, other: &CliffordCircuit) -> Result<CliffordCircuit, CircuitError> {
+        if self.n != other.n {
+            let message = format!(
+                "Cannot compose circuits on {:?} and {:?} qubits",
+                self.n, other.n
+            );
+            return CircuitError::make_err(message);
+        }
+        let gates = self
+            .gates
+            .iter()
+            .chain(other.gates.iter())
+            .cloned()
+            .collect();
+        Ok(CliffordCircuit { n: self.n, gates })
+    }
+
+    /// Apply the circuit to `r`, with gates on qubit `i` acting on the qubit at position `i` in
+    /// `r`. Returns an error if `r` does not have `n` qubits.
+    pub fn apply(&self, b: &mut dyn UnitaryBuilder, r: Register) -> Result<Register, CircuitError> {
+        if r.n() != self.n {
+            let message = format!(
+                "Circuit acts on {:?} qubits but the register has {:?}",
+                self.n,
+                r.n()
+            );
+            return CircuitError::make_err(message);
+        }
+        let mut qubits: Vec<Option<Register>> = b.split_all(r).into_iter().map(Some).collect();
+        for gate in &self.gates {
+            match *gate {
+                CliffordGate::CNOT(i, j) | CliffordGate::CZ(i, j) | CliffordGate::Swap(i, j) => {
+                    let q = qubits[i as usize].take().unwrap();
+                    let r = qubits[j as usize].take().unwrap();
+                    let (q, r) = match gate {
+                        CliffordGate::CNOT(_, _) => b.cnot(q, r),
+                        CliffordGate::CZ(_, _) => b.cz(q, r),
+                        _ => b.swap(q, r)?,
+                    };
+                    qubits[i as usize] = Some(q);
+                    qubits[j as usize] = Some(r);
+                }
+                gate => {
+                    let i = gate.qubits()[0] as usize;
+                    let q = qubits[i].take().unwrap();
+                    qubits[i] = Some(match gate {
+                        CliffordGate::H(_) => b.hadamard(q),
+                        CliffordGate::S(_) => b.s(q),
+                        CliffordGate::Sdag(_) => b.sdagger(q),
+                        CliffordGate::X(_) => b.x(q),
+                        _ => b.z(q),
+                    });
+                }
+            }
+        }
+        b.merge(qubits.into_iter().map(Option::unwrap).collect())
+    }
+}
+
+/// Apply a uniformly random Clifford to `r`, see `CliffordCircuit::random`.
+pub fn random_clifford(b: &mut dyn UnitaryBuilder, r: Register) -> Result<Register, CircuitError> {
+    CliffordCircuit::random(r.n()).apply(b, r)
+}
+
+/// Sample the layer of Hadamards and the permutation of the canonical form from the quantum
+/// Mallows distribution. Qubit `i` has a Hadamard if `hadamards[i]` and is then moved to
+/// `permutation[i]`.
+fn sample_quantum_mallows(n: usize) -> (Vec<bool>, Vec<usize>) {
+    let mut remaining: Vec<usize> = (0..n).collect();
+    (0..n)
+        .map(|i| {
+            let m = (n - i) as i32;
+            let eps = 4f64.powi(-m);
+            let r = random::<f64>();
+            let index = -(r + (1.0 - r) * eps).log2().ceil() as i32;
+            let hadamard = index < m;
+            let k = if hadamard { index } else { 2 * m - index - 1 };
+            (hadamard, remaining.remove(k as usize))
+        })
+        .unzip()
+}
+
+/// Sample a uniformly random hadamard free Clifford, up to Paulis, as a layer of S and CZ gates
+/// from a random symmetric matrix followed by CNOTs from a random lower unitriangular matrix.
+fn random_hadamard_free(n: u64) -> Vec<CliffordGate> {
+    let mut gates = vec![];
+    (0..n).for_each(|i| {
+        if random::<bool>() {
+            gates.push(CliffordGate::S(i));
+        }
+        (i + 1..n).for_each(|j| {
+            if random::<bool>() {
+                gates.push(CliffordGate::CZ(i, j));
+            }
+        })
+    });
+    // Each qubit is a target before it is used as a control, so each CNOT adds the original
+    // value of its control.
+    (0..n).rev().for_each(|i| {
+        (0..i).for_each(|j| {
+            if random::<bool>() {
+                gates.push(CliffordGate::CNOT(j, i));
+            }
+        })
+    });
+    gates
+}
+
+/// Get swaps which move qubit `i` to position `permutation[i]`.
+fn permutation_swaps(permutation: &[usize]) -> Vec<CliffordGate> {
+    // The qubit currently at each position.
+    let mut current: Vec<usize> = (0..permutation.len()).collect();
+    let mut gates = vec![];
+    permutation.iter().enumerate().for_each(|(qubit, target)| {
+        let position = current.iter().position(|q| *q == qubit).unwrap();
+        if position != *target {
+            gates.push(CliffordGate::Swap(position as u64, *target as u64));
+            current.swap(position, *target);
+        }
+    });
+    gates
+}
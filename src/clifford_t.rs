@@ -0,0 +1,406 @@
+use crate::errors::CircuitError;
+use crate::{Complex, Register, UnitaryBuilder};
+use std::collections::HashSet;
+
+/// Length of the longest sequence in the table of basic approximations.
+const BASIC_SEQUENCE_LENGTH: usize = 16;
+
+/// Deepest level of Solovay-Kitaev recursion tried before giving up on a precision.
+const MAX_RECURSION_DEPTH: usize = 8;
+
+/// A single qubit unitary up to global phase, as the unit quaternion `(w, x, y, z)` for
+/// `w I - i (x X + y Y + z Z)`. The quaternions `q` and `-q` are the same op.
+type Quaternion = [f64; 4];
+
+/// Gates from the Clifford+T set used to approximate rotations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CliffordTGate {
+    /// Hadamard.
+    H,
+    /// Phase by `i` on `|1>`.
+    S,
+    /// Inverse of `S`.
+    Sdag,
+    /// Phase by `e^{i pi/4}` on `|1>`.
+    T,
+    /// Inverse of `T`.
+    Tdag,
+}
+
+impl CliffordTGate {
+    /// Get the inverse of the gate.
+    pub fn inverse(self) -> CliffordTGate {
+        match self {
+            CliffordTGate::H => CliffordTGate::H,
+            CliffordTGate::S => CliffordTGate::Sdag,
+            CliffordTGate::Sdag => CliffordTGate::S,
+            CliffordTGate::T => CliffordTGate::Tdag,
+            CliffordTGate::Tdag => CliffordTGate::T,
+        }
+    }
+
+    /// Get the number of T gates the gate is made of, as a power of T, or None for H.
+    fn t_power(self) -> Option<u8> {
+        match self {
+            CliffordTGate::H => None,
+            CliffordTGate::S => Some(2),
+            CliffordTGate::Sdag => Some(6),
+            CliffordTGate::T => Some(1),
+            CliffordTGate::Tdag => Some(7),
+        }
+    }
+
+    fn quaternion(self) -> Quaternion {
+        match self {
+            CliffordTGate::H => {
+                let c = std::f64::consts::FRAC_1_SQRT_2;
+                [0.0, c, 0.0, c]
+            }
+            gate => rz_quaternion(gate.t_power().unwrap() as f64 * std::f64::consts::FRAC_PI_4),
+        }
+    }
+}
+
+/// Find a sequence of Clifford+T gates, in the order they are applied, equal to `Rz(theta)` up
+/// to a global phase and to within `precision` in operator norm.
+///
+/// Sequences are found with the Solovay-Kitaev algorithm, starting from a table of all short
+/// sequences and refining them with group commutators until they are precise enough. Each level
+/// of refinement makes the sequence around five times longer, so very small precisions give very
+/// long sequences. An error is returned if the precision cannot be reached.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::clifford_t::{approximate_rz, CliffordTGate};
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// // Rz(pi/4) is T up to a global phase.
+/// let gates = approximate_rz(std::f64::consts::FRAC_PI_4, 1e-6)?;
+/// assert_eq!(gates, vec![CliffordTGate::T]);
+///
+/// let gates = approximate_rz(0.3, 1e-2)?;
+/// assert!(gates.len() > 1);
+/// # Ok(())
+/// # }
+/// ```
+pub fn approximate_rz(theta: f64, precision: f64) -> Result<Vec<CliffordTGate>, CircuitError> {
+    approximate_quaternion(rz_quaternion(theta), precision)
+}
+
+/// Find a sequence of Clifford+T gates, in the order they are applied, equal to the single qubit
+/// unitary `mat` (given in row major order) up to a global phase and to within `precision` in
+/// operator norm. See `approximate_rz` for details.
+pub fn approximate_unitary(
+    mat: &[Complex<f64>],
+    precision: f64,
+) -> Result<Vec<CliffordTGate>, CircuitError> {
+    if mat.len() != 4 {
+        let message = format!("Expected a 2x2 matrix, found {:?} entries", mat.len());
+        return CircuitError::make_err(message);
+    }
+    let det = mat[0] * mat[3] - mat[1] * mat[2];
+    if (det.norm() - 1.0).abs() > 1e-8 {
+        return CircuitError::make_str_err("Matrix is not unitary.");
+    }
+    let root = det.sqrt();
+    let u: Vec<Complex<f64>> = mat.iter().map(|c| c / root).collect();
+    let q = [
+        (u[0].re + u[3].re) / 2.0,
+        -(u[1].im + u[2].im) / 2.0,
+        (u[2].re - u[1].re) / 2.0,
+        (u[3].im - u[0].im) / 2.0,
+    ];
+    approximate_quaternion(normalize(q), precision)
+}
+
+/// Get the operator norm distance between the ops applied by two sequences of gates, minimized
+/// over global phases.
+pub fn sequence_distance(a: &[CliffordTGate], b: &[CliffordTGate]) -> f64 {
+    distance(&sequence_quaternion(a), &sequence_quaternion(b))
+}
+
+/// Get the operator norm distance between `Rz(theta)` and the op applied by `gates`, minimized
+/// over global phases.
+pub fn rz_distance(theta: f64, gates: &[CliffordTGate]) -> f64 {
+    distance(&rz_quaternion(theta), &sequence_quaternion(gates))
+}
+
+/// Apply `gates` to each qubit of `r` in order.
+pub fn apply_clifford_t<B: UnitaryBuilder>(
+    b: &mut B,
+    r: Register,
+    gates: &[CliffordTGate],
+) -> Register {
+    gates.iter().fold(r, |r, gate| match gate {
+        CliffordTGate::H => b.hadamard(r),
+        CliffordTGate::S => b.s(r),
+        CliffordTGate::Sdag => b.sdagger(r),
+        CliffordTGate::T => b.t(r),
+        CliffordTGate::Tdag => b.tdagger(r),
+    })
+}
+
+/// Apply a Clifford+T approximation of `Rz(theta)` to within `precision` (see `approximate_rz`)
+/// to each qubit of `r`.
+pub fn rz_clifford_t<B: UnitaryBuilder>(
+    b: &mut B,
+    r: Register,
+    theta: f64,
+    precision: f64,
+) -> Result<Register, CircuitError> {
+    let gates = approximate_rz(theta, precision)?;
+    Ok(apply_clifford_t(b, r, &gates))
+}
+
+/// A sequence of gates along with the op they apply.
+#[derive(Clone)]
+struct Approximation {
+    gates: Vec<CliffordTGate>,
+    q: Quaternion,
+}
+
+impl Approximation {
+    fn inverse(&self) -> Approximation {
+        Approximation {
+            gates: self.gates.iter().rev().map(|g| g.inverse()).collect(),
+            q: conjugate(&self.q),
+        }
+    }
+
+    /// The approximation which applies `self` and then `other`.
+    fn then(mut self, other: &Approximation) -> Approximation {
+        self.gates.extend(other.gates.iter().cloned());
+        Approximation {
+            gates: self.gates,
+            q: multiply(&other.q, &self.q),
+        }
+    }
+}
+
+fn approximate_quaternion(
+    target: Quaternion,
+    precision: f64,
+) -> Result<Vec<CliffordTGate>, CircuitError> {
+    if precision <= 0.0 {
+        return CircuitError::make_str_err("Precision must be positive.");
+    }
+    let basic = basic_approximations();
+    for depth in 0..=MAX_RECURSION_DEPTH {
+        let approx = solovay_kitaev(&basic, &target, depth);
+        let gates = simplify(&approx.gates);
+        if distance(&target, &sequence_quaternion(&gates)) <= precision {
+            return Ok(gates);
+        }
+    }
+    let message = format!("Could not approximate op to within {:?}", precision);
+    CircuitError::make_err(message)
+}
+
+fn solovay_kitaev(basic: &[Approximation], target: &Quaternion, depth: usize) -> Approximation {
+    if depth == 0 {
+        return basic
+            .iter()
+            .min_by(|a, b| {
+                distance(&a.q, target)
+                    .partial_cmp(&distance(&b.q, target))
+                    .unwrap()
+            })
+            .unwrap()
+            .clone();
+    }
+    let previous = solovay_kitaev(basic, target, depth - 1);
+    // Write the remaining error as a group commutator of two ops, each of which is further from
+    // the identity but needs only to be approximated as well as this level.
+    let error = multiply(target, &conjugate(&previous.q));
+    let (v, w) = balanced_commutator(&error);
+    let v = solovay_kitaev(basic, &v, depth - 1);
+    let w = solovay_kitaev(basic, &w, depth - 1);
+    // target = V W V^dag W^dag previous, so W^dag is applied first.
+    previous
+        .then(&w.inverse())
+        .then(&v.inverse())
+        .then(&w)
+        .then(&v)
+}
+
+/// Find `v` and `w` with `v w v^dag w^dag = q`, both rotating by about the square root of the
+/// angle of `q`.
+fn balanced_commutator(q: &Quaternion) -> (Quaternion, Quaternion) {
+    let q = canonical_sign(*q);
+    let w = q[0].min(1.0);
+    let phi = 2.0 * ((1.0 - w) / 2.0).sqrt().sqrt().asin();
+    let v = [(phi / 2.0).cos(), (phi / 2.0).sin(), 0.0, 0.0];
+    let u = [(phi / 2.0).cos(), 0.0, (phi / 2.0).sin(), 0.0];
+    let commutator = canonical_sign(multiply(
+        &multiply(&v, &u),
+        &multiply(&conjugate(&v), &conjugate(&u)),
+    ));
+    // Rotate the commutator's axis onto the axis of q.
+    let s = rotation_between(&axis(&commutator), &axis(&q));
+    let conj = |p: &Quaternion| multiply(&multiply(&s, p), &conjugate(&s));
+    (conj(&v), conj(&u))
+}
+
+/// Find the rotation taking the unit vector `a` to the unit vector `b`.
+fn rotation_between(a: &[f64; 3], b: &[f64; 3]) -> Quaternion {
+    let dot = a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+    let cross = [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ];
+    if dot < -1.0 + 1e-12 {
+        // Rotate by pi about any axis perpendicular to a.
+        let other = if a[0].abs() < 0.9 {
+            [1.0, 0.0, 0.0]
+        } else {
+            [0.0, 1.0, 0.0]
+        };
+        let perp = [
+            a[1] * other[2] - a[2] * other[1],
+            a[2] * other[0] - a[0] * other[2],
+            a[0] * other[1] - a[1] * other[0],
+        ];
+        return normalize([0.0, perp[0], perp[1], perp[2]]);
+    }
+    // The quaternion for a rotation by the angle between a and b is (1 + a.b, a x b) normalized.
+    normalize([1.0 + dot, cross[0], cross[1], cross[2]])
+}
+
+/// Build the table of ops made by short sequences, keeping the shortest sequence for each.
+fn basic_approximations() -> Vec<Approximation> {
+    let generators = [CliffordTGate::H, CliffordTGate::T, CliffordTGate::Tdag];
+    let identity = Approximation {
+        gates: vec![],
+        q: [1.0, 0.0, 0.0, 0.0],
+    };
+    let mut seen = HashSet::new();
+    seen.insert(quaternion_key(&identity.q));
+    let mut all = vec![identity.clone()];
+    let mut layer = vec![identity];
+    (0..BASIC_SEQUENCE_LENGTH).for_each(|_| {
+        let mut next = vec![];
+        layer.iter().for_each(|approx| {
+            generators.iter().for_each(|gate| {
+                let candidate = approx.clone().then(&Approximation {
+                    gates: vec![*gate],
+                    q: gate.quaternion(),
+                });
+                if seen.insert(quaternion_key(&candidate.q)) {
+                    next.push(candidate);
+                }
+            })
+        });
+        all.extend(next.iter().cloned());
+        layer = next;
+    });
+    all
+}
+
+/// Merge adjacent phase gates and remove pairs of Hadamards.
+fn simplify(gates: &[CliffordTGate]) -> Vec<CliffordTGate> {
+    // Runs of phase gates are powers of T, held as Some(power), while None is a Hadamard.
+    let mut tokens: Vec<Option<u8>> = vec![];
+    gates.iter().for_each(|gate| {
+        match (gate.t_power(), tokens.last().cloned()) {
+            (None, Some(None)) => {
+                tokens.pop();
+            }
+            (Some(p), Some(Some(q))) => {
+                tokens.pop();
+                if (p + q) % 8 != 0 {
+                    tokens.push(Some((p + q) % 8));
+                }
+            }
+            (power, _) => tokens.push(power),
+        };
+    });
+    tokens
+        .into_iter()
+        .flat_map(|token| match token {
+            None => vec![CliffordTGate::H],
+            Some(1) => vec![CliffordTGate::T],
+            Some(2) => vec![CliffordTGate::S],
+            Some(3) => vec![CliffordTGate::S, CliffordTGate::T],
+            Some(4) => vec![CliffordTGate::S, CliffordTGate::S],
+            Some(5) => vec![CliffordTGate::Sdag, CliffordTGate::Tdag],
+            Some(6) => vec![CliffordTGate::Sdag],
+            Some(7) => vec![CliffordTGate::Tdag],
+            Some(_) => vec![],
+        })
+        .collect()
+}
+
+fn sequence_quaternion(gates: &[CliffordTGate]) -> Quaternion {
+    gates.iter().fold([1.0, 0.0, 0.0, 0.0], |q, gate| {
+        multiply(&gate.quaternion(), &q)
+    })
+}
+
+fn rz_quaternion(theta: f64) -> Quaternion {
+    [(theta / 2.0).cos(), 0.0, 0.0, (theta / 2.0).sin()]
+}
+
+fn multiply(a: &Quaternion, b: &Quaternion) -> Quaternion {
+    [
+        a[0] * b[0] - a[1] * b[1] - a[2] * b[2] - a[3] * b[3],
+        a[0] * b[1] + a[1] * b[0] + a[2] * b[3] - a[3] * b[2],
+        a[0] * b[2] - a[1] * b[3] + a[2] * b[0] + a[3] * b[1],
+        a[0] * b[3] + a[1] * b[2] - a[2] * b[1] + a[3] * b[0],
+    ]
+}
+
+fn conjugate(q: &Quaternion) -> Quaternion {
+    [q[0], -q[1], -q[2], -q[3]]
+}
+
+fn normalize(q: Quaternion) -> Quaternion {
+    let norm = q.iter().map(|x| x * x).sum::<f64>().sqrt();
+    [q[0] / norm, q[1] / norm, q[2] / norm, q[3] / norm]
+}
+
+/// Pick the sign of `q` with a non-negative `w`, so it rotates by at most pi.
+fn canonical_sign(q: Quaternion) -> Quaternion {
+    if q[0] < 0.0 {
+        [-q[0], -q[1], -q[2], -q[3]]
+    } else {
+        q
+    }
+}
+
+/// Get the axis of rotation of `q`, or the z axis for the identity.
+fn axis(q: &Quaternion) -> [f64; 3] {
+    let norm = (q[1] * q[1] + q[2] * q[2] + q[3] * q[3]).sqrt();
+    if norm < 1e-15 {
+        [0.0, 0.0, 1.0]
+    } else {
+        [q[1] / norm, q[2] / norm, q[3] / norm]
+    }
+}
+
+/// The operator norm distance between the ops, minimized over global phase.
+fn distance(a: &Quaternion, b: &Quaternion) -> f64 {
+    let dot = a
+        .iter()
+        .zip(b.iter())
+        .map(|(x, y)| x * y)
+        .sum::<f64>()
+        .abs();
+    2.0 * (dot.min(1.0).acos() / 2.0).sin()
+}
+
+/// Round the quaternion, with a fixed sign, so it can be used to find repeated ops.
+fn quaternion_key(q: &Quaternion) -> [i64; 4] {
+    let mut q = *q;
+    if let Some(first) = q.iter().find(|x| x.abs() > 1e-9).cloned() {
+        if first < 0.0 {
+            q.iter_mut().for_each(|x| *x = -*x);
+        }
+    }
+    let mut key = [0; 4];
+    key.iter_mut()
+        .zip(q.iter())
+        .for_each(|(k, x)| *k = (x * 1e8).round() as i64);
+    key
+}
@@ -1,6 +1,12 @@
+use crate::builders::parse_pauli_string;
 use crate::errors::CircuitError;
 /// Common circuits for general usage.
-use crate::{OpBuilder, Register, UnitaryBuilder};
+use crate::pipeline::MeasurementHandle;
+use crate::{
+    classical_oracle, inverter, run_local, try_condition, Complex, OpBuilder, Register,
+    UnitaryBuilder,
+};
+use num::{One, Zero};
 
 /// Extract a set of indices, provide them to a function, then reinsert them in the correct order.
 pub fn work_on<F>(
@@ -61,10 +67,1377 @@ pub fn epr_pair(b: &mut OpBuilder, n: u64) -> (Register, Register) {
     (ra, rb)
 }
 
+/// Teleport the state of `source` onto `epr_b`, using the pair `epr_a` and `epr_b` made by
+/// `epr_pair`. `source` and `epr_a` are measured, and the measurements classically control the X
+/// and Z corrections on `epr_b`, which is returned holding the original state of `source`.
+///
+/// All three Registers must have the same number of qubits. Teleporting more than one qubit needs
+/// a separate pair for each, so qubit `i` of `epr_a` and of `epr_b` should come from the same call
+/// to `epr_pair(b, 1)`.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let source = b.qubit();
+/// let source = b.ry(source, 1.0);
+/// let (epr_a, epr_b) = qip::epr_pair(&mut b, 1);
+/// let r = qip::teleport(&mut b, source, epr_a, epr_b)?;
+///
+/// // Whatever was measured, r is left in Ry(1.0)|0>.
+/// let (mut state, _) = run_local::<f64>(&r)?;
+/// let p: f64 = state.stochastic_measure(&r.indices, 0.0)[1];
+/// assert!((p - 0.5f64.sin().powi(2)).abs() < 1e-10);
+/// # Ok(())
+/// # }
+/// ```
+pub fn teleport(
+    b: &mut OpBuilder,
+    source: Register,
+    epr_a: Register,
+    epr_b: Register,
+) -> Result<Register, CircuitError> {
+    let n = source.n();
+    if epr_a.n() != n || epr_b.n() != n {
+        let message = format!(
+            "Expected registers of equal size, found {:?}, {:?} and {:?} qubits",
+            n,
+            epr_a.n(),
+            epr_b.n()
+        );
+        return CircuitError::make_err(message);
+    }
+    let (sources, pairs): (Vec<_>, Vec<_>) = b
+        .split_all(source)
+        .into_iter()
+        .zip(b.split_all(epr_a))
+        .map(|(s, a)| {
+            let (s, a) = b.cnot(s, a);
+            (b.hadamard(s), a)
+        })
+        .unzip();
+    // Bit i of the measurement is source qubit i, and bit n + i is epr_a qubit i.
+    let measured = b.merge(sources.into_iter().chain(pairs).collect())?;
+    let (_, handle) = b.measure(measured);
+    Ok(b.single_register_classical_sidechannel(
+        epr_b,
+        &[handle],
+        Box::new(move |b, r, measured| {
+            let qs = b
+                .split_all(r)
+                .into_iter()
+                .enumerate()
+                .map(|(i, q)| {
+                    let q = if (measured[0] >> (n + i as u64)) & 1 == 1 {
+                        b.x(q)
+                    } else {
+                        q
+                    };
+                    if (measured[0] >> i) & 1 == 1 {
+                        b.z(q)
+                    } else {
+                        q
+                    }
+                })
+                .collect();
+            b.merge(qs)
+        }),
+    ))
+}
+
+/// Encode two classical bits into `epr_a`, one qubit of a pair made by `epr_pair(b, 1)`, for
+/// `superdense_decode`: `bit_a` applies Z and `bit_b` applies X.
+pub fn superdense_encode(
+    b: &mut dyn UnitaryBuilder,
+    epr_a: Register,
+    bit_a: bool,
+    bit_b: bool,
+) -> Result<Register, CircuitError> {
+    check_single_qubits(&[&epr_a])?;
+    let r = if bit_b { b.x(epr_a) } else { epr_a };
+    Ok(if bit_a { b.z(r) } else { r })
+}
+
+/// Recover the two bits encoded into `epr_a` by `superdense_encode`, using the other qubit of
+/// the pair `epr_b`. Both qubits are measured, the returned handle gives `bit_a` as bit 1 and
+/// `bit_b` as bit 0 of the measured value.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let (epr_a, epr_b) = qip::epr_pair(&mut b, 1);
+/// let epr_a = qip::superdense_encode(&mut b, epr_a, true, false)?;
+/// let (r, m) = qip::superdense_decode(&mut b, epr_a, epr_b)?;
+///
+/// let (_, measured) = run_local::<f64>(&r)?;
+/// assert_eq!(measured.get_measurement(&m).map(|(v, _)| v), Some(0b10));
+/// # Ok(())
+/// # }
+/// ```
+pub fn superdense_decode(
+    b: &mut OpBuilder,
+    epr_a: Register,
+    epr_b: Register,
+) -> Result<(Register, MeasurementHandle), CircuitError> {
+    check_single_qubits(&[&epr_a, &epr_b])?;
+    let (epr_a, epr_b) = b.cnot(epr_a, epr_b);
+    let epr_a = b.hadamard(epr_a);
+    let r = b.merge(vec![epr_b, epr_a])?;
+    Ok(b.measure(r))
+}
+
+fn check_single_qubits(rs: &[&Register]) -> Result<(), CircuitError> {
+    match rs.iter().find(|r| r.n() != 1) {
+        Some(r) => CircuitError::make_err(format!(
+            "Superdense coding uses single qubits, found a register of {:?}",
+            r.n()
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Makes a Register of `n` qubits in the GHZ state `|0n> + |1n>`, the n-party generalization of
+/// `epr_pair`.
+/// # Example
+/// ```
+/// use qip::*;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// // Make 3 qubits in state |000> + |111> and give one to each party.
+/// let r = qip::ghz(&mut b, 3)?;
+/// let parties = b.split_all(r);
+/// # Ok(())
+/// # }
+/// ```
+pub fn ghz(b: &mut OpBuilder, n: u64) -> Result<Register, CircuitError> {
+    let r = b.qubit();
+    let r = b.hadamard(r);
+    if n > 1 {
+        let rs = b.register(n - 1)?;
+        let (r, rs) = b.cnot(r, rs);
+        b.merge(vec![r, rs])
+    } else if n == 1 {
+        Ok(r)
+    } else {
+        CircuitError::make_str_err("Expected n > 0 for GHZ state.")
+    }
+}
+
+/// Makes a Register of `n` qubits in the W state `|10..0> + |010..0> + ... + |0..01>`, where a
+/// single excitation is shared equally between all the qubits.
+/// # Example
+/// ```
+/// use qip::*;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let r = qip::w_state(&mut b, 3)?;
+/// let (state, _) = run_local::<f64>(&r)?;
+/// let state = state.get_state(true);
+/// assert!((state[0b001].norm_sqr() - 1.0 / 3.0).abs() < 1e-10);
+/// # Ok(())
+/// # }
+/// ```
+pub fn w_state(b: &mut OpBuilder, n: u64) -> Result<Register, CircuitError> {
+    let r = b.register(n)?;
+    let mut qs = b.split_all(r);
+    let first = b.not(qs.remove(0));
+    // Before each step the excitation on prev has weight (n - i) / n, leave 1 / n of it there and
+    // move the rest to next.
+    let (mut qs, last) =
+        qs.into_iter()
+            .enumerate()
+            .fold((vec![], first), |(mut qs, prev), (i, next)| {
+                let theta = 2.0 * (1.0 / ((n as usize - i) as f64).sqrt()).acos();
+                let (prev, next) = b.cry(prev, next, theta);
+                let (next, prev) = b.cnot(next, prev);
+                qs.push(prev);
+                (qs, next)
+            });
+    qs.push(last);
+    b.merge(qs)
+}
+
+/// Apply the swap test to `ra` and `rb`, returning a new ancilla qubit along with the registers.
+/// The ancilla is measured as `|0>` with probability `(1 + |<a|b>|^2) / 2`.
+pub fn swap_test(
+    b: &mut dyn UnitaryBuilder,
+    ra: Register,
+    rb: Register,
+) -> Result<(Register, Register, Register), CircuitError> {
+    b.push_name_scope("SwapTest");
+    let q = b.qubit();
+    let q = b.hadamard(q);
+    let result = b
+        .cswap(q, ra, rb)
+        .map(|(q, ra, rb)| (b.hadamard(q), ra, rb));
+    b.pop_name_scope();
+    result
+}
+
+/// Estimate the overlap `|<a|b>|^2` between the states prepared on `n` qubits by `prep_a` and
+/// `prep_b` by sampling the ancilla of `swap_test` `shots` times.
+/// # Example
+/// ```
+/// use qip::*;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// // |<0|+>|^2 = 1/2
+/// let overlap = swap_test_overlap(1, |_, r| Ok(r), |b, r| Ok(b.hadamard(r)), 1000)?;
+/// assert!((overlap - 0.5).abs() < 0.2);
+/// # Ok(())
+/// # }
+/// ```
+pub fn swap_test_overlap<A, B>(
+    n: u64,
+    prep_a: A,
+    prep_b: B,
+    shots: usize,
+) -> Result<f64, CircuitError>
+where
+    A: Fn(&mut dyn UnitaryBuilder, Register) -> Result<Register, CircuitError>,
+    B: Fn(&mut dyn UnitaryBuilder, Register) -> Result<Register, CircuitError>,
+{
+    if shots == 0 {
+        return CircuitError::make_str_err("Expected shots > 0 for swap test.");
+    }
+    let mut b = OpBuilder::new();
+    let ra = b.register(n)?;
+    let rb = b.register(n)?;
+    let ra = prep_a(&mut b, ra)?;
+    let rb = prep_b(&mut b, rb)?;
+    let (q, ra, rb) = swap_test(&mut b, ra, rb)?;
+    let indices = q.indices.clone();
+    let r = b.merge(vec![q, ra, rb])?;
+    let (state, _) = run_local::<f64>(&r)?;
+    let zeros = state
+        .sample_measurements(&indices, shots)
+        .get(&0)
+        .cloned()
+        .unwrap_or(0);
+    let overlap = 2.0 * zeros as f64 / shots as f64 - 1.0;
+    Ok(overlap.max(0.0))
+}
+
+/// Apply the Hadamard test to `r` using the ancilla `cr`, which should start as `|0>`. The
+/// function `controlled_u` applies `U` to `r` and is always conditioned on `cr`. Afterwards `cr`
+/// is measured as `|0>` with probability `(1 + Re(<psi|U|psi>)) / 2`.
+pub fn hadamard_test<F>(
+    b: &mut dyn UnitaryBuilder,
+    cr: Register,
+    r: Register,
+    controlled_u: F,
+) -> Result<(Register, Register), CircuitError>
+where
+    F: FnOnce(&mut dyn UnitaryBuilder, Register) -> Result<Register, CircuitError>,
+{
+    b.push_name_scope("HadamardTest");
+    let cr = b.hadamard(cr);
+    let result = try_condition(b, cr, r, controlled_u).map(|(cr, r)| (b.hadamard(cr), r));
+    b.pop_name_scope();
+    result
+}
+
+/// Like `hadamard_test` but with an additional `S^dagger` on the ancilla, so that `cr` is
+/// measured as `|0>` with probability `(1 + Im(<psi|U|psi>)) / 2`.
+pub fn hadamard_test_imaginary<F>(
+    b: &mut dyn UnitaryBuilder,
+    cr: Register,
+    r: Register,
+    controlled_u: F,
+) -> Result<(Register, Register), CircuitError>
+where
+    F: FnOnce(&mut dyn UnitaryBuilder, Register) -> Result<Register, CircuitError>,
+{
+    b.push_name_scope("HadamardTestImaginary");
+    let cr = b.hadamard(cr);
+    let cr = b.sdagger(cr);
+    let result = try_condition(b, cr, r, controlled_u).map(|(cr, r)| (b.hadamard(cr), r));
+    b.pop_name_scope();
+    result
+}
+
+/// Estimate `<psi|U|psi>` where `|psi>` is prepared on `n` qubits by `state_prep` and `U` is
+/// applied by `u`, by sampling the ancillas of `hadamard_test` and `hadamard_test_imaginary`
+/// `shots` times each.
+/// # Example
+/// ```
+/// use qip::*;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// // <1|Z|1> = -1
+/// let value = hadamard_test_estimate(1, |b, r| Ok(b.not(r)), |b, r| Ok(b.z(r)), 100)?;
+/// assert!((value.re + 1.0).abs() < 1e-10);
+/// # Ok(())
+/// # }
+/// ```
+pub fn hadamard_test_estimate<A, U>(
+    n: u64,
+    state_prep: A,
+    u: U,
+    shots: usize,
+) -> Result<Complex<f64>, CircuitError>
+where
+    A: Fn(&mut dyn UnitaryBuilder, Register) -> Result<Register, CircuitError>,
+    U: Fn(&mut dyn UnitaryBuilder, Register) -> Result<Register, CircuitError>,
+{
+    if shots == 0 {
+        return CircuitError::make_str_err("Expected shots > 0 for hadamard test.");
+    }
+    let run_part = |imaginary: bool| -> Result<f64, CircuitError> {
+        let mut b = OpBuilder::new();
+        let cr = b.qubit();
+        let r = b.register(n)?;
+        let r = state_prep(&mut b, r)?;
+        let (cr, r) = if imaginary {
+            hadamard_test_imaginary(&mut b, cr, r, &u)?
+        } else {
+            hadamard_test(&mut b, cr, r, &u)?
+        };
+        let indices = cr.indices.clone();
+        let r = b.merge(vec![cr, r])?;
+        let (state, _) = run_local::<f64>(&r)?;
+        let zeros = state
+            .sample_measurements(&indices, shots)
+            .get(&0)
+            .cloned()
+            .unwrap_or(0);
+        Ok(2.0 * zeros as f64 / shots as f64 - 1.0)
+    };
+    Ok(Complex {
+        re: run_part(false)?,
+        im: run_part(true)?,
+    })
+}
+
+/// Apply the quantum fourier transform to `r`, including the final swap network.
+/// Using the same convention as the rest of the library (qubit 0 of `r` is the least significant
+/// bit) this maps `|x>` to `1/sqrt(N) sum_k e^{2 pi i x k / N} |k>`.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let r = b.register(3)?;
+/// let r = qft(&mut b, r)?;
+/// let r = inverse_qft(&mut b, r)?;
+///
+/// # Ok(())
+/// # }
+/// ```
+pub fn qft(b: &mut dyn UnitaryBuilder, r: Register) -> Result<Register, CircuitError> {
+    b.push_name_scope("QFT");
+    let n = r.n() as usize;
+    let mut qs: Vec<Option<Register>> = b.split_all(r).into_iter().map(Some).collect();
+    let result = (0..n).rev().try_for_each(|j| {
+        let q = b.hadamard(qs[j].take().unwrap());
+        let q = (0..j).rev().try_fold(q, |q, m| {
+            let cq = qs[m].take().unwrap();
+            let (cq, q) = qft_rotation(b, cq, q, (j - m + 1) as u64, false)?;
+            qs[m] = Some(cq);
+            Ok(q)
+        })?;
+        qs[j] = Some(q);
+        Ok(())
+    });
+    let result = result.and_then(|_| {
+        let qs = qs.into_iter().map(|q| q.unwrap()).collect();
+        qft_swap_network(b, qs)
+    });
+    b.pop_name_scope();
+    result
+}
+
+/// Apply the inverse of `qft` to `r`.
+pub fn inverse_qft(b: &mut dyn UnitaryBuilder, r: Register) -> Result<Register, CircuitError> {
+    b.push_name_scope("InvQFT");
+    let n = r.n() as usize;
+    let qs = b.split_all(r);
+    let result = qft_swap_network(b, qs);
+    let result = result.and_then(|r| {
+        let mut qs: Vec<Option<Register>> = b.split_all(r).into_iter().map(Some).collect();
+        (0..n).try_for_each(|j| {
+            let q = qs[j].take().unwrap();
+            let q = (0..j).try_fold(q, |q, m| {
+                let cq = qs[m].take().unwrap();
+                let (cq, q) = qft_rotation(b, cq, q, (j - m + 1) as u64, true)?;
+                qs[m] = Some(cq);
+                Ok(q)
+            })?;
+            qs[j] = Some(b.hadamard(q));
+            Ok(())
+        })?;
+        b.merge(qs.into_iter().map(|q| q.unwrap()).collect())
+    });
+    b.pop_name_scope();
+    result
+}
+
+/// Apply `R_k = diag(1, e^{2 pi i / 2^k})` (or its inverse) to `r` conditioned on `cr`.
+fn qft_rotation(
+    b: &mut dyn UnitaryBuilder,
+    cr: Register,
+    r: Register,
+    k: u64,
+    inverse: bool,
+) -> Result<(Register, Register), CircuitError> {
+    let theta = 2.0 * std::f64::consts::PI / f64::from(1 << k);
+    let theta = if inverse { -theta } else { theta };
+    let name = format!("R{}", k);
+    let phase = Complex { re: 0.0, im: theta }.exp();
+    b.cmat(
+        &name,
+        cr,
+        r,
+        vec![Complex::one(), Complex::zero(), Complex::zero(), phase],
+    )
+}
+
+/// Reverse the order of the qubits in `qs` using swaps, then merge them in their original order.
+fn qft_swap_network(
+    b: &mut dyn UnitaryBuilder,
+    qs: Vec<Register>,
+) -> Result<Register, CircuitError> {
+    let n = qs.len();
+    let mut qs: Vec<Option<Register>> = qs.into_iter().map(Some).collect();
+    (0..n / 2).try_for_each(|i| {
+        let qa = qs[i].take().unwrap();
+        let qb = qs[n - 1 - i].take().unwrap();
+        let (qa, qb) = b.swap(qa, qb)?;
+        qs[i] = Some(qa);
+        qs[n - 1 - i] = Some(qb);
+        Ok(())
+    })?;
+    b.merge(qs.into_iter().map(|q| q.unwrap()).collect())
+}
+
+/// Apply a toffoli gate to `r`, flipping it when both `ca` and `cb` are `|1>`. This is decomposed
+/// into hadamard, t, t-dagger, and cnot gates. All registers must be single qubits.
+pub fn ccx(
+    b: &mut dyn UnitaryBuilder,
+    ca: Register,
+    cb: Register,
+    r: Register,
+) -> Result<(Register, Register, Register), CircuitError> {
+    if ca.n() != 1 || cb.n() != 1 || r.n() != 1 {
+        let message = format!(
+            "ccx requires single qubits, found sizes {:?}, {:?}, and {:?}",
+            ca.n(),
+            cb.n(),
+            r.n()
+        );
+        return CircuitError::make_err(message);
+    }
+    b.push_name_scope("CCX");
+    let r = b.hadamard(r);
+    let (cb, r) = b.cnot(cb, r);
+    let r = b.tdagger(r);
+    let (ca, r) = b.cnot(ca, r);
+    let r = b.t(r);
+    let (cb, r) = b.cnot(cb, r);
+    let r = b.tdagger(r);
+    let (ca, r) = b.cnot(ca, r);
+    let cb = b.t(cb);
+    let r = b.t(r);
+    let r = b.hadamard(r);
+    let (ca, cb) = b.cnot(ca, cb);
+    let ca = b.t(ca);
+    let cb = b.tdagger(cb);
+    let (ca, cb) = b.cnot(ca, cb);
+    b.pop_name_scope();
+    Ok((ca, cb, r))
+}
+
+/// Apply an x gate to `r` controlled by the three qubits `ca`, `cb`, and `cc`. See `mcx`.
+pub fn cccx(
+    b: &mut dyn UnitaryBuilder,
+    ca: Register,
+    cb: Register,
+    cc: Register,
+    r: Register,
+) -> Result<(Register, Register, Register, Register), CircuitError> {
+    let (mut cs, r, _) = mcx(b, vec![ca, cb, cc], r, None)?;
+    let cc = cs.pop().unwrap();
+    let cb = cs.pop().unwrap();
+    let ca = cs.pop().unwrap();
+    Ok((ca, cb, cc, r))
+}
+
+/// Apply an x gate to each qubit of `r` when every qubit of `controls` is `|1>`, decomposed into
+/// `ccx` and cnot gates. With more than two control qubits a chain of toffolis computes the AND
+/// of the controls into `m - 2` ancilla qubits, which are taken from `ancillas` if provided
+/// (and must be `|0>`), and otherwise temporarily from the builder. Returns the controls, `r`, and
+/// the ancillas if given.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let cs = b.register(4)?;
+/// let r = b.qubit();
+/// let cs = b.not(cs);
+/// let (cs, r, _) = mcx(&mut b, vec![cs], r, None)?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn mcx(
+    b: &mut dyn UnitaryBuilder,
+    controls: Vec<Register>,
+    r: Register,
+    ancillas: Option<Register>,
+) -> Result<(Vec<Register>, Register, Option<Register>), CircuitError> {
+    let sizes: Vec<u64> = controls.iter().map(Register::n).collect();
+    let m: u64 = sizes.iter().sum();
+    let required = m.saturating_sub(2);
+    if let Some(ancillas) = &ancillas {
+        if ancillas.n() < required {
+            let message = format!(
+                "mcx with {:?} controls requires {:?} ancillas, found {:?}",
+                m,
+                required,
+                ancillas.n()
+            );
+            return CircuitError::make_err(message);
+        }
+    }
+    // Select the ancillas to use, any extra ancillas given are returned untouched.
+    let use_temp = ancillas.is_none();
+    let (anc, extra) = match ancillas {
+        Some(ancillas) if required > 0 => {
+            let indices: Vec<u64> = (0..required).collect();
+            let (anc, extra) = b.split(ancillas, &indices)?;
+            (Some(anc), extra)
+        }
+        Some(ancillas) => (None, Some(ancillas)),
+        None if required > 0 => (Some(b.get_temp_register(required, false)), None),
+        None => (None, None),
+    };
+
+    b.push_name_scope("MCX");
+    let mut cs: Vec<Option<Register>> = controls
+        .into_iter()
+        .flat_map(|c| b.split_all(c))
+        .map(Some)
+        .collect();
+    let ts = b.split_all(r);
+    let mut anc: Vec<Option<Register>> = anc
+        .map(|anc| b.split_all(anc))
+        .unwrap_or_default()
+        .into_iter()
+        .map(Some)
+        .collect();
+    let result = mcx_qubits(b, &mut cs, ts, &mut anc);
+    b.pop_name_scope();
+    let ts = result?;
+    let cs: Vec<Register> = cs.into_iter().map(|c| c.unwrap()).collect();
+    let anc: Vec<Register> = anc.into_iter().map(|a| a.unwrap()).collect();
+
+    let r = b.merge(ts)?;
+    let (r, anc) = if use_temp && !anc.is_empty() {
+        // Ops are only run if they are in the history of the output, so tie the last ops on the
+        // temporary ancillas to r before giving them back.
+        let r_n = r.n();
+        let anc = b.merge(anc)?;
+        let merged = b.merge(vec![r, anc])?;
+        let (r, anc) = b.split(merged, &(0..r_n).collect::<Vec<_>>())?;
+        (r, b.split_all(anc.unwrap()))
+    } else {
+        (r, anc)
+    };
+    let mut cs = cs.into_iter();
+    let controls = sizes
+        .iter()
+        .map(|n| b.merge(cs.by_ref().take(*n as usize).collect()))
+        .collect::<Result<Vec<_>, CircuitError>>()?;
+    let ancillas = if anc.is_empty() {
+        extra
+    } else {
+        let anc = b.merge(anc)?;
+        if use_temp {
+            b.return_temp_register(anc, false);
+            None
+        } else if let Some(extra) = extra {
+            Some(b.merge(vec![anc, extra])?)
+        } else {
+            Some(anc)
+        }
+    };
+    Ok((controls, r, ancillas))
+}
+
+/// Apply `mcx` to single qubits, with `cs.len() - 2` zeroed ancillas for three or more controls.
+/// The controls and ancillas are put back in place, returns the targets.
+fn mcx_qubits(
+    b: &mut dyn UnitaryBuilder,
+    cs: &mut [Option<Register>],
+    ts: Vec<Register>,
+    anc: &mut [Option<Register>],
+) -> Result<Vec<Register>, CircuitError> {
+    let m = cs.len();
+    let ts = match m {
+        0 => ts.into_iter().map(|t| b.x(t)).collect(),
+        1 => {
+            let c = cs[0].take().unwrap();
+            let (c, ts) = ts.into_iter().fold((c, vec![]), |(c, mut ts), t| {
+                let (c, t) = b.cnot(c, t);
+                ts.push(t);
+                (c, ts)
+            });
+            cs[0] = Some(c);
+            ts
+        }
+        _ => {
+            // Compute the AND of all but the last control into the last ancilla.
+            (0..m - 2).try_for_each(|i| mcx_chain_step(b, cs, anc, i))?;
+            let ca = cs[m - 1].take().unwrap();
+            let cb = if m == 2 {
+                cs[0].take().unwrap()
+            } else {
+                anc[m - 3].take().unwrap()
+            };
+            let (ca, cb, ts) =
+                ts.into_iter()
+                    .try_fold((ca, cb, vec![]), |(ca, cb, mut ts), t| {
+                        let (ca, cb, t) = ccx(b, ca, cb, t)?;
+                        ts.push(t);
+                        Ok((ca, cb, ts))
+                    })?;
+            cs[m - 1] = Some(ca);
+            if m == 2 {
+                cs[0] = Some(cb);
+            } else {
+                anc[m - 3] = Some(cb);
+            }
+            // Uncompute the ancillas.
+            (0..m - 2)
+                .rev()
+                .try_for_each(|i| mcx_chain_step(b, cs, anc, i))?;
+            ts
+        }
+    };
+    Ok(ts)
+}
+
+/// Flip ancilla `i` by the AND of control `i + 1` and the previous ancilla (or the first two
+/// controls for `i = 0`).
+fn mcx_chain_step(
+    b: &mut dyn UnitaryBuilder,
+    cs: &mut [Option<Register>],
+    anc: &mut [Option<Register>],
+    i: usize,
+) -> Result<(), CircuitError> {
+    let a = anc[i].take().unwrap();
+    if i == 0 {
+        let (ca, cb, a) = ccx(b, cs[0].take().unwrap(), cs[1].take().unwrap(), a)?;
+        cs[0] = Some(ca);
+        cs[1] = Some(cb);
+        anc[i] = Some(a);
+    } else {
+        let (ca, cb, a) = ccx(b, cs[i + 1].take().unwrap(), anc[i - 1].take().unwrap(), a)?;
+        cs[i + 1] = Some(ca);
+        anc[i - 1] = Some(cb);
+        anc[i] = Some(a);
+    }
+    Ok(())
+}
+
+/// Apply `p` layers of the quantum approximate optimization algorithm to `r`, starting from the
+/// uniform superposition. Layer `l` applies `exp(-i gammas[l] C)` for the cost hamiltonian `C`
+/// followed by the mixer `exp(-i betas[l] sum_j X_j)`. The cost hamiltonian is a weighted sum of
+/// strings of `Z` and `I`, where the character at position `j` acts on qubit `j` of `r`.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// // MaxCut on a triangle.
+/// let cost = [(0.5, "ZZI"), (0.5, "IZZ"), (0.5, "ZIZ")];
+/// let mut b = OpBuilder::new();
+/// let r = b.register(3)?;
+/// let r = qaoa(&mut b, r, &cost, 2, &[0.4, 0.8], &[0.7, 0.3])?;
+///
+/// # Ok(())
+/// # }
+/// ```
+pub fn qaoa(
+    b: &mut dyn UnitaryBuilder,
+    r: Register,
+    cost_hamiltonian: &[(f64, &str)],
+    p: usize,
+    gammas: &[f64],
+    betas: &[f64],
+) -> Result<Register, CircuitError> {
+    if gammas.len() != p || betas.len() != p {
+        let message = format!(
+            "Expected {:?} gammas and betas, found {:?} and {:?}",
+            p,
+            gammas.len(),
+            betas.len()
+        );
+        return CircuitError::make_err(message);
+    }
+    let n = r.n() as usize;
+    let terms = cost_hamiltonian
+        .iter()
+        .map(|(weight, pauli)| {
+            if pauli.len() > n {
+                let message = format!(
+                    "Pauli string {:?} is longer than the register ({:?} qubits)",
+                    pauli, n
+                );
+                return CircuitError::make_err(message);
+            }
+            pauli
+                .chars()
+                .enumerate()
+                .try_fold(vec![], |mut acc, (i, c)| match c {
+                    'Z' => {
+                        acc.push(i);
+                        Ok(acc)
+                    }
+                    'I' => Ok(acc),
+                    c => CircuitError::make_err(format!(
+                        "Cost hamiltonians may only contain Z and I, found {:?}",
+                        c
+                    )),
+                })
+                .map(|indices| (*weight, indices))
+        })
+        .collect::<Result<Vec<_>, CircuitError>>()?;
+
+    b.push_name_scope("QAOA");
+    let r = b.hadamard(r);
+    let mut qs: Vec<Option<Register>> = b.split_all(r).into_iter().map(Some).collect();
+    let result = gammas
+        .iter()
+        .zip(betas.iter())
+        .try_for_each(|(gamma, beta)| {
+            terms.iter().try_for_each(|(weight, indices)| {
+                z_string_rotation(b, &mut qs, indices, 2.0 * gamma * weight)
+            })?;
+            qs.iter_mut()
+                .for_each(|q| *q = Some(b.rx(q.take().unwrap(), 2.0 * beta)));
+            Ok(())
+        });
+    let result = result.and_then(|_| b.merge(qs.into_iter().map(|q| q.unwrap()).collect()));
+    b.pop_name_scope();
+    result
+}
+
+/// Apply `steps` steps of the first order Trotter decomposition of `exp(-i H time)` to `r`, where
+/// the hamiltonian `H` is a weighted sum of strings of `I`, `X`, `Y` and `Z`, and the character at
+/// position `j` acts on qubit `j` of `r`. Each step applies `exp(-i w P time/steps)` for each term
+/// `(w, P)` in order, so the result is exact when all the terms commute.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// // A transverse field ising model on 3 qubits.
+/// let hamiltonian = [(1.0, "ZZI"), (1.0, "IZZ"), (0.5, "XII"), (0.5, "IXI"), (0.5, "IIX")];
+/// let mut b = OpBuilder::new();
+/// let r = b.register(3)?;
+/// let r = trotter_evolve(&mut b, r, &hamiltonian, 1.0, 10)?;
+///
+/// # Ok(())
+/// # }
+/// ```
+pub fn trotter_evolve(
+    b: &mut dyn UnitaryBuilder,
+    r: Register,
+    pauli_sum: &[(f64, &str)],
+    time: f64,
+    steps: usize,
+) -> Result<Register, CircuitError> {
+    if steps == 0 {
+        return CircuitError::make_str_err("Expected at least one trotter step.");
+    }
+    // Check every term first so an invalid one leaves `b` untouched.
+    pauli_sum
+        .iter()
+        .try_for_each(|(_, pauli)| parse_pauli_string(pauli, r.n()).map(|_| ()))?;
+    b.push_name_scope("Trotter");
+    let dt = time / steps as f64;
+    let result = (0..steps).try_fold(r, |r, _| {
+        pauli_sum.iter().try_fold(r, |r, (weight, pauli)| {
+            b.exp_pauli(r, pauli, 2.0 * weight * dt)
+        })
+    });
+    b.pop_name_scope();
+    result
+}
+
+/// Apply `exp(-i theta/2 Z_{indices[0]} ... Z_{indices[k]})` to the qubits at `indices` by
+/// computing their parity onto the last of them.
+fn z_string_rotation(
+    b: &mut dyn UnitaryBuilder,
+    qs: &mut [Option<Register>],
+    indices: &[usize],
+    theta: f64,
+) -> Result<(), CircuitError> {
+    let (target, controls) = match indices.split_last() {
+        Some(split) => split,
+        // A string of identities is only a global phase.
+        None => return Ok(()),
+    };
+    let parity = |b: &mut dyn UnitaryBuilder, qs: &mut [Option<Register>]| {
+        controls.iter().for_each(|c| {
+            let (cq, t) = b.cnot(qs[*c].take().unwrap(), qs[*target].take().unwrap());
+            qs[*c] = Some(cq);
+            qs[*target] = Some(t);
+        })
+    };
+    parity(b, qs);
+    qs[*target] = Some(b.rz(qs[*target].take().unwrap(), theta));
+    parity(b, qs);
+    Ok(())
+}
+
+/// Apply the Grover diffusion operator `2|s><s| - I` (up to a global phase) to `r`, where `|s>` is
+/// the uniform superposition over all values of `r`.
+pub fn grover_diffusion(b: &mut dyn UnitaryBuilder, r: Register) -> Result<Register, CircuitError> {
+    b.push_name_scope("Diffusion");
+    let r = b.hadamard(r);
+    let result = flip_zero_phase(b, r).map(|r| b.hadamard(r));
+    b.pop_name_scope();
+    result
+}
+
+/// Search for a value marked by `oracle` among the `2^n` values of an `n` qubit register. The
+/// oracle should flip the phase of marked values, it is applied `iterations` times alternating
+/// with `grover_diffusion`, starting from the uniform superposition. Returns the measured value,
+/// which is a marked value with high probability when `iterations` is close to
+/// `pi/4 sqrt(2^n / M)` for `M` marked values. Oracles may not allocate qubits.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// // Mark |11> by flipping its phase.
+/// let oracle = |b: &mut dyn UnitaryBuilder, r: Register| {
+///     let (q0, q1) = b.split(r, &[0])?;
+///     let (q0, q1) = b.cz(q0, q1.unwrap());
+///     b.merge(vec![q0, q1])
+/// };
+/// // A single iteration finds one marked value out of four with certainty.
+/// assert_eq!(grover_search(oracle, 2, 1)?, 0b11);
+/// # Ok(())
+/// # }
+/// ```
+pub fn grover_search<F>(oracle: F, n: u64, iterations: u64) -> Result<u64, CircuitError>
+where
+    F: Fn(&mut dyn UnitaryBuilder, Register) -> Result<Register, CircuitError>,
+{
+    let mut b = OpBuilder::new();
+    let r = b.register(n)?;
+    let r = b.hadamard(r);
+    let mut rs = b.repeat(iterations, vec![r], |b, mut rs| {
+        b.push_name_scope("Grover");
+        let r = oracle(b, rs.pop().unwrap()).and_then(|r| grover_diffusion(b, r));
+        b.pop_name_scope();
+        Ok(vec![r?])
+    })?;
+    let (r, m) = b.measure(rs.pop().unwrap());
+    let (_, measured) = run_local::<f64>(&r)?;
+    Ok(measured.get_measurement(&m).unwrap().0)
+}
+
+/// Flip the phase of the `|0...0>` state of `r`.
+fn flip_zero_phase(b: &mut dyn UnitaryBuilder, r: Register) -> Result<Register, CircuitError> {
+    let n = r.n();
+    let r = b.not(r);
+    let r = if n == 1 {
+        b.z(r)
+    } else {
+        let indices: Vec<u64> = (0..n - 1).collect();
+        let (cr, t) = b.split(r, &indices)?;
+        let (cr, t) = b.cz(cr, t.unwrap());
+        b.merge(vec![cr, t])?
+    };
+    Ok(b.not(r))
+}
+
+/// Build the Bernstein-Vazirani circuit for an `n` bit `secret`, returning the `n` qubit input
+/// Register, which measures as `secret`, and the ancilla used by the oracle. The oracle is the
+/// `classical_oracle` for `f(x) = x . secret (mod 2)` acting on an ancilla in `|->`, so a single
+/// query reveals every bit of `secret`.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let (r, _) = bernstein_vazirani(&mut b, 0b1011, 4)?;
+/// let (r, m) = b.measure(r);
+///
+/// let (_, measured) = run_local::<f64>(&r)?;
+/// assert_eq!(measured.get_measurement(&m).map(|(v, _)| v), Some(0b1011));
+/// # Ok(())
+/// # }
+/// ```
+pub fn bernstein_vazirani(
+    b: &mut dyn UnitaryBuilder,
+    secret: u64,
+    n: u64,
+) -> Result<(Register, Register), CircuitError> {
+    if n < 64 && secret >> n != 0 {
+        let message = format!("Secret {:?} does not fit in {:?} bits", secret, n);
+        return CircuitError::make_err(message);
+    }
+    let r = b.register(n)?;
+    let ancilla = b.qubit();
+    let r = b.hadamard(r);
+    let ancilla = b.x(ancilla);
+    let ancilla = b.hadamard(ancilla);
+    let (r, ancilla) = classical_oracle(b, r, ancilla, move |x| {
+        u64::from((x & secret).count_ones() & 1)
+    })?;
+    let r = b.hadamard(r);
+    Ok((r, ancilla))
+}
+
+/// A sub-circuit applying `|x>|y> -> |x>|y ^ f(x)>` to an input Register and a single qubit.
+pub type OracleCircuitFn = dyn Fn(
+    &mut dyn UnitaryBuilder,
+    Register,
+    Register,
+) -> Result<(Register, Register), CircuitError>;
+
+/// The oracle for a function `f` which is promised to be constant or balanced, for
+/// `deutsch_jozsa`.
+pub enum DeutschJozsaOracle {
+    /// The function itself, applied with `classical_oracle`.
+    Function(Box<dyn Fn(u64) -> bool + Send + Sync>),
+    /// A sub-circuit computing the function into a single qubit.
+    Circuit(Box<OracleCircuitFn>),
+}
+
+impl std::fmt::Debug for DeutschJozsaOracle {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DeutschJozsaOracle::Function(_) => write!(f, "DeutschJozsaOracle::Function"),
+            DeutschJozsaOracle::Circuit(_) => write!(f, "DeutschJozsaOracle::Circuit"),
+        }
+    }
+}
+
+/// Build the Deutsch-Jozsa circuit on `n` qubits for `oracle`, returning the input Register and
+/// the ancilla used by the oracle. The input Register measures as `0` if the function is
+/// constant and as any other value if it is balanced.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// // The parity of the lowest bit is balanced.
+/// let oracle = DeutschJozsaOracle::Function(Box::new(|x| x & 1 == 1));
+/// let (r, _) = deutsch_jozsa(&mut b, oracle, 3)?;
+/// let (r, m) = b.measure(r);
+///
+/// let (_, measured) = run_local::<f64>(&r)?;
+/// assert_ne!(measured.get_measurement(&m).map(|(v, _)| v), Some(0));
+/// # Ok(())
+/// # }
+/// ```
+pub fn deutsch_jozsa(
+    b: &mut dyn UnitaryBuilder,
+    oracle: DeutschJozsaOracle,
+    n: u64,
+) -> Result<(Register, Register), CircuitError> {
+    let r = b.register(n)?;
+    let ancilla = b.qubit();
+    let r = b.hadamard(r);
+    let ancilla = b.x(ancilla);
+    let ancilla = b.hadamard(ancilla);
+    let (r, ancilla) = match oracle {
+        DeutschJozsaOracle::Function(f) => {
+            classical_oracle(b, r, ancilla, move |x| u64::from(f(x)))?
+        }
+        DeutschJozsaOracle::Circuit(f) => f(b, r, ancilla)?,
+    };
+    if r.n() != n || ancilla.n() != 1 {
+        let message = format!(
+            "Oracle returned registers of {:?} and {:?} qubits, expected {:?} and 1",
+            r.n(),
+            ancilla.n(),
+            n
+        );
+        return CircuitError::make_err(message);
+    }
+    let r = b.hadamard(r);
+    Ok((r, ancilla))
+}
+
+/// Apply quantum phase estimation to `target` for the unitary `U` using `precision`. The function
+/// `controlled_u` is called as `controlled_u(b, target, power)` to apply `U^power` to `target`,
+/// always inside a condition on one of the qubits of `precision`, so that `U^(2^j)` is
+/// conditioned on qubit `j`. The inverse qft is then applied to `precision`. If `target` is an
+/// eigenstate of `U` with eigenvalue `e^{2 pi i phi}` the value of `precision` is then the closest
+/// approximation to `phi 2^m` for `m` precision qubits.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use num::{One, Zero};
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// // U = diag(1, e^{2 pi i 5/8}) has eigenstate |1> with phi = 5/8.
+/// let phi = 5.0 / 8.0;
+/// let mut b = OpBuilder::new();
+/// let precision = b.register(3)?;
+/// let target = b.qubit();
+/// let target = b.not(target);
+/// let (precision, _) = phase_estimation(&mut b, precision, target, |b, r, power| {
+///     let theta = 2.0 * std::f64::consts::PI * phi * power as f64;
+///     let phase = Complex::from_polar(&1.0, &theta);
+///     b.mat("U", r, vec![Complex::one(), Complex::zero(), Complex::zero(), phase])
+/// })?;
+/// let (precision, m) = b.measure(precision);
+///
+/// let (_, measured) = run_local::<f64>(&precision)?;
+/// assert_eq!(measured.get_measurement(&m).unwrap().0, 5);
+/// # Ok(())
+/// # }
+/// ```
+pub fn phase_estimation<F>(
+    b: &mut dyn UnitaryBuilder,
+    precision: Register,
+    target: Register,
+    controlled_u: F,
+) -> Result<(Register, Register), CircuitError>
+where
+    F: Fn(&mut dyn UnitaryBuilder, Register, u64) -> Result<Register, CircuitError>,
+{
+    b.push_name_scope("QPE");
+    let precision = b.hadamard(precision);
+    let qs = b.split_all(precision);
+    let result = qs
+        .into_iter()
+        .enumerate()
+        .try_fold((vec![], target), |(mut acc, target), (j, q)| {
+            let (q, target) =
+                try_condition(b, q, target, |b, target| controlled_u(b, target, 1 << j))?;
+            acc.push(q);
+            Ok((acc, target))
+        })
+        .and_then(|(qs, target)| {
+            let precision = b.merge(qs)?;
+            let precision = inverse_qft(b, precision)?;
+            Ok((precision, target))
+        });
+    b.pop_name_scope();
+    result
+}
+
+/// Apply the Grover operator `Q = -A S_0 A^dagger S_oracle` for amplitude amplification, where
+/// `A` is `state_prep`, `S_oracle` is given by `oracle` and should flip the phase of the good
+/// states, and `S_0` flips the phase of `|0...0>`. The global phase is included so that if `A|0>`
+/// has probability `sin^2(theta)` of being in a good state then `Q` has eigenvalues
+/// `e^{+-2 i theta}` on the space spanned by `A|0>`, which matters once `Q` is conditioned.
+pub fn grover_operator<A, O>(
+    b: &mut dyn UnitaryBuilder,
+    r: Register,
+    state_prep: A,
+    oracle: O,
+) -> Result<Register, CircuitError>
+where
+    A: Fn(&mut dyn UnitaryBuilder, Register) -> Result<Register, CircuitError>,
+    O: Fn(&mut dyn UnitaryBuilder, Register) -> Result<Register, CircuitError>,
+{
+    b.push_name_scope("GroverOperator");
+    let result = oracle(b, r).and_then(|r| {
+        let mut rs = inverter(b, vec![r], |b, mut rs| {
+            Ok(vec![state_prep(b, rs.pop().unwrap())?])
+        })?;
+        let r = flip_zero_phase(b, rs.pop().unwrap())?;
+        let r = state_prep(b, r)?;
+        let (q, rest) = b.split(r, &[0])?;
+        let q = b.phase(q, std::f64::consts::PI);
+        match rest {
+            Some(rest) => b.merge(vec![q, rest]),
+            None => Ok(q),
+        }
+    });
+    b.pop_name_scope();
+    result
+}
+
+/// Prepare a state with `state_prep` (`A`) on `r` then apply `k` rounds of the generalized Grover
+/// operator `-A S_0 A^dagger S_oracle` (see `grover_operator`), where `S_oracle` is given by
+/// `oracle` and should flip the phase of the good states while `S_0` flips the phase of
+/// `|0...0>`. If `A|0>` has probability `sin^2(theta)` of being in a good state then the final
+/// probability is `sin^2((2k + 1) theta)`. Neither closure may measure, and `state_prep` may not
+/// use classical side channels since its inverse is applied.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// // Prepare |1> with probability 1/4, so theta = pi/6 and a single round finds it.
+/// let mut b = OpBuilder::new();
+/// let r = b.qubit();
+/// let r = amplitude_amplification(
+///     &mut b,
+///     r,
+///     |b, r| Ok(b.ry(r, std::f64::consts::PI / 3.0)),
+///     |b, r| Ok(b.z(r)),
+///     1,
+/// )?;
+///
+/// let (state, _) = run_local::<f64>(&r)?;
+/// assert!((state.get_state(true)[1].norm_sqr() - 1.0).abs() < 1e-10);
+/// # Ok(())
+/// # }
+/// ```
+pub fn amplitude_amplification<A, O>(
+    b: &mut dyn UnitaryBuilder,
+    r: Register,
+    state_prep: A,
+    oracle: O,
+    k: u64,
+) -> Result<Register, CircuitError>
+where
+    A: Fn(&mut dyn UnitaryBuilder, Register) -> Result<Register, CircuitError>,
+    O: Fn(&mut dyn UnitaryBuilder, Register) -> Result<Register, CircuitError>,
+{
+    b.push_name_scope("AmplitudeAmplification");
+    let result = state_prep(b, r)
+        .and_then(|r| (0..k).try_fold(r, |r, _| grover_operator(b, r, &state_prep, &oracle)));
+    b.pop_name_scope();
+    result
+}
+
 #[cfg(test)]
 mod common_circuit_tests {
     use super::*;
+    use crate::pipeline::{make_circuit_matrix, run_local_with_init};
     use crate::run_debug;
+    use crate::utils::flip_bits;
+    use crate::QuantumState;
+
+    fn assert_matrices_close(a: &[Vec<Complex<f64>>], b: &[Vec<Complex<f64>>]) {
+        assert_eq!(a.len(), b.len());
+        a.iter().zip(b.iter()).for_each(|(ra, rb)| {
+            assert_eq!(ra.len(), rb.len());
+            ra.iter().zip(rb.iter()).for_each(|(va, vb)| {
+                assert!((va - vb).norm() < 1e-10, "{:?} != {:?}", va, vb);
+            })
+        });
+    }
+
+    #[test]
+    fn test_ghz() -> Result<(), CircuitError> {
+        for n in 1..=4 {
+            let mut b = OpBuilder::new();
+            let r = ghz(&mut b, n)?;
+            let (state, _) = run_local::<f64>(&r)?;
+            let state = state.get_state(true);
+            state.iter().enumerate().for_each(|(i, v)| {
+                let expected = if i == 0 || i == (1 << n) - 1 {
+                    0.5
+                } else {
+                    0.0
+                };
+                assert!((v.norm_sqr() - expected).abs() < 1e-10);
+            });
+        }
+        let mut b = OpBuilder::new();
+        assert!(ghz(&mut b, 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_swap_test_probability() -> Result<(), CircuitError> {
+        // |<0|Ry(theta)0>|^2 = cos^2(theta / 2)
+        let theta = 1.2f64;
+        let mut b = OpBuilder::new();
+        let ra = b.qubit();
+        let rb = b.qubit();
+        let rb = b.ry(rb, theta);
+        let (q, ra, rb) = swap_test(&mut b, ra, rb)?;
+        let indices = q.indices.clone();
+        let r = b.merge(vec![q, ra, rb])?;
+        let (mut state, _) = run_local::<f64>(&r)?;
+        let probs = state.stochastic_measure(&indices, 0.0);
+        let overlap = (theta / 2.0).cos().powi(2);
+        assert!((probs[0] - (1.0 + overlap) / 2.0).abs() < 1e-10);
+        Ok(())
+    }
+
+    #[test]
+    fn test_swap_test_overlap() -> Result<(), CircuitError> {
+        let same = swap_test_overlap(2, |b, r| Ok(b.hadamard(r)), |b, r| Ok(b.hadamard(r)), 100)?;
+        assert!((same - 1.0).abs() < 1e-10);
+        let orthogonal = swap_test_overlap(2, |_, r| Ok(r), |b, r| Ok(b.not(r)), 2000)?;
+        assert!(orthogonal < 0.15);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hadamard_test_probabilities() -> Result<(), CircuitError> {
+        // <+|Rz(theta)|+> = cos(theta / 2)
+        let theta = 0.9f64;
+        let expected = [(theta / 2.0).cos(), 0.0];
+        for (imaginary, expected) in [false, true].iter().zip(expected.iter()) {
+            let mut b = OpBuilder::new();
+            let cr = b.qubit();
+            let r = b.qubit();
+            let r = b.hadamard(r);
+            let u = |b: &mut dyn UnitaryBuilder, r| Ok(b.rz(r, theta));
+            let (cr, r) = if *imaginary {
+                hadamard_test_imaginary(&mut b, cr, r, u)?
+            } else {
+                hadamard_test(&mut b, cr, r, u)?
+            };
+            let indices = cr.indices.clone();
+            let r = b.merge(vec![cr, r])?;
+            let (mut state, _) = run_local::<f64>(&r)?;
+            let probs = state.stochastic_measure(&indices, 0.0);
+            assert!((probs[0] - (1.0 + expected) / 2.0).abs() < 1e-10);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_hadamard_test_imaginary() -> Result<(), CircuitError> {
+        // <0|S|0> = 1 but <1|S|1> = i, so for |+> the value is (1 + i) / 2.
+        let mut b = OpBuilder::new();
+        let cr = b.qubit();
+        let r = b.qubit();
+        let r = b.hadamard(r);
+        let (cr, r) = hadamard_test_imaginary(&mut b, cr, r, |b, r| Ok(b.s(r)))?;
+        let indices = cr.indices.clone();
+        let r = b.merge(vec![cr, r])?;
+        let (mut state, _) = run_local::<f64>(&r)?;
+        let probs = state.stochastic_measure(&indices, 0.0);
+        assert!((probs[0] - 0.75).abs() < 1e-10);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hadamard_test_estimate() -> Result<(), CircuitError> {
+        // |++> is an eigenstate of XX with eigenvalue 1.
+        let value = hadamard_test_estimate(2, |b, r| Ok(b.hadamard(r)), |b, r| Ok(b.not(r)), 100)?;
+        assert!((value.re - 1.0).abs() < 1e-10);
+        let value = hadamard_test_estimate(1, |b, r| Ok(b.not(r)), |b, r| Ok(b.s(r)), 100)?;
+        assert!((value.im - 1.0).abs() < 1e-10);
+        Ok(())
+    }
+
+    fn trotter_state(
+        n: u64,
+        hamiltonian: &[(f64, &str)],
+        time: f64,
+        steps: usize,
+    ) -> Result<Vec<Complex<f64>>, CircuitError> {
+        let mut b = OpBuilder::new();
+        let r = b.register(n)?;
+        let r = trotter_evolve(&mut b, r, hamiltonian, time, steps)?;
+        let (state, _) = run_local::<f64>(&r)?;
+        Ok(state.get_state(true))
+    }
+
+    #[test]
+    fn test_trotter_single_paulis() -> Result<(), CircuitError> {
+        let t = 0.7f64;
+        let (cos, sin) = (t.cos(), t.sin());
+        // exp(-itX)|0> = cos|0> - i sin|1>
+        let state = trotter_state(1, &[(1.0, "X")], t, 1)?;
+        assert!((state[0] - Complex::new(cos, 0.0)).norm() < 1e-10);
+        assert!((state[1] - Complex::new(0.0, -sin)).norm() < 1e-10);
+        // exp(-itY)|0> = cos|0> + sin|1>
+        let state = trotter_state(1, &[(1.0, "Y")], t, 1)?;
+        assert!((state[0] - Complex::new(cos, 0.0)).norm() < 1e-10);
+        assert!((state[1] - Complex::new(sin, 0.0)).norm() < 1e-10);
+        // X0 Y1 |00> = i|11>, so exp(-it X0 Y1)|00> = cos|00> + sin|11>
+        let state = trotter_state(2, &[(1.0, "XY")], t, 3)?;
+        assert!((state[0] - Complex::new(cos, 0.0)).norm() < 1e-10);
+        assert!((state[3] - Complex::new(sin, 0.0)).norm() < 1e-10);
+        Ok(())
+    }
+
+    #[test]
+    fn test_trotter_converges() -> Result<(), CircuitError> {
+        // H = aX + cZ has exp(-iHt) = cos(ht) - i sin(ht) H / h for h = sqrt(a^2 + c^2).
+        let (a, c, t) = (0.8f64, 0.6f64, 1.3f64);
+        let h = (a * a + c * c).sqrt();
+        let expected = [
+            Complex::new((h * t).cos(), -(h * t).sin() * c / h),
+            Complex::new(0.0, -(h * t).sin() * a / h),
+        ];
+        let error = |steps| -> Result<f64, CircuitError> {
+            let state = trotter_state(1, &[(a, "X"), (c, "Z")], t, steps)?;
+            Ok((state[0] - expected[0]).norm() + (state[1] - expected[1]).norm())
+        };
+        let coarse = error(2)?;
+        let fine = error(200)?;
+        assert!(fine < 1e-2);
+        assert!(fine < coarse / 10.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_trotter_errors() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let r = b.register(2)?;
+        assert!(trotter_evolve(&mut b, r, &[(1.0, "XQ")], 1.0, 1).is_err());
+        let r = b.register(2)?;
+        assert!(trotter_evolve(&mut b, r, &[(1.0, "XXX")], 1.0, 1).is_err());
+        let r = b.register(2)?;
+        assert!(trotter_evolve(&mut b, r, &[(1.0, "XX")], 1.0, 0).is_err());
+
+        // A valid term before an invalid one adds no ops, so the next op id is the same as in a
+        // builder where trotter_evolve was never called.
+        let next_op_id = |call: bool| -> Result<u64, CircuitError> {
+            let mut b = OpBuilder::new();
+            let r = b.register(2)?;
+            if call {
+                let terms = [(1.0, "XZ"), (1.0, "XQ")];
+                assert!(trotter_evolve(&mut b, r, &terms, 1.0, 2).is_err());
+            }
+            let q = b.qubit();
+            let (_, m) = b.measure(q);
+            Ok(m.get_id())
+        };
+        assert_eq!(next_op_id(true)?, next_op_id(false)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_w_state() -> Result<(), CircuitError> {
+        for n in 1..=5 {
+            let mut b = OpBuilder::new();
+            let r = w_state(&mut b, n)?;
+            let (state, _) = run_local::<f64>(&r)?;
+            let state = state.get_state(true);
+            state.iter().enumerate().for_each(|(i, v)| {
+                let expected = if i.count_ones() == 1 {
+                    1.0 / n as f64
+                } else {
+                    0.0
+                };
+                assert!((v.norm_sqr() - expected).abs() < 1e-10);
+            });
+        }
+        Ok(())
+    }
 
     #[test]
     fn test_work_on() -> Result<(), CircuitError> {
@@ -77,4 +1450,362 @@ mod common_circuit_tests {
         assert_eq!(r_indices, r.indices);
         Ok(())
     }
+
+    /// Run the circuit built by `f` on each of the basis states of `n` qubits and check that it
+    /// maps `|x>` to `|expected(x)>`.
+    fn check_basis_map<F, G>(n: u64, f: F, expected: G) -> Result<(), CircuitError>
+    where
+        F: Fn(&mut OpBuilder, Register) -> Result<Register, CircuitError>,
+        G: Fn(u64) -> u64,
+    {
+        (0..1 << n).try_for_each(|x| {
+            let mut b = OpBuilder::new();
+            let r = b.register(n)?;
+            let h = r.handle();
+            let r = f(&mut b, r)?;
+            let (state, _) = run_local_with_init::<f64>(&r, &[h.make_init_from_index(x)?])?;
+            let state = state.get_state(true);
+            let y = expected(x);
+            assert!(
+                (state[y as usize].norm() - 1.0).abs() < 1e-10,
+                "{} -> {}",
+                x,
+                y
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_ccx_matrix() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let r = b.register(3)?;
+        let mut qs = b.split_all(r);
+        let r = qs.pop().unwrap();
+        let cb = qs.pop().unwrap();
+        let ca = qs.pop().unwrap();
+        let (ca, cb, r) = ccx(&mut b, ca, cb, r)?;
+        let r = b.merge(vec![ca, cb, r])?;
+
+        let mut nb = OpBuilder::new();
+        let nr = nb.register(3)?;
+        let (cs, nr) = nb.split(nr, &[0, 1])?;
+        let (cs, nr) = nb.cnot(cs, nr.unwrap());
+        let nr = nb.merge(vec![cs, nr])?;
+
+        let circuit = make_circuit_matrix::<f64>(3, &r, false);
+        let expected = make_circuit_matrix::<f64>(3, &nr, false);
+        assert_matrices_close(&circuit, &expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ccx_requires_qubits() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let ca = b.register(2)?;
+        let cb = b.qubit();
+        let r = b.qubit();
+        assert!(ccx(&mut b, ca, cb, r).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cccx() -> Result<(), CircuitError> {
+        let f = |b: &mut OpBuilder, r: Register| {
+            let mut qs = b.split_all(r);
+            let t = qs.pop().unwrap();
+            let cc = qs.pop().unwrap();
+            let cb = qs.pop().unwrap();
+            let ca = qs.pop().unwrap();
+            let (ca, cb, cc, t) = cccx(b, ca, cb, cc, t)?;
+            b.merge(vec![ca, cb, cc, t])
+        };
+        check_basis_map(4, f, |x| if x & 0b111 == 0b111 { x ^ 0b1000 } else { x })
+    }
+
+    #[test]
+    fn test_mcx_temp_ancillas() -> Result<(), CircuitError> {
+        let f = |b: &mut OpBuilder, r: Register| {
+            let (cs, t) = b.split(r, &[0, 1, 2, 3, 4])?;
+            let (ca, cb) = b.split(cs, &[0, 1])?;
+            let (mut cs, t, anc) = mcx(b, vec![ca, cb.unwrap()], t.unwrap(), None)?;
+            assert!(anc.is_none());
+            let cb = cs.pop().unwrap();
+            let ca = cs.pop().unwrap();
+            assert_eq!((ca.n(), cb.n()), (2, 3));
+            b.merge(vec![ca, cb, t])
+        };
+        check_basis_map(6, f, |x| {
+            if x & 0b11111 == 0b11111 {
+                x ^ 0b100000
+            } else {
+                x
+            }
+        })
+    }
+
+    #[test]
+    fn test_mcx_given_ancillas() -> Result<(), CircuitError> {
+        // 4 controls and 1 target, with 3 fresh ancillas of which only 2 are used.
+        let f = |b: &mut OpBuilder, r: Register| {
+            let anc = b.register(3)?;
+            let (cs, t) = b.split(r, &[0, 1, 2, 3])?;
+            let (cs, t, anc) = mcx(b, vec![cs], t.unwrap(), Some(anc))?;
+            let anc = anc.unwrap();
+            assert_eq!(anc.n(), 3);
+            let mut rs = cs;
+            rs.push(t);
+            rs.push(anc);
+            b.merge(rs)
+        };
+        // The ancillas are left in |000>.
+        check_basis_map(5, f, |x| if x & 0b1111 == 0b1111 { x ^ 0b10000 } else { x })
+    }
+
+    #[test]
+    fn test_mcx_few_controls() -> Result<(), CircuitError> {
+        let f = |b: &mut OpBuilder, r: Register| {
+            let (c, t) = b.split(r, &[0])?;
+            let (mut cs, t, _) = mcx(b, vec![c], t.unwrap(), None)?;
+            b.merge(vec![cs.pop().unwrap(), t])
+        };
+        // A single control flips each target qubit.
+        check_basis_map(3, f, |x| if x & 1 == 1 { x ^ 0b110 } else { x })
+    }
+
+    #[test]
+    fn test_mcx_too_few_ancillas() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let cs = b.register(4)?;
+        let t = b.qubit();
+        let anc = b.qubit();
+        assert!(mcx(&mut b, vec![cs], t, Some(anc)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_qft_matrix() -> Result<(), CircuitError> {
+        let n = 3;
+        let mut b = OpBuilder::new();
+        let r = b.register(n)?;
+        let r = qft(&mut b, r)?;
+        run_debug(&r)?;
+
+        let size = 1 << n;
+        let norm = 1.0 / (size as f64).sqrt();
+        // Matrix indices have qubit 0 as the most significant bit, register values have it as
+        // the least significant bit.
+        let expected: Vec<Vec<Complex<f64>>> = (0..size)
+            .map(|row| {
+                (0..size)
+                    .map(|col| {
+                        let x = flip_bits(n as usize, col);
+                        let y = flip_bits(n as usize, row);
+                        let theta = 2.0 * std::f64::consts::PI * (x * y) as f64 / size as f64;
+                        Complex { re: 0.0, im: theta }.exp() * norm
+                    })
+                    .collect()
+            })
+            .collect();
+        let circuit = make_circuit_matrix::<f64>(n, &r, false);
+        assert_matrices_close(&circuit, &expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_inverse_qft() -> Result<(), CircuitError> {
+        let n = 4;
+        let mut b = OpBuilder::new();
+        let r = b.register(n)?;
+        let r = qft(&mut b, r)?;
+        let r = inverse_qft(&mut b, r)?;
+
+        let identity: Vec<Vec<Complex<f64>>> = (0..1 << n)
+            .map(|row| {
+                (0..1 << n)
+                    .map(|col| {
+                        if row == col {
+                            Complex::one()
+                        } else {
+                            Complex::zero()
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let circuit = make_circuit_matrix::<f64>(n, &r, false);
+        assert_matrices_close(&circuit, &identity);
+        Ok(())
+    }
+
+    #[test]
+    fn test_qaoa_state() -> Result<(), CircuitError> {
+        let n = 3;
+        let cost = [(0.5, "ZZI"), (-1.5, "IZZ"), (0.25, "ZIZ"), (0.75, "IZ")];
+        let (gammas, betas) = ([0.4, -0.9], [0.7, 0.2]);
+        let mut b = OpBuilder::new();
+        let r = b.register(n)?;
+        let r = qaoa(&mut b, r, &cost, 2, &gammas, &betas)?;
+        let (state, _) = run_local_with_init::<f64>(&r, &[])?;
+        let state = state.get_state(true);
+
+        // Compute the state directly, index bit j is qubit j.
+        let size = 1usize << n;
+        let cost_of = |x: usize| -> f64 {
+            cost.iter()
+                .map(|(w, pauli)| {
+                    let parity = pauli
+                        .chars()
+                        .enumerate()
+                        .filter(|(j, c)| *c == 'Z' && (x >> j) & 1 == 1)
+                        .count();
+                    if parity % 2 == 0 {
+                        *w
+                    } else {
+                        -*w
+                    }
+                })
+                .sum()
+        };
+        let norm = 1.0 / (size as f64).sqrt();
+        let mut expected = vec![Complex { re: norm, im: 0.0 }; size];
+        gammas.iter().zip(betas.iter()).for_each(|(gamma, beta)| {
+            (0..size).for_each(|x| {
+                expected[x] *= Complex::from_polar(&1.0, &(-gamma * cost_of(x)));
+            });
+            let (sin, cos) = beta.sin_cos();
+            (0..n).for_each(|j| {
+                let mask = 1 << j;
+                (0..size).filter(|x| x & mask == 0).for_each(|x| {
+                    let (a, b) = (expected[x], expected[x | mask]);
+                    let minus_i_sin = Complex { re: 0.0, im: -sin };
+                    expected[x] = a * cos + b * minus_i_sin;
+                    expected[x | mask] = a * minus_i_sin + b * cos;
+                });
+            });
+        });
+
+        // Matches up to global phase.
+        let phase = expected[0] / state[0];
+        state.iter().zip(expected.iter()).for_each(|(a, e)| {
+            assert!((a * phase - e).norm() < 1e-10);
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn test_qaoa_errors() {
+        let mut b = OpBuilder::new();
+        let r = b.register(2).unwrap();
+        assert!(qaoa(&mut b, r, &[(1.0, "ZX")], 1, &[0.1], &[0.1]).is_err());
+        let r = b.register(2).unwrap();
+        assert!(qaoa(&mut b, r, &[(1.0, "ZZZ")], 1, &[0.1], &[0.1]).is_err());
+        let r = b.register(2).unwrap();
+        assert!(qaoa(&mut b, r, &[(1.0, "ZZ")], 2, &[0.1], &[0.1]).is_err());
+    }
+
+    fn not_bits(b: &mut OpBuilder, r: Register, mask: u64) -> Result<Register, CircuitError> {
+        let qs = b
+            .split_all(r)
+            .into_iter()
+            .enumerate()
+            .map(|(i, q)| if (mask >> i) & 1 == 1 { b.not(q) } else { q })
+            .collect();
+        b.merge(qs)
+    }
+
+    #[test]
+    fn test_grover_iterations() -> Result<(), CircuitError> {
+        let n = 4;
+        let marked = 0b1011u64;
+        let theta = (1.0 / ((1 << n) as f64).sqrt()).asin();
+        (0..4).try_for_each(|k| {
+            let mut b = OpBuilder::new();
+            let r = b.register(n)?;
+            let r = b.hadamard(r);
+            let r = (0..k).try_fold(r, |r, _| {
+                // Flip the phase of |marked> by mapping it to |0000>.
+                let r = not_bits(&mut b, r, marked)?;
+                let r = flip_zero_phase(&mut b, r)?;
+                let r = not_bits(&mut b, r, marked)?;
+                grover_diffusion(&mut b, r)
+            })?;
+            let (state, _) = run_local::<f64>(&r)?;
+            let p = state.get_state(true)[marked as usize].norm_sqr();
+            let expected = ((2 * k + 1) as f64 * theta).sin().powi(2);
+            assert!((p - expected).abs() < 1e-10);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_grover_search() -> Result<(), CircuitError> {
+        // With one marked value out of four a single iteration always succeeds.
+        let found = grover_search(
+            |b, r| {
+                let r = b.x(r);
+                let r = flip_zero_phase(b, r)?;
+                Ok(b.x(r))
+            },
+            2,
+            1,
+        )?;
+        assert_eq!(found, 0b11);
+        Ok(())
+    }
+
+    #[test]
+    fn test_phase_estimation() -> Result<(), CircuitError> {
+        let m = 4;
+        (0..1u64 << m).try_for_each(|k| {
+            let phi = k as f64 / (1 << m) as f64;
+            let mut b = OpBuilder::new();
+            let precision = b.register(m)?;
+            let target = b.qubit();
+            let target = b.not(target);
+            let (precision, target) =
+                phase_estimation(&mut b, precision, target, |b, r, power| {
+                    let theta = 2.0 * std::f64::consts::PI * phi * power as f64;
+                    let phase = Complex::from_polar(&1.0, &theta);
+                    b.mat(
+                        "U",
+                        r,
+                        vec![Complex::one(), Complex::zero(), Complex::zero(), phase],
+                    )
+                })?;
+            let r = b.merge(vec![precision, target])?;
+            let (state, _) = run_local::<f64>(&r)?;
+            // Precision holds k, target is still |1>.
+            let index = k | (1 << m);
+            assert!((state.get_state(true)[index as usize].norm() - 1.0).abs() < 1e-10);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_amplitude_amplification() -> Result<(), CircuitError> {
+        let alpha = 0.5f64;
+        // Good state |11> has probability sin^4(alpha / 2) after ry(alpha) on both qubits.
+        let theta = (alpha / 2.0).sin().powi(2).asin();
+        (0..5).try_for_each(|k| {
+            let mut b = OpBuilder::new();
+            let r = b.register(2)?;
+            let r = amplitude_amplification(
+                &mut b,
+                r,
+                |b, r| Ok(b.ry(r, alpha)),
+                |b, r| {
+                    let (q0, q1) = b.split(r, &[0])?;
+                    let (q0, q1) = b.cz(q0, q1.unwrap());
+                    b.merge(vec![q0, q1])
+                },
+                k,
+            )?;
+            let (state, _) = run_local::<f64>(&r)?;
+            let p = state.get_state(true)[0b11].norm_sqr();
+            let expected = ((2 * k + 1) as f64 * theta).sin().powi(2);
+            assert!((p - expected).abs() < 1e-10);
+            Ok(())
+        })
+    }
 }
@@ -0,0 +1,53 @@
+/// State struct
+pub mod state;
+
+use crate::pipeline::{
+    get_opfns_and_frontier, get_required_state_size, get_required_state_size_from_frontier,
+    run_with_statebuilder, MeasuredResults, RegisterInitialState, StateModifier, StateModifierType,
+};
+use crate::{CircuitError, Precision, QuantumState, Register};
+pub use state::DensityMatrixState;
+
+/// Check if `modifier` chooses ops from the results of measurements.
+fn is_feed_forward(modifier: &StateModifier) -> bool {
+    match &modifier.modifier {
+        StateModifierType::SideChannelModifiers(_, _) => true,
+        StateModifierType::Subcircuit(modifiers) => modifiers.iter().any(is_feed_forward),
+        _ => false,
+    }
+}
+
+/// Check if the circuit ending at `r` has side channels, which need selective measurements.
+fn has_feed_forward(r: &Register) -> bool {
+    let (_, ops) = get_opfns_and_frontier(r);
+    ops.into_iter().any(is_feed_forward)
+}
+
+/// `run` the pipeline using `DensityMatrixState`. Measurements are selective if the circuit has
+/// side channels, and otherwise decohere the measured qubits.
+pub fn run_density_local<P: Precision>(
+    r: &Register,
+) -> Result<(DensityMatrixState<P>, MeasuredResults<P>), CircuitError> {
+    let selective = has_feed_forward(r);
+    run_with_statebuilder(r, |rs| {
+        let n = get_required_state_size_from_frontier(&rs);
+        let mut state: DensityMatrixState<P> = QuantumState::new(n);
+        state.set_selective_measurement(selective);
+        Ok(state)
+    })
+}
+
+/// `run_with_init` the pipeline using `DensityMatrixState`, with measurements chosen as in
+/// `run_density_local`.
+pub fn run_density_local_with_init<P: Precision>(
+    r: &Register,
+    states: &[RegisterInitialState<P>],
+) -> Result<(DensityMatrixState<P>, MeasuredResults<P>), CircuitError> {
+    let selective = has_feed_forward(r);
+    run_with_statebuilder(r, |rs| {
+        let n = get_required_state_size(&rs, states);
+        let mut state: DensityMatrixState<P> = QuantumState::new_from_initial_states(n, states);
+        state.set_selective_measurement(selective);
+        Ok(state)
+    })
+}
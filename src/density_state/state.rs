@@ -0,0 +1,491 @@
+use crate::errors::CircuitError;
+use crate::measurement_ops::{MeasuredCondition, PauliMasks};
+use crate::parallel::prelude::*;
+use crate::pipeline::{InitialState, LocalQuantumState};
+use crate::rng;
+use crate::state_ops::{apply_op, from_reals, make_matrix_op, UnitaryOp};
+use crate::utils::{extract_bits, flip_bits};
+use crate::{Complex, Precision, QuantumState};
+use num::Zero;
+
+/// A quantum state stored as a full density matrix `rho`, allowing mixed states.
+///
+/// The matrix is kept as a vector of length `4^n` where entry `(row << n) | col` is
+/// `<row|rho|col>`. Read as a state on `2n` qubits, qubit `i` of the circuit lines up with qubit
+/// `i` of the row half, which lets unitaries be applied with the same kernels as
+/// `LocalQuantumState`.
+///
+/// By default measurements are not selective: the measured qubits are decohered (the off
+/// diagonal blocks between different outcomes are traced out) and the reported result is sampled
+/// from the outcome distribution. Use `set_selective_measurement` to instead project onto the
+/// sampled outcome, which is required for circuits with side channels: running one on a state
+/// with non selective measurements returns an error. `run_density_local` and
+/// `run_density_local_with_init` turn on selective measurements for such circuits.
+#[derive(Debug)]
+pub struct DensityMatrixState<P: Precision> {
+    n: u64,
+    state: Vec<Complex<P>>,
+    arena: Vec<Complex<P>>,
+    multithread: bool,
+    selective: bool,
+}
+
+impl<P: Precision> DensityMatrixState<P> {
+    /// Make a density matrix for the pure state `psi`, given in the same order as
+    /// `QuantumState::get_state`.
+    pub fn new_from_pure_state(
+        n: u64,
+        psi: &[Complex<P>],
+        natural_order: bool,
+    ) -> DensityMatrixState<P> {
+        let psi: Vec<Complex<P>> = if natural_order {
+            (0..psi.len())
+                .map(|i| psi[flip_bits(n as usize, i as u64) as usize])
+                .collect()
+        } else {
+            psi.to_vec()
+        };
+        let size = psi.len();
+        let state = (0..size * size)
+            .map(|i| psi[i / size] * psi[i % size].conj())
+            .collect();
+        DensityMatrixState {
+            n,
+            state,
+            arena: vec![Complex::zero(); size * size],
+            multithread: true,
+            selective: false,
+        }
+    }
+
+    /// Return a reference to the internal row major density matrix.
+    pub fn state_ref(&self) -> &Vec<Complex<P>> {
+        &self.state
+    }
+
+    /// Get the entry `<row|rho|col>` where qubit 0 is the least significant bit if `natural_order`.
+    pub fn get_entry(&self, row: u64, col: u64, natural_order: bool) -> Complex<P> {
+        let (row, col) = if natural_order {
+            (
+                flip_bits(self.n as usize, row),
+                flip_bits(self.n as usize, col),
+            )
+        } else {
+            (row, col)
+        };
+        self.state[((row << self.n) | col) as usize]
+    }
+
+    /// Get the purity `tr(rho^2)` of the state, 1.0 for pure states.
+    pub fn purity(&self) -> P {
+        // rho is hermitian so tr(rho^2) is the sum of |rho_ij|^2
+        if self.multithread {
+            self.state.par_iter().map(Complex::<P>::norm_sqr).sum()
+        } else {
+            self.state.iter().map(Complex::<P>::norm_sqr).sum()
+        }
+    }
+
+    /// Rotate to a new computational basis:
+    /// `|0'> =  cos(angle)|0> + sin(angle)|1>`
+    /// `|1'> = -sin(angle)|0> + cos(angle)|1>`
+    pub fn rotate_basis(&mut self, indices: &[u64], angle: f64) {
+        if angle != 0.0 {
+            let (sangle, cangle) = angle.sin_cos();
+            let basis_mat = from_reals(&[cangle, -sangle, sangle, cangle]);
+            indices.iter().for_each(|indx| {
+                let op = make_matrix_op(vec![*indx], basis_mat.clone()).unwrap();
+                self.apply_op(&op);
+            });
+        }
+    }
+
+    /// Set whether the state will use multithreading.
+    pub fn set_multithreading(&mut self, multithread: bool) {
+        self.multithread = multithread;
+    }
+
+    /// Set whether measurements project onto the measured outcome rather than decohering the
+    /// measured qubits.
+    pub fn set_selective_measurement(&mut self, selective: bool) {
+        self.selective = selective;
+    }
+
+    /// Overwrite `arena` with the conjugate transpose of `state`.
+    fn dagger_into_arena(&mut self) {
+        let n = self.n;
+        let mask = (1 << n) - 1;
+        let state = &self.state;
+        let f = |(i, outputloc): (usize, &mut Complex<P>)| {
+            let (row, col) = (i as u64 >> n, i as u64 & mask);
+            *outputloc = state[((col << n) | row) as usize].conj();
+        };
+        if self.multithread {
+            self.arena.par_iter_mut().enumerate().for_each(f);
+        } else {
+            self.arena.iter_mut().enumerate().for_each(f);
+        }
+    }
+
+    /// Apply `op` to the row half of the vectorized matrix, `state -> op * state`.
+    fn left_multiply(&mut self, op: &UnitaryOp) {
+        apply_op(
+            2 * self.n,
+            op,
+            &self.state,
+            &mut self.arena,
+            0,
+            0,
+            self.multithread,
+        );
+        std::mem::swap(&mut self.state, &mut self.arena);
+    }
+
+    /// Probability of each outcome for `indices` (no basis rotation).
+    fn probs(&self, indices: &[u64]) -> Vec<P> {
+        let n = self.n;
+        let indices: Vec<u64> = indices.iter().map(|indx| n - 1 - indx).collect();
+        let mut probs = vec![P::zero(); 1 << indices.len()];
+        (0..1u64 << n).for_each(|r| {
+            let m = extract_bits(r, &indices);
+            probs[m as usize] = probs[m as usize] + self.state[((r << n) | r) as usize].re;
+        });
+        probs
+    }
+
+    /// Sample an outcome from `probs`.
+    fn sample(probs: &[P]) -> u64 {
+        let total: P = probs.iter().cloned().sum();
+        let mut r = P::from(rng::random::<f64>()).unwrap() * total;
+        probs
+            .iter()
+            .position(|p| {
+                r = r - *p;
+                r <= P::zero()
+            })
+            .unwrap_or(probs.len() - 1) as u64
+    }
+}
+
+impl<P: Precision> Clone for DensityMatrixState<P> {
+    fn clone(&self) -> Self {
+        DensityMatrixState {
+            n: self.n,
+            state: self.state.clone(),
+            arena: self.arena.clone(),
+            multithread: self.multithread,
+            selective: self.selective,
+        }
+    }
+}
+
+impl<P: Precision> QuantumState<P> for DensityMatrixState<P> {
+    fn new(n: u64) -> Self {
+        DensityMatrixState::new_from_initial_states(n, &[])
+    }
+
+    fn new_from_initial_states(n: u64, states: &[(Vec<u64>, InitialState<P>)]) -> Self {
+        let psi = LocalQuantumState::<P>::new_from_initial_states(n, states);
+        let n = psi.n();
+        DensityMatrixState::new_from_pure_state(n, &psi.get_state(false), false)
+    }
+
+    fn n(&self) -> u64 {
+        self.n
+    }
+
+    fn apply_op_with_name(&mut self, _name: Option<&str>, op: &UnitaryOp) {
+        // U rho U^dagger = U (U rho)^dagger since rho is hermitian.
+        self.left_multiply(op);
+        self.dagger_into_arena();
+        std::mem::swap(&mut self.state, &mut self.arena);
+        self.left_multiply(op);
+    }
+
+    fn apply_channel(
+        &mut self,
+        _name: Option<&str>,
+        indices: &[u64],
+        kraus_ops: &[Vec<Complex<f64>>],
+    ) -> Result<(), CircuitError> {
+        let ops = kraus_ops
+            .iter()
+            .map(|k| make_matrix_op(indices.to_vec(), k.clone()))
+            .collect::<Result<Vec<_>, CircuitError>>()?;
+        let original = self.state.clone();
+        let mut total = vec![Complex::zero(); original.len()];
+        ops.iter().for_each(|op| {
+            // K rho K^dagger = K (K rho)^dagger since rho is hermitian.
+            self.state.copy_from_slice(&original);
+            self.left_multiply(op);
+            self.dagger_into_arena();
+            std::mem::swap(&mut self.state, &mut self.arena);
+            self.left_multiply(op);
+            total
+                .iter_mut()
+                .zip(self.state.iter())
+                .for_each(|(t, v)| *t = *t + v);
+        });
+        self.state = total;
+        Ok(())
+    }
+
+    /// Ops chosen from a sampled outcome are only consistent with the state if the measurement
+    /// projected onto that outcome.
+    fn check_feed_forward(&self) -> Result<(), CircuitError> {
+        if self.selective {
+            Ok(())
+        } else {
+            CircuitError::make_str_err(
+                "Side channels on a DensityMatrixState require selective measurements",
+            )
+        }
+    }
+
+    fn pauli_expectation(&self, pauli: &str) -> Result<P, CircuitError> {
+        // tr(rho P) = sum_x phase(x) <x|rho|x ^ flip_mask>
+        let masks = PauliMasks::new(self.n, pauli)?;
+        let n = self.n;
+        let state = &self.state;
+        let f = |x: u64| -> P {
+            let col = x ^ masks.flip_mask;
+            (state[((x << n) | col) as usize] * masks.phase::<P>(x)).re
+        };
+        let e = if self.multithread {
+            (0..1u64 << n).into_par_iter().map(f).sum()
+        } else {
+            (0..1u64 << n).map(f).sum()
+        };
+        Ok(e)
+    }
+
+    fn measure(
+        &mut self,
+        indices: &[u64],
+        measured: Option<MeasuredCondition<P>>,
+        angle: f64,
+    ) -> (u64, P) {
+        self.rotate_basis(indices, angle);
+        let probs = self.probs(indices);
+        let (m, p, selective) = match measured {
+            Some(measured) => {
+                let p = measured
+                    .prob
+                    .unwrap_or_else(|| probs[measured.measured as usize]);
+                (measured.measured, p, true)
+            }
+            None => {
+                let m = Self::sample(&probs);
+                (m, probs[m as usize], self.selective)
+            }
+        };
+
+        let n = self.n;
+        let mask = (1 << n) - 1;
+        let bit_indices: Vec<u64> = indices.iter().map(|indx| n - 1 - indx).collect();
+        let p_mult = if p.is_zero() { P::zero() } else { P::one() / p };
+        let f = |(i, entry): (usize, &mut Complex<P>)| {
+            let (row, col) = (i as u64 >> n, i as u64 & mask);
+            let row_m = extract_bits(row, &bit_indices);
+            let col_m = extract_bits(col, &bit_indices);
+            if selective {
+                if row_m == m && col_m == m {
+                    *entry = *entry * p_mult;
+                } else {
+                    *entry = Complex::zero();
+                }
+            } else if row_m != col_m {
+                *entry = Complex::zero();
+            }
+        };
+        if self.multithread {
+            self.state.par_iter_mut().enumerate().for_each(f);
+        } else {
+            self.state.iter_mut().enumerate().for_each(f);
+        }
+        self.rotate_basis(indices, -angle);
+        (m, p)
+    }
+
+    fn soft_measure(&mut self, indices: &[u64], measured: Option<u64>, angle: f64) -> (u64, P) {
+        self.rotate_basis(indices, angle);
+        let probs = self.probs(indices);
+        let m = measured.unwrap_or_else(|| Self::sample(&probs));
+        self.rotate_basis(indices, -angle);
+        (m, probs[m as usize])
+    }
+
+    fn state_magnitude(&self) -> P {
+        let n = self.n;
+        (0..1u64 << n)
+            .map(|r| self.state[((r << n) | r) as usize].re)
+            .sum()
+    }
+
+    fn stochastic_measure(&mut self, indices: &[u64], angle: f64) -> Vec<P> {
+        self.rotate_basis(indices, angle);
+        let probs = self.probs(indices);
+        self.rotate_basis(indices, -angle);
+        probs
+    }
+
+    /// Returns the flattened density matrix, entry `(row << n) | col` is `<row|rho|col>`.
+    fn get_state(self, natural_order: bool) -> Vec<Complex<P>> {
+        if natural_order {
+            let n = self.n;
+            let mask = (1 << n) - 1;
+            (0..self.state.len() as u64)
+                .map(|i| self.get_entry(i >> n, i & mask, true))
+                .collect()
+        } else {
+            self.state
+        }
+    }
+}
+
+#[cfg(test)]
+mod density_state_tests {
+    use super::*;
+    use crate::density_state::{run_density_local, run_density_local_with_init};
+    use crate::pipeline::{run_local, run_local_with_init, run_with_state, MeasurementHandle};
+    use crate::{OpBuilder, Register, UnitaryBuilder};
+
+    fn assert_almost_eq(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-10, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_pauli_expectation() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let q = b.qubit();
+        let r = b.qubit();
+        let q = b.hadamard(q);
+        let (q, r) = b.cnot(q, r);
+        let r = b.merge(vec![q, r])?;
+        let (rho, _) = run_density_local::<f64>(&r)?;
+        let (psi, _) = run_local::<f64>(&r)?;
+
+        ["XX", "YY", "ZZ", "XY", "ZI", "IX"].iter().try_for_each(
+            |pauli| -> Result<(), CircuitError> {
+                let expected = psi.pauli_expectation(pauli)?;
+                assert!((rho.pauli_expectation(pauli)? - expected).abs() < 1e-10);
+                Ok(())
+            },
+        )?;
+        assert!(rho.pauli_expectation("ZZZ").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_matches_pure_state() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let r = b.register(3)?;
+        let h = r.handle();
+        let r = b.hadamard(r);
+        let r = b.ry(r, 0.3);
+        let (r1, r2) = b.split(r, &[0])?;
+        let r2 = r2.unwrap();
+        let (r1, r2) = b.cnot(r1, r2);
+        let r = b.merge(vec![r1, r2])?;
+
+        let init = [h.make_init_from_index(0b101)?];
+        let (pure, _) = run_local_with_init::<f64>(&r, &init)?;
+        let (rho, _) = run_density_local_with_init::<f64>(&r, &init)?;
+        let expected = DensityMatrixState::new_from_pure_state(3, &pure.get_state(true), true);
+
+        assert_almost_eq(rho.purity(), 1.0);
+        (0..8).for_each(|row| {
+            (0..8).for_each(|col| {
+                let a = rho.get_entry(row, col, true);
+                let b = expected.get_entry(row, col, true);
+                assert_almost_eq(a.re, b.re);
+                assert_almost_eq(a.im, b.im);
+            })
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn test_measure_decoheres() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let q = b.qubit();
+        let q = b.hadamard(q);
+        let (q, m) = b.measure(q);
+        let (rho, measured) = run_density_local::<f64>(&q)?;
+
+        let (_, p) = measured.get_measurement(&m).unwrap();
+        assert_almost_eq(p, 0.5);
+        assert_almost_eq(rho.state_magnitude(), 1.0);
+        assert_almost_eq(rho.purity(), 0.5);
+        assert_almost_eq(rho.get_entry(0, 0, true).re, 0.5);
+        assert_almost_eq(rho.get_entry(1, 1, true).re, 0.5);
+        assert_almost_eq(rho.get_entry(0, 1, true).norm(), 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_selective_measure() -> Result<(), CircuitError> {
+        let inv_sqrt = 1.0 / 2.0f64.sqrt();
+        let h = make_matrix_op(
+            vec![0],
+            from_reals(&[inv_sqrt, inv_sqrt, inv_sqrt, -inv_sqrt]),
+        )?;
+        let mut rho = DensityMatrixState::<f64>::new(1);
+        rho.apply_op(&h);
+        let (m, p) = rho.measure(
+            &[0],
+            Some(MeasuredCondition {
+                measured: 1,
+                prob: None,
+            }),
+            0.0,
+        );
+
+        assert_eq!(m, 1);
+        assert_almost_eq(p, 0.5);
+        assert_almost_eq(rho.purity(), 1.0);
+        assert_almost_eq(rho.get_entry(1, 1, true).re, 1.0);
+        Ok(())
+    }
+
+    /// Measure `q` in superposition and flip `r` if it was `|1>`, giving the register `[q, r]`
+    /// and the measurement handle.
+    fn feed_forward_circuit(
+        b: &mut OpBuilder,
+    ) -> Result<(Register, MeasurementHandle), CircuitError> {
+        let q = b.qubit();
+        let r = b.qubit();
+        let q = b.hadamard(q);
+        let (q, m) = b.measure(q);
+        let r = b.c_if(r, &m, 1, Box::new(|b, r| Ok(b.not(r))))?;
+        Ok((b.merge(vec![q, r])?, m))
+    }
+
+    #[test]
+    fn test_feed_forward_is_selective() -> Result<(), CircuitError> {
+        for _ in 0..10 {
+            let mut b = OpBuilder::new();
+            let (r, m) = feed_forward_circuit(&mut b)?;
+            let (rho, measured) = run_density_local::<f64>(&r)?;
+            let (m, _) = measured.get_measurement(&m).unwrap();
+            // Both qubits match the recorded outcome.
+            let index = m | (m << 1);
+            assert_almost_eq(rho.purity(), 1.0);
+            assert_almost_eq(rho.get_entry(index, index, true).re, 1.0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_feed_forward_needs_selective() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let (r, _) = feed_forward_circuit(&mut b)?;
+        let rho = DensityMatrixState::<f64>::new(2);
+        assert!(run_with_state(&r, rho).is_err());
+
+        let mut rho = DensityMatrixState::<f64>::new(2);
+        rho.set_selective_measurement(true);
+        assert!(run_with_state(&r, rho).is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+use crate::errors::CircuitError;
+use crate::pipeline::{run_with_state, LocalQuantumState};
+use crate::rng;
+use crate::stats::inner_product;
+use crate::unitary_state::{run_unitary_local, unitary_circuit_size};
+use crate::{Complex, Register};
+use num::Zero;
+
+/// Circuits on at most this many qubits are compared by their full unitaries, larger ones by
+/// running random states through both.
+pub const MAX_UNITARY_QUBITS: u64 = 6;
+
+/// Number of random states used to compare circuits too large for their unitaries.
+const NUM_PROBES: usize = 8;
+
+/// Check whether the circuits ending at `a` and `b` apply the same unitary up to a global phase,
+/// with each amplitude within `tolerance`. Both circuits must act on the same number of qubits and
+/// contain no measurements or channels.
+///
+/// Circuits with at most `MAX_UNITARY_QUBITS` qubits are compared exactly using their unitaries.
+/// Larger circuits are compared by running random states through both, so may rarely report
+/// different circuits as equivalent. The random states are drawn using `rng`, so may be made
+/// reproducible with `rng::with_seed`.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::equivalence::circuits_equivalent;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// // Z is the same as HXH.
+/// let mut b = OpBuilder::new();
+/// let q = b.qubit();
+/// let a = b.z(q);
+///
+/// let mut b = OpBuilder::new();
+/// let q = b.qubit();
+/// let q = b.hadamard(q);
+/// let q = b.not(q);
+/// let c = b.hadamard(q);
+///
+/// assert!(circuits_equivalent(&a, &c, 1e-10)?);
+/// # Ok(())
+/// # }
+/// ```
+pub fn circuits_equivalent(
+    a: &Register,
+    b: &Register,
+    tolerance: f64,
+) -> Result<bool, CircuitError> {
+    let n = unitary_circuit_size(a)?;
+    let m = unitary_circuit_size(b)?;
+    if n != m {
+        let message = format!(
+            "Cannot compare a circuit on {:?} qubits with one on {:?} qubits",
+            n, m
+        );
+        return CircuitError::make_err(message);
+    }
+    if n <= MAX_UNITARY_QUBITS {
+        let (ua, _) = run_unitary_local::<f64>(a)?;
+        let (ub, _) = run_unitary_local::<f64>(b)?;
+        Ok(equal_up_to_phase(ua.state_ref(), ub.state_ref(), tolerance))
+    } else {
+        probe_equivalent(n, a, b, tolerance)
+    }
+}
+
+/// Panic with a description of the problem unless `circuits_equivalent(a, b, tolerance)`.
+pub fn assert_circuits_equivalent(a: &Register, b: &Register, tolerance: f64) {
+    match circuits_equivalent(a, b, tolerance) {
+        Ok(true) => {}
+        Ok(false) => panic!(
+            "Circuits are not equivalent up to global phase (tolerance {:?})",
+            tolerance
+        ),
+        Err(err) => panic!("Circuits could not be compared: {:?}", err),
+    }
+}
+
+/// Check that `b` is `a` times some global phase.
+fn equal_up_to_phase(a: &[Complex<f64>], b: &[Complex<f64>], tolerance: f64) -> bool {
+    // Take the phase from the largest entry, where it is least affected by rounding.
+    let largest = (0..a.len()).fold(0, |acc, i| {
+        if a[i].norm_sqr() > a[acc].norm_sqr() {
+            i
+        } else {
+            acc
+        }
+    });
+    let phase = if a[largest].norm() > tolerance && b[largest].norm() > tolerance {
+        let ratio = b[largest] / a[largest];
+        ratio / ratio.norm()
+    } else {
+        Complex::new(1.0, 0.0)
+    };
+    equal_up_to_phase_with(a, b, phase, tolerance)
+}
+
+/// Compare the outputs of both circuits on random states. The global phase is taken from the first
+/// state and must be shared by the rest.
+fn probe_equivalent(
+    n: u64,
+    a: &Register,
+    b: &Register,
+    tolerance: f64,
+) -> Result<bool, CircuitError> {
+    let mut phase: Option<Complex<f64>> = None;
+    for _ in 0..NUM_PROBES {
+        let state = random_state(n);
+        let make_state = || LocalQuantumState::new_from_full_state(n, state.clone(), false, true);
+        let (sa, _) = run_with_state(a, make_state()?)?;
+        let (sb, _) = run_with_state(b, make_state()?)?;
+        let overlap = inner_product(&sa, &sb)?;
+        if overlap.norm() < tolerance {
+            return Ok(false);
+        }
+        let p = *phase.get_or_insert(overlap / overlap.norm());
+        if !equal_up_to_phase_with(sa.state_ref(), sb.state_ref(), p, tolerance) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Check that `b` is `a` times `phase`.
+fn equal_up_to_phase_with(
+    a: &[Complex<f64>],
+    b: &[Complex<f64>],
+    phase: Complex<f64>,
+    tolerance: f64,
+) -> bool {
+    a.iter()
+        .zip(b.iter())
+        .all(|(x, y)| (x * phase - y).norm() <= tolerance)
+}
+
+/// Make a random normalized state on `n` qubits.
+fn random_state(n: u64) -> Vec<Complex<f64>> {
+    let state: Vec<Complex<f64>> = (0..1 << n)
+        .map(|_| Complex::new(rng::random::<f64>() - 0.5, rng::random::<f64>() - 0.5))
+        .collect();
+    let norm = state.iter().fold(0.0, |acc, c| acc + c.norm_sqr()).sqrt();
+    if norm.is_zero() {
+        random_state(n)
+    } else {
+        state.into_iter().map(|c| c / norm).collect()
+    }
+}
@@ -0,0 +1,153 @@
+use crate::pipeline::{StateModifier, StateModifierType};
+use crate::state_ops::{get_index, make_local_op_matrix, num_indices, UnitaryOp};
+use crate::Complex;
+use num::{One, Zero};
+
+/// The largest number of qubits a fused op may act on.
+const MAX_FUSED_QUBITS: usize = 2;
+
+/// A modifier left after fusion, either one from the original circuit or a matrix op made from a
+/// run of several.
+#[derive(Debug)]
+pub enum FusedModifier<'a> {
+    /// A modifier which is unchanged.
+    Original(&'a StateModifier),
+    /// A matrix op equal to a run of unitary ops.
+    Fused(StateModifier),
+}
+
+impl<'a> FusedModifier<'a> {
+    /// Get the modifier to run.
+    pub fn modifier(&self) -> &StateModifier {
+        match self {
+            FusedModifier::Original(m) => m,
+            FusedModifier::Fused(m) => m,
+        }
+    }
+}
+
+/// A run of unitary ops which together act on at most `MAX_FUSED_QUBITS` qubits.
+struct Block<'a> {
+    indices: Vec<u64>,
+    ops: Vec<&'a StateModifier>,
+}
+
+/// Fuse runs of unitary ops which together act on at most two qubits into single matrix ops, so
+/// fewer passes are made over the state when the circuit is run. Ops on other qubits may be
+/// interleaved with a run, while measurements, channels, and larger ops end the runs on the qubits
+/// they touch. Side channels, subcircuits, named gates, loops, debug and parameterized ops end
+/// every run.
+///
+/// A run containing a single op keeps the original modifier, fused ops are named after the ops
+/// they contain.
+pub fn fuse_modifiers<'a>(ops: &[&'a StateModifier]) -> Vec<FusedModifier<'a>> {
+    let mut blocks: Vec<Block<'a>> = vec![];
+    let mut fused = vec![];
+    ops.iter().for_each(|modifier| match &modifier.modifier {
+        StateModifierType::UnitaryOp(op) => {
+            let indices = op_indices(op);
+            let (touching, rest): (Vec<_>, Vec<_>) = blocks
+                .drain(..)
+                .partition(|block| block.indices.iter().any(|i| indices.contains(i)));
+            blocks = rest;
+            let mut merged_indices: Vec<u64> =
+                touching.iter().flat_map(|b| b.indices.clone()).collect();
+            indices.iter().for_each(|indx| {
+                if !merged_indices.contains(indx) {
+                    merged_indices.push(*indx)
+                }
+            });
+            if merged_indices.len() <= MAX_FUSED_QUBITS {
+                let mut ops: Vec<_> = touching.into_iter().flat_map(|b| b.ops).collect();
+                ops.push(modifier);
+                blocks.push(Block {
+                    indices: merged_indices,
+                    ops,
+                });
+            } else {
+                touching
+                    .into_iter()
+                    .for_each(|block| fused.push(fuse_block(block)));
+                if indices.len() <= MAX_FUSED_QUBITS {
+                    blocks.push(Block {
+                        indices,
+                        ops: vec![modifier],
+                    });
+                } else {
+                    fused.push(FusedModifier::Original(modifier));
+                }
+            }
+        }
+        StateModifierType::MeasureState(_, indices, _)
+        | StateModifierType::StochasticMeasureState(_, indices, _)
+        | StateModifierType::Channel(indices, _) => {
+            let (touching, rest): (Vec<_>, Vec<_>) = blocks
+                .drain(..)
+                .partition(|block| block.indices.iter().any(|i| indices.contains(i)));
+            blocks = rest;
+            touching
+                .into_iter()
+                .for_each(|block| fused.push(fuse_block(block)));
+            fused.push(FusedModifier::Original(modifier));
+        }
+        _ => {
+            blocks
+                .drain(..)
+                .for_each(|block| fused.push(fuse_block(block)));
+            fused.push(FusedModifier::Original(modifier));
+        }
+    });
+    blocks
+        .into_iter()
+        .for_each(|block| fused.push(fuse_block(block)));
+    fused
+}
+
+fn op_indices(op: &UnitaryOp) -> Vec<u64> {
+    let mut indices: Vec<u64> = vec![];
+    (0..num_indices(op))
+        .map(|i| get_index(op, i))
+        .for_each(|indx| {
+            if !indices.contains(&indx) {
+                indices.push(indx)
+            }
+        });
+    indices
+}
+
+/// Multiply together the ops of `block` into a single matrix op.
+fn fuse_block(block: Block) -> FusedModifier {
+    if block.ops.len() == 1 {
+        return FusedModifier::Original(block.ops[0]);
+    }
+    let size = 1 << block.indices.len();
+    let mut mat: Vec<Complex<f64>> = (0..size * size)
+        .map(|i| {
+            if i / size == i % size {
+                Complex::one()
+            } else {
+                Complex::zero()
+            }
+        })
+        .collect();
+    let mut names = vec![];
+    block.ops.iter().for_each(|modifier| {
+        if let StateModifierType::UnitaryOp(op) = &modifier.modifier {
+            let op_mat = make_local_op_matrix(op, &block.indices);
+            mat = (0..size * size)
+                .map(|i| {
+                    let (row, col) = (i / size, i % size);
+                    (0..size).fold(Complex::zero(), |acc, k| {
+                        acc + op_mat[row * size + k] * mat[k * size + col]
+                    })
+                })
+                .collect();
+            names.push(modifier.name.clone());
+        }
+    });
+    let name = format!("Fused({})", names.join(", "));
+    FusedModifier::Fused(StateModifier::new_unitary(
+        name,
+        UnitaryOp::Matrix(block.indices, mat),
+    ))
+}
@@ -0,0 +1,266 @@
+use crate::errors::CircuitError;
+use crate::utils::flip_bits;
+use crate::{phase_estimation, qft, run_local, try_condition};
+use crate::{OpBuilder, Register, UnitaryBuilder};
+use num::{Complex, One, Zero};
+use std::f64::consts::PI;
+
+/// The outcome of `solve_linear_system`.
+#[derive(Debug, Clone)]
+pub struct HhlResult {
+    /// The estimate of `x` with `A x = b`, recovered from the amplitudes of the system register
+    /// after postselecting on the ancilla.
+    pub solution: Vec<Complex<f64>>,
+    /// Probability of measuring the ancilla as `|1>`, the chance that a run of the circuit on a
+    /// device would succeed.
+    pub success_probability: f64,
+}
+
+/// Apply the HHL algorithm for the hermitian `matrix` `A`, given row major with bit `j` of each
+/// index as qubit `j` of `system`. The `system` should hold `|b>`, for instance from
+/// `UnitaryBuilder::prepare_state`, and `clock` and the single qubit `ancilla` should start as
+/// `|0...0>`.
+///
+/// Phase estimation of `exp(i A time)` writes each eigenvalue `l` of `A` into `clock` as
+/// `k = l time 2^t / 2 pi` for `t` clock qubits, with values of `k` at least `2^(t-1)` standing for
+/// negative eigenvalues. The ancilla is then rotated to `sqrt(1 - c^2/l^2)|0> + c/l |1>` and phase
+/// estimation is undone, so that when the eigenvalues are exact multiples of `2 pi / 2^t time`
+/// the clock returns to `|0...0>` and the system is left in `c A^-1 |b>` alongside `|1>` on the
+/// ancilla. `time` should be small enough that `|l| time < pi` for every eigenvalue, and `c` no
+/// larger than the smallest `|l|`.
+///
+/// The evolution is found classically by exponentiating `matrix`, so this is only suitable for
+/// small well-conditioned systems.
+pub fn hhl(
+    b: &mut dyn UnitaryBuilder,
+    clock: Register,
+    system: Register,
+    ancilla: Register,
+    matrix: &[Complex<f64>],
+    time: f64,
+    c: f64,
+) -> Result<(Register, Register, Register), CircuitError> {
+    let n = system.n();
+    let d = 1 << n;
+    if matrix.len() != d * d {
+        let message = format!(
+            "Expected a {:?} by {:?} matrix for {:?} qubits, found {:?} entries",
+            d,
+            d,
+            n,
+            matrix.len()
+        );
+        return CircuitError::make_err(message);
+    }
+    let hermitian = (0..d)
+        .all(|i| (0..d).all(|j| (matrix[i * d + j] - matrix[j * d + i].conj()).norm() < 1e-10));
+    if !hermitian {
+        return CircuitError::make_str_err("HHL requires a hermitian matrix.");
+    }
+    if ancilla.n() != 1 {
+        let message = format!("Expected a single ancilla qubit, found {:?}", ancilla.n());
+        return CircuitError::make_err(message);
+    }
+
+    // exp(+-i A time 2^j) for each clock qubit j.
+    let t = clock.n();
+    let evolutions = |sign: f64| {
+        let scaled = matrix
+            .iter()
+            .map(|a| a * Complex::new(0.0, sign * time))
+            .collect::<Vec<_>>();
+        let mut u = matrix_exp(&scaled, d);
+        (0..t)
+            .map(|_| {
+                let m = to_mat_order(&u, n);
+                u = mat_mul(&u, &u, d);
+                m
+            })
+            .collect::<Vec<_>>()
+    };
+    let forward = evolutions(1.0);
+    let backward = evolutions(-1.0);
+
+    b.push_name_scope("HHL");
+    let result = phase_estimation(b, clock, system, |b, r, power| {
+        b.mat("U", r, forward[power.trailing_zeros() as usize].clone())
+    })
+    .and_then(|(clock, system)| {
+        let rotation = eigenvalue_rotation(t, time, c);
+        let r = b.merge(vec![clock, ancilla])?;
+        let r = b.mat("Rotation", r, to_mat_order(&rotation, t + 1))?;
+        let (clock, ancilla) = b.split(r, &(0..t).collect::<Vec<_>>())?;
+        let ancilla = ancilla.unwrap();
+
+        // Undo the phase estimation.
+        let clock = qft(b, clock)?;
+        let qs = b.split_all(clock);
+        let (qs, system) = qs.into_iter().enumerate().rev().try_fold(
+            (vec![], system),
+            |(mut acc, system), (j, q)| {
+                let (q, system) = try_condition(b, q, system, |b, system| {
+                    b.mat("Udag", system, backward[j].clone())
+                })?;
+                acc.push(q);
+                Ok((acc, system))
+            },
+        )?;
+        let clock = b.merge(qs.into_iter().rev().collect())?;
+        let clock = b.hadamard(clock);
+        Ok((clock, system, ancilla))
+    });
+    b.pop_name_scope();
+    result
+}
+
+/// Solve `A x = b` for the hermitian `matrix` `A` and `vector` `b` with the HHL algorithm, using
+/// `precision` clock qubits and evolution `time` (see `hhl`). The rotation constant is the
+/// smallest eigenvalue the clock can represent, `2 pi / 2^precision time`. The circuit is
+/// simulated and the state of the system register postselected on the ancilla is rescaled to
+/// estimate `x`, which is exact when the eigenvalues of `A` are multiples of that constant.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::hhl::solve_linear_system;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// // Eigenvalues 1 and 2, which 3 clock qubits represent exactly with time 2 pi / 8.
+/// let matrix = [1.5, 0.5, 0.5, 1.5].iter().map(|x| Complex::new(*x, 0.0)).collect::<Vec<_>>();
+/// let vector = [Complex::new(1.0, 0.0), Complex::new(0.0, 0.0)];
+/// let result = solve_linear_system(&matrix, &vector, 3, std::f64::consts::PI / 4.0)?;
+/// assert!((result.solution[0] - Complex::new(0.75, 0.0)).norm() < 1e-6);
+/// assert!((result.solution[1] - Complex::new(-0.25, 0.0)).norm() < 1e-6);
+/// # Ok(())
+/// # }
+/// ```
+pub fn solve_linear_system(
+    matrix: &[Complex<f64>],
+    vector: &[Complex<f64>],
+    precision: u64,
+    time: f64,
+) -> Result<HhlResult, CircuitError> {
+    let d = vector.len();
+    if d < 2 || !d.is_power_of_two() {
+        let message = format!(
+            "Vector length must be a power of two of at least 2, found {:?}",
+            d
+        );
+        return CircuitError::make_err(message);
+    }
+    if precision == 0 || time <= 0.0 {
+        let message = format!(
+            "Expected at least one clock qubit and positive time, found {:?} and {:?}",
+            precision, time
+        );
+        return CircuitError::make_err(message);
+    }
+    let norm = vector.iter().map(|a| a.norm_sqr()).sum::<f64>().sqrt();
+    if norm == 0.0 {
+        return CircuitError::make_str_err("Cannot solve for a vector of all zeros.");
+    }
+    let normalized = vector.iter().map(|a| a / norm).collect::<Vec<_>>();
+    let n = u64::from(d.trailing_zeros());
+    let c = 2.0 * PI / ((1 << precision) as f64 * time);
+
+    let mut b = OpBuilder::new();
+    let clock = b.register(precision)?;
+    let system = b.register(n)?;
+    let ancilla = b.qubit();
+    let system = b.prepare_state(system, &normalized)?;
+    let (clock, system, ancilla) = hhl(&mut b, clock, system, ancilla, matrix, time, c)?;
+    let r = b.merge(vec![system, clock, ancilla])?;
+    let (state, _) = run_local::<f64>(&r)?;
+    let state = state.get_state_for_registers(&[&r])?;
+
+    let success = 1 << (n + precision);
+    let success_probability = state[success..].iter().map(|a| a.norm_sqr()).sum();
+    // The postselected system is c A^-1 |b> / |b| when the clock is uncomputed.
+    let solution = state[success..success + d]
+        .iter()
+        .map(|a| a * norm / c)
+        .collect();
+    Ok(HhlResult {
+        solution,
+        success_probability,
+    })
+}
+
+/// Get the rotation of the ancilla for each value of the clock, block diagonal with the ancilla
+/// as the most significant bit of each index.
+fn eigenvalue_rotation(t: u64, time: f64, c: f64) -> Vec<Complex<f64>> {
+    let clock_d = 1 << t;
+    let d = 2 * clock_d;
+    let mut m = vec![Complex::zero(); d * d];
+    (0..clock_d).for_each(|k| {
+        let signed = if k >= clock_d / 2 {
+            k as f64 - clock_d as f64
+        } else {
+            k as f64
+        };
+        let eigenvalue = 2.0 * PI * signed / (clock_d as f64 * time);
+        let s = if k == 0 {
+            0.0
+        } else {
+            (c / eigenvalue).clamp(-1.0, 1.0)
+        };
+        let cs = (1.0 - s * s).sqrt();
+        let (zero, one) = (k, k + clock_d);
+        m[zero * d + zero] = Complex::new(cs, 0.0);
+        m[zero * d + one] = Complex::new(-s, 0.0);
+        m[one * d + zero] = Complex::new(s, 0.0);
+        m[one * d + one] = Complex::new(cs, 0.0);
+    });
+    m
+}
+
+/// Reorder a matrix given with bit `j` of each index as qubit `j` to the order used by `mat`,
+/// where the first qubit is the most significant bit.
+fn to_mat_order(m: &[Complex<f64>], n: u64) -> Vec<Complex<f64>> {
+    let d = 1 << n;
+    let reverse = |i: usize| flip_bits(n as usize, i as u64) as usize;
+    (0..d * d)
+        .map(|index| m[reverse(index / d) * d + reverse(index % d)])
+        .collect()
+}
+
+fn mat_mul(a: &[Complex<f64>], b: &[Complex<f64>], d: usize) -> Vec<Complex<f64>> {
+    (0..d * d)
+        .map(|index| {
+            let (i, j) = (index / d, index % d);
+            (0..d).map(|k| a[i * d + k] * b[k * d + j]).sum()
+        })
+        .collect()
+}
+
+/// Exponentiate a `d` by `d` matrix by scaling and squaring its taylor series.
+fn matrix_exp(a: &[Complex<f64>], d: usize) -> Vec<Complex<f64>> {
+    let norm = (0..d)
+        .map(|j| (0..d).map(|i| a[i * d + j].norm()).sum::<f64>())
+        .fold(0.0, f64::max);
+    let squarings = if norm > 0.5 {
+        (norm / 0.5).log2().ceil() as u32
+    } else {
+        0
+    };
+    let scale = f64::from(2u32.pow(squarings));
+    let a = a.iter().map(|x| x / scale).collect::<Vec<_>>();
+    let identity = (0..d * d)
+        .map(|i| {
+            if i / d == i % d {
+                Complex::one()
+            } else {
+                Complex::zero()
+            }
+        })
+        .collect::<Vec<_>>();
+    let (result, _) = (1..20).fold((identity.clone(), identity), |(sum, term), k| {
+        let term = mat_mul(&term, &a, d)
+            .into_iter()
+            .map(|x| x / f64::from(k))
+            .collect::<Vec<_>>();
+        let sum = sum.iter().zip(term.iter()).map(|(s, t)| s + t).collect();
+        (sum, term)
+    });
+    (0..squarings).fold(result, |u, _| mat_mul(&u, &u, d))
+}
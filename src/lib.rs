@@ -172,44 +172,121 @@ pub use self::builders::*;
 pub use self::common_circuits::*;
 pub use self::errors::*;
 pub use self::macros::*;
-pub use self::pipeline::{run_local, run_local_with_init, run_with_state, QuantumState};
-pub use self::pipeline_debug::run_debug;
+pub use self::pipeline::{
+    run_and_sample, run_local, run_local_shots, run_local_with_init, run_local_with_parameters,
+    run_with_state, QuantumState,
+};
+pub use self::pipeline_debug::{draw, run_debug, to_dot};
 pub use self::qubits::Register;
-pub use self::types::Precision;
+pub use self::types::{Endianness, Precision};
 pub use num::Complex;
 
+/// Estimation of the probability that a prepared state is good.
+pub mod amplitude_estimation;
+/// Benchmarking simulated devices with random circuits, by quantum volume and cross entropy.
+pub mod benchmarks;
 /// Quantum analogues of boolean circuits
 pub mod boolean_circuits;
 /// Opbuilder and such
 pub mod builders;
+/// Statistics about circuits such as gate counts and depth.
+pub mod circuit_stats;
+/// Classical registers computed from measurement results.
+pub mod classical;
+/// Sampling random Clifford circuits.
+pub mod clifford;
+/// Approximating single qubit ops with sequences of Clifford+T gates.
+pub mod clifford_t;
 /// Common circuits for general usage.
 pub mod common_circuits;
+/// Density matrix quantum states
+pub mod density_state;
+/// Checking that circuits apply the same unitary up to global phase.
+pub mod equivalence;
 /// Error values for the library.
 pub mod errors;
+/// Fusing runs of small gates into single ops before simulation.
+pub mod fusion;
 /// Macros for general ease of use.
 #[macro_use]
 pub mod macros;
+/// Solving small linear systems with the HHL algorithm.
+pub mod hhl;
 /// Efficient iterators for sparse kronprod matrices.
 pub mod iterators;
+/// Bounded classical loops whose body is built once and unrolled when run or exported.
+pub mod loops;
 /// Functions for measuring states.
 pub mod measurement_ops;
+/// Matrix product quantum states
+pub mod mps_state;
+/// Reusable named gates whose definitions are shared between each place they are applied.
+pub mod named_gates;
+/// Noise models and common channels.
+pub mod noise;
+/// Optimization passes which simplify circuits.
+pub mod optimize;
+mod parallel;
+/// Symbolic parameters for ops which are given values when run, and gradients with respect to them.
+pub mod parameters;
 /// Code for building pipelines.
 pub mod pipeline;
 /// Tools for displaying pipelines.
 pub mod pipeline_debug;
+/// Parsing of OpenQASM 2.0 programs.
+pub mod qasm;
+/// Quantum error correcting codes.
+pub mod qec;
 /// Quantum fourier transform support.
 pub mod qfft;
+/// Bucket brigade QRAM for reading classical data at addresses in superposition.
+pub mod qram;
+/// Discrete time coined quantum walks on lines and cycles.
+pub mod quantum_walk;
 /// Basic classes for defining circuits/pipelines.
 pub mod qubits;
+/// Export of circuits as Quil programs.
+pub mod quil;
+/// Repeating probabilistic circuits until a flag measurement reports success.
+pub mod repeat_until_success;
+/// Seedable randomness for reproducible measurements.
+pub mod rng;
+/// Routing circuits onto the connected qubits of a device.
+pub mod routing;
+/// Building circuits on named registers without passing them in and out of each op.
+pub mod scope;
+/// Estimating many observables from few measurements with classical shadows.
+pub mod shadows;
+/// Order finding and factoring with Shor's algorithm.
+pub mod shor;
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod simd;
+/// Finding hidden periods with Simon's algorithm.
+pub mod simon;
 /// Sparse quantum states
 pub mod sparse_state;
+/// Stabilizer (clifford) quantum states
+pub mod stabilizer_state;
 /// Functions for running ops on states.
 pub mod state_ops;
+/// Statistics of quantum states such as reduced density matrices, entanglement and fidelity.
+pub mod stats;
+/// Reusable circuit components which can be inverted, controlled, and applied to any matching
+/// registers.
+pub mod subcircuit;
+/// Reconstructing states and processes from measurements in many bases.
+pub mod tomography;
 /// Tracing state
 pub mod trace_state;
+/// Rewriting circuits into a restricted set of basis gates.
+pub mod transpile;
 /// Commonly used types.
 pub mod types;
 /// Break unitary matrices into circuits.
 pub mod unitary_decomposition;
+/// Full unitary matrices of circuits.
+pub mod unitary_state;
 /// Commonly used short functions.
 pub mod utils;
+/// Variational quantum eigensolver for finding low energy states of pauli sum hamiltonians.
+pub mod vqe;
@@ -0,0 +1,168 @@
+use crate::errors::CircuitError;
+use crate::macros::inverter::remap_indices;
+use crate::parameters::Parameter;
+use crate::pipeline::{StateModifier, StateModifierType};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// A loop registered by `OpBuilder::for_loop`. The body is built once on its own qubits `0..n`,
+/// with ops which depend on the loop variable kept as parameterized ops, and is only unrolled into
+/// one copy for each value of the variable when the circuit is run or exported.
+#[derive(Debug)]
+pub struct ClassicalLoop {
+    variable: Parameter,
+    range: Range<i64>,
+    n: u64,
+    body: Vec<StateModifier>,
+}
+
+impl ClassicalLoop {
+    /// Make a loop of `body` acting on the qubits `0..n` over the values of `variable` in `range`.
+    /// Only unitary, parameterized, and nested subcircuit, gate and loop ops are allowed.
+    pub(crate) fn new(
+        variable: Parameter,
+        range: Range<i64>,
+        n: u64,
+        body: Vec<StateModifier>,
+    ) -> Result<ClassicalLoop, CircuitError> {
+        body.iter().try_for_each(|modifier| {
+            let indices = match &modifier.modifier {
+                StateModifierType::UnitaryOp(_)
+                | StateModifierType::ParameterizedOp(_, _)
+                | StateModifierType::Subcircuit(_)
+                | StateModifierType::Gate(_, _)
+                | StateModifierType::Loop(_, _) => modifier.indices().unwrap_or_default(),
+                _ => {
+                    let message = format!(
+                        "Loop over {:?} cannot contain non-unitary op {:?}",
+                        variable.name(),
+                        modifier.name
+                    );
+                    return CircuitError::make_err(message);
+                }
+            };
+            match indices.iter().find(|indx| **indx >= n) {
+                Some(indx) => {
+                    let message = format!("Loop on {:?} qubits uses qubit {:?}", n, indx);
+                    CircuitError::make_err(message)
+                }
+                None => Ok(()),
+            }
+        })?;
+        Ok(ClassicalLoop {
+            variable,
+            range,
+            n,
+            body,
+        })
+    }
+
+    /// The parameter which takes each value of the range in turn.
+    pub fn variable(&self) -> &Parameter {
+        &self.variable
+    }
+
+    /// The values taken by the loop variable.
+    pub fn range(&self) -> Range<i64> {
+        self.range.clone()
+    }
+
+    /// The number of qubits the body acts on.
+    pub fn n(&self) -> u64 {
+        self.n
+    }
+
+    /// The ops making up one iteration, on the qubits `0..n`.
+    pub fn body(&self) -> &[StateModifier] {
+        &self.body
+    }
+
+    /// Get the ops of every iteration in order, applied to `indices` where qubit `i` of the body
+    /// is `indices[i]`. Parameterized ops are replaced by the op for their value, taken from the
+    /// loop variable or otherwise from `params`, and nested loops are unrolled as well. Returns an
+    /// error if an op uses a parameter which isn't bound.
+    pub(crate) fn unroll(
+        &self,
+        indices: &[u64],
+        params: Option<&HashMap<String, f64>>,
+    ) -> Result<Vec<StateModifier>, CircuitError> {
+        let mut bindings = params.cloned().unwrap_or_default();
+        let mut ops = vec![];
+        self.range.clone().try_for_each(|value| {
+            bindings.insert(self.variable.name().to_string(), value as f64);
+            self.body
+                .iter()
+                .try_for_each(|modifier| bind_modifier(modifier, indices, &bindings, &mut ops))
+        })?;
+        Ok(ops)
+    }
+
+    /// The parameters used in the body other than the loop variable, in the order they are used.
+    pub(crate) fn free_parameters(&self) -> Vec<Parameter> {
+        let mut params = vec![];
+        self.body
+            .iter()
+            .for_each(|modifier| add_free_parameters(&mut params, modifier));
+        params.retain(|param| param != &self.variable);
+        params
+    }
+}
+
+fn add_free_parameters(params: &mut Vec<Parameter>, modifier: &StateModifier) {
+    let found = match &modifier.modifier {
+        StateModifierType::ParameterizedOp(param, _) => vec![param.clone()],
+        StateModifierType::Subcircuit(modifiers) => {
+            let mut found = vec![];
+            modifiers
+                .iter()
+                .for_each(|modifier| add_free_parameters(&mut found, modifier));
+            found
+        }
+        StateModifierType::Loop(body, _) => body.free_parameters(),
+        _ => vec![],
+    };
+    found.into_iter().for_each(|param| {
+        if !params.contains(&param) {
+            params.push(param)
+        }
+    });
+}
+
+/// Add the ops of `modifier` applied to `indices` to `ops`, using `bindings` for the values of
+/// parameters.
+fn bind_modifier(
+    modifier: &StateModifier,
+    indices: &[u64],
+    bindings: &HashMap<String, f64>,
+    ops: &mut Vec<StateModifier>,
+) -> Result<(), CircuitError> {
+    let remap =
+        |inner: &[u64]| -> Vec<u64> { inner.iter().map(|i| indices[*i as usize]).collect() };
+    let op = match &modifier.modifier {
+        StateModifierType::UnitaryOp(op) => {
+            StateModifierType::UnitaryOp(remap_indices(op.clone(), indices))
+        }
+        StateModifierType::ParameterizedOp(param, f) => {
+            let value = bindings.get(param.name()).ok_or_else(|| {
+                CircuitError::new(format!("Parameter {:?} not bound", param.name()))
+            })?;
+            StateModifierType::UnitaryOp(remap_indices(f(*value)?, indices))
+        }
+        StateModifierType::Gate(def, inner) => StateModifierType::Gate(def.clone(), remap(inner)),
+        StateModifierType::Subcircuit(modifiers) => {
+            return modifiers
+                .iter()
+                .try_for_each(|modifier| bind_modifier(modifier, indices, bindings, ops))
+        }
+        StateModifierType::Loop(body, inner) => {
+            ops.extend(body.unroll(&remap(inner), Some(bindings))?);
+            return Ok(());
+        }
+        _ => return Ok(()),
+    };
+    ops.push(StateModifier {
+        name: modifier.name.clone(),
+        modifier: op,
+    });
+    Ok(())
+}
@@ -6,6 +6,14 @@ wrap_fn!(pub y, UnitaryBuilder::y, r);
 
 wrap_fn!(pub z, UnitaryBuilder::z, r);
 
+wrap_fn!(pub s, UnitaryBuilder::s, r);
+
+wrap_fn!(pub sdagger, UnitaryBuilder::sdagger, r);
+
+wrap_fn!(pub t, UnitaryBuilder::t, r);
+
+wrap_fn!(pub tdagger, UnitaryBuilder::tdagger, r);
+
 wrap_fn!(pub not, UnitaryBuilder::not, r);
 
 wrap_fn!(pub swap, (UnitaryBuilder::swap), ra, rb);
@@ -1,4 +1,5 @@
-use crate::pipeline::{get_owned_opfns, StateModifierType};
+use crate::parameters::Parameter;
+use crate::pipeline::{get_owned_opfns, ParameterizedOpFn, StateModifierType};
 use crate::state_ops::{invert_op, UnitaryOp};
 use crate::{CircuitError, OpBuilder, Register, UnitaryBuilder};
 
@@ -90,11 +91,13 @@ macro_rules! invert_fn {
     };
 }
 
-/// Invert a circuit applied via the function f.
+/// Invert a circuit applied via the function f. Only unitary ops may be inverted, measurements,
+/// side channels, and channels produce an error.
 pub fn inverter<
+    B: UnitaryBuilder + ?Sized,
     F: Fn(&mut dyn UnitaryBuilder, Vec<Register>) -> Result<Vec<Register>, CircuitError>,
 >(
-    b: &mut dyn UnitaryBuilder,
+    b: &mut B,
     mut rs: Vec<Register>,
     f: F,
 ) -> Result<Vec<Register>, CircuitError> {
@@ -107,11 +110,14 @@ pub fn inverter<
             inv_builder.register(n).unwrap()
         })
         .collect();
-    let flat_indices: Vec<_> = original_indices.iter().flatten().cloned().collect();
+    let mut flat_indices: Vec<_> = original_indices.iter().flatten().cloned().collect();
 
     // Call the function and count any qubits allocated inside.
     let before_n = inv_builder.get_qubit_count();
-    let new_rs = f(&mut inv_builder, new_rs)?;
+    let mut new_rs = f(&mut inv_builder, new_rs)?;
+    // Returned temps must be included so that the ops which last touched them are found.
+    let (zero_temps, one_temps) = inv_builder.get_temp_indices();
+    new_rs.extend(inv_builder.take_temp_registers());
     let end_reg = inv_builder.merge(new_rs)?;
     let after_n = inv_builder.get_qubit_count();
 
@@ -123,6 +129,7 @@ pub fn inverter<
         let temp_reg = b.get_temp_register(temps, false);
         let temp_indices = temp_reg.indices.clone();
         rs.push(temp_reg);
+        flat_indices.extend(temp_indices.iter().cloned());
         temp_indices
     } else {
         vec![]
@@ -131,49 +138,52 @@ pub fn inverter<
     let reg = b.merge(rs)?;
     let reg: Register = get_owned_opfns(end_reg)
         .into_iter()
-        .fold(vec![], |mut acc, modifier| {
+        .try_fold(vec![], |mut acc, modifier| {
             let name = format!("Inverse({})", modifier.name);
-            let op = match modifier.modifier {
-                StateModifierType::UnitaryOp(op) => remap_indices(invert_op(op), &flat_indices),
-                StateModifierType::Debug(_, _) => unimplemented!(),
-                StateModifierType::SideChannelModifiers(_, _) => unimplemented!(),
-                StateModifierType::MeasureState(_, _, _) => unimplemented!(),
-                StateModifierType::StochasticMeasureState(_, _, _) => unimplemented!(),
+            match modifier.modifier {
+                StateModifierType::UnitaryOp(op) => acc.push((
+                    name,
+                    InverseOp::Unitary(remap_indices(invert_op(op), &flat_indices)),
+                )),
+                StateModifierType::ParameterizedOp(param, f) => {
+                    let flat_indices = flat_indices.clone();
+                    let f = Box::new(move |theta| {
+                        Ok(remap_indices(invert_op(f(theta)?), &flat_indices))
+                    });
+                    acc.push((name, InverseOp::Parameterized(param, f)))
+                }
+                StateModifierType::Debug(_, _) => {}
+                _ => {
+                    let message = format!("Cannot invert non-unitary op {:?}", modifier.name);
+                    return CircuitError::make_err(message);
+                }
             };
-            acc.push((name, op));
-            acc
-        })
+            Ok(acc)
+        })?
         .into_iter()
         .rev()
         .try_fold(reg, |reg, (name, op)| {
             let indices = match &op {
-                UnitaryOp::Matrix(indices, _) => indices.clone(),
-                UnitaryOp::SparseMatrix(indices, _) => indices.clone(),
-                UnitaryOp::Swap(a_indices, b_indices) => {
-                    let vecs = [a_indices.clone(), b_indices.clone()];
-                    vecs.iter().flatten().cloned().collect()
-                }
-                UnitaryOp::Control(c_indices, op_indices, _) => {
-                    let vecs = [c_indices.clone(), op_indices.clone()];
-                    vecs.iter().flatten().cloned().collect()
-                }
-                UnitaryOp::Function(x_indices, y_indices, _) => {
-                    let vecs = [x_indices.clone(), y_indices.clone()];
-                    vecs.iter().flatten().cloned().collect()
-                }
+                InverseOp::Unitary(op) => op_indices(op),
+                // The qubits an op acts on don't depend on the parameter value.
+                InverseOp::Parameterized(_, f) => op_indices(&f(0.0)?),
             };
 
             let (sel_reg, reg) = b.split_absolute(reg, &indices)?;
+            let sel_reg = match op {
+                InverseOp::Unitary(op) => b.merge_with_op(vec![sel_reg], Some((name, op)))?,
+                InverseOp::Parameterized(param, f) => {
+                    b.merge_with_parameterized_op(vec![sel_reg], name, param, f)?
+                }
+            };
             if let Some(reg) = reg {
-                let sel_reg = b.merge_with_op(vec![sel_reg], Some((name, op)))?;
                 b.merge(vec![sel_reg, reg])
             } else {
-                b.merge_with_op(vec![sel_reg], Some((name, op)))
+                Ok(sel_reg)
             }
         })?;
 
     // Any temps which were returned, add them to the returned bucket with the correct value.
-    let (zero_temps, one_temps) = inv_builder.get_temp_indices();
     let temp_vecs = vec![(zero_temps, false), (one_temps, true)];
     let reg = temp_vecs.into_iter().try_fold(reg, |reg, (temps, value)| {
         if temps.is_empty() {
@@ -193,7 +203,32 @@ pub fn inverter<
     Ok(rs)
 }
 
-fn remap_indices(op: UnitaryOp, new_indices: &[u64]) -> UnitaryOp {
+/// An inverted op waiting to be applied.
+enum InverseOp {
+    Unitary(UnitaryOp),
+    Parameterized(Parameter, Box<ParameterizedOpFn>),
+}
+
+fn op_indices(op: &UnitaryOp) -> Vec<u64> {
+    match op {
+        UnitaryOp::Matrix(indices, _) => indices.clone(),
+        UnitaryOp::SparseMatrix(indices, _) => indices.clone(),
+        UnitaryOp::Swap(a_indices, b_indices) => {
+            let vecs = [a_indices.clone(), b_indices.clone()];
+            vecs.iter().flatten().cloned().collect()
+        }
+        UnitaryOp::Control(c_indices, op_indices, _) => {
+            let vecs = [c_indices.clone(), op_indices.clone()];
+            vecs.iter().flatten().cloned().collect()
+        }
+        UnitaryOp::Function(x_indices, y_indices, _) => {
+            let vecs = [x_indices.clone(), y_indices.clone()];
+            vecs.iter().flatten().cloned().collect()
+        }
+    }
+}
+
+pub(crate) fn remap_indices(op: UnitaryOp, new_indices: &[u64]) -> UnitaryOp {
     let remap = |indices: Vec<u64>| -> Vec<u64> {
         indices
             .into_iter()
@@ -219,10 +254,10 @@ fn remap_indices(op: UnitaryOp, new_indices: &[u64]) -> UnitaryOp {
 #[cfg(test)]
 mod inverter_test {
     use super::*;
-    use crate::boolean_circuits::arithmetic::{add, add_op};
+    use crate::boolean_circuits::arithmetic::{add, add_op, ripple_carry_add_op};
     use crate::pipeline::{get_required_state_size_from_frontier, InitialState};
     use crate::utils::flip_bits;
-    use crate::{run_debug, run_local_with_init, Complex, QuantumState};
+    use crate::{run_debug, run_local, run_local_with_init, Complex, QuantumState};
     use num::One;
 
     fn test_inversion<
@@ -287,6 +322,30 @@ mod inverter_test {
         test_inversion(&mut b, vec![ra, rb], wrap_gamma)
     }
 
+    #[test]
+    fn test_invert_with_temps() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let ra = b.register(2)?;
+        let rb = b.register(3)?;
+        let rs = ripple_carry_add_op(&mut b, vec![ra, rb])?;
+        let rs = inverter(&mut b, rs, ripple_carry_add_op)?;
+        let r = b.merge(rs)?;
+
+        // Temps start as |0>, so only the non-temp inputs are varied.
+        let indices: Vec<_> = (0..5).collect();
+        (0..1 << 5).for_each(|indx| {
+            let (state, _) =
+                run_local_with_init::<f64>(&r, &[(indices.clone(), InitialState::Index(indx))])
+                    .unwrap();
+            let pos = state
+                .get_state(true)
+                .into_iter()
+                .position(|v| v == Complex::one());
+            assert_eq!(pos, Some(indx as usize));
+        });
+        Ok(())
+    }
+
     #[test]
     fn test_invert_add() -> Result<(), CircuitError> {
         let mut b = OpBuilder::new();
@@ -305,6 +364,43 @@ mod inverter_test {
         test_inversion(&mut b, vec![rc, ra, rb], add_op)
     }
 
+    #[test]
+    fn test_dagger_in_condition() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let q = b.qubit();
+        let r = b.qubit();
+        let q = b.not(q);
+        let r = b.hadamard(r);
+        let (q, r) = {
+            let mut c = b.with_condition(q);
+            let r = c.dagger(r, Box::new(|b, r| Ok(b.s(r))))?;
+            (c.release_register(), r)
+        };
+        let r = b.sdagger(r);
+        let r = b.z(r);
+        let r = b.hadamard(r);
+        let r = b.merge(vec![q, r])?;
+
+        // S^dagger S^dagger Z = I, so r returns to |0>.
+        let (state, _) = run_local::<f64>(&r)?;
+        let state = state.get_state(true);
+        assert!((state[1].norm() - 1.0).abs() < 1e-10);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invert_measurement_err() {
+        let mut b = OpBuilder::new();
+        let q = b.qubit();
+        let r = b.qubit();
+        let (_, m) = b.measure(q);
+        let res = inverter(&mut b, vec![r], |b, mut rs| {
+            let r = b.classical_if(rs.pop().unwrap(), &m, Box::new(|b, r| Ok(b.not(r))));
+            Ok(vec![r])
+        });
+        assert!(res.is_err());
+    }
+
     #[test]
     fn test_invert_and_wrap_add() -> Result<(), CircuitError> {
         wrap_and_invert!(add_op, inv_add, (add), rc, ra, rb);
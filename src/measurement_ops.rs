@@ -1,9 +1,9 @@
-extern crate rand;
-extern crate rayon;
+use crate::errors::CircuitError;
+use crate::parallel::prelude::*;
+use crate::rng;
 use crate::utils::extract_bits;
 use crate::{Complex, Precision};
 use num::Zero;
-use rayon::prelude::*;
 use std::cmp::{max, min};
 
 /// Get total magnitude of state.
@@ -147,7 +147,7 @@ pub fn soft_measure<P: Precision>(
     multithread: bool,
 ) -> u64 {
     let input_offset = input_offset.unwrap_or(0);
-    let mut r = P::from(rand::random::<f64>()).unwrap()
+    let mut r = P::from(rng::random::<f64>()).unwrap()
         * if input.len() < (1 << n) as usize {
             prob_magnitude(input, multithread)
         } else {
@@ -269,6 +269,110 @@ pub fn measure_state<P: Precision>(
     }
 }
 
+/// The action of a pauli string on computational basis states: `P|x> = phase(x)|x ^ flip_mask>`,
+/// with `phase(x) = i^num_y * (-1)^popcount(x & phase_mask)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PauliMasks {
+    /// Bits flipped by X and Y.
+    pub flip_mask: u64,
+    /// Bits which give a sign from Z and Y.
+    pub phase_mask: u64,
+    /// Number of Y terms in the string.
+    pub num_y: u64,
+}
+
+impl PauliMasks {
+    /// Parse a pauli string such as `"XZIY"` acting on a state of `n` qubits, the character at
+    /// position `i` acts on qubit `i` and qubits past the end of the string are left alone.
+    pub fn new(n: u64, pauli: &str) -> Result<Self, CircuitError> {
+        if pauli.len() as u64 > n {
+            let message = format!(
+                "Pauli string {:?} is longer than the number of qubits ({:?})",
+                pauli, n
+            );
+            return CircuitError::make_err(message);
+        }
+        pauli.chars().enumerate().try_fold(
+            PauliMasks {
+                flip_mask: 0,
+                phase_mask: 0,
+                num_y: 0,
+            },
+            |mut masks, (i, c)| {
+                let bit = 1 << (n - 1 - i as u64);
+                match c {
+                    'I' => {}
+                    'X' => masks.flip_mask |= bit,
+                    'Y' => {
+                        masks.flip_mask |= bit;
+                        masks.phase_mask |= bit;
+                        masks.num_y += 1;
+                    }
+                    'Z' => masks.phase_mask |= bit,
+                    _ => {
+                        let message = format!("Unknown pauli {:?} in {:?}", c, pauli);
+                        return CircuitError::make_err(message);
+                    }
+                }
+                Ok(masks)
+            },
+        )
+    }
+
+    /// Get the phase picked up by the basis state `x`.
+    pub fn phase<P: Precision>(&self, x: u64) -> Complex<P> {
+        let sign = if (x & self.phase_mask).count_ones() & 1 == 0 {
+            P::one()
+        } else {
+            -P::one()
+        };
+        match self.num_y % 4 {
+            0 => Complex::new(sign, P::zero()),
+            1 => Complex::new(P::zero(), sign),
+            2 => Complex::new(-sign, P::zero()),
+            _ => Complex::new(P::zero(), -sign),
+        }
+    }
+}
+
+/// Calculate the expectation value `<psi|P|psi>` of the pauli string `pauli` (see `PauliMasks`)
+/// on the state `input` of `n` qubits.
+///
+/// # Examples
+/// ```
+/// use qip::state_ops::from_reals;
+/// use qip::measurement_ops::pauli_expectation;
+/// # fn main() -> Result<(), qip::CircuitError> {
+///
+/// // The state |10>
+/// let input = from_reals(&[0.0, 0.0, 1.0, 0.0]);
+///
+/// assert_eq!(pauli_expectation(2, "ZZ", &input, false)?, -1.0);
+/// assert_eq!(pauli_expectation(2, "IZ", &input, false)?, 1.0);
+/// assert_eq!(pauli_expectation(2, "XI", &input, false)?, 0.0);
+/// # Ok(())
+/// # }
+/// ```
+pub fn pauli_expectation<P: Precision>(
+    n: u64,
+    pauli: &str,
+    input: &[Complex<P>],
+    multithread: bool,
+) -> Result<P, CircuitError> {
+    let masks = PauliMasks::new(n, pauli)?;
+    let f = |(x, c): (usize, &Complex<P>)| -> P {
+        let x = x as u64;
+        let v = input[(x ^ masks.flip_mask) as usize].conj() * masks.phase::<P>(x) * c;
+        v.re
+    };
+    let e = if multithread {
+        input.par_iter().enumerate().map(f).sum()
+    } else {
+        input.iter().enumerate().map(f).sum()
+    };
+    Ok(e)
+}
+
 #[cfg(test)]
 mod measurement_tests {
     use super::*;
@@ -326,6 +430,30 @@ mod measurement_tests {
         );
     }
 
+    #[test]
+    fn test_pauli_expectation() -> Result<(), CircuitError> {
+        let half: f64 = 1.0 / 2.0;
+        // (|0> + i|1>)/sqrt(2) is the +1 eigenstate of Y.
+        let input = vec![
+            Complex::new(half.sqrt(), 0.0),
+            Complex::new(0.0, half.sqrt()),
+        ];
+        assert!((pauli_expectation(1, "Y", &input, false)? - 1.0).abs() < 1e-10);
+        assert!(pauli_expectation(1, "X", &input, false)?.abs() < 1e-10);
+        assert!(pauli_expectation(1, "Z", &input, false)?.abs() < 1e-10);
+
+        // Bell state |00> + |11>
+        let input = from_reals(&[half.sqrt(), 0.0, 0.0, half.sqrt()]);
+        assert!((pauli_expectation(2, "XX", &input, true)? - 1.0).abs() < 1e-10);
+        assert!((pauli_expectation(2, "YY", &input, false)? + 1.0).abs() < 1e-10);
+        assert!((pauli_expectation(2, "ZZ", &input, false)? - 1.0).abs() < 1e-10);
+        assert!(pauli_expectation(2, "ZI", &input, false)?.abs() < 1e-10);
+
+        assert!(pauli_expectation(2, "XZY", &input, false).is_err());
+        assert!(pauli_expectation(2, "XA", &input, false).is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_measure_probs() {
         let n = 2;
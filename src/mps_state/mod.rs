@@ -0,0 +1,36 @@
+/// State struct
+pub mod state;
+pub(crate) mod svd;
+
+use crate::pipeline::{
+    get_required_state_size, get_required_state_size_from_frontier, run_with_statebuilder,
+    MeasuredResults, RegisterInitialState,
+};
+use crate::{CircuitError, Precision, Register};
+pub use state::MpsQuantumState;
+
+/// `run` the pipeline using `MpsQuantumState` with at most `max_bond` singular values per bond.
+pub fn run_mps_local<P: Precision>(
+    r: &Register,
+    max_bond: Option<usize>,
+) -> Result<(MpsQuantumState<P>, MeasuredResults<P>), CircuitError> {
+    run_with_statebuilder(r, |rs| {
+        let n = get_required_state_size_from_frontier(&rs);
+        Ok(MpsQuantumState::new_with_bond_dimension(n, max_bond))
+    })
+}
+
+/// `run_with_init` the pipeline using `MpsQuantumState` with at most `max_bond` singular values
+/// per bond.
+pub fn run_mps_local_with_init<P: Precision>(
+    r: &Register,
+    states: &[RegisterInitialState<P>],
+    max_bond: Option<usize>,
+) -> Result<(MpsQuantumState<P>, MeasuredResults<P>), CircuitError> {
+    run_with_statebuilder(r, |rs| {
+        let n = get_required_state_size(&rs, states);
+        Ok(MpsQuantumState::new_from_initial_states_and_bond_dimension(
+            n, states, max_bond,
+        ))
+    })
+}
@@ -0,0 +1,587 @@
+use crate::errors::CircuitError;
+use crate::macros::inverter::remap_indices;
+use crate::measurement_ops::MeasuredCondition;
+use crate::mps_state::svd::svd;
+use crate::pipeline::InitialState;
+use crate::rng;
+use crate::state_ops::{apply_op, from_reals, get_index, make_matrix_op, num_indices, UnitaryOp};
+use crate::utils::flip_bits;
+use crate::{Complex, Precision, QuantumState};
+use num::{One, Zero};
+
+/// A site of the matrix product state, a tensor with indices `(left, physical, right)`.
+#[derive(Debug, Clone)]
+struct Site<P: Precision> {
+    left: usize,
+    right: usize,
+    data: Vec<Complex<P>>,
+}
+
+impl<P: Precision> Site<P> {
+    fn get(&self, l: usize, s: usize, r: usize) -> Complex<P> {
+        self.data[(l * 2 + s) * self.right + r]
+    }
+}
+
+/// A quantum state stored as a matrix product state with a limited bond dimension.
+///
+/// Ops contract the sites between their lowest and highest qubit, apply the op, then split the
+/// block back into sites with SVDs, keeping at most `max_bond` singular values per bond. Ops on
+/// nearby qubits are therefore cheap while ops spanning many qubits are exponentially expensive.
+/// The discarded weight from truncation is accumulated and available from `truncation_error`.
+#[derive(Debug)]
+pub struct MpsQuantumState<P: Precision> {
+    n: u64,
+    sites: Vec<Site<P>>,
+    max_bond: Option<usize>,
+    truncation_error: P,
+}
+
+impl<P: Precision> MpsQuantumState<P> {
+    /// Make a new state of `n` qubits in `|0...0>`, keeping at most `max_bond` singular values
+    /// across each bond. `None` keeps all nonzero singular values (exact simulation).
+    pub fn new_with_bond_dimension(n: u64, max_bond: Option<usize>) -> MpsQuantumState<P> {
+        let sites = (0..n)
+            .map(|_| Site {
+                left: 1,
+                right: 1,
+                data: vec![Complex::one(), Complex::zero()],
+            })
+            .collect();
+        MpsQuantumState {
+            n,
+            sites,
+            max_bond,
+            truncation_error: P::zero(),
+        }
+    }
+
+    /// Build a state from initial states, keeping at most `max_bond` singular values across each
+    /// bond.
+    pub fn new_from_initial_states_and_bond_dimension(
+        n: u64,
+        states: &[(Vec<u64>, InitialState<P>)],
+        max_bond: Option<usize>,
+    ) -> MpsQuantumState<P> {
+        let max_init_n = states
+            .iter()
+            .flat_map(|(indices, _)| indices.iter().cloned())
+            .max()
+            .map(|m| m + 1)
+            .unwrap_or(0);
+        let mut s = Self::new_with_bond_dimension(n.max(max_init_n), max_bond);
+        states.iter().for_each(|(indices, state)| {
+            let op = match state {
+                InitialState::Index(index) => {
+                    let k = indices.len();
+                    let index = flip_bits(k, *index);
+                    let size = 1 << k;
+                    let mat = (0..size * size)
+                        .map(|i| {
+                            // Permutation swapping |0> and |index>
+                            let (row, col) = ((i / size) as u64, (i % size) as u64);
+                            let target = if col == 0 {
+                                index
+                            } else if col == index {
+                                0
+                            } else {
+                                col
+                            };
+                            if row == target {
+                                Complex::one()
+                            } else {
+                                Complex::zero()
+                            }
+                        })
+                        .collect();
+                    make_matrix_op(indices.clone(), mat).unwrap()
+                }
+                InitialState::FullState(vals) => {
+                    let k = indices.len();
+                    let v: Vec<Complex<f64>> = (0..1u64 << k)
+                        .map(|i| {
+                            let c = vals[flip_bits(k, i) as usize];
+                            Complex {
+                                re: c.re.to_f64().unwrap(),
+                                im: c.im.to_f64().unwrap(),
+                            }
+                        })
+                        .collect();
+                    make_matrix_op(indices.clone(), unitary_from_first_column(&v)).unwrap()
+                }
+            };
+            s.apply_op(&op);
+        });
+        s
+    }
+
+    /// The accumulated weight of discarded singular values, relative to the state norm at each
+    /// truncation. This is zero for exact simulations.
+    pub fn truncation_error(&self) -> P {
+        self.truncation_error
+    }
+
+    /// The bond dimensions between each pair of neighboring qubits.
+    pub fn bond_dimensions(&self) -> Vec<usize> {
+        self.sites
+            .iter()
+            .take(self.sites.len().saturating_sub(1))
+            .map(|site| site.right)
+            .collect()
+    }
+
+    /// Rotate to a new computational basis:
+    /// `|0'> =  cos(angle)|0> + sin(angle)|1>`
+    /// `|1'> = -sin(angle)|0> + cos(angle)|1>`
+    pub fn rotate_basis(&mut self, indices: &[u64], angle: f64) {
+        if angle != 0.0 {
+            let (sangle, cangle) = angle.sin_cos();
+            let basis_mat = from_reals(&[cangle, -sangle, sangle, cangle]);
+            indices.iter().for_each(|indx| {
+                let op = make_matrix_op(vec![*indx], basis_mat.clone()).unwrap();
+                self.apply_op(&op);
+            });
+        }
+    }
+
+    /// Compute `<psi|O|psi>` where `O` is diagonal, given by `diag(site, s)` for each site.
+    fn diagonal_expectation<F: Fn(usize, usize) -> P>(&self, diag: F) -> P {
+        let env = self
+            .sites
+            .iter()
+            .enumerate()
+            .fold(vec![Complex::one()], |env, (i, site)| {
+                let chi = site.right;
+                let mut next = vec![Complex::zero(); chi * chi];
+                (0..site.left).for_each(|a| {
+                    (0..site.left).for_each(|b| {
+                        let e: Complex<P> = env[a * site.left + b];
+                        if e.is_zero() {
+                            return;
+                        }
+                        (0..2).for_each(|s| {
+                            let d = diag(i, s);
+                            if d.is_zero() {
+                                return;
+                            }
+                            (0..chi).for_each(|c| {
+                                let x = site.get(a, s, c).conj() * e * d;
+                                (0..chi).for_each(|dd| {
+                                    next[c * chi + dd] =
+                                        next[c * chi + dd] + x * site.get(b, s, dd);
+                                })
+                            })
+                        })
+                    })
+                });
+                next
+            });
+        env[0].re
+    }
+
+    fn norm_sqr(&self) -> P {
+        self.diagonal_expectation(|_, _| P::one())
+    }
+
+    /// Probability of measuring `outcome` on qubit `q`.
+    fn qubit_prob(&self, q: usize, outcome: usize) -> P {
+        let p = self.diagonal_expectation(|i, s| {
+            if i != q || s == outcome {
+                P::one()
+            } else {
+                P::zero()
+            }
+        });
+        p / self.norm_sqr()
+    }
+
+    /// Project qubit `q` onto `outcome` and normalize.
+    fn project_qubit(&mut self, q: usize, outcome: usize) {
+        let site = &mut self.sites[q];
+        let right = site.right;
+        (0..site.left).for_each(|l| {
+            (0..right).for_each(|r| {
+                site.data[(l * 2 + (1 - outcome)) * right + r] = Complex::zero();
+            })
+        });
+        let norm = self.norm_sqr();
+        if !norm.is_zero() {
+            let scale = P::one() / norm.sqrt();
+            self.sites[q].data.iter_mut().for_each(|x| *x = *x * scale);
+        }
+    }
+
+    /// Measure each of `indices` in turn, returns the combined outcome and its probability.
+    fn measure_indices(&mut self, indices: &[u64], forced: Option<u64>) -> (u64, P) {
+        indices
+            .iter()
+            .enumerate()
+            .fold((0, P::one()), |(m, p), (j, indx)| {
+                let q = *indx as usize;
+                let p_one = self.qubit_prob(q, 1);
+                let outcome = match forced {
+                    Some(f) => ((f >> j) & 1) as usize,
+                    None => {
+                        if P::from(rng::random::<f64>()).unwrap() < p_one {
+                            1
+                        } else {
+                            0
+                        }
+                    }
+                };
+                let p_outcome = if outcome == 1 {
+                    p_one
+                } else {
+                    P::one() - p_one
+                };
+                self.project_qubit(q, outcome);
+                (m | ((outcome as u64) << j), p * p_outcome)
+            })
+    }
+
+    /// Probability of each outcome on `indices`.
+    fn outcome_probs(&self, indices: &[u64]) -> Vec<P> {
+        match indices.split_first() {
+            None => vec![P::one()],
+            Some((indx, rest)) => {
+                let branch = |outcome: usize| -> Vec<P> {
+                    let p = self.qubit_prob(*indx as usize, outcome);
+                    if p.is_zero() {
+                        return vec![P::zero(); 1 << rest.len()];
+                    }
+                    let mut s = self.clone();
+                    s.project_qubit(*indx as usize, outcome);
+                    s.outcome_probs(rest).into_iter().map(|q| p * q).collect()
+                };
+                // Bit 0 of the outcome corresponds to the first index.
+                branch(0)
+                    .into_iter()
+                    .zip(branch(1))
+                    .flat_map(|(a, b)| vec![a, b])
+                    .collect()
+            }
+        }
+    }
+
+    /// Contract sites `lo..=hi` into a tensor with indices `(left, physical, right)`.
+    fn contract_block(&self, lo: usize, hi: usize) -> (usize, usize, Vec<Complex<P>>) {
+        let first = &self.sites[lo];
+        let init = (2, first.data.clone());
+        let (phys, data) = self.sites[lo + 1..=hi]
+            .iter()
+            .fold(init, |(phys, data), site| {
+                let left = self.sites[lo].left;
+                let mut next = vec![Complex::zero(); left * phys * 2 * site.right];
+                (0..left).for_each(|a| {
+                    (0..phys).for_each(|s| {
+                        (0..site.left).for_each(|b| {
+                            let x = data[(a * phys + s) * site.left + b];
+                            if x.is_zero() {
+                                return;
+                            }
+                            (0..2).for_each(|s2| {
+                                (0..site.right).for_each(|c| {
+                                    let indx = (a * phys * 2 + s * 2 + s2) * site.right + c;
+                                    next[indx] = next[indx] + x * site.get(b, s2, c);
+                                })
+                            })
+                        })
+                    })
+                });
+                (phys * 2, next)
+            });
+        (self.sites[lo].left, phys, data)
+    }
+
+    /// Split a block tensor back into sites `lo..=hi`, truncating each bond.
+    fn split_block(&mut self, lo: usize, hi: usize, left: usize, data: Vec<Complex<P>>) {
+        let right = self.sites[hi].right;
+        let (left, data) = (lo..hi).fold((left, data), |(left, data), i| {
+            let rem_phys = 1 << (hi - i);
+            let rows = left * 2;
+            let cols = rem_phys * right;
+            let result = svd(&data, rows, cols);
+
+            let total: P = result.s.iter().map(|s| *s * *s).sum();
+            let cutoff = result.s.first().cloned().unwrap_or_else(P::zero)
+                * P::epsilon()
+                * P::from(rows.max(cols)).unwrap();
+            let nonzero = result.s.iter().filter(|s| **s > cutoff).count().max(1);
+            let keep = self.max_bond.map(|m| m.min(nonzero)).unwrap_or(nonzero);
+            if keep < nonzero && !total.is_zero() {
+                let discarded: P = result.s[keep..].iter().map(|s| *s * *s).sum();
+                self.truncation_error = self.truncation_error + discarded / total;
+            }
+
+            self.sites[i] = Site {
+                left,
+                right: keep,
+                data: (0..rows * keep)
+                    .map(|x| result.u[(x / keep) * result.k + x % keep])
+                    .collect(),
+            };
+            let remainder = (0..keep * cols)
+                .map(|x| {
+                    let (r, c) = (x / cols, x % cols);
+                    result.vh[r * cols + c] * result.s[r]
+                })
+                .collect();
+            (keep, remainder)
+        });
+        self.sites[hi] = Site { left, right, data };
+    }
+}
+
+impl<P: Precision> Clone for MpsQuantumState<P> {
+    fn clone(&self) -> Self {
+        MpsQuantumState {
+            n: self.n,
+            sites: self.sites.clone(),
+            max_bond: self.max_bond,
+            truncation_error: self.truncation_error,
+        }
+    }
+}
+
+/// Build a unitary matrix (row-major) whose first column is the normalized `v`.
+fn unitary_from_first_column(v: &[Complex<f64>]) -> Vec<Complex<f64>> {
+    let size = v.len();
+    let norm = v.iter().map(|c| c.norm_sqr()).sum::<f64>().sqrt();
+    let v: Vec<Complex<f64>> = v.iter().map(|c| c / norm).collect();
+    // A householder reflection H maps alpha e0 to v, so alpha H has first column v.
+    let alpha = if v[0].norm() > 0.0 {
+        v[0] / v[0].norm()
+    } else {
+        Complex::one()
+    };
+    let u: Vec<Complex<f64>> = (0..size)
+        .map(|i| if i == 0 { v[0] - alpha } else { v[i] })
+        .collect();
+    let u_norm = u.iter().map(|c| c.norm_sqr()).sum::<f64>();
+    (0..size * size)
+        .map(|i| {
+            let (r, c) = (i / size, i % size);
+            let identity: Complex<f64> = if r == c {
+                Complex::one()
+            } else {
+                Complex::zero()
+            };
+            let reflection = if u_norm > 0.0 {
+                identity - u[r] * u[c].conj() * (2.0 / u_norm)
+            } else {
+                identity
+            };
+            reflection * alpha
+        })
+        .collect()
+}
+
+impl<P: Precision> QuantumState<P> for MpsQuantumState<P> {
+    fn new(n: u64) -> Self {
+        Self::new_with_bond_dimension(n, None)
+    }
+
+    fn new_from_initial_states(n: u64, states: &[(Vec<u64>, InitialState<P>)]) -> Self {
+        Self::new_from_initial_states_and_bond_dimension(n, states, None)
+    }
+
+    fn n(&self) -> u64 {
+        self.n
+    }
+
+    fn apply_op_with_name(&mut self, _name: Option<&str>, op: &UnitaryOp) {
+        let indices: Vec<u64> = (0..num_indices(op)).map(|i| get_index(op, i)).collect();
+        let lo = *indices.iter().min().unwrap() as usize;
+        let hi = *indices.iter().max().unwrap() as usize;
+        let m = (hi - lo + 1) as u64;
+        let new_indices: Vec<u64> = (0..=hi as u64)
+            .map(|i| i.saturating_sub(lo as u64))
+            .collect();
+        let local_op = remap_indices(op.clone(), &new_indices);
+
+        let (left, phys, mut data) = self.contract_block(lo, hi);
+        let right = self.sites[hi].right;
+        let mut input = vec![Complex::zero(); phys];
+        let mut output = vec![Complex::zero(); phys];
+        (0..left).for_each(|a| {
+            (0..right).for_each(|c| {
+                (0..phys).for_each(|s| input[s] = data[(a * phys + s) * right + c]);
+                apply_op(m, &local_op, &input, &mut output, 0, 0, false);
+                (0..phys).for_each(|s| data[(a * phys + s) * right + c] = output[s]);
+            })
+        });
+        self.split_block(lo, hi, left, data);
+    }
+
+    fn apply_channel(
+        &mut self,
+        _name: Option<&str>,
+        indices: &[u64],
+        kraus_ops: &[Vec<Complex<f64>>],
+    ) -> Result<(), CircuitError> {
+        let ops = kraus_ops
+            .iter()
+            .map(|k| make_matrix_op(indices.to_vec(), k.clone()))
+            .collect::<Result<Vec<_>, CircuitError>>()?;
+        // Sample kraus operator k with probability |K_k psi|^2
+        let norm = self.norm_sqr();
+        let mut r = P::from(rng::random::<f64>()).unwrap() * norm;
+        let last = ops.len() - 1;
+        for (i, op) in ops.iter().enumerate() {
+            let mut s = self.clone();
+            s.apply_op(op);
+            let p = s.norm_sqr();
+            r = r - p;
+            if (r <= P::zero() || i == last) && !p.is_zero() {
+                let scale = (norm / p).sqrt();
+                s.sites[0].data.iter_mut().for_each(|c| *c = *c * scale);
+                *self = s;
+                return Ok(());
+            }
+        }
+        CircuitError::make_str_err("Channel has zero probability for the current state")
+    }
+
+    fn measure(
+        &mut self,
+        indices: &[u64],
+        measured: Option<MeasuredCondition<P>>,
+        angle: f64,
+    ) -> (u64, P) {
+        self.rotate_basis(indices, angle);
+        let (m, p) = self.measure_indices(indices, measured.as_ref().map(|m| m.measured));
+        self.rotate_basis(indices, -angle);
+        (m, measured.and_then(|m| m.prob).unwrap_or(p))
+    }
+
+    fn soft_measure(&mut self, indices: &[u64], measured: Option<u64>, angle: f64) -> (u64, P) {
+        let mut s = self.clone();
+        s.rotate_basis(indices, angle);
+        s.measure_indices(indices, measured)
+    }
+
+    fn state_magnitude(&self) -> P {
+        self.norm_sqr()
+    }
+
+    fn stochastic_measure(&mut self, indices: &[u64], angle: f64) -> Vec<P> {
+        let mut s = self.clone();
+        s.rotate_basis(indices, angle);
+        s.outcome_probs(indices)
+    }
+
+    /// Contract the full state vector, only feasible for small `n`.
+    fn get_state(self, natural_order: bool) -> Vec<Complex<P>> {
+        let n = self.n;
+        let state = if n == 0 {
+            vec![Complex::one()]
+        } else {
+            let (_, _, data) = self.contract_block(0, n as usize - 1);
+            data
+        };
+        if natural_order {
+            (0..state.len())
+                .map(|i| state[flip_bits(n as usize, i as u64) as usize])
+                .collect()
+        } else {
+            state
+        }
+    }
+}
+
+#[cfg(test)]
+mod mps_state_tests {
+    use super::*;
+    use crate::mps_state::{run_mps_local, run_mps_local_with_init};
+    use crate::pipeline::{run_local, run_local_with_init};
+    use crate::{OpBuilder, Register, UnitaryBuilder};
+
+    fn assert_states_close(a: &[Complex<f64>], b: &[Complex<f64>]) {
+        assert_eq!(a.len(), b.len());
+        a.iter().zip(b.iter()).for_each(|(a, b)| {
+            assert!((a - b).norm() < 1e-8, "{:?} != {:?}", a, b);
+        });
+    }
+
+    fn make_circuit(b: &mut OpBuilder, n: u64) -> Result<Register, CircuitError> {
+        let r = b.register(n)?;
+        let mut rs = b.split_all(r);
+        let q = b.hadamard(rs.remove(0));
+        let (q, mut rs) = rs.into_iter().fold((q, vec![]), |(q, mut acc), r| {
+            let (q, r) = b.cnot(q, r);
+            let r = b.ry(r, 0.4);
+            acc.push(r);
+            (q, acc)
+        });
+        let last = rs.pop().unwrap();
+        let (q, last) = b.cz(q, last);
+        rs.insert(0, q);
+        rs.push(last);
+        b.merge(rs)
+    }
+
+    #[test]
+    fn test_matches_local_state() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let r = make_circuit(&mut b, 5)?;
+        let (mps, _) = run_mps_local::<f64>(&r, None)?;
+        assert!(mps.truncation_error().abs() < 1e-12);
+        let (local, _) = run_local::<f64>(&r)?;
+        assert_states_close(&mps.get_state(true), &local.get_state(true));
+        Ok(())
+    }
+
+    #[test]
+    fn test_initial_states() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let ra = b.register(2)?;
+        let rb = b.register(2)?;
+        let (ha, hb) = (ra.handle(), rb.handle());
+        let ra = b.hadamard(ra);
+        let r = b.merge(vec![ra, rb])?;
+
+        let v = from_reals(&[0.5, 0.5, -0.5, 0.5]);
+        let init = [ha.make_init_from_index(0b10)?, hb.make_init_from_state(v)?];
+        let (mps, _) = run_mps_local_with_init::<f64>(&r, &init, None)?;
+        let (local, _) = run_local_with_init::<f64>(&r, &init)?;
+        assert_states_close(&mps.get_state(true), &local.get_state(true));
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncation() -> Result<(), CircuitError> {
+        // Nested bell pairs (2, 3), (1, 4), (0, 5) need a bond dimension of 8 in the middle.
+        let mut b = OpBuilder::new();
+        let r = b.register(6)?;
+        let mut rs: Vec<Option<Register>> = b.split_all(r).into_iter().map(Some).collect();
+        (0..3).try_for_each(|i| -> Result<(), CircuitError> {
+            let q = b.hadamard(rs[i].take().unwrap());
+            let (q, t) = b.cnot(q, rs[5 - i].take().unwrap());
+            rs[i] = Some(q);
+            rs[5 - i] = Some(t);
+            Ok(())
+        })?;
+        let r = b.merge(rs.into_iter().map(|r| r.unwrap()).collect())?;
+
+        let (mps, _) = run_mps_local::<f64>(&r, Some(2))?;
+        assert!(mps.bond_dimensions().iter().all(|chi| *chi <= 2));
+        assert!(mps.truncation_error() > 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_measure() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let r = make_circuit(&mut b, 4)?;
+        let (r, m) = b.stochastic_measure(r);
+        let (_, measured) = run_mps_local::<f64>(&r, None)?;
+        let (_, local_measured) = run_local::<f64>(&r)?;
+        let probs = measured.clone_stochastic_measurements(m).unwrap();
+        let local_probs = local_measured.clone_stochastic_measurements(m).unwrap();
+        probs
+            .iter()
+            .zip(local_probs.iter())
+            .for_each(|(a, b)| assert!((a - b).abs() < 1e-8));
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+use crate::{Complex, Precision};
+use num::{One, Zero};
+
+/// The singular value decomposition `A = U diag(S) V^dagger` of a row-major `rows` by `cols`
+/// matrix, with singular values in descending order.
+#[derive(Debug)]
+pub(crate) struct Svd<P: Precision> {
+    /// `rows` by `k` row-major matrix.
+    pub(crate) u: Vec<Complex<P>>,
+    /// `k` singular values.
+    pub(crate) s: Vec<P>,
+    /// `k` by `cols` row-major matrix.
+    pub(crate) vh: Vec<Complex<P>>,
+    /// Number of singular values `k = min(rows, cols)`.
+    pub(crate) k: usize,
+}
+
+/// Compute the thin SVD of `a` using one-sided Jacobi rotations.
+pub(crate) fn svd<P: Precision>(a: &[Complex<P>], rows: usize, cols: usize) -> Svd<P> {
+    if rows < cols {
+        // Decompose A^dagger = U' S V'^dagger, so A = V' S U'^dagger
+        let ah: Vec<Complex<P>> = (0..cols * rows)
+            .map(|i| {
+                let (r, c) = (i / rows, i % rows);
+                a[c * cols + r].conj()
+            })
+            .collect();
+        let Svd { u, s, vh, k } = svd(&ah, cols, rows);
+        let new_u = (0..rows * k)
+            .map(|i| {
+                let (r, c) = (i / k, i % k);
+                vh[c * rows + r].conj()
+            })
+            .collect();
+        let new_vh = (0..k * cols)
+            .map(|i| {
+                let (r, c) = (i / cols, i % cols);
+                u[c * k + r].conj()
+            })
+            .collect();
+        return Svd {
+            u: new_u,
+            s,
+            vh: new_vh,
+            k,
+        };
+    }
+
+    // Columns of `w` are rotated until orthogonal, `v` accumulates the rotations.
+    let mut w: Vec<Vec<Complex<P>>> = (0..cols)
+        .map(|c| (0..rows).map(|r| a[r * cols + c]).collect())
+        .collect();
+    let mut v: Vec<Vec<Complex<P>>> = (0..cols)
+        .map(|c| {
+            (0..cols)
+                .map(|r| {
+                    if r == c {
+                        Complex::one()
+                    } else {
+                        Complex::zero()
+                    }
+                })
+                .collect()
+        })
+        .collect();
+
+    let eps = P::epsilon();
+    let two = P::one() + P::one();
+    for _ in 0..64 {
+        let mut rotated = false;
+        for p in 0..cols {
+            for q in p + 1..cols {
+                let alpha: P = w[p].iter().map(Complex::norm_sqr).sum();
+                let beta: P = w[q].iter().map(Complex::norm_sqr).sum();
+                let gamma: Complex<P> = w[p]
+                    .iter()
+                    .zip(w[q].iter())
+                    .map(|(x, y)| x.conj() * y)
+                    .sum();
+                let g = gamma.norm();
+                if g <= eps * (alpha * beta).sqrt() || g.is_zero() {
+                    continue;
+                }
+                rotated = true;
+                let phase = gamma / g;
+                let zeta = (beta - alpha) / (two * g);
+                let t = zeta.signum() / (zeta.abs() + (P::one() + zeta * zeta).sqrt());
+                let c = P::one() / (P::one() + t * t).sqrt();
+                let s = c * t;
+                let rotate = |cols: &mut Vec<Vec<Complex<P>>>| {
+                    let (left, right) = cols.split_at_mut(q);
+                    left[p]
+                        .iter_mut()
+                        .zip(right[0].iter_mut())
+                        .for_each(|(x, y)| {
+                            let yt = *y * phase.conj();
+                            let new_x = *x * c - yt * s;
+                            let new_y = *x * s + yt * c;
+                            *x = new_x;
+                            *y = new_y;
+                        });
+                };
+                rotate(&mut w);
+                rotate(&mut v);
+            }
+        }
+        if !rotated {
+            break;
+        }
+    }
+
+    let mut order: Vec<(usize, P)> = w
+        .iter()
+        .map(|col| col.iter().map(Complex::norm_sqr).sum::<P>().sqrt())
+        .enumerate()
+        .collect();
+    order.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap());
+
+    let k = cols;
+    let s: Vec<P> = order.iter().map(|(_, s)| *s).collect();
+    let mut u = vec![Complex::zero(); rows * k];
+    order.iter().enumerate().for_each(|(j, (c, sigma))| {
+        if !sigma.is_zero() {
+            (0..rows).for_each(|r| u[r * k + j] = w[*c][r] / *sigma);
+        } else if j < rows {
+            // Zero singular value, any unit vector will do for the decomposition.
+            u[j * k + j] = Complex::one();
+        }
+    });
+    let vh = order
+        .iter()
+        .flat_map(|(c, _)| v[*c].iter().map(|x| x.conj()).collect::<Vec<_>>())
+        .collect();
+    Svd { u, s, vh, k }
+}
+
+#[cfg(test)]
+mod svd_tests {
+    use super::*;
+
+    fn reconstruct(svd: &Svd<f64>, rows: usize, cols: usize) -> Vec<Complex<f64>> {
+        (0..rows * cols)
+            .map(|i| {
+                let (r, c) = (i / cols, i % cols);
+                (0..svd.k)
+                    .map(|j| svd.u[r * svd.k + j] * svd.s[j] * svd.vh[j * cols + c])
+                    .sum()
+            })
+            .collect()
+    }
+
+    fn test_matrix(rows: usize, cols: usize) -> Vec<Complex<f64>> {
+        (0..rows * cols)
+            .map(|i| Complex {
+                re: ((i * 7) % 5) as f64 - 2.0,
+                im: ((i * 3) % 4) as f64 - 1.5,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_svd_tall() {
+        let a = test_matrix(6, 3);
+        let result = svd(&a, 6, 3);
+        assert_eq!(result.k, 3);
+        reconstruct(&result, 6, 3)
+            .into_iter()
+            .zip(a)
+            .for_each(|(x, y)| assert!((x - y).norm() < 1e-10));
+        assert!(result.s.windows(2).all(|w| w[0] >= w[1]));
+    }
+
+    #[test]
+    fn test_svd_wide() {
+        let a = test_matrix(2, 5);
+        let result = svd(&a, 2, 5);
+        assert_eq!(result.k, 2);
+        reconstruct(&result, 2, 5)
+            .into_iter()
+            .zip(a)
+            .for_each(|(x, y)| assert!((x - y).norm() < 1e-10));
+    }
+
+    #[test]
+    fn test_svd_rank_one() {
+        let a: Vec<Complex<f64>> = (0..4 * 4)
+            .map(|i| Complex::from(((i / 4) + 1) as f64 * ((i % 4) + 1) as f64))
+            .collect();
+        let result = svd(&a, 4, 4);
+        assert!(result.s[1] < 1e-10);
+        reconstruct(&result, 4, 4)
+            .into_iter()
+            .zip(a)
+            .for_each(|(x, y)| assert!((x - y).norm() < 1e-10));
+    }
+}
@@ -0,0 +1,121 @@
+use crate::errors::CircuitError;
+use crate::macros::inverter::remap_indices;
+use crate::pipeline::{StateModifier, StateModifierType};
+
+/// A composite gate registered with `OpBuilder::define_gate`. The definition is built once on its
+/// own qubits `0..n`, with the registers of the gate laid out one after another, and each place
+/// the gate is applied shares it rather than copying its ops.
+#[derive(Debug)]
+pub struct GateDefinition {
+    name: String,
+    widths: Vec<u64>,
+    modifiers: Vec<StateModifier>,
+}
+
+impl GateDefinition {
+    /// Make a definition from `modifiers` acting on the qubits `0..n` where `n` is the sum of
+    /// `widths`. Only unitary ops and applications of other named gates are allowed.
+    pub(crate) fn new(
+        name: String,
+        widths: Vec<u64>,
+        modifiers: Vec<StateModifier>,
+    ) -> Result<GateDefinition, CircuitError> {
+        let n: u64 = widths.iter().sum();
+        modifiers.iter().try_for_each(|modifier| {
+            let indices = match &modifier.modifier {
+                StateModifierType::UnitaryOp(_) | StateModifierType::Gate(_, _) => {
+                    modifier.indices().unwrap_or_default()
+                }
+                _ => {
+                    let message = format!(
+                        "Gate {:?} cannot contain non-unitary op {:?}",
+                        name, modifier.name
+                    );
+                    return CircuitError::make_err(message);
+                }
+            };
+            match indices.iter().find(|indx| **indx >= n) {
+                Some(indx) => {
+                    let message =
+                        format!("Gate {:?} on {:?} qubits uses qubit {:?}", name, n, indx);
+                    CircuitError::make_err(message)
+                }
+                None => Ok(()),
+            }
+        })?;
+        Ok(GateDefinition {
+            name,
+            widths,
+            modifiers,
+        })
+    }
+
+    /// The name the gate was registered with.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The number of qubits in each register the gate is applied to.
+    pub fn widths(&self) -> &[u64] {
+        &self.widths
+    }
+
+    /// The total number of qubits the gate acts on.
+    pub fn n(&self) -> u64 {
+        self.widths.iter().sum()
+    }
+
+    /// The ops making up the gate, on the qubits `0..n`.
+    pub fn modifiers(&self) -> &[StateModifier] {
+        &self.modifiers
+    }
+
+    /// Get the ops of the gate applied to `indices`, where qubit `i` of the definition is
+    /// `indices[i]`. Nested gates are left as applications of their shared definitions.
+    pub(crate) fn instantiate(&self, indices: &[u64]) -> Vec<StateModifier> {
+        self.modifiers
+            .iter()
+            .filter_map(|modifier| {
+                let op = match &modifier.modifier {
+                    StateModifierType::UnitaryOp(op) => {
+                        StateModifierType::UnitaryOp(remap_indices(op.clone(), indices))
+                    }
+                    StateModifierType::Gate(def, inner) => StateModifierType::Gate(
+                        def.clone(),
+                        inner.iter().map(|indx| indices[*indx as usize]).collect(),
+                    ),
+                    _ => return None,
+                };
+                Some(StateModifier {
+                    name: modifier.name.clone(),
+                    modifier: op,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Names which would clash with the gates of `qelib1.inc`, keywords, or the quantum register when
+/// exported to OpenQASM.
+const RESERVED_NAMES: &[&str] = &[
+    "barrier", "ccx", "ch", "cos", "cp", "creg", "crx", "cry", "crz", "cswap", "cu1", "cu3", "cx",
+    "cy", "cz", "exp", "gate", "h", "id", "if", "include", "ln", "measure", "opaque", "p", "pi",
+    "q", "qreg", "reset", "rx", "ry", "rz", "s", "sdg", "sin", "sqrt", "swap", "t", "tan", "tdg",
+    "u", "u1", "u2", "u3", "x", "y", "z",
+];
+
+/// Check that `name` can be used for a gate, it must start with a lowercase letter followed by
+/// letters, digits and underscores so that it can be exported as an OpenQASM identifier, and must
+/// not be one of the standard gates.
+pub(crate) fn check_gate_name(name: &str) -> Result<(), CircuitError> {
+    let mut chars = name.chars();
+    let valid = matches!(chars.next(), Some(c) if c.is_ascii_lowercase())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !RESERVED_NAMES.contains(&name);
+    if valid {
+        Ok(())
+    } else {
+        let message = format!("Invalid gate name {:?}", name);
+        CircuitError::make_err(message)
+    }
+}
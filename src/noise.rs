@@ -0,0 +1,220 @@
+use crate::errors::CircuitError;
+use crate::state_ops::{from_reals, from_tuples, get_index, num_indices, UnitaryOp};
+use crate::Complex;
+use std::collections::HashMap;
+
+/// A set of kraus operators defining a channel on one or more qubits.
+pub type KrausOps = Vec<Vec<Complex<f64>>>;
+
+/// Make the kraus operators for the single qubit depolarizing channel
+/// `rho -> (1 - p) rho + p I/2`.
+pub fn depolarizing(p: f64) -> Result<KrausOps, CircuitError> {
+    if !(0.0..=1.0).contains(&p) {
+        let message = format!("Depolarizing probability must be in [0, 1], found {:?}", p);
+        return CircuitError::make_err(message);
+    }
+    let a = (1.0 - 0.75 * p).sqrt();
+    let b = (0.25 * p).sqrt();
+    Ok(vec![
+        from_reals(&[a, 0.0, 0.0, a]),
+        from_reals(&[0.0, b, b, 0.0]),
+        from_tuples(&[(0.0, 0.0), (0.0, -b), (0.0, b), (0.0, 0.0)]),
+        from_reals(&[b, 0.0, 0.0, -b]),
+    ])
+}
+
+/// Make the kraus operators for the single qubit amplitude damping channel, which decays `|1>` to
+/// `|0>` with probability `gamma` (as in T1 relaxation).
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::noise::amplitude_damping;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let q = b.qubit();
+/// let q = b.not(q);
+/// let q = b.channel("T1", q, amplitude_damping(0.1)?)?;
+///
+/// # Ok(())
+/// # }
+/// ```
+pub fn amplitude_damping(gamma: f64) -> Result<KrausOps, CircuitError> {
+    if !(0.0..=1.0).contains(&gamma) {
+        let message = format!("Damping probability must be in [0, 1], found {:?}", gamma);
+        return CircuitError::make_err(message);
+    }
+    Ok(vec![
+        from_reals(&[1.0, 0.0, 0.0, (1.0 - gamma).sqrt()]),
+        from_reals(&[0.0, gamma.sqrt(), 0.0, 0.0]),
+    ])
+}
+
+/// Make the kraus operators for the single qubit phase damping channel, which scales the
+/// coherences between `|0>` and `|1>` by `sqrt(1 - lambda)` without changing populations (as in
+/// T2 dephasing).
+pub fn phase_damping(lambda: f64) -> Result<KrausOps, CircuitError> {
+    if !(0.0..=1.0).contains(&lambda) {
+        let message = format!("Damping probability must be in [0, 1], found {:?}", lambda);
+        return CircuitError::make_err(message);
+    }
+    Ok(vec![
+        from_reals(&[1.0, 0.0, 0.0, (1.0 - lambda).sqrt()]),
+        from_reals(&[0.0, 0.0, 0.0, lambda.sqrt()]),
+    ])
+}
+
+/// A noise model which inserts a depolarizing channel on each qubit touched by a unitary op,
+/// immediately after the op. Probabilities may be given per op name, ops without one use the
+/// default probability.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::noise::NoiseModel;
+/// use qip::pipeline::{run_with_noise, LocalQuantumState};
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut noise = NoiseModel::new();
+/// noise.set_default_probability(0.001)?;
+/// noise.set_gate_probability("C(not)", 0.01)?;
+///
+/// let mut b = OpBuilder::new();
+/// let q = b.qubit();
+/// let r = b.qubit();
+/// let q = b.hadamard(q);
+/// let (q, r) = b.cnot(q, r);
+/// let r = b.merge(vec![q, r])?;
+///
+/// let (state, _) = run_with_noise::<f64, LocalQuantumState<f64>>(&r, &noise)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default, Debug, Clone)]
+pub struct NoiseModel {
+    default_probability: f64,
+    gate_probabilities: HashMap<String, f64>,
+}
+
+impl NoiseModel {
+    /// Make a new noise model which does not add any noise.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the depolarizing probability used for ops without a specific probability.
+    pub fn set_default_probability(&mut self, p: f64) -> Result<(), CircuitError> {
+        depolarizing(p)?;
+        self.default_probability = p;
+        Ok(())
+    }
+
+    /// Set the depolarizing probability for ops with a given name. The name is matched against
+    /// the full name of the op (including name scopes) and then against the last component of the
+    /// name, so `"H"` matches both `"H"` and `"qft/H"`.
+    pub fn set_gate_probability(&mut self, name: &str, p: f64) -> Result<(), CircuitError> {
+        depolarizing(p)?;
+        self.gate_probabilities.insert(name.to_string(), p);
+        Ok(())
+    }
+
+    /// Get the depolarizing probability applied after an op with the given name.
+    pub fn get_probability(&self, name: &str) -> f64 {
+        self.gate_probabilities
+            .get(name)
+            .or_else(|| {
+                name.rsplit('/')
+                    .next()
+                    .and_then(|short| self.gate_probabilities.get(short))
+            })
+            .cloned()
+            .unwrap_or(self.default_probability)
+    }
+
+    /// Get the channels, as pairs of indices and kraus operators, which should be applied after
+    /// `op` named `name`.
+    pub fn get_channels(&self, name: &str, op: &UnitaryOp) -> Vec<(Vec<u64>, KrausOps)> {
+        let p = self.get_probability(name);
+        if p == 0.0 {
+            return vec![];
+        }
+        // p was checked when it was set.
+        let kraus_ops = depolarizing(p).unwrap();
+        (0..num_indices(op))
+            .map(|i| (vec![get_index(op, i)], kraus_ops.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod noise_tests {
+    use super::*;
+    use crate::state_ops::make_matrix_op;
+
+    /// Compute `sum_k K_k^dagger K_k` for single qubit `kraus_ops`.
+    fn kraus_dagger_kraus(kraus_ops: &[Vec<Complex<f64>>]) -> Vec<Complex<f64>> {
+        (0..4)
+            .map(|i| {
+                let (row, col) = (i / 2, i % 2);
+                kraus_ops
+                    .iter()
+                    .map(|k| {
+                        (0..2)
+                            .map(|j| k[j * 2 + row].conj() * k[j * 2 + col])
+                            .sum::<Complex<f64>>()
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_depolarizing_complete() -> Result<(), CircuitError> {
+        let kraus_ops = depolarizing(0.3)?;
+        let sum = kraus_dagger_kraus(&kraus_ops);
+        let expected = from_reals(&[1.0, 0.0, 0.0, 1.0]);
+        sum.iter()
+            .zip(expected.iter())
+            .for_each(|(a, b)| assert!((a - b).norm() < 1e-10));
+        assert!(depolarizing(1.5).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_damping_complete() -> Result<(), CircuitError> {
+        let expected = from_reals(&[1.0, 0.0, 0.0, 1.0]);
+        let channels = [amplitude_damping(0.3)?, phase_damping(0.6)?];
+        channels.iter().for_each(|kraus_ops| {
+            let sum = kraus_dagger_kraus(kraus_ops);
+            sum.iter()
+                .zip(expected.iter())
+                .for_each(|(a, b)| assert!((a - b).norm() < 1e-10));
+        });
+        assert!(amplitude_damping(-0.1).is_err());
+        assert!(phase_damping(1.1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_gate_probabilities() -> Result<(), CircuitError> {
+        let mut noise = NoiseModel::new();
+        noise.set_default_probability(0.1)?;
+        noise.set_gate_probability("H", 0.2)?;
+        noise.set_gate_probability("qft/X", 0.3)?;
+        assert_eq!(noise.get_probability("H"), 0.2);
+        assert_eq!(noise.get_probability("scope/H"), 0.2);
+        assert_eq!(noise.get_probability("qft/X"), 0.3);
+        assert_eq!(noise.get_probability("X"), 0.1);
+
+        let op = make_matrix_op(vec![2, 4], from_reals(&[0.0; 16]))?;
+        let channels = noise.get_channels("H", &op);
+        assert_eq!(channels.len(), 2);
+        assert_eq!(channels[0].0, vec![2]);
+        assert_eq!(channels[1].0, vec![4]);
+
+        noise.set_gate_probability("Z", 0.0)?;
+        assert!(noise.get_channels("Z", &op).is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,372 @@
+use crate::errors::CircuitError;
+use crate::pipeline::{
+    get_opfns_and_frontier, get_required_state_size_from_frontier, StateModifier, StateModifierType,
+};
+use crate::state_ops::{get_index, make_local_op_matrix, num_indices, UnitaryOp};
+use crate::{Complex, OpBuilder, Register, UnitaryBuilder};
+use num::{One, Zero};
+
+/// Ops on more qubits than this are never compared against their neighbours.
+const MAX_COMPARED_QUBITS: usize = 3;
+
+/// Pairs of ops on more qubits than this are assumed not to commute.
+const MAX_COMMUTE_QUBITS: usize = 4;
+
+/// A modifier along with the qubits it uses.
+type Entry = (Vec<u64>, StateModifier);
+
+/// Products within this distance of the identity are treated as cancelling.
+const EPSILON: f64 = 1e-10;
+
+/// Rewrite the circuit which produces `r` with adjacent inverse gates removed. Two unitary ops
+/// cancel when their product is the identity and any ops between them on their qubits commute
+/// with them (see `ops_commute`), which covers self-inverse pairs such as `X X`, `H H` and
+/// `CNOT CNOT` along with ops followed by their inverses. Rotations about the same axis (`Rx`,
+/// `Ry`, `Rz` and their controlled versions) are merged into a single rotation in the same way.
+/// Removing a pair can let the ops around it cancel too, so `H X X H` is removed entirely.
+///
+/// Measurements and channels are kept unchanged and block cancellation across them. Debug ops are
+/// dropped, while classical side channels and parameterized ops produce an error.
+///
+/// The returned Register has the same indices as `r`, followed by any other qubits used by the
+/// circuit.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::optimize::cancel_inverses;
+/// use qip::pipeline::get_opfns_and_frontier;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let q = b.qubit();
+/// let r = b.qubit();
+/// let q = b.hadamard(q);
+/// let (q, r) = b.cnot(q, r);
+/// let (q, r) = b.cnot(q, r);
+/// let q = b.rz(q, 0.3);
+/// let q = b.rz(q, 0.4);
+/// let r = b.merge(vec![q, r])?;
+///
+/// let r = cancel_inverses(&r)?;
+/// let (_, ops) = get_opfns_and_frontier(&r);
+/// let names: Vec<&str> = ops.iter().map(|op| op.name.as_str()).collect();
+/// assert_eq!(names, vec!["H", "Rz"]);
+/// # Ok(())
+/// # }
+/// ```
+pub fn cancel_inverses(r: &Register) -> Result<Register, CircuitError> {
+    let (n, modifiers) = copy_modifiers(r)?;
+
+    let mut kept: Vec<Option<Entry>> = vec![];
+    modifiers.into_iter().for_each(|(indices, modifier)| {
+        // Look back through earlier ops on these qubits, stepping over those which commute.
+        for p in (0..kept.len()).rev() {
+            let (prev_indices, prev) = match &kept[p] {
+                Some(entry) => entry,
+                None => continue,
+            };
+            if !prev_indices.iter().any(|indx| indices.contains(indx)) {
+                continue;
+            }
+            match combine(prev_indices, prev, &indices, &modifier) {
+                Combined::Cancelled => {
+                    kept[p] = None;
+                    return;
+                }
+                Combined::Merged(op) => {
+                    let name = prev.name.clone();
+                    kept[p] = Some((indices, StateModifier::new_unitary(name, op)));
+                    return;
+                }
+                Combined::Kept => {}
+            }
+            if !modifiers_commute(prev, &modifier) {
+                break;
+            }
+        }
+        kept.push(Some((indices, modifier)));
+    });
+    rebuild(n, r, kept.into_iter().flatten())
+}
+
+/// Rewrite the circuit which produces `r` with each op moved as early as the ops before it allow,
+/// so that ops which commute with their predecessors (see `ops_commute`) can share a layer with
+/// them. This reduces the depth of the circuit, and can bring together gates which other passes
+/// may then cancel or fuse.
+///
+/// Measurements and channels are kept in order with every op on their qubits. As for
+/// `cancel_inverses` debug ops are dropped, and classical side channels and parameterized ops
+/// produce an error.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::circuit_stats::CircuitStats;
+/// use qip::optimize::reorder_commuting;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let q = b.qubit();
+/// let r = b.qubit();
+/// let q = b.hadamard(q);
+/// let q = b.t(q);
+/// let (q, r) = b.cnot(q, r);
+/// let r = b.not(r);
+/// let r = b.merge(vec![q, r])?;
+///
+/// // The not commutes with the cnot on its target, so can run alongside the first gates.
+/// assert_eq!(CircuitStats::new(&r).depth, 4);
+/// let r = reorder_commuting(&r)?;
+/// assert_eq!(CircuitStats::new(&r).depth, 3);
+/// # Ok(())
+/// # }
+/// ```
+pub fn reorder_commuting(r: &Register) -> Result<Register, CircuitError> {
+    let (n, modifiers) = copy_modifiers(r)?;
+
+    let mut layers: Vec<Vec<Entry>> = vec![];
+    modifiers.into_iter().for_each(|(indices, modifier)| {
+        let shares = |entry: &Entry| entry.0.iter().any(|indx| indices.contains(indx));
+        // Go after the last layer holding an op which must stay before this one.
+        let mut layer = layers
+            .iter()
+            .rposition(|layer| {
+                layer
+                    .iter()
+                    .any(|entry| shares(entry) && !modifiers_commute(&entry.1, &modifier))
+            })
+            .map(|l| l + 1)
+            .unwrap_or(0);
+        while layer < layers.len() && layers[layer].iter().any(&shares) {
+            layer += 1;
+        }
+        if layer == layers.len() {
+            layers.push(vec![]);
+        }
+        layers[layer].push((indices, modifier));
+    });
+    rebuild(n, r, layers.into_iter().flatten())
+}
+
+/// Check whether two unitary ops commute. Ops on separate qubits and diagonal ops (such as phases
+/// and Rz, including controlled versions of them) always commute, otherwise ops on at most
+/// four qubits between them are compared by their matrices. Larger ops are assumed not to commute.
+///
+/// # Example
+/// ```
+/// use qip::optimize::ops_commute;
+/// use qip::state_ops::{from_reals, UnitaryOp};
+///
+/// let x = from_reals(&[0.0, 1.0, 1.0, 0.0]);
+/// let z = from_reals(&[1.0, 0.0, 0.0, -1.0]);
+/// let cnot = UnitaryOp::Control(vec![0], vec![1], Box::new(UnitaryOp::Matrix(vec![1], x.clone())));
+///
+/// // X on the target and Z on the control commute with the cnot, but not the other way around.
+/// assert!(ops_commute(&cnot, &UnitaryOp::Matrix(vec![1], x.clone())));
+/// assert!(ops_commute(&cnot, &UnitaryOp::Matrix(vec![0], z.clone())));
+/// assert!(!ops_commute(&cnot, &UnitaryOp::Matrix(vec![0], x)));
+/// assert!(!ops_commute(&cnot, &UnitaryOp::Matrix(vec![1], z)));
+/// ```
+pub fn ops_commute(a: &UnitaryOp, b: &UnitaryOp) -> bool {
+    let a_indices = op_indices(a);
+    let b_indices = op_indices(b);
+    if !a_indices.iter().any(|indx| b_indices.contains(indx)) {
+        return true;
+    }
+    if is_diagonal(a) && is_diagonal(b) {
+        return true;
+    }
+    let mut indices = a_indices;
+    b_indices.into_iter().for_each(|indx| {
+        if !indices.contains(&indx) {
+            indices.push(indx)
+        }
+    });
+    if indices.len() > MAX_COMMUTE_QUBITS {
+        return false;
+    }
+    let a = make_local_op_matrix(a, &indices);
+    let b = make_local_op_matrix(b, &indices);
+    let size = 1 << indices.len();
+    (0..size * size).all(|i| {
+        let (row, col) = (i / size, i % size);
+        let (ab, ba) = (0..size).fold(
+            (Complex::<f64>::zero(), Complex::<f64>::zero()),
+            |(ab, ba), k| {
+                (
+                    ab + a[row * size + k] * b[k * size + col],
+                    ba + b[row * size + k] * a[k * size + col],
+                )
+            },
+        );
+        (ab - ba).norm() < EPSILON
+    })
+}
+
+/// Copy the modifiers of the circuit which produces `r` along with the qubits each one uses,
+/// returning the number of qubits in the circuit.
+fn copy_modifiers(r: &Register) -> Result<(u64, Vec<Entry>), CircuitError> {
+    let (frontier, ops) = get_opfns_and_frontier(r);
+    let n = get_required_state_size_from_frontier(&frontier);
+    let modifiers = ops
+        .into_iter()
+        .filter(|modifier| !matches!(&modifier.modifier, StateModifierType::Debug(_, _)))
+        .map(|modifier| match &modifier.modifier {
+            StateModifierType::SideChannelModifiers(_, _) => {
+                CircuitError::make_str_err("Classical side channels cannot be optimized")
+            }
+            StateModifierType::ParameterizedOp(_, _) => {
+                CircuitError::make_str_err("Parameterized ops cannot be optimized")
+            }
+            StateModifierType::Loop(_, _) => {
+                CircuitError::make_str_err("Classical loops cannot be optimized")
+            }
+            _ => match (modifier.indices(), modifier.try_clone()) {
+                (Some(indices), Some(modifier)) => Ok((indices, modifier)),
+                _ => {
+                    let message = format!("Op {:?} cannot be optimized", modifier.name);
+                    CircuitError::make_err(message)
+                }
+            },
+        })
+        .collect::<Result<Vec<_>, CircuitError>>()?;
+    Ok((n, modifiers))
+}
+
+/// Build a new circuit on `n` qubits from `entries`, returning a Register ordered like `r`.
+fn rebuild<It: IntoIterator<Item = Entry>>(
+    n: u64,
+    r: &Register,
+    entries: It,
+) -> Result<Register, CircuitError> {
+    let mut b = OpBuilder::new();
+    let reg = b.register(n)?;
+    let reg = entries
+        .into_iter()
+        .try_fold(reg, |reg, (indices, modifier)| {
+            b.apply_modifier_to_indices(reg, &indices, modifier)
+        })?;
+    match b.split_absolute(reg, &r.indices)? {
+        (r, Some(rest)) => b.merge(vec![r, rest]),
+        (r, None) => Ok(r),
+    }
+}
+
+/// Check whether two modifiers may be swapped, only unitary ops ever commute.
+fn modifiers_commute(a: &StateModifier, b: &StateModifier) -> bool {
+    match (&a.modifier, &b.modifier) {
+        (StateModifierType::UnitaryOp(a), StateModifierType::UnitaryOp(b)) => ops_commute(a, b),
+        _ => false,
+    }
+}
+
+fn op_indices(op: &UnitaryOp) -> Vec<u64> {
+    (0..num_indices(op)).map(|i| get_index(op, i)).collect()
+}
+
+fn is_diagonal(op: &UnitaryOp) -> bool {
+    match op {
+        UnitaryOp::Matrix(indices, mat) => {
+            let size = 1 << indices.len();
+            (0..size * size).all(|i| i / size == i % size || mat[i].norm() < EPSILON)
+        }
+        UnitaryOp::SparseMatrix(_, rows) => rows.iter().enumerate().all(|(row, entries)| {
+            entries
+                .iter()
+                .all(|(col, c)| *col == row as u64 || c.norm() < EPSILON)
+        }),
+        UnitaryOp::Control(_, _, op) => is_diagonal(op),
+        _ => false,
+    }
+}
+
+/// The result of applying one modifier directly after another.
+enum Combined {
+    /// The two modifiers cancel.
+    Cancelled,
+    /// The two modifiers are equivalent to a single op.
+    Merged(UnitaryOp),
+    /// Both modifiers must be kept.
+    Kept,
+}
+
+fn combine(
+    prev_indices: &[u64],
+    prev: &StateModifier,
+    indices: &[u64],
+    modifier: &StateModifier,
+) -> Combined {
+    let (prev_op, op) = match (&prev.modifier, &modifier.modifier) {
+        (StateModifierType::UnitaryOp(a), StateModifierType::UnitaryOp(b)) => (a, b),
+        _ => return Combined::Kept,
+    };
+    if prev_indices.len() != indices.len()
+        || indices.len() > MAX_COMPARED_QUBITS
+        || !indices.iter().all(|indx| prev_indices.contains(indx))
+    {
+        return Combined::Kept;
+    }
+
+    let merged = match (rotation_name(&prev.name), rotation_name(&modifier.name)) {
+        (Some(a), Some(b)) if a == b => merge_rotations(prev_op, op),
+        _ => None,
+    };
+    let a = make_local_op_matrix(prev_op, indices);
+    let b = make_local_op_matrix(op, indices);
+    let size = 1 << indices.len();
+    let is_identity = (0..size * size).all(|i| {
+        let (row, col) = (i / size, i % size);
+        let c: Complex<f64> = (0..size).fold(Complex::zero(), |acc, k| {
+            acc + b[row * size + k] * a[k * size + col]
+        });
+        let expected = if row == col {
+            Complex::one()
+        } else {
+            Complex::zero()
+        };
+        (c - expected).norm() < EPSILON
+    });
+    match (is_identity, merged) {
+        (true, _) => Combined::Cancelled,
+        (false, Some(op)) => Combined::Merged(op),
+        (false, None) => Combined::Kept,
+    }
+}
+
+/// Get the name of a rotation op without any scopes, or None if `name` is not a rotation.
+fn rotation_name(name: &str) -> Option<&str> {
+    let name = name.rsplit('/').next().unwrap_or(name);
+    let mut base = name;
+    while base.starts_with("C(") && base.ends_with(')') {
+        base = &base[2..base.len() - 1];
+    }
+    match base {
+        "Rx" | "Ry" | "Rz" => Some(name),
+        _ => None,
+    }
+}
+
+/// Multiply two single qubit rotations with the same controls, `b` applied after `a`.
+fn merge_rotations(a: &UnitaryOp, b: &UnitaryOp) -> Option<UnitaryOp> {
+    match (a, b) {
+        (UnitaryOp::Matrix(a_indices, a_mat), UnitaryOp::Matrix(b_indices, b_mat))
+            if a_indices.len() == 1 && a_indices == b_indices =>
+        {
+            let mat = (0..4)
+                .map(|i| {
+                    let (row, col) = (i / 2, i % 2);
+                    b_mat[row * 2] * a_mat[col] + b_mat[row * 2 + 1] * a_mat[2 + col]
+                })
+                .collect();
+            Some(UnitaryOp::Matrix(a_indices.clone(), mat))
+        }
+        (UnitaryOp::Control(a_c, a_o, a_op), UnitaryOp::Control(b_c, b_o, b_op))
+            if a_c == b_c && a_o == b_o =>
+        {
+            let op = merge_rotations(a_op, b_op)?;
+            Some(UnitaryOp::Control(a_c.clone(), a_o.clone(), Box::new(op)))
+        }
+        _ => None,
+    }
+}
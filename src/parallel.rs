@@ -0,0 +1,100 @@
+//! The parts of rayon used by the crate. With the `parallel` feature these are rayon's own, without
+//! it they are single threaded stand ins with the same names, so the library can be built for
+//! targets without threads.
+
+#[cfg(feature = "parallel")]
+pub(crate) use rayon::{prelude, ThreadPool, ThreadPoolBuilder};
+
+#[cfg(not(feature = "parallel"))]
+pub(crate) use self::sequential::{prelude, ThreadPool, ThreadPoolBuilder};
+
+#[cfg(not(feature = "parallel"))]
+mod sequential {
+    /// Iterators which run on the current thread in place of rayon's parallel iterators.
+    pub(crate) mod prelude {
+        /// Stand in for `rayon::iter::IntoParallelIterator`.
+        pub(crate) trait IntoParallelIterator: IntoIterator + Sized {
+            fn into_par_iter(self) -> Self::IntoIter {
+                self.into_iter()
+            }
+        }
+
+        impl<T: IntoIterator> IntoParallelIterator for T {}
+
+        /// Stand in for `rayon::iter::IntoParallelRefIterator`.
+        pub(crate) trait IntoParallelRefIterator<'a> {
+            type Iter: Iterator;
+            fn par_iter(&'a self) -> Self::Iter;
+        }
+
+        impl<'a, T: 'a + ?Sized> IntoParallelRefIterator<'a> for T
+        where
+            &'a T: IntoIterator,
+        {
+            type Iter = <&'a T as IntoIterator>::IntoIter;
+            fn par_iter(&'a self) -> Self::Iter {
+                self.into_iter()
+            }
+        }
+
+        /// Stand in for `rayon::iter::IntoParallelRefMutIterator`.
+        pub(crate) trait IntoParallelRefMutIterator<'a> {
+            type Iter: Iterator;
+            fn par_iter_mut(&'a mut self) -> Self::Iter;
+        }
+
+        impl<'a, T: 'a + ?Sized> IntoParallelRefMutIterator<'a> for T
+        where
+            &'a mut T: IntoIterator,
+        {
+            type Iter = <&'a mut T as IntoIterator>::IntoIter;
+            fn par_iter_mut(&'a mut self) -> Self::Iter {
+                self.into_iter()
+            }
+        }
+
+        /// Stand in for `rayon::slice::ParallelSliceMut`.
+        pub(crate) trait ParallelSliceMut<T> {
+            fn par_chunks_mut(&mut self, chunk_size: usize) -> std::slice::ChunksMut<'_, T>;
+            fn par_sort_by_key<K: Ord, F: FnMut(&T) -> K>(&mut self, f: F);
+        }
+
+        impl<T> ParallelSliceMut<T> for [T] {
+            fn par_chunks_mut(&mut self, chunk_size: usize) -> std::slice::ChunksMut<'_, T> {
+                self.chunks_mut(chunk_size)
+            }
+
+            fn par_sort_by_key<K: Ord, F: FnMut(&T) -> K>(&mut self, f: F) {
+                self.sort_by_key(f)
+            }
+        }
+    }
+
+    /// Stand in for `rayon::ThreadPool` which runs everything on the current thread.
+    #[derive(Debug)]
+    pub(crate) struct ThreadPool;
+
+    impl ThreadPool {
+        pub(crate) fn install<T, F: FnOnce() -> T>(&self, f: F) -> T {
+            f()
+        }
+    }
+
+    /// Stand in for `rayon::ThreadPoolBuilder`.
+    #[derive(Debug, Default)]
+    pub(crate) struct ThreadPoolBuilder;
+
+    impl ThreadPoolBuilder {
+        pub(crate) fn new() -> Self {
+            ThreadPoolBuilder
+        }
+
+        pub(crate) fn num_threads(self, _: usize) -> Self {
+            self
+        }
+
+        pub(crate) fn build(self) -> Result<ThreadPool, std::convert::Infallible> {
+            Ok(ThreadPool)
+        }
+    }
+}
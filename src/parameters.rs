@@ -0,0 +1,165 @@
+use crate::errors::CircuitError;
+use crate::pipeline::{
+    get_opfns_and_frontier, get_required_state_size_from_frontier, run_ops_with_parameters,
+    LocalQuantumState, StateModifier, StateModifierType,
+};
+use crate::{Complex, QuantumState, Register};
+use std::collections::HashMap;
+
+/// A function which maps the value of a parameter to the matrix of an op.
+pub type ParameterizedMatFn = dyn Fn(f64) -> Vec<Complex<f64>>;
+
+/// A named symbolic parameter for an op, the value is only given when the circuit is run. This
+/// allows a circuit to be built once and run for many different parameter values.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Parameter(pub String);
+
+impl Parameter {
+    /// Make a new parameter with the name `name`.
+    pub fn new(name: &str) -> Parameter {
+        Parameter(name.to_string())
+    }
+
+    /// Get the name of the parameter.
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Get the parameters used in the circuit which produces `r`, in the order they are first used.
+pub fn get_parameters(r: &Register) -> Vec<Parameter> {
+    let (_, ops) = get_opfns_and_frontier(r);
+    ops.into_iter().fold(vec![], |mut acc, modifier| {
+        add_parameters(&mut acc, modifier);
+        acc
+    })
+}
+
+fn add_parameters(params: &mut Vec<Parameter>, modifier: &StateModifier) {
+    match &modifier.modifier {
+        StateModifierType::ParameterizedOp(param, _) if !params.contains(param) => {
+            params.push(param.clone())
+        }
+        StateModifierType::Subcircuit(modifiers) => modifiers
+            .iter()
+            .for_each(|modifier| add_parameters(params, modifier)),
+        StateModifierType::Loop(body, _) => body.free_parameters().into_iter().for_each(|param| {
+            if !params.contains(&param) {
+                params.push(param)
+            }
+        }),
+        _ => {}
+    }
+}
+
+/// Get the expectation value of `observable` for the state produced by the circuit for `r`, using
+/// `params` for the values of any `Parameter`s by name. The observable is a weighted sum of pauli
+/// strings such as `[(0.5, "ZZ"), (1.0, "XI")]`, where the character at position `i` acts on
+/// qubit `i`.
+pub fn expectation(
+    r: &Register,
+    observable: &[(f64, &str)],
+    params: &HashMap<String, f64>,
+) -> Result<f64, CircuitError> {
+    let (frontier, ops) = get_opfns_and_frontier(r);
+    let n = get_required_state_size_from_frontier(&frontier);
+    ops_expectation(n, &ops, observable, params)
+}
+
+/// Get the gradient of the expectation value of `observable` (see `expectation`) with respect to
+/// each parameter in the circuit for `r`, evaluated at `params`. The gradient is computed with the
+/// parameter-shift rule, running the circuit twice for each use of each parameter. Parameters
+/// used by several ops have the contribution of each op summed.
+///
+/// The rule is exact for ops of the form `exp(-i theta G)` where `G` has eigenvalues `+-1/2`,
+/// such as those made by `rx_param`, `ry_param`, and `rz_param`. Conditioned rotations do not
+/// have this form. Ops built inside classical side channels are not differentiated, and returns
+/// an error if a parameter other than the loop variable is used inside a loop.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::parameters::{gradient, Parameter};
+/// use std::collections::HashMap;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let q = b.qubit();
+/// let q = b.ry_param(q, &Parameter::new("theta"))?;
+///
+/// // <Z> = cos(theta)
+/// let mut params = HashMap::new();
+/// params.insert("theta".to_string(), 0.3);
+/// let grads = gradient(&q, &[(1.0, "Z")], &params)?;
+/// assert!((grads["theta"] + 0.3f64.sin()).abs() < 1e-10);
+/// # Ok(())
+/// # }
+/// ```
+pub fn gradient(
+    r: &Register,
+    observable: &[(f64, &str)],
+    params: &HashMap<String, f64>,
+) -> Result<HashMap<String, f64>, CircuitError> {
+    let (frontier, ops) = get_opfns_and_frontier(r);
+    let n = get_required_state_size_from_frontier(&frontier);
+    let ops = ops.into_iter().fold(vec![], |mut acc, modifier| {
+        flatten_modifier(&mut acc, modifier);
+        acc
+    });
+    let in_loop = ops.iter().find_map(|modifier| match &modifier.modifier {
+        StateModifierType::Loop(body, _) => body.free_parameters().into_iter().next(),
+        _ => None,
+    });
+    if let Some(param) = in_loop {
+        let message = format!(
+            "Parameter {:?} is used inside a loop and cannot be differentiated",
+            param.name()
+        );
+        return CircuitError::make_err(message);
+    }
+
+    let shift = std::f64::consts::FRAC_PI_2;
+    let mut grads = HashMap::new();
+    ops.iter()
+        .enumerate()
+        .try_for_each(|(i, modifier)| -> Result<(), CircuitError> {
+            if let StateModifierType::ParameterizedOp(param, f) = &modifier.modifier {
+                let value = params.get(param.name()).ok_or_else(|| {
+                    CircuitError::new(format!("Parameter {:?} not bound", param.name()))
+                })?;
+                let shifted_expectation = |delta: f64| -> Result<f64, CircuitError> {
+                    let shifted =
+                        StateModifier::new_unitary(modifier.name.clone(), f(value + delta)?);
+                    let mut shifted_ops = ops.clone();
+                    shifted_ops[i] = &shifted;
+                    ops_expectation(n, &shifted_ops, observable, params)
+                };
+                let grad = (shifted_expectation(shift)? - shifted_expectation(-shift)?) / 2.0;
+                *grads.entry(param.name().to_string()).or_insert(0.0) += grad;
+            }
+            Ok(())
+        })?;
+    Ok(grads)
+}
+
+/// Add `modifier` to `ops`, replacing subcircuits with the modifiers they contain.
+fn flatten_modifier<'a>(ops: &mut Vec<&'a StateModifier>, modifier: &'a StateModifier) {
+    match &modifier.modifier {
+        StateModifierType::Subcircuit(modifiers) => modifiers
+            .iter()
+            .for_each(|modifier| flatten_modifier(ops, modifier)),
+        _ => ops.push(modifier),
+    }
+}
+
+fn ops_expectation(
+    n: u64,
+    ops: &[&StateModifier],
+    observable: &[(f64, &str)],
+    params: &HashMap<String, f64>,
+) -> Result<f64, CircuitError> {
+    let (state, _) = run_ops_with_parameters(ops, LocalQuantumState::<f64>::new(n), params)?;
+    observable.iter().try_fold(0.0, |acc, (weight, pauli)| {
+        Ok(acc + weight * state.pauli_expectation(pauli)?)
+    })
+}
@@ -1,15 +1,21 @@
-extern crate rayon;
-
+use std::cell::RefCell;
 use std::cmp::{max, Ordering};
 use std::collections::HashMap;
 use std::collections::{BinaryHeap, VecDeque};
 
-use rayon::prelude::*;
+use crate::parallel::prelude::*;
+use crate::parallel::{ThreadPool, ThreadPoolBuilder};
 
 use crate::errors::CircuitError;
+use crate::fusion::fuse_modifiers;
+use crate::loops::ClassicalLoop;
 use crate::measurement_ops::{
-    measure, measure_prob, measure_probs, prob_magnitude, soft_measure, MeasuredCondition,
+    measure, measure_prob, measure_probs, pauli_expectation, prob_magnitude, soft_measure,
+    MeasuredCondition,
 };
+use crate::named_gates::GateDefinition;
+use crate::noise::NoiseModel;
+use crate::parameters::Parameter;
 use crate::qubits::Parent;
 use crate::state_ops::*;
 use crate::utils::flip_bits;
@@ -17,11 +23,15 @@ use crate::*;
 use num::{One, Zero};
 use std::fmt;
 use std::rc::Rc;
+use std::sync::Arc;
 
 /// A functions which maps measured values to a series of StateModifiers which will be applied to
 /// the state.
 pub type SideChannelModifierFn = dyn Fn(&[u64]) -> Result<Vec<StateModifier>, CircuitError>;
 
+/// A function which maps the value of a parameter to the op which is applied to the state.
+pub type ParameterizedOpFn = dyn Fn(f64) -> Result<UnitaryOp, CircuitError>;
+
 /// The set of ways to modify a QuantumState
 pub enum StateModifierType {
     /// Ops such as matrices, swaps, and conditions
@@ -34,6 +44,18 @@ pub enum StateModifierType {
     SideChannelModifiers(Vec<MeasurementHandle>, Box<SideChannelModifierFn>),
     /// Debugging op
     Debug(Vec<Vec<u64>>, Box<dyn Fn(Vec<Vec<f64>>) -> ()>),
+    /// A quantum channel given by a set of kraus operators on the indices.
+    Channel(Vec<u64>, Vec<Vec<Complex<f64>>>),
+    /// A sequence of modifiers applied in order, shared between each place it is used.
+    Subcircuit(Rc<Vec<StateModifier>>),
+    /// Ops which depend on the value of a parameter given when the circuit is run.
+    ParameterizedOp(Parameter, Box<ParameterizedOpFn>),
+    /// A named gate applied to the indices, qubit `i` of the definition is `indices[i]`. The
+    /// definition is shared between each place the gate is used.
+    Gate(Rc<GateDefinition>, Vec<u64>),
+    /// A loop applied to the indices, qubit `i` of the body is `indices[i]`. The body is shared and
+    /// only unrolled when run.
+    Loop(Rc<ClassicalLoop>, Vec<u64>),
 }
 
 impl fmt::Debug for StateModifierType {
@@ -65,6 +87,28 @@ impl fmt::Debug for StateModifierType {
                 write!(f, "SideChannelModifiers[{:?}]", handle)
             }
             StateModifierType::Debug(indices, _) => write!(f, "Debug[{:?}]", indices),
+            StateModifierType::Channel(indices, kraus_ops) => write!(
+                f,
+                "Channel[{:?}, {:?} kraus ops]",
+                to_strs(indices),
+                kraus_ops.len()
+            ),
+            StateModifierType::Subcircuit(modifiers) => {
+                write!(f, "Subcircuit[{:?} modifiers]", modifiers.len())
+            }
+            StateModifierType::ParameterizedOp(param, _) => {
+                write!(f, "ParameterizedOp[{:?}]", param.name())
+            }
+            StateModifierType::Gate(def, indices) => {
+                write!(f, "Gate[{:?}, {:?}]", def.name(), to_strs(indices))
+            }
+            StateModifierType::Loop(body, indices) => write!(
+                f,
+                "Loop[{:?} in {:?}, {:?}]",
+                body.variable().name(),
+                body.range(),
+                to_strs(indices)
+            ),
         }
     }
 }
@@ -139,6 +183,59 @@ impl StateModifier {
         }
     }
 
+    /// Create a new channel state modifier which applies the kraus operators `kraus_ops` to
+    /// `indices`.
+    pub fn new_channel(
+        name: String,
+        indices: Vec<u64>,
+        kraus_ops: Vec<Vec<Complex<f64>>>,
+    ) -> StateModifier {
+        StateModifier {
+            name,
+            modifier: StateModifierType::Channel(indices, kraus_ops),
+        }
+    }
+
+    /// Create a new subcircuit state modifier which applies each of `modifiers` in order. The
+    /// modifiers are shared with any other subcircuit made from the same `Rc`.
+    pub fn new_subcircuit(name: String, modifiers: Rc<Vec<StateModifier>>) -> StateModifier {
+        StateModifier {
+            name,
+            modifier: StateModifierType::Subcircuit(modifiers),
+        }
+    }
+
+    /// Create a new state modifier which applies the named gate `def` to `indices`, sharing the
+    /// definition with every other application of the gate.
+    pub fn new_gate(name: String, def: Rc<GateDefinition>, indices: Vec<u64>) -> StateModifier {
+        StateModifier {
+            name,
+            modifier: StateModifierType::Gate(def, indices),
+        }
+    }
+
+    /// Create a new state modifier which applies the loop `body` to `indices`, sharing the body
+    /// between every iteration.
+    pub fn new_loop(name: String, body: Rc<ClassicalLoop>, indices: Vec<u64>) -> StateModifier {
+        StateModifier {
+            name,
+            modifier: StateModifierType::Loop(body, indices),
+        }
+    }
+
+    /// Create a new parameterized state modifier which applies the op given by `f` for the value
+    /// of `param`.
+    pub fn new_parameterized(
+        name: String,
+        param: Parameter,
+        f: Box<ParameterizedOpFn>,
+    ) -> StateModifier {
+        StateModifier {
+            name,
+            modifier: StateModifierType::ParameterizedOp(param, f),
+        }
+    }
+
     /// Create a new debug state modifier (which doesn't modify the state).
     pub fn new_debug(
         name: String,
@@ -150,6 +247,71 @@ impl StateModifier {
             modifier: StateModifierType::Debug(indices, f),
         }
     }
+
+    /// Copy the modifier if it only holds data (unitary ops, measurements, channels, and shared
+    /// subcircuits and gates) rather than functions.
+    pub(crate) fn try_clone(&self) -> Option<StateModifier> {
+        let modifier = match &self.modifier {
+            StateModifierType::UnitaryOp(op) => StateModifierType::UnitaryOp(op.clone()),
+            StateModifierType::MeasureState(id, indices, angle) => {
+                StateModifierType::MeasureState(*id, indices.clone(), *angle)
+            }
+            StateModifierType::StochasticMeasureState(id, indices, angle) => {
+                StateModifierType::StochasticMeasureState(*id, indices.clone(), *angle)
+            }
+            StateModifierType::Channel(indices, kraus_ops) => {
+                StateModifierType::Channel(indices.clone(), kraus_ops.clone())
+            }
+            StateModifierType::Subcircuit(modifiers) => {
+                StateModifierType::Subcircuit(modifiers.clone())
+            }
+            StateModifierType::Gate(def, indices) => {
+                StateModifierType::Gate(def.clone(), indices.clone())
+            }
+            StateModifierType::Loop(body, indices) => {
+                StateModifierType::Loop(body.clone(), indices.clone())
+            }
+            _ => return None,
+        };
+        Some(StateModifier {
+            name: self.name.clone(),
+            modifier,
+        })
+    }
+
+    /// Get the qubits used by the modifier, or None for side channels whose ops are only known
+    /// when run.
+    pub(crate) fn indices(&self) -> Option<Vec<u64>> {
+        let op_indices = |op: &UnitaryOp| -> Vec<u64> {
+            (0..num_indices(op)).map(|i| get_index(op, i)).collect()
+        };
+        let mut indices = match &self.modifier {
+            StateModifierType::UnitaryOp(op) => op_indices(op),
+            StateModifierType::MeasureState(_, indices, _)
+            | StateModifierType::StochasticMeasureState(_, indices, _)
+            | StateModifierType::Channel(indices, _)
+            | StateModifierType::Gate(_, indices)
+            | StateModifierType::Loop(_, indices) => indices.clone(),
+            StateModifierType::Debug(indices, _) => indices.iter().flatten().cloned().collect(),
+            StateModifierType::Subcircuit(modifiers) => modifiers
+                .iter()
+                .map(|m| m.indices())
+                .collect::<Option<Vec<_>>>()?
+                .into_iter()
+                .flatten()
+                .collect(),
+            // The qubits an op acts on don't depend on the parameter value.
+            StateModifierType::ParameterizedOp(_, f) => op_indices(&f(0.0).ok()?),
+            StateModifierType::SideChannelModifiers(_, _) => return None,
+        };
+        let mut seen = vec![];
+        indices.retain(|indx| {
+            let new = !seen.contains(indx);
+            seen.push(*indx);
+            new
+        });
+        Some(indices)
+    }
 }
 
 /// A handle which can be used to retrieve measured values.
@@ -206,7 +368,7 @@ impl PartialOrd for MeasurementHandle {
 }
 
 /// A struct which provides the measured values from the circuit.
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct MeasuredResults<P: Precision> {
     results: HashMap<u64, (u64, P)>,
     stochastic_results: HashMap<u64, Vec<P>>,
@@ -253,6 +415,38 @@ pub trait QuantumState<P: Precision> {
     /// Apply op with a given name. Mutate self using op.
     fn apply_op_with_name(&mut self, name: Option<&str>, op: &UnitaryOp);
 
+    /// Apply the channel `rho -> sum_k K_k rho K_k^dagger` where each `K_k` in `kraus_ops` is a
+    /// matrix on `indices` (same layout as `UnitaryOp::Matrix`). States which cannot represent
+    /// mixtures instead sample a single kraus operator, following a quantum trajectory.
+    /// By default channels are not supported.
+    fn apply_channel(
+        &mut self,
+        _name: Option<&str>,
+        _indices: &[u64],
+        _kraus_ops: &[Vec<Complex<f64>>],
+    ) -> Result<(), CircuitError> {
+        CircuitError::make_str_err("Channels are not supported by this quantum state")
+    }
+
+    /// Get the expectation value `<psi|P|psi>` of a pauli string such as `"XZIY"` without
+    /// modifying the state. The character at position `i` acts on qubit `i`.
+    /// By default pauli expectations are not supported.
+    fn pauli_expectation(&self, _pauli: &str) -> Result<P, CircuitError> {
+        CircuitError::make_str_err("Pauli expectations are not supported by this quantum state")
+    }
+
+    /// Check that the state can apply ops chosen from the results of earlier measurements, such as
+    /// those of side channels. By default it can.
+    fn check_feed_forward(&self) -> Result<(), CircuitError> {
+        Ok(())
+    }
+
+    /// Take the error recorded by an op this state could not apply, if any. This is checked after
+    /// each op of a run, which stops and returns the error. By default every op can be applied.
+    fn take_error(&mut self) -> Option<CircuitError> {
+        None
+    }
+
     /// Mutate self with measurement, return result as index and probability
     fn measure(
         &mut self,
@@ -279,14 +473,47 @@ pub trait QuantumState<P: Precision> {
 }
 
 /// A basic representation of a quantum state, given by a vector of complex numbers stored
-/// locally on the machine (plus an arena of equal size to work in).
+/// locally on the machine (plus an arena of equal size to work in). The arena is only allocated
+/// once an op which can't be applied in place is run, see `apply_op_in_place`.
+///
+/// The amplitudes are stored with precision `P`, ops are converted to `P` as they are applied.
+/// Using `f32` halves the memory used compared to `f64`, at the cost of accuracy:
+/// `run_local::<f32>(&r)`.
 #[derive(Debug)]
 pub struct LocalQuantumState<P: Precision> {
     // A bundle with the quantum state data.
     n: u64,
     state: Vec<Complex<P>>,
     arena: Vec<Complex<P>>,
+    // Whether this state is currently using threads, given by `parallel` and `n`.
     multithread: bool,
+    parallel: ParallelConfig,
+    pool: Option<Arc<ThreadPool>>,
+}
+
+/// Configuration for how a `LocalQuantumState` splits work between threads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParallelConfig {
+    /// Whether to use threads at all, turn this off when running inside of another parallel
+    /// framework.
+    pub multithread: bool,
+    /// States with fewer qubits than this run on a single thread.
+    pub threshold: u64,
+    /// Number of threads to use, `None` shares rayon's global thread pool.
+    pub num_threads: Option<usize>,
+    /// Number of amplitudes handed to each task of the in place kernels.
+    pub chunk_size: usize,
+}
+
+impl Default for ParallelConfig {
+    fn default() -> Self {
+        Self {
+            multithread: true,
+            threshold: 0,
+            num_threads: None,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
 }
 
 impl<P: Precision> LocalQuantumState<P> {
@@ -335,12 +562,16 @@ impl<P: Precision> LocalQuantumState<P> {
             cvec[(delta_index + template) as usize] = val;
         });
 
-        let arena = vec![Complex::zero(); cvec.len()];
         LocalQuantumState {
             n,
             state: cvec,
-            arena,
+            arena: vec![],
             multithread,
+            parallel: ParallelConfig {
+                multithread,
+                ..Default::default()
+            },
+            pool: None,
         }
     }
 
@@ -360,8 +591,6 @@ impl<P: Precision> LocalQuantumState<P> {
             return CircuitError::make_err(message);
         }
 
-        let arena = vec![Complex::zero(); state.len()];
-
         let state = if natural_order {
             let mut state: Vec<_> = state.into_iter().enumerate().collect();
             state.sort_by_key(|(indx, _)| flip_bits(n as usize, *indx as u64));
@@ -373,11 +602,79 @@ impl<P: Precision> LocalQuantumState<P> {
         Ok(LocalQuantumState {
             n,
             state,
-            arena,
+            arena: vec![],
             multithread,
+            parallel: ParallelConfig {
+                multithread,
+                ..Default::default()
+            },
+            pool: None,
         })
     }
 
+    /// Make a new LocalQuantumState in a Haar random state of `n` qubits, uniformly distributed
+    /// over all pure states. Random choices use the generator from `rng::with_seed` when there is
+    /// one.
+    ///
+    /// # Example
+    /// ```
+    /// use qip::*;
+    /// use qip::pipeline::LocalQuantumState;
+    /// use qip::rng::with_seed;
+    /// # fn main() -> Result<(), CircuitError> {
+    ///
+    /// let mut b = OpBuilder::new();
+    /// let r = b.register(3)?;
+    /// let r = b.hadamard(r);
+    ///
+    /// let state = with_seed(1, || LocalQuantumState::<f64>::haar_random(3));
+    /// let (state, _) = run_with_state(&r, state)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn haar_random(n: u64) -> LocalQuantumState<P> {
+        // Gaussian amplitudes are invariant under unitaries, so normalizing them gives the Haar
+        // measure.
+        let state: Vec<Complex<f64>> = (0..1u64 << n)
+            .map(|_| Complex::new(rng::gaussian(), rng::gaussian()))
+            .collect();
+        Self::from_unnormalized(n, state, false)
+    }
+
+    /// Make a new LocalQuantumState of `n` qubits in a product of Haar random single qubit
+    /// states. Random choices use the generator from `rng::with_seed` when there is one.
+    pub fn random_product_state(n: u64) -> LocalQuantumState<P> {
+        // Qubit i is bit i of the natural order index.
+        let state = (0..n).fold(vec![Complex::one()], |state: Vec<Complex<f64>>, _| {
+            let qubit = [
+                Complex::new(rng::gaussian(), rng::gaussian()),
+                Complex::new(rng::gaussian(), rng::gaussian()),
+            ];
+            qubit
+                .iter()
+                .flat_map(|a| state.iter().map(move |s| a * s))
+                .collect()
+        });
+        Self::from_unnormalized(n, state, true)
+    }
+
+    fn from_unnormalized(
+        n: u64,
+        state: Vec<Complex<f64>>,
+        natural_order: bool,
+    ) -> LocalQuantumState<P> {
+        let norm = state.iter().map(|c| c.norm_sqr()).sum::<f64>().sqrt();
+        let state = state
+            .into_iter()
+            .map(|c| {
+                let c = c / norm;
+                Complex::new(P::from(c.re).unwrap(), P::from(c.im).unwrap())
+            })
+            .collect();
+        // The state has 2^n entries.
+        Self::new_from_full_state(n, state, natural_order, true).unwrap()
+    }
+
     /// Return a reference to the internal state.
     pub fn state_ref(&self) -> &Vec<Complex<P>> {
         &self.state
@@ -391,23 +688,89 @@ impl<P: Precision> LocalQuantumState<P> {
     /// Clone the state in either the `natural_order` or the internal order.
     pub fn clone_state(&mut self, natural_order: bool) -> Vec<Complex<P>> {
         if natural_order {
-            let n = self.n;
-            let state = &self.state;
-            let f = |(i, outputloc): (usize, &mut Complex<P>)| {
-                *outputloc = state[flip_bits(n as usize, i as u64) as usize];
-            };
-
-            if self.multithread {
-                self.arena.par_iter_mut().enumerate().for_each(f);
-            } else {
-                self.arena.iter_mut().enumerate().for_each(f);
-            }
-            self.arena.clone()
+            natural_order_state(self.n, &self.state, self.multithread)
         } else {
             self.state.clone()
         }
     }
 
+    /// Clone the state with qubit `order[i]` as bit `i` of each index, so `order[0]` is the least
+    /// significant bit. `order` must contain each qubit of the state exactly once.
+    ///
+    /// # Example
+    /// ```
+    /// use qip::*;
+    /// # fn main() -> Result<(), CircuitError> {
+    ///
+    /// let mut b = OpBuilder::new();
+    /// let q = b.qubit();
+    /// let r = b.qubit();
+    /// let r = b.not(r);
+    /// let qr = b.merge(vec![q, r])?;
+    ///
+    /// let (state, _) = run_local::<f64>(&qr)?;
+    /// // With qubit 1 as the least significant bit the state is |01>.
+    /// let amplitudes = state.get_state_with_order(&[1, 0])?;
+    /// assert_eq!(amplitudes[0b01], Complex::new(1.0, 0.0));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn get_state_with_order(&self, order: &[u64]) -> Result<Vec<Complex<P>>, CircuitError> {
+        let mut seen = vec![false; self.n as usize];
+        let valid = order.len() == self.n as usize
+            && order
+                .iter()
+                .all(|q| *q < self.n && !std::mem::replace(&mut seen[*q as usize], true));
+        if !valid {
+            let message = format!(
+                "Order {:?} must contain each of the {:?} qubits exactly once",
+                order, self.n
+            );
+            return CircuitError::make_err(message);
+        }
+        let n = self.n;
+        let state = &self.state;
+        let f = |o: usize| {
+            let i = order
+                .iter()
+                .enumerate()
+                .fold(0, |acc, (j, q)| acc | (((o >> j) & 1) << (n - 1 - q)));
+            state[i]
+        };
+        Ok(if self.multithread {
+            (0..state.len()).into_par_iter().map(f).collect()
+        } else {
+            (0..state.len()).map(f).collect()
+        })
+    }
+
+    /// Clone the state with the qubits of `rs` as the bits of each index, the first qubit of the
+    /// first register being the least significant bit. The registers must cover the whole state,
+    /// see `get_state_with_order`.
+    pub fn get_state_for_registers(
+        &self,
+        rs: &[&Register],
+    ) -> Result<Vec<Complex<P>>, CircuitError> {
+        let order: Vec<u64> = rs.iter().flat_map(|r| r.indices.iter().cloned()).collect();
+        self.get_state_with_order(&order)
+    }
+
+    /// Check if the arena used by ops which can't be applied in place has been allocated.
+    pub fn has_arena(&self) -> bool {
+        !self.arena.is_empty()
+    }
+
+    /// Free the arena, it will be allocated again if required by a later op.
+    pub fn release_arena(&mut self) {
+        self.arena = vec![];
+    }
+
+    fn allocate_arena(&mut self) {
+        if self.arena.len() != self.state.len() {
+            self.arena = vec![Complex::zero(); self.state.len()];
+        }
+    }
+
     /// Rotate to a new computational basis:
     /// `|0'> =  cos(angle)|0> + sin(angle)|1>`
     /// `|1'> = -sin(angle)|0> + cos(angle)|1>`
@@ -424,17 +787,155 @@ impl<P: Precision> LocalQuantumState<P> {
 
     /// Set whether the state will use multithreading.
     pub fn set_multithreading(&mut self, multithread: bool) {
-        self.multithread = multithread;
+        self.parallel.multithread = multithread;
+        self.multithread = multithread && self.n >= self.parallel.threshold;
+    }
+
+    /// Set how the state splits work between threads. Builds a new thread pool if
+    /// `config.num_threads` is set.
+    ///
+    /// # Example
+    /// ```
+    /// use qip::*;
+    /// use qip::pipeline::{LocalQuantumState, ParallelConfig};
+    /// # fn main() -> Result<(), CircuitError> {
+    ///
+    /// let mut b = OpBuilder::new();
+    /// let r = b.register(3)?;
+    /// let r = b.hadamard(r);
+    ///
+    /// let mut state = LocalQuantumState::<f64>::new(3);
+    /// state.set_parallel_config(ParallelConfig {
+    ///     threshold: 10,
+    ///     num_threads: Some(2),
+    ///     ..Default::default()
+    /// })?;
+    /// let (state, _) = run_with_state(&r, state)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_parallel_config(&mut self, config: ParallelConfig) -> Result<(), CircuitError> {
+        if config.chunk_size == 0 {
+            return CircuitError::make_str_err("Parallel chunk size must be greater than 0.");
+        }
+        self.pool = match config.num_threads {
+            Some(num_threads) => {
+                let pool = ThreadPoolBuilder::new()
+                    .num_threads(num_threads)
+                    .build()
+                    .map_err(|e| {
+                        CircuitError::new(format!("Could not build thread pool: {}", e))
+                    })?;
+                Some(Arc::new(pool))
+            }
+            None => None,
+        };
+        self.parallel = config;
+        self.multithread = config.multithread && self.n >= config.threshold;
+        Ok(())
+    }
+
+    /// Get how the state splits work between threads.
+    pub fn parallel_config(&self) -> ParallelConfig {
+        self.parallel
+    }
+
+    fn in_pool<T: Send, F: FnOnce(&mut Self) -> T + Send>(&mut self, f: F) -> T {
+        match self.pool.clone() {
+            Some(pool) => in_thread_pool(&pool, || f(self)),
+            None => f(self),
+        }
+    }
+
+    fn in_pool_ref<T: Send, F: FnOnce(&Self) -> T + Send>(&self, f: F) -> T {
+        match &self.pool {
+            Some(pool) => in_thread_pool(pool, || f(self)),
+            None => f(self),
+        }
+    }
+
+    /// Probability that measuring the qubits at `indices` would give `measured` (in the order given
+    /// by `indices`), without changing the state.
+    pub fn probability_of(&self, indices: &[u64], measured: u64) -> P {
+        self.in_pool_ref(|s| measure_prob(s.n, measured, indices, &s.state, None, s.multithread))
+    }
+
+    /// Probabilities of each possible value of the qubits at `indices` (in the order given by
+    /// `indices`), without changing the state.
+    ///
+    /// # Example
+    /// ```
+    /// use qip::*;
+    /// # fn main() -> Result<(), CircuitError> {
+    ///
+    /// let mut b = OpBuilder::new();
+    /// let q = b.qubit();
+    /// let r = b.qubit();
+    /// let q = b.hadamard(q);
+    /// let (q, r) = b.cnot(q, r);
+    /// let r = b.merge(vec![q, r])?;
+    ///
+    /// let (state, _) = run_local::<f64>(&r)?;
+    /// let probs = state.peek_probabilities(&r.indices);
+    /// assert!((probs[0b00] - 0.5).abs() < 1e-10);
+    /// assert!((probs[0b11] - 0.5).abs() < 1e-10);
+    /// assert!((state.probability_of(&[0], 1) - 0.5).abs() < 1e-10);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn peek_probabilities(&self, indices: &[u64]) -> Vec<P> {
+        self.in_pool_ref(|s| measure_probs(s.n, indices, &s.state, None, s.multithread))
+    }
+
+    /// Sample `shots` measurements of the qubits at `indices` without changing the state, returning
+    /// the number of times each measured value (in the order given by `indices`) was seen.
+    pub fn sample_measurements(&self, indices: &[u64], shots: usize) -> HashMap<u64, usize> {
+        sample_from_probs(&self.peek_probabilities(indices), shots)
     }
 }
 
+/// Run `f` in `pool`, carrying over any seeded generator from `rng::with_seed`.
+fn in_thread_pool<T: Send, F: FnOnce() -> T + Send>(pool: &ThreadPool, f: F) -> T {
+    let seeded = rng::replace_rng(None);
+    let (result, seeded) = pool.install(move || {
+        rng::replace_rng(seeded);
+        let result = f();
+        (result, rng::replace_rng(None))
+    });
+    rng::replace_rng(seeded);
+    result
+}
+
+/// Draw `shots` values from the distribution `probs`, returning the number of times each value
+/// was drawn.
+pub(crate) fn sample_from_probs<P: Precision>(probs: &[P], shots: usize) -> HashMap<u64, usize> {
+    let cumulative: Vec<P> = probs
+        .iter()
+        .scan(P::zero(), |acc, p| {
+            *acc = *acc + *p;
+            Some(*acc)
+        })
+        .collect();
+    let total = cumulative.last().cloned().unwrap_or_else(P::zero);
+    let last = cumulative.len() - 1;
+    let mut counts = HashMap::new();
+    (0..shots).for_each(|_| {
+        let r = P::from(rng::random::<f64>()).unwrap() * total;
+        let measured = cumulative.iter().position(|c| r < *c).unwrap_or(last);
+        *counts.entry(measured as u64).or_insert(0) += 1;
+    });
+    counts
+}
+
 impl<P: Precision> Clone for LocalQuantumState<P> {
     fn clone(&self) -> Self {
         LocalQuantumState {
             n: self.n,
             state: self.state.clone(),
-            arena: self.arena.clone(),
+            arena: vec![],
             multithread: self.multithread,
+            parallel: self.parallel,
+            pool: self.pool.clone(),
         }
     }
 }
@@ -468,16 +969,50 @@ impl<P: Precision> QuantumState<P> for LocalQuantumState<P> {
     }
 
     fn apply_op_with_name(&mut self, _name: Option<&str>, op: &UnitaryOp) {
-        apply_op(
-            self.n,
-            op,
-            &self.state,
-            &mut self.arena,
-            0,
-            0,
-            self.multithread,
-        );
-        std::mem::swap(&mut self.state, &mut self.arena);
+        self.in_pool(|s| {
+            // Fall back to writing into the arena for ops which permute the state.
+            let chunk_size = s.parallel.chunk_size;
+            if !apply_op_in_place_with_chunk_size(s.n, op, &mut s.state, s.multithread, chunk_size)
+            {
+                s.allocate_arena();
+                apply_op(s.n, op, &s.state, &mut s.arena, 0, 0, s.multithread);
+                std::mem::swap(&mut s.state, &mut s.arena);
+            }
+        })
+    }
+
+    fn pauli_expectation(&self, pauli: &str) -> Result<P, CircuitError> {
+        self.in_pool_ref(|s| pauli_expectation(s.n, pauli, &s.state, s.multithread))
+    }
+
+    fn apply_channel(
+        &mut self,
+        _name: Option<&str>,
+        indices: &[u64],
+        kraus_ops: &[Vec<Complex<f64>>],
+    ) -> Result<(), CircuitError> {
+        let ops = kraus_ops
+            .iter()
+            .map(|k| make_matrix_op(indices.to_vec(), k.clone()))
+            .collect::<Result<Vec<_>, CircuitError>>()?;
+        self.in_pool(|s| {
+            // Sample kraus operator k with probability |K_k psi|^2
+            s.allocate_arena();
+            let mut r = P::from(rng::random::<f64>()).unwrap() * s.state_magnitude();
+            let last = ops.len() - 1;
+            for (i, op) in ops.iter().enumerate() {
+                apply_op(s.n, op, &s.state, &mut s.arena, 0, 0, s.multithread);
+                let p = prob_magnitude(&s.arena, s.multithread);
+                r = r - p;
+                if (r <= P::zero() || i == last) && !p.is_zero() {
+                    let scale = P::one() / p.sqrt();
+                    s.arena.iter_mut().for_each(|c| *c = *c * scale);
+                    std::mem::swap(&mut s.state, &mut s.arena);
+                    return Ok(());
+                }
+            }
+            CircuitError::make_str_err("Channel has zero probability for the current state")
+        })
     }
 
     fn measure(
@@ -486,65 +1021,75 @@ impl<P: Precision> QuantumState<P> for LocalQuantumState<P> {
         measured: Option<MeasuredCondition<P>>,
         angle: f64,
     ) -> (u64, P) {
-        self.rotate_basis(indices, angle);
-        let measured_result = measure(
-            self.n,
-            indices,
-            &self.state,
-            &mut self.arena,
-            None,
-            measured,
-            self.multithread,
-        );
-        self.rotate_basis(indices, -angle);
+        self.in_pool(|s| {
+            s.rotate_basis(indices, angle);
+            s.allocate_arena();
+            let measured_result = measure(
+                s.n,
+                indices,
+                &s.state,
+                &mut s.arena,
+                None,
+                measured,
+                s.multithread,
+            );
+            s.rotate_basis(indices, -angle);
 
-        std::mem::swap(&mut self.state, &mut self.arena);
-        measured_result
+            std::mem::swap(&mut s.state, &mut s.arena);
+            measured_result
+        })
     }
 
     fn soft_measure(&mut self, indices: &[u64], measured: Option<u64>, angle: f64) -> (u64, P) {
-        self.rotate_basis(indices, angle);
-        let m = if let Some(m) = measured {
-            m
-        } else {
-            soft_measure(self.n, indices, &self.state, None, self.multithread)
-        };
-        let p = measure_prob(self.n, m, indices, &self.state, None, self.multithread);
-        self.rotate_basis(indices, -angle);
-        (m, p)
+        self.in_pool(|s| {
+            s.rotate_basis(indices, angle);
+            let m = if let Some(m) = measured {
+                m
+            } else {
+                soft_measure(s.n, indices, &s.state, None, s.multithread)
+            };
+            let p = measure_prob(s.n, m, indices, &s.state, None, s.multithread);
+            s.rotate_basis(indices, -angle);
+            (m, p)
+        })
     }
 
     fn state_magnitude(&self) -> P {
-        prob_magnitude(&self.state, self.multithread)
+        self.in_pool_ref(|s| prob_magnitude(&s.state, s.multithread))
     }
 
     fn stochastic_measure(&mut self, indices: &[u64], angle: f64) -> Vec<P> {
-        self.rotate_basis(indices, angle);
-        let probs = measure_probs(self.n, indices, &self.state, None, self.multithread);
-        self.rotate_basis(indices, -angle);
-        probs
+        self.in_pool(|s| {
+            s.rotate_basis(indices, angle);
+            let probs = measure_probs(s.n, indices, &s.state, None, s.multithread);
+            s.rotate_basis(indices, -angle);
+            probs
+        })
     }
 
-    fn get_state(mut self, natural_order: bool) -> Vec<Complex<P>> {
+    fn get_state(self, natural_order: bool) -> Vec<Complex<P>> {
         if natural_order {
-            let n = self.n;
-            let state = self.state;
-            let f = |(i, outputloc): (usize, &mut Complex<P>)| {
-                *outputloc = state[flip_bits(n as usize, i as u64) as usize];
-            };
-
-            if self.multithread {
-                self.arena.par_iter_mut().enumerate().for_each(f);
-            } else {
-                self.arena.iter_mut().enumerate().for_each(f);
-            }
-            self.arena
+            self.in_pool_ref(|s| natural_order_state(s.n, &s.state, s.multithread))
         } else {
             self.state
         }
     }
 }
 
+/// Copy `state` into the natural order, where qubit 0 is the least significant bit.
+fn natural_order_state<P: Precision>(
+    n: u64,
+    state: &[Complex<P>],
+    multithread: bool,
+) -> Vec<Complex<P>> {
+    let f = |i: usize| state[flip_bits(n as usize, i as u64) as usize];
+    if multithread {
+        (0..state.len()).into_par_iter().map(f).collect()
+    } else {
+        (0..state.len()).map(f).collect()
+    }
+}
+
 pub(crate) fn create_state_entry<P: Precision>(
     n: u64,
     i: u64,
@@ -583,20 +1128,126 @@ pub(crate) fn create_state_entry<P: Precision>(
     (delta_index, val)
 }
 
+/// Description of an op given to the observer of `run_with_state_and_observer`.
+#[derive(Debug)]
+pub struct ObservedOp<'a> {
+    /// Number of ops observed before this one.
+    pub index: usize,
+    /// Name of the op, including the name scopes it was built in.
+    pub name: &'a str,
+    /// The unitary which was applied, `None` for measurements and channels.
+    pub op: Option<&'a UnitaryOp>,
+}
+
+/// A function called with each `ObservedOp` and the state after it has been applied.
+pub type ObserverFn<'a, QS> = dyn FnMut(&ObservedOp, &QS) + 'a;
+
+/// An observer and the number of ops it has seen.
+struct ObserverState<'a, QS> {
+    f: &'a mut ObserverFn<'a, QS>,
+    count: usize,
+}
+
+/// The `RunOptions` used while the modifiers of a circuit are applied to a state.
+struct RunContext<'a, QS> {
+    noise: Option<&'a NoiseModel>,
+    parameters: Option<&'a HashMap<String, f64>>,
+    observer: Option<&'a RefCell<ObserverState<'a, QS>>>,
+}
+
+impl<'a, QS> Default for RunContext<'a, QS> {
+    fn default() -> Self {
+        RunContext {
+            noise: None,
+            parameters: None,
+            observer: None,
+        }
+    }
+}
+
+impl<'a, QS> Clone for RunContext<'a, QS> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, QS> Copy for RunContext<'a, QS> {}
+
+impl<'a, QS> RunContext<'a, QS> {
+    /// Tell the observer, if any, that the op `name` has been applied to `s`.
+    fn observe(&self, name: &str, op: Option<&UnitaryOp>, s: &QS) {
+        if let Some(observer) = self.observer {
+            let mut observer = observer.borrow_mut();
+            let info = ObservedOp {
+                index: observer.count,
+                name,
+                op,
+            };
+            (observer.f)(&info, s);
+            observer.count += 1;
+        }
+    }
+}
+
+/// Apply `op` to the state `s`, followed by any noise channels from the context.
+fn apply_unitary_op<P: Precision, QS: QuantumState<P>>(
+    ctx: RunContext<QS>,
+    s: &mut QS,
+    name: &str,
+    op: &UnitaryOp,
+) -> Result<(), CircuitError> {
+    s.apply_op_with_name(Some(name), op);
+    if let Some(noise) = ctx.noise {
+        noise
+            .get_channels(name, op)
+            .into_iter()
+            .try_for_each(|(indices, kraus_ops)| {
+                s.apply_channel(Some("noise"), &indices, &kraus_ops)
+            })?;
+    }
+    ctx.observe(name, Some(op), s);
+    Ok(())
+}
+
 /// Apply an QubitOp to the state `s` and return the new state.
 fn fold_modify_state<P: Precision, QS: QuantumState<P>>(
+    ctx: RunContext<QS>,
+    acc: (QS, MeasuredResults<P>),
+    modifier: &StateModifier,
+) -> Result<(QS, MeasuredResults<P>), CircuitError> {
+    let (mut s, mr) = apply_modifier(ctx, acc, modifier)?;
+    match s.take_error() {
+        Some(err) => Err(err),
+        None => Ok((s, mr)),
+    }
+}
+
+fn apply_modifier<P: Precision, QS: QuantumState<P>>(
+    ctx: RunContext<QS>,
     acc: (QS, MeasuredResults<P>),
     modifier: &StateModifier,
 ) -> Result<(QS, MeasuredResults<P>), CircuitError> {
     let (mut s, mut mr) = acc;
     match &modifier.modifier {
         StateModifierType::UnitaryOp(op) => {
-            s.apply_op_with_name(Some(&modifier.name), op);
+            apply_unitary_op(ctx, &mut s, &modifier.name, op)?;
+            Ok((s, mr))
+        }
+        StateModifierType::ParameterizedOp(param, f) => {
+            let value = ctx
+                .parameters
+                .and_then(|params| params.get(param.name()))
+                .ok_or_else(|| {
+                    CircuitError::new(format!("Parameter {:?} not bound", param.name()))
+                })?;
+            let op = f(*value)?;
+            apply_unitary_op(ctx, &mut s, &modifier.name, &op)?;
             Ok((s, mr))
         }
         StateModifierType::MeasureState(id, indices, angle) => {
             let result = s.measure(indices, None, *angle);
             mr.results.insert(id.clone(), result);
+            ctx.observe(&modifier.name, None, &s);
             Ok((s, mr))
         }
         StateModifierType::StochasticMeasureState(id, indices, angle) => {
@@ -605,6 +1256,7 @@ fn fold_modify_state<P: Precision, QS: QuantumState<P>>(
             Ok((s, mr))
         }
         StateModifierType::SideChannelModifiers(handles, f) => {
+            s.check_feed_forward()?;
             let measured_values: Vec<_> = handles
                 .iter()
                 .map(|handle| mr.get_measurement(handle))
@@ -618,7 +1270,9 @@ fn fold_modify_state<P: Precision, QS: QuantumState<P>>(
                 .map(|m| m.map(|(m, _)| m).unwrap())
                 .collect();
             let modifiers = f(&measured_values)?;
-            modifiers.iter().try_fold((s, mr), fold_modify_state)
+            modifiers
+                .iter()
+                .try_fold((s, mr), |acc, m| fold_modify_state(ctx, acc, m))
         }
         StateModifierType::Debug(index_groups, f) => {
             let result = index_groups
@@ -629,6 +1283,22 @@ fn fold_modify_state<P: Precision, QS: QuantumState<P>>(
             f(result);
             Ok((s, mr))
         }
+        StateModifierType::Channel(indices, kraus_ops) => {
+            s.apply_channel(Some(&modifier.name), indices, kraus_ops)?;
+            ctx.observe(&modifier.name, None, &s);
+            Ok((s, mr))
+        }
+        StateModifierType::Subcircuit(modifiers) => modifiers
+            .iter()
+            .try_fold((s, mr), |acc, m| fold_modify_state(ctx, acc, m)),
+        StateModifierType::Gate(def, indices) => def
+            .instantiate(indices)
+            .iter()
+            .try_fold((s, mr), |acc, m| fold_modify_state(ctx, acc, m)),
+        StateModifierType::Loop(body, indices) => body
+            .unroll(indices, ctx.parameters)?
+            .iter()
+            .try_fold((s, mr), |acc, m| fold_modify_state(ctx, acc, m)),
     }
 }
 
@@ -703,22 +1373,270 @@ pub fn run_with_state<P: Precision, QS: QuantumState<P>>(
     r: &Register,
     state: QS,
 ) -> Result<(QS, MeasuredResults<P>), CircuitError> {
-    let (frontier, ops) = get_opfns_and_frontier(r);
+    run_with_options(r, state, RunOptions::default())
+}
 
-    let req_n = get_required_state_size::<P>(&frontier, &[]);
+/// Options for `run_with_options`, which may be combined freely. The default options run the
+/// circuit as it was built, the same as `run_with_state`.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::noise::NoiseModel;
+/// use qip::parameters::Parameter;
+/// use qip::pipeline::{run_with_options, LocalQuantumState, RunOptions};
+/// use std::collections::HashMap;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let q = b.qubit();
+/// let q = b.ry_param(q, &Parameter::new("theta"))?;
+///
+/// let mut params = HashMap::new();
+/// params.insert("theta".to_string(), std::f64::consts::PI);
+/// let noise = NoiseModel::new();
+/// let mut count = 0;
+/// let mut observer = |_: &pipeline::ObservedOp, _: &LocalQuantumState<f64>| count += 1;
+/// let options = RunOptions {
+///     noise: Some(&noise),
+///     parameters: Some(&params),
+///     observer: Some(&mut observer),
+///     ..Default::default()
+/// };
+/// let (state, _) = run_with_options(&q, LocalQuantumState::<f64>::new(1), options)?;
+/// assert!((state.get_state(true)[1].re - 1.0).abs() < 1e-10);
+/// assert_eq!(count, 1);
+/// # Ok(())
+/// # }
+/// ```
+pub struct RunOptions<'a, QS> {
+    /// Channels to insert after each op.
+    pub noise: Option<&'a NoiseModel>,
+    /// Values for any `Parameter`s in the circuit, by name.
+    pub parameters: Option<&'a HashMap<String, f64>>,
+    /// Called with a description of each op and the state after it has been applied.
+    /// Measurements and channels are observed too, with no unitary.
+    pub observer: Option<&'a mut ObserverFn<'a, QS>>,
+    /// Fuse runs of one and two qubit gates into single matrix ops before running, see
+    /// `fusion::fuse_modifiers`.
+    pub fuse: bool,
+}
+
+impl<'a, QS> Default for RunOptions<'a, QS> {
+    fn default() -> Self {
+        RunOptions {
+            noise: None,
+            parameters: None,
+            observer: None,
+            fuse: false,
+        }
+    }
+}
 
+impl<'a, QS> fmt::Debug for RunOptions<'a, QS> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RunOptions")
+            .field("noise", &self.noise)
+            .field("parameters", &self.parameters)
+            .field("observer", &self.observer.is_some())
+            .field("fuse", &self.fuse)
+            .finish()
+    }
+}
+
+/// Run the circuit on `state` with the given `options`. Returns an error if `state` does not have
+/// exactly the number of qubits used by the circuit.
+pub fn run_with_options<P: Precision, QS: QuantumState<P>>(
+    r: &Register,
+    state: QS,
+    options: RunOptions<QS>,
+) -> Result<(QS, MeasuredResults<P>), CircuitError> {
+    let (frontier, ops) = get_opfns_and_frontier(r);
+    let req_n = get_required_state_size::<P>(&frontier, &[]);
     if req_n != state.n() {
         let message = format!(
             "Circuit expected {:?} qubits but state contained {:?}",
             req_n,
             state.n()
         );
-        CircuitError::make_err(message)
+        return CircuitError::make_err(message);
+    }
+
+    let observer = options
+        .observer
+        .map(|f| RefCell::new(ObserverState { f, count: 0 }));
+    let ctx = RunContext {
+        noise: options.noise,
+        parameters: options.parameters,
+        observer: observer.as_ref(),
+    };
+    if options.fuse {
+        let fused = fuse_modifiers(&ops);
+        let ops: Vec<&StateModifier> = fused.iter().map(|m| m.modifier()).collect();
+        run_with_context(&ops, state, ctx)
     } else {
-        run_with_state_and_ops(&ops, state)
+        run_with_context(&ops, state, ctx)
     }
 }
 
+/// Run the circuit with a given starting state, first fusing runs of one and two qubit gates into
+/// single matrix ops (see `fusion::fuse_modifiers`). This gives the same results as
+/// `run_with_state` with fewer passes over the state for deep circuits.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::pipeline::{run_with_state_fused, LocalQuantumState};
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let q = b.qubit();
+/// let r = b.qubit();
+/// let q = b.hadamard(q);
+/// let q = b.rz(q, 0.3);
+/// let (q, r) = b.cnot(q, r);
+/// let r = b.merge(vec![q, r])?;
+///
+/// let (fused, _) = run_with_state_fused(&r, LocalQuantumState::<f64>::new(2))?;
+/// let (expected, _) = run_local::<f64>(&r)?;
+/// let (fused, expected) = (fused.get_state(true), expected.get_state(true));
+/// fused.iter().zip(expected.iter()).for_each(|(a, b)| assert!((a - b).norm() < 1e-10));
+/// # Ok(())
+/// # }
+/// ```
+pub fn run_with_state_fused<P: Precision, QS: QuantumState<P>>(
+    r: &Register,
+    state: QS,
+) -> Result<(QS, MeasuredResults<P>), CircuitError> {
+    let options = RunOptions {
+        fuse: true,
+        ..Default::default()
+    };
+    run_with_options(r, state, options)
+}
+
+/// `run` the pipeline using `LocalQuantumState` after fusing runs of one and two qubit gates.
+pub fn run_local_fused<P: Precision>(
+    r: &Register,
+) -> Result<(LocalQuantumState<P>, MeasuredResults<P>), CircuitError> {
+    let (frontier, _) = get_opfns_and_frontier(r);
+    let n = get_required_state_size_from_frontier(&frontier);
+    run_with_state_fused(r, LocalQuantumState::new(n))
+}
+
+/// Run the circuit on a default state, inserting the channels given by `noise` after each op.
+pub fn run_with_noise<P: Precision, QS: QuantumState<P>>(
+    r: &Register,
+    noise: &NoiseModel,
+) -> Result<(QS, MeasuredResults<P>), CircuitError> {
+    let (frontier, _) = get_opfns_and_frontier(r);
+    let n = get_required_state_size_from_frontier(&frontier);
+    run_with_state_and_noise(r, QS::new(n), noise)
+}
+
+/// Run the circuit on `state`, inserting the channels given by `noise` after each op.
+pub fn run_with_state_and_noise<P: Precision, QS: QuantumState<P>>(
+    r: &Register,
+    state: QS,
+    noise: &NoiseModel,
+) -> Result<(QS, MeasuredResults<P>), CircuitError> {
+    let options = RunOptions {
+        noise: Some(noise),
+        ..Default::default()
+    };
+    run_with_options(r, state, options)
+}
+
+/// Run the circuit on `state`, calling `observer` with a description of each op and the state
+/// after it has been applied. Measurements and channels are observed too, with no unitary.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::pipeline::{run_with_state_and_observer, LocalQuantumState};
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let q = b.qubit();
+/// let r = b.qubit();
+/// let q = b.hadamard(q);
+/// let (q, r) = b.cnot(q, r);
+/// let r = b.merge(vec![q, r])?;
+///
+/// let mut names = vec![];
+/// let mut observer = |op: &pipeline::ObservedOp, s: &LocalQuantumState<f64>| {
+///     names.push(op.name.to_string());
+///     // The state can be inspected between ops.
+///     assert!((s.peek_probabilities(&[0])[0] - 0.5).abs() < 1e-10);
+/// };
+/// run_with_state_and_observer(&r, LocalQuantumState::<f64>::new(2), &mut observer)?;
+/// assert_eq!(names, vec!["H", "C(not)"]);
+/// # Ok(())
+/// # }
+/// ```
+pub fn run_with_state_and_observer<P: Precision, QS: QuantumState<P>>(
+    r: &Register,
+    state: QS,
+    observer: &mut ObserverFn<QS>,
+) -> Result<(QS, MeasuredResults<P>), CircuitError> {
+    let options = RunOptions {
+        observer: Some(observer),
+        ..Default::default()
+    };
+    run_with_options(r, state, options)
+}
+
+/// Run the circuit on a default state, using `params` for the values of any `Parameter`s by name.
+/// The same circuit may be run for many different values without being rebuilt.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::parameters::Parameter;
+/// use std::collections::HashMap;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let q = b.qubit();
+/// let q = b.ry_param(q, &Parameter::new("theta"))?;
+///
+/// let mut params = HashMap::new();
+/// params.insert("theta".to_string(), std::f64::consts::PI);
+/// let (state, _) = run_local_with_parameters::<f64>(&q, &params)?;
+/// assert!((state.get_state(true)[1].re - 1.0).abs() < 1e-10);
+/// # Ok(())
+/// # }
+/// ```
+pub fn run_with_parameters<P: Precision, QS: QuantumState<P>>(
+    r: &Register,
+    params: &HashMap<String, f64>,
+) -> Result<(QS, MeasuredResults<P>), CircuitError> {
+    let (frontier, _) = get_opfns_and_frontier(r);
+    let n = get_required_state_size_from_frontier(&frontier);
+    run_with_state_and_parameters(r, QS::new(n), params)
+}
+
+/// Run the circuit on `state`, using `params` for the values of any `Parameter`s by name.
+pub fn run_with_state_and_parameters<P: Precision, QS: QuantumState<P>>(
+    r: &Register,
+    state: QS,
+    params: &HashMap<String, f64>,
+) -> Result<(QS, MeasuredResults<P>), CircuitError> {
+    let options = RunOptions {
+        parameters: Some(params),
+        ..Default::default()
+    };
+    run_with_options(r, state, options)
+}
+
+/// `run_with_parameters` using `LocalQuantumState`.
+pub fn run_local_with_parameters<P: Precision>(
+    r: &Register,
+    params: &HashMap<String, f64>,
+) -> Result<(LocalQuantumState<P>, MeasuredResults<P>), CircuitError> {
+    run_with_parameters(r, params)
+}
+
 /// `run` the pipeline using `LocalQuantumState`.
 pub fn run_local<P: Precision>(
     r: &Register,
@@ -726,6 +1644,235 @@ pub fn run_local<P: Precision>(
     run(r)
 }
 
+/// `run` the pipeline once using `LocalQuantumState` then sample `shots` measurements of `r` from
+/// the final state, returning a histogram of the number of times each value of `r` was measured.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let q = b.qubit();
+/// let r = b.qubit();
+/// let q = b.hadamard(q);
+/// let (q, r) = b.cnot(q, r);
+/// let r = b.merge(vec![q, r])?;
+///
+/// let counts = run_and_sample::<f64>(&r, 100)?;
+/// // Only |00> and |11> are ever measured.
+/// assert_eq!(counts.get(&0b00).unwrap_or(&0) + counts.get(&0b11).unwrap_or(&0), 100);
+/// # Ok(())
+/// # }
+/// ```
+pub fn run_and_sample<P: Precision>(
+    r: &Register,
+    shots: usize,
+) -> Result<HashMap<u64, usize>, CircuitError> {
+    let branches = run_local_shots::<P>(r, shots)?;
+    let mut counts = HashMap::new();
+    branches.into_iter().for_each(|branch| {
+        branch.counts.into_iter().for_each(|(m, c)| {
+            *counts.entry(m).or_insert(0) += c;
+        })
+    });
+    Ok(counts)
+}
+
+/// A group of shots from `run_shots` which saw the same mid-circuit measurements.
+#[derive(Debug)]
+pub struct ShotBranch<P: Precision> {
+    /// The measurements made during the circuit for these shots.
+    pub measured: MeasuredResults<P>,
+    /// Histogram of the values of the output register measured at the end of each shot.
+    pub counts: HashMap<u64, usize>,
+}
+
+impl<P: Precision> ShotBranch<P> {
+    /// Total number of shots in this branch.
+    pub fn shots(&self) -> usize {
+        self.counts.values().sum()
+    }
+}
+
+/// Run `shots` shots of the circuit and measure `r` at the end of each one, without re-running
+/// the circuit for each shot. Ops are applied once to a shared state, which is only cloned at
+/// mid-circuit measurements: the shots are divided among the possible outcomes and each outcome
+/// continues from its own collapsed copy of the state. Each returned `ShotBranch` holds the shots
+/// which saw one set of mid-circuit measurements, so a circuit with only terminal measurements
+/// gives a single branch.
+///
+/// Channels are sampled independently for every shot, so a branch reaching a channel is split
+/// into one branch per shot. Circuits with parameters must be run with
+/// `run_shots_with_parameters`.
+pub fn run_shots<P: Precision, QS: QuantumState<P> + Clone>(
+    r: &Register,
+    shots: usize,
+) -> Result<Vec<ShotBranch<P>>, CircuitError> {
+    run_shots_with_context::<P, QS>(r, shots, RunContext::default())
+}
+
+/// `run_shots` using `params` for the values of any `Parameter`s by name.
+pub fn run_shots_with_parameters<P: Precision, QS: QuantumState<P> + Clone>(
+    r: &Register,
+    shots: usize,
+    params: &HashMap<String, f64>,
+) -> Result<Vec<ShotBranch<P>>, CircuitError> {
+    let ctx = RunContext {
+        parameters: Some(params),
+        ..Default::default()
+    };
+    run_shots_with_context::<P, QS>(r, shots, ctx)
+}
+
+fn run_shots_with_context<P: Precision, QS: QuantumState<P> + Clone>(
+    r: &Register,
+    shots: usize,
+    ctx: RunContext<QS>,
+) -> Result<Vec<ShotBranch<P>>, CircuitError> {
+    let (frontier, ops) = get_opfns_and_frontier(r);
+    let n = get_required_state_size_from_frontier(&frontier);
+    let branches = vec![(QS::new(n), MeasuredResults::new(), shots)];
+    let branches = ops.into_iter().try_fold(branches, |branches, m| {
+        fold_modify_branches(ctx, branches, m)
+    })?;
+    Ok(branches
+        .into_iter()
+        .filter(|(_, _, shots)| *shots > 0)
+        .map(|(mut s, measured, shots)| {
+            let probs = s.stochastic_measure(&r.indices, 0.0);
+            let counts = sample_from_probs(&probs, shots);
+            ShotBranch { measured, counts }
+        })
+        .collect())
+}
+
+/// `run_shots` using `LocalQuantumState`.
+pub fn run_local_shots<P: Precision>(
+    r: &Register,
+    shots: usize,
+) -> Result<Vec<ShotBranch<P>>, CircuitError> {
+    run_shots::<P, LocalQuantumState<P>>(r, shots)
+}
+
+/// A state along with the measurements which led to it, and the number of shots sharing it.
+type ShotState<P, QS> = (QS, MeasuredResults<P>, usize);
+
+/// Apply `modifier` to each branch, splitting branches at measurements and channels.
+fn fold_modify_branches<P: Precision, QS: QuantumState<P> + Clone>(
+    ctx: RunContext<QS>,
+    branches: Vec<ShotState<P, QS>>,
+    modifier: &StateModifier,
+) -> Result<Vec<ShotState<P, QS>>, CircuitError> {
+    let mut result = vec![];
+    for (mut s, mr, shots) in branches {
+        match &modifier.modifier {
+            StateModifierType::MeasureState(id, indices, angle) => {
+                let probs = s.stochastic_measure(indices, *angle);
+                let mut outcomes: Vec<_> = sample_from_probs(&probs, shots).into_iter().collect();
+                outcomes.sort_by_key(|(m, _)| *m);
+                let last = outcomes.len().saturating_sub(1);
+                let mut s = Some(s);
+                for (i, (measured, count)) in outcomes.into_iter().enumerate() {
+                    // The last outcome can reuse the original state.
+                    let mut s = if i == last {
+                        s.take().unwrap()
+                    } else {
+                        s.as_ref().unwrap().clone()
+                    };
+                    let prob = probs[measured as usize];
+                    let condition = MeasuredCondition {
+                        measured,
+                        prob: Some(prob),
+                    };
+                    s.measure(indices, Some(condition), *angle);
+                    let mut mr = mr.clone();
+                    mr.results.insert(*id, (measured, prob));
+                    result.push((s, mr, count));
+                }
+            }
+            StateModifierType::SideChannelModifiers(handles, f) => {
+                s.check_feed_forward()?;
+                let measured_values: Vec<_> = handles
+                    .iter()
+                    .map(|handle| mr.get_measurement(handle).map(|(m, _)| m))
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| CircuitError::new("Not all measurements found".to_string()))?;
+                let modifiers = f(&measured_values)?;
+                result.extend(fold_branches(ctx, (s, mr, shots), &modifiers)?);
+            }
+            StateModifierType::Subcircuit(modifiers) => {
+                result.extend(fold_branches(ctx, (s, mr, shots), modifiers)?);
+            }
+            StateModifierType::Gate(def, indices) => {
+                let modifiers = def.instantiate(indices);
+                result.extend(fold_branches(ctx, (s, mr, shots), &modifiers)?);
+            }
+            StateModifierType::Loop(body, indices) => {
+                let modifiers = body.unroll(indices, ctx.parameters)?;
+                result.extend(fold_branches(ctx, (s, mr, shots), &modifiers)?);
+            }
+            StateModifierType::Channel(..) if shots > 1 => {
+                for _ in 0..shots {
+                    let (s, mr) = fold_modify_state(ctx, (s.clone(), mr.clone()), modifier)?;
+                    result.push((s, mr, 1));
+                }
+            }
+            _ => {
+                let (s, mr) = fold_modify_state(ctx, (s, mr), modifier)?;
+                result.push((s, mr, shots));
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Apply each of `modifiers` in turn to the single branch `branch`.
+fn fold_branches<P: Precision, QS: QuantumState<P> + Clone>(
+    ctx: RunContext<QS>,
+    branch: ShotState<P, QS>,
+    modifiers: &[StateModifier],
+) -> Result<Vec<ShotState<P, QS>>, CircuitError> {
+    modifiers.iter().try_fold(vec![branch], |branches, m| {
+        fold_modify_branches(ctx, branches, m)
+    })
+}
+
+/// `run` the pipeline with measurements drawn from a generator seeded with `seed`, so that the
+/// same seed always gives the same results. See `rng::with_seed` to seed other functions.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::pipeline::{run_with_seed, LocalQuantumState};
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let r = b.register(3)?;
+/// let r = b.hadamard(r);
+/// let (r, m) = b.measure(r);
+///
+/// let (_, a) = run_with_seed::<f64, LocalQuantumState<f64>>(&r, 7)?;
+/// let (_, b) = run_with_seed::<f64, LocalQuantumState<f64>>(&r, 7)?;
+/// assert_eq!(a.get_measurement(&m), b.get_measurement(&m));
+/// # Ok(())
+/// # }
+/// ```
+pub fn run_with_seed<P: Precision, QS: QuantumState<P>>(
+    r: &Register,
+    seed: u64,
+) -> Result<(QS, MeasuredResults<P>), CircuitError> {
+    rng::with_seed(seed, || run(r))
+}
+
+/// `run_with_seed` using `LocalQuantumState`.
+pub fn run_local_with_seed<P: Precision>(
+    r: &Register,
+    seed: u64,
+) -> Result<(LocalQuantumState<P>, MeasuredResults<P>), CircuitError> {
+    run_with_seed(r, seed)
+}
+
 /// `run_with_init` the pipeline using `LocalQuantumState`
 pub fn run_local_with_init<P: Precision>(
     r: &Register,
@@ -734,24 +1881,65 @@ pub fn run_local_with_init<P: Precision>(
     run_with_init(r, states)
 }
 
+/// Run `ops` on `state`, using `params` for the values of any `Parameter`s by name.
+pub(crate) fn run_ops_with_parameters<P: Precision, QS: QuantumState<P>>(
+    ops: &[&StateModifier],
+    state: QS,
+    params: &HashMap<String, f64>,
+) -> Result<(QS, MeasuredResults<P>), CircuitError> {
+    let ctx = RunContext {
+        parameters: Some(params),
+        ..Default::default()
+    };
+    run_with_context(ops, state, ctx)
+}
+
 fn run_with_state_and_ops<P: Precision, QS: QuantumState<P>>(
     ops: &[&StateModifier],
     state: QS,
+) -> Result<(QS, MeasuredResults<P>), CircuitError> {
+    run_with_context(ops, state, RunContext::default())
+}
+
+fn run_with_context<P: Precision, QS: QuantumState<P>>(
+    ops: &[&StateModifier],
+    state: QS,
+    ctx: RunContext<QS>,
 ) -> Result<(QS, MeasuredResults<P>), CircuitError> {
     ops.iter()
-        .cloned()
-        .try_fold((state, MeasuredResults::new()), fold_modify_state)
+        .try_fold((state, MeasuredResults::new()), |acc, m| {
+            fold_modify_state(ctx, acc, m)
+        })
 }
 
 /// Get the frontier of a circuit as well as references to all the StateModifiers needed in the
 /// correct order.
 pub fn get_opfns_and_frontier(r: &Register) -> (Vec<&Register>, Vec<&StateModifier>) {
+    get_opfns_and_frontier_since(r, 0)
+}
+
+/// Get references to the StateModifiers in the circuit given by `r` which were added to
+/// Registers with an id of at least `first_id`, in the correct order. Registers with earlier ids
+/// are treated as part of the frontier, so this gives the ops added since `first_id` was the next
+/// id of the builder.
+pub(crate) fn get_opfns_since(r: &Register, first_id: u64) -> Vec<&StateModifier> {
+    get_opfns_and_frontier_since(r, first_id).1
+}
+
+fn get_opfns_and_frontier_since(
+    r: &Register,
+    first_id: u64,
+) -> (Vec<&Register>, Vec<&StateModifier>) {
     let mut heap = BinaryHeap::new();
     heap.push(r);
     let mut frontier_registers: Vec<&Register> = vec![];
     let mut fn_queue = VecDeque::new();
     while !heap.is_empty() {
         if let Some(r) = heap.pop() {
+            if r.id < first_id {
+                frontier_registers.push(r);
+                continue;
+            }
             match &r.parent {
                 Some(parent) => match &parent {
                     Parent::Owned(parents, modifier) => {
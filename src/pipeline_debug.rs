@@ -3,9 +3,56 @@ use crate::measurement_ops::MeasuredCondition;
 use crate::pipeline::{
     get_required_state_size_from_frontier, run_with_statebuilder, InitialState, QuantumState,
 };
+use crate::qubits::Parent;
 use crate::state_ops::{get_index, num_indices, UnitaryOp};
 use crate::{Complex, Precision, Register};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt::Write;
 use std::marker::PhantomData;
+use std::rc::Rc;
+
+/// A label and the probabilities recorded with it.
+type DebugEntry = (String, Vec<f64>);
+
+/// A record of the probability distributions seen by `UnitaryBuilder::debug_record` ops, in the
+/// order they were run. Clones share the same record.
+#[derive(Default, Debug, Clone)]
+pub struct DebugLog {
+    entries: Rc<RefCell<Vec<DebugEntry>>>,
+}
+
+impl DebugLog {
+    /// Make a new empty log.
+    pub fn new() -> DebugLog {
+        DebugLog::default()
+    }
+
+    /// Add the probabilities `probs` seen by the op `label`.
+    pub fn record(&self, label: &str, probs: Vec<f64>) {
+        self.entries.borrow_mut().push((label.to_string(), probs));
+    }
+
+    /// Clone all the labels and probabilities recorded so far.
+    pub fn entries(&self) -> Vec<DebugEntry> {
+        self.entries.borrow().clone()
+    }
+
+    /// Clone the probabilities from each time the op `label` was run.
+    pub fn get(&self, label: &str) -> Vec<Vec<f64>> {
+        self.entries
+            .borrow()
+            .iter()
+            .filter(|(l, _)| l == label)
+            .map(|(_, probs)| probs.clone())
+            .collect()
+    }
+
+    /// Remove all entries.
+    pub fn clear(&self) {
+        self.entries.borrow_mut().clear()
+    }
+}
 
 struct PrintPipeline<P: Precision> {
     n: u64,
@@ -131,6 +178,30 @@ impl<P: Precision> QuantumState<P> for PrintPipeline<P> {
         println!("{}", tmp.join(" "));
     }
 
+    fn apply_channel(
+        &mut self,
+        name: Option<&str>,
+        indices: &[u64],
+        _: &[Vec<Complex<f64>>],
+    ) -> Result<(), CircuitError> {
+        let mut tmp: Vec<String> = vec![];
+        for i in 0u64..self.n {
+            if indices.contains(&i) {
+                tmp.push("N".to_string())
+            } else {
+                tmp.push("|".to_string())
+            }
+        }
+        print!("{}", tmp.join(" "));
+        if let Some(name) = name {
+            print!("\t{}", name);
+        }
+        println!();
+        let tmp: Vec<String> = (0..self.n).map(|_| "|".to_string()).collect();
+        println!("{}", tmp.join(" "));
+        Ok(())
+    }
+
     fn measure(&mut self, indices: &[u64], _: Option<MeasuredCondition<P>>, _: f64) -> (u64, P) {
         let mut tmp: Vec<String> = vec![];
         for i in 0u64..self.n {
@@ -171,3 +242,273 @@ pub fn run_debug(r: &Register) -> Result<(), CircuitError> {
     })
     .map(|_| ())
 }
+
+/// A single qubit's part of a column in a wire diagram.
+#[derive(Clone)]
+enum WireCell {
+    Wire,
+    Crossing,
+    Control,
+    Swap,
+    Gate(String),
+}
+
+struct DrawPipeline<P: Precision> {
+    n: u64,
+    columns: Vec<Vec<WireCell>>,
+    phantom: PhantomData<P>,
+}
+
+impl<P: Precision> DrawPipeline<P> {
+    /// Strip name scopes and control wrappers from an op name to get a short label.
+    fn label(name: Option<&str>, default: &str) -> String {
+        let mut name = name.unwrap_or(default);
+        while name.starts_with("C(") && name.ends_with(')') {
+            name = &name[2..name.len() - 1];
+        }
+        name.rsplit('/').next().unwrap_or(default).to_string()
+    }
+
+    /// Add a column with the given cells, drawing vertical lines across uninvolved qubits between
+    /// the outermost involved qubits.
+    fn add_column(&mut self, cells: Vec<(u64, WireCell)>) {
+        let mut column = vec![WireCell::Wire; self.n as usize];
+        let lower = cells.iter().map(|(i, _)| *i).min();
+        let upper = cells.iter().map(|(i, _)| *i).max();
+        if let (Some(lower), Some(upper)) = (lower, upper) {
+            (lower..upper).for_each(|i| column[i as usize] = WireCell::Crossing);
+        }
+        cells
+            .into_iter()
+            .for_each(|(i, cell)| column[i as usize] = cell);
+        self.columns.push(column);
+    }
+
+    fn render(&self) -> String {
+        let labels: Vec<String> = (0..self.n).map(|i| format!("q{}: ", i)).collect();
+        let label_width = labels.iter().map(|l| l.len()).max().unwrap_or(0);
+        let widths: Vec<usize> = self
+            .columns
+            .iter()
+            .map(|column| {
+                column
+                    .iter()
+                    .map(|cell| match cell {
+                        WireCell::Gate(label) => label.chars().count() + 2,
+                        _ => 1,
+                    })
+                    .max()
+                    .unwrap_or(1)
+            })
+            .collect();
+        labels
+            .into_iter()
+            .enumerate()
+            .map(|(i, label)| {
+                let mut line = format!("{:<width$}", label, width = label_width);
+                self.columns
+                    .iter()
+                    .zip(widths.iter())
+                    .for_each(|(column, width)| {
+                        let cell = match &column[i] {
+                            WireCell::Wire => "-".to_string(),
+                            WireCell::Crossing => "|".to_string(),
+                            WireCell::Control => "*".to_string(),
+                            WireCell::Swap => "x".to_string(),
+                            WireCell::Gate(label) => format!("[{}]", label),
+                        };
+                        let padding = width - cell.chars().count();
+                        line.push('-');
+                        line.push_str(&"-".repeat(padding / 2));
+                        line.push_str(&cell);
+                        line.push_str(&"-".repeat(padding - padding / 2));
+                    });
+                line.push('-');
+                line.push('\n');
+                line
+            })
+            .collect()
+    }
+}
+
+impl<P: Precision> QuantumState<P> for DrawPipeline<P> {
+    fn new(n: u64) -> DrawPipeline<P> {
+        DrawPipeline {
+            n,
+            columns: vec![],
+            phantom: PhantomData,
+        }
+    }
+
+    fn new_from_initial_states(n: u64, _states: &[(Vec<u64>, InitialState<P>)]) -> DrawPipeline<P> {
+        DrawPipeline::<P>::new(n)
+    }
+
+    fn n(&self) -> u64 {
+        self.n
+    }
+
+    fn apply_op_with_name(&mut self, name: Option<&str>, op: &UnitaryOp) {
+        let mut op = op;
+        let mut cells = vec![];
+        while let UnitaryOp::Control(c_indices, _, inner) = op {
+            cells.extend(c_indices.iter().map(|indx| (*indx, WireCell::Control)));
+            op = inner;
+        }
+        match op {
+            UnitaryOp::Swap(a_indices, b_indices) => cells.extend(
+                a_indices
+                    .iter()
+                    .chain(b_indices.iter())
+                    .map(|indx| (*indx, WireCell::Swap)),
+            ),
+            _ => {
+                let label = Self::label(name, "U");
+                cells.extend(
+                    (0..num_indices(op)).map(|i| (get_index(op, i), WireCell::Gate(label.clone()))),
+                )
+            }
+        }
+        self.add_column(cells);
+    }
+
+    fn apply_channel(
+        &mut self,
+        name: Option<&str>,
+        indices: &[u64],
+        _: &[Vec<Complex<f64>>],
+    ) -> Result<(), CircuitError> {
+        let label = Self::label(name, "N");
+        self.add_column(
+            indices
+                .iter()
+                .map(|indx| (*indx, WireCell::Gate(label.clone())))
+                .collect(),
+        );
+        Ok(())
+    }
+
+    fn measure(&mut self, indices: &[u64], _: Option<MeasuredCondition<P>>, _: f64) -> (u64, P) {
+        self.add_column(
+            indices
+                .iter()
+                .map(|indx| (*indx, WireCell::Gate("M".to_string())))
+                .collect(),
+        );
+        (0, P::zero())
+    }
+
+    fn soft_measure(&mut self, _: &[u64], _: Option<u64>, _: f64) -> (u64, P) {
+        (0, P::zero())
+    }
+
+    fn state_magnitude(&self) -> P {
+        P::zero()
+    }
+
+    fn stochastic_measure(&mut self, _: &[u64], _: f64) -> Vec<P> {
+        vec![]
+    }
+
+    fn get_state(self, _: bool) -> Vec<Complex<P>> {
+        vec![]
+    }
+}
+
+/// Render the circuit as an ASCII wire diagram, with one line per qubit. Gates are drawn as boxes
+/// labeled with the op name (without scopes), controls as `*`, swaps as `x`, and vertical lines
+/// connect the qubits of multi-qubit ops. Classical side channels are drawn as though all
+/// measurements returned 0.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let q = b.qubit();
+/// let r = b.qubit();
+/// let q = b.hadamard(q);
+/// let (q, r) = b.cnot(q, r);
+/// let qr = b.merge(vec![q, r])?;
+///
+/// let diagram = draw(&qr)?;
+/// assert_eq!(diagram, "q0: -[H]---*---\nq1: -----[not]-\n");
+/// # Ok(())
+/// # }
+/// ```
+pub fn draw(r: &Register) -> Result<String, CircuitError> {
+    let (pipeline, _) = run_with_statebuilder(r, |rs| {
+        let n = get_required_state_size_from_frontier(&rs);
+        Ok(DrawPipeline::<f32>::new(n))
+    })?;
+    Ok(pipeline.render())
+}
+
+/// Export the graph of Registers which produce `r` in the GraphViz DOT format. Each node is a
+/// Register labeled with its id and indices, edges point from parents to children. Edges into
+/// Registers produced by an op are labeled with the op name, edges from a split Register are
+/// dashed, and edges from classical dependencies (such as measurements) are dotted.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let q = b.qubit();
+/// let q = b.hadamard(q);
+///
+/// let dot = to_dot(&q);
+/// assert!(dot.starts_with("digraph circuit {"));
+/// assert!(dot.contains("[label=\"H\"]"));
+/// # Ok(())
+/// # }
+/// ```
+pub fn to_dot(r: &Register) -> String {
+    let mut nodes = vec![];
+    let mut edges = vec![];
+    let mut visited = HashSet::new();
+    let mut stack = vec![r];
+    while let Some(r) = stack.pop() {
+        if !visited.insert(r.id) {
+            continue;
+        }
+        nodes.push(format!("r{} [label=\"{}: {:?}\"];", r.id, r.id, r.indices));
+        match &r.parent {
+            Some(Parent::Owned(parents, modifier)) => parents.iter().for_each(|p| {
+                let edge = match modifier {
+                    Some(modifier) => format!(
+                        "r{} -> r{} [label=\"{}\"];",
+                        p.id,
+                        r.id,
+                        modifier.name.replace('"', "\\\"")
+                    ),
+                    None => format!("r{} -> r{};", p.id, r.id),
+                };
+                edges.push(edge);
+                stack.push(p);
+            }),
+            Some(Parent::Shared(p)) => {
+                edges.push(format!("r{} -> r{} [style=dashed];", p.id, r.id));
+                stack.push(p.as_ref());
+            }
+            None => {}
+        }
+        if let Some(deps) = &r.deps {
+            deps.iter().for_each(|d| {
+                edges.push(format!("r{} -> r{} [style=dotted];", d.id, r.id));
+                stack.push(d.as_ref());
+            })
+        }
+    }
+    let mut s = String::new();
+    writeln!(s, "digraph circuit {{").unwrap();
+    nodes
+        .iter()
+        .rev()
+        .chain(edges.iter().rev())
+        .for_each(|line| writeln!(s, "    {}", line).unwrap());
+    writeln!(s, "}}").unwrap();
+    s
+}
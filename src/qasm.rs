@@ -0,0 +1,1145 @@
+use crate::builders::{u3_angles, u3_matrix};
+use crate::errors::CircuitError;
+use crate::named_gates::GateDefinition;
+use crate::pipeline::{
+    get_opfns_and_frontier, get_required_state_size_from_frontier, MeasurementHandle,
+    StateModifier, StateModifierType,
+};
+use crate::state_ops::UnitaryOp;
+use crate::{Complex, OpBuilder, Register, UnitaryBuilder};
+use std::collections::HashMap;
+
+/// The circuit produced by parsing an OpenQASM program.
+#[derive(Debug)]
+pub struct QasmCircuit {
+    /// Quantum registers with their names, in the order they were declared.
+    pub registers: Vec<(String, Register)>,
+    /// Classical registers with their sizes, in the order they were declared.
+    pub classical_registers: Vec<(String, u64)>,
+    /// Measurements as the classical register name, the bit written, and the handle of the
+    /// measured qubit. Ordered as they appear in the program.
+    pub measurements: Vec<(String, u64, MeasurementHandle)>,
+}
+
+impl QasmCircuit {
+    /// Get the quantum register with the given name.
+    pub fn get_register(&self, name: &str) -> Option<&Register> {
+        self.registers
+            .iter()
+            .find(|(rname, _)| rname == name)
+            .map(|(_, r)| r)
+    }
+
+    /// Merge all the quantum registers, in declaration order, into a single Register.
+    pub fn merge_registers(self, b: &mut OpBuilder) -> Result<Register, CircuitError> {
+        b.merge(self.registers.into_iter().map(|(_, r)| r).collect())
+    }
+}
+
+/// Parse an OpenQASM 2.0 program into `b`. The standard gates of `qelib1.inc` are built in, and
+/// custom `gate` definitions are expanded where they are used. Classically controlled (`if`) and
+/// `opaque` gates are not supported.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::qasm::parse_qasm;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let source = r#"
+///     OPENQASM 2.0;
+///     include "qelib1.inc";
+///     qreg q[2];
+///     creg c[2];
+///     h q[0];
+///     cx q[0],q[1];
+///     measure q -> c;
+/// "#;
+///
+/// let mut b = OpBuilder::new();
+/// let circuit = parse_qasm(&mut b, source)?;
+/// let handles: Vec<_> = circuit.measurements.iter().map(|(_, _, h)| h.clone()).collect();
+/// let r = circuit.merge_registers(&mut b)?;
+/// let (_, measured) = run_local::<f64>(&r)?;
+///
+/// let (m0, _) = measured.get_measurement(&handles[0]).unwrap();
+/// let (m1, _) = measured.get_measurement(&handles[1]).unwrap();
+/// assert_eq!(m0, m1);
+/// # Ok(())
+/// # }
+/// ```
+pub fn parse_qasm(b: &mut OpBuilder, source: &str) -> Result<QasmCircuit, CircuitError> {
+    let tokens = tokenize(source)?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        qubits: HashMap::new(),
+        qreg_order: vec![],
+        cregs: vec![],
+        gates: HashMap::new(),
+        measurements: vec![],
+    };
+    parser.parse_program(b)?;
+
+    let mut qubits = parser.qubits;
+    let registers = parser
+        .qreg_order
+        .into_iter()
+        .map(|name| {
+            let qs = qubits.remove(&name).unwrap();
+            let qs = qs.into_iter().map(|q| q.unwrap()).collect();
+            b.merge(qs).map(|r| (name, r))
+        })
+        .collect::<Result<Vec<_>, CircuitError>>()?;
+    Ok(QasmCircuit {
+        registers,
+        classical_registers: parser.cregs,
+        measurements: parser.measurements,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenType {
+    Ident(String),
+    Number(f64),
+    Str(String),
+    Symbol(&'static str),
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    token: TokenType,
+    line: usize,
+}
+
+const SYMBOLS: &[&str] = &[
+    "->", "==", ";", ",", "(", ")", "[", "]", "{", "}", "+", "-", "*", "/", "^",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, CircuitError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = vec![];
+    let mut line = 1;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\n' {
+            line += 1;
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let ident = chars[start..i].iter().collect();
+            tokens.push(Token {
+                token: TokenType::Ident(ident),
+                line,
+            });
+        } else if c.is_ascii_digit() || c == '.' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                i += 1;
+                if i < chars.len() && (chars[i] == '+' || chars[i] == '-') {
+                    i += 1;
+                }
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+            }
+            let s: String = chars[start..i].iter().collect();
+            let value = s.parse::<f64>().map_err(|_| {
+                CircuitError::new(format!("Invalid number {:?} on line {}", s, line))
+            })?;
+            tokens.push(Token {
+                token: TokenType::Number(value),
+                line,
+            });
+        } else if c == '"' {
+            let start = i + 1;
+            i = start;
+            while i < chars.len() && chars[i] != '"' {
+                i += 1;
+            }
+            if i == chars.len() {
+                return CircuitError::make_err(format!("Unterminated string on line {}", line));
+            }
+            let s = chars[start..i].iter().collect();
+            i += 1;
+            tokens.push(Token {
+                token: TokenType::Str(s),
+                line,
+            });
+        } else {
+            let symbol = SYMBOLS.iter().find(|sym| {
+                sym.chars()
+                    .enumerate()
+                    .all(|(j, sc)| chars.get(i + j) == Some(&sc))
+            });
+            match symbol {
+                Some(sym) => {
+                    i += sym.len();
+                    tokens.push(Token {
+                        token: TokenType::Symbol(sym),
+                        line,
+                    });
+                }
+                None => {
+                    let message = format!("Unexpected character {:?} on line {}", c, line);
+                    return CircuitError::make_err(message);
+                }
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// A user defined gate, the body is kept as tokens and expanded on each use.
+#[derive(Debug)]
+struct GateDef {
+    params: Vec<String>,
+    qargs: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// A gate argument, either a single qubit or a whole register.
+#[derive(Debug, Clone, PartialEq)]
+enum Arg {
+    Qubit(String, u64),
+    Register(String),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    qubits: HashMap<String, Vec<Option<Register>>>,
+    qreg_order: Vec<String>,
+    cregs: Vec<(String, u64)>,
+    gates: HashMap<String, GateDef>,
+    measurements: Vec<(String, u64, MeasurementHandle)>,
+}
+
+impl Parser {
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or_else(|| self.tokens.last())
+            .map(|t| t.line)
+            .unwrap_or(0)
+    }
+
+    fn err<T>(&self, message: &str) -> Result<T, CircuitError> {
+        CircuitError::make_err(format!("{} on line {}", message, self.line()))
+    }
+
+    fn peek(&self) -> Option<&TokenType> {
+        self.tokens.get(self.pos).map(|t| &t.token)
+    }
+
+    fn next(&mut self) -> Result<TokenType, CircuitError> {
+        match self.tokens.get(self.pos) {
+            Some(t) => {
+                self.pos += 1;
+                Ok(t.token.clone())
+            }
+            None => self.err("Unexpected end of program"),
+        }
+    }
+
+    fn is_symbol(&self, sym: &str) -> bool {
+        match self.peek() {
+            Some(TokenType::Symbol(s)) => *s == sym,
+            _ => false,
+        }
+    }
+
+    fn expect_symbol(&mut self, sym: &str) -> Result<(), CircuitError> {
+        if self.is_symbol(sym) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            self.err(&format!("Expected {:?}", sym))
+        }
+    }
+
+    fn expect_ident(&mut self) -> Result<String, CircuitError> {
+        match self.next()? {
+            TokenType::Ident(s) => Ok(s),
+            t => {
+                self.pos -= 1;
+                self.err(&format!("Expected identifier but found {:?}", t))
+            }
+        }
+    }
+
+    fn expect_int(&mut self) -> Result<u64, CircuitError> {
+        match self.next()? {
+            TokenType::Number(v) if v >= 0.0 && v.fract() == 0.0 => Ok(v as u64),
+            t => {
+                self.pos -= 1;
+                self.err(&format!("Expected integer but found {:?}", t))
+            }
+        }
+    }
+
+    fn parse_program(&mut self, b: &mut OpBuilder) -> Result<(), CircuitError> {
+        if self.peek() == Some(&TokenType::Ident("OPENQASM".to_string())) {
+            self.pos += 1;
+            self.next()?;
+            self.expect_symbol(";")?;
+        }
+        while self.pos < self.tokens.len() {
+            self.parse_statement(b)?;
+        }
+        Ok(())
+    }
+
+    fn parse_statement(&mut self, b: &mut OpBuilder) -> Result<(), CircuitError> {
+        let name = self.expect_ident()?;
+        match name.as_str() {
+            "include" => {
+                match self.next()? {
+                    TokenType::Str(_) => {}
+                    _ => return self.err("Expected file name after include"),
+                }
+                self.expect_symbol(";")
+            }
+            "qreg" => {
+                let (name, n) = self.parse_declaration()?;
+                if self.qubits.contains_key(&name) {
+                    return self.err(&format!("Register {:?} declared twice", name));
+                }
+                let r = b.register(n)?;
+                self.qubits
+                    .insert(name.clone(), b.split_all(r).into_iter().map(Some).collect());
+                self.qreg_order.push(name);
+                Ok(())
+            }
+            "creg" => {
+                let (name, n) = self.parse_declaration()?;
+                self.cregs.push((name, n));
+                Ok(())
+            }
+            "gate" => self.parse_gate_definition(),
+            "barrier" => {
+                while !self.is_symbol(";") {
+                    self.next()?;
+                }
+                self.expect_symbol(";")
+            }
+            "measure" => {
+                let qarg = self.parse_arg()?;
+                self.expect_symbol("->")?;
+                let carg = self.parse_arg()?;
+                self.expect_symbol(";")?;
+                self.measure(b, qarg, carg)
+            }
+            "reset" => {
+                let arg = self.parse_arg()?;
+                self.expect_symbol(";")?;
+                self.resolve_args(&[arg])?
+                    .into_iter()
+                    .try_for_each(|mut args| {
+                        let (name, index) = args.pop().unwrap();
+                        let q = self.take_qubit(&name, index)?;
+                        let q = b.reset(q);
+                        self.put_qubit(&name, index, q);
+                        Ok(())
+                    })
+            }
+            "if" => self.err("Classically controlled gates are not supported"),
+            "opaque" => self.err("Opaque gates are not supported"),
+            _ => {
+                let params = self.parse_params(&HashMap::new())?;
+                let mut args = vec![self.parse_arg()?];
+                while self.is_symbol(",") {
+                    self.pos += 1;
+                    args.push(self.parse_arg()?);
+                }
+                self.expect_symbol(";")?;
+                self.resolve_args(&args)?
+                    .into_iter()
+                    .try_for_each(|qargs| self.apply_gate(b, &name, &params, &qargs))
+            }
+        }
+    }
+
+    fn parse_declaration(&mut self) -> Result<(String, u64), CircuitError> {
+        let name = self.expect_ident()?;
+        self.expect_symbol("[")?;
+        let n = self.expect_int()?;
+        self.expect_symbol("]")?;
+        self.expect_symbol(";")?;
+        if n == 0 {
+            return self.err(&format!("Register {:?} must be nonempty", name));
+        }
+        Ok((name, n))
+    }
+
+    fn parse_gate_definition(&mut self) -> Result<(), CircuitError> {
+        let name = self.expect_ident()?;
+        let params = if self.is_symbol("(") {
+            self.pos += 1;
+            let params = self.parse_ident_list(")")?;
+            self.expect_symbol(")")?;
+            params
+        } else {
+            vec![]
+        };
+        let qargs = self.parse_ident_list("{")?;
+        if qargs.is_empty() {
+            return self.err(&format!("Gate {:?} must act on at least one qubit", name));
+        }
+        self.expect_symbol("{")?;
+        let start = self.pos;
+        while !self.is_symbol("}") {
+            self.next()?;
+        }
+        let body = self.tokens[start..self.pos].to_vec();
+        self.expect_symbol("}")?;
+        self.gates.insert(
+            name,
+            GateDef {
+                params,
+                qargs,
+                body,
+            },
+        );
+        Ok(())
+    }
+
+    fn parse_ident_list(&mut self, end: &str) -> Result<Vec<String>, CircuitError> {
+        let mut idents = vec![];
+        while !self.is_symbol(end) {
+            if !idents.is_empty() {
+                self.expect_symbol(",")?;
+            }
+            idents.push(self.expect_ident()?);
+        }
+        Ok(idents)
+    }
+
+    fn parse_arg(&mut self) -> Result<Arg, CircuitError> {
+        let name = self.expect_ident()?;
+        if self.is_symbol("[") {
+            self.pos += 1;
+            let index = self.expect_int()?;
+            self.expect_symbol("]")?;
+            Ok(Arg::Qubit(name, index))
+        } else {
+            Ok(Arg::Register(name))
+        }
+    }
+
+    fn parse_params(&mut self, env: &HashMap<String, f64>) -> Result<Vec<f64>, CircuitError> {
+        let mut params = vec![];
+        if self.is_symbol("(") {
+            self.pos += 1;
+            while !self.is_symbol(")") {
+                if !params.is_empty() {
+                    self.expect_symbol(",")?;
+                }
+                params.push(self.parse_expr(env)?);
+            }
+            self.expect_symbol(")")?;
+        }
+        Ok(params)
+    }
+
+    fn parse_expr(&mut self, env: &HashMap<String, f64>) -> Result<f64, CircuitError> {
+        let mut acc = self.parse_term(env)?;
+        loop {
+            if self.is_symbol("+") {
+                self.pos += 1;
+                acc += self.parse_term(env)?;
+            } else if self.is_symbol("-") {
+                self.pos += 1;
+                acc -= self.parse_term(env)?;
+            } else {
+                return Ok(acc);
+            }
+        }
+    }
+
+    fn parse_term(&mut self, env: &HashMap<String, f64>) -> Result<f64, CircuitError> {
+        let mut acc = self.parse_factor(env)?;
+        loop {
+            if self.is_symbol("*") {
+                self.pos += 1;
+                acc *= self.parse_factor(env)?;
+            } else if self.is_symbol("/") {
+                self.pos += 1;
+                acc /= self.parse_factor(env)?;
+            } else {
+                return Ok(acc);
+            }
+        }
+    }
+
+    fn parse_factor(&mut self, env: &HashMap<String, f64>) -> Result<f64, CircuitError> {
+        if self.is_symbol("-") {
+            self.pos += 1;
+            return Ok(-self.parse_factor(env)?);
+        }
+        let base = self.parse_primary(env)?;
+        if self.is_symbol("^") {
+            self.pos += 1;
+            Ok(base.powf(self.parse_factor(env)?))
+        } else {
+            Ok(base)
+        }
+    }
+
+    fn parse_primary(&mut self, env: &HashMap<String, f64>) -> Result<f64, CircuitError> {
+        match self.next()? {
+            TokenType::Number(v) => Ok(v),
+            TokenType::Symbol("(") => {
+                let v = self.parse_expr(env)?;
+                self.expect_symbol(")")?;
+                Ok(v)
+            }
+            TokenType::Ident(ref s) if s == "pi" => Ok(std::f64::consts::PI),
+            TokenType::Ident(s) => {
+                if let Some(v) = env.get(&s) {
+                    return Ok(*v);
+                }
+                let f: fn(f64) -> f64 = match s.as_str() {
+                    "sin" => f64::sin,
+                    "cos" => f64::cos,
+                    "tan" => f64::tan,
+                    "exp" => f64::exp,
+                    "ln" => f64::ln,
+                    "sqrt" => f64::sqrt,
+                    _ => {
+                        self.pos -= 1;
+                        return self.err(&format!("Unknown parameter {:?}", s));
+                    }
+                };
+                self.expect_symbol("(")?;
+                let v = self.parse_expr(env)?;
+                self.expect_symbol(")")?;
+                Ok(f(v))
+            }
+            t => {
+                self.pos -= 1;
+                self.err(&format!("Unexpected {:?} in expression", t))
+            }
+        }
+    }
+
+    fn register_size(&self, name: &str) -> Result<u64, CircuitError> {
+        match self.qubits.get(name) {
+            Some(qs) => Ok(qs.len() as u64),
+            None => self.err(&format!("Unknown register {:?}", name)),
+        }
+    }
+
+    /// Expand arguments into lists of qubits, broadcasting whole registers.
+    fn resolve_args(&self, args: &[Arg]) -> Result<Vec<Vec<(String, u64)>>, CircuitError> {
+        let mut size = None;
+        for arg in args {
+            let n = match arg {
+                Arg::Qubit(name, index) => {
+                    if *index >= self.register_size(name)? {
+                        return self.err(&format!("Index {} out of range for {:?}", index, name));
+                    }
+                    continue;
+                }
+                Arg::Register(name) => self.register_size(name)?,
+            };
+            match size {
+                Some(m) if m != n => return self.err("Registers must have the same size"),
+                _ => size = Some(n),
+            }
+        }
+        let resolved = (0..size.unwrap_or(1))
+            .map(|i| {
+                args.iter()
+                    .map(|arg| match arg {
+                        Arg::Qubit(name, index) => (name.clone(), *index),
+                        Arg::Register(name) => (name.clone(), i),
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(resolved)
+    }
+
+    fn take_qubit(&mut self, name: &str, index: u64) -> Result<Register, CircuitError> {
+        let q = self
+            .qubits
+            .get_mut(name)
+            .and_then(|qs| qs.get_mut(index as usize))
+            .and_then(|q| q.take());
+        match q {
+            Some(q) => Ok(q),
+            None => self.err(&format!("Qubit {}[{}] used more than once", name, index)),
+        }
+    }
+
+    fn put_qubit(&mut self, name: &str, index: u64, q: Register) {
+        self.qubits.get_mut(name).unwrap()[index as usize] = Some(q);
+    }
+
+    fn measure(&mut self, b: &mut OpBuilder, qarg: Arg, carg: Arg) -> Result<(), CircuitError> {
+        let (cname, csize) = match &carg {
+            Arg::Qubit(name, _) | Arg::Register(name) => {
+                match self.cregs.iter().find(|(cname, _)| cname == name) {
+                    Some((cname, size)) => (cname.clone(), *size),
+                    None => return self.err(&format!("Unknown classical register {:?}", name)),
+                }
+            }
+        };
+        let pairs: Vec<((String, u64), u64)> = match (qarg, carg) {
+            (Arg::Qubit(qname, qi), Arg::Qubit(_, ci)) => vec![((qname, qi), ci)],
+            (Arg::Register(qname), Arg::Register(_)) => {
+                let n = self.register_size(&qname)?;
+                if n != csize {
+                    return self.err("Registers must have the same size");
+                }
+                (0..n).map(|i| ((qname.clone(), i), i)).collect()
+            }
+            _ => return self.err("Measure arguments must both be registers or both be bits"),
+        };
+        pairs.into_iter().try_for_each(|((qname, qi), ci)| {
+            if ci >= csize {
+                return self.err(&format!("Index {} out of range for {:?}", ci, cname));
+            }
+            self.resolve_args(&[Arg::Qubit(qname.clone(), qi)])?;
+            let q = self.take_qubit(&qname, qi)?;
+            let (q, handle) = b.measure(q);
+            self.put_qubit(&qname, qi, q);
+            self.measurements.push((cname.clone(), ci, handle));
+            Ok(())
+        })
+    }
+
+    fn apply_gate(
+        &mut self,
+        b: &mut OpBuilder,
+        name: &str,
+        params: &[f64],
+        qargs: &[(String, u64)],
+    ) -> Result<(), CircuitError> {
+        let qs = qargs
+            .iter()
+            .map(|(qname, index)| self.take_qubit(qname, *index))
+            .collect::<Result<Vec<_>, CircuitError>>()?;
+        let qs = self.apply_gate_to_qubits(b, name, params, qs)?;
+        qargs
+            .iter()
+            .zip(qs)
+            .for_each(|((qname, index), q)| self.put_qubit(qname, *index, q));
+        Ok(())
+    }
+
+    fn apply_gate_to_qubits(
+        &mut self,
+        b: &mut OpBuilder,
+        name: &str,
+        params: &[f64],
+        qs: Vec<Register>,
+    ) -> Result<Vec<Register>, CircuitError> {
+        if name != "U" && name != "CX" && self.gates.contains_key(name) {
+            return self.apply_defined_gate(b, name, params, qs);
+        }
+        let expected = match name {
+            "rx" | "ry" | "rz" | "u1" | "p" | "crx" | "cry" | "crz" | "cu1" | "cp" => 1,
+            "u2" => 2,
+            "u3" | "u" | "U" | "cu3" => 3,
+            _ => 0,
+        };
+        if params.len() != expected {
+            let message = format!(
+                "Gate {:?} expects {} parameters but found {}",
+                name,
+                expected,
+                params.len()
+            );
+            return self.err(&message);
+        }
+        let nqubits = match name {
+            "cx" | "CX" | "cy" | "cz" | "ch" | "crx" | "cry" | "crz" | "cu1" | "cp" | "cu3"
+            | "swap" => 2,
+            "ccx" | "cswap" => 3,
+            _ => 1,
+        };
+        if qs.len() != nqubits {
+            let message = format!(
+                "Gate {:?} expects {} qubits but found {}",
+                name,
+                nqubits,
+                qs.len()
+            );
+            return self.err(&message);
+        }
+        let mut qs = qs;
+        let q = qs.pop().unwrap();
+        let qs = match (name, qs.len()) {
+            ("id", 0) => vec![q],
+            ("x", 0) => vec![b.x(q)],
+            ("y", 0) => vec![b.y(q)],
+            ("z", 0) => vec![b.z(q)],
+            ("h", 0) => vec![b.hadamard(q)],
+            ("s", 0) => vec![b.s(q)],
+            ("sdg", 0) => vec![b.sdagger(q)],
+            ("t", 0) => vec![b.t(q)],
+            ("tdg", 0) => vec![b.tdagger(q)],
+            ("rx", 0) => vec![b.rx(q, params[0])],
+            ("ry", 0) => vec![b.ry(q, params[0])],
+            ("rz", 0) => vec![b.rz(q, params[0])],
+            ("u1", 0) | ("p", 0) => vec![b.mat(name, q, u3_matrix(0.0, 0.0, params[0]))?],
+            ("u2", 0) => {
+                let mat = u3_matrix(std::f64::consts::FRAC_PI_2, params[0], params[1]);
+                vec![b.mat(name, q, mat)?]
+            }
+            ("u3", 0) | ("u", 0) | ("U", 0) => {
+                let mat = u3_matrix(params[0], params[1], params[2]);
+                vec![b.mat(name, q, mat)?]
+            }
+            ("swap", 1) => {
+                let (qa, qb) = b.swap(qs.pop().unwrap(), q)?;
+                vec![qa, qb]
+            }
+            (_, 1) => {
+                let c = qs.pop().unwrap();
+                let (c, q) = match name {
+                    "cx" | "CX" => b.cnot(c, q),
+                    "cy" => b.cy(c, q),
+                    "cz" => b.cz(c, q),
+                    "ch" => {
+                        let mut cb = b.with_condition(c);
+                        let q = cb.hadamard(q);
+                        (cb.release_register(), q)
+                    }
+                    "crx" => b.crx(c, q, params[0]),
+                    "cry" => b.cry(c, q, params[0]),
+                    "crz" => b.crz(c, q, params[0]),
+                    "cu1" | "cp" => b.cmat(name, c, q, u3_matrix(0.0, 0.0, params[0]))?,
+                    _ => {
+                        let mat = u3_matrix(params[0], params[1], params[2]);
+                        b.cmat(name, c, q, mat)?
+                    }
+                };
+                vec![c, q]
+            }
+            ("ccx", 2) => {
+                let cb = qs.pop().unwrap();
+                let ca = qs.pop().unwrap();
+                let cr = b.merge(vec![ca, cb])?;
+                let (cr, q) = b.cnot(cr, q);
+                let (ca, cb) = b.split(cr, &[0])?;
+                vec![ca, cb.unwrap(), q]
+            }
+            ("cswap", 2) => {
+                let qa = qs.pop().unwrap();
+                let c = qs.pop().unwrap();
+                let (c, qa, qb) = b.cswap(c, qa, q)?;
+                vec![c, qa, qb]
+            }
+            _ => return self.err(&format!("Unknown gate {:?}", name)),
+        };
+        Ok(qs)
+    }
+
+    fn apply_defined_gate(
+        &mut self,
+        b: &mut OpBuilder,
+        name: &str,
+        params: &[f64],
+        qs: Vec<Register>,
+    ) -> Result<Vec<Register>, CircuitError> {
+        let def = self.gates.get(name).unwrap();
+        if def.params.len() != params.len() || def.qargs.len() != qs.len() {
+            let message = format!(
+                "Gate {:?} expects {} parameters and {} qubits",
+                name,
+                def.params.len(),
+                def.qargs.len()
+            );
+            return self.err(&message);
+        }
+        let env: HashMap<String, f64> = def
+            .params
+            .iter()
+            .cloned()
+            .zip(params.iter().cloned())
+            .collect();
+        let qarg_names = def.qargs.clone();
+        let body = def.body.clone();
+
+        // Evaluate the body with a parser over just the body tokens, sharing the gate table and
+        // treating each qarg as a single qubit register.
+        let gates = std::mem::take(&mut self.gates);
+        let mut sub = Parser {
+            tokens: body,
+            pos: 0,
+            qubits: qarg_names
+                .iter()
+                .cloned()
+                .zip(qs.into_iter().map(|q| vec![Some(q)]))
+                .collect(),
+            qreg_order: vec![],
+            cregs: vec![],
+            gates,
+            measurements: vec![],
+        };
+        b.push_name_scope(name);
+        let result = sub.parse_gate_body(b, &env);
+        b.pop_name_scope();
+        self.gates = sub.gates;
+        result?;
+        let mut qubits = sub.qubits;
+        let qs = qarg_names
+            .iter()
+            .map(|qname| qubits.remove(qname).unwrap().pop().unwrap().unwrap())
+            .collect();
+        Ok(qs)
+    }
+
+    fn parse_gate_body(
+        &mut self,
+        b: &mut OpBuilder,
+        env: &HashMap<String, f64>,
+    ) -> Result<(), CircuitError> {
+        while self.pos < self.tokens.len() {
+            let name = self.expect_ident()?;
+            if name == "barrier" {
+                while !self.is_symbol(";") {
+                    self.next()?;
+                }
+                self.expect_symbol(";")?;
+                continue;
+            }
+            let params = self.parse_params(env)?;
+            let mut qargs = vec![(self.expect_ident()?, 0)];
+            while self.is_symbol(",") {
+                self.pos += 1;
+                qargs.push((self.expect_ident()?, 0));
+            }
+            self.expect_symbol(";")?;
+            if let Some((qname, _)) = qargs.iter().find(|(q, _)| !self.qubits.contains_key(q)) {
+                return self.err(&format!("Unknown qubit {:?} in gate body", qname));
+            }
+            self.apply_gate(b, &name, &params, &qargs)?;
+        }
+        Ok(())
+    }
+}
+
+/// Serialize the circuit which produces `r` as an OpenQASM 2.0 program using the gates of
+/// `qelib1.inc`, on a single quantum register `q` which keeps the qubit indices of the circuit.
+/// Gates registered with `OpBuilder::define_gate` are emitted once as `gate` declarations and
+/// applied by name. Each measurement writes to its own classical register `m{id}` (where `id` is
+/// given by `MeasurementHandle::get_id`), with bit `i` holding the measurement of the `i`th qubit
+/// of the measured Register.
+///
+/// Single qubit ops are written as standard gates where possible and as `u3` otherwise, dropping
+/// their global phase. Ops with a single control are written with the controlled gates of
+/// `qelib1.inc`, correcting the phase on the control, along with `ccx` and `cswap`. Any other op
+/// has no equivalent and produces an error, as do stochastic measurements, classical side
+/// channels, parameterized ops, and channels other than `reset`.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::qasm::to_qasm;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// b.define_gate("bell", &[1, 1], |b, mut rs| {
+///     let rb = rs.pop().unwrap();
+///     let ra = b.hadamard(rs.pop().unwrap());
+///     let (ra, rb) = b.cnot(ra, rb);
+///     Ok(vec![ra, rb])
+/// })?;
+/// let q = b.qubit();
+/// let r = b.qubit();
+/// let qr = b.apply_gate("bell", vec![q, r])?;
+/// let qr = b.apply_gate("bell", qr)?;
+///
+/// let program = to_qasm(&b.merge(qr)?)?;
+/// let expected = "OPENQASM 2.0;\ninclude \"qelib1.inc\";\n\
+///                 gate bell q0,q1 {\n  h q0;\n  cx q0,q1;\n}\n\
+///                 qreg q[2];\nbell q[0],q[1];\nbell q[0],q[1];\n";
+/// assert_eq!(program, expected);
+/// # Ok(())
+/// # }
+/// ```
+pub fn to_qasm(r: &Register) -> Result<String, CircuitError> {
+    let (frontier, ops) = get_opfns_and_frontier(r);
+    let mut program = QasmProgram {
+        n: get_required_state_size_from_frontier(&frontier),
+        ..Default::default()
+    };
+    ops.into_iter()
+        .try_for_each(|modifier| program.add_modifier(modifier))?;
+    Ok(program.to_string())
+}
+
+#[derive(Default)]
+struct QasmProgram {
+    n: u64,
+    cregs: Vec<String>,
+    gate_names: Vec<String>,
+    gate_definitions: Vec<String>,
+    body: Vec<String>,
+}
+
+impl QasmProgram {
+    fn add_modifier(&mut self, modifier: &StateModifier) -> Result<(), CircuitError> {
+        match &modifier.modifier {
+            StateModifierType::MeasureState(id, indices, angle) => {
+                let name = format!("m{}", id);
+                self.cregs
+                    .push(format!("creg {}[{}];", name, indices.len()));
+                if *angle != 0.0 {
+                    indices.iter().for_each(|indx| {
+                        self.body
+                            .push(format!("ry({:?}) q[{}];", 2.0 * angle, indx))
+                    });
+                }
+                indices.iter().enumerate().for_each(|(i, indx)| {
+                    self.body
+                        .push(format!("measure q[{}] -> {}[{}];", indx, name, i));
+                });
+                if *angle != 0.0 {
+                    indices.iter().for_each(|indx| {
+                        self.body
+                            .push(format!("ry({:?}) q[{}];", -2.0 * angle, indx))
+                    });
+                }
+                Ok(())
+            }
+            StateModifierType::Channel(indices, _)
+                if modifier.name.rsplit('/').next() == Some("reset") =>
+            {
+                indices
+                    .iter()
+                    .for_each(|indx| self.body.push(format!("reset q[{}];", indx)));
+                Ok(())
+            }
+            StateModifierType::Subcircuit(modifiers) => modifiers
+                .iter()
+                .try_for_each(|modifier| self.add_modifier(modifier)),
+            StateModifierType::Loop(body, indices) => body
+                .unroll(indices, None)?
+                .iter()
+                .try_for_each(|modifier| self.add_modifier(modifier)),
+            StateModifierType::Debug(_, _) => Ok(()),
+            StateModifierType::StochasticMeasureState(_, _, _) => {
+                CircuitError::make_str_err("Stochastic measurements cannot be exported to OpenQASM")
+            }
+            StateModifierType::SideChannelModifiers(_, _) => {
+                CircuitError::make_str_err("Classical side channels cannot be exported to OpenQASM")
+            }
+            StateModifierType::ParameterizedOp(_, _) => {
+                CircuitError::make_str_err("Parameterized ops cannot be exported to OpenQASM")
+            }
+            StateModifierType::Channel(_, _) => CircuitError::make_err(format!(
+                "Channel {:?} cannot be exported to OpenQASM",
+                modifier.name
+            )),
+            StateModifierType::UnitaryOp(_) | StateModifierType::Gate(_, _) => {
+                let instructions = self.unitary_instructions(modifier)?;
+                self.body
+                    .extend(instructions.into_iter().map(|(gate, qubits)| {
+                        format_instruction(&gate, &qubits, |q| format!("q[{}]", q))
+                    }));
+                Ok(())
+            }
+        }
+    }
+
+    /// Get the gates and qubits which together make up the unitary `modifier`, adding the
+    /// declarations of any named gates it uses.
+    fn unitary_instructions(
+        &mut self,
+        modifier: &StateModifier,
+    ) -> Result<Vec<(String, Vec<u64>)>, CircuitError> {
+        match &modifier.modifier {
+            StateModifierType::UnitaryOp(op) => op_instructions(op).ok_or_else(|| {
+                CircuitError::new(format!(
+                    "Op {:?} has no equivalent in OpenQASM, decompose it before exporting",
+                    modifier.name
+                ))
+            }),
+            StateModifierType::Gate(def, indices) => {
+                self.define_gate(def)?;
+                Ok(vec![(def.name().to_string(), indices.clone())])
+            }
+            StateModifierType::Subcircuit(modifiers) => {
+                modifiers.iter().try_fold(vec![], |mut acc, modifier| {
+                    acc.extend(self.unitary_instructions(modifier)?);
+                    Ok(acc)
+                })
+            }
+            _ => CircuitError::make_err(format!(
+                "Op {:?} is not unitary and cannot be exported in a gate",
+                modifier.name
+            )),
+        }
+    }
+
+    /// Add a `gate` declaration for `def` if there isn't one already, after those of any gates
+    /// it uses.
+    fn define_gate(&mut self, def: &GateDefinition) -> Result<(), CircuitError> {
+        if self.gate_names.iter().any(|name| name == def.name()) {
+            return Ok(());
+        }
+        let instructions = def
+            .modifiers()
+            .iter()
+            .try_fold(vec![], |mut acc, modifier| {
+                acc.extend(self.unitary_instructions(modifier)?);
+                Ok(acc)
+            })?;
+        let qargs: Vec<String> = (0..def.n()).map(|q| format!("q{}", q)).collect();
+        let mut definition = format!("gate {} {} {{\n", def.name(), qargs.join(","));
+        instructions.into_iter().for_each(|(gate, qubits)| {
+            let line = format_instruction(&gate, &qubits, |q| format!("q{}", q));
+            definition.push_str(&format!("  {}\n", line));
+        });
+        definition.push('}');
+        self.gate_names.push(def.name().to_string());
+        self.gate_definitions.push(definition);
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for QasmProgram {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "OPENQASM 2.0;")?;
+        writeln!(f, "include \"qelib1.inc\";")?;
+        self.gate_definitions
+            .iter()
+            .try_for_each(|d| writeln!(f, "{}", d))?;
+        writeln!(f, "qreg q[{}];", self.n)?;
+        self.cregs.iter().try_for_each(|d| writeln!(f, "{}", d))?;
+        self.body
+            .iter()
+            .try_for_each(|line| writeln!(f, "{}", line))
+    }
+}
+
+fn format_instruction<F: Fn(u64) -> String>(gate: &str, qubits: &[u64], name: F) -> String {
+    let qubits: Vec<String> = qubits.iter().map(|q| name(*q)).collect();
+    format!("{} {};", gate, qubits.join(","))
+}
+
+/// Get the `qelib1.inc` gates making up `op`, if it has an equivalent.
+fn op_instructions(op: &UnitaryOp) -> Option<Vec<(String, Vec<u64>)>> {
+    match op {
+        UnitaryOp::Swap(a_indices, b_indices) => Some(
+            a_indices
+                .iter()
+                .zip(b_indices.iter())
+                .map(|(a, b)| ("swap".to_string(), vec![*a, *b]))
+                .collect(),
+        ),
+        UnitaryOp::Matrix(indices, mat) if indices.len() == 1 => {
+            let (gate, _) = single_qubit_gate(mat)?;
+            Some(vec![(gate, indices.clone())])
+        }
+        UnitaryOp::Control(c_indices, _, op) => match (c_indices.as_slice(), op.as_ref()) {
+            ([c], UnitaryOp::Matrix(indices, mat)) if indices.len() == 1 => {
+                let t = indices[0];
+                let (gate, phase) = single_qubit_gate(mat)?;
+                match gate.as_str() {
+                    "x" | "y" | "z" | "h" if phase == 0.0 => {
+                        Some(vec![(format!("c{}", gate), vec![*c, t])])
+                    }
+                    _ => {
+                        let (theta, phi, lambda) = u3_angles(mat).ok()?;
+                        let phase = global_phase(mat, &u3_matrix(theta, phi, lambda))?;
+                        let mut gates = vec![];
+                        if phase != 0.0 {
+                            gates.push((format!("u1({:?})", phase), vec![*c]));
+                        }
+                        let gate = format!("cu3({:?},{:?},{:?})", theta, phi, lambda);
+                        gates.push((gate, vec![*c, t]));
+                        Some(gates)
+                    }
+                }
+            }
+            ([c], UnitaryOp::Swap(a, b)) if a.len() == 1 && b.len() == 1 => {
+                Some(vec![("cswap".to_string(), vec![*c, a[0], b[0]])])
+            }
+            ([ca, cb], UnitaryOp::Matrix(indices, mat)) if indices.len() == 1 => {
+                match single_qubit_gate(mat)? {
+                    (ref gate, phase) if gate == "x" && phase == 0.0 => {
+                        Some(vec![("ccx".to_string(), vec![*ca, *cb, indices[0]])])
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Find the `qelib1.inc` gate for the 2x2 unitary `mat`, along with the global phase `alpha` such
+/// that `mat` is `e^{i alpha}` times the gate.
+fn single_qubit_gate(mat: &[Complex<f64>]) -> Option<(String, f64)> {
+    let c = |re: f64, im: f64| Complex { re, im };
+    let h = std::f64::consts::FRAC_1_SQRT_2;
+    let fixed = [
+        ("id", [c(1.0, 0.0), c(0.0, 0.0), c(0.0, 0.0), c(1.0, 0.0)]),
+        ("x", [c(0.0, 0.0), c(1.0, 0.0), c(1.0, 0.0), c(0.0, 0.0)]),
+        ("y", [c(0.0, 0.0), c(0.0, -1.0), c(0.0, 1.0), c(0.0, 0.0)]),
+        ("z", [c(1.0, 0.0), c(0.0, 0.0), c(0.0, 0.0), c(-1.0, 0.0)]),
+        ("h", [c(h, 0.0), c(h, 0.0), c(h, 0.0), c(-h, 0.0)]),
+        ("s", [c(1.0, 0.0), c(0.0, 0.0), c(0.0, 0.0), c(0.0, 1.0)]),
+        ("sdg", [c(1.0, 0.0), c(0.0, 0.0), c(0.0, 0.0), c(0.0, -1.0)]),
+        ("t", [c(1.0, 0.0), c(0.0, 0.0), c(0.0, 0.0), c(h, h)]),
+        ("tdg", [c(1.0, 0.0), c(0.0, 0.0), c(0.0, 0.0), c(h, -h)]),
+    ];
+    // Prefer gates equal to `mat` exactly, so that they can be controlled without a correction.
+    let exact = fixed
+        .iter()
+        .find(|(_, m)| global_phase(mat, m) == Some(0.0));
+    if let Some((name, _)) = exact {
+        return Some((name.to_string(), 0.0));
+    }
+    let up_to_phase = fixed
+        .iter()
+        .find_map(|(name, m)| global_phase(mat, m).map(|phase| (name.to_string(), phase)));
+    if up_to_phase.is_some() {
+        return up_to_phase;
+    }
+    let (theta, phi, lambda) = u3_angles(mat).ok()?;
+    let phase = global_phase(mat, &u3_matrix(theta, phi, lambda))?;
+    Some((format!("u3({:?},{:?},{:?})", theta, phi, lambda), phase))
+}
+
+/// Find `alpha` with `a = e^{i alpha} b`, rounded to `0` when it is negligible.
+fn global_phase(a: &[Complex<f64>], b: &[Complex<f64>]) -> Option<f64> {
+    let (index, _) = b.iter().enumerate().fold((0, 0.0), |(best, norm), (i, x)| {
+        if x.norm() > norm {
+            (i, x.norm())
+        } else {
+            (best, norm)
+        }
+    });
+    let phase = (a[index] / b[index]).arg();
+    let rotation = Complex::from_polar(&1.0, &phase);
+    let equal = a
+        .iter()
+        .zip(b.iter())
+        .all(|(a, b)| (a - b * rotation).norm() < 1e-10);
+    match (equal, phase.abs() < 1e-10) {
+        (false, _) => None,
+        (true, true) => Some(0.0),
+        (true, false) => Some(phase),
+    }
+}
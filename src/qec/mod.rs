@@ -0,0 +1,96 @@
+/// Repetition codes protecting a qubit against bit flips.
+pub mod repetition;
+/// Rotated surface code patches.
+pub mod surface;
+
+use crate::errors::CircuitError;
+use crate::pipeline::MeasurementHandle;
+use crate::{OpBuilder, Register, UnitaryBuilder};
+
+/// Measure the Pauli string `pauli` made of `I`, `X`, `Y` and `Z` on `data` using the single
+/// qubit `ancilla`, where the character at position `j` acts on qubit `j` of `data`. The ancilla
+/// should start as `|0>`, it is put into `|+>` and controls each Pauli onto the data before being
+/// rotated back and measured. A measured `1` means the data was projected onto the `-1`
+/// eigenspace of the string.
+///
+/// Returns the measured ancilla, which is not reset, the data in the same order, and the handle
+/// for the measurement.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::qec::measure_stabilizer;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let data = b.register(2)?;
+/// let ancilla = b.qubit();
+/// // Prepare (|01> + |10>)/sqrt(2), which is a -1 eigenstate of ZZ and a +1 eigenstate of XX.
+/// let (q, r) = b.split(data, &[0])?;
+/// let q = b.hadamard(q);
+/// let r = b.x(r.unwrap());
+/// let (q, r) = b.cnot(q, r);
+/// let data = b.merge(vec![q, r])?;
+/// let (ancilla, data, zz) = measure_stabilizer(&mut b, ancilla, data, "ZZ")?;
+/// let ancilla = b.reset(ancilla);
+/// let (ancilla, data, xx) = measure_stabilizer(&mut b, ancilla, data, "XX")?;
+///
+/// let r = b.merge(vec![ancilla, data])?;
+/// let (_, measured) = run_local::<f64>(&r)?;
+/// assert_eq!(measured.get_measurement(&zz).unwrap().0, 1);
+/// assert_eq!(measured.get_measurement(&xx).unwrap().0, 0);
+/// # Ok(())
+/// # }
+/// ```
+pub fn measure_stabilizer(
+    b: &mut OpBuilder,
+    ancilla: Register,
+    data: Register,
+    pauli: &str,
+) -> Result<(Register, Register, MeasurementHandle), CircuitError> {
+    if ancilla.n() != 1 {
+        let message = format!("Ancilla must be a single qubit, found {:?}", ancilla.n());
+        return CircuitError::make_err(message);
+    }
+    if pauli.len() as u64 > data.n() {
+        let message = format!(
+            "Pauli string {:?} is longer than the register ({:?} qubits)",
+            pauli,
+            data.n()
+        );
+        return CircuitError::make_err(message);
+    }
+    if let Some(c) = pauli.chars().find(|c| !"IXYZ".contains(*c)) {
+        let message = format!(
+            "Pauli strings may only contain I, X, Y and Z, found {:?}",
+            c
+        );
+        return CircuitError::make_err(message);
+    }
+
+    let ancilla = b.hadamard(ancilla);
+    let mut cb = b.with_condition(ancilla);
+    let qubits = cb
+        .split_all(data)
+        .into_iter()
+        .zip(pauli.chars().chain(std::iter::repeat('I')))
+        .map(|(q, c)| match c {
+            'X' => cb.x(q),
+            'Y' => cb.y(q),
+            'Z' => cb.z(q),
+            _ => q,
+        })
+        .collect();
+    let data = cb.merge(qubits)?;
+    let ancilla = cb.release_register();
+    let ancilla = b.hadamard(ancilla);
+    let (ancilla, m) = b.measure(ancilla);
+    Ok((ancilla, data, m))
+}
+
+/// Make a Pauli string on `n` qubits with `pauli` at each of `positions` and `I` elsewhere.
+pub(crate) fn pauli_on_positions(n: u64, positions: &[u64], pauli: char) -> String {
+    (0..n)
+        .map(|i| if positions.contains(&i) { pauli } else { 'I' })
+        .collect()
+}
@@ -0,0 +1,215 @@
+use crate::errors::CircuitError;
+use crate::pipeline::{MeasuredResults, MeasurementHandle};
+use crate::qec::{measure_stabilizer, pauli_on_positions};
+use crate::{OpBuilder, Precision, Register, UnitaryBuilder};
+
+/// A bit flip repetition code, storing one logical qubit as `n` data qubits which all agree. The
+/// parity of each neighbouring pair of data qubits is measured onto its own ancilla, which finds
+/// any `(n - 1) / 2` or fewer bit flips without disturbing the logical state.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::qec::repetition::RepetitionCode;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let code = RepetitionCode::new(3)?;
+/// let mut b = OpBuilder::new();
+/// let q = b.qubit();
+/// let q = b.ry(q, 0.8);
+/// let data = code.encode(&mut b, q)?;
+///
+/// // Flip the middle qubit, then find and undo the flip.
+/// let (first, rest) = b.split(data, &[0])?;
+/// let (middle, last) = b.split(rest.unwrap(), &[0])?;
+/// let middle = b.not(middle);
+/// let data = b.merge(vec![first, middle, last.unwrap()])?;
+/// let ancillas = b.register(code.num_ancilla_qubits())?;
+/// let (data, ancillas, syndrome) = code.measure_syndrome(&mut b, data, ancillas)?;
+/// let data = code.correct(&mut b, data, &syndrome)?;
+/// let (q, rest) = code.decode(&mut b, data)?;
+///
+/// let r = b.merge(vec![q, rest, ancillas])?;
+/// let (state, measured) = run_local::<f64>(&r)?;
+/// assert_eq!(code.syndrome_values(&syndrome, &measured), vec![true, true]);
+/// let state = state.get_state(true);
+/// assert!((state[0].re - 0.4f64.cos()).abs() < 1e-10);
+/// assert!((state[1].re - 0.4f64.sin()).abs() < 1e-10);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct RepetitionCode {
+    n: u64,
+}
+
+impl RepetitionCode {
+    /// Make a code with `n` data qubits, `n` must be odd and at least 3.
+    pub fn new(n: u64) -> Result<Self, CircuitError> {
+        if n < 3 || n & 1 == 0 {
+            let message = format!(
+                "Repetition code size must be odd and at least 3, not {:?}",
+                n
+            );
+            return CircuitError::make_err(message);
+        }
+        Ok(RepetitionCode { n })
+    }
+
+    /// Get the number of data qubits.
+    pub fn n(&self) -> u64 {
+        self.n
+    }
+
+    /// Get the number of ancilla qubits needed to measure the syndrome, one for each neighbouring
+    /// pair of data qubits.
+    pub fn num_ancilla_qubits(&self) -> u64 {
+        self.n - 1
+    }
+
+    /// Encode the single qubit `q` by copying it onto `n - 1` new qubits, returning the data
+    /// Register with `q` first.
+    pub fn encode(&self, b: &mut OpBuilder, q: Register) -> Result<Register, CircuitError> {
+        if q.n() != 1 {
+            let message = format!("Can only encode a single qubit, found {:?}", q.n());
+            return CircuitError::make_err(message);
+        }
+        let copies = b.register(self.n - 1)?;
+        let (q, copies) = b.cnot(q, copies);
+        b.merge(vec![q, copies])
+    }
+
+    /// Undo `encode`, returning the logical qubit along with the other `n - 1` data qubits which
+    /// are left as `|0>` if there are no errors.
+    pub fn decode(
+        &self,
+        b: &mut OpBuilder,
+        data: Register,
+    ) -> Result<(Register, Register), CircuitError> {
+        self.check_data(&data)?;
+        let (q, copies) = b.split(data, &[0])?;
+        let (q, copies) = b.cnot(q, copies.unwrap());
+        Ok((q, copies))
+    }
+
+    /// Measure the parity of each neighbouring pair of data qubits onto `ancillas`, which are
+    /// reset to `|0>` afterwards so further rounds can reuse them. Returns a handle for each pair
+    /// in order.
+    pub fn measure_syndrome(
+        &self,
+        b: &mut OpBuilder,
+        data: Register,
+        ancillas: Register,
+    ) -> Result<(Register, Register, Vec<MeasurementHandle>), CircuitError> {
+        self.check_data(&data)?;
+        if ancillas.n() != self.num_ancilla_qubits() {
+            let message = format!(
+                "Expected {:?} ancilla qubits, found {:?}",
+                self.num_ancilla_qubits(),
+                ancillas.n()
+            );
+            return CircuitError::make_err(message);
+        }
+        let mut data = data;
+        let mut measured_ancillas = vec![];
+        let mut handles = vec![];
+        for (i, ancilla) in b.split_all(ancillas).into_iter().enumerate() {
+            let i = i as u64;
+            let pauli = pauli_on_positions(self.n, &[i, i + 1], 'Z');
+            let (ancilla, measured_data, m) = measure_stabilizer(b, ancilla, data, &pauli)?;
+            data = measured_data;
+            handles.push(m);
+            measured_ancillas.push(b.reset(ancilla));
+        }
+        let ancillas = b.merge(measured_ancillas)?;
+        Ok((data, ancillas, handles))
+    }
+
+    /// Flip back the data qubits which disagree with the majority, as found from the measured
+    /// `syndrome` of `measure_syndrome`. See `errors_from_syndrome`.
+    pub fn correct(
+        &self,
+        b: &mut OpBuilder,
+        data: Register,
+        syndrome: &[MeasurementHandle],
+    ) -> Result<Register, CircuitError> {
+        self.check_data(&data)?;
+        if syndrome.len() as u64 != self.num_ancilla_qubits() {
+            let message = format!(
+                "Expected {:?} syndrome measurements, found {:?}",
+                self.num_ancilla_qubits(),
+                syndrome.len()
+            );
+            return CircuitError::make_err(message);
+        }
+        let code = *self;
+        Ok(b.single_register_classical_sidechannel(
+            data,
+            syndrome,
+            Box::new(move |b, data, measured| {
+                let syndrome: Vec<bool> = measured.iter().map(|m| *m == 1).collect();
+                let flips = code.errors_from_syndrome(&syndrome);
+                if flips.is_empty() {
+                    return Ok(data);
+                }
+                let qubits = b
+                    .split_all(data)
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, q)| {
+                        if flips.contains(&(i as u64)) {
+                            b.not(q)
+                        } else {
+                            q
+                        }
+                    })
+                    .collect();
+                b.merge(qubits)
+            }),
+        ))
+    }
+
+    /// Find the data qubits which disagree with the majority given the parities of neighbouring
+    /// pairs, where `syndrome[i]` is `true` if qubits `i` and `i + 1` differ.
+    pub fn errors_from_syndrome(&self, syndrome: &[bool]) -> Vec<u64> {
+        // Assuming qubit 0 is correct fixes every other qubit relative to it.
+        let mut flipped = vec![false];
+        syndrome.iter().for_each(|s| {
+            let last = *flipped.last().unwrap();
+            flipped.push(last ^ *s);
+        });
+        let num_flipped = flipped.iter().filter(|f| **f).count();
+        // If most qubits disagree with qubit 0, it is the one in error.
+        let flip_value = num_flipped * 2 <= flipped.len();
+        (0..flipped.len() as u64)
+            .filter(|i| flipped[*i as usize] == flip_value)
+            .collect()
+    }
+
+    /// Get the measured syndrome bits from a run of the circuit, where `true` means the pair of
+    /// qubits had odd parity.
+    pub fn syndrome_values<P: Precision>(
+        &self,
+        syndrome: &[MeasurementHandle],
+        measured: &MeasuredResults<P>,
+    ) -> Vec<bool> {
+        syndrome
+            .iter()
+            .map(|m| {
+                measured
+                    .get_measurement(m)
+                    .map(|(v, _)| v == 1)
+                    .unwrap_or(false)
+            })
+            .collect()
+    }
+
+    fn check_data(&self, data: &Register) -> Result<(), CircuitError> {
+        if data.n() != self.n {
+            let message = format!("Expected {:?} data qubits, found {:?}", self.n, data.n());
+            CircuitError::make_err(message)
+        } else {
+            Ok(())
+        }
+    }
+}
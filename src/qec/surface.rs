@@ -0,0 +1,268 @@
+use crate::errors::CircuitError;
+use crate::pipeline::{MeasuredResults, MeasurementHandle};
+use crate::qec::{measure_stabilizer, pauli_on_positions};
+use crate::{OpBuilder, Precision, Register, UnitaryBuilder};
+
+/// The layout of a distance `d` rotated surface code patch, with `d * d` data qubits on a grid
+/// and one ancilla for each of its `d * d - 1` stabilizers.
+///
+/// Data qubit `(row, col)` is at position `row * d + col` of the data Register. Stabilizers are
+/// given as positions in the data Register, and each uses the ancilla at the same position in the
+/// ancilla Register with the X stabilizers first. X stabilizers of weight two lie along the top and
+/// bottom of the grid, and Z stabilizers of weight two along the left and right sides, so logical
+/// Z is a row of Z gates and logical X a column of X gates.
+#[derive(Debug, Clone)]
+pub struct SurfaceCode {
+    distance: u64,
+    x_stabilizers: Vec<Vec<u64>>,
+    z_stabilizers: Vec<Vec<u64>>,
+}
+
+/// Measurements of the stabilizers from one round of `SurfaceCode::measure_stabilizers`.
+#[derive(Debug)]
+pub struct SurfaceSyndrome {
+    /// Measurements of the X stabilizers, in order.
+    pub x: Vec<MeasurementHandle>,
+    /// Measurements of the Z stabilizers, in order.
+    pub z: Vec<MeasurementHandle>,
+}
+
+impl SurfaceSyndrome {
+    /// Get the syndrome bits for the X and Z stabilizers from a run of the circuit, where `true`
+    /// means the stabilizer was measured as `-1`.
+    pub fn values<P: Precision>(&self, measured: &MeasuredResults<P>) -> (Vec<bool>, Vec<bool>) {
+        let bits = |handles: &[MeasurementHandle]| {
+            handles
+                .iter()
+                .map(|m| {
+                    measured
+                        .get_measurement(m)
+                        .map(|(v, _)| v == 1)
+                        .unwrap_or(false)
+                })
+                .collect()
+        };
+        (bits(&self.x), bits(&self.z))
+    }
+}
+
+impl SurfaceCode {
+    /// Make the layout of a distance `d` patch, `d` must be at least 2.
+    pub fn new(distance: u64) -> Result<Self, CircuitError> {
+        if distance < 2 {
+            return CircuitError::make_str_err("Surface code distance must be at least 2.");
+        }
+        let d = distance as i64;
+        let mut x_stabilizers = vec![];
+        let mut z_stabilizers = vec![];
+        // Plaquette (row, col) sits at the corner shared by data qubits (row - 1, col - 1)
+        // through (row, col), alternating between X and Z across the grid.
+        (0..=d).for_each(|row| {
+            (0..=d).for_each(|col| {
+                let is_x = (row + col) % 2 == 0;
+                let on_top_or_bottom = row == 0 || row == d;
+                let on_side = col == 0 || col == d;
+                let keep = match (on_top_or_bottom, on_side) {
+                    (false, false) => true,
+                    (true, false) => is_x,
+                    (false, true) => !is_x,
+                    (true, true) => false,
+                };
+                if !keep {
+                    return;
+                }
+                let qubits = [
+                    (row - 1, col - 1),
+                    (row - 1, col),
+                    (row, col - 1),
+                    (row, col),
+                ]
+                .iter()
+                .filter(|(r, c)| *r >= 0 && *r < d && *c >= 0 && *c < d)
+                .map(|(r, c)| (r * d + c) as u64)
+                .collect();
+                if is_x {
+                    x_stabilizers.push(qubits);
+                } else {
+                    z_stabilizers.push(qubits);
+                }
+            })
+        });
+        Ok(SurfaceCode {
+            distance,
+            x_stabilizers,
+            z_stabilizers,
+        })
+    }
+
+    /// Get the distance of the code.
+    pub fn distance(&self) -> u64 {
+        self.distance
+    }
+
+    /// Get the number of data qubits, `d * d`.
+    pub fn num_data_qubits(&self) -> u64 {
+        self.distance * self.distance
+    }
+
+    /// Get the number of ancilla qubits, one for each stabilizer.
+    pub fn num_ancilla_qubits(&self) -> u64 {
+        (self.x_stabilizers.len() + self.z_stabilizers.len()) as u64
+    }
+
+    /// Get the position of data qubit `(row, col)` in the data Register.
+    pub fn data_index(&self, row: u64, col: u64) -> u64 {
+        row * self.distance + col
+    }
+
+    /// Get the data qubits of each X stabilizer.
+    pub fn x_stabilizers(&self) -> &[Vec<u64>] {
+        &self.x_stabilizers
+    }
+
+    /// Get the data qubits of each Z stabilizer.
+    pub fn z_stabilizers(&self) -> &[Vec<u64>] {
+        &self.z_stabilizers
+    }
+
+    /// Get the data qubits of the logical X operator, the first column of the grid.
+    pub fn logical_x_qubits(&self) -> Vec<u64> {
+        (0..self.distance)
+            .map(|row| self.data_index(row, 0))
+            .collect()
+    }
+
+    /// Get the data qubits of the logical Z operator, the first row of the grid.
+    pub fn logical_z_qubits(&self) -> Vec<u64> {
+        (0..self.distance)
+            .map(|col| self.data_index(0, col))
+            .collect()
+    }
+
+    /// Make the data and ancilla Registers for a patch. Starting with every qubit as `|0>` gives
+    /// the logical `|0>` once the stabilizers have been measured.
+    pub fn make_registers(&self, b: &mut OpBuilder) -> Result<(Register, Register), CircuitError> {
+        let data = b.register(self.num_data_qubits())?;
+        let ancillas = b.register(self.num_ancilla_qubits())?;
+        Ok((data, ancillas))
+    }
+
+    /// Measure every stabilizer once, each with its own ancilla. The ancillas are reset to `|0>`
+    /// afterwards so further rounds can reuse them.
+    ///
+    /// # Example
+    /// ```
+    /// use qip::*;
+    /// use qip::qec::surface::SurfaceCode;
+    /// # fn main() -> Result<(), CircuitError> {
+    ///
+    /// let code = SurfaceCode::new(2)?;
+    /// let mut b = OpBuilder::new();
+    /// let (data, ancillas) = code.make_registers(&mut b)?;
+    /// let (data, ancillas, first) = code.measure_stabilizers(&mut b, data, ancillas)?;
+    /// let data = code.logical_x(&mut b, data)?;
+    /// let (data, ancillas, second) = code.measure_stabilizers(&mut b, data, ancillas)?;
+    /// let r = b.merge(vec![data, ancillas])?;
+    ///
+    /// // Logical operators commute with the stabilizers, so the syndrome doesn't change.
+    /// let (_, measured) = run_local::<f64>(&r)?;
+    /// let (first_x, first_z) = first.values(&measured);
+    /// let (second_x, second_z) = second.values(&measured);
+    /// assert_eq!(first_x, second_x);
+    /// assert_eq!(first_z, vec![false; 2]);
+    /// assert_eq!(second_z, vec![false; 2]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn measure_stabilizers(
+        &self,
+        b: &mut OpBuilder,
+        data: Register,
+        ancillas: Register,
+    ) -> Result<(Register, Register, SurfaceSyndrome), CircuitError> {
+        self.check_registers(&data, Some(&ancillas))?;
+        let n = self.num_data_qubits();
+        let num_x = self.x_stabilizers.len();
+        let mut data = data;
+        let mut measured_ancillas = vec![];
+        let mut syndrome = SurfaceSyndrome {
+            x: vec![],
+            z: vec![],
+        };
+        let stabilizers = self.x_stabilizers.iter().chain(self.z_stabilizers.iter());
+        for (i, (stabilizer, ancilla)) in stabilizers.zip(b.split_all(ancillas)).enumerate() {
+            let is_x = i < num_x;
+            let pauli = pauli_on_positions(n, stabilizer, if is_x { 'X' } else { 'Z' });
+            let (ancilla, measured_data, m) = measure_stabilizer(b, ancilla, data, &pauli)?;
+            data = measured_data;
+            if is_x {
+                syndrome.x.push(m);
+            } else {
+                syndrome.z.push(m);
+            }
+            measured_ancillas.push(b.reset(ancilla));
+        }
+        let ancillas = b.merge(measured_ancillas)?;
+        Ok((data, ancillas, syndrome))
+    }
+
+    /// Apply the logical X operator to the data qubits.
+    pub fn logical_x(&self, b: &mut OpBuilder, data: Register) -> Result<Register, CircuitError> {
+        self.check_registers(&data, None)?;
+        apply_to_positions(b, data, &self.logical_x_qubits(), |b, q| b.x(q))
+    }
+
+    /// Apply the logical Z operator to the data qubits.
+    pub fn logical_z(&self, b: &mut OpBuilder, data: Register) -> Result<Register, CircuitError> {
+        self.check_registers(&data, None)?;
+        apply_to_positions(b, data, &self.logical_z_qubits(), |b, q| b.z(q))
+    }
+
+    fn check_registers(
+        &self,
+        data: &Register,
+        ancillas: Option<&Register>,
+    ) -> Result<(), CircuitError> {
+        if data.n() != self.num_data_qubits() {
+            let message = format!(
+                "Expected {:?} data qubits, found {:?}",
+                self.num_data_qubits(),
+                data.n()
+            );
+            return CircuitError::make_err(message);
+        }
+        match ancillas {
+            Some(ancillas) if ancillas.n() != self.num_ancilla_qubits() => {
+                let message = format!(
+                    "Expected {:?} ancilla qubits, found {:?}",
+                    self.num_ancilla_qubits(),
+                    ancillas.n()
+                );
+                CircuitError::make_err(message)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Apply `f` to the qubits at `positions` within `r`, keeping the order of `r`.
+fn apply_to_positions<F: Fn(&mut OpBuilder, Register) -> Register>(
+    b: &mut OpBuilder,
+    r: Register,
+    positions: &[u64],
+    f: F,
+) -> Result<Register, CircuitError> {
+    let qubits = b
+        .split_all(r)
+        .into_iter()
+        .enumerate()
+        .map(|(i, q)| {
+            if positions.contains(&(i as u64)) {
+                f(b, q)
+            } else {
+                q
+            }
+        })
+        .collect();
+    b.merge(qubits)
+}
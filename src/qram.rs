@@ -0,0 +1,152 @@
+use crate::errors::CircuitError;
+use crate::{Register, UnitaryBuilder};
+
+/// Load the classical `memory` into `data` at the addresses held by `address`, applying
+/// `|a>|d> -> |a>|d ^ memory[a]>` with a bucket brigade of routing qubits. Addresses past the end
+/// of `memory` read as `0`.
+///
+/// The routers form a binary tree with one leaf for each of the `2^n` addresses: a single
+/// excitation starts at the root and each address qubit, least significant first, routes it down
+/// one level with controlled swaps, so that it ends up at leaf `a`. Each leaf then flips the bits
+/// of `data` set in its memory entry, and the routing is undone. The `2^n` routers are taken from
+/// `routers` if provided (and must be `|0>`), and otherwise temporarily from the builder. Returns
+/// the address, data, and the routers if given.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::qram::qram;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let memory = [3, 1, 0, 2];
+/// let mut b = OpBuilder::new();
+/// let address = b.register(2)?;
+/// let data = b.register(2)?;
+/// let handle = address.handle();
+/// let (address, data, _) = qram(&mut b, address, data, &memory, None)?;
+/// let (data, m) = b.measure(data);
+///
+/// let (_, measured) = run_local_with_init::<f64>(&data, &[handle.make_init_from_index(3)?])?;
+/// assert_eq!(measured.get_measurement(&m).unwrap().0, 2);
+/// # Ok(())
+/// # }
+/// ```
+pub fn qram(
+    b: &mut dyn UnitaryBuilder,
+    address: Register,
+    data: Register,
+    memory: &[u64],
+    routers: Option<Register>,
+) -> Result<(Register, Register, Option<Register>), CircuitError> {
+    let n = address.n();
+    if n >= 32 {
+        let message = format!("Address has too many qubits: {:?}", n);
+        return CircuitError::make_err(message);
+    }
+    let leaves = 1 << n;
+    if memory.len() > leaves {
+        let message = format!(
+            "Memory of {:?} entries does not fit in {:?} address qubits",
+            memory.len(),
+            n
+        );
+        return CircuitError::make_err(message);
+    }
+    let w = data.n();
+    if let Some(value) = memory.iter().find(|v| w < 64 && **v >> w != 0) {
+        let message = format!("Value {:?} does not fit in {:?} data qubits", value, w);
+        return CircuitError::make_err(message);
+    }
+    if let Some(routers) = &routers {
+        if routers.n() < leaves as u64 {
+            let message = format!(
+                "QRAM with {:?} address qubits requires {:?} routers, found {:?}",
+                n,
+                leaves,
+                routers.n()
+            );
+            return CircuitError::make_err(message);
+        }
+    }
+    // Select the routers to use, any extra routers given are returned untouched.
+    let use_temp = routers.is_none();
+    let (tree, extra) = match routers {
+        Some(routers) => b.split(routers, &(0..leaves as u64).collect::<Vec<_>>())?,
+        None => (b.get_temp_register(leaves as u64, false), None),
+    };
+
+    b.push_name_scope("QRAM");
+    let mut addr: Vec<Option<Register>> = b.split_all(address).into_iter().map(Some).collect();
+    let mut tree: Vec<Option<Register>> = b.split_all(tree).into_iter().map(Some).collect();
+    let mut ds: Vec<Option<Register>> = b.split_all(data).into_iter().map(Some).collect();
+    let result = route(b, &mut addr, &mut tree, false).and_then(|_| {
+        memory.iter().enumerate().for_each(|(i, value)| {
+            (0..w as usize)
+                .filter(|j| (value >> j) & 1 == 1)
+                .for_each(|j| {
+                    let (leaf, d) = b.cnot(tree[i].take().unwrap(), ds[j].take().unwrap());
+                    tree[i] = Some(leaf);
+                    ds[j] = Some(d);
+                })
+        });
+        route(b, &mut addr, &mut tree, true)
+    });
+    b.pop_name_scope();
+    result?;
+    let address = b.merge(addr.into_iter().map(Option::unwrap).collect())?;
+    let data = b.merge(ds.into_iter().map(Option::unwrap).collect())?;
+    let tree = b.merge(tree.into_iter().map(Option::unwrap).collect())?;
+
+    if use_temp {
+        // Ops are only run if they are in the history of the output, so tie the last ops on the
+        // temporary routers to the data before giving them back.
+        let data_n = data.n();
+        let merged = b.merge(vec![data, tree])?;
+        let (data, tree) = b.split(merged, &(0..data_n).collect::<Vec<_>>())?;
+        b.return_temp_register(tree.unwrap(), false);
+        Ok((address, data, None))
+    } else {
+        let routers = match extra {
+            Some(extra) => b.merge(vec![tree, extra])?,
+            None => tree,
+        };
+        Ok((address, data, Some(routers)))
+    }
+}
+
+/// Route an excitation from the root of `tree` down to the leaf given by `addr`, or back up to
+/// the root if `reverse`. Leaf `i` of the tree is `tree[i]`.
+fn route(
+    b: &mut dyn UnitaryBuilder,
+    addr: &mut [Option<Register>],
+    tree: &mut [Option<Register>],
+    reverse: bool,
+) -> Result<(), CircuitError> {
+    if !reverse {
+        tree[0] = Some(b.not(tree[0].take().unwrap()));
+    }
+    let levels: Vec<usize> = if reverse {
+        (0..addr.len()).rev().collect()
+    } else {
+        (0..addr.len()).collect()
+    };
+    // At level k the excitation is at one of the first 2^k nodes, and moves from node i to node
+    // i + 2^k when address qubit k is |1>.
+    levels.into_iter().try_for_each(|k| {
+        (0..1 << k).try_for_each(|i| {
+            let (c, left, right) = b.cswap(
+                addr[k].take().unwrap(),
+                tree[i].take().unwrap(),
+                tree[i + (1 << k)].take().unwrap(),
+            )?;
+            addr[k] = Some(c);
+            tree[i] = Some(left);
+            tree[i + (1 << k)] = Some(right);
+            Ok(())
+        })
+    })?;
+    if reverse {
+        tree[0] = Some(b.not(tree[0].take().unwrap()));
+    }
+    Ok(())
+}
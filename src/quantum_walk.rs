@@ -0,0 +1,168 @@
+use crate::errors::CircuitError;
+use crate::{try_condition, Register, UnitaryBuilder};
+use num::{Complex, One};
+
+/// Add `step` modulo `size` to the value of `position`, leaving values of at least `size` alone.
+/// The shift is a permutation of indices, so it is applied as a sparse matrix op.
+fn shift(
+    b: &mut dyn UnitaryBuilder,
+    position: Register,
+    size: u64,
+    step: u64,
+) -> Result<Register, CircuitError> {
+    let back = size - step % size;
+    b.sparse_mat_from_fn(
+        "Shift",
+        position,
+        Box::new(move |row| {
+            let col = if row < size { (row + back) % size } else { row };
+            vec![(col, Complex::one())]
+        }),
+        true,
+    )
+}
+
+/// Apply the shift of a coined walk on a cycle of `size` sites, moving `position` forward by one
+/// site when `coin` is `|1>` and back by one when it is `|0>`. Sites are the values `0` to `size -
+/// 1` of `position`, larger values are left alone. Returns an error if `coin` is not a single qubit
+/// or `size` is not between `1` and `2^n` for `n` position qubits.
+pub fn cycle_shift(
+    b: &mut dyn UnitaryBuilder,
+    coin: Register,
+    position: Register,
+    size: u64,
+) -> Result<(Register, Register), CircuitError> {
+    if coin.n() != 1 {
+        let message = format!("Expected a single coin qubit, found {:?}", coin.n());
+        return CircuitError::make_err(message);
+    }
+    if size == 0 || (position.n() < 64 && size > 1 << position.n()) {
+        let message = format!(
+            "Cannot fit a cycle of {:?} sites in {:?} qubits",
+            size,
+            position.n()
+        );
+        return CircuitError::make_err(message);
+    }
+    b.push_name_scope("CycleShift");
+    let result = try_condition(b, coin, position, |b, position| shift(b, position, size, 1))
+        .and_then(|(coin, position)| {
+            let coin = b.not(coin);
+            let (coin, position) = try_condition(b, coin, position, |b, position| {
+                shift(b, position, size, size - 1)
+            })?;
+            Ok((b.not(coin), position))
+        });
+    b.pop_name_scope();
+    result
+}
+
+/// Apply the shift of a coined walk on a line, moving `position` forward by one when `coin` is
+/// `|1>` and back by one when it is `|0>`. Positions are read in two's complement, see
+/// `line_position`, and wrap around at the ends of the register so it should have enough qubits
+/// that the walk never reaches them: `steps` steps from `0` need `2^n > 2 steps`.
+pub fn line_shift(
+    b: &mut dyn UnitaryBuilder,
+    coin: Register,
+    position: Register,
+) -> Result<(Register, Register), CircuitError> {
+    if position.n() >= 64 {
+        let message = format!("Position has too many qubits: {:?}", position.n());
+        return CircuitError::make_err(message);
+    }
+    let size = 1 << position.n();
+    cycle_shift(b, coin, position, size)
+}
+
+/// Apply `steps` steps of a coined walk on a cycle of `size` sites, each applying `coin_op` to
+/// `coin` followed by `cycle_shift`.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::quantum_walk::cycle_walk;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let coin = b.qubit();
+/// let position = b.register(2)?;
+/// let (coin, position) = cycle_walk(&mut b, coin, position, 3, 4, |b, coin| Ok(b.hadamard(coin)))?;
+/// let (position, m) = b.measure(position);
+/// let (_, measured) = run_local::<f64>(&position)?;
+/// // The walk stays on the 3 sites of the cycle.
+/// assert!(measured.get_measurement(&m).unwrap().0 < 3);
+/// # Ok(())
+/// # }
+/// ```
+pub fn cycle_walk<F>(
+    b: &mut dyn UnitaryBuilder,
+    coin: Register,
+    position: Register,
+    size: u64,
+    steps: usize,
+    coin_op: F,
+) -> Result<(Register, Register), CircuitError>
+where
+    F: Fn(&mut dyn UnitaryBuilder, Register) -> Result<Register, CircuitError>,
+{
+    b.push_name_scope("CycleWalk");
+    let result = (0..steps).try_fold((coin, position), |(coin, position), _| {
+        let coin = coin_op(b, coin)?;
+        cycle_shift(b, coin, position, size)
+    });
+    b.pop_name_scope();
+    result
+}
+
+/// Apply `steps` steps of a coined walk on a line, each applying `coin_op` to `coin` followed by
+/// `line_shift`.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::quantum_walk::{line_position, line_walk};
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// // The hadamard walk, starting from |0> at position 0.
+/// let mut b = OpBuilder::new();
+/// let coin = b.qubit();
+/// let position = b.register(3)?;
+/// let (coin, position) = line_walk(&mut b, coin, position, 3, |b, coin| Ok(b.hadamard(coin)))?;
+/// let (position, m) = b.measure(position);
+/// let (_, measured) = run_local::<f64>(&position)?;
+/// let x = line_position(measured.get_measurement(&m).unwrap().0, 3);
+/// // Three steps reach an odd position no further than 3 away.
+/// assert!(x.abs() <= 3 && x.abs() % 2 == 1);
+/// # Ok(())
+/// # }
+/// ```
+pub fn line_walk<F>(
+    b: &mut dyn UnitaryBuilder,
+    coin: Register,
+    position: Register,
+    steps: usize,
+    coin_op: F,
+) -> Result<(Register, Register), CircuitError>
+where
+    F: Fn(&mut dyn UnitaryBuilder, Register) -> Result<Register, CircuitError>,
+{
+    b.push_name_scope("LineWalk");
+    let result = (0..steps).try_fold((coin, position), |(coin, position), _| {
+        let coin = coin_op(b, coin)?;
+        line_shift(b, coin, position)
+    });
+    b.pop_name_scope();
+    result
+}
+
+/// Read a measured `value` of an `n` qubit position register from a walk on a line as a signed
+/// position, in two's complement.
+pub fn line_position(value: u64, n: u64) -> i64 {
+    if n == 0 {
+        0
+    } else if n >= 64 || value & (1 << (n - 1)) == 0 {
+        value as i64
+    } else {
+        value as i64 - (1 << n)
+    }
+}
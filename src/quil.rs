@@ -0,0 +1,315 @@
+use crate::errors::CircuitError;
+use crate::macros::inverter::remap_indices;
+use crate::pipeline::{get_opfns_and_frontier, StateModifier, StateModifierType};
+use crate::state_ops::{get_index, make_op_matrix, num_indices, UnitaryOp};
+use crate::{Complex, Register};
+use std::fmt::Write;
+
+/// Serialize the circuit which produces `r` as a Quil program. Qubits keep their indices from the
+/// circuit, standard gates are named directly, and any other unitary is emitted as a `DEFGATE`.
+/// Each measurement writes to its own classical register `m{id}` (where `id` is given by
+/// `MeasurementHandle::get_id`), with bit `i` holding the measurement of the `i`th qubit of the
+/// measured Register. Stochastic measurements, classical side channels, parameterized ops, and
+/// channels other than `reset` have no Quil equivalent and produce an error.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::quil::to_quil;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let q = b.qubit();
+/// let r = b.qubit();
+/// let q = b.hadamard(q);
+/// let (q, r) = b.cnot(q, r);
+/// let qr = b.merge(vec![q, r])?;
+/// let (qr, m) = b.measure(qr);
+///
+/// let program = to_quil(&qr)?;
+/// let expected = format!(
+///     "DECLARE m{0} BIT[2]\nH 0\nCNOT 0 1\nMEASURE 0 m{0}[0]\nMEASURE 1 m{0}[1]\n",
+///     m.get_id()
+/// );
+/// assert_eq!(program, expected);
+/// # Ok(())
+/// # }
+/// ```
+pub fn to_quil(r: &Register) -> Result<String, CircuitError> {
+    let (_, ops) = get_opfns_and_frontier(r);
+    let mut program = QuilProgram::default();
+    ops.into_iter()
+        .try_for_each(|modifier| program.add_modifier(modifier))?;
+    Ok(program.to_string())
+}
+
+#[derive(Default)]
+struct QuilProgram {
+    declarations: Vec<String>,
+    gate_definitions: Vec<(Vec<Complex<f64>>, String)>,
+    body: Vec<String>,
+}
+
+impl QuilProgram {
+    fn add_modifier(&mut self, modifier: &StateModifier) -> Result<(), CircuitError> {
+        match &modifier.modifier {
+            StateModifierType::UnitaryOp(op) => self.add_op(op),
+            StateModifierType::MeasureState(id, indices, angle) => {
+                self.add_measurement(*id, indices, *angle);
+                Ok(())
+            }
+            StateModifierType::Channel(indices, _)
+                if modifier.name.rsplit('/').next() == Some("reset") =>
+            {
+                indices
+                    .iter()
+                    .for_each(|indx| self.body.push(format!("RESET {}", indx)));
+                Ok(())
+            }
+            StateModifierType::Subcircuit(modifiers) => modifiers
+                .iter()
+                .try_for_each(|modifier| self.add_modifier(modifier)),
+            StateModifierType::Gate(def, indices) => def
+                .instantiate(indices)
+                .iter()
+                .try_for_each(|modifier| self.add_modifier(modifier)),
+            StateModifierType::Loop(body, indices) => body
+                .unroll(indices, None)?
+                .iter()
+                .try_for_each(|modifier| self.add_modifier(modifier)),
+            StateModifierType::Debug(_, _) => Ok(()),
+            StateModifierType::StochasticMeasureState(_, _, _) => {
+                CircuitError::make_str_err("Stochastic measurements cannot be exported to Quil")
+            }
+            StateModifierType::SideChannelModifiers(_, _) => {
+                CircuitError::make_str_err("Classical side channels cannot be exported to Quil")
+            }
+            StateModifierType::ParameterizedOp(_, _) => {
+                CircuitError::make_str_err("Parameterized ops cannot be exported to Quil")
+            }
+            StateModifierType::Channel(_, _) => CircuitError::make_err(format!(
+                "Channel {:?} cannot be exported to Quil",
+                modifier.name
+            )),
+        }
+    }
+
+    fn add_op(&mut self, op: &UnitaryOp) -> Result<(), CircuitError> {
+        let instructions = self.gate_instructions(op)?;
+        self.body.extend(
+            instructions
+                .into_iter()
+                .map(|(gate, qubits)| format_instruction(&gate, &qubits)),
+        );
+        Ok(())
+    }
+
+    fn add_measurement(&mut self, id: u64, indices: &[u64], angle: f64) {
+        let name = format!("m{}", id);
+        self.declarations
+            .push(format!("DECLARE {} BIT[{}]", name, indices.len()));
+        if angle != 0.0 {
+            indices
+                .iter()
+                .for_each(|indx| self.body.push(format!("RY({:?}) {}", 2.0 * angle, indx)));
+        }
+        indices.iter().enumerate().for_each(|(i, indx)| {
+            self.body.push(format!("MEASURE {} {}[{}]", indx, name, i));
+        });
+        if angle != 0.0 {
+            indices
+                .iter()
+                .for_each(|indx| self.body.push(format!("RY({:?}) {}", -2.0 * angle, indx)));
+        }
+    }
+
+    /// Get the gates and qubits which together make up `op`.
+    fn gate_instructions(
+        &mut self,
+        op: &UnitaryOp,
+    ) -> Result<Vec<(String, Vec<u64>)>, CircuitError> {
+        match op {
+            UnitaryOp::Swap(a_indices, b_indices) => Ok(a_indices
+                .iter()
+                .zip(b_indices.iter())
+                .map(|(a, b)| ("SWAP".to_string(), vec![*a, *b]))
+                .collect()),
+            UnitaryOp::Control(c_indices, _, op) => {
+                let instructions = self.gate_instructions(op)?;
+                Ok(instructions
+                    .into_iter()
+                    .map(|(gate, qubits)| {
+                        let gate = controlled_gate_name(&gate, c_indices.len());
+                        let qubits = c_indices.iter().chain(qubits.iter()).cloned().collect();
+                        (gate, qubits)
+                    })
+                    .collect())
+            }
+            UnitaryOp::Matrix(indices, mat) if indices.len() == 1 => {
+                let gate = match single_qubit_gate_name(mat) {
+                    Some(gate) => gate,
+                    None => self.define_gate(mat.clone()),
+                };
+                Ok(vec![(gate, indices.clone())])
+            }
+            _ => {
+                let indices: Vec<u64> = (0..num_indices(op)).map(|i| get_index(op, i)).collect();
+                let n = indices.len() as u64;
+                let mut local_indices =
+                    vec![0; indices.iter().max().map(|m| m + 1).unwrap_or(0) as usize];
+                indices
+                    .iter()
+                    .enumerate()
+                    .for_each(|(i, indx)| local_indices[*indx as usize] = i as u64);
+                let local_op = remap_indices(op.clone(), &local_indices);
+                // cols[c][r] = <r|U|c>, DEFGATE wants rows.
+                let cols = make_op_matrix::<f64>(n, &local_op, false);
+                let size = cols.len();
+                let mat = (0..size * size).map(|i| cols[i % size][i / size]).collect();
+                let gate = self.define_gate(mat);
+                Ok(vec![(gate, indices)])
+            }
+        }
+    }
+
+    /// Add a `DEFGATE` for `mat` if one doesn't already exist, return the gate name.
+    fn define_gate(&mut self, mat: Vec<Complex<f64>>) -> String {
+        let existing = self.gate_definitions.iter().find(|(m, _)| {
+            m.len() == mat.len()
+                && m.iter()
+                    .zip(mat.iter())
+                    .all(|(a, b)| (a - b).norm() < 1e-10)
+        });
+        match existing {
+            Some((_, name)) => name.clone(),
+            None => {
+                let name = format!("GATE{}", self.gate_definitions.len());
+                self.gate_definitions.push((mat, name.clone()));
+                name
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for QuilProgram {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut s = String::new();
+        self.declarations
+            .iter()
+            .try_for_each(|d| writeln!(s, "{}", d))?;
+        self.gate_definitions.iter().try_for_each(|(mat, name)| {
+            writeln!(s, "DEFGATE {}:", name)?;
+            let size = (mat.len() as f64).sqrt() as usize;
+            (0..size).try_for_each(|row| {
+                let entries: Vec<String> = mat[row * size..(row + 1) * size]
+                    .iter()
+                    .map(format_complex)
+                    .collect();
+                writeln!(s, "    {}", entries.join(", "))
+            })?;
+            writeln!(s)
+        })?;
+        self.body
+            .iter()
+            .try_for_each(|line| writeln!(s, "{}", line))?;
+        write!(f, "{}", s)
+    }
+}
+
+fn format_instruction(gate: &str, qubits: &[u64]) -> String {
+    let qubits: Vec<String> = qubits.iter().map(|q| q.to_string()).collect();
+    format!("{} {}", gate, qubits.join(" "))
+}
+
+fn format_complex(c: &Complex<f64>) -> String {
+    if c.im == 0.0 {
+        format!("{:?}", c.re)
+    } else if c.re == 0.0 {
+        format!("{:?}i", c.im)
+    } else if c.im < 0.0 {
+        format!("{:?}-{:?}i", c.re, -c.im)
+    } else {
+        format!("{:?}+{:?}i", c.re, c.im)
+    }
+}
+
+/// Use the shortest Quil name for the gate `gate` with `n` additional controls.
+fn controlled_gate_name(gate: &str, n: usize) -> String {
+    match (gate, n) {
+        ("X", 1) => "CNOT".to_string(),
+        ("X", 2) => "CCNOT".to_string(),
+        ("CNOT", 1) => "CCNOT".to_string(),
+        ("Z", 1) => "CZ".to_string(),
+        ("SWAP", 1) => "CSWAP".to_string(),
+        _ if gate.starts_with("PHASE(") && n == 1 => format!("C{}", gate),
+        _ => {
+            let mut name = "CONTROLLED ".repeat(n);
+            name.push_str(gate);
+            name
+        }
+    }
+}
+
+fn approx_eq(a: &[Complex<f64>], b: &[Complex<f64>]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| (a - b).norm() < 1e-10)
+}
+
+/// Find the standard Quil gate for the 2x2 matrix `mat`, if there is one.
+fn single_qubit_gate_name(mat: &[Complex<f64>]) -> Option<String> {
+    let c = |re: f64, im: f64| Complex { re, im };
+    let h = std::f64::consts::FRAC_1_SQRT_2;
+    let fixed = [
+        ("I", [c(1.0, 0.0), c(0.0, 0.0), c(0.0, 0.0), c(1.0, 0.0)]),
+        ("X", [c(0.0, 0.0), c(1.0, 0.0), c(1.0, 0.0), c(0.0, 0.0)]),
+        ("Y", [c(0.0, 0.0), c(0.0, -1.0), c(0.0, 1.0), c(0.0, 0.0)]),
+        ("Z", [c(1.0, 0.0), c(0.0, 0.0), c(0.0, 0.0), c(-1.0, 0.0)]),
+        ("H", [c(h, 0.0), c(h, 0.0), c(h, 0.0), c(-h, 0.0)]),
+        ("S", [c(1.0, 0.0), c(0.0, 0.0), c(0.0, 0.0), c(0.0, 1.0)]),
+        (
+            "DAGGER S",
+            [c(1.0, 0.0), c(0.0, 0.0), c(0.0, 0.0), c(0.0, -1.0)],
+        ),
+        ("T", [c(1.0, 0.0), c(0.0, 0.0), c(0.0, 0.0), c(h, h)]),
+        (
+            "DAGGER T",
+            [c(1.0, 0.0), c(0.0, 0.0), c(0.0, 0.0), c(h, -h)],
+        ),
+    ];
+    if let Some((name, _)) = fixed.iter().find(|(_, m)| approx_eq(m, mat)) {
+        return Some(name.to_string());
+    }
+
+    let zero = c(0.0, 0.0);
+    // PHASE(phi) = diag(1, e^{i phi})
+    let phi = mat[3].arg();
+    if approx_eq(
+        mat,
+        &[c(1.0, 0.0), zero, zero, Complex::from_polar(&1.0, &phi)],
+    ) {
+        return Some(format!("PHASE({:?})", phi));
+    }
+    // RZ(theta) = diag(e^{-i theta/2}, e^{i theta/2})
+    let theta = 2.0 * mat[3].arg();
+    let rz = [
+        Complex::from_polar(&1.0, &(-theta / 2.0)),
+        zero,
+        zero,
+        Complex::from_polar(&1.0, &(theta / 2.0)),
+    ];
+    if approx_eq(mat, &rz) {
+        return Some(format!("RZ({:?})", theta));
+    }
+    // RX(theta) = [[cos, -i sin], [-i sin, cos]]
+    let theta = 2.0 * (-mat[1].im).atan2(mat[0].re);
+    let (sin, cos) = (theta / 2.0).sin_cos();
+    if approx_eq(mat, &[c(cos, 0.0), c(0.0, -sin), c(0.0, -sin), c(cos, 0.0)]) {
+        return Some(format!("RX({:?})", theta));
+    }
+    // RY(theta) = [[cos, -sin], [sin, cos]]
+    let theta = 2.0 * mat[2].re.atan2(mat[0].re);
+    let (sin, cos) = (theta / 2.0).sin_cos();
+    if approx_eq(mat, &[c(cos, 0.0), c(-sin, 0.0), c(sin, 0.0), c(cos, 0.0)]) {
+        return Some(format!("RY({:?})", theta));
+    }
+    None
+}
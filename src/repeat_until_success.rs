@@ -0,0 +1,164 @@
+use crate::classical::ClassicalRegister;
+use crate::errors::CircuitError;
+use crate::pipeline::{MeasuredResults, MeasurementHandle};
+use crate::{OpBuilder, Precision, Register, UnitaryBuilder};
+use std::rc::Rc;
+
+/// The measurements made by `repeat_until_success`, one per attempt.
+#[derive(Debug)]
+pub struct RepeatUntilSuccess {
+    flags: Vec<MeasurementHandle>,
+    success: u64,
+}
+
+impl RepeatUntilSuccess {
+    /// The measurement of the flag register after each attempt. Once an attempt succeeds the
+    /// later ones are skipped, and measure the same successful value again.
+    pub fn flags(&self) -> &[MeasurementHandle] {
+        &self.flags
+    }
+
+    /// Single bit register which is `1` if one of the attempts succeeded.
+    pub fn succeeded(&self) -> ClassicalRegister {
+        ClassicalRegister::from(self.flags.last().unwrap()).equals(self.success)
+    }
+
+    /// The number of attempts made in a run with results `measured`, or None if the flags were
+    /// not measured.
+    pub fn attempts<P: Precision>(&self, measured: &MeasuredResults<P>) -> Option<usize> {
+        let mut attempts = 0;
+        for flag in &self.flags {
+            let (value, _) = measured.get_measurement(flag)?;
+            attempts += 1;
+            if value == self.success {
+                break;
+            }
+        }
+        Some(attempts)
+    }
+}
+
+/// Apply the probabilistic circuit `attempt` to `r` until measuring `flags` afterwards gives
+/// `success`, making at most `max_iterations` attempts. After each failure `recover` is applied
+/// to `r` to undo the effect of the failed attempt, and `flags` is returned to `|0...0>` before
+/// trying again. `flags` must start as `|0...0>`, and the measured value is read in the bit order
+/// of `b.endianness()`.
+///
+/// Whether each attempt is made depends on the measurement before it, so the number of attempts
+/// is chosen separately for each run of the circuit. Returns an error if `max_iterations` is zero
+/// or `success` does not fit in `flags`.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::repeat_until_success::repeat_until_success;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let r = b.qubit();
+/// let flag = b.qubit();
+/// // Each attempt flips r with probability 1/2, flagged by the flag measuring |1>.
+/// let (r, flag, rus) = repeat_until_success(
+///     &mut b,
+///     r,
+///     flag,
+///     1,
+///     10,
+///     |b, r, flag| {
+///         let flag = b.hadamard(flag);
+///         Ok(b.cnot(flag, r))
+///     },
+///     |_, r| Ok(r),
+/// )?;
+///
+/// let r = b.merge(vec![r, flag])?;
+/// let (state, measured) = run_local::<f64>(&r)?;
+/// if measured.get_classical(&rus.succeeded()) == Some(1) {
+///     assert!((state.get_state(true)[0b11].norm() - 1.0).abs() < 1e-10);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn repeat_until_success<F, G>(
+    b: &mut OpBuilder,
+    r: Register,
+    flags: Register,
+    success: u64,
+    max_iterations: usize,
+    attempt: F,
+    recover: G,
+) -> Result<(Register, Register, RepeatUntilSuccess), CircuitError>
+where
+    F: 'static
+        + Fn(
+            &mut dyn UnitaryBuilder,
+            Register,
+            Register,
+        ) -> Result<(Register, Register), CircuitError>,
+    G: 'static + Fn(&mut dyn UnitaryBuilder, Register) -> Result<Register, CircuitError>,
+{
+    if max_iterations == 0 {
+        return CircuitError::make_str_err("Repeat until success needs at least one iteration");
+    }
+    let n = flags.n();
+    if n < 64 && success >> n != 0 {
+        let message = format!(
+            "Success value {} does not fit in {} flag qubits",
+            success, n
+        );
+        return CircuitError::make_err(message);
+    }
+    // Bit i of a measurement of the flags is the qubit at flag_positions[i] of flags.indices.
+    let flag_positions: Vec<usize> = b
+        .endianness()
+        .qubit_order(&flags.indices)
+        .iter()
+        .map(|indx| flags.indices.iter().position(|i| i == indx).unwrap())
+        .collect();
+    let flag_positions = Rc::new(flag_positions);
+    let attempt = Rc::new(attempt);
+    let recover = Rc::new(recover);
+
+    let (mut r, flags) = attempt(b, r, flags)?;
+    let (mut flags, handle) = b.measure(flags);
+    let mut handles = vec![handle];
+    for _ in 1..max_iterations {
+        let attempt = attempt.clone();
+        let recover = recover.clone();
+        let flag_positions = flag_positions.clone();
+        let mut rs = b.classical_sidechannel(
+            vec![r, flags],
+            std::slice::from_ref(handles.last().unwrap()),
+            Box::new(move |b, mut rs, measured| {
+                let flags = rs.pop().unwrap();
+                let r = rs.pop().unwrap();
+                if measured[0] == success {
+                    return Ok(vec![r, flags]);
+                }
+                let flip: Vec<usize> = (0..flag_positions.len())
+                    .filter(|i| (measured[0] >> i) & 1 == 1)
+                    .map(|i| flag_positions[i])
+                    .collect();
+                let qs = b
+                    .split_all(flags)
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, q)| if flip.contains(&i) { b.x(q) } else { q })
+                    .collect();
+                let flags = b.merge(qs)?;
+                let r = recover(b, r)?;
+                let (r, flags) = attempt(b, r, flags)?;
+                Ok(vec![r, flags])
+            }),
+        );
+        let (measured_flags, handle) = b.measure(rs.pop().unwrap());
+        r = rs.pop().unwrap();
+        flags = measured_flags;
+        handles.push(handle);
+    }
+    let rus = RepeatUntilSuccess {
+        flags: handles,
+        success,
+    };
+    Ok((r, flags, rus))
+}
@@ -0,0 +1,86 @@
+extern crate rand;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
+
+thread_local! {
+    static SEEDED_RNG: RefCell<Option<StdRng>> = const { RefCell::new(None) };
+}
+
+/// Run `f` with all measurements and other random choices made on this thread drawn from a
+/// generator seeded with `seed`, so that repeated calls give the same results. The previous
+/// generator (if any) is restored afterwards.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::rng::with_seed;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let r = b.register(4)?;
+/// let r = b.hadamard(r);
+///
+/// let a = with_seed(42, || run_and_sample::<f64>(&r, 100))?;
+/// let b = with_seed(42, || run_and_sample::<f64>(&r, 100))?;
+/// assert_eq!(a, b);
+/// # Ok(())
+/// # }
+/// ```
+pub fn with_seed<T, F: FnOnce() -> T>(seed: u64, f: F) -> T {
+    let previous = replace_rng(Some(StdRng::seed_from_u64(seed)));
+    let result = f();
+    replace_rng(previous);
+    result
+}
+
+/// Replace the seeded generator for this thread, returning the old one. Used to carry the
+/// generator onto other threads.
+pub(crate) fn replace_rng(rng: Option<StdRng>) -> Option<StdRng> {
+    SEEDED_RNG.with(|r| r.replace(rng))
+}
+
+/// Draw a value from the seeded generator for this thread, or from `rand::random` if there is
+/// none.
+pub(crate) fn random<T>() -> T
+where
+    rand::distributions::Standard: rand::distributions::Distribution<T>,
+{
+    SEEDED_RNG.with(|r| match r.borrow_mut().as_mut() {
+        Some(rng) => rng.gen(),
+        None => rand::random(),
+    })
+}
+
+/// Draw from the standard normal distribution with the Box-Muller transform, using `random`.
+pub(crate) fn gaussian() -> f64 {
+    let u = 1.0 - random::<f64>();
+    let v = random::<f64>();
+    (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos()
+}
+
+#[cfg(test)]
+mod rng_tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_repeats() {
+        let a: Vec<f64> = with_seed(1, || (0..10).map(|_| random()).collect());
+        let b: Vec<f64> = with_seed(1, || (0..10).map(|_| random()).collect());
+        let c: Vec<f64> = with_seed(2, || (0..10).map(|_| random()).collect());
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn test_nested_seeds_restore() {
+        let (a, b) = with_seed(1, || {
+            let first: f64 = random();
+            with_seed(2, random::<f64>);
+            (first, random::<f64>())
+        });
+        let expected: Vec<f64> = with_seed(1, || (0..2).map(|_| random()).collect());
+        assert_eq!(vec![a, b], expected);
+    }
+}
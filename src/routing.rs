@@ -0,0 +1,524 @@
+use crate::errors::CircuitError;
+use crate::pipeline::{
+    get_opfns_and_frontier, get_required_state_size_from_frontier, StateModifier, StateModifierType,
+};
+use crate::state_ops::UnitaryOp;
+use crate::{OpBuilder, Register, UnitaryBuilder};
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+/// Number of upcoming two qubit ops considered when choosing between swaps.
+const EXTENDED_SET_SIZE: usize = 20;
+
+/// Weight given to upcoming two qubit ops relative to those which are waiting to run.
+const EXTENDED_SET_WEIGHT: f64 = 0.5;
+
+/// Penalty added for each recent swap on a qubit, to spread swaps between qubits.
+const DECAY_STEP: f64 = 0.001;
+
+/// A modifier along with the qubits it uses.
+type Entry = (Vec<u64>, StateModifier);
+
+/// The physical qubits of a device and which pairs of them two qubit ops may act on.
+#[derive(Debug, Clone)]
+pub struct CouplingMap {
+    neighbours: Vec<Vec<u64>>,
+    distances: Vec<Vec<u64>>,
+}
+
+impl CouplingMap {
+    /// Make a coupling map for `n` physical qubits connected by `edges`. Edges go both ways, and
+    /// the qubits must form a connected graph.
+    pub fn new(n: u64, edges: &[(u64, u64)]) -> Result<Self, CircuitError> {
+        if n == 0 {
+            return CircuitError::make_str_err("Coupling map must have at least one qubit.");
+        }
+        let mut neighbours = vec![vec![]; n as usize];
+        for (a, b) in edges {
+            if *a >= n || *b >= n || a == b {
+                let message = format!("Invalid edge ({:?}, {:?}) for {:?} qubits", a, b, n);
+                return CircuitError::make_err(message);
+            }
+            if !neighbours[*a as usize].contains(b) {
+                neighbours[*a as usize].push(*b);
+                neighbours[*b as usize].push(*a);
+            }
+        }
+        let distances = (0..n)
+            .map(|start| breadth_first_distances(&neighbours, start))
+            .collect::<Vec<_>>();
+        if distances[0].contains(&u64::MAX) {
+            return CircuitError::make_str_err("Coupling map must be connected.");
+        }
+        Ok(CouplingMap {
+            neighbours,
+            distances,
+        })
+    }
+
+    /// Make a coupling map for `n` qubits in a line, where qubit `i` is connected to `i + 1`.
+    pub fn line(n: u64) -> Result<Self, CircuitError> {
+        let edges: Vec<_> = (1..n).map(|i| (i - 1, i)).collect();
+        CouplingMap::new(n, &edges)
+    }
+
+    /// Make a coupling map for a grid of qubits, where qubit `row * cols + col` is connected to
+    /// the qubits beside it.
+    pub fn grid(rows: u64, cols: u64) -> Result<Self, CircuitError> {
+        let index = |row: u64, col: u64| row * cols + col;
+        let mut edges = vec![];
+        (0..rows).for_each(|row| {
+            (0..cols).for_each(|col| {
+                if col + 1 < cols {
+                    edges.push((index(row, col), index(row, col + 1)));
+                }
+                if row + 1 < rows {
+                    edges.push((index(row, col), index(row + 1, col)));
+                }
+            })
+        });
+        CouplingMap::new(rows * cols, &edges)
+    }
+
+    /// Get the number of physical qubits.
+    pub fn n(&self) -> u64 {
+        self.neighbours.len() as u64
+    }
+
+    /// Check whether two qubit ops may act on qubits `a` and `b`.
+    pub fn are_connected(&self, a: u64, b: u64) -> bool {
+        self.distance(a, b) == 1
+    }
+
+    /// Get the number of edges on the shortest path between qubits `a` and `b`.
+    pub fn distance(&self, a: u64, b: u64) -> u64 {
+        self.distances[a as usize][b as usize]
+    }
+
+    /// Get the qubits connected to qubit `a`.
+    pub fn neighbours(&self, a: u64) -> &[u64] {
+        &self.neighbours[a as usize]
+    }
+}
+
+fn breadth_first_distances(neighbours: &[Vec<u64>], start: u64) -> Vec<u64> {
+    let mut distances = vec![u64::MAX; neighbours.len()];
+    distances[start as usize] = 0;
+    let mut queue = VecDeque::new();
+    queue.push_back(start);
+    while let Some(a) = queue.pop_front() {
+        let d = distances[a as usize] + 1;
+        neighbours[a as usize].iter().for_each(|b| {
+            if distances[*b as usize] == u64::MAX {
+                distances[*b as usize] = d;
+                queue.push_back(*b);
+            }
+        });
+    }
+    distances
+}
+
+/// A circuit rewritten onto the physical qubits of a `CouplingMap`.
+#[derive(Debug)]
+pub struct RoutedCircuit {
+    /// Register holding every physical qubit, with qubit `i` at position `i`.
+    pub register: Register,
+    /// The physical qubit holding each qubit of the original circuit when it starts.
+    pub initial_layout: Vec<u64>,
+    /// The physical qubit holding each qubit of the original circuit once it has run.
+    pub final_layout: Vec<u64>,
+    /// Number of swaps added to the circuit.
+    pub num_swaps: usize,
+}
+
+/// Rewrite the circuit which produces `r` onto the physical qubits of `coupling`, adding swaps so
+/// that each two qubit op acts on connected qubits. Qubit `i` of the circuit starts on physical
+/// qubit `i`, see `route_with_layout` to choose another placement.
+///
+/// Swaps are chosen in the style of SABRE: whenever no waiting op can run, the swap which brings
+/// the qubits of waiting and upcoming two qubit ops closest together is added. Ops on more than two
+/// qubits must first be decomposed, for instance with `transpile`. Measurements are kept, with their
+/// `MeasurementHandle`s referring to the same qubits as before. Debug ops are dropped, while
+/// classical side channels and parameterized ops produce an error.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::routing::{route, CouplingMap};
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let q = b.qubit();
+/// let r = b.qubit();
+/// let s = b.qubit();
+/// let q = b.hadamard(q);
+/// let (q, s) = b.cnot(q, s);
+/// let r = b.merge(vec![q, r, s])?;
+///
+/// // Qubits 0 and 2 are not connected, so one of them is swapped next to the other.
+/// let routed = route(&r, &CouplingMap::line(3)?)?;
+/// assert_eq!(routed.num_swaps, 1);
+/// assert_eq!(routed.final_layout, vec![1, 0, 2]);
+/// # Ok(())
+/// # }
+/// ```
+pub fn route(r: &Register, coupling: &CouplingMap) -> Result<RoutedCircuit, CircuitError> {
+    let (frontier, _) = get_opfns_and_frontier(r);
+    let n = get_required_state_size_from_frontier(&frontier);
+    route_with_layout(r, coupling, &(0..n).collect::<Vec<_>>())
+}
+
+/// Rewrite the circuit which produces `r` onto the physical qubits of `coupling` as with `route`,
+/// with qubit `i` of the circuit starting on physical qubit `initial_layout[i]`.
+pub fn route_with_layout(
+    r: &Register,
+    coupling: &CouplingMap,
+    initial_layout: &[u64],
+) -> Result<RoutedCircuit, CircuitError> {
+    let num_physical = coupling.n();
+    let (n, entries) = copy_modifiers(r)?;
+    if n > num_physical {
+        let message = format!(
+            "Circuit uses {:?} qubits but the coupling map only has {:?}",
+            n, num_physical
+        );
+        return CircuitError::make_err(message);
+    }
+    if initial_layout.len() as u64 != n {
+        let message = format!(
+            "Layout has {:?} qubits but the circuit uses {:?}",
+            initial_layout.len(),
+            n
+        );
+        return CircuitError::make_err(message);
+    }
+    if let Some(p) = (0..initial_layout.len()).find(|i| {
+        initial_layout[*i] >= num_physical || initial_layout[..*i].contains(&initial_layout[*i])
+    }) {
+        let message = format!(
+            "Physical qubit {:?} is not a distinct qubit of the coupling map",
+            initial_layout[p]
+        );
+        return CircuitError::make_err(message);
+    }
+
+    // Place the idle physical qubits after the circuit's qubits so every physical qubit has a
+    // logical one, then the layout is a permutation.
+    let mut layout = initial_layout.to_vec();
+    (0..num_physical).for_each(|p| {
+        if !layout.contains(&p) {
+            layout.push(p)
+        }
+    });
+    let mut router = Router::new(coupling, entries, layout);
+    let entries = router.run();
+
+    let mut b = OpBuilder::new();
+    let reg = b.register(num_physical)?;
+    let reg = entries
+        .into_iter()
+        .try_fold(reg, |reg, (indices, modifier)| {
+            b.apply_modifier_to_indices(reg, &indices, modifier)
+        })?;
+    let (register, _) = b.split_absolute(reg, &(0..num_physical).collect::<Vec<_>>())?;
+    Ok(RoutedCircuit {
+        register,
+        initial_layout: initial_layout.to_vec(),
+        final_layout: router.layout[..n as usize].to_vec(),
+        num_swaps: router.num_swaps,
+    })
+}
+
+/// Copy the modifiers of the circuit which produces `r` along with the qubits each one uses,
+/// returning the number of qubits in the circuit.
+fn copy_modifiers(r: &Register) -> Result<(u64, Vec<Entry>), CircuitError> {
+    let (frontier, ops) = get_opfns_and_frontier(r);
+    let n = get_required_state_size_from_frontier(&frontier);
+    let entries = ops
+        .into_iter()
+        .filter(|modifier| !matches!(&modifier.modifier, StateModifierType::Debug(_, _)))
+        .map(|modifier| {
+            let entry = match &modifier.modifier {
+                StateModifierType::SideChannelModifiers(_, _)
+                | StateModifierType::ParameterizedOp(_, _) => None,
+                _ => modifier.indices().zip(modifier.try_clone()),
+            };
+            match entry {
+                Some((indices, _)) if indices.len() > 2 && !is_measurement(modifier) => {
+                    let message = format!(
+                        "Op {:?} acts on {:?} qubits, decompose it before routing",
+                        modifier.name,
+                        indices.len()
+                    );
+                    CircuitError::make_err(message)
+                }
+                Some(entry) => Ok(entry),
+                None => {
+                    let message = format!("Op {:?} cannot be routed", modifier.name);
+                    CircuitError::make_err(message)
+                }
+            }
+        })
+        .collect::<Result<Vec<_>, CircuitError>>()?;
+    Ok((n, entries))
+}
+
+fn is_measurement(modifier: &StateModifier) -> bool {
+    matches!(
+        &modifier.modifier,
+        StateModifierType::MeasureState(_, _, _)
+            | StateModifierType::StochasticMeasureState(_, _, _)
+    )
+}
+
+/// Tracks which ops are left to place and where each logical qubit currently is.
+struct Router<'a> {
+    coupling: &'a CouplingMap,
+    entries: Vec<Option<Entry>>,
+    /// Number of earlier ops each op is still waiting on.
+    waiting_on: Vec<usize>,
+    successors: Vec<Vec<usize>>,
+    /// Physical qubit of each logical qubit.
+    layout: Vec<u64>,
+    decay: Vec<f64>,
+    num_swaps: usize,
+}
+
+impl<'a> Router<'a> {
+    fn new(coupling: &'a CouplingMap, entries: Vec<Entry>, layout: Vec<u64>) -> Self {
+        let mut waiting_on = vec![0; entries.len()];
+        let mut successors = vec![vec![]; entries.len()];
+        let mut last_op: Vec<Option<usize>> = vec![None; layout.len()];
+        entries.iter().enumerate().for_each(|(i, (indices, _))| {
+            indices.iter().for_each(|indx| {
+                if let Some(p) = last_op[*indx as usize] {
+                    if !successors[p].contains(&i) {
+                        successors[p].push(i);
+                        waiting_on[i] += 1;
+                    }
+                }
+                last_op[*indx as usize] = Some(i);
+            })
+        });
+        let decay = vec![1.0; layout.len()];
+        Router {
+            coupling,
+            entries: entries.into_iter().map(Some).collect(),
+            waiting_on,
+            successors,
+            layout,
+            decay,
+            num_swaps: 0,
+        }
+    }
+
+    /// Place every op, returning the ops on physical qubits in the order they should run.
+    fn run(&mut self) -> Vec<Entry> {
+        let mut routed = vec![];
+        let mut front: Vec<usize> = (0..self.entries.len())
+            .filter(|i| self.waiting_on[*i] == 0)
+            .collect();
+        let mut swaps_since_progress = 0;
+        while !front.is_empty() {
+            let (ready, blocked): (Vec<usize>, Vec<usize>) =
+                front.iter().partition(|i| self.can_run(**i));
+            if !ready.is_empty() {
+                front = blocked;
+                ready.into_iter().for_each(|i| {
+                    routed.push(self.place(i));
+                    self.successors[i].clone().into_iter().for_each(|j| {
+                        self.waiting_on[j] -= 1;
+                        if self.waiting_on[j] == 0 {
+                            front.push(j);
+                        }
+                    });
+                });
+                front.sort_unstable();
+                self.decay.iter_mut().for_each(|d| *d = 1.0);
+                swaps_since_progress = 0;
+                continue;
+            }
+
+            // The heuristic can get stuck moving qubits back and forth, in which case walk the
+            // first waiting op's qubits together along a shortest path.
+            let (a, b) = if swaps_since_progress > 2 * self.coupling.n() as usize {
+                let (p, q) = self.physical_pair(front[0]);
+                let next = *self
+                    .coupling
+                    .neighbours(p)
+                    .iter()
+                    .find(|c| self.coupling.distance(**c, q) < self.coupling.distance(p, q))
+                    .unwrap();
+                (p, next)
+            } else {
+                self.best_swap(&front)
+            };
+            routed.push(self.swap(a, b));
+            swaps_since_progress += 1;
+        }
+        routed
+    }
+
+    fn is_two_qubit(&self, i: usize) -> bool {
+        match &self.entries[i] {
+            Some((indices, modifier)) => indices.len() == 2 && !is_measurement(modifier),
+            None => false,
+        }
+    }
+
+    fn physical_pair(&self, i: usize) -> (u64, u64) {
+        let indices = &self.entries[i].as_ref().unwrap().0;
+        (
+            self.layout[indices[0] as usize],
+            self.layout[indices[1] as usize],
+        )
+    }
+
+    fn can_run(&self, i: usize) -> bool {
+        if !self.is_two_qubit(i) {
+            return true;
+        }
+        let (a, b) = self.physical_pair(i);
+        self.coupling.are_connected(a, b)
+    }
+
+    /// Take the op at `i` out of the circuit and move it onto the physical qubits.
+    fn place(&mut self, i: usize) -> Entry {
+        let (indices, modifier) = self.entries[i].take().unwrap();
+        let indices = indices
+            .into_iter()
+            .map(|indx| self.layout[indx as usize])
+            .collect();
+        (indices, relabel_modifier(modifier, &self.layout))
+    }
+
+    /// Swap the logical qubits on physical qubits `a` and `b`.
+    fn swap(&mut self, a: u64, b: u64) -> Entry {
+        self.layout.iter_mut().for_each(|p| {
+            if *p == a {
+                *p = b
+            } else if *p == b {
+                *p = a
+            }
+        });
+        self.decay[a as usize] += DECAY_STEP;
+        self.decay[b as usize] += DECAY_STEP;
+        self.num_swaps += 1;
+        let op = UnitaryOp::Swap(vec![a], vec![b]);
+        (
+            vec![a, b],
+            StateModifier::new_unitary("swap".to_string(), op),
+        )
+    }
+
+    /// Find the swap on a qubit of a waiting two qubit op which leaves the qubits of waiting and
+    /// upcoming ops closest together.
+    fn best_swap(&self, front: &[usize]) -> (u64, u64) {
+        let front: Vec<(u64, u64)> = front
+            .iter()
+            .filter(|i| self.is_two_qubit(**i))
+            .map(|i| self.physical_pair(*i))
+            .collect();
+        let extended: Vec<(u64, u64)> = (0..self.entries.len())
+            .filter(|i| self.waiting_on[*i] > 0 && self.is_two_qubit(*i))
+            .take(EXTENDED_SET_SIZE)
+            .map(|i| self.physical_pair(i))
+            .collect();
+        let mut best: Option<((u64, u64), f64)> = None;
+        front.iter().for_each(|(p, q)| {
+            [*p, *q].iter().for_each(|a| {
+                self.coupling.neighbours(*a).iter().for_each(|b| {
+                    let score = self.score((*a, *b), &front, &extended);
+                    if best.map(|(_, s)| score < s).unwrap_or(true) {
+                        best = Some(((*a, *b), score));
+                    }
+                })
+            })
+        });
+        best.unwrap().0
+    }
+
+    fn score(&self, (a, b): (u64, u64), front: &[(u64, u64)], extended: &[(u64, u64)]) -> f64 {
+        let moved = |p: u64| {
+            if p == a {
+                b
+            } else if p == b {
+                a
+            } else {
+                p
+            }
+        };
+        let mean_distance = |pairs: &[(u64, u64)]| {
+            if pairs.is_empty() {
+                return 0.0;
+            }
+            let total: u64 = pairs
+                .iter()
+                .map(|(p, q)| self.coupling.distance(moved(*p), moved(*q)))
+                .sum();
+            total as f64 / pairs.len() as f64
+        };
+        let decay = self.decay[a as usize].max(self.decay[b as usize]);
+        decay * (mean_distance(front) + EXTENDED_SET_WEIGHT * mean_distance(extended))
+    }
+}
+
+fn relabel(indices: Vec<u64>, layout: &[u64]) -> Vec<u64> {
+    indices
+        .into_iter()
+        .map(|indx| layout[indx as usize])
+        .collect()
+}
+
+fn relabel_op(op: UnitaryOp, layout: &[u64]) -> UnitaryOp {
+    match op {
+        UnitaryOp::Matrix(indices, mat) => UnitaryOp::Matrix(relabel(indices, layout), mat),
+        UnitaryOp::SparseMatrix(indices, mat) => {
+            UnitaryOp::SparseMatrix(relabel(indices, layout), mat)
+        }
+        UnitaryOp::Swap(a, b) => UnitaryOp::Swap(relabel(a, layout), relabel(b, layout)),
+        UnitaryOp::Control(c, o, op) => UnitaryOp::Control(
+            relabel(c, layout),
+            relabel(o, layout),
+            Box::new(relabel_op(*op, layout)),
+        ),
+        UnitaryOp::Function(a, b, f) => {
+            UnitaryOp::Function(relabel(a, layout), relabel(b, layout), f)
+        }
+    }
+}
+
+/// Move a modifier copied with `try_clone` from logical qubits onto physical ones.
+fn relabel_modifier(modifier: StateModifier, layout: &[u64]) -> StateModifier {
+    let op = match modifier.modifier {
+        StateModifierType::UnitaryOp(op) => StateModifierType::UnitaryOp(relabel_op(op, layout)),
+        StateModifierType::MeasureState(id, indices, angle) => {
+            StateModifierType::MeasureState(id, relabel(indices, layout), angle)
+        }
+        StateModifierType::StochasticMeasureState(id, indices, angle) => {
+            StateModifierType::StochasticMeasureState(id, relabel(indices, layout), angle)
+        }
+        StateModifierType::Channel(indices, kraus_ops) => {
+            StateModifierType::Channel(relabel(indices, layout), kraus_ops)
+        }
+        StateModifierType::Subcircuit(modifiers) => {
+            let modifiers = modifiers
+                .iter()
+                .filter_map(|m| m.try_clone())
+                .map(|m| relabel_modifier(m, layout))
+                .collect();
+            StateModifierType::Subcircuit(Rc::new(modifiers))
+        }
+        StateModifierType::Gate(def, indices) => {
+            StateModifierType::Gate(def, relabel(indices, layout))
+        }
+        StateModifierType::Loop(body, indices) => {
+            StateModifierType::Loop(body, relabel(indices, layout))
+        }
+        op => op,
+    };
+    StateModifier {
+        name: modifier.name,
+        modifier: op,
+    }
+}
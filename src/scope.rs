@@ -0,0 +1,242 @@
+use crate::errors::CircuitError;
+use crate::{OpBuilder, Register, UnitaryBuilder};
+
+/// Build a circuit on registers held by name, so ops don't need to be passed Registers and return
+/// them. Each op takes the registers it names out of the scope and puts back the registers it
+/// produces under the same names.
+///
+/// Ops return the scope so they can be chained, and problems such as unknown names, using the
+/// same register twice in one op, or an op failing are recorded rather than returned: the first
+/// is reported by `build`, and ops after it are skipped.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::scope::Scope;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let mut scope = Scope::new(&mut b);
+/// scope.register("q0", 1).register("q1", 1);
+/// scope.h("q0").cnot("q0", "q1");
+/// let rs = scope.build()?;
+///
+/// let r = b.merge(rs.into_iter().map(|(_, r)| r).collect())?;
+/// let (state, _) = run_local::<f64>(&r)?;
+/// let x = std::f64::consts::FRAC_1_SQRT_2;
+/// assert!((state.get_state(true)[0b11].re - x).abs() < 1e-10);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Scope<'a> {
+    b: &'a mut OpBuilder,
+    registers: Vec<(String, Option<Register>)>,
+    error: Option<CircuitError>,
+}
+
+impl<'a> Scope<'a> {
+    /// Make a new empty scope which builds its ops with `b`.
+    pub fn new(b: &'a mut OpBuilder) -> Scope<'a> {
+        Scope {
+            b,
+            registers: vec![],
+            error: None,
+        }
+    }
+
+    /// Add a new register of `n` qubits called `name`.
+    pub fn register(&mut self, name: &str, n: u64) -> &mut Self {
+        let r = self.b.register(n);
+        match r {
+            Ok(r) => self.add(name, r),
+            Err(err) => self.fail(err),
+        }
+    }
+
+    /// Add the existing register `r` to the scope as `name`.
+    pub fn add(&mut self, name: &str, r: Register) -> &mut Self {
+        if self.registers.iter().any(|(rname, _)| rname == name) {
+            let message = format!("Register {:?} is already in the scope", name);
+            return self.fail(CircuitError::new(message));
+        }
+        self.registers.push((name.to_string(), Some(r)));
+        self
+    }
+
+    /// Apply `f` to the registers called `names`, in that order. `f` must return registers of the
+    /// same sizes in the same order, which replace them in the scope.
+    pub fn apply<F>(&mut self, names: &[&str], f: F) -> &mut Self
+    where
+        F: FnOnce(&mut dyn UnitaryBuilder, Vec<Register>) -> Result<Vec<Register>, CircuitError>,
+    {
+        if self.error.is_some() {
+            return self;
+        }
+        let result = self.take_registers(names).and_then(|rs| {
+            let widths: Vec<u64> = rs.iter().map(|r| r.n()).collect();
+            let rs = f(self.b, rs)?;
+            let found: Vec<u64> = rs.iter().map(|r| r.n()).collect();
+            if found == widths {
+                Ok(rs)
+            } else {
+                let message = format!(
+                    "Op on {:?} with {:?} qubits returned registers of {:?} qubits",
+                    names, widths, found
+                );
+                CircuitError::make_err(message)
+            }
+        });
+        match result {
+            Ok(rs) => {
+                names.iter().zip(rs).for_each(|(name, r)| {
+                    let entry = self.registers.iter_mut().find(|(rname, _)| rname == name);
+                    entry.unwrap().1 = Some(r);
+                });
+                self
+            }
+            Err(err) => self.fail(err),
+        }
+    }
+
+    /// Take the registers called `names` out of the scope.
+    fn take_registers(&mut self, names: &[&str]) -> Result<Vec<Register>, CircuitError> {
+        let duplicate = names
+            .iter()
+            .enumerate()
+            .find(|(i, name)| names[..*i].contains(name));
+        if let Some((_, name)) = duplicate {
+            let message = format!("Register {:?} is used more than once in one op", name);
+            return CircuitError::make_err(message);
+        }
+        if let Some(name) = names
+            .iter()
+            .find(|name| !self.registers.iter().any(|(rname, _)| rname == *name))
+        {
+            let message = format!("No register {:?} in the scope", name);
+            return CircuitError::make_err(message);
+        }
+        Ok(names
+            .iter()
+            .map(|name| {
+                let entry = self.registers.iter_mut().find(|(rname, _)| rname == name);
+                entry.unwrap().1.take().unwrap()
+            })
+            .collect())
+    }
+
+    fn fail(&mut self, err: CircuitError) -> &mut Self {
+        if self.error.is_none() {
+            self.error = Some(err);
+        }
+        self
+    }
+
+    fn single<F: FnOnce(&mut dyn UnitaryBuilder, Register) -> Register>(
+        &mut self,
+        name: &str,
+        f: F,
+    ) -> &mut Self {
+        self.apply(&[name], |b, mut rs| Ok(vec![f(b, rs.pop().unwrap())]))
+    }
+
+    fn pair<F: FnOnce(&mut dyn UnitaryBuilder, Register, Register) -> (Register, Register)>(
+        &mut self,
+        a: &str,
+        b: &str,
+        f: F,
+    ) -> &mut Self {
+        self.apply(&[a, b], |builder, mut rs| {
+            let rb = rs.pop().unwrap();
+            let (ra, rb) = f(builder, rs.pop().unwrap(), rb);
+            Ok(vec![ra, rb])
+        })
+    }
+
+    /// Apply NOT to the register `name`.
+    pub fn x(&mut self, name: &str) -> &mut Self {
+        self.single(name, |b, r| b.x(r))
+    }
+
+    /// Apply Y to the register `name`.
+    pub fn y(&mut self, name: &str) -> &mut Self {
+        self.single(name, |b, r| b.y(r))
+    }
+
+    /// Apply Z to the register `name`.
+    pub fn z(&mut self, name: &str) -> &mut Self {
+        self.single(name, |b, r| b.z(r))
+    }
+
+    /// Apply the hadamard to the register `name`.
+    pub fn h(&mut self, name: &str) -> &mut Self {
+        self.single(name, |b, r| b.hadamard(r))
+    }
+
+    /// Apply S to the register `name`.
+    pub fn s(&mut self, name: &str) -> &mut Self {
+        self.single(name, |b, r| b.s(r))
+    }
+
+    /// Apply the adjoint of S to the register `name`.
+    pub fn sdagger(&mut self, name: &str) -> &mut Self {
+        self.single(name, |b, r| b.sdagger(r))
+    }
+
+    /// Apply T to the register `name`.
+    pub fn t(&mut self, name: &str) -> &mut Self {
+        self.single(name, |b, r| b.t(r))
+    }
+
+    /// Apply the adjoint of T to the register `name`.
+    pub fn tdagger(&mut self, name: &str) -> &mut Self {
+        self.single(name, |b, r| b.tdagger(r))
+    }
+
+    /// Rotate the register `name` around the x axis by `theta`.
+    pub fn rx(&mut self, name: &str, theta: f64) -> &mut Self {
+        self.single(name, |b, r| b.rx(r, theta))
+    }
+
+    /// Rotate the register `name` around the y axis by `theta`.
+    pub fn ry(&mut self, name: &str, theta: f64) -> &mut Self {
+        self.single(name, |b, r| b.ry(r, theta))
+    }
+
+    /// Rotate the register `name` around the z axis by `theta`.
+    pub fn rz(&mut self, name: &str, theta: f64) -> &mut Self {
+        self.single(name, |b, r| b.rz(r, theta))
+    }
+
+    /// Apply NOT to the register `target` conditioned on all of `control`.
+    pub fn cnot(&mut self, control: &str, target: &str) -> &mut Self {
+        self.pair(control, target, |b, c, r| b.cnot(c, r))
+    }
+
+    /// Apply Z to the register `target` conditioned on all of `control`.
+    pub fn cz(&mut self, control: &str, target: &str) -> &mut Self {
+        self.pair(control, target, |b, c, r| b.cz(c, r))
+    }
+
+    /// Swap the registers `a` and `b`, which must be the same size.
+    pub fn swap(&mut self, a: &str, b: &str) -> &mut Self {
+        self.apply(&[a, b], |builder, mut rs| {
+            let rb = rs.pop().unwrap();
+            let (ra, rb) = builder.swap(rs.pop().unwrap(), rb)?;
+            Ok(vec![ra, rb])
+        })
+    }
+
+    /// Finish the scope, returning its registers with their names in the order they were added,
+    /// or the first error recorded by an op.
+    pub fn build(self) -> Result<Vec<(String, Register)>, CircuitError> {
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(self
+                .registers
+                .into_iter()
+                .map(|(name, r)| (name, r.unwrap()))
+                .collect()),
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use crate::errors::CircuitError;
+use crate::pipeline::{run_local, QuantumState};
+use crate::rng::random;
+use crate::state_ops::{from_reals, from_tuples, UnitaryOp};
+use crate::{Complex, Precision, Register};
+
+/// The measurement bases and outcomes of one snapshot, for each qubit of the Register.
+#[derive(Debug, Clone)]
+struct Snapshot {
+    bases: Vec<char>,
+    outcomes: Vec<bool>,
+}
+
+/// A classical shadow of the state of a Register: the results of measuring many copies of the
+/// state, each after a random single qubit Clifford on every qubit. Expectation values of Pauli
+/// strings can be estimated from a shadow without knowing them in advance, with the number of
+/// snapshots needed growing as `3^k` for strings acting on `k` qubits rather than with the size of
+/// the state.
+#[derive(Debug, Clone)]
+pub struct ClassicalShadow {
+    n: u64,
+    snapshots: Vec<Snapshot>,
+}
+
+/// Collect a classical shadow of `num_snapshots` snapshots of the state of `r`. The circuit is
+/// run once, then for each snapshot every qubit of `r` is rotated with a random Clifford which
+/// takes the X, Y or Z basis onto the computational basis, and the qubits are measured.
+///
+/// Random choices use the generator from `rng::with_seed` when there is one.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::rng::with_seed;
+/// use qip::shadows::classical_shadow;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let q = b.qubit();
+/// let r = b.qubit();
+/// let q = b.hadamard(q);
+/// let (q, r) = b.cnot(q, r);
+/// let r = b.merge(vec![q, r])?;
+///
+/// let shadow = with_seed(1, || classical_shadow::<f64>(&r, 2000))?;
+/// // A bell pair has <ZZ> = <XX> = 1 and <ZI> = 0.
+/// assert!((shadow.estimate("ZZ")? - 1.0).abs() < 0.2);
+/// assert!((shadow.estimate("XX")? - 1.0).abs() < 0.2);
+/// assert!(shadow.estimate("ZI")?.abs() < 0.2);
+/// # Ok(())
+/// # }
+/// ```
+pub fn classical_shadow<P: Precision>(
+    r: &Register,
+    num_snapshots: usize,
+) -> Result<ClassicalShadow, CircuitError> {
+    if num_snapshots == 0 {
+        return CircuitError::make_str_err("A classical shadow needs at least one snapshot.");
+    }
+    let (state, _) = run_local::<P>(r)?;
+    let snapshots = (0..num_snapshots)
+        .map(|_| {
+            let bases: Vec<char> = r
+                .indices
+                .iter()
+                .map(|_| match random::<u64>() % 3 {
+                    0 => 'X',
+                    1 => 'Y',
+                    _ => 'Z',
+                })
+                .collect();
+            let mut copy = state.clone();
+            r.indices
+                .iter()
+                .zip(bases.iter())
+                .for_each(|(indx, basis)| {
+                    if let Some(mat) = basis_rotation(*basis) {
+                        copy.apply_op(&UnitaryOp::Matrix(vec![*indx], mat));
+                    }
+                });
+            let (measured, _) = copy.measure(&r.indices, None, 0.0);
+            let outcomes = (0..r.indices.len())
+                .map(|i| (measured >> i) & 1 == 1)
+                .collect();
+            Snapshot { bases, outcomes }
+        })
+        .collect();
+    Ok(ClassicalShadow {
+        n: r.n(),
+        snapshots,
+    })
+}
+
+/// The rotation taking the eigenbasis of `basis` onto the computational basis, or None for Z.
+fn basis_rotation(basis: char) -> Option<Vec<Complex<f64>>> {
+    let h = std::f64::consts::FRAC_1_SQRT_2;
+    match basis {
+        'X' => Some(from_reals(&[h, h, h, -h])),
+        // H S^dagger
+        'Y' => Some(from_tuples(&[(h, 0.0), (0.0, -h), (h, 0.0), (0.0, h)])),
+        _ => None,
+    }
+}
+
+impl ClassicalShadow {
+    /// Get the number of qubits in the Register the shadow was taken of.
+    pub fn n(&self) -> u64 {
+        self.n
+    }
+
+    /// Get the number of snapshots in the shadow.
+    pub fn num_snapshots(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    /// Estimate the expectation value of the Pauli string `pauli` made of `I`, `X`, `Y` and `Z`,
+    /// where the character at position `j` acts on qubit `j` of the Register. This is the mean
+    /// over all snapshots of the classical shadows estimator.
+    pub fn estimate(&self, pauli: &str) -> Result<f64, CircuitError> {
+        let paulis = self.parse(pauli)?;
+        Ok(self.mean(&paulis, &self.snapshots))
+    }
+
+    /// Estimate the expectation value of the Pauli string `pauli` as with `estimate`, but using
+    /// the median of the means of `num_groups` equal groups of snapshots. This is less affected by
+    /// rare outlying snapshots, which matters when estimating many observables at once.
+    pub fn estimate_median_of_means(
+        &self,
+        pauli: &str,
+        num_groups: usize,
+    ) -> Result<f64, CircuitError> {
+        if num_groups == 0 || num_groups > self.snapshots.len() {
+            let message = format!(
+                "Cannot split {:?} snapshots into {:?} groups",
+                self.snapshots.len(),
+                num_groups
+            );
+            return CircuitError::make_err(message);
+        }
+        let paulis = self.parse(pauli)?;
+        let group_size = self.snapshots.len() / num_groups;
+        let mut means: Vec<f64> = (0..num_groups)
+            .map(|g| {
+                let group = &self.snapshots[g * group_size..(g + 1) * group_size];
+                self.mean(&paulis, group)
+            })
+            .collect();
+        means.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let mid = num_groups / 2;
+        Ok(if num_groups & 1 == 1 {
+            means[mid]
+        } else {
+            (means[mid - 1] + means[mid]) / 2.0
+        })
+    }
+
+    /// Estimate the expectation value of a hamiltonian given as a weighted sum of Pauli strings,
+    /// as in `vqe`.
+    pub fn estimate_observable(&self, hamiltonian: &[(f64, &str)]) -> Result<f64, CircuitError> {
+        hamiltonian.iter().try_fold(0.0, |acc, (weight, pauli)| {
+            Ok(acc + weight * self.estimate(pauli)?)
+        })
+    }
+
+    /// Get the non-identity terms of `pauli` along with the qubit they act on.
+    fn parse(&self, pauli: &str) -> Result<Vec<(usize, char)>, CircuitError> {
+        if pauli.len() as u64 > self.n {
+            let message = format!(
+                "Pauli string {:?} is longer than the register ({:?} qubits)",
+                pauli, self.n
+            );
+            return CircuitError::make_err(message);
+        }
+        pauli
+            .chars()
+            .enumerate()
+            .try_fold(vec![], |mut acc, (i, c)| match c {
+                'X' | 'Y' | 'Z' => {
+                    acc.push((i, c));
+                    Ok(acc)
+                }
+                'I' => Ok(acc),
+                c => CircuitError::make_err(format!(
+                    "Pauli strings may only contain I, X, Y and Z, found {:?}",
+                    c
+                )),
+            })
+    }
+
+    /// Average the estimator over `snapshots`. Each snapshot measured in the bases of every term
+    /// gives `3^k` times the product of the measured signs, and all others give zero.
+    fn mean(&self, paulis: &[(usize, char)], snapshots: &[Snapshot]) -> f64 {
+        let scale = 3f64.powi(paulis.len() as i32);
+        let total: f64 = snapshots
+            .iter()
+            .filter(|s| paulis.iter().all(|(i, c)| s.bases[*i] == *c))
+            .map(|s| {
+                let flips = paulis.iter().filter(|(i, _)| s.outcomes[*i]).count();
+                if flips & 1 == 0 {
+                    scale
+                } else {
+                    -scale
+                }
+            })
+            .sum();
+        total / snapshots.len() as f64
+    }
+}
@@ -0,0 +1,234 @@
+use crate::boolean_circuits::arithmetic::{mod_mul_const, pow_mod};
+use crate::common_circuits::phase_estimation;
+use crate::errors::CircuitError;
+use crate::pipeline::QuantumState;
+use crate::rng;
+use crate::sparse_state::run_sparse_local;
+use crate::{OpBuilder, Register, UnitaryBuilder};
+
+/// Apply the order finding circuit for `a` modulo `modulus` to `precision` and `target`, both of
+/// which should start as `|0...0>`. The target is prepared as `|1>` and phase estimation is
+/// applied for the unitary `|x> -> |a x mod modulus>`, so that measuring `precision` gives
+/// approximately `s 2^t / r` for a random `s`, where `r` is the order of `a` and `t` is the
+/// number of precision qubits. The target needs `ceil(log2(modulus))` qubits.
+pub fn order_finding(
+    b: &mut dyn UnitaryBuilder,
+    precision: Register,
+    target: Register,
+    a: u64,
+    modulus: u64,
+) -> Result<(Register, Register), CircuitError> {
+    b.push_name_scope("OrderFinding");
+    let (one, rest) = b.split(target, &[0])?;
+    let one = b.not(one);
+    let result = match rest {
+        Some(rest) => b.merge(vec![one, rest]),
+        None => Ok(one),
+    }
+    .and_then(|target| {
+        phase_estimation(b, precision, target, |b, r, power| {
+            mod_mul_const(b, r, pow_mod(a, power, modulus), modulus)
+        })
+    });
+    b.pop_name_scope();
+    result
+}
+
+/// Find the order of `a` modulo `modulus`, the smallest `r > 0` with `a^r = 1 mod modulus`, by
+/// running the order finding circuit with `precision` qubits and sampling it `shots` times. Each
+/// sample is expanded as a continued fraction and the denominators of its convergents are checked
+/// classically. Returns `None` if no sample revealed the order.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::shor::find_order;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let order = find_order(2, 5, 3, 10)?;
+/// assert_eq!(order, Some(4));
+/// # Ok(())
+/// # }
+/// ```
+pub fn find_order(
+    a: u64,
+    modulus: u64,
+    precision: u64,
+    shots: usize,
+) -> Result<Option<u64>, CircuitError> {
+    if modulus < 2 {
+        return CircuitError::make_err(format!("Expected modulus > 1, found {}", modulus));
+    }
+    if gcd(a, modulus) != 1 {
+        return CircuitError::make_err(format!(
+            "Expected a={} to be coprime to modulus={}",
+            a, modulus
+        ));
+    }
+    let n = 64 - u64::from((modulus - 1).leading_zeros());
+    let mut b = OpBuilder::new();
+    let rp = b.register(precision)?;
+    let rt = b.register(n)?;
+    let (rp, rt) = order_finding(&mut b, rp, rt, a, modulus)?;
+    let indices = rp.indices.clone();
+    let r = b.merge(vec![rp, rt])?;
+
+    let (mut state, _) = run_sparse_local::<f64>(&r)?;
+    let cumulative: Vec<f64> = state
+        .stochastic_measure(&indices, 0.0)
+        .into_iter()
+        .scan(0.0, |acc, p| {
+            *acc += p;
+            Some(*acc)
+        })
+        .collect();
+    let total = cumulative.last().cloned().unwrap_or(0.0);
+
+    // Denominators which weren't the order may still be factors of it.
+    let mut denominators = vec![];
+    let mut order: Option<u64> = None;
+    (0..shots).for_each(|_| {
+        let x = rng::random::<f64>() * total;
+        let y = cumulative
+            .iter()
+            .position(|c| x < *c)
+            .unwrap_or(cumulative.len() - 1) as u64;
+        convergents(y, 1 << precision)
+            .into_iter()
+            .map(|(_, q)| q)
+            .filter(|q| *q > 0 && *q < modulus)
+            .for_each(|q| {
+                if pow_mod(a, q, modulus) == 1 {
+                    order = Some(order.map_or(q, |r| r.min(q)));
+                } else {
+                    denominators.push(q);
+                }
+            });
+    });
+    if order.is_none() {
+        order = denominators
+            .iter()
+            .flat_map(|p| denominators.iter().map(move |q| lcm(*p, *q)))
+            .filter(|r| *r < modulus && pow_mod(a, *r, modulus) == 1)
+            .min();
+    }
+    Ok(order)
+}
+
+/// Try to find a pair of nontrivial factors of `modulus` using Shor's algorithm with the guess
+/// `a`, see `find_order`. Returns `None` if this guess failed, in which case another `a` may
+/// succeed.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::rng::with_seed;
+/// use qip::shor::shor_factor;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// // The order of 2 mod 15 is 4, which reveals 15 = 3 * 5 using 2^2 +- 1.
+/// let factors = with_seed(1, || shor_factor(15, 2, 3, 10))?;
+/// assert_eq!(factors, Some((3, 5)));
+/// # Ok(())
+/// # }
+/// ```
+pub fn shor_factor(
+    modulus: u64,
+    a: u64,
+    precision: u64,
+    shots: usize,
+) -> Result<Option<(u64, u64)>, CircuitError> {
+    let sorted = |p: u64, q: u64| Some((p.min(q), p.max(q)));
+    if modulus & 1 == 0 && modulus > 2 {
+        return Ok(sorted(2, modulus / 2));
+    }
+    let d = gcd(a, modulus);
+    if d != 1 {
+        return Ok(if d == modulus {
+            None
+        } else {
+            sorted(d, modulus / d)
+        });
+    }
+    let order = find_order(a, modulus, precision, shots)?;
+    Ok(order.and_then(|r| {
+        if r % 2 != 0 {
+            return None;
+        }
+        let y = pow_mod(a, r / 2, modulus);
+        if y == modulus - 1 {
+            return None;
+        }
+        [y - 1, y + 1]
+            .iter()
+            .map(|v| gcd(*v, modulus))
+            .find(|f| *f != 1 && *f != modulus)
+            .and_then(|f| sorted(f, modulus / f))
+    }))
+}
+
+/// Get the convergents `(p, q)` of the continued fraction expansion of `numerator / denominator`.
+pub fn convergents(numerator: u64, denominator: u64) -> Vec<(u64, u64)> {
+    let (mut num, mut den) = (numerator, denominator);
+    let (mut p_prev, mut p) = (0u64, 1u64);
+    let (mut q_prev, mut q) = (1u64, 0u64);
+    let mut result = vec![];
+    while den != 0 {
+        let a = num / den;
+        let (p_next, q_next) = (a * p + p_prev, a * q + q_prev);
+        p_prev = p;
+        p = p_next;
+        q_prev = q;
+        q = q_next;
+        result.push((p, q));
+        let rem = num % den;
+        num = den;
+        den = rem;
+    }
+    result
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn lcm(a: u64, b: u64) -> u64 {
+    a / gcd(a, b) * b
+}
+
+#[cfg(test)]
+mod shor_tests {
+    use super::*;
+
+    #[test]
+    fn test_convergents() {
+        // 3 / 8 = [0; 2, 1, 2]
+        assert_eq!(convergents(3, 8), vec![(0, 1), (1, 2), (1, 3), (3, 8)]);
+        assert_eq!(convergents(0, 8), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn test_find_order() -> Result<(), CircuitError> {
+        assert_eq!(find_order(7, 15, 3, 20)?, Some(4));
+        assert_eq!(find_order(4, 15, 3, 20)?, Some(2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_order_not_coprime() {
+        assert!(find_order(6, 15, 3, 20).is_err());
+    }
+
+    #[test]
+    fn test_shor_factor() -> Result<(), CircuitError> {
+        assert_eq!(shor_factor(15, 7, 3, 20)?, Some((3, 5)));
+        assert_eq!(shor_factor(15, 6, 3, 20)?, Some((3, 5)));
+        // The order of 14 is 2 but 14 = -1 mod 15.
+        assert_eq!(shor_factor(15, 14, 3, 20)?, None);
+        Ok(())
+    }
+}
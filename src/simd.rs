@@ -0,0 +1,99 @@
+//! SSE2 kernels for `f64` amplitudes. SSE2 is part of the x86_64 baseline so no runtime detection
+//! is needed. Each `Complex<f64>` fits exactly in one `__m128d` as `[re, im]`.
+#![allow(unsafe_code)]
+
+use crate::Complex;
+use std::arch::x86_64::*;
+
+/// A matrix entry `m` prepared for multiplying as `[m.re, m.re] * x + [-m.im, m.im] * swap(x)`.
+#[derive(Clone, Copy)]
+struct Entry {
+    re: __m128d,
+    im: __m128d,
+}
+
+#[inline(always)]
+unsafe fn prepare(m: &Complex<f64>) -> Entry {
+    Entry {
+        re: _mm_set1_pd(m.re),
+        im: _mm_set_pd(m.im, -m.im),
+    }
+}
+
+#[inline(always)]
+unsafe fn load(c: &Complex<f64>) -> (__m128d, __m128d) {
+    // Complex is repr(C) so it has the layout [re, im].
+    let p: *const Complex<f64> = c;
+    let x = _mm_loadu_pd(p as *const f64);
+    (x, _mm_shuffle_pd(x, x, 1))
+}
+
+#[inline(always)]
+unsafe fn store(c: &mut Complex<f64>, v: __m128d) {
+    let p: *mut Complex<f64> = c;
+    _mm_storeu_pd(p as *mut f64, v)
+}
+
+#[inline(always)]
+unsafe fn mul(m: Entry, x: (__m128d, __m128d)) -> __m128d {
+    _mm_add_pd(_mm_mul_pd(m.re, x.0), _mm_mul_pd(m.im, x.1))
+}
+
+/// See `state_ops::single_qubit_kernel`.
+pub(crate) fn single_qubit_kernel_f64(
+    mat: &[Complex<f64>; 4],
+    zeros: &mut [Complex<f64>],
+    ones: &mut [Complex<f64>],
+    offset: u64,
+    control_mask: u64,
+) {
+    unsafe {
+        let m = [
+            prepare(&mat[0]),
+            prepare(&mat[1]),
+            prepare(&mat[2]),
+            prepare(&mat[3]),
+        ];
+        zeros
+            .iter_mut()
+            .zip(ones.iter_mut())
+            .enumerate()
+            .filter(|(j, _)| (offset + *j as u64) & control_mask == control_mask)
+            .for_each(|(_, (a, b))| {
+                let (x, y) = (load(a), load(b));
+                store(a, _mm_add_pd(mul(m[0], x), mul(m[1], y)));
+                store(b, _mm_add_pd(mul(m[2], x), mul(m[3], y)));
+            });
+    }
+}
+
+/// See `state_ops::two_qubit_kernel`.
+pub(crate) fn two_qubit_kernel_f64(
+    mat: &[Complex<f64>; 16],
+    x: [&mut [Complex<f64>]; 4],
+    offset: u64,
+    control_mask: u64,
+) {
+    let [x0, x1, x2, x3] = x;
+    unsafe {
+        let mut m = [prepare(&mat[0]); 16];
+        m.iter_mut()
+            .zip(mat.iter())
+            .for_each(|(e, c)| *e = prepare(c));
+        (0..x0.len())
+            .filter(|j| (offset + *j as u64) & control_mask == control_mask)
+            .for_each(|j| {
+                let v = [load(&x0[j]), load(&x1[j]), load(&x2[j]), load(&x3[j])];
+                let row = |r: usize| {
+                    _mm_add_pd(
+                        _mm_add_pd(mul(m[4 * r], v[0]), mul(m[4 * r + 1], v[1])),
+                        _mm_add_pd(mul(m[4 * r + 2], v[2]), mul(m[4 * r + 3], v[3])),
+                    )
+                };
+                store(&mut x0[j], row(0));
+                store(&mut x1[j], row(1));
+                store(&mut x2[j], row(2));
+                store(&mut x3[j], row(3));
+            });
+    }
+}
@@ -0,0 +1,152 @@
+use crate::errors::CircuitError;
+use crate::pipeline::run_and_sample;
+use crate::{classical_oracle, OpBuilder, Register, UnitaryBuilder};
+use std::sync::Arc;
+
+/// Apply the circuit for Simon's algorithm with the function `f` on `n` bits, which is promised
+/// to have a period `s`: `f(x) = f(y)` exactly when `x = y` or `x ^ y = s`. Returns the input and
+/// output registers, both of `n` qubits. Measuring the input gives a uniformly random `y` with
+/// `y . s = 0 mod 2`, values of `f` are masked to `n` bits.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::simon::simon_circuit;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let (r, _) = simon_circuit(&mut b, |x| x & 0b10, 2)?;
+/// let (r, m) = b.measure(r);
+/// let (_, measured) = run_local::<f64>(&r)?;
+/// let (y, _) = measured.get_measurement(&m).unwrap();
+/// // The period is 0b01, so the lowest bit of every sample is 0.
+/// assert_eq!(y & 1, 0);
+/// # Ok(())
+/// # }
+/// ```
+pub fn simon_circuit<F: 'static + Fn(u64) -> u64 + Send + Sync>(
+    b: &mut dyn UnitaryBuilder,
+    f: F,
+    n: u64,
+) -> Result<(Register, Register), CircuitError> {
+    if n == 0 || n > 32 {
+        let message = format!("Simon's algorithm needs 1 to 32 bits, found {:?}", n);
+        return CircuitError::make_err(message);
+    }
+    b.push_name_scope("Simon");
+    let mask = (1 << n) - 1;
+    let r_in = b.register(n)?;
+    let r_out = b.register(n)?;
+    let r_in = b.hadamard(r_in);
+    let result = classical_oracle(b, r_in, r_out, move |x| f(x) & mask).map(|(r_in, r_out)| {
+        let r_in = b.hadamard(r_in);
+        (r_in, r_out)
+    });
+    b.pop_name_scope();
+    result
+}
+
+/// Find the period of Simon's algorithm from sampled values `samples` of the `n` input qubits,
+/// by solving `y . s = 0 mod 2` for every sample `y` with gaussian elimination over GF(2).
+/// Returns `Some(0)` if the samples have rank `n`, so only `s = 0` is consistent with them,
+/// `Some(s)` for the single nonzero solution if they have rank `n - 1`, and `None` if more samples
+/// are needed to pin down the period.
+///
+/// If the rank is `n - 1` the true period may still be `0` (that is, `f` is one to one), which can
+/// be ruled out by checking `f(0) = f(s)`; `simon` does this.
+///
+/// # Example
+/// ```
+/// use qip::simon::solve_period;
+///
+/// assert_eq!(solve_period(&[0b011, 0b100], 3), Some(0b011));
+/// assert_eq!(solve_period(&[0b011], 3), None);
+/// assert_eq!(solve_period(&[0b001, 0b010, 0b100], 3), Some(0));
+/// ```
+pub fn solve_period(samples: &[u64], n: u64) -> Option<u64> {
+    let mask = if n >= 64 { !0 } else { (1 << n) - 1 };
+    // Rows of the reduced echelon form, each with its pivot bit.
+    let mut rows: Vec<(u64, u64)> = vec![];
+    samples.iter().for_each(|sample| {
+        let mut row = sample & mask;
+        rows.iter().for_each(|(pivot, r)| {
+            if row & (1 << pivot) != 0 {
+                row ^= r;
+            }
+        });
+        if row != 0 {
+            let pivot = u64::from(row.trailing_zeros());
+            rows.iter_mut().for_each(|(_, r)| {
+                if *r & (1 << pivot) != 0 {
+                    *r ^= row;
+                }
+            });
+            rows.push((pivot, row));
+        }
+    });
+    let rank = rows.len() as u64;
+    if rank == n {
+        Some(0)
+    } else if rank + 1 == n {
+        let free = (0..n).find(|bit| rows.iter().all(|(pivot, _)| pivot != bit))?;
+        // Setting the free bit fixes each pivot bit to that row's entry in the free column.
+        let period = rows
+            .iter()
+            .filter(|(_, r)| r & (1 << free) != 0)
+            .fold(1 << free, |s, (pivot, _)| s | (1 << pivot));
+        Some(period)
+    } else {
+        None
+    }
+}
+
+/// Run Simon's algorithm to find the period `s` of `f` on `n` bits, see `simon_circuit`. The
+/// circuit is sampled in batches of `n` shots until the samples determine the period or
+/// `max_samples` have been taken, in which case an error is returned. A nonzero candidate is
+/// checked classically with `f(0) = f(s)`, a one to one `f` gives a period of `0`.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::rng::with_seed;
+/// use qip::simon::simon;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// // Two to one with period 0b101.
+/// let f = |x: u64| std::cmp::min(x, x ^ 0b101);
+/// let period = with_seed(1, || simon(f, 3, 100))?;
+/// assert_eq!(period, 0b101);
+/// # Ok(())
+/// # }
+/// ```
+pub fn simon<F: 'static + Fn(u64) -> u64 + Send + Sync>(
+    f: F,
+    n: u64,
+    max_samples: usize,
+) -> Result<u64, CircuitError> {
+    let f = Arc::new(f);
+    let mut b = OpBuilder::new();
+    let oracle = f.clone();
+    let (r_in, r_out) = simon_circuit(&mut b, move |x| oracle(x), n)?;
+    let r = b.merge(vec![r_in, r_out])?;
+    let mask = (1 << n) - 1;
+
+    let mut samples = vec![];
+    while samples.len() < max_samples {
+        let shots = std::cmp::min(n as usize, max_samples - samples.len());
+        let counts = run_and_sample::<f64>(&r, shots)?;
+        counts
+            .into_iter()
+            .for_each(|(value, count)| (0..count).for_each(|_| samples.push(value & mask)));
+        match solve_period(&samples, n) {
+            Some(0) => return Ok(0),
+            Some(s) if f(0) & mask == f(s) & mask => return Ok(s),
+            _ => {}
+        }
+    }
+    let message = format!(
+        "Could not determine the period after {:?} samples",
+        max_samples
+    );
+    CircuitError::make_err(message)
+}
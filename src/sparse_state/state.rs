@@ -1,8 +1,11 @@
+use crate::errors::CircuitError;
 use crate::iterators::{fold_for_op_cols, precision_get_index, precision_num_indices};
 use crate::measurement_ops::MeasuredCondition;
-use crate::pipeline::{create_state_entry, InitialState};
+use crate::parallel::prelude::*;
+use crate::pipeline::{create_state_entry, InitialState, LocalQuantumState};
+use crate::rng;
 use crate::sparse_state::utils::{
-    consolidate_vec, sparse_measure, sparse_measure_prob, sparse_measure_probs, sparse_soft_measure,
+    consolidate, sparse_measure, sparse_measure_prob, sparse_measure_probs, sparse_soft_measure,
 };
 use crate::state_ops::{
     clone_as_precision_op, from_reals, full_to_sub, make_matrix_op, sub_to_full, transpose_op,
@@ -11,18 +14,43 @@ use crate::state_ops::{
 use crate::utils::flip_bits;
 use crate::{Complex, Precision, QuantumState};
 use num::{One, Zero};
-use rayon::prelude::*;
 use std::cmp::max;
+use std::collections::HashMap;
+
+/// Default fraction of nonzero amplitudes above which the state switches to a dense vector.
+const DEFAULT_DENSITY_THRESHOLD: f64 = 0.25;
 
 /// A quantum state which doesn't track zero values.
+///
+/// Nonzero amplitudes are kept in a hash map keyed by index. Once the fraction of nonzero
+/// amplitudes grows past the density threshold the state is converted to a `LocalQuantumState`,
+/// which is faster when most amplitudes are nonzero. The state stays dense after that.
 #[derive(Debug)]
 pub struct SparseQuantumState<P: Precision> {
     n: u64,
-    state: Option<Vec<(u64, Complex<P>)>>,
+    state: SparseStorage<P>,
+    density_threshold: f64,
     multithread: bool,
 }
 
+#[derive(Debug)]
+enum SparseStorage<P: Precision> {
+    Sparse(HashMap<u64, Complex<P>>),
+    Dense(LocalQuantumState<P>),
+}
+
 impl<P: Precision> SparseQuantumState<P> {
+    fn new_from_sparse(n: u64, state: HashMap<u64, Complex<P>>) -> Self {
+        let mut s = Self {
+            n,
+            state: SparseStorage::Sparse(state),
+            density_threshold: DEFAULT_DENSITY_THRESHOLD,
+            multithread: true,
+        };
+        s.check_density();
+        s
+    }
+
     /// Rotate to a new computational basis:
     /// `|0'> =  cos(angle)|0> + sin(angle)|1>`
     /// `|1'> = -sin(angle)|0> + cos(angle)|1>`
@@ -37,26 +65,77 @@ impl<P: Precision> SparseQuantumState<P> {
         }
     }
 
-    /// Operate on state
+    /// Operate on the nonzero entries of the state, sorted by index.
     pub fn borrow_state<T, F: FnOnce(&Vec<(u64, Complex<P>)>) -> T>(&mut self, f: F) -> T {
-        let s = self.state.take();
-        let ret = if let Some(s) = &s {
-            f(s)
-        } else {
-            panic!();
+        let mut s: Vec<(u64, Complex<P>)> = match &self.state {
+            SparseStorage::Sparse(s) => s.iter().map(|(indx, v)| (*indx, *v)).collect(),
+            SparseStorage::Dense(s) => s
+                .state_ref()
+                .iter()
+                .enumerate()
+                .filter(|(_, v)| !v.is_zero())
+                .map(|(indx, v)| (indx as u64, *v))
+                .collect(),
         };
-        self.state = s;
-        ret
+        s.sort_unstable_by_key(|(indx, _)| *indx);
+        f(&s)
+    }
+
+    /// Set the fraction of nonzero amplitudes above which the state switches to a dense vector.
+    /// A threshold of `1.0` or more keeps the state sparse.
+    pub fn set_density_threshold(&mut self, threshold: f64) {
+        self.density_threshold = threshold;
+        self.check_density();
+    }
+
+    /// Check if the state has switched to a dense vector.
+    pub fn is_dense(&self) -> bool {
+        match &self.state {
+            SparseStorage::Sparse(_) => false,
+            SparseStorage::Dense(_) => true,
+        }
+    }
+
+    /// Number of amplitudes currently stored.
+    pub fn num_stored(&self) -> usize {
+        match &self.state {
+            SparseStorage::Sparse(s) => s.len(),
+            SparseStorage::Dense(s) => s.state_ref().len(),
+        }
+    }
+
+    /// Set whether the state will use multithreading.
+    pub fn set_multithreading(&mut self, multithread: bool) {
+        self.multithread = multithread;
+        if let SparseStorage::Dense(s) = &mut self.state {
+            s.set_multithreading(multithread);
+        }
+    }
+
+    fn check_density(&mut self) {
+        let size = 2f64.powi(self.n as i32);
+        let dense_state = match &mut self.state {
+            SparseStorage::Sparse(s) if s.len() as f64 > self.density_threshold * size => {
+                let mut dense = vec![Complex::zero(); 1 << self.n];
+                s.drain().for_each(|(indx, v)| dense[indx as usize] = v);
+                Some(dense)
+            }
+            _ => None,
+        };
+        if let Some(dense) = dense_state {
+            let dense =
+                LocalQuantumState::new_from_full_state(self.n, dense, false, self.multithread)
+                    .unwrap();
+            self.state = SparseStorage::Dense(dense);
+        }
     }
 }
 
 impl<P: Precision> QuantumState<P> for SparseQuantumState<P> {
     fn new(n: u64) -> Self {
-        Self {
-            n,
-            state: Some(vec![(0, Complex::one())]),
-            multithread: true,
-        }
+        let mut state = HashMap::new();
+        state.insert(0, Complex::one());
+        Self::new_from_sparse(n, state)
     }
 
     fn new_from_initial_states(n: u64, states: &[(Vec<u64>, InitialState<P>)]) -> Self {
@@ -90,26 +169,29 @@ impl<P: Precision> QuantumState<P> for SparseQuantumState<P> {
         });
 
         // Go through each combination of full index locations
-        let cvec = (0..1 << n_fullindices).fold(vec![], |mut acc, i| {
+        let cvec = (0..1 << n_fullindices).fold(HashMap::new(), |mut acc, i| {
             let (delta_index, val) = create_state_entry(n, i, states);
             if val != Complex::zero() {
-                acc.push((delta_index + template, val));
+                acc.insert(delta_index + template, val);
             }
             acc
         });
 
-        Self {
-            n,
-            state: Some(cvec),
-            multithread: true,
-        }
+        Self::new_from_sparse(n, cvec)
     }
 
     fn n(&self) -> u64 {
         self.n
     }
 
-    fn apply_op_with_name(&mut self, _name: Option<&str>, op: &UnitaryOp) {
+    fn apply_op_with_name(&mut self, name: Option<&str>, op: &UnitaryOp) {
+        let state = match &mut self.state {
+            SparseStorage::Dense(s) => {
+                s.apply_op_with_name(name, op);
+                return;
+            }
+            SparseStorage::Sparse(s) => s,
+        };
         // More efficient for sparse to use transposed op.
         let op = transpose_op(op.clone());
         let op = clone_as_precision_op::<P>(&op);
@@ -119,22 +201,61 @@ impl<P: Precision> QuantumState<P> for SparseQuantumState<P> {
         let mat_mask = sub_to_full(self.n, &mat_indices, std::u64::MAX, 0);
         let nindices = precision_num_indices(&op) as u64;
 
-        let state = self.state.as_ref().unwrap();
-        let f = |(col, val): &(u64, Complex<P>)| -> Vec<(u64, Complex<P>)> {
-            let matcol = full_to_sub(self.n, &mat_indices, *col);
+        let n = self.n;
+        let f = |(col, val): (&u64, &Complex<P>)| -> Vec<(u64, Complex<P>)> {
+            let matcol = full_to_sub(n, &mat_indices, *col);
             let col_template = (*col) & !mat_mask;
             fold_for_op_cols(nindices, matcol, &op, vec![], |mut acc, (row, row_val)| {
-                let full_row = sub_to_full(self.n, &mat_indices, row, col_template);
+                let full_row = sub_to_full(n, &mat_indices, row, col_template);
                 acc.push((full_row, val * row_val));
                 acc
             })
         };
-        let flat = if self.multithread {
+        let flat: Vec<_> = if self.multithread {
             state.par_iter().map(f).flatten().collect()
         } else {
             state.iter().map(f).flatten().collect()
         };
-        self.state = Some(consolidate_vec(flat, self.multithread));
+        *state = consolidate(flat);
+        self.check_density();
+    }
+
+    fn apply_channel(
+        &mut self,
+        name: Option<&str>,
+        indices: &[u64],
+        kraus_ops: &[Vec<Complex<f64>>],
+    ) -> Result<(), CircuitError> {
+        let state = match &mut self.state {
+            SparseStorage::Dense(s) => return s.apply_channel(name, indices, kraus_ops),
+            SparseStorage::Sparse(s) => s,
+        };
+        let ops = kraus_ops
+            .iter()
+            .map(|k| make_matrix_op(indices.to_vec(), k.clone()))
+            .collect::<Result<Vec<_>, CircuitError>>()?;
+        // Sample kraus operator k with probability |K_k psi|^2
+        let original = state.clone();
+        let mut r = P::from(rng::random::<f64>()).unwrap() * self.state_magnitude();
+        let last = ops.len() - 1;
+        for (i, op) in ops.iter().enumerate() {
+            self.state = SparseStorage::Sparse(original.clone());
+            self.apply_op(op);
+            let p = self.state_magnitude();
+            r = r - p;
+            if (r <= P::zero() || i == last) && !p.is_zero() {
+                let scale = P::one() / p.sqrt();
+                match &mut self.state {
+                    SparseStorage::Sparse(s) => s.values_mut().for_each(|c| *c = *c * scale),
+                    SparseStorage::Dense(s) => {
+                        s.mut_state_ref().iter_mut().for_each(|c| *c = *c * scale)
+                    }
+                }
+                return Ok(());
+            }
+        }
+        self.state = SparseStorage::Sparse(original);
+        CircuitError::make_str_err("Channel has zero probability for the current state")
     }
 
     fn measure(
@@ -143,59 +264,146 @@ impl<P: Precision> QuantumState<P> for SparseQuantumState<P> {
         measured: Option<MeasuredCondition<P>>,
         angle: f64,
     ) -> (u64, P) {
+        // Rotating the basis may switch the state to a dense vector, so dispatch afterwards.
         self.rotate_basis(indices, angle);
-        let state = self.state.take().unwrap();
-        let (measured_result, new_state) =
-            sparse_measure(self.n, indices, state, measured, self.multithread);
-        self.state = Some(new_state);
+        let measured_result = match &mut self.state {
+            SparseStorage::Sparse(state) => {
+                let (measured_result, new_state) =
+                    sparse_measure(self.n, indices, state, measured, self.multithread);
+                *state = new_state;
+                measured_result
+            }
+            SparseStorage::Dense(s) => s.measure(indices, measured, 0.0),
+        };
         self.rotate_basis(indices, -angle);
         measured_result
     }
 
     fn soft_measure(&mut self, indices: &[u64], measured: Option<u64>, angle: f64) -> (u64, P) {
         self.rotate_basis(indices, angle);
-        let state = self.state.as_ref().unwrap();
-        let m = if let Some(m) = measured {
-            m
-        } else {
-            sparse_soft_measure(self.n, indices, state, self.multithread)
+        let result = match &mut self.state {
+            SparseStorage::Sparse(state) => {
+                let m = if let Some(m) = measured {
+                    m
+                } else {
+                    sparse_soft_measure(self.n, indices, state, self.multithread)
+                };
+                let p = sparse_measure_prob(self.n, m, indices, state, self.multithread);
+                (m, p)
+            }
+            SparseStorage::Dense(s) => s.soft_measure(indices, measured, 0.0),
         };
-        let p = sparse_measure_prob(self.n, m, indices, state, self.multithread);
         self.rotate_basis(indices, -angle);
-        (m, p)
+        result
     }
 
     fn state_magnitude(&self) -> P {
-        let p: P = self
-            .state
-            .as_ref()
-            .unwrap()
-            .iter()
-            .map(|(_, v)| v.norm_sqr())
-            .sum();
-        p.sqrt()
+        match &self.state {
+            SparseStorage::Sparse(s) => s.values().map(|v| v.norm_sqr()).sum(),
+            SparseStorage::Dense(s) => s.state_magnitude(),
+        }
     }
 
     fn stochastic_measure(&mut self, indices: &[u64], angle: f64) -> Vec<P> {
         self.rotate_basis(indices, angle);
-        let state = self.state.as_ref().unwrap();
-        let probs = sparse_measure_probs(self.n, indices, state, self.multithread);
+        let probs = match &mut self.state {
+            SparseStorage::Sparse(state) => {
+                sparse_measure_probs(self.n, indices, state, self.multithread)
+            }
+            SparseStorage::Dense(s) => s.stochastic_measure(indices, 0.0),
+        };
         self.rotate_basis(indices, -angle);
         probs
     }
 
     fn get_state(self, natural_order: bool) -> Vec<Complex<P>> {
-        let mut state = vec![];
+        let state = match self.state {
+            SparseStorage::Sparse(state) => state,
+            SparseStorage::Dense(s) => return s.get_state(natural_order),
+        };
         let n = self.n as usize;
-        state.resize(1 << n, Complex::zero());
-        self.state.unwrap().into_iter().for_each(|(indx, val)| {
+        let mut dense = vec![Complex::zero(); 1 << n];
+        state.into_iter().for_each(|(indx, val)| {
             let indx = if natural_order {
                 flip_bits(n, indx)
             } else {
                 indx
             };
-            state[indx as usize] = val;
+            dense[indx as usize] = val;
+        });
+        dense
+    }
+}
+
+#[cfg(test)]
+mod sparse_state_tests {
+    use super::*;
+    use crate::pipeline::run;
+    use crate::{OpBuilder, UnitaryBuilder};
+
+    #[test]
+    fn test_cancellations_are_dropped() {
+        let mut state = SparseQuantumState::<f64>::new(6);
+        state.set_density_threshold(1.0);
+        let half = 0.5f64.sqrt();
+        let h = from_reals(&[half, half, half, -half]);
+        (0..2).for_each(|_| {
+            (0..6).for_each(|i| state.apply_op(&make_matrix_op(vec![i], h.clone()).unwrap()))
         });
+        assert!(!state.is_dense());
+        assert_eq!(state.num_stored(), 1);
+    }
+
+    #[test]
+    fn test_stays_sparse() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let r = b.register(10)?;
+        let qs = b.split_all(r);
+        let qs = qs
+            .into_iter()
+            .enumerate()
+            .map(|(i, q)| if i & 1 == 0 { b.not(q) } else { q })
+            .collect();
+        let r = b.merge(qs)?;
+        let (state, _) = run::<f64, SparseQuantumState<f64>>(&r)?;
+        assert!(!state.is_dense());
+        assert_eq!(state.num_stored(), 1);
+        let s = state.get_state(true);
+        assert_eq!(s[0b01_0101_0101], Complex::one());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dense_fallback() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let r = b.register(4)?;
+        let r = b.hadamard(r);
+        let (ra, rb) = b.split(r, &[0])?;
+        let (ra, rb) = b.cnot(ra, rb.unwrap());
+        let r = b.merge(vec![ra, rb])?;
+        let (state, _) = run::<f64, SparseQuantumState<f64>>(&r)?;
+        assert!(state.is_dense());
+        let (local, _) = run::<f64, LocalQuantumState<f64>>(&r)?;
         state
+            .get_state(true)
+            .into_iter()
+            .zip(local.get_state(true))
+            .for_each(|(a, b)| assert!((a - b).norm() < 1e-10));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotated_measure_matches_local() {
+        let angle = std::f64::consts::FRAC_PI_8;
+        let mut sparse = SparseQuantumState::<f64>::new(2);
+        let mut local = LocalQuantumState::<f64>::new(2);
+        let probs = sparse.stochastic_measure(&[0, 1], angle);
+        assert!(sparse.is_dense());
+        local
+            .stochastic_measure(&[0, 1], angle)
+            .into_iter()
+            .zip(probs)
+            .for_each(|(a, b)| assert!((a - b).abs() < 1e-10));
+        assert!((sparse.state_magnitude() - local.state_magnitude()).abs() < 1e-10);
     }
 }
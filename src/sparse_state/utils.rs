@@ -1,44 +1,36 @@
 use crate::measurement_ops::MeasuredCondition;
+use crate::parallel::prelude::*;
+use crate::rng;
 use crate::state_ops::{full_to_sub, sub_to_full};
 use crate::utils::{extract_bits, flip_bits};
 use crate::{Complex, Precision};
-use rayon::prelude::*;
-use std::ops::Add;
-
-pub(crate) fn consolidate_vec<
-    K: PartialEq + Ord + Clone + Send + Sync,
-    V: Add<Output = V> + Send + Sync,
->(
-    mut v: Vec<(K, V)>,
-    multithread: bool,
-) -> Vec<(K, V)> {
-    if multithread {
-        v.par_sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
-    } else {
-        v.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
-    }
-    v.into_iter().fold(vec![], |mut acc, (indx, val)| {
-        let last_indx = acc.last().map(|(indx, _)| indx);
-        match last_indx {
-            Some(last_indx) if indx.eq(last_indx) => {
-                let (last_indx, last_val) = acc.pop().unwrap();
-                acc.push((last_indx, last_val + val));
-            }
-            _ => acc.push((indx, val)),
-        }
-        acc
-    })
+use num::Zero;
+use std::collections::HashMap;
+
+/// Sum the amplitudes for each index, dropping those which are zero up to rounding errors.
+pub(crate) fn consolidate<P: Precision>(v: Vec<(u64, Complex<P>)>) -> HashMap<u64, Complex<P>> {
+    let mut state = v.into_iter().fold(
+        HashMap::new(),
+        |mut acc: HashMap<u64, Complex<P>>, (indx, val)| {
+            let entry = acc.entry(indx).or_insert_with(Complex::zero);
+            *entry = *entry + val;
+            acc
+        },
+    );
+    let tolerance = P::epsilon() * P::epsilon();
+    state.retain(|_, v| v.norm_sqr() > tolerance);
+    state
 }
 
 pub(crate) fn sparse_prob_magnitude<P: Precision>(
-    state: &[(u64, Complex<P>)],
+    state: &HashMap<u64, Complex<P>>,
     multithread: bool,
 ) -> P {
     if multithread {
         let p: P = state.par_iter().map(|(_, v)| v.norm_sqr()).sum();
         p.sqrt()
     } else {
-        let p: P = state.iter().map(|(_, v)| v.norm_sqr()).sum();
+        let p: P = state.values().map(|v| v.norm_sqr()).sum();
         p.sqrt()
     }
 }
@@ -47,45 +39,45 @@ fn sparse_measure_state<P: Precision>(
     n: u64,
     indices: &[u64],
     measured: (u64, P),
-    state: Vec<(u64, Complex<P>)>,
+    state: &HashMap<u64, Complex<P>>,
     multithread: bool,
-) -> Vec<(u64, Complex<P>)> {
+) -> HashMap<u64, Complex<P>> {
     let (m, measured_prob) = measured;
     let p_mult = P::one() / measured_prob.sqrt();
     let rev_m = flip_bits(indices.len(), m);
     let mask = sub_to_full(n, indices, std::u64::MAX, 0);
-    let f = |(indx, v): (u64, Complex<P>)| -> Option<(u64, Complex<P>)> {
+    let f = |(indx, v): (&u64, &Complex<P>)| -> Option<(u64, Complex<P>)> {
         if full_to_sub(n, indices, indx & mask) == rev_m {
-            Some((indx, v * p_mult))
+            Some((*indx, v * p_mult))
         } else {
             None
         }
     };
     if multithread {
-        state.into_par_iter().filter_map(f).collect()
+        state.par_iter().filter_map(f).collect()
     } else {
-        state.into_iter().filter_map(f).collect()
+        state.iter().filter_map(f).collect()
     }
 }
 
-pub(crate) type MeasurementAndNewState<P> = ((u64, P), Vec<(u64, Complex<P>)>);
+pub(crate) type MeasurementAndNewState<P> = ((u64, P), HashMap<u64, Complex<P>>);
 pub(crate) fn sparse_measure<P: Precision>(
     n: u64,
     indices: &[u64],
-    state: Vec<(u64, Complex<P>)>,
+    state: &HashMap<u64, Complex<P>>,
     measured: Option<MeasuredCondition<P>>,
     multithread: bool,
 ) -> MeasurementAndNewState<P> {
     let m = if let Some(measured) = &measured {
         measured.measured
     } else {
-        sparse_soft_measure(n, indices, &state, multithread)
+        sparse_soft_measure(n, indices, state, multithread)
     };
 
     let p = if let Some(measured_prob) = measured.and_then(|m| m.prob) {
         measured_prob
     } else {
-        sparse_measure_prob(n, m, indices, &state, multithread)
+        sparse_measure_prob(n, m, indices, state, multithread)
     };
     let measured = (m, p);
 
@@ -96,10 +88,10 @@ pub(crate) fn sparse_measure<P: Precision>(
 pub(crate) fn sparse_soft_measure<P: Precision>(
     n: u64,
     indices: &[u64],
-    state: &[(u64, Complex<P>)],
+    state: &HashMap<u64, Complex<P>>,
     multithread: bool,
 ) -> u64 {
-    let mut r = P::from(rand::random::<f64>()).unwrap() * sparse_prob_magnitude(state, multithread);
+    let mut r = P::from(rng::random::<f64>()).unwrap() * sparse_prob_magnitude(state, multithread);
     let mut measured_indx = 0;
     for (i, c) in state.iter() {
         r = r - c.norm_sqr();
@@ -116,12 +108,12 @@ pub(crate) fn sparse_measure_prob<P: Precision>(
     n: u64,
     m: u64,
     indices: &[u64],
-    state: &[(u64, Complex<P>)],
+    state: &HashMap<u64, Complex<P>>,
     multithread: bool,
 ) -> P {
     let mask = sub_to_full(n, indices, std::u64::MAX, 0);
     let rev_m = flip_bits(indices.len(), m);
-    let f = |(indx, v): &(u64, Complex<P>)| -> Option<P> {
+    let f = |(indx, v): (&u64, &Complex<P>)| -> Option<P> {
         if full_to_sub(n, indices, indx & mask) == rev_m {
             Some(v.norm_sqr())
         } else {
@@ -138,7 +130,7 @@ pub(crate) fn sparse_measure_prob<P: Precision>(
 pub(crate) fn sparse_measure_probs<P: Precision>(
     n: u64,
     indices: &[u64],
-    state: &[(u64, Complex<P>)],
+    state: &HashMap<u64, Complex<P>>,
     multithread: bool,
 ) -> Vec<P> {
     let r = 0u64..1 << indices.len();
@@ -162,20 +154,20 @@ mod sparse_tests {
         }
     }
 
-    fn approx_eq(a: &[(u64, Complex<f64>)], b: &[(u64, Complex<f64>)], prec: i32) {
+    fn approx_eq(a: &HashMap<u64, Complex<f64>>, b: &HashMap<u64, Complex<f64>>, prec: i32) {
         let prec = 10.0f64.powi(-prec);
-        let a: Vec<_> = a
-            .iter()
-            .map(|(indx, v)| (indx, round(v * prec) / prec))
-            .collect();
-        let b: Vec<_> = b
-            .iter()
-            .map(|(indx, v)| (indx, round(v * prec) / prec))
-            .collect();
-        assert_eq!(a, b)
+        let sorted = |s: &HashMap<u64, Complex<f64>>| {
+            let mut s: Vec<_> = s
+                .iter()
+                .map(|(indx, v)| (*indx, round(v * prec) / prec))
+                .collect();
+            s.sort_by_key(|(indx, _)| *indx);
+            s
+        };
+        assert_eq!(sorted(a), sorted(b))
     }
 
-    fn make_state<P: Precision>(indices: &[u64], reals: &[P]) -> Vec<(u64, Complex<P>)> {
+    fn make_state<P: Precision>(indices: &[u64], reals: &[P]) -> HashMap<u64, Complex<P>> {
         let cs = from_reals(reals);
         indices.iter().cloned().zip(cs.into_iter()).collect()
     }
@@ -188,7 +180,7 @@ mod sparse_tests {
         let p = sparse_measure_prob(n, m, &[0], &state, false);
         assert_eq!(p, 0.5);
 
-        let output = sparse_measure_state(n, &[0], (m, p), state, false);
+        let output = sparse_measure_state(n, &[0], (m, p), &state, false);
 
         let half: f64 = 1.0 / 2.0;
         approx_eq(
@@ -206,7 +198,7 @@ mod sparse_tests {
         let p = sparse_measure_prob(n, m, &[0], &state, false);
         assert_eq!(p, 0.5);
 
-        let output = sparse_measure_state(n, &[0], (m, p), state, false);
+        let output = sparse_measure_state(n, &[0], (m, p), &state, false);
 
         let half: f64 = 1.0 / 2.0;
         approx_eq(
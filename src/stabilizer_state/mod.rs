@@ -0,0 +1,25 @@
+/// State struct
+pub mod state;
+mod utils;
+
+use crate::pipeline::{run, run_with_init, MeasuredResults, RegisterInitialState};
+use crate::{CircuitError, Precision, Register};
+pub use state::StabilizerState;
+
+/// `run` the pipeline using `StabilizerState`. Returns an error if the circuit contains any
+/// operation which is not a clifford.
+pub fn run_stabilizer<P: Precision>(
+    r: &Register,
+) -> Result<(StabilizerState, MeasuredResults<P>), CircuitError> {
+    run::<P, StabilizerState>(r)
+}
+
+/// `run_with_init` the pipeline using `StabilizerState`. Returns an error if the circuit contains
+/// any operation which is not a clifford, or if an initial state is not a computational basis
+/// state.
+pub fn run_stabilizer_with_init<P: Precision>(
+    r: &Register,
+    states: &[RegisterInitialState<P>],
+) -> Result<(StabilizerState, MeasuredResults<P>), CircuitError> {
+    run_with_init::<P, StabilizerState>(r, states)
+}
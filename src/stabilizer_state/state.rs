@@ -0,0 +1,539 @@
+use crate::errors::CircuitError;
+use crate::measurement_ops::MeasuredCondition;
+use crate::pipeline::InitialState;
+use crate::rng;
+use crate::stabilizer_state::utils::{clifford_images, pauli_product_phase, LocalPauli};
+use crate::state_ops::{from_reals, make_matrix_op, UnitaryOp};
+use crate::utils::flip_bits;
+use crate::{Complex, Precision, QuantumState};
+use num::{One, Zero};
+
+/// A quantum state restricted to stabilizer states, stored as an Aaronson-Gottesman tableau.
+/// This can simulate clifford circuits on thousands of qubits, since each op costs `O(n)` and
+/// each measurement `O(n^2)`.
+///
+/// Ops which are not cliffords cannot be represented: the first one encountered is recorded as an
+/// error and all further ops are ignored. Runs stop at this error and return it instead of the
+/// (invalid) state.
+#[derive(Debug)]
+pub struct StabilizerState {
+    n: u64,
+    // Rows 0..n are destabilizers, n..2n are stabilizers, 2n is scratch space.
+    xs: Vec<Vec<bool>>,
+    zs: Vec<Vec<bool>>,
+    rs: Vec<bool>,
+    error: Option<CircuitError>,
+}
+
+impl StabilizerState {
+    /// Get the stabilizer generators as `(negative, x bits, z bits)`, where qubit `j` is `X` if
+    /// only `x[j]` is set, `Z` if only `z[j]` is set, and `Y` if both are.
+    pub fn get_stabilizers(&self) -> Vec<(bool, Vec<bool>, Vec<bool>)> {
+        let n = self.n as usize;
+        (n..2 * n)
+            .map(|i| (self.rs[i], self.xs[i].clone(), self.zs[i].clone()))
+            .collect()
+    }
+
+    /// Rotate to a new computational basis:
+    /// `|0'> =  cos(angle)|0> + sin(angle)|1>`
+    /// `|1'> = -sin(angle)|0> + cos(angle)|1>`
+    pub fn rotate_basis(&mut self, indices: &[u64], angle: f64) {
+        if angle != 0.0 {
+            let (sangle, cangle) = angle.sin_cos();
+            let basis_mat = from_reals(&[cangle, -sangle, sangle, cangle]);
+            indices.iter().for_each(|indx| {
+                let op = make_matrix_op(vec![*indx], basis_mat.clone()).unwrap();
+                QuantumState::<f64>::apply_op(self, &op);
+            });
+        }
+    }
+
+    fn set_error(&mut self, message: String) {
+        if self.error.is_none() {
+            self.error = Some(CircuitError::new(message));
+        }
+    }
+
+    /// Left multiply row `h` by row `i`.
+    fn rowsum(&mut self, h: usize, i: usize) {
+        let n = self.n as usize;
+        let phase: i64 = (0..n).fold(0, |acc, j| {
+            acc + i64::from(pauli_product_phase(
+                self.xs[i][j],
+                self.zs[i][j],
+                self.xs[h][j],
+                self.zs[h][j],
+            ))
+        });
+        let total = 2 * i64::from(self.rs[h] as u8) + 2 * i64::from(self.rs[i] as u8) + phase;
+        self.rs[h] = total.rem_euclid(4) == 2;
+        (0..n).for_each(|j| {
+            self.xs[h][j] ^= self.xs[i][j];
+            self.zs[h][j] ^= self.zs[i][j];
+        });
+    }
+
+    fn apply_clifford(&mut self, op: &UnitaryOp) -> Result<(), CircuitError> {
+        let images = clifford_images(op)?;
+        let indices: Vec<usize> = images.indices.iter().map(|i| *i as usize).collect();
+        (0..2 * self.n as usize).for_each(|row| {
+            let identity = LocalPauli {
+                phase: 0,
+                x: 0,
+                z: 0,
+            };
+            let image = indices.iter().zip(images.images.iter()).fold(
+                identity,
+                |acc, (indx, (x_image, z_image))| {
+                    let (x, z) = (self.xs[row][*indx], self.zs[row][*indx]);
+                    let acc = if x { acc.mul(*x_image) } else { acc };
+                    let acc = if z { acc.mul(*z_image) } else { acc };
+                    // P(1, 1) = i X Z
+                    if x && z {
+                        LocalPauli {
+                            phase: (acc.phase + 1) % 4,
+                            ..acc
+                        }
+                    } else {
+                        acc
+                    }
+                },
+            );
+            // Images of hermitian paulis are hermitian, so the phase is +/-1.
+            let total = 2 * u16::from(self.rs[row] as u8) + u16::from(image.phase);
+            self.rs[row] = total % 4 == 2;
+            indices.iter().enumerate().for_each(|(j, indx)| {
+                self.xs[row][*indx] = (image.x >> j) & 1 == 1;
+                self.zs[row][*indx] = (image.z >> j) & 1 == 1;
+            });
+        });
+        Ok(())
+    }
+
+    /// Measure qubit `a` in the Z basis, forcing the outcome `forced` if it is random.
+    /// Returns the outcome and whether it was random.
+    fn measure_qubit(&mut self, a: usize, forced: Option<bool>) -> (bool, bool) {
+        let n = self.n as usize;
+        match (n..2 * n).find(|p| self.xs[*p][a]) {
+            Some(p) => {
+                let rows: Vec<usize> = (0..2 * n).filter(|i| *i != p && self.xs[*i][a]).collect();
+                rows.into_iter().for_each(|i| self.rowsum(i, p));
+                self.xs[p - n] = self.xs[p].clone();
+                self.zs[p - n] = self.zs[p].clone();
+                self.rs[p - n] = self.rs[p];
+                let outcome = forced.unwrap_or_else(rng::random);
+                self.xs[p] = vec![false; n];
+                self.zs[p] = vec![false; n];
+                self.zs[p][a] = true;
+                self.rs[p] = outcome;
+                (outcome, true)
+            }
+            None => {
+                let scratch = 2 * n;
+                self.xs[scratch] = vec![false; n];
+                self.zs[scratch] = vec![false; n];
+                self.rs[scratch] = false;
+                let rows: Vec<usize> = (0..n).filter(|i| self.xs[*i][a]).collect();
+                rows.into_iter().for_each(|i| self.rowsum(scratch, i + n));
+                (self.rs[scratch], false)
+            }
+        }
+    }
+
+    /// Measure each of `indices` in turn, returns the combined outcome and its probability.
+    fn measure_indices(&mut self, indices: &[u64], forced: Option<u64>) -> (u64, f64) {
+        indices
+            .iter()
+            .enumerate()
+            .fold((0, 1.0), |(m, p), (j, indx)| {
+                let forced_bit = forced.map(|f| (f >> j) & 1 == 1);
+                let (outcome, random) = self.measure_qubit(*indx as usize, forced_bit);
+                let p = match (random, forced_bit) {
+                    (true, _) => p * 0.5,
+                    (false, Some(bit)) if bit != outcome => 0.0,
+                    (false, _) => p,
+                };
+                let outcome = forced_bit.unwrap_or(outcome);
+                (m | ((outcome as u64) << j), p)
+            })
+    }
+
+    /// Probability of each outcome on `indices`.
+    fn outcome_probs(&self, indices: &[u64]) -> Vec<f64> {
+        match indices.split_first() {
+            None => vec![1.0],
+            Some((indx, rest)) => {
+                let branch = |outcome: bool| {
+                    let mut s = self.clone();
+                    let (m, random) = s.measure_qubit(*indx as usize, Some(outcome));
+                    let p = if random {
+                        0.5
+                    } else if m == outcome {
+                        1.0
+                    } else {
+                        0.0
+                    };
+                    let probs = s.outcome_probs(rest);
+                    probs.into_iter().map(move |q| p * q)
+                };
+                // Bit 0 of the outcome corresponds to the first index.
+                let zeros: Vec<f64> = branch(false).collect();
+                let ones: Vec<f64> = branch(true).collect();
+                zeros
+                    .into_iter()
+                    .zip(ones)
+                    .flat_map(|(a, b)| vec![a, b])
+                    .collect()
+            }
+        }
+    }
+}
+
+impl Clone for StabilizerState {
+    fn clone(&self) -> Self {
+        StabilizerState {
+            n: self.n,
+            xs: self.xs.clone(),
+            zs: self.zs.clone(),
+            rs: self.rs.clone(),
+            error: self
+                .error
+                .as_ref()
+                .map(|err| CircuitError::new(err.to_string())),
+        }
+    }
+}
+
+impl<P: Precision> QuantumState<P> for StabilizerState {
+    fn new(n: u64) -> Self {
+        let size = n as usize;
+        let xs = (0..2 * size + 1)
+            .map(|i| (0..size).map(|j| i < size && i == j).collect())
+            .collect();
+        let zs = (0..2 * size + 1)
+            .map(|i| {
+                (0..size)
+                    .map(|j| i >= size && i < 2 * size && i - size == j)
+                    .collect()
+            })
+            .collect();
+        StabilizerState {
+            n,
+            xs,
+            zs,
+            rs: vec![false; 2 * size + 1],
+            error: None,
+        }
+    }
+
+    fn new_from_initial_states(n: u64, states: &[(Vec<u64>, InitialState<P>)]) -> Self {
+        let max_init_n = states
+            .iter()
+            .flat_map(|(indices, _)| indices.iter().cloned())
+            .max()
+            .map(|m| m + 1)
+            .unwrap_or(0);
+        let n = n.max(max_init_n);
+        let mut s: StabilizerState = QuantumState::<P>::new(n);
+        let x = from_reals(&[0.0, 1.0, 1.0, 0.0]);
+        states.iter().for_each(|(indices, state)| {
+            let index = match state {
+                InitialState::Index(index) => Some(*index),
+                InitialState::FullState(vals) => {
+                    let nonzero: Vec<_> = vals
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, v)| **v != Complex::zero())
+                        .collect();
+                    match nonzero.as_slice() {
+                        [(index, v)] if (v.norm_sqr() - P::one()).abs() < P::epsilon() => {
+                            Some(*index as u64)
+                        }
+                        _ => None,
+                    }
+                }
+            };
+            match index {
+                Some(index) => indices.iter().enumerate().for_each(|(j, indx)| {
+                    if (index >> j) & 1 == 1 {
+                        let op = make_matrix_op(vec![*indx], x.clone()).unwrap();
+                        QuantumState::<P>::apply_op(&mut s, &op);
+                    }
+                }),
+                None => s.set_error(format!(
+                    "Initial state for {:?} is not a computational basis state",
+                    indices
+                )),
+            }
+        });
+        s
+    }
+
+    fn n(&self) -> u64 {
+        self.n
+    }
+
+    fn take_error(&mut self) -> Option<CircuitError> {
+        self.error.take()
+    }
+
+    fn apply_op_with_name(&mut self, name: Option<&str>, op: &UnitaryOp) {
+        if self.error.is_none() {
+            if let Err(err) = self.apply_clifford(op) {
+                self.set_error(format!("{} ({})", err, name.unwrap_or("unnamed op")));
+            }
+        }
+    }
+
+    /// Only mixtures of cliffords are supported, each kraus operator must be proportional to a
+    /// clifford unitary. One is sampled according to their weights.
+    fn apply_channel(
+        &mut self,
+        name: Option<&str>,
+        indices: &[u64],
+        kraus_ops: &[Vec<Complex<f64>>],
+    ) -> Result<(), CircuitError> {
+        let size = 1usize << indices.len();
+        // K^dagger K = w I for each K
+        let weights = kraus_ops
+            .iter()
+            .map(|k| {
+                let w: f64 = (0..size).map(|i| k[i * size].norm_sqr()).sum();
+                let proportional = (0..size).all(|row| {
+                    (0..size).all(|col| {
+                        let v: Complex<f64> = (0..size)
+                            .map(|i| k[i * size + row].conj() * k[i * size + col])
+                            .sum();
+                        let expected = if row == col { w } else { 0.0 };
+                        (v - expected).norm() < 1e-10
+                    })
+                });
+                if proportional {
+                    Ok(w)
+                } else {
+                    let message = format!(
+                        "Kraus operators must be proportional to unitaries for stabilizer states ({})",
+                        name.unwrap_or("unnamed channel")
+                    );
+                    CircuitError::make_err(message)
+                }
+            })
+            .collect::<Result<Vec<_>, CircuitError>>()?;
+        let mut r = rng::random::<f64>();
+        let chosen = weights
+            .iter()
+            .position(|w| {
+                r -= w;
+                r <= 0.0
+            })
+            .unwrap_or(weights.len() - 1);
+        let scale = 1.0 / weights[chosen].sqrt();
+        let mat = kraus_ops[chosen].iter().map(|c| c * scale).collect();
+        let op = make_matrix_op(indices.to_vec(), mat)?;
+        self.apply_clifford(&op)
+    }
+
+    fn measure(
+        &mut self,
+        indices: &[u64],
+        measured: Option<MeasuredCondition<P>>,
+        angle: f64,
+    ) -> (u64, P) {
+        self.rotate_basis(indices, angle);
+        let (m, p) = self.measure_indices(indices, measured.as_ref().map(|m| m.measured));
+        self.rotate_basis(indices, -angle);
+        let p = measured
+            .and_then(|m| m.prob)
+            .unwrap_or_else(|| P::from(p).unwrap());
+        (m, p)
+    }
+
+    fn soft_measure(&mut self, indices: &[u64], measured: Option<u64>, angle: f64) -> (u64, P) {
+        let mut s = self.clone();
+        s.rotate_basis(indices, angle);
+        let (m, p) = s.measure_indices(indices, measured);
+        (m, P::from(p).unwrap())
+    }
+
+    fn state_magnitude(&self) -> P {
+        P::one()
+    }
+
+    fn stochastic_measure(&mut self, indices: &[u64], angle: f64) -> Vec<P> {
+        let mut s = self.clone();
+        s.rotate_basis(indices, angle);
+        s.outcome_probs(indices)
+            .into_iter()
+            .map(|p| P::from(p).unwrap())
+            .collect()
+    }
+
+    /// Build the dense state vector (up to a global phase), only feasible for small `n`.
+    fn get_state(self, natural_order: bool) -> Vec<Complex<P>> {
+        let n = self.n;
+        // Find a computational basis state with support in the stabilizer state.
+        let mut s = self.clone();
+        let b = (0..n).fold(0u64, |acc, q| {
+            let (outcome, _) = s.measure_qubit(q as usize, Some(false));
+            acc | ((outcome as u64) << (n - 1 - q))
+        });
+        let mut state = vec![Complex::<f64>::zero(); 1 << n];
+        state[b as usize] = Complex::one();
+        // Project with prod_i (1 + S_i)
+        let state = self
+            .get_stabilizers()
+            .into_iter()
+            .fold(state, |state, (negative, xs, zs)| {
+                let x_mask = (0..n).fold(0u64, |acc, q| {
+                    acc | ((xs[q as usize] as u64) << (n - 1 - q))
+                });
+                let z_mask = (0..n).fold(0u64, |acc, q| {
+                    acc | ((zs[q as usize] as u64) << (n - 1 - q))
+                });
+                let ys = (x_mask & z_mask).count_ones();
+                let base = Complex::i().powu(ys) * if negative { -1.0 } else { 1.0 };
+                let mut out = state.clone();
+                state.iter().enumerate().for_each(|(i, v)| {
+                    let i = i as u64;
+                    let sign = if (i & z_mask).count_ones() & 1 == 0 {
+                        1.0
+                    } else {
+                        -1.0
+                    };
+                    out[(i ^ x_mask) as usize] += base * sign * v;
+                });
+                out
+            });
+        let norm = state.iter().map(|v| v.norm_sqr()).sum::<f64>().sqrt();
+        let convert = |v: Complex<f64>| Complex {
+            re: P::from(v.re / norm).unwrap(),
+            im: P::from(v.im / norm).unwrap(),
+        };
+        if natural_order {
+            (0..state.len())
+                .map(|i| convert(state[flip_bits(n as usize, i as u64) as usize]))
+                .collect()
+        } else {
+            state.into_iter().map(convert).collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod stabilizer_state_tests {
+    use super::*;
+    use crate::pipeline::{run_local, run_with_state};
+    use crate::stabilizer_state::{run_stabilizer, run_stabilizer_with_init};
+    use crate::{OpBuilder, UnitaryBuilder};
+
+    fn assert_states_match(a: &[Complex<f64>], b: &[Complex<f64>]) {
+        // Compare up to a global phase.
+        let (i, v) = b
+            .iter()
+            .enumerate()
+            .find(|(_, v)| v.norm_sqr() > 1e-10)
+            .unwrap();
+        let phase = a[i] / v;
+        a.iter().zip(b.iter()).for_each(|(a, b)| {
+            assert!((a - b * phase).norm() < 1e-10, "{:?} != {:?}", a, b);
+        });
+    }
+
+    #[test]
+    fn test_ghz_state() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let r = b.register(3)?;
+        let mut rs = b.split_all(r);
+        let q2 = rs.pop().unwrap();
+        let q1 = rs.pop().unwrap();
+        let q0 = rs.pop().unwrap();
+        let q0 = b.hadamard(q0);
+        let (q0, q1) = b.cnot(q0, q1);
+        let (q1, q2) = b.cnot(q1, q2);
+        let q2 = b.s(q2);
+        let q1 = b.y(q1);
+        let r = b.merge(vec![q0, q1, q2])?;
+
+        let (stab, _) = run_stabilizer::<f64>(&r)?;
+        let (local, _) = run_local::<f64>(&r)?;
+        assert_states_match(
+            &QuantumState::<f64>::get_state(stab, true),
+            &local.get_state(true),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_measurements() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let r = b.register(2)?;
+        let h = r.handle();
+        let (q0, q1) = b.split(r, &[0])?;
+        let q1 = q1.unwrap();
+        let q0 = b.hadamard(q0);
+        let (q0, q1) = b.cnot(q0, q1);
+        let r = b.merge(vec![q0, q1])?;
+        let (r, m) = b.measure(r);
+
+        let (_, measured) = run_stabilizer_with_init::<f64>(&r, &[h.make_init_from_index(0)?])?;
+        let (m, p) = measured.get_measurement(&m).unwrap();
+        assert!(m == 0 || m == 3);
+        assert_eq!(p, 0.5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stochastic_measure() -> Result<(), CircuitError> {
+        let mut s: StabilizerState = QuantumState::<f64>::new(2);
+        let inv_sqrt = 1.0 / 2.0f64.sqrt();
+        let h = make_matrix_op(
+            vec![1],
+            from_reals(&[inv_sqrt, inv_sqrt, inv_sqrt, -inv_sqrt]),
+        )?;
+        QuantumState::<f64>::apply_op(&mut s, &h);
+        let probs: Vec<f64> = s.stochastic_measure(&[0, 1], 0.0);
+        assert_eq!(probs, vec![0.5, 0.0, 0.5, 0.0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_non_clifford_error() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let q = b.qubit();
+        let q = b.hadamard(q);
+        let q = b.t(q);
+        assert!(run_stabilizer::<f64>(&q).is_err());
+        let state = QuantumState::<f64>::new(1);
+        assert!(run_with_state::<f64, StabilizerState>(&q, state).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_many_qubits() -> Result<(), CircuitError> {
+        let n = 500;
+        let mut b = OpBuilder::new();
+        let r = b.register(n)?;
+        let mut rs = b.split_all(r);
+        let last = rs.pop().unwrap();
+        let q = b.hadamard(rs.remove(0));
+        let (q, rs) = rs.into_iter().fold((q, vec![]), |(q, mut acc), r| {
+            let (q, r) = b.cnot(q, r);
+            acc.push(r);
+            (q, acc)
+        });
+        let (q, last) = b.cnot(q, last);
+        let (q, mq) = b.measure(q);
+        let (last, mlast) = b.measure(last);
+        let rest = b.merge(rs)?;
+        let r = b.merge(vec![q, rest, last])?;
+
+        let (_, measured) = run_stabilizer::<f64>(&r)?;
+        let (mq, pq) = measured.get_measurement(&mq).unwrap();
+        let (mlast, plast) = measured.get_measurement(&mlast).unwrap();
+        assert_eq!(mq, mlast);
+        // Whichever is measured first is random, the other is then determined.
+        assert_eq!(pq * plast, 0.5);
+        Ok(())
+    }
+}
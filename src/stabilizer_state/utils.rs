@@ -0,0 +1,264 @@
+use crate::errors::CircuitError;
+use crate::macros::inverter::remap_indices;
+use crate::state_ops::{get_index, make_op_matrix, num_indices, UnitaryOp};
+use crate::Complex;
+use num::{One, Zero};
+
+/// Largest number of qubits a single op may act on for the stabilizer backend.
+pub(crate) const MAX_CLIFFORD_QUBITS: usize = 6;
+
+const TOLERANCE: f64 = 1e-8;
+
+/// A pauli string on a handful of qubits given by `i^phase * prod_j P(x_j, z_j)` where bit `j` of
+/// `x` and `z` refers to qubit `j` and `P(1, 0) = X`, `P(0, 1) = Z`, `P(1, 1) = Y`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct LocalPauli {
+    pub(crate) phase: u8,
+    pub(crate) x: u64,
+    pub(crate) z: u64,
+}
+
+/// The exponent of `i` produced by multiplying the single qubit paulis `P(x1, z1) P(x2, z2)`.
+pub(crate) fn pauli_product_phase(x1: bool, z1: bool, x2: bool, z2: bool) -> i8 {
+    let (x2, z2) = (x2 as i8, z2 as i8);
+    match (x1, z1) {
+        (false, false) => 0,
+        (true, true) => z2 - x2,
+        (true, false) => z2 * (2 * x2 - 1),
+        (false, true) => x2 * (1 - 2 * z2),
+    }
+}
+
+impl LocalPauli {
+    /// Multiply `self * other`.
+    pub(crate) fn mul(self, other: LocalPauli) -> LocalPauli {
+        let bits = 64 - (self.x | self.z | other.x | other.z).leading_zeros();
+        let phase: i8 = (0..bits).fold(0, |acc, j| {
+            let b = |v: u64| (v >> j) & 1 == 1;
+            acc + pauli_product_phase(b(self.x), b(self.z), b(other.x), b(other.z))
+        });
+        let phase =
+            (i16::from(self.phase) + i16::from(other.phase) + i16::from(phase)).rem_euclid(4) as u8;
+        LocalPauli {
+            phase,
+            x: self.x ^ other.x,
+            z: self.z ^ other.z,
+        }
+    }
+}
+
+/// The images `(U X_j U^dagger, U Z_j U^dagger)` for each qubit `j` of an op, along with the
+/// global indices the op acts on.
+#[derive(Debug)]
+pub(crate) struct CliffordImages {
+    pub(crate) indices: Vec<u64>,
+    pub(crate) images: Vec<(LocalPauli, LocalPauli)>,
+}
+
+/// Find how `op` conjugates single qubit paulis, returns an error if `op` is not a clifford.
+pub(crate) fn clifford_images(op: &UnitaryOp) -> Result<CliffordImages, CircuitError> {
+    let indices: Vec<u64> = (0..num_indices(op)).map(|i| get_index(op, i)).collect();
+    let k = indices.len();
+    if k > MAX_CLIFFORD_QUBITS {
+        let message = format!(
+            "Stabilizer ops may act on at most {:?} qubits, found {:?}",
+            MAX_CLIFFORD_QUBITS, k
+        );
+        return CircuitError::make_err(message);
+    }
+    let max_index = indices.iter().cloned().max().unwrap_or(0) as usize;
+    let mut new_indices = vec![0; max_index + 1];
+    indices
+        .iter()
+        .enumerate()
+        .for_each(|(j, indx)| new_indices[*indx as usize] = j as u64);
+    let local_op = remap_indices(op.clone(), &new_indices);
+    // cols[c][r] = <r|U|c>, with qubit j as bit k - 1 - j.
+    let cols = make_op_matrix::<f64>(k as u64, &local_op, false);
+
+    let images = (0..k)
+        .map(|j| {
+            let bit = 1u64 << (k - 1 - j);
+            let x_image = conjugate_pauli(&cols, |s| (s ^ bit, Complex::one()))?;
+            let z_image = conjugate_pauli(&cols, |s| {
+                let sign = if s & bit == 0 { 1.0 } else { -1.0 };
+                (s, Complex { re: sign, im: 0.0 })
+            })?;
+            Ok((x_image, z_image))
+        })
+        .collect::<Result<Vec<_>, CircuitError>>()?;
+    Ok(CliffordImages { indices, images })
+}
+
+/// Compute `U P U^dagger` and decompose it as a pauli string. `pauli` maps `|s>` to `c|t>`.
+fn conjugate_pauli<F: Fn(u64) -> (u64, Complex<f64>)>(
+    cols: &[Vec<Complex<f64>>],
+    pauli: F,
+) -> Result<LocalPauli, CircuitError> {
+    let size = cols.len();
+    let k = (size as u64).trailing_zeros() as u64;
+    // m[s][r] = <r|U P U^dagger|s>
+    let m: Vec<Vec<Complex<f64>>> = (0..size)
+        .map(|s| {
+            let mut w = vec![Complex::zero(); size];
+            (0..size).for_each(|u| {
+                let v = cols[u][s].conj();
+                if v.norm_sqr() > TOLERANCE {
+                    let (t, c) = pauli(u as u64);
+                    w[t as usize] += c * v;
+                }
+            });
+            (0..size)
+                .map(|r| {
+                    w.iter()
+                        .enumerate()
+                        .map(|(t, wt)| cols[t][r] * wt)
+                        .sum::<Complex<f64>>()
+                })
+                .collect()
+        })
+        .collect();
+
+    let not_clifford = || CircuitError::make_str_err("Op is not a clifford");
+
+    // X^x Z^z |s> = (-1)^{z.s} |s ^ x>
+    let x = match m[0].iter().position(|v| v.norm_sqr() > TOLERANCE) {
+        Some(x) => x as u64,
+        None => return not_clifford(),
+    };
+    let c = m[0][x as usize];
+    let z = (0..k).fold(0u64, |acc, b| {
+        let s = 1u64 << b;
+        if (m[s as usize][(s ^ x) as usize] + c).norm_sqr() < TOLERANCE {
+            acc | s
+        } else {
+            acc
+        }
+    });
+    let consistent = (0..size as u64).all(|s| {
+        (0..size as u64).all(|r| {
+            let expected = if r == s ^ x {
+                if (z & s).count_ones() & 1 == 0 {
+                    c
+                } else {
+                    -c
+                }
+            } else {
+                Complex::zero()
+            };
+            (m[s as usize][r as usize] - expected).norm_sqr() < TOLERANCE
+        })
+    });
+    if !consistent {
+        return not_clifford();
+    }
+    let c_phase = if (c - Complex::one()).norm_sqr() < TOLERANCE {
+        0
+    } else if (c - Complex::i()).norm_sqr() < TOLERANCE {
+        1
+    } else if (c + Complex::one()).norm_sqr() < TOLERANCE {
+        2
+    } else if (c + Complex::i()).norm_sqr() < TOLERANCE {
+        3
+    } else {
+        return not_clifford();
+    };
+
+    // Convert from matrix index bits to qubit bits, X^x Z^z = i^{-|x & z|} prod_j P(x_j, z_j)
+    let to_qubits = |v: u64| (0..k).fold(0, |acc, j| acc | (((v >> (k - 1 - j)) & 1) << j));
+    let (qx, qz) = (to_qubits(x), to_qubits(z));
+    let w = (qx & qz).count_ones() as i64;
+    let phase = (c_phase - w).rem_euclid(4) as u8;
+    Ok(LocalPauli {
+        phase,
+        x: qx,
+        z: qz,
+    })
+}
+
+#[cfg(test)]
+mod stabilizer_utils_tests {
+    use super::*;
+    use crate::state_ops::{from_reals, make_control_op, make_matrix_op};
+
+    #[test]
+    fn test_hadamard_images() -> Result<(), CircuitError> {
+        let inv_sqrt = 1.0 / 2.0f64.sqrt();
+        let op = make_matrix_op(
+            vec![3],
+            from_reals(&[inv_sqrt, inv_sqrt, inv_sqrt, -inv_sqrt]),
+        )?;
+        let images = clifford_images(&op)?;
+        assert_eq!(images.indices, vec![3]);
+        let (x, z) = images.images[0];
+        assert_eq!(
+            x,
+            LocalPauli {
+                phase: 0,
+                x: 0,
+                z: 1
+            }
+        );
+        assert_eq!(
+            z,
+            LocalPauli {
+                phase: 0,
+                x: 1,
+                z: 0
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_cnot_images() -> Result<(), CircuitError> {
+        let not = make_matrix_op(vec![1], from_reals(&[0.0, 1.0, 1.0, 0.0]))?;
+        let op = make_control_op(vec![0], not)?;
+        let images = clifford_images(&op)?;
+        // X_c -> X_c X_t, Z_c -> Z_c, X_t -> X_t, Z_t -> Z_c Z_t
+        assert_eq!(
+            images.images[0].0,
+            LocalPauli {
+                phase: 0,
+                x: 3,
+                z: 0
+            }
+        );
+        assert_eq!(
+            images.images[0].1,
+            LocalPauli {
+                phase: 0,
+                x: 0,
+                z: 1
+            }
+        );
+        assert_eq!(
+            images.images[1].0,
+            LocalPauli {
+                phase: 0,
+                x: 2,
+                z: 0
+            }
+        );
+        assert_eq!(
+            images.images[1].1,
+            LocalPauli {
+                phase: 0,
+                x: 0,
+                z: 3
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_t_not_clifford() -> Result<(), CircuitError> {
+        let phase = Complex::from_polar(&1.0, &std::f64::consts::FRAC_PI_4);
+        let op = make_matrix_op(
+            vec![0],
+            vec![Complex::one(), Complex::zero(), Complex::zero(), phase],
+        )?;
+        assert!(clifford_images(&op).is_err());
+        Ok(())
+    }
+}
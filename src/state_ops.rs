@@ -1,12 +1,12 @@
 /// Contains functions, structs, and enums for storing and manipulating the quantum state.
-extern crate rayon;
-use rayon::prelude::*;
+use crate::parallel::prelude::*;
 
 use crate::errors::CircuitError;
 use crate::iterators::*;
+use crate::macros::inverter::remap_indices;
 use crate::utils::*;
 use crate::{Complex, Precision};
-use num::One;
+use num::{Float, One, Zero};
 use std::cmp::{max, min};
 use std::fmt;
 
@@ -443,6 +443,40 @@ pub fn get_index(op: &UnitaryOp, i: usize) -> u64 {
     }
 }
 
+/// Check if `op` leaves the computational basis values of the qubits at `indices` unchanged, so it
+/// may only use them as controls or apply phases to them.
+pub fn preserves_basis_values(op: &UnitaryOp, indices: &[u64]) -> bool {
+    let touches = |qubits: &[u64]| qubits.iter().any(|q| indices.contains(q));
+    // Mask of the bits of a matrix row or column which belong to `indices`.
+    let mask = |qubits: &[u64]| -> u64 {
+        let k = qubits.len() as u64;
+        qubits
+            .iter()
+            .enumerate()
+            .filter(|(_, q)| indices.contains(q))
+            .fold(0, |acc, (j, _)| acc | (1 << (k - 1 - j as u64)))
+    };
+    match op {
+        UnitaryOp::Matrix(qubits, data) => {
+            let (mask, d) = (mask(qubits), 1 << qubits.len());
+            data.iter().enumerate().all(|(i, c)| {
+                let (row, col) = (i as u64 / d, i as u64 % d);
+                (row ^ col) & mask == 0 || *c == Complex::zero()
+            })
+        }
+        UnitaryOp::SparseMatrix(qubits, rows) => {
+            let mask = mask(qubits);
+            rows.iter().enumerate().all(|(row, cols)| {
+                cols.iter()
+                    .all(|(col, c)| (row as u64 ^ col) & mask == 0 || *c == Complex::zero())
+            })
+        }
+        UnitaryOp::Swap(a, b) => !touches(a) && !touches(b),
+        UnitaryOp::Control(_, _, op) => preserves_basis_values(op, indices),
+        UnitaryOp::Function(_, outputs, _) => !touches(outputs),
+    }
+}
+
 /// Convert &UnitaryOp to equivalent PrecisionUnitaryOp<P>
 pub(crate) fn clone_as_precision_op<P: Precision>(op: &UnitaryOp) -> PrecisionUnitaryOp<P> {
     match op {
@@ -537,6 +571,264 @@ pub fn apply_op<P: Precision>(
     }
 }
 
+/// An op which can be applied to a state without a second buffer.
+enum InPlaceOp<P: Precision> {
+    /// Indices, diagonal entries
+    Diagonal(Vec<u64>, Vec<Complex<P>>),
+    /// Index, row major 2x2 matrix
+    SingleQubit(u64, [Complex<P>; 4]),
+    /// Indices, row major 4x4 matrix
+    TwoQubit(u64, u64, [Complex<P>; 16]),
+}
+
+/// Get the in place form of a (non-control) `op` if there is one.
+fn make_in_place_op<P: Precision>(op: &UnitaryOp) -> Option<InPlaceOp<P>> {
+    let to_p = |c: &Complex<f64>| Complex {
+        re: P::from(c.re).unwrap(),
+        im: P::from(c.im).unwrap(),
+    };
+    match op {
+        UnitaryOp::Matrix(indices, data) => {
+            let d = 1 << indices.len();
+            let is_diagonal = data
+                .iter()
+                .enumerate()
+                .all(|(i, c)| i / d == i % d || *c == Complex::zero());
+            if is_diagonal {
+                let diag = (0..d).map(|i| to_p(&data[i * d + i])).collect();
+                Some(InPlaceOp::Diagonal(indices.clone(), diag))
+            } else if indices.len() == 1 {
+                let mat = [
+                    to_p(&data[0]),
+                    to_p(&data[1]),
+                    to_p(&data[2]),
+                    to_p(&data[3]),
+                ];
+                Some(InPlaceOp::SingleQubit(indices[0], mat))
+            } else if indices.len() == 2 {
+                let mut mat = [Complex::zero(); 16];
+                mat.iter_mut()
+                    .zip(data.iter())
+                    .for_each(|(m, c)| *m = to_p(c));
+                Some(InPlaceOp::TwoQubit(indices[0], indices[1], mat))
+            } else {
+                None
+            }
+        }
+        UnitaryOp::SparseMatrix(indices, data) => {
+            let is_diagonal = data.iter().enumerate().all(|(row, cols)| {
+                cols.iter()
+                    .all(|(col, c)| *col == row as u64 || *c == Complex::zero())
+            });
+            if is_diagonal {
+                let diag = data
+                    .iter()
+                    .enumerate()
+                    .map(|(row, cols)| {
+                        cols.iter()
+                            .filter(|(col, _)| *col == row as u64)
+                            .map(|(_, c)| to_p(c))
+                            .sum()
+                    })
+                    .collect();
+                Some(InPlaceOp::Diagonal(indices.clone(), diag))
+            } else if indices.len() == 1 {
+                let mut mat = [Complex::zero(); 4];
+                data.iter().enumerate().for_each(|(row, cols)| {
+                    cols.iter()
+                        .for_each(|(col, c)| mat[2 * row + *col as usize] = to_p(c))
+                });
+                Some(InPlaceOp::SingleQubit(indices[0], mat))
+            } else if indices.len() == 2 {
+                let mut mat = [Complex::zero(); 16];
+                data.iter().enumerate().for_each(|(row, cols)| {
+                    cols.iter()
+                        .for_each(|(col, c)| mat[4 * row + *col as usize] = to_p(c))
+                });
+                Some(InPlaceOp::TwoQubit(indices[0], indices[1], mat))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Default number of amplitudes handed to each call of a kernel by `apply_op_in_place`, this lets
+/// ops on the highest qubits run in parallel too.
+pub const DEFAULT_CHUNK_SIZE: usize = 1 << 12;
+
+/// Apply `op` directly to `state`, without the second buffer required by `apply_op`. This is only
+/// possible for diagonal ops (such as phases) and one or two qubit ops, along with controlled
+/// versions of those. Returns `false` without modifying `state` for any other op.
+///
+/// One and two qubit ops are applied with kernels which have SSE2 versions for `f64` when the
+/// `simd` feature is enabled on x86_64.
+pub fn apply_op_in_place<P: Precision>(
+    n: u64,
+    op: &UnitaryOp,
+    state: &mut [Complex<P>],
+    multithread: bool,
+) -> bool {
+    apply_op_in_place_with_chunk_size(n, op, state, multithread, DEFAULT_CHUNK_SIZE)
+}
+
+/// Like `apply_op_in_place` but with `chunk_size` amplitudes handed to each call of a kernel.
+pub fn apply_op_in_place_with_chunk_size<P: Precision>(
+    n: u64,
+    op: &UnitaryOp,
+    state: &mut [Complex<P>],
+    multithread: bool,
+    chunk_size: usize,
+) -> bool {
+    let (control_mask, op) = match op {
+        UnitaryOp::Control(c_indices, _, op) => {
+            (sub_to_full(n, c_indices, u64::MAX, 0), op.as_ref())
+        }
+        op => (0, op),
+    };
+    match make_in_place_op::<P>(op) {
+        Some(InPlaceOp::Diagonal(indices, diag)) => {
+            let f = |(i, c): (usize, &mut Complex<P>)| {
+                let i = i as u64;
+                if i & control_mask == control_mask {
+                    *c = *c * diag[full_to_sub(n, &indices, i) as usize];
+                }
+            };
+            if multithread {
+                state.par_iter_mut().enumerate().for_each(f);
+            } else {
+                state.iter_mut().enumerate().for_each(f);
+            }
+            true
+        }
+        Some(InPlaceOp::SingleQubit(index, mat)) => {
+            let mask = 1 << (n - 1 - index);
+            let block = min(mask, chunk_size);
+            // Each chunk has the target bit as 0 in its lower half, and 1 in its upper half.
+            let f = |(chunk, values): (usize, &mut [Complex<P>])| {
+                let base = chunk * 2 * mask;
+                let (lower, upper) = values.split_at_mut(mask);
+                let g = |i: usize, zeros: &mut [Complex<P>], ones: &mut [Complex<P>]| {
+                    let offset = (base + i * block) as u64;
+                    P::single_qubit_kernel(&mat, zeros, ones, offset, control_mask);
+                };
+                if multithread {
+                    lower
+                        .par_chunks_mut(block)
+                        .zip(upper.par_chunks_mut(block))
+                        .enumerate()
+                        .for_each(|(i, (zeros, ones))| g(i, zeros, ones));
+                } else {
+                    lower
+                        .chunks_mut(block)
+                        .zip(upper.chunks_mut(block))
+                        .enumerate()
+                        .for_each(|(i, (zeros, ones))| g(i, zeros, ones));
+                }
+            };
+            if multithread {
+                state.par_chunks_mut(2 * mask).enumerate().for_each(f);
+            } else {
+                state.chunks_mut(2 * mask).enumerate().for_each(f);
+            }
+            true
+        }
+        Some(InPlaceOp::TwoQubit(index_a, index_b, mat)) => {
+            let (mask_a, mask_b) = (1 << (n - 1 - index_a), 1 << (n - 1 - index_b));
+            let (high, low) = (max(mask_a, mask_b), min(mask_a, mask_b));
+            let block = min(low, chunk_size);
+            // Split by the higher bit then by the lower bit to get four slices which only differ
+            // in the two bits.
+            let f = |(chunk, values): (usize, &mut [Complex<P>])| {
+                let base = chunk * 2 * high;
+                let (h0, h1) = values.split_at_mut(high);
+                h0.chunks_mut(2 * low)
+                    .zip(h1.chunks_mut(2 * low))
+                    .enumerate()
+                    .for_each(|(low_chunk, (h0, h1))| {
+                        let base = base + low_chunk * 2 * low;
+                        let (x00, x01) = h0.split_at_mut(low);
+                        let (x10, x11) = h1.split_at_mut(low);
+                        // The matrix uses the bit of index_a as its most significant bit.
+                        let (x01, x10) = if high == mask_a {
+                            (x01, x10)
+                        } else {
+                            (x10, x01)
+                        };
+                        x00.chunks_mut(block)
+                            .zip(x01.chunks_mut(block))
+                            .zip(x10.chunks_mut(block).zip(x11.chunks_mut(block)))
+                            .enumerate()
+                            .for_each(|(i, ((x00, x01), (x10, x11)))| {
+                                let offset = (base + i * block) as u64;
+                                P::two_qubit_kernel(
+                                    &mat,
+                                    [x00, x01, x10, x11],
+                                    offset,
+                                    control_mask,
+                                );
+                            });
+                    });
+            };
+            if multithread {
+                state.par_chunks_mut(2 * high).enumerate().for_each(f);
+            } else {
+                state.chunks_mut(2 * high).enumerate().for_each(f);
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// Apply the row major 2x2 matrix `mat` to each pair `(zeros[j], ones[j])` where the state index
+/// `offset + j` has all the bits of `control_mask` set.
+pub(crate) fn single_qubit_kernel<P: Float>(
+    mat: &[Complex<P>; 4],
+    zeros: &mut [Complex<P>],
+    ones: &mut [Complex<P>],
+    offset: u64,
+    control_mask: u64,
+) {
+    zeros
+        .iter_mut()
+        .zip(ones.iter_mut())
+        .enumerate()
+        .filter(|(j, _)| (offset + *j as u64) & control_mask == control_mask)
+        .for_each(|(_, (a, b))| {
+            let (x, y) = (*a, *b);
+            *a = mat[0] * x + mat[1] * y;
+            *b = mat[2] * x + mat[3] * y;
+        });
+}
+
+/// Apply the row major 4x4 matrix `mat` to each `(x[0][j], x[1][j], x[2][j], x[3][j])` where the
+/// state index `offset + j` has all the bits of `control_mask` set.
+pub(crate) fn two_qubit_kernel<P: Float>(
+    mat: &[Complex<P>; 16],
+    x: [&mut [Complex<P>]; 4],
+    offset: u64,
+    control_mask: u64,
+) {
+    let [x0, x1, x2, x3] = x;
+    (0..x0.len())
+        .filter(|j| (offset + *j as u64) & control_mask == control_mask)
+        .for_each(|j| {
+            let v = [x0[j], x1[j], x2[j], x3[j]];
+            let row = |r: usize| {
+                mat[4 * r] * v[0]
+                    + mat[4 * r + 1] * v[1]
+                    + mat[4 * r + 2] * v[2]
+                    + mat[4 * r + 3] * v[3]
+            };
+            x0[j] = row(0);
+            x1[j] = row(1);
+            x2[j] = row(2);
+            x3[j] = row(3);
+        });
+}
+
 /// Apply `ops` to the `input`, storing the results in `output`. If either start at a nonzero state
 /// index in their 0th index, use `input/output_offset`.
 /// This is much less efficient as compared to repeated applications of `apply_op`, if your ops can
@@ -653,6 +945,22 @@ pub fn make_op_matrix<P: Precision>(
         .collect()
 }
 
+/// Make the row major matrix of `op` acting on `indices`, where `indices[0]` is the most
+/// significant bit. Any of `indices` which `op` doesn't act on are left unchanged.
+pub(crate) fn make_local_op_matrix(op: &UnitaryOp, indices: &[u64]) -> Vec<Complex<f64>> {
+    let n = indices.len() as u64;
+    let mut local_indices = vec![0; indices.iter().max().map(|m| m + 1).unwrap_or(0) as usize];
+    indices
+        .iter()
+        .enumerate()
+        .for_each(|(i, indx)| local_indices[*indx as usize] = i as u64);
+    let local_op = remap_indices(op.clone(), &local_indices);
+    // cols[c][r] = <r|U|c>
+    let cols = make_op_matrix::<f64>(n, &local_op, false);
+    let size = cols.len();
+    (0..size * size).map(|i| cols[i % size][i / size]).collect()
+}
+
 #[cfg(test)]
 mod state_ops_tests {
     use super::*;
@@ -756,6 +1064,110 @@ mod state_ops_tests {
         apply_ops(n, &r_ops, &input, &mut output, 0, 0, false);
     }
 
+    fn assert_in_place_matches(n: u64, op: &UnitaryOp) {
+        let input: Vec<Complex<f64>> = (0..1 << n)
+            .map(|i| Complex::new(1.0 + i as f64, 0.5 - i as f64))
+            .collect();
+        let mut expected = input.clone();
+        apply_op(n, op, &input, &mut expected, 0, 0, false);
+        for multithread in &[false, true] {
+            let mut state = input.clone();
+            assert!(apply_op_in_place(n, op, &mut state, *multithread));
+            state.iter().zip(expected.iter()).for_each(|(a, b)| {
+                assert!((a - b).norm() < 1e-10, "{:?} != {:?}", a, b);
+            });
+        }
+    }
+
+    #[test]
+    fn test_in_place_single_qubit() {
+        let mat = from_tuples(&[(0.3, 0.1), (-0.2, 0.5), (0.7, 0.0), (0.1, -0.4)]);
+        (0..4).for_each(|indx| {
+            assert_in_place_matches(4, &UnitaryOp::Matrix(vec![indx], mat.clone()));
+        });
+    }
+
+    #[test]
+    fn test_in_place_diagonal() {
+        let diag = from_tuples(&[
+            (1.0, 0.0),
+            (0.0, 0.0),
+            (0.0, 0.0),
+            (0.0, 0.0),
+            (0.0, 0.0),
+            (0.0, 1.0),
+            (0.0, 0.0),
+            (0.0, 0.0),
+            (0.0, 0.0),
+            (0.0, 0.0),
+            (-1.0, 0.0),
+            (0.0, 0.0),
+            (0.0, 0.0),
+            (0.0, 0.0),
+            (0.0, 0.0),
+            (0.6, 0.8),
+        ]);
+        assert_in_place_matches(3, &UnitaryOp::Matrix(vec![2, 0], diag));
+        let sparse = vec![
+            vec![(0, Complex::one())],
+            vec![(1, Complex::new(0.0, -1.0))],
+        ];
+        assert_in_place_matches(3, &UnitaryOp::SparseMatrix(vec![1], sparse));
+    }
+
+    #[test]
+    fn test_in_place_controlled() {
+        let mat = from_reals(&[0.0, 1.0, 1.0, 0.0]);
+        let op = make_control_op(vec![0, 3], UnitaryOp::Matrix(vec![2], mat)).unwrap();
+        assert_in_place_matches(4, &op);
+        let phase = from_tuples(&[(1.0, 0.0), (0.0, 0.0), (0.0, 0.0), (0.0, 1.0)]);
+        let op = make_control_op(vec![1], UnitaryOp::Matrix(vec![3], phase)).unwrap();
+        assert_in_place_matches(4, &op);
+    }
+
+    #[test]
+    fn test_in_place_two_qubit() {
+        let mat: Vec<_> = (0..16)
+            .map(|i| Complex::new(0.1 * i as f64, 0.3 - 0.05 * i as f64))
+            .collect();
+        for (a, b) in &[(0, 1), (1, 0), (0, 3), (3, 1), (2, 3)] {
+            assert_in_place_matches(4, &UnitaryOp::Matrix(vec![*a, *b], mat.clone()));
+        }
+        let op = make_control_op(vec![2], UnitaryOp::Matrix(vec![3, 0], mat)).unwrap();
+        assert_in_place_matches(4, &op);
+        let one = Complex::one();
+        let sparse = vec![
+            vec![(0, one)],
+            vec![(1, one)],
+            vec![(3, one)],
+            vec![(2, Complex::new(0.0, 1.0))],
+        ];
+        assert_in_place_matches(3, &UnitaryOp::SparseMatrix(vec![2, 0], sparse));
+    }
+
+    #[test]
+    fn test_in_place_blocks() {
+        // Large enough that the highest qubits are split into several kernel blocks.
+        let n = 14;
+        let mat = from_tuples(&[(0.3, 0.1), (-0.2, 0.5), (0.7, 0.0), (0.1, -0.4)]);
+        assert_in_place_matches(n, &UnitaryOp::Matrix(vec![0], mat));
+        let mat: Vec<_> = (0..16).map(|i| Complex::new(0.1 * i as f64, 0.2)).collect();
+        let op = make_control_op(vec![5], UnitaryOp::Matrix(vec![1, 0], mat)).unwrap();
+        assert_in_place_matches(n, &op);
+    }
+
+    #[test]
+    fn test_in_place_unsupported() {
+        let mut state = from_reals(&[1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        let op = make_swap_op(vec![0], vec![1]).unwrap();
+        assert!(!apply_op_in_place(3, &op, &mut state, false));
+        let mut mat = vec![Complex::zero(); 64];
+        (0..8).for_each(|i| mat[8 * i + (7 - i)] = Complex::one());
+        let op = UnitaryOp::Matrix(vec![0, 1, 2], mat);
+        assert!(!apply_op_in_place(3, &op, &mut state, false));
+        assert_eq!(state, from_reals(&[1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]));
+    }
+
     #[test]
     fn test_make_sparse_mat() {
         let one = Complex::<f64>::one();
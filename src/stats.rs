@@ -0,0 +1,314 @@
+use crate::errors::CircuitError;
+use crate::mps_state::svd::svd;
+use crate::pipeline::{LocalQuantumState, QuantumState};
+use crate::{Complex, Precision};
+use num::Zero;
+
+/// Check that `indices` are distinct qubits of an `n` qubit state.
+fn check_indices(n: u64, indices: &[u64]) -> Result<(), CircuitError> {
+    if let Some(index) = indices.iter().find(|i| **i >= n) {
+        let message = format!("Qubit {:?} is outside of a {:?} qubit state", index, n);
+        return CircuitError::make_err(message);
+    }
+    if (1..indices.len()).any(|i| indices[..i].contains(&indices[i])) {
+        let message = format!("Qubits {:?} contain duplicates", indices);
+        return CircuitError::make_err(message);
+    }
+    Ok(())
+}
+
+/// Reshape the state into `2^(n-k)` rows of `2^k` amplitudes, where `k` is the number of
+/// `indices`. Each row holds the amplitudes for one value of the other qubits, and within a row
+/// the value of `indices` uses `indices[0]` as its least significant bit (as for measurements).
+fn split_state<P: Precision>(
+    n: u64,
+    indices: &[u64],
+    state: &[Complex<P>],
+) -> Vec<Vec<Complex<P>>> {
+    let remaining: Vec<u64> = (0..n).filter(|i| !indices.contains(i)).collect();
+    let bit_value = |i: usize, qubits: &[u64]| -> usize {
+        qubits
+            .iter()
+            .enumerate()
+            .map(|(j, q)| ((i >> (n - 1 - q)) & 1) << j)
+            .sum()
+    };
+    let mut rows = vec![vec![Complex::zero(); 1 << indices.len()]; 1 << remaining.len()];
+    state.iter().enumerate().for_each(|(i, c)| {
+        rows[bit_value(i, &remaining)][bit_value(i, indices)] = *c;
+    });
+    rows
+}
+
+/// Compute the density matrix of the qubits at `indices` by tracing out all others. The matrix is
+/// returned in row major order with `2^k` rows, where `k = indices.len()`, and row `r` corresponds
+/// to the value `r` of `indices` using the same order as measurements (`indices[0]` is the least
+/// significant bit).
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::stats::reduced_density_matrix;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let q = b.qubit();
+/// let r = b.qubit();
+/// let q = b.hadamard(q);
+/// let (q, r) = b.cnot(q, r);
+/// let r = b.merge(vec![q, r])?;
+///
+/// let (state, _) = run_local::<f64>(&r)?;
+/// // Half of a bell pair is maximally mixed.
+/// let rho = reduced_density_matrix(&state, &[0])?;
+/// assert!((rho[0].re - 0.5).abs() < 1e-10);
+/// assert!(rho[1].norm() < 1e-10);
+/// assert!(rho[2].norm() < 1e-10);
+/// assert!((rho[3].re - 0.5).abs() < 1e-10);
+/// # Ok(())
+/// # }
+/// ```
+pub fn reduced_density_matrix<P: Precision>(
+    state: &LocalQuantumState<P>,
+    indices: &[u64],
+) -> Result<Vec<Complex<P>>, CircuitError> {
+    let n = state.n();
+    check_indices(n, indices)?;
+    let rows = split_state(n, indices, state.state_ref());
+    let dim = 1 << indices.len();
+    let mut rho = vec![Complex::zero(); dim * dim];
+    rows.iter().for_each(|row| {
+        (0..dim).for_each(|a| {
+            if row[a] != Complex::zero() {
+                (0..dim).for_each(|b| {
+                    rho[a * dim + b] = rho[a * dim + b] + row[a] * row[b].conj();
+                })
+            }
+        })
+    });
+    Ok(rho)
+}
+
+/// Compute the von Neumann entanglement entropy (in bits) between the qubits at `partition` and
+/// the rest of the state, from the Schmidt coefficients of the bipartition. This is `0` for product
+/// states and `min(k, n - k)` for maximally entangled states, where `k = partition.len()`.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::stats::entanglement_entropy;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let q = b.qubit();
+/// let r = b.qubit();
+/// let q = b.hadamard(q);
+/// let (q, r) = b.cnot(q, r);
+/// let r = b.merge(vec![q, r])?;
+///
+/// let (state, _) = run_local::<f64>(&r)?;
+/// assert!((entanglement_entropy(&state, &[0])? - 1.0).abs() < 1e-10);
+/// # Ok(())
+/// # }
+/// ```
+pub fn entanglement_entropy<P: Precision>(
+    state: &LocalQuantumState<P>,
+    partition: &[u64],
+) -> Result<P, CircuitError> {
+    let n = state.n();
+    check_indices(n, partition)?;
+    let rows = split_state(n, partition, state.state_ref());
+    let (num_rows, num_cols) = (rows.len(), 1 << partition.len());
+    let data: Vec<Complex<P>> = rows.into_iter().flatten().collect();
+    let eps = P::from(1e-12).unwrap();
+    Ok(svd(&data, num_rows, num_cols)
+        .s
+        .into_iter()
+        .map(|s| s * s)
+        .filter(|p| *p > eps)
+        .map(|p| -p * p.log2())
+        .fold(P::zero(), |acc, x| acc + x))
+}
+
+/// Compute the inner product `<a|b>` of two states. Both states store amplitudes with the same
+/// qubit order, so they only need to have the same number of qubits.
+pub fn inner_product<P: Precision>(
+    a: &LocalQuantumState<P>,
+    b: &LocalQuantumState<P>,
+) -> Result<Complex<P>, CircuitError> {
+    if a.n() != b.n() {
+        let message = format!(
+            "Cannot compare a {:?} qubit state with a {:?} qubit state",
+            a.n(),
+            b.n()
+        );
+        return CircuitError::make_err(message);
+    }
+    Ok(a.state_ref()
+        .iter()
+        .zip(b.state_ref().iter())
+        .fold(Complex::zero(), |acc, (x, y)| acc + x.conj() * y))
+}
+
+/// Compute the fidelity `|<a|b>|^2` of two pure states, `1` if they are equal up to a global phase
+/// and `0` if they are orthogonal.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::pipeline::LocalQuantumState;
+/// use qip::stats::fidelity;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let r = b.register(2)?;
+/// let r = b.hadamard(r);
+/// let r = b.hadamard(r);
+///
+/// let (state, _) = run_local::<f64>(&r)?;
+/// let zeros = LocalQuantumState::<f64>::new(2);
+/// assert!((fidelity(&state, &zeros)? - 1.0).abs() < 1e-10);
+/// # Ok(())
+/// # }
+/// ```
+pub fn fidelity<P: Precision>(
+    a: &LocalQuantumState<P>,
+    b: &LocalQuantumState<P>,
+) -> Result<P, CircuitError> {
+    inner_product(a, b).map(|c| c.norm_sqr())
+}
+
+#[cfg(test)]
+mod stats_tests {
+    use super::*;
+    use crate::pipeline::run_local;
+    use crate::{OpBuilder, UnitaryBuilder};
+
+    #[test]
+    fn test_reduced_product_state() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let q = b.qubit();
+        let r = b.qubit();
+        let s = b.qubit();
+        let r = b.not(r);
+        let s = b.hadamard(s);
+        let r = b.merge(vec![q, r, s])?;
+        let (state, _) = run_local::<f64>(&r)?;
+
+        // Qubit 1 is |1><1|.
+        let rho = reduced_density_matrix(&state, &[1])?;
+        assert!(rho[0].norm() < 1e-10);
+        assert!((rho[3].re - 1.0).abs() < 1e-10);
+
+        // Qubits 2 and 1 are |+>|1>, with qubit 2 as the least significant bit.
+        let rho = reduced_density_matrix(&state, &[2, 1])?;
+        [(2, 2), (2, 3), (3, 2), (3, 3)].iter().for_each(|(a, b)| {
+            assert!((rho[a * 4 + b].re - 0.5).abs() < 1e-10);
+        });
+        let total: f64 = rho.iter().map(|c| c.norm()).sum();
+        assert!((total - 2.0).abs() < 1e-10);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reduced_full_state() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let r = b.register(2)?;
+        let r = b.hadamard(r);
+        let (state, _) = run_local::<f64>(&r)?;
+        let rho = reduced_density_matrix(&state, &[0, 1])?;
+        rho.iter()
+            .for_each(|c| assert!((c.re - 0.25).abs() < 1e-10));
+        Ok(())
+    }
+
+    #[test]
+    fn test_entropy() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let q = b.qubit();
+        let r = b.qubit();
+        let s = b.register(2)?;
+        let t = b.qubit();
+        let q = b.hadamard(q);
+        let (q, r) = b.cnot(q, r);
+        let s = b.hadamard(s);
+        let t = b.ry(t, 0.8);
+        let r = b.merge(vec![q, r, s, t])?;
+        let (state, _) = run_local::<f64>(&r)?;
+
+        // Only the bell pair on qubits 0 and 1 is entangled.
+        let cases: &[(&[u64], f64)] = &[
+            (&[0], 1.0),
+            (&[1], 1.0),
+            (&[0, 1], 0.0),
+            (&[2], 0.0),
+            (&[0, 2, 4], 1.0),
+            (&[1, 2, 3, 4], 1.0),
+            (&[], 0.0),
+        ];
+        for (partition, expected) in cases {
+            let entropy = entanglement_entropy(&state, partition)?;
+            assert!((entropy - expected).abs() < 1e-8);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_entropy_partial_entanglement() -> Result<(), CircuitError> {
+        let theta: f64 = 0.9;
+        let mut b = OpBuilder::new();
+        let q = b.qubit();
+        let r = b.qubit();
+        let q = b.ry(q, theta);
+        let (q, r) = b.cnot(q, r);
+        let r = b.merge(vec![q, r])?;
+        let (state, _) = run_local::<f64>(&r)?;
+
+        let p = (theta / 2.0).cos().powi(2);
+        let expected = -p * p.log2() - (1.0 - p) * (1.0 - p).log2();
+        assert!((entanglement_entropy(&state, &[1])? - expected).abs() < 1e-8);
+        Ok(())
+    }
+
+    #[test]
+    fn test_inner_product() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let q = b.qubit();
+        let r = b.qubit();
+        let q = b.hadamard(q);
+        let r = b.merge(vec![q, r])?;
+        let (plus, _) = run_local::<f64>(&r)?;
+
+        let mut b = OpBuilder::new();
+        let q = b.qubit();
+        let r = b.qubit();
+        let q = b.not(q);
+        let q = b.phase(q, 0.3);
+        let r = b.merge(vec![q, r])?;
+        let (one, _) = run_local::<f64>(&r)?;
+
+        let zero = LocalQuantumState::<f64>::new(2);
+        let c = inner_product(&plus, &one)?;
+        let expected = Complex::from_polar(&std::f64::consts::FRAC_1_SQRT_2, &0.3);
+        assert!((c - expected).norm() < 1e-10);
+        assert!((fidelity(&plus, &zero)? - 0.5).abs() < 1e-10);
+        assert!(fidelity(&one, &zero)?.abs() < 1e-10);
+        assert!((fidelity(&one, &one)? - 1.0).abs() < 1e-10);
+        Ok(())
+    }
+
+    #[test]
+    fn test_inner_product_size_mismatch() {
+        let a = LocalQuantumState::<f64>::new(2);
+        let b = LocalQuantumState::<f64>::new(3);
+        assert!(inner_product(&a, &b).is_err());
+        assert!(fidelity(&a, &b).is_err());
+    }
+
+    #[test]
+    fn test_reduced_bad_indices() {
+        let state = LocalQuantumState::<f64>::new(2);
+        assert!(reduced_density_matrix(&state, &[2]).is_err());
+        assert!(reduced_density_matrix(&state, &[0, 0]).is_err());
+    }
+}
@@ -0,0 +1,166 @@
+use crate::errors::CircuitError;
+use crate::macros::inverter::inverter;
+use crate::{Register, UnitaryBuilder};
+use std::fmt;
+use std::rc::Rc;
+
+/// A function which builds a circuit on a set of Registers, returning them in the same order.
+pub type SubcircuitFn =
+    dyn Fn(&mut dyn UnitaryBuilder, Vec<Register>) -> Result<Vec<Register>, CircuitError>;
+
+/// A reusable circuit component on registers of fixed widths, which can be applied to any
+/// matching registers. The adjoint and controlled versions of a subcircuit are themselves
+/// subcircuits, so components built from each other compose freely.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::subcircuit::Subcircuit;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// // Prepare a bell pair from |00>.
+/// let bell = Subcircuit::new(&[1, 1], |b, mut rs| {
+///     let rb = rs.pop().unwrap();
+///     let ra = b.hadamard(rs.pop().unwrap());
+///     let (ra, rb) = b.cnot(ra, rb);
+///     Ok(vec![ra, rb])
+/// })?;
+/// // Undo it, but only if the control is |1>.
+/// let unbell = bell.adjoint().controlled();
+///
+/// let mut b = OpBuilder::new();
+/// let c = b.qubit();
+/// let ra = b.qubit();
+/// let rb = b.qubit();
+/// let c = b.x(c);
+/// let mut rs = bell.apply(&mut b, vec![ra, rb])?;
+/// rs.insert(0, c);
+/// let rs = unbell.apply(&mut b, rs)?;
+///
+/// let r = b.merge(rs)?;
+/// let (state, _) = run_local::<f64>(&r)?;
+/// // Back to |0> on ra and rb, with the control still |1>.
+/// assert!((state.get_state(true)[0b001].re - 1.0).abs() < 1e-10);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Subcircuit {
+    widths: Vec<u64>,
+    f: Rc<SubcircuitFn>,
+    adjoint: bool,
+    controls: usize,
+}
+
+impl Subcircuit {
+    /// Make a subcircuit from `f` which acts on registers of `widths` qubits. Returns an error if
+    /// there are no registers or any are empty.
+    pub fn new<F>(widths: &[u64], f: F) -> Result<Subcircuit, CircuitError>
+    where
+        F: 'static
+            + Fn(&mut dyn UnitaryBuilder, Vec<Register>) -> Result<Vec<Register>, CircuitError>,
+    {
+        if widths.is_empty() || widths.contains(&0) {
+            let message = format!("Subcircuits need nonempty registers, found {:?}", widths);
+            return CircuitError::make_err(message);
+        }
+        Ok(Subcircuit {
+            widths: widths.to_vec(),
+            f: Rc::new(f),
+            adjoint: false,
+            controls: 0,
+        })
+    }
+
+    /// The widths of the registers the subcircuit is applied to, starting with a single qubit
+    /// for each control added by `controlled`.
+    pub fn widths(&self) -> Vec<u64> {
+        let mut widths = vec![1; self.controls];
+        widths.extend(self.widths.iter().cloned());
+        widths
+    }
+
+    /// Get the inverse of the subcircuit.
+    pub fn adjoint(&self) -> Subcircuit {
+        Subcircuit {
+            adjoint: !self.adjoint,
+            ..self.clone()
+        }
+    }
+
+    /// Get the subcircuit controlled by an extra single qubit register, given before the others,
+    /// so it is only applied where the control is `|1>`.
+    pub fn controlled(&self) -> Subcircuit {
+        Subcircuit {
+            controls: self.controls + 1,
+            ..self.clone()
+        }
+    }
+
+    /// Apply the subcircuit to `rs`, which must match `widths`, returning them in the same order.
+    pub fn apply(
+        &self,
+        b: &mut dyn UnitaryBuilder,
+        rs: Vec<Register>,
+    ) -> Result<Vec<Register>, CircuitError> {
+        let widths = self.widths();
+        let found: Vec<u64> = rs.iter().map(|r| r.n()).collect();
+        if found != widths {
+            let message = format!(
+                "Subcircuit expects registers of {:?} qubits, found {:?}",
+                widths, found
+            );
+            return CircuitError::make_err(message);
+        }
+        if self.controls == 0 {
+            return self.apply_body(b, rs);
+        }
+        let mut rs = rs;
+        let targets = rs.split_off(self.controls);
+        let control_indices: Vec<_> = rs.iter().map(|r| r.indices.clone()).collect();
+        let cr = b.merge(rs)?;
+
+        let mut cb = b.with_condition(cr);
+        let targets = self.apply_body(&mut cb, targets);
+        let cr = cb.release_register();
+        let targets = targets?;
+        let (controls, _) = b.split_absolute_many(cr, &control_indices)?;
+        Ok(controls.into_iter().chain(targets).collect())
+    }
+
+    /// Apply the uncontrolled body, inverted if needed.
+    fn apply_body(
+        &self,
+        b: &mut dyn UnitaryBuilder,
+        rs: Vec<Register>,
+    ) -> Result<Vec<Register>, CircuitError> {
+        let body = |b: &mut dyn UnitaryBuilder, rs: Vec<Register>| {
+            let rs = (self.f)(b, rs)?;
+            let found: Vec<u64> = rs.iter().map(|r| r.n()).collect();
+            if found == self.widths {
+                Ok(rs)
+            } else {
+                let message = format!(
+                    "Subcircuit on registers of {:?} qubits returned registers of {:?} qubits",
+                    self.widths, found
+                );
+                CircuitError::make_err(message)
+            }
+        };
+        if self.adjoint {
+            inverter(b, rs, body)
+        } else {
+            body(b, rs)
+        }
+    }
+}
+
+impl fmt::Debug for Subcircuit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Subcircuit")
+            .field("widths", &self.widths)
+            .field("adjoint", &self.adjoint)
+            .field("controls", &self.controls)
+            .finish()
+    }
+}
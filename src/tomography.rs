@@ -0,0 +1,394 @@
+use crate::errors::CircuitError;
+use crate::pipeline::run_and_sample;
+use crate::{Complex, OpBuilder, Register, UnitaryBuilder};
+use num::{One, Zero};
+use std::collections::HashMap;
+
+/// Measurement probabilities below this are clamped when maximizing the likelihood.
+const MIN_PROBABILITY: f64 = 1e-12;
+
+/// Counts of measured values for each measurement basis of a state, collected by
+/// `collect_tomography`.
+#[derive(Debug, Clone)]
+pub struct TomographyData {
+    /// Number of qubits in the measured Register.
+    pub n: u64,
+    /// For each basis string (see `measurement_bases`) the number of times each value was
+    /// measured, with qubit `j` as bit `j` of the value.
+    pub counts: Vec<(String, HashMap<u64, usize>)>,
+}
+
+/// Get every measurement basis used for full tomography of `n` qubits, as strings of `X`, `Y` and
+/// `Z` where the character at position `j` is the basis for qubit `j`.
+pub fn measurement_bases(n: u64) -> Vec<String> {
+    (0..n).fold(vec![String::new()], |bases, _| {
+        bases
+            .into_iter()
+            .flat_map(|basis| {
+                ['X', 'Y', 'Z'].iter().map(move |c| {
+                    let mut basis = basis.clone();
+                    basis.push(*c);
+                    basis
+                })
+            })
+            .collect()
+    })
+}
+
+/// Rotate each qubit of `r` so that measuring it in the computational basis measures it in the
+/// basis given by the character at the same position of `basis`: `X`, `Y`, or `Z`. Measuring `0`
+/// corresponds to the `+1` eigenstate of the basis.
+pub fn rotate_to_basis(
+    b: &mut dyn UnitaryBuilder,
+    r: Register,
+    basis: &str,
+) -> Result<Register, CircuitError> {
+    if basis.len() as u64 != r.n() {
+        let message = format!(
+            "Basis {:?} does not match the register ({:?} qubits)",
+            basis,
+            r.n()
+        );
+        return CircuitError::make_err(message);
+    }
+    let qubits = b
+        .split_all(r)
+        .into_iter()
+        .zip(basis.chars())
+        .map(|(q, c)| match c {
+            'X' => Ok(b.hadamard(q)),
+            'Y' => {
+                let q = b.sdagger(q);
+                Ok(b.hadamard(q))
+            }
+            'Z' => Ok(q),
+            c => CircuitError::make_err(format!(
+                "Measurement bases may only contain X, Y and Z, found {:?}",
+                c
+            )),
+        })
+        .collect::<Result<Vec<_>, CircuitError>>()?;
+    b.merge(qubits)
+}
+
+/// Prepare a state with `prepare` and measure it `shots` times in each basis of
+/// `measurement_bases`, building a new circuit for each basis.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::tomography::{collect_tomography, linear_inversion};
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let data = collect_tomography(
+///     |b| {
+///         let q = b.qubit();
+///         Ok(b.hadamard(q))
+///     },
+///     1000,
+/// )?;
+/// // |+><+| has every entry equal to one half.
+/// let rho = linear_inversion(&data)?;
+/// assert!(rho.iter().all(|c| (c - Complex::new(0.5, 0.0)).norm() < 0.1));
+/// # Ok(())
+/// # }
+/// ```
+pub fn collect_tomography<F: Fn(&mut OpBuilder) -> Result<Register, CircuitError>>(
+    prepare: F,
+    shots: usize,
+) -> Result<TomographyData, CircuitError> {
+    if shots == 0 {
+        return CircuitError::make_str_err("Tomography needs at least one shot per basis.");
+    }
+    let n = {
+        let mut b = OpBuilder::new();
+        prepare(&mut b)?.n()
+    };
+    let counts = measurement_bases(n)
+        .into_iter()
+        .map(|basis| {
+            let mut b = OpBuilder::new();
+            let r = prepare(&mut b)?;
+            let r = rotate_to_basis(&mut b, r, &basis)?;
+            Ok((basis, run_and_sample::<f64>(&r, shots)?))
+        })
+        .collect::<Result<Vec<_>, CircuitError>>()?;
+    Ok(TomographyData { n, counts })
+}
+
+/// Reconstruct the density matrix from tomography data by linear inversion: the expectation of
+/// each Pauli string is estimated from every basis which measures it, and the density matrix is
+/// `sum_P <P> P / 2^n`. This always has unit trace but may not be positive for noisy data, see
+/// `maximum_likelihood`.
+///
+/// The matrix is returned in row major order, with qubit `j` as bit `j` of the row and column
+/// (as for `stats::reduced_density_matrix`).
+pub fn linear_inversion(data: &TomographyData) -> Result<Vec<Complex<f64>>, CircuitError> {
+    check_data(data)?;
+    let n = data.n as usize;
+    let dim = 1 << n;
+    let mut rho = vec![Complex::zero(); dim * dim];
+    // Each Pauli string is a choice of I, X, Y or Z for each qubit.
+    (0..1usize << (2 * n)).for_each(|p| {
+        let pauli: Vec<char> = (0..n)
+            .map(|j| ['I', 'X', 'Y', 'Z'][(p >> (2 * j)) & 3])
+            .collect();
+        let expectation = match pauli_expectation(data, &pauli) {
+            Some(e) => e,
+            None => return,
+        };
+        (0..dim * dim).for_each(|i| {
+            let (row, col) = (i / dim, i % dim);
+            let entry = pauli
+                .iter()
+                .enumerate()
+                .fold(Complex::one(), |acc, (j, c)| {
+                    acc * pauli_entry(*c, (row >> j) & 1, (col >> j) & 1)
+                });
+            rho[i] += entry * expectation / dim as f64;
+        });
+    });
+    Ok(rho)
+}
+
+/// Reconstruct the density matrix from tomography data by maximizing the likelihood of the
+/// measured counts, using `iterations` steps of the iterative `R rho R` algorithm. Unlike
+/// `linear_inversion` the result is always a valid density matrix. The layout of the matrix is
+/// the same as for `linear_inversion`.
+pub fn maximum_likelihood(
+    data: &TomographyData,
+    iterations: usize,
+) -> Result<Vec<Complex<f64>>, CircuitError> {
+    check_data(data)?;
+    let dim = 1 << data.n;
+    // Each measured outcome along with its projector and observed frequency.
+    let outcomes: Vec<(Vec<Complex<f64>>, f64)> = data
+        .counts
+        .iter()
+        .flat_map(|(basis, counts)| {
+            let total: usize = counts.values().sum();
+            counts.iter().map(move |(value, count)| {
+                let state = basis_state(basis, *value);
+                (state, *count as f64 / total as f64)
+            })
+        })
+        .collect();
+    let mut rho = vec![Complex::zero(); dim * dim];
+    (0..dim).for_each(|i| rho[i * dim + i] = Complex::new(1.0 / dim as f64, 0.0));
+    for _ in 0..iterations {
+        let mut r = vec![Complex::zero(); dim * dim];
+        outcomes.iter().for_each(|(state, frequency)| {
+            let p = expectation_value(&rho, state).max(MIN_PROBABILITY);
+            (0..dim * dim).for_each(|i| {
+                let (row, col) = (i / dim, i % dim);
+                r[i] += state[row] * state[col].conj() * (frequency / p);
+            })
+        });
+        let next = multiply(&multiply(&r, &rho, dim), &r, dim);
+        let trace: f64 = (0..dim).map(|i| next[i * dim + i].re).sum();
+        rho = next.into_iter().map(|c| c / trace).collect();
+    }
+    Ok(rho)
+}
+
+/// Reconstruct the process applied by `process` to an `n` qubit Register as its Choi matrix
+/// `sum_ij |i><j| (x) E(|i><j|)`, where `E` is the process. Each qubit is prepared in turn as
+/// `|0>`, `|1>`, `|+>` and `|+i>`, the output of `process` is reconstructed with
+/// `collect_tomography` and `linear_inversion`, and the action on each `|i><j|` is found as a
+/// combination of these outputs. Channels within `process`, such as those from `noise`, are
+/// sampled for every shot.
+///
+/// The Choi matrix is returned in row major order with the input as the most significant part of
+/// each index: the entry for `|i><j| (x) |k><l|` is at row `i * 2^m + k` and column `j * 2^m + l`
+/// where `m` is the number of qubits returned by `process`. With this normalization its trace is
+/// `2^n`.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::tomography::process_tomography;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let choi = process_tomography(1, |b, r| Ok(b.not(r)), 1000)?;
+/// // The not gate takes |0><0| to |1><1|, the entry at row 1 and column 1.
+/// assert!((choi[5] - Complex::new(1.0, 0.0)).norm() < 0.1);
+/// assert!(choi[0].norm() < 0.1);
+/// # Ok(())
+/// # }
+/// ```
+pub fn process_tomography<F: Fn(&mut OpBuilder, Register) -> Result<Register, CircuitError>>(
+    n: u64,
+    process: F,
+    shots: usize,
+) -> Result<Vec<Complex<f64>>, CircuitError> {
+    let n = n as usize;
+    // The output state for each input, the input for qubit `j` given by bits `2j` and `2j + 1`.
+    let outputs = (0..1usize << (2 * n))
+        .map(|input| {
+            let data = collect_tomography(
+                |b| {
+                    let r = b.register(n as u64)?;
+                    let qubits = b
+                        .split_all(r)
+                        .into_iter()
+                        .enumerate()
+                        .map(|(j, q)| match (input >> (2 * j)) & 3 {
+                            0 => q,
+                            1 => b.x(q),
+                            2 => b.hadamard(q),
+                            _ => {
+                                let q = b.hadamard(q);
+                                b.s(q)
+                            }
+                        })
+                        .collect();
+                    let r = b.merge(qubits)?;
+                    process(b, r)
+                },
+                shots,
+            )?;
+            Ok((data.n, linear_inversion(&data)?))
+        })
+        .collect::<Result<Vec<_>, CircuitError>>()?;
+    let dim_in = 1 << n;
+    let dim_out = 1 << outputs[0].0;
+    let mut choi = vec![Complex::zero(); dim_in * dim_in * dim_out * dim_out];
+    (0..dim_in * dim_in).for_each(|i| {
+        let (row, col) = (i / dim_in, i % dim_in);
+        outputs.iter().enumerate().for_each(|(input, (_, rho))| {
+            let coefficient = (0..n).fold(Complex::<f64>::one(), |acc, j| {
+                let element = ((row >> j) & 1, (col >> j) & 1);
+                acc * input_coefficient(element, (input >> (2 * j)) & 3)
+            });
+            if coefficient.norm() == 0.0 {
+                return;
+            }
+            (0..dim_out * dim_out).for_each(|o| {
+                let (out_row, out_col) = (o / dim_out, o % dim_out);
+                let index = (row * dim_out + out_row) * dim_in * dim_out + col * dim_out + out_col;
+                choi[index] += coefficient * rho[o];
+            })
+        })
+    });
+    Ok(choi)
+}
+
+/// Get the coefficient of the input state `input` (`|0>`, `|1>`, `|+>`, `|+i>`) when writing the
+/// single qubit matrix `|row><col|` as a combination of the input states.
+fn input_coefficient(element: (usize, usize), input: usize) -> Complex<f64> {
+    match (element, input) {
+        ((0, 0), 0) | ((1, 1), 1) => Complex::one(),
+        // |0><1| = |+><+| + i|+i><+i| - (1 + i)/2 (|0><0| + |1><1|)
+        ((0, 1), 0) | ((0, 1), 1) => Complex::new(-0.5, -0.5),
+        ((0, 1), 3) => Complex::new(0.0, 1.0),
+        // |1><0| = |+><+| - i|+i><+i| - (1 - i)/2 (|0><0| + |1><1|)
+        ((1, 0), 0) | ((1, 0), 1) => Complex::new(-0.5, 0.5),
+        ((1, 0), 3) => Complex::new(0.0, -1.0),
+        ((0, 1), 2) | ((1, 0), 2) => Complex::one(),
+        _ => Complex::zero(),
+    }
+}
+
+fn check_data(data: &TomographyData) -> Result<(), CircuitError> {
+    match data
+        .counts
+        .iter()
+        .find(|(basis, _)| basis.len() as u64 != data.n)
+    {
+        Some((basis, _)) => {
+            let message = format!(
+                "Basis {:?} does not match the data ({:?} qubits)",
+                basis, data.n
+            );
+            CircuitError::make_err(message)
+        }
+        None => Ok(()),
+    }
+}
+
+/// Estimate `<P>` from every basis which agrees with `pauli` on its non-identity terms, or None if
+/// there are no such bases.
+fn pauli_expectation(data: &TomographyData, pauli: &[char]) -> Option<f64> {
+    let (total, num_shots) = data
+        .counts
+        .iter()
+        .filter(|(basis, _)| {
+            basis
+                .chars()
+                .zip(pauli.iter())
+                .all(|(b, p)| *p == 'I' || b == *p)
+        })
+        .flat_map(|(_, counts)| counts.iter())
+        .fold((0.0, 0), |(total, num_shots), (value, count)| {
+            let parity = pauli
+                .iter()
+                .enumerate()
+                .filter(|(j, p)| **p != 'I' && (value >> j) & 1 == 1)
+                .count();
+            let sign = if parity & 1 == 0 { 1.0 } else { -1.0 };
+            (total + sign * *count as f64, num_shots + count)
+        });
+    if num_shots == 0 {
+        None
+    } else {
+        Some(total / num_shots as f64)
+    }
+}
+
+/// Get `<row|P|col>` for a single qubit Pauli.
+fn pauli_entry(pauli: char, row: usize, col: usize) -> Complex<f64> {
+    match (pauli, row, col) {
+        ('I', r, c) | ('Z', r, c) if r != c => Complex::zero(),
+        ('I', _, _) => Complex::one(),
+        ('Z', 0, _) => Complex::one(),
+        ('Z', _, _) => -Complex::one(),
+        ('X', r, c) if r != c => Complex::one(),
+        ('Y', 0, 1) => Complex::new(0.0, -1.0),
+        ('Y', 1, 0) => Complex::new(0.0, 1.0),
+        _ => Complex::zero(),
+    }
+}
+
+/// Get the state measured as `value` in `basis`, with qubit `j` as bit `j` of the index.
+fn basis_state(basis: &str, value: u64) -> Vec<Complex<f64>> {
+    let h = std::f64::consts::FRAC_1_SQRT_2;
+    basis
+        .chars()
+        .enumerate()
+        .fold(vec![Complex::one()], |state, (j, c)| {
+            let bit = (value >> j) & 1;
+            let qubit = match (c, bit) {
+                ('X', 0) => [Complex::new(h, 0.0), Complex::new(h, 0.0)],
+                ('X', _) => [Complex::new(h, 0.0), Complex::new(-h, 0.0)],
+                ('Y', 0) => [Complex::new(h, 0.0), Complex::new(0.0, h)],
+                ('Y', _) => [Complex::new(h, 0.0), Complex::new(0.0, -h)],
+                (_, 0) => [Complex::one(), Complex::zero()],
+                (_, _) => [Complex::zero(), Complex::one()],
+            };
+            // Qubit j is the most significant bit so far.
+            qubit
+                .iter()
+                .flat_map(|a| state.iter().map(move |s| a * s))
+                .collect()
+        })
+}
+
+/// Get `<v|rho|v>`.
+fn expectation_value(rho: &[Complex<f64>], v: &[Complex<f64>]) -> f64 {
+    let dim = v.len();
+    (0..dim * dim)
+        .map(|i| v[i / dim].conj() * rho[i] * v[i % dim])
+        .fold(Complex::zero(), |acc: Complex<f64>, c| acc + c)
+        .re
+}
+
+fn multiply(a: &[Complex<f64>], b: &[Complex<f64>], dim: usize) -> Vec<Complex<f64>> {
+    (0..dim * dim)
+        .map(|i| {
+            let (row, col) = (i / dim, i % dim);
+            (0..dim).fold(Complex::zero(), |acc, k| {
+                acc + a[row * dim + k] * b[k * dim + col]
+            })
+        })
+        .collect()
+}
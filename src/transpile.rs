@@ -0,0 +1,538 @@
+use crate::errors::CircuitError;
+use crate::pipeline::{
+    get_opfns_and_frontier, get_required_state_size_from_frontier, StateModifier, StateModifierType,
+};
+use crate::state_ops::{get_index, make_local_op_matrix, num_indices, UnitaryOp};
+use crate::{Complex, OpBuilder, Register, UnitaryBuilder};
+use num::{One, Zero};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+/// Entries below this magnitude are treated as zero when decomposing ops.
+const EPSILON: f64 = 1e-12;
+
+/// A row major 2x2 matrix.
+type Mat2 = [Complex<f64>; 4];
+
+/// Sets of gates which circuits can be rewritten into with `transpile`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BasisGates {
+    /// CNOT along with U3(theta, phi, lambda) on single qubits.
+    CnotU3,
+    /// CZ along with Rz(theta) and SX (the square root of X) on single qubits.
+    CzRzSx,
+}
+
+/// Rewrite the circuit which produces `r` into a new circuit using only the gates in `basis`. Each
+/// unitary op (including any `mat` or `sparse_mat`) is decomposed into single qubit gates and
+/// CNOTs, which are then expressed in the basis. Adjacent single qubit gates are merged, and the
+/// result is equal to the original circuit up to a global phase.
+///
+/// Measurements and channels are kept unchanged, so `MeasurementHandle`s from the original circuit
+/// can be used with the results of running the new one. Debug ops are dropped, while classical
+/// side channels and parameterized ops produce an error since their ops are not known until run.
+///
+/// The returned Register has the same indices as `r`, followed by any other qubits used by the
+/// circuit.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::transpile::{transpile, BasisGates};
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let q = b.qubit();
+/// let r = b.qubit();
+/// let q = b.hadamard(q);
+/// let (q, r) = b.cnot(q, r);
+/// let r = b.merge(vec![q, r])?;
+///
+/// let t = transpile(&r, BasisGates::CzRzSx)?;
+/// let (state, _) = run_local::<f64>(&t)?;
+/// let state = state.get_state(true);
+/// assert!((state[0].norm() - std::f64::consts::FRAC_1_SQRT_2).abs() < 1e-10);
+/// assert!((state[3].norm() - std::f64::consts::FRAC_1_SQRT_2).abs() < 1e-10);
+/// # Ok(())
+/// # }
+/// ```
+pub fn transpile(r: &Register, basis: BasisGates) -> Result<Register, CircuitError> {
+    let (frontier, ops) = get_opfns_and_frontier(r);
+    let n = get_required_state_size_from_frontier(&frontier);
+
+    let mut instructions = vec![];
+    ops.into_iter()
+        .try_for_each(|modifier| lower_modifier(modifier, basis, &mut instructions))?;
+    let instructions = merge_single_qubit_gates(instructions);
+
+    let mut b = OpBuilder::new();
+    let reg = b.register(n)?;
+    let reg = instructions.into_iter().try_fold(reg, |reg, instruction| {
+        apply_instruction(&mut b, reg, instruction, basis)
+    })?;
+    match b.split_absolute(reg, &r.indices)? {
+        (r, Some(rest)) => b.merge(vec![r, rest]),
+        (r, None) => Ok(r),
+    }
+}
+
+/// An op in the rewritten circuit.
+enum Instruction {
+    /// A single qubit gate which is yet to be expressed in the basis.
+    Single(u64, Mat2),
+    /// A CNOT with a control and target.
+    Cnot(u64, u64),
+    /// A CZ between two qubits.
+    Cz(u64, u64),
+    /// A modifier which is copied from the original circuit, along with the qubits it uses.
+    Modifier(Vec<u64>, StateModifier),
+}
+
+/// A gate on `target` controlled on all of `controls`.
+struct ControlledGate {
+    controls: Vec<u64>,
+    target: u64,
+    mat: Mat2,
+}
+
+fn lower_modifier(
+    modifier: &StateModifier,
+    basis: BasisGates,
+    instructions: &mut Vec<Instruction>,
+) -> Result<(), CircuitError> {
+    match &modifier.modifier {
+        StateModifierType::UnitaryOp(op) => {
+            let mut gates = vec![];
+            lower_op(op, &[], &mut gates);
+            gates
+                .into_iter()
+                .for_each(|gate| lower_controlled_gate(gate, basis, instructions));
+        }
+        StateModifierType::MeasureState(_, indices, _)
+        | StateModifierType::StochasticMeasureState(_, indices, _)
+        | StateModifierType::Channel(indices, _) => {
+            if let Some(m) = modifier.try_clone() {
+                instructions.push(Instruction::Modifier(indices.clone(), m));
+            }
+        }
+        StateModifierType::Subcircuit(modifiers) => modifiers
+            .iter()
+            .try_for_each(|modifier| lower_modifier(modifier, basis, instructions))?,
+        StateModifierType::Gate(def, indices) => def
+            .instantiate(indices)
+            .iter()
+            .try_for_each(|modifier| lower_modifier(modifier, basis, instructions))?,
+        StateModifierType::Loop(body, indices) => body
+            .unroll(indices, None)?
+            .iter()
+            .try_for_each(|modifier| lower_modifier(modifier, basis, instructions))?,
+        StateModifierType::Debug(_, _) => {}
+        StateModifierType::SideChannelModifiers(_, _) => {
+            return CircuitError::make_str_err("Classical side channels cannot be transpiled")
+        }
+        StateModifierType::ParameterizedOp(_, _) => {
+            return CircuitError::make_str_err("Parameterized ops cannot be transpiled")
+        }
+    }
+    Ok(())
+}
+
+/// Break `op`, controlled on `controls`, into controlled single qubit gates.
+fn lower_op(op: &UnitaryOp, controls: &[u64], gates: &mut Vec<ControlledGate>) {
+    match op {
+        UnitaryOp::Matrix(indices, mat) if indices.len() == 1 => gates.push(ControlledGate {
+            controls: controls.to_vec(),
+            target: indices[0],
+            mat: [mat[0], mat[1], mat[2], mat[3]],
+        }),
+        UnitaryOp::Swap(a_indices, b_indices) => {
+            // Only the middle CNOT of the three needs the controls.
+            let x = x_matrix();
+            a_indices.iter().zip(b_indices.iter()).for_each(|(a, b)| {
+                let cnot = |controls: Vec<u64>, target: u64| ControlledGate {
+                    controls,
+                    target,
+                    mat: x,
+                };
+                let mut middle_controls = controls.to_vec();
+                middle_controls.push(*a);
+                gates.push(cnot(vec![*b], *a));
+                gates.push(cnot(middle_controls, *b));
+                gates.push(cnot(vec![*b], *a));
+            })
+        }
+        UnitaryOp::Control(c_indices, _, op) => {
+            let controls: Vec<u64> = controls.iter().chain(c_indices.iter()).cloned().collect();
+            lower_op(op, &controls, gates)
+        }
+        _ => {
+            let indices: Vec<u64> = (0..num_indices(op)).map(|i| get_index(op, i)).collect();
+            let mat = make_local_op_matrix(op, &indices);
+            decompose_two_level(&indices, mat, controls, gates)
+        }
+    }
+}
+
+/// Decompose the row major matrix `mat` on `indices` (with the first index as the most significant
+/// bit) into two level unitaries between basis states which are adjacent in the gray code. Each of
+/// these is a single qubit gate controlled on the value of every other qubit.
+fn decompose_two_level(
+    indices: &[u64],
+    mat: Vec<Complex<f64>>,
+    controls: &[u64],
+    gates: &mut Vec<ControlledGate>,
+) {
+    let n = indices.len();
+    let size = 1 << n;
+    let gray: Vec<usize> = (0..size).map(|i| i ^ (i >> 1)).collect();
+    // Work with the rows and columns permuted into gray code order.
+    let mut v: Vec<Complex<f64>> = (0..size * size)
+        .map(|i| mat[gray[i / size] * size + gray[i % size]])
+        .collect();
+
+    // Zero out entries below the diagonal with rotations on adjacent rows, giving G_m...G_1 U = D.
+    let mut rotations: Vec<(usize, Mat2)> = vec![];
+    (0..size - 1).for_each(|col| {
+        (col + 1..size).rev().for_each(|row| {
+            let (x, y) = (v[(row - 1) * size + col], v[row * size + col]);
+            if y.norm() < EPSILON {
+                return;
+            }
+            let norm = (x.norm_sqr() + y.norm_sqr()).sqrt();
+            let g = [x.conj() / norm, y.conj() / norm, -y / norm, x / norm];
+            (0..size).for_each(|c| {
+                let (a, b) = (v[(row - 1) * size + c], v[row * size + c]);
+                v[(row - 1) * size + c] = g[0] * a + g[1] * b;
+                v[row * size + c] = g[2] * a + g[3] * b;
+            });
+            rotations.push((row, g));
+        })
+    });
+
+    // U = G_1^dagger ... G_m^dagger D, only the last entry of D may differ from 1.
+    let mut two_level = vec![];
+    let last = v[size * size - 1];
+    if (last - Complex::one()).norm() > EPSILON {
+        two_level.push((
+            size - 1,
+            [Complex::one(), Complex::zero(), Complex::zero(), last],
+        ));
+    }
+    rotations.into_iter().rev().for_each(|(row, g)| {
+        let g_dag = [g[0].conj(), g[2].conj(), g[1].conj(), g[3].conj()];
+        two_level.push((row, g_dag))
+    });
+
+    two_level.into_iter().for_each(|(row, mat)| {
+        let (from, to) = (gray[row - 1], gray[row]);
+        let bit = n - 1 - (from ^ to).trailing_zeros() as usize;
+        // Put the state with the bit unset first.
+        let mat = if to & (1 << (n - 1 - bit)) == 0 {
+            [mat[3], mat[2], mat[1], mat[0]]
+        } else {
+            mat
+        };
+        // Controls which should be zero are negated before and after.
+        let zero_controls: Vec<u64> = (0..n)
+            .filter(|i| *i != bit && to & (1 << (n - 1 - i)) == 0)
+            .map(|i| indices[i])
+            .collect();
+        let negate = |gates: &mut Vec<ControlledGate>| {
+            zero_controls.iter().for_each(|indx| {
+                gates.push(ControlledGate {
+                    controls: vec![],
+                    target: *indx,
+                    mat: x_matrix(),
+                })
+            })
+        };
+        negate(gates);
+        gates.push(ControlledGate {
+            controls: (0..n)
+                .filter(|i| *i != bit)
+                .map(|i| indices[i])
+                .chain(controls.iter().cloned())
+                .collect(),
+            target: indices[bit],
+            mat,
+        });
+        negate(gates);
+    })
+}
+
+/// Break a controlled single qubit gate into single qubit gates and CNOTs.
+fn lower_controlled_gate(
+    gate: ControlledGate,
+    basis: BasisGates,
+    instructions: &mut Vec<Instruction>,
+) {
+    let ControlledGate {
+        mut controls,
+        target,
+        mat,
+    } = gate;
+    match controls.len() {
+        0 => instructions.push(Instruction::Single(target, mat)),
+        1 if approx_eq(&mat, &x_matrix()) => {
+            let control = controls[0];
+            match basis {
+                BasisGates::CnotU3 => instructions.push(Instruction::Cnot(control, target)),
+                BasisGates::CzRzSx => {
+                    instructions.push(Instruction::Single(target, h_matrix()));
+                    instructions.push(Instruction::Cz(control, target));
+                    instructions.push(Instruction::Single(target, h_matrix()));
+                }
+            }
+        }
+        1 => {
+            // U = e^{ia} Rz(b) Ry(c) Rz(d) = e^{ia} A X B X C where ABC = I.
+            let control = controls[0];
+            let (alpha, beta, gamma, delta) = zyz_decomposition(&mat);
+            let a = mat_mul(&rz(beta), &ry(gamma / 2.0));
+            let b = mat_mul(&ry(-gamma / 2.0), &rz(-(delta + beta) / 2.0));
+            let c = rz((delta - beta) / 2.0);
+            let phase = Complex::from_polar(&1.0, &alpha);
+            let cnot = |instructions: &mut Vec<Instruction>| {
+                lower_controlled_gate(
+                    ControlledGate {
+                        controls: vec![control],
+                        target,
+                        mat: x_matrix(),
+                    },
+                    basis,
+                    instructions,
+                )
+            };
+            instructions.push(Instruction::Single(target, c));
+            cnot(instructions);
+            instructions.push(Instruction::Single(target, b));
+            cnot(instructions);
+            instructions.push(Instruction::Single(target, a));
+            instructions.push(Instruction::Single(
+                control,
+                [Complex::one(), Complex::zero(), Complex::zero(), phase],
+            ));
+        }
+        _ => {
+            // With V^2 = U, apply V controlled on the last control, then flip the last control on
+            // the rest and undo V, then flip back and apply V controlled on the rest.
+            let last = controls.pop().unwrap();
+            let v = sqrt_unitary(&mat);
+            let v_dag = [v[0].conj(), v[2].conj(), v[1].conj(), v[3].conj()];
+            let gates = vec![
+                (vec![last], target, v),
+                (controls.clone(), last, x_matrix()),
+                (vec![last], target, v_dag),
+                (controls.clone(), last, x_matrix()),
+                (controls, target, v),
+            ];
+            gates.into_iter().for_each(|(controls, target, mat)| {
+                let gate = ControlledGate {
+                    controls,
+                    target,
+                    mat,
+                };
+                lower_controlled_gate(gate, basis, instructions)
+            })
+        }
+    }
+}
+
+/// Combine runs of single qubit gates on the same qubit into a single gate, dropping any which are
+/// the identity up to a phase.
+fn merge_single_qubit_gates(instructions: Vec<Instruction>) -> Vec<Instruction> {
+    let mut pending: HashMap<u64, Mat2> = HashMap::new();
+    let mut merged = vec![];
+    let flush = |pending: &mut HashMap<u64, Mat2>, merged: &mut Vec<Instruction>, indx: u64| {
+        if let Some(mat) = pending.remove(&indx) {
+            if !is_phase(&mat) {
+                merged.push(Instruction::Single(indx, mat));
+            }
+        }
+    };
+    instructions
+        .into_iter()
+        .for_each(|instruction| match instruction {
+            Instruction::Single(indx, mat) => {
+                let mat = match pending.get(&indx) {
+                    Some(prev) => mat_mul(&mat, prev),
+                    None => mat,
+                };
+                pending.insert(indx, mat);
+            }
+            Instruction::Cnot(a, b) | Instruction::Cz(a, b) => {
+                flush(&mut pending, &mut merged, a);
+                flush(&mut pending, &mut merged, b);
+                merged.push(instruction);
+            }
+            Instruction::Modifier(indices, modifier) => {
+                indices
+                    .iter()
+                    .for_each(|indx| flush(&mut pending, &mut merged, *indx));
+                merged.push(Instruction::Modifier(indices, modifier));
+            }
+        });
+    let mut remaining: Vec<u64> = pending.keys().cloned().collect();
+    remaining.sort_unstable();
+    remaining
+        .into_iter()
+        .for_each(|indx| flush(&mut pending, &mut merged, indx));
+    merged
+}
+
+fn apply_instruction(
+    b: &mut OpBuilder,
+    reg: Register,
+    instruction: Instruction,
+    basis: BasisGates,
+) -> Result<Register, CircuitError> {
+    let indices = match instruction {
+        Instruction::Single(indx, _) => vec![indx],
+        Instruction::Cnot(a, b) | Instruction::Cz(a, b) => vec![a, b],
+        Instruction::Modifier(indices, modifier) => {
+            return b.apply_modifier_to_indices(reg, &indices, modifier)
+        }
+    };
+    let (sel, rest) = b.split_absolute(reg, &indices)?;
+    let sel = match instruction {
+        Instruction::Single(_, mat) => apply_single(b, sel, &mat, basis)?,
+        _ => {
+            let (c, t) = b.split(sel, &[0])?;
+            let (c, t) = match instruction {
+                Instruction::Cnot(_, _) => b.cnot(c, t.unwrap()),
+                _ => b.cz(c, t.unwrap()),
+            };
+            b.merge(vec![c, t])?
+        }
+    };
+    match rest {
+        Some(rest) => b.merge(vec![sel, rest]),
+        None => Ok(sel),
+    }
+}
+
+/// Apply the single qubit gate `mat` to `q` using the gates in `basis`, up to a global phase.
+fn apply_single(
+    b: &mut OpBuilder,
+    q: Register,
+    mat: &Mat2,
+    basis: BasisGates,
+) -> Result<Register, CircuitError> {
+    let (_, phi, theta, lambda) = zyz_decomposition(mat);
+    match basis {
+        BasisGates::CnotU3 => b.mat("U3", q, u3(theta, phi, lambda).to_vec()),
+        BasisGates::CzRzSx if theta.abs() < EPSILON => Ok(b.rz(q, phi + lambda)),
+        BasisGates::CzRzSx => {
+            // U3(theta, phi, lambda) = Rz(phi + pi) SX Rz(theta + pi) SX Rz(lambda) up to phase.
+            let sx = sx_matrix().to_vec();
+            let q = b.rz(q, lambda);
+            let q = b.mat("SX", q, sx.clone())?;
+            let q = b.rz(q, theta + PI);
+            let q = b.mat("SX", q, sx)?;
+            Ok(b.rz(q, phi + PI))
+        }
+    }
+}
+
+/// Find `(alpha, beta, gamma, delta)` such that `mat = e^{i alpha} Rz(beta) Ry(gamma) Rz(delta)`.
+fn zyz_decomposition(mat: &Mat2) -> (f64, f64, f64, f64) {
+    let det = mat[0] * mat[3] - mat[1] * mat[2];
+    let alpha = det.arg() / 2.0;
+    let phase = Complex::from_polar(&1.0, &-alpha);
+    let (a, b) = (mat[0] * phase, mat[2] * phase);
+    let gamma = 2.0 * b.norm().atan2(a.norm());
+    let (arg_a, arg_b) = (zero_arg(a), zero_arg(b));
+    (alpha, arg_b - arg_a, gamma, -arg_a - arg_b)
+}
+
+/// The argument of `c`, or zero if `c` is too small for it to matter.
+fn zero_arg(c: Complex<f64>) -> f64 {
+    if c.norm() < EPSILON {
+        0.0
+    } else {
+        c.arg()
+    }
+}
+
+/// Find a unitary square root of the 2x2 unitary `mat`.
+fn sqrt_unitary(mat: &Mat2) -> Mat2 {
+    let det = mat[0] * mat[3] - mat[1] * mat[2];
+    let trace = mat[0] + mat[3];
+    // sqrt(M) = (M + sI) / t with s^2 = det(M) and t^2 = tr(M) + 2s, pick s to keep t away from 0.
+    let s = det.sqrt();
+    let s = if (trace + s * 2.0).norm() >= (trace - s * 2.0).norm() {
+        s
+    } else {
+        -s
+    };
+    let t = (trace + s * 2.0).sqrt();
+    [(mat[0] + s) / t, mat[1] / t, mat[2] / t, (mat[3] + s) / t]
+}
+
+fn mat_mul(a: &Mat2, b: &Mat2) -> Mat2 {
+    [
+        a[0] * b[0] + a[1] * b[2],
+        a[0] * b[1] + a[1] * b[3],
+        a[2] * b[0] + a[3] * b[2],
+        a[2] * b[1] + a[3] * b[3],
+    ]
+}
+
+fn approx_eq(a: &Mat2, b: &Mat2) -> bool {
+    a.iter()
+        .zip(b.iter())
+        .all(|(a, b)| (a - b).norm() < EPSILON)
+}
+
+/// Check if `mat` is the identity up to a phase.
+fn is_phase(mat: &Mat2) -> bool {
+    mat[1].norm() < EPSILON && mat[2].norm() < EPSILON && (mat[0] - mat[3]).norm() < EPSILON
+}
+
+fn x_matrix() -> Mat2 {
+    [
+        Complex::zero(),
+        Complex::one(),
+        Complex::one(),
+        Complex::zero(),
+    ]
+}
+
+fn h_matrix() -> Mat2 {
+    let x = Complex::new(std::f64::consts::FRAC_1_SQRT_2, 0.0);
+    [x, x, x, -x]
+}
+
+fn sx_matrix() -> Mat2 {
+    let (a, b) = (Complex::new(0.5, 0.5), Complex::new(0.5, -0.5));
+    [a, b, b, a]
+}
+
+fn rz(theta: f64) -> Mat2 {
+    [
+        Complex::from_polar(&1.0, &(-theta / 2.0)),
+        Complex::zero(),
+        Complex::zero(),
+        Complex::from_polar(&1.0, &(theta / 2.0)),
+    ]
+}
+
+fn ry(theta: f64) -> Mat2 {
+    let (sin, cos) = (theta / 2.0).sin_cos();
+    [
+        Complex::new(cos, 0.0),
+        Complex::new(-sin, 0.0),
+        Complex::new(sin, 0.0),
+        Complex::new(cos, 0.0),
+    ]
+}
+
+fn u3(theta: f64, phi: f64, lambda: f64) -> Mat2 {
+    let (sin, cos) = (theta / 2.0).sin_cos();
+    [
+        Complex::new(cos, 0.0),
+        -Complex::from_polar(&sin, &lambda),
+        Complex::from_polar(&sin, &phi),
+        Complex::from_polar(&cos, &(phi + lambda)),
+    ]
+}
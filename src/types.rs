@@ -1,11 +1,90 @@
-extern crate rayon;
-
 use num::Float;
 use std::fmt::Display;
 use std::iter::Sum;
 
-/// The float precision of the circuit.
-pub trait Precision: Default + Float + Sum + Send + Sync + Display {}
+/// The float precision of the circuit, implemented for `f32` and `f64`.
+pub trait Precision: Default + Float + Sum + Send + Sync + Display + Kernels {}
 
 impl Precision for f64 {}
 impl Precision for f32 {}
+
+pub(crate) use self::kernels::Kernels;
+
+mod kernels {
+    use crate::state_ops::{single_qubit_kernel, two_qubit_kernel};
+    use crate::Complex;
+    use num::Float;
+
+    /// Inner loops of `apply_op_in_place`, which may be specialized for a given precision. This
+    /// can't be named outside the crate, so `Precision` can't be implemented for other types.
+    pub trait Kernels: Float {
+        /// Apply the row major 2x2 matrix `mat` to each pair `(zeros[j], ones[j])` where the
+        /// state index `offset + j` has all the bits of `control_mask` set.
+        fn single_qubit_kernel(
+            mat: &[Complex<Self>; 4],
+            zeros: &mut [Complex<Self>],
+            ones: &mut [Complex<Self>],
+            offset: u64,
+            control_mask: u64,
+        ) {
+            single_qubit_kernel(mat, zeros, ones, offset, control_mask)
+        }
+
+        /// Apply the row major 4x4 matrix `mat` to each `(x[0][j], x[1][j], x[2][j], x[3][j])`
+        /// where the state index `offset + j` has all the bits of `control_mask` set.
+        fn two_qubit_kernel(
+            mat: &[Complex<Self>; 16],
+            x: [&mut [Complex<Self>]; 4],
+            offset: u64,
+            control_mask: u64,
+        ) {
+            two_qubit_kernel(mat, x, offset, control_mask)
+        }
+    }
+
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    impl Kernels for f64 {
+        fn single_qubit_kernel(
+            mat: &[Complex<Self>; 4],
+            zeros: &mut [Complex<Self>],
+            ones: &mut [Complex<Self>],
+            offset: u64,
+            control_mask: u64,
+        ) {
+            crate::simd::single_qubit_kernel_f64(mat, zeros, ones, offset, control_mask)
+        }
+
+        fn two_qubit_kernel(
+            mat: &[Complex<Self>; 16],
+            x: [&mut [Complex<Self>]; 4],
+            offset: u64,
+            control_mask: u64,
+        ) {
+            crate::simd::two_qubit_kernel_f64(mat, x, offset, control_mask)
+        }
+    }
+
+    #[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+    impl Kernels for f64 {}
+    impl Kernels for f32 {}
+}
+
+/// The bit order used to turn a list of measured qubits into an integer.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    /// The first qubit is the least significant bit, the default.
+    #[default]
+    Little,
+    /// The first qubit is the most significant bit.
+    Big,
+}
+
+impl Endianness {
+    /// Order `indices` from least to most significant bit, as used by measurements.
+    pub fn qubit_order(self, indices: &[u64]) -> Vec<u64> {
+        match self {
+            Endianness::Little => indices.to_vec(),
+            Endianness::Big => indices.iter().rev().cloned().collect(),
+        }
+    }
+}
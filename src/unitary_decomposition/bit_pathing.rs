@@ -1,6 +1,6 @@
 use crate::errors::CircuitError;
+use crate::parallel::prelude::*;
 use crate::unitary_decomposition::utils::gray_code;
-use rayon::prelude::*;
 
 pub(crate) struct BitPather {
     n: u64,
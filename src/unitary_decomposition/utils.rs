@@ -1,7 +1,5 @@
-extern crate rayon;
-
+use crate::parallel::prelude::*;
 use crate::{Complex, Precision};
-use rayon::prelude::*;
 use std::cmp::max;
 use std::ops::{Add, Mul};
 
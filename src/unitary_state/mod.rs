@@ -0,0 +1,64 @@
+/// State struct
+pub mod state;
+
+use crate::pipeline::{
+    get_opfns_and_frontier, get_required_state_size_from_frontier, run_with_state, MeasuredResults,
+    StateModifier, StateModifierType,
+};
+use crate::{CircuitError, Precision, Register};
+pub use state::UnitaryBackend;
+
+/// Check that `modifier` can be represented by a unitary.
+fn check_unitary(modifier: &StateModifier) -> Result<(), CircuitError> {
+    match &modifier.modifier {
+        StateModifierType::UnitaryOp(_)
+        | StateModifierType::ParameterizedOp(_, _)
+        | StateModifierType::Debug(_, _)
+        | StateModifierType::Gate(_, _) => Ok(()),
+        StateModifierType::Subcircuit(modifiers) => modifiers.iter().try_for_each(check_unitary),
+        StateModifierType::Loop(body, _) => body.body().iter().try_for_each(check_unitary),
+        _ => {
+            let message = format!(
+                "Op {:?} is not unitary and cannot be included in a UnitaryBackend",
+                modifier.name
+            );
+            CircuitError::make_err(message)
+        }
+    }
+}
+
+/// `run` the pipeline using `UnitaryBackend`, giving the unitary of the whole circuit. Returns an
+/// error if the circuit contains measurements, channels or side channels.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::unitary_state::run_unitary_local;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let q = b.qubit();
+/// let q = b.hadamard(q);
+///
+/// let (u, _) = run_unitary_local::<f64>(&q)?;
+/// let u = u.get_unitary(true);
+/// let x = std::f64::consts::FRAC_1_SQRT_2;
+/// assert!((u[0][0].re - x).abs() < 1e-10);
+/// assert!((u[1][1].re + x).abs() < 1e-10);
+/// # Ok(())
+/// # }
+/// ```
+pub fn run_unitary_local<P: Precision>(
+    r: &Register,
+) -> Result<(UnitaryBackend<P>, MeasuredResults<P>), CircuitError> {
+    let n = unitary_circuit_size(r)?;
+    run_with_state(r, UnitaryBackend::new_identity(n))
+}
+
+/// Get the number of qubits used by the circuit ending at `r`, or an error if it contains any ops
+/// which are not unitary.
+pub(crate) fn unitary_circuit_size(r: &Register) -> Result<u64, CircuitError> {
+    let (frontier, ops) = get_opfns_and_frontier(r);
+    ops.into_iter().try_for_each(check_unitary)?;
+    Ok(get_required_state_size_from_frontier(&frontier))
+}
@@ -0,0 +1,270 @@
+use crate::measurement_ops::MeasuredCondition;
+use crate::parallel::prelude::*;
+use crate::pipeline::{InitialState, LocalQuantumState};
+use crate::state_ops::{apply_op, apply_op_in_place, UnitaryOp};
+use crate::utils::flip_bits;
+use crate::{CircuitError, Complex, Precision, QuantumState};
+use num::{One, Zero};
+
+/// A "state" which holds the full `2^n` by `2^n` unitary of the ops applied to it, starting from
+/// the identity. Useful for checking small circuits against known matrices.
+///
+/// The matrix is kept as a vector of length `4^n` where entry `(row << n) | col` is
+/// `<row|U|col>`. Read as a state on `2n` qubits, qubit `i` of the circuit lines up with qubit `i`
+/// of the row half, so each op is applied with the same kernels as `LocalQuantumState`.
+///
+/// Measurements cannot be represented: probabilities (such as those given to debug ops) are those
+/// of the state `U|0...0>`, and a measurement is recorded as an error which stops the run. Use
+/// `run_unitary_local` to check the circuit before running it.
+#[derive(Debug)]
+pub struct UnitaryBackend<P: Precision> {
+    n: u64,
+    state: Vec<Complex<P>>,
+    arena: Vec<Complex<P>>,
+    multithread: bool,
+    error: Option<CircuitError>,
+}
+
+impl<P: Precision> UnitaryBackend<P> {
+    /// Make the identity on `n` qubits.
+    pub fn new_identity(n: u64) -> UnitaryBackend<P> {
+        let size = 1usize << n;
+        let mut state = vec![Complex::zero(); size * size];
+        (0..size).for_each(|i| state[i * size + i] = Complex::one());
+        UnitaryBackend {
+            n,
+            state,
+            arena: vec![],
+            multithread: true,
+            error: None,
+        }
+    }
+
+    /// Return a reference to the internal row major matrix.
+    pub fn state_ref(&self) -> &Vec<Complex<P>> {
+        &self.state
+    }
+
+    /// Get the entry `<row|U|col>` where qubit 0 is the least significant bit if `natural_order`.
+    pub fn get_entry(&self, row: u64, col: u64, natural_order: bool) -> Complex<P> {
+        let (row, col) = if natural_order {
+            (
+                flip_bits(self.n as usize, row),
+                flip_bits(self.n as usize, col),
+            )
+        } else {
+            (row, col)
+        };
+        self.state[((row << self.n) | col) as usize]
+    }
+
+    /// Get the rows of the unitary, in the same layout as `pipeline::make_circuit_matrix`.
+    pub fn get_unitary(&self, natural_order: bool) -> Vec<Vec<Complex<P>>> {
+        let size = 1 << self.n;
+        (0..size)
+            .map(|row| {
+                (0..size)
+                    .map(|col| self.get_entry(row, col, natural_order))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Set whether the state will use multithreading.
+    pub fn set_multithreading(&mut self, multithread: bool) {
+        self.multithread = multithread;
+    }
+
+    /// The state `U|0...0>`, in the internal order.
+    fn first_column(&self) -> LocalQuantumState<P> {
+        let n = self.n;
+        let column = (0..1 << n).map(|row| self.state[row << n]).collect();
+        LocalQuantumState::new_from_full_state(n, column, false, self.multithread).unwrap()
+    }
+}
+
+impl<P: Precision> Clone for UnitaryBackend<P> {
+    fn clone(&self) -> Self {
+        UnitaryBackend {
+            n: self.n,
+            state: self.state.clone(),
+            arena: vec![],
+            multithread: self.multithread,
+            error: self
+                .error
+                .as_ref()
+                .map(|err| CircuitError::new(err.to_string())),
+        }
+    }
+}
+
+impl<P: Precision> QuantumState<P> for UnitaryBackend<P> {
+    fn new(n: u64) -> Self {
+        UnitaryBackend::new_identity(n)
+    }
+
+    /// The unitary doesn't depend on the initial state, so `states` only set the number of qubits.
+    fn new_from_initial_states(n: u64, _states: &[(Vec<u64>, InitialState<P>)]) -> Self {
+        UnitaryBackend::new_identity(n)
+    }
+
+    fn n(&self) -> u64 {
+        self.n
+    }
+
+    fn apply_op_with_name(&mut self, _name: Option<&str>, op: &UnitaryOp) {
+        // Acting on the row half gives `U -> op * U`.
+        let n = 2 * self.n;
+        if !apply_op_in_place(n, op, &mut self.state, self.multithread) {
+            if self.arena.is_empty() {
+                self.arena = vec![Complex::zero(); self.state.len()];
+            }
+            apply_op(n, op, &self.state, &mut self.arena, 0, 0, self.multithread);
+            std::mem::swap(&mut self.state, &mut self.arena);
+        }
+    }
+
+    fn take_error(&mut self) -> Option<CircuitError> {
+        self.error.take()
+    }
+
+    fn measure(
+        &mut self,
+        indices: &[u64],
+        _measured: Option<MeasuredCondition<P>>,
+        _angle: f64,
+    ) -> (u64, P) {
+        if self.error.is_none() {
+            let message = format!(
+                "Measurement of {:?} cannot be applied to a UnitaryBackend",
+                indices
+            );
+            self.error = Some(CircuitError::new(message));
+        }
+        (0, P::zero())
+    }
+
+    fn soft_measure(&mut self, indices: &[u64], measured: Option<u64>, angle: f64) -> (u64, P) {
+        self.first_column().soft_measure(indices, measured, angle)
+    }
+
+    fn state_magnitude(&self) -> P {
+        // Each column of a unitary has unit norm.
+        let total: P = if self.multithread {
+            self.state.par_iter().map(Complex::<P>::norm_sqr).sum()
+        } else {
+            self.state.iter().map(Complex::<P>::norm_sqr).sum()
+        };
+        (total / P::from(1u64 << self.n).unwrap()).sqrt()
+    }
+
+    fn stochastic_measure(&mut self, indices: &[u64], angle: f64) -> Vec<P> {
+        self.first_column().stochastic_measure(indices, angle)
+    }
+
+    /// Returns the row major unitary, see `get_unitary`.
+    fn get_state(self, natural_order: bool) -> Vec<Complex<P>> {
+        if natural_order {
+            self.get_unitary(true).into_iter().flatten().collect()
+        } else {
+            self.state
+        }
+    }
+}
+
+#[cfg(test)]
+mod unitary_state_tests {
+    use super::*;
+    use crate::pipeline::{run_local_with_init, run_with_state};
+    use crate::unitary_state::run_unitary_local;
+    use crate::{CircuitError, OpBuilder, Register, UnitaryBuilder};
+
+    fn make_circuit(b: &mut OpBuilder) -> Result<Register, CircuitError> {
+        let r = b.register(3)?;
+        let qs = b.split_all(r);
+        let qs = qs
+            .into_iter()
+            .enumerate()
+            .map(|(i, q)| {
+                let q = b.ry(q, 0.3 + 0.5 * i as f64);
+                b.rz(q, 0.2 * i as f64)
+            })
+            .collect();
+        let r = b.merge(qs)?;
+        let (ra, rb) = b.split(r, &[0])?;
+        let (ra, rb) = b.cnot(ra, rb.unwrap());
+        let (rb, rc) = b.split(rb, &[0])?;
+        let (rb, rc) = b.swap(rb, rc.unwrap())?;
+        let rc = b.hadamard(rc);
+        b.merge(vec![ra, rb, rc])
+    }
+
+    #[test]
+    fn test_columns_match_basis_states() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let r = make_circuit(&mut b)?;
+        let (u, _) = run_unitary_local::<f64>(&r)?;
+        assert!((u.state_magnitude() - 1.0).abs() < 1e-10);
+        let u = u.get_unitary(true);
+        (0..8).try_for_each(|col| {
+            let init = [(r.indices.clone(), InitialState::Index(col))];
+            let (state, _) = run_local_with_init::<f64>(&r, &init)?;
+            state
+                .get_state(true)
+                .into_iter()
+                .enumerate()
+                .for_each(|(row, c)| assert!((u[row][col as usize] - c).norm() < 1e-10));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_swap_matrix() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let q = b.qubit();
+        let r = b.qubit();
+        let (q, r) = b.swap(q, r)?;
+        let r = b.merge(vec![q, r])?;
+        let (u, _) = run_unitary_local::<f64>(&r)?;
+        let one = Complex::one();
+        [(0, 0), (1, 2), (2, 1), (3, 3)]
+            .iter()
+            .for_each(|(row, col)| assert_eq!(u.get_entry(*row, *col, true), one));
+        Ok(())
+    }
+
+    #[test]
+    fn test_first_column_probabilities() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let r = b.register(2)?;
+        let r = b.not(r);
+        let (mut u, _) = run_unitary_local::<f64>(&r)?;
+        assert_eq!(
+            u.stochastic_measure(&r.indices, 0.0),
+            vec![0.0, 0.0, 0.0, 1.0]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_measurement_errors() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let q = b.qubit();
+        let q = b.hadamard(q);
+        let (q, _) = b.measure(q);
+        assert!(run_unitary_local::<f64>(&q).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_measurement_errors_with_state() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let q = b.qubit();
+        let q = b.hadamard(q);
+        let (q, _) = b.measure(q);
+        let q = b.hadamard(q);
+        let result = run_with_state(&q, UnitaryBackend::<f64>::new_identity(1));
+        assert!(result.is_err());
+        Ok(())
+    }
+}
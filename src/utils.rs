@@ -1,4 +1,4 @@
-use rayon::prelude::*;
+use crate::parallel::prelude::*;
 use std::sync::{Arc, Mutex};
 
 /// Set the `bit_index` bit in `num` to `value`.
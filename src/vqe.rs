@@ -0,0 +1,177 @@
+use crate::errors::CircuitError;
+use crate::parameters::{expectation, gradient, Parameter};
+use crate::{Register, UnitaryBuilder};
+use std::collections::HashMap;
+
+/// Options for the gradient descent used by `vqe`.
+#[derive(Debug, Clone, Copy)]
+pub struct VqeOptions {
+    /// Step size for each gradient descent update.
+    pub learning_rate: f64,
+    /// Maximum number of gradient descent updates.
+    pub max_iterations: usize,
+    /// Stop once an update changes the energy by less than this amount.
+    pub tolerance: f64,
+}
+
+impl Default for VqeOptions {
+    fn default() -> Self {
+        VqeOptions {
+            learning_rate: 0.1,
+            max_iterations: 200,
+            tolerance: 1e-8,
+        }
+    }
+}
+
+/// The outcome of running `vqe`.
+#[derive(Debug, Clone)]
+pub struct VqeResult {
+    /// The lowest energy found.
+    pub energy: f64,
+    /// The parameter values which produce `energy`.
+    pub parameters: HashMap<String, f64>,
+    /// Number of gradient descent updates made.
+    pub iterations: usize,
+}
+
+/// Apply a hardware efficient ansatz to `r`: each of the `layers` applies `Ry` and `Rz` rotations
+/// to every qubit followed by a chain of cnots between neighboring qubits, and a final set of
+/// rotations is applied at the end. Returns the parameters used, named `theta_{i}`.
+pub fn hardware_efficient_ansatz(
+    b: &mut dyn UnitaryBuilder,
+    r: Register,
+    layers: usize,
+) -> Result<(Register, Vec<Parameter>), CircuitError> {
+    let mut params = vec![];
+    let mut rotations =
+        |b: &mut dyn UnitaryBuilder, rs: Vec<Register>| -> Result<Vec<Register>, CircuitError> {
+            rs.into_iter()
+                .map(|r| {
+                    let ry = Parameter(format!("theta_{}", params.len()));
+                    let rz = Parameter(format!("theta_{}", params.len() + 1));
+                    let r = b.ry_param(r, &ry)?;
+                    let r = b.rz_param(r, &rz)?;
+                    params.push(ry);
+                    params.push(rz);
+                    Ok(r)
+                })
+                .collect()
+        };
+    let mut rs = b.split_all(r);
+    for _ in 0..layers {
+        let mut layer = rotations(b, rs)?.into_iter();
+        let first = layer.next().unwrap();
+        let (mut chained, last) = layer.fold((vec![], first), |(mut acc, ra), rb| {
+            let (ra, rb) = b.cnot(ra, rb);
+            acc.push(ra);
+            (acc, rb)
+        });
+        chained.push(last);
+        rs = chained;
+    }
+    let rs = rotations(b, rs)?;
+    Ok((b.merge(rs)?, params))
+}
+
+/// Find the parameters for the circuit producing `ansatz` which minimize the expectation value of
+/// `hamiltonian`, a weighted sum of pauli strings (see `parameters::expectation`). Starting from
+/// `initial`, which must contain a value for every parameter in the circuit, the parameters are
+/// updated by gradient descent with gradients from the parameter-shift rule.
+///
+/// # Example
+/// ```
+/// use qip::*;
+/// use qip::parameters::Parameter;
+/// use qip::vqe::{vqe, VqeOptions};
+/// use std::collections::HashMap;
+/// # fn main() -> Result<(), CircuitError> {
+///
+/// let mut b = OpBuilder::new();
+/// let q = b.qubit();
+/// let q = b.ry_param(q, &Parameter::new("theta"))?;
+///
+/// let mut initial = HashMap::new();
+/// initial.insert("theta".to_string(), 0.1);
+/// let result = vqe(&q, &[(1.0, "Z")], &initial, &VqeOptions::default())?;
+/// assert!((result.energy + 1.0).abs() < 1e-4);
+/// # Ok(())
+/// # }
+/// ```
+pub fn vqe(
+    ansatz: &Register,
+    hamiltonian: &[(f64, &str)],
+    initial: &HashMap<String, f64>,
+    options: &VqeOptions,
+) -> Result<VqeResult, CircuitError> {
+    let mut parameters = initial.clone();
+    let mut energy = expectation(ansatz, hamiltonian, &parameters)?;
+    let mut iterations = 0;
+    while iterations < options.max_iterations {
+        let grads = gradient(ansatz, hamiltonian, &parameters)?;
+        let mut next = parameters.clone();
+        grads.into_iter().for_each(|(name, grad)| {
+            if let Some(value) = next.get_mut(&name) {
+                *value -= options.learning_rate * grad;
+            }
+        });
+        let next_energy = expectation(ansatz, hamiltonian, &next)?;
+        iterations += 1;
+        let delta = energy - next_energy;
+        if next_energy < energy {
+            energy = next_energy;
+            parameters = next;
+        }
+        if delta.abs() < options.tolerance {
+            break;
+        }
+    }
+    Ok(VqeResult {
+        energy,
+        parameters,
+        iterations,
+    })
+}
+
+#[cfg(test)]
+mod vqe_tests {
+    use super::*;
+    use crate::parameters::get_parameters;
+    use crate::OpBuilder;
+
+    #[test]
+    fn test_ansatz_parameters() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let r = b.register(3)?;
+        let (r, params) = hardware_efficient_ansatz(&mut b, r, 2)?;
+        assert_eq!(r.n(), 3);
+        assert_eq!(params.len(), 3 * 2 * 3);
+        assert_eq!(get_parameters(&r), params);
+        Ok(())
+    }
+
+    #[test]
+    fn test_vqe_two_qubits() -> Result<(), CircuitError> {
+        let mut b = OpBuilder::new();
+        let r = b.register(2)?;
+        let (r, params) = hardware_efficient_ansatz(&mut b, r, 1)?;
+        let initial = params
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (p.name().to_string(), 0.1 * (i + 1) as f64))
+            .collect();
+
+        // Eigenvalues of ZZ + 0.5 XI are +-sqrt(1.25)
+        let hamiltonian = [(1.0, "ZZ"), (0.5, "XI")];
+        let options = VqeOptions {
+            learning_rate: 0.2,
+            max_iterations: 500,
+            tolerance: 1e-10,
+        };
+        let result = vqe(&r, &hamiltonian, &initial, &options)?;
+        assert!((result.energy + 1.25f64.sqrt()).abs() < 1e-4);
+        let energy = expectation(&r, &hamiltonian, &result.parameters)?;
+        assert!((energy - result.energy).abs() < 1e-10);
+        Ok(())
+    }
+}
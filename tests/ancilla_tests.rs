@@ -0,0 +1,129 @@
+extern crate qip;
+
+use qip::*;
+use std::cell::Cell;
+use std::rc::Rc;
+
+fn and_into_ancilla(
+    b: &mut dyn UnitaryBuilder,
+    r: Register,
+    anc: Register,
+) -> Result<(Register, Register), CircuitError> {
+    let (r, out) = b.split(r, &[0, 1])?;
+    let (r, anc) = b.cnot(r, anc);
+    Ok((b.merge(vec![r, out.unwrap()])?, anc))
+}
+
+fn copy_ancilla(
+    b: &mut dyn UnitaryBuilder,
+    r: Register,
+    anc: Register,
+) -> Result<(Register, Register), CircuitError> {
+    let (r, out) = b.split(r, &[0, 1])?;
+    let (anc, out) = b.cnot(anc, out.unwrap());
+    Ok((b.merge(vec![r, out])?, anc))
+}
+
+#[test]
+fn test_ancilla_and_oracle() -> Result<(), CircuitError> {
+    for x in 0..4u64 {
+        let mut b = OpBuilder::new();
+        let r = b.register(3)?;
+        let init = (r.indices.clone(), pipeline::InitialState::Index(x));
+        let r = b.with_ancilla(1, r, Box::new(and_into_ancilla), Box::new(copy_ancilla))?;
+        let (state, _) = run_local_with_init::<f64>(&r, &[init])?;
+        let state = state.get_state(true);
+        let expected = if x == 0b11 { 0b111 } else { x };
+        // The ancilla is qubit 3, so anything above 0b111 means it was left dirty.
+        assert!((state[expected as usize].re - 1.0).abs() < 1e-10);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_ancilla_phase_kickback_allowed() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(3)?;
+    let r = b.hadamard(r);
+    let r = b.with_ancilla(
+        1,
+        r,
+        Box::new(and_into_ancilla),
+        Box::new(|b, r, anc| {
+            let (r, out) = b.split(r, &[0, 1])?;
+            let (anc, out) = b.cz(anc, out.unwrap());
+            Ok((b.merge(vec![r, out])?, anc))
+        }),
+    )?;
+    let (state, _) = run_local::<f64>(&r)?;
+    let state = state.get_state(true);
+    let amp = 1.0 / 8f64.sqrt();
+    (0..8).for_each(|i| {
+        let sign = if i == 0b111 { -1.0 } else { 1.0 };
+        assert!((state[i].re - sign * amp).abs() < 1e-10);
+    });
+    Ok(())
+}
+
+#[test]
+fn test_ancilla_reused() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(3)?;
+    let r = b.with_ancilla(1, r, Box::new(and_into_ancilla), Box::new(copy_ancilla))?;
+    let r = b.with_ancilla(1, r, Box::new(and_into_ancilla), Box::new(copy_ancilla))?;
+    assert_eq!(b.get_qubit_count(), 4);
+    let (state, _) = run_local::<f64>(&r)?;
+    assert!((state.get_state(true)[0].re - 1.0).abs() < 1e-10);
+    Ok(())
+}
+
+#[test]
+fn test_ancilla_modified_errors() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(3)?;
+    let result = b.with_ancilla(
+        1,
+        r,
+        Box::new(and_into_ancilla),
+        Box::new(|b, r, anc| Ok((r, b.hadamard(anc)))),
+    );
+    assert!(result.is_err());
+    Ok(())
+}
+
+#[test]
+fn test_ancilla_leaked_errors() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(3)?;
+    // Hand back a qubit of r in place of the ancilla.
+    let result = b.with_ancilla(
+        1,
+        r,
+        Box::new(and_into_ancilla),
+        Box::new(|b, r, anc| {
+            let (q, r) = b.split(r, &[0])?;
+            Ok((b.merge(vec![anc, r.unwrap()])?, q))
+        }),
+    );
+    assert!(result.is_err());
+    Ok(())
+}
+
+#[test]
+fn test_ancilla_apply_called_once() -> Result<(), CircuitError> {
+    let calls = Rc::new(Cell::new(0));
+    let apply_calls = calls.clone();
+    let mut b = OpBuilder::new();
+    let r = b.register(3)?;
+    b.with_ancilla(
+        1,
+        r,
+        Box::new(and_into_ancilla),
+        Box::new(move |b, r, anc| {
+            apply_calls.set(apply_calls.get() + 1);
+            copy_ancilla(b, r, anc)
+        }),
+    )?;
+    assert_eq!(calls.get(), 1);
+    Ok(())
+}
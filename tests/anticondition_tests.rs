@@ -0,0 +1,80 @@
+extern crate qip;
+
+use qip::*;
+
+/// Run a two qubit circuit on each basis state and return the index each is mapped to.
+fn basis_map<F>(f: F) -> Result<Vec<u64>, CircuitError>
+where
+    F: Fn(&mut OpBuilder, Register, Register) -> (Register, Register),
+{
+    (0..4)
+        .map(|i| {
+            let mut b = OpBuilder::new();
+            let q = b.qubit();
+            let r = b.qubit();
+            let (hq, hr) = (q.handle(), r.handle());
+            let (q, r) = f(&mut b, q, r);
+            let r = b.merge(vec![q, r])?;
+            let init = [
+                hq.make_init_from_index(i & 1)?,
+                hr.make_init_from_index(i >> 1)?,
+            ];
+            let (state, _) = run_local_with_init::<f64>(&r, &init)?;
+            let state = state.get_state(true);
+            let out = (0..4).find(|j| state[*j].norm() > 0.5).unwrap();
+            Ok(out as u64)
+        })
+        .collect()
+}
+
+#[test]
+fn test_ncnot() -> Result<(), CircuitError> {
+    let out = basis_map(|b, q, r| b.ncnot(q, r))?;
+    // r is flipped only when q is |0>.
+    assert_eq!(out, vec![0b10, 0b01, 0b00, 0b11]);
+    Ok(())
+}
+
+#[test]
+fn test_ncx_matches_ncnot() -> Result<(), CircuitError> {
+    let ncx = basis_map(|b, q, r| b.ncx(q, r))?;
+    let ncnot = basis_map(|b, q, r| b.ncnot(q, r))?;
+    assert_eq!(ncx, ncnot);
+    Ok(())
+}
+
+#[test]
+fn test_ncz_phase() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let r = b.hadamard(r);
+    let (q, r) = b.ncz(q, r);
+    let r = b.hadamard(r);
+    let r = b.merge(vec![q, r])?;
+    let (state, _) = run_local::<f64>(&r)?;
+
+    // With q in |0> the z fires, and H Z H = X takes r to |1>.
+    assert!((state.get_state(true)[0b10].norm() - 1.0).abs() < 1e-10);
+    Ok(())
+}
+
+#[test]
+fn test_nested_anticondition() -> Result<(), CircuitError> {
+    // Fires when the first qubit is |1> and the second is |0>.
+    let (c0, c1, t) = (1u64, 0u64, 0u64);
+    let mut b = OpBuilder::new();
+    let qa = b.qubit();
+    let qb = b.qubit();
+    let qt = b.qubit();
+    let qa = b.not(qa);
+    let (qa, (qb, qt)) = condition(&mut b, qa, (qb, qt), |b, (qb, qt)| {
+        anticondition(b, qb, qt, |b, qt| b.not(qt))
+    });
+    let r = b.merge(vec![qa, qb, qt])?;
+    let (state, _) = run_local::<f64>(&r)?;
+
+    let expected = c0 | (c1 << 1) | ((t ^ 1) << 2);
+    assert!((state.get_state(true)[expected as usize].norm() - 1.0).abs() < 1e-10);
+    Ok(())
+}
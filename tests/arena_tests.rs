@@ -0,0 +1,48 @@
+extern crate qip;
+
+use qip::*;
+
+#[test]
+fn test_arena_not_allocated_in_place() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.hadamard(q);
+    let (q, r) = b.cnot(q, r);
+    let r = b.rz(r, 0.4);
+    let r = b.merge(vec![q, r])?;
+    let (state, _) = run_local::<f64>(&r)?;
+    assert!(!state.has_arena());
+    Ok(())
+}
+
+#[test]
+fn test_arena_allocated_for_swap() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.not(q);
+    let (q, r) = b.swap(q, r)?;
+    let r = b.merge(vec![q, r])?;
+    let (mut state, _) = run_local::<f64>(&r)?;
+    assert!(state.has_arena());
+    state.release_arena();
+    assert!(!state.has_arena());
+    let state = state.get_state(true);
+    assert_eq!(state[0b10], Complex::new(1.0, 0.0));
+    Ok(())
+}
+
+#[test]
+fn test_arena_allocated_for_measurement() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let q = b.hadamard(q);
+    let (q, m) = b.measure(q);
+    let (state, measured) = run_local::<f64>(&q)?;
+    assert!(state.has_arena());
+    let (m, _) = measured.get_measurement(&m).unwrap();
+    let state = state.get_state(true);
+    assert_eq!(state[m as usize], Complex::new(1.0, 0.0));
+    Ok(())
+}
@@ -0,0 +1,139 @@
+extern crate qip;
+
+use qip::benchmarks::*;
+use qip::noise::NoiseModel;
+use qip::rng::with_seed;
+use qip::*;
+use std::collections::HashMap;
+
+#[test]
+fn test_model_circuit_is_unitary() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(3)?;
+    let r = with_seed(1, || model_circuit(&mut b, r, 3))?;
+    assert_eq!(r.n(), 3);
+    let (state, _) = run_local::<f64>(&r)?;
+    let norm: f64 = state.get_state(false).iter().map(|c| c.norm_sqr()).sum();
+    assert!((norm - 1.0).abs() < 1e-10);
+    Ok(())
+}
+
+#[test]
+fn test_model_circuit_seeded() -> Result<(), CircuitError> {
+    let make = || -> Result<Vec<Complex<f64>>, CircuitError> {
+        let mut b = OpBuilder::new();
+        let r = b.register(4)?;
+        let r = with_seed(5, || model_circuit(&mut b, r, 4))?;
+        let (state, _) = run_local::<f64>(&r)?;
+        Ok(state.get_state(false))
+    };
+    assert_eq!(make()?, make()?);
+    Ok(())
+}
+
+#[test]
+fn test_heavy_output_probability() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(4)?;
+    let r = with_seed(2, || model_circuit(&mut b, r, 4))?;
+
+    let ideal = heavy_output_probability(&r, &NoiseModel::new())?;
+    assert!(ideal > 0.5 && ideal <= 1.0);
+
+    let mut noise = NoiseModel::new();
+    noise.set_default_probability(0.05)?;
+    let noisy = heavy_output_probability(&r, &noise)?;
+    assert!(noisy < ideal && noisy > 0.5);
+
+    noise.set_gate_probability("SU(4)", 1.0)?;
+    let mixed = heavy_output_probability(&r, &noise)?;
+    assert!((mixed - 0.5).abs() < 1e-10);
+    Ok(())
+}
+
+#[test]
+fn test_quantum_volume_noise() -> Result<(), CircuitError> {
+    let mut noise = NoiseModel::new();
+    noise.set_gate_probability("SU(4)", 0.1)?;
+    let result = with_seed(3, || quantum_volume(4, 30, &noise))?;
+    assert_eq!(result.widths.len(), 3);
+    assert_eq!(
+        result.widths.iter().map(|w| w.width).collect::<Vec<_>>(),
+        vec![2, 3, 4]
+    );
+    assert!(result
+        .widths
+        .iter()
+        .all(|w| w.heavy_output_probabilities.len() == 30));
+    // Heavy output probabilities fall as the circuits grow and collect more noise.
+    assert!(result.widths[0].mean() > result.widths[2].mean());
+    let achieved = result
+        .widths
+        .iter()
+        .take_while(|w| w.passed)
+        .last()
+        .map(|w| w.width)
+        .unwrap_or(0);
+    assert_eq!(result.quantum_volume, 1 << achieved);
+    Ok(())
+}
+
+#[test]
+fn test_quantum_volume_errors() {
+    let noise = NoiseModel::new();
+    assert!(quantum_volume(1, 10, &noise).is_err());
+    assert!(quantum_volume(3, 0, &noise).is_err());
+}
+
+#[test]
+fn test_xeb_circuit() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(4)?;
+    let r = with_seed(7, || xeb_circuit(&mut b, r, 6))?;
+    assert_eq!(r.n(), 4);
+    let ideal = ideal_probabilities(&r)?;
+    assert_eq!(ideal.len(), 16);
+    assert!((ideal.iter().sum::<f64>() - 1.0).abs() < 1e-10);
+    // Random circuits spread the state over every output.
+    assert!(ideal.iter().all(|p| *p < 0.5));
+    Ok(())
+}
+
+#[test]
+fn test_linear_xeb_fidelity() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(4)?;
+    let r = with_seed(8, || xeb_circuit(&mut b, r, 12))?;
+    let ideal = ideal_probabilities(&r)?;
+
+    let samples = with_seed(9, || run_and_sample::<f64>(&r, 20000))?;
+    let ideal_fidelity = linear_xeb_fidelity(&ideal, &samples)?;
+
+    // The expected value is 2^n sum p^2 - 1 for samples from the ideal distribution.
+    let expected = 16.0 * ideal.iter().map(|p| p * p).sum::<f64>() - 1.0;
+    assert!((ideal_fidelity - expected).abs() < 0.05);
+
+    // Uniform samples score zero.
+    let uniform: HashMap<u64, usize> = (0..16).map(|x| (x, 10)).collect();
+    assert!(linear_xeb_fidelity(&ideal, &uniform)?.abs() < 1e-10);
+
+    // Noise lowers the fidelity, and fully depolarizing leaves nothing.
+    let mut noise = NoiseModel::new();
+    noise.set_default_probability(0.02)?;
+    let noisy = with_seed(10, || noisy_samples(&r, &noise, 20000))?;
+    let noisy_fidelity = linear_xeb_fidelity(&ideal, &noisy)?;
+    assert!(noisy_fidelity < ideal_fidelity - 0.1 && noisy_fidelity > 0.0);
+
+    noise.set_default_probability(1.0)?;
+    let probs = noisy_probabilities(&r, &noise)?;
+    assert!(probs.iter().all(|p| (p - 1.0 / 16.0).abs() < 1e-10));
+    Ok(())
+}
+
+#[test]
+fn test_linear_xeb_errors() {
+    let ideal = vec![0.5, 0.5];
+    assert!(linear_xeb_fidelity(&ideal, &HashMap::new()).is_err());
+    let samples: HashMap<u64, usize> = vec![(2, 1)].into_iter().collect();
+    assert!(linear_xeb_fidelity(&ideal, &samples).is_err());
+}
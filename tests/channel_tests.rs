@@ -0,0 +1,170 @@
+extern crate num;
+extern crate qip;
+
+use num::{One, Zero};
+use qip::density_state::run_density_local;
+use qip::noise::amplitude_damping;
+use qip::rng::with_seed;
+use qip::sparse_state::run_sparse_local;
+use qip::stabilizer_state::run_stabilizer;
+use qip::state_ops::from_reals;
+use qip::*;
+
+fn assert_almost_eq(a: f64, b: f64, prec: i32) {
+    let mult = 10.0f64.powi(prec);
+    let (a, b) = (a * mult, b * mult);
+    let (a, b) = (a.round(), b.round());
+    assert_eq!(a / mult, b / mult);
+}
+
+fn bit_flip(p: f64) -> Vec<Vec<Complex<f64>>> {
+    let (a, b) = ((1.0 - p).sqrt(), p.sqrt());
+    vec![from_reals(&[a, 0.0, 0.0, a]), from_reals(&[0.0, b, b, 0.0])]
+}
+
+#[test]
+fn test_density_bit_flip() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let q = b.channel("bitflip", q, bit_flip(0.25))?;
+    let (rho, _) = run_density_local::<f64>(&q)?;
+
+    assert_almost_eq(rho.get_entry(0, 0, true).re, 0.75, 10);
+    assert_almost_eq(rho.get_entry(1, 1, true).re, 0.25, 10);
+    assert_almost_eq(rho.get_entry(0, 1, true).norm(), 0.0, 10);
+    Ok(())
+}
+
+#[test]
+fn test_density_broadcast() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(2)?;
+    let r = b.channel("bitflip", r, bit_flip(0.5))?;
+    let (rho, _) = run_density_local::<f64>(&r)?;
+
+    (0..4).for_each(|i| assert_almost_eq(rho.get_entry(i, i, true).re, 0.25, 10));
+    assert_almost_eq(rho.purity(), 0.25, 10);
+    Ok(())
+}
+
+#[test]
+fn test_local_trajectory() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let q = b.channel("bitflip", q, bit_flip(1.0))?;
+    let (state, _) = run_local::<f64>(&q)?;
+
+    let state = state.get_state(true);
+    assert_almost_eq(state[0].norm(), 0.0, 10);
+    assert_almost_eq(state[1].norm(), 1.0, 10);
+    Ok(())
+}
+
+#[test]
+fn test_local_trajectory_normalized() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let q = b.hadamard(q);
+    let q = b.channel("bitflip", q, bit_flip(0.3))?;
+    let (state, _) = run_local::<f64>(&q)?;
+
+    assert_almost_eq(state.state_magnitude(), 1.0, 10);
+    Ok(())
+}
+
+#[test]
+fn test_sparse_trajectory() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let idle = b.register(8)?;
+    let q = b.not(q);
+    let q = b.channel("damping", q, amplitude_damping(0.36)?)?;
+    let (q, m) = b.measure(q);
+    let r = b.merge(vec![q, idle])?;
+
+    let shots = 2000;
+    let ones = (0..shots).try_fold(0, |acc, seed| -> Result<u64, CircuitError> {
+        let (state, measured) = with_seed(seed, || run_sparse_local::<f64>(&r))?;
+        assert!(!state.is_dense());
+        assert_almost_eq(state.state_magnitude(), 1.0, 10);
+        Ok(acc + measured.get_measurement(&m).unwrap().0)
+    })?;
+    let p = ones as f64 / shots as f64;
+    assert!((p - 0.64).abs() < 0.05, "P(1) = {}", p);
+    Ok(())
+}
+
+#[test]
+fn test_stabilizer_pauli_channel() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let q = b.channel("bitflip", q, bit_flip(1.0))?;
+    let (q, m) = b.measure(q);
+    let (_, measured) = run_stabilizer::<f64>(&q)?;
+
+    assert_eq!(measured.get_measurement(&m), Some((1, 1.0)));
+    Ok(())
+}
+
+#[test]
+fn test_invalid_channel() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let not_complete = vec![vec![
+        Complex::one(),
+        Complex::zero(),
+        Complex::zero(),
+        Complex::zero(),
+    ]];
+    assert!(b.channel("bad", q, not_complete).is_err());
+
+    let q = b.qubit();
+    assert!(b.channel("bad", q, vec![from_reals(&[1.0])]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_reset_local() -> Result<(), CircuitError> {
+    for _ in 0..10 {
+        let mut b = OpBuilder::new();
+        let r = b.register(3)?;
+        let r = b.hadamard(r);
+        let r = b.reset(r);
+        let (state, _) = run_local::<f64>(&r)?;
+        assert_almost_eq(state.get_state(true)[0].norm(), 1.0, 10);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_reset_density() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.hadamard(q);
+    let (q, r) = b.cnot(q, r);
+    let q = b.reset(q);
+    let r = b.merge(vec![q, r])?;
+    let (rho, _) = run_density_local::<f64>(&r)?;
+
+    // q is reset, r is left maximally mixed.
+    assert_almost_eq(rho.get_entry(0b00, 0b00, true).re, 0.5, 10);
+    assert_almost_eq(rho.get_entry(0b10, 0b10, true).re, 0.5, 10);
+    assert_almost_eq(rho.purity(), 0.5, 10);
+    Ok(())
+}
+
+#[test]
+fn test_reset_reuse() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let q = b.not(q);
+    let (q, m1) = b.measure(q);
+    let q = b.reset(q);
+    let (q, m2) = b.measure(q);
+    let (_, measured) = run_local::<f64>(&q)?;
+
+    assert_eq!(measured.get_measurement(&m1).map(|(m, _)| m), Some(1));
+    assert_eq!(measured.get_measurement(&m2).map(|(m, _)| m), Some(0));
+    Ok(())
+}
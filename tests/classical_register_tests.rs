@@ -0,0 +1,117 @@
+extern crate qip;
+
+use qip::classical::ClassicalRegister;
+use qip::pipeline::MeasurementHandle;
+use qip::*;
+
+/// Make a register of `n` qubits in the basis state `value`.
+fn prepare(b: &mut OpBuilder, n: u64, value: u64) -> Result<Register, CircuitError> {
+    let r = b.register(n)?;
+    let qs = b
+        .split_all(r)
+        .into_iter()
+        .enumerate()
+        .map(|(i, q)| if (value >> i) & 1 == 1 { b.x(q) } else { q })
+        .collect();
+    b.merge(qs)
+}
+
+/// Measure a register prepared in the basis state `value`.
+fn measure_value(
+    b: &mut OpBuilder,
+    n: u64,
+    value: u64,
+) -> Result<(Register, MeasurementHandle), CircuitError> {
+    let r = prepare(b, n, value)?;
+    Ok(b.measure(r))
+}
+
+#[test]
+fn test_bit_operations() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let (ra, ma) = measure_value(&mut b, 3, 0b101)?;
+    let (rb, mb) = measure_value(&mut b, 2, 0b11)?;
+    let r = b.merge(vec![ra, rb])?;
+    let (state, measured) = run_local::<f64>(&r)?;
+    assert!((state.get_state(true)[0b11101].re - 1.0).abs() < 1e-10);
+
+    let a = ClassicalRegister::from(&ma);
+    let c = ClassicalRegister::from(&mb);
+    let cases = vec![
+        (a.clone() & c.clone(), 0b001),
+        (a.clone() | c.clone(), 0b111),
+        (a.clone() ^ c.clone(), 0b110),
+        (!a.clone(), 0b010),
+        (!c.clone(), 0b00),
+        (a.clone() ^ 0b111, 0b010),
+        (a.concat(&c), 0b11101),
+        (a.bit(0), 1),
+        (a.bit(1), 0),
+        (a.parity(), 0),
+        ((a.clone() & 0b100).parity(), 1),
+        (a.equals(0b101), 1),
+        (a.not_equals(0b101), 0),
+        (a.less_than(c.clone()), 0),
+        (a.greater_than(c.clone()), 1),
+        (c.less_equal(3), 1),
+        (c.greater_equal(4), 0),
+        (ClassicalRegister::constant(0b1111, 2), 0b11),
+    ];
+    cases.iter().for_each(|(cr, expected)| {
+        assert_eq!(measured.get_classical(cr), Some(*expected), "{:?}", cr);
+    });
+    assert_eq!((a.clone() & c.clone()).n(), 3);
+    assert_eq!(a.concat(&c).n(), 5);
+    assert_eq!(a.equals(c.clone()).n(), 1);
+    assert_eq!((a.clone() ^ c.clone() ^ a.clone()).handles(), vec![ma, mb]);
+    Ok(())
+}
+
+#[test]
+fn test_missing_measurement() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let (ra, _) = measure_value(&mut b, 1, 1)?;
+    let (_, unused) = measure_value(&mut b, 1, 1)?;
+    let (_, measured) = run_local::<f64>(&ra)?;
+    let cr = ClassicalRegister::from(&unused);
+    assert_eq!(measured.get_classical(&cr), None);
+    Ok(())
+}
+
+/// Correct a bit flip on any of three qubits encoding `|1>`, using the syndrome to pick which
+/// qubit to flip back.
+#[test]
+fn test_bit_flip_decoder() -> Result<(), CircuitError> {
+    (0..4).try_for_each(|error: u64| {
+        let mut b = OpBuilder::new();
+        // |111> with a flip on qubit `error - 1`, if any.
+        let flipped = if error > 0 { 1 << (error - 1) } else { 0 };
+        let code = prepare(&mut b, 3, 0b111 ^ flipped)?;
+        let mut qs = b.split_all(code);
+        let (q2, q1, q0) = (qs.pop().unwrap(), qs.pop().unwrap(), qs.pop().unwrap());
+
+        // Parities of (q0, q1) and (q1, q2) on two ancillas.
+        let sa = b.qubit();
+        let sb = b.qubit();
+        let (q0, sa) = b.cnot(q0, sa);
+        let (q1, sa) = b.cnot(q1, sa);
+        let (q1, sb) = b.cnot(q1, sb);
+        let (q2, sb) = b.cnot(q2, sb);
+        let (sa, ma) = b.measure(sa);
+        let (sb, mb) = b.measure(sb);
+        let syndrome = ClassicalRegister::from(&ma).concat(&ClassicalRegister::from(&mb));
+
+        let q0 = b.classical_register_if(q0, &syndrome.equals(0b01), Box::new(|b, q| Ok(b.x(q))));
+        let q1 = b.classical_register_if(q1, &syndrome.equals(0b11), Box::new(|b, q| Ok(b.x(q))));
+        let q2 = b.classical_register_if(q2, &syndrome.equals(0b10), Box::new(|b, q| Ok(b.x(q))));
+
+        let r = b.merge(vec![q0, q1, q2, sa, sb])?;
+        let (state, measured) = run_local::<f64>(&r)?;
+        let syndrome = measured.get_classical(&syndrome).unwrap();
+        let expected = [0b00, 0b01, 0b11, 0b10][error as usize];
+        assert_eq!(syndrome, expected);
+        let index = 0b111 | (syndrome << 3);
+        assert!((state.get_state(true)[index as usize].norm() - 1.0).abs() < 1e-10);
+        Ok(())
+    })
+}
@@ -0,0 +1,83 @@
+extern crate qip;
+
+use qip::clifford_t::*;
+use qip::stats::fidelity;
+use qip::*;
+use std::f64::consts::{FRAC_1_SQRT_2, FRAC_PI_2, FRAC_PI_4};
+
+#[test]
+fn test_exact_rotations() -> Result<(), CircuitError> {
+    assert_eq!(approximate_rz(0.0, 1e-9)?, vec![]);
+    assert_eq!(approximate_rz(FRAC_PI_4, 1e-9)?, vec![CliffordTGate::T]);
+    assert_eq!(approximate_rz(FRAC_PI_2, 1e-9)?, vec![CliffordTGate::S]);
+    assert_eq!(approximate_rz(-FRAC_PI_4, 1e-9)?, vec![CliffordTGate::Tdag]);
+    Ok(())
+}
+
+#[test]
+fn test_approximate_rz_precision() -> Result<(), CircuitError> {
+    for theta in [0.3, -1.1, 2.5].iter() {
+        let mut previous_len = 0;
+        for precision in [1e-1, 1e-2, 1e-3].iter() {
+            let gates = approximate_rz(*theta, *precision)?;
+            assert!(rz_distance(*theta, &gates) <= *precision);
+            assert!(gates.len() >= previous_len);
+            previous_len = gates.len();
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_rz_clifford_t_circuit() -> Result<(), CircuitError> {
+    let (theta, precision) = (0.7, 1e-3);
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let q = b.hadamard(q);
+    let q = b.rz(q, theta);
+    let (expected, _) = run_local::<f64>(&q)?;
+
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let q = b.hadamard(q);
+    let q = rz_clifford_t(&mut b, q, theta, precision)?;
+    let (state, _) = run_local::<f64>(&q)?;
+    assert!(fidelity(&expected, &state)? > 1.0 - precision * precision);
+    Ok(())
+}
+
+#[test]
+fn test_approximate_unitary() -> Result<(), CircuitError> {
+    let h = [
+        Complex::new(FRAC_1_SQRT_2, 0.0),
+        Complex::new(FRAC_1_SQRT_2, 0.0),
+        Complex::new(FRAC_1_SQRT_2, 0.0),
+        Complex::new(-FRAC_1_SQRT_2, 0.0),
+    ];
+    assert_eq!(approximate_unitary(&h, 1e-9)?, vec![CliffordTGate::H]);
+
+    // Rx(theta) up to a global phase.
+    let theta: f64 = 0.4;
+    let (c, s) = ((theta / 2.0).cos(), (theta / 2.0).sin());
+    let rx = [
+        Complex::new(0.0, c),
+        Complex::new(s, 0.0),
+        Complex::new(s, 0.0),
+        Complex::new(0.0, c),
+    ];
+    let gates = approximate_unitary(&rx, 1e-2)?;
+    // Rx is Rz conjugated by H.
+    let mut conjugated = vec![CliffordTGate::H];
+    conjugated.extend(approximate_rz(theta, 1e-4)?);
+    conjugated.push(CliffordTGate::H);
+    assert!(sequence_distance(&gates, &conjugated) <= 1e-2 + 1e-4);
+    Ok(())
+}
+
+#[test]
+fn test_approximate_errors() {
+    assert!(approximate_rz(0.3, 0.0).is_err());
+    let not_unitary = [Complex::new(1.0, 0.0); 4];
+    assert!(approximate_unitary(&not_unitary, 1e-2).is_err());
+    assert!(approximate_unitary(&[Complex::new(1.0, 0.0)], 1e-2).is_err());
+}
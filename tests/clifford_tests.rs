@@ -0,0 +1,136 @@
+extern crate qip;
+
+use qip::clifford::*;
+use qip::rng::with_seed;
+use qip::stabilizer_state::run_stabilizer;
+use qip::unitary_state::run_unitary_local;
+use qip::*;
+use std::collections::HashSet;
+
+fn unitary(c: &CliffordCircuit) -> Result<Vec<Complex<f64>>, CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(c.n())?;
+    let r = c.apply(&mut b, r)?;
+    let (u, _) = run_unitary_local::<f64>(&r)?;
+    Ok(u.get_unitary(true).into_iter().flatten().collect())
+}
+
+/// Describe the unitary of `c` with its global phase removed.
+fn unitary_key(c: &CliffordCircuit) -> Result<String, CircuitError> {
+    let u = unitary(c)?;
+    let first = *u.iter().find(|c| c.norm() > 1e-6).unwrap();
+    let phase = first.conj() / first.norm();
+    Ok(u.iter()
+        .map(|c| {
+            let c = c * phase;
+            format!("{:.3},{:.3};", c.re + 0.0, c.im + 0.0)
+        })
+        .collect())
+}
+
+#[test]
+fn test_random_clifford_inverse() -> Result<(), CircuitError> {
+    let clifford = with_seed(1, || CliffordCircuit::random(3));
+    let u = unitary(&clifford.then(&clifford.inverse())?)?;
+    u.iter().enumerate().for_each(|(i, c)| {
+        let expected = if i % 9 == 0 { 1.0 } else { 0.0 };
+        assert!((c - Complex::new(expected, 0.0)).norm() < 1e-10);
+    });
+    Ok(())
+}
+
+#[test]
+fn test_random_clifford_is_stabilizer() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(6)?;
+    let r = with_seed(2, || random_clifford(&mut b, r))?;
+    let (state, _) = run_stabilizer::<f64>(&r)?;
+    assert_eq!(state.get_stabilizers().len(), 6);
+    Ok(())
+}
+
+#[test]
+fn test_random_clifford_seeded() {
+    let a = with_seed(3, || CliffordCircuit::random(4));
+    let b = with_seed(3, || CliffordCircuit::random(4));
+    let c = with_seed(4, || CliffordCircuit::random(4));
+    assert_eq!(a, b);
+    assert_ne!(a, c);
+}
+
+#[test]
+fn test_random_clifford_covers_single_qubit_group() -> Result<(), CircuitError> {
+    // There are 24 single qubit Cliffords up to global phase.
+    let keys = with_seed(5, || {
+        (0..1000)
+            .map(|_| unitary_key(&CliffordCircuit::random(1)))
+            .collect::<Result<HashSet<_>, CircuitError>>()
+    })?;
+    assert_eq!(keys.len(), 24);
+    Ok(())
+}
+
+#[test]
+fn test_random_clifford_uniform_two_qubits() -> Result<(), CircuitError> {
+    // A uniform Clifford takes |00> to a uniformly random one of the 60 two qubit stabilizer
+    // states, so the probability of measuring |00> afterwards has a known distribution.
+    let samples = 6000;
+    let counts = with_seed(6, || -> Result<[usize; 4], CircuitError> {
+        let mut counts = [0; 4];
+        for _ in 0..samples {
+            let u = unitary(&CliffordCircuit::random(2))?;
+            // The first column is the state made from |00>.
+            let p = u[0].norm_sqr();
+            let bucket = [0.0, 0.25, 0.5, 1.0]
+                .iter()
+                .position(|x| (p - x).abs() < 1e-6)
+                .unwrap();
+            counts[bucket] += 1;
+        }
+        Ok(counts)
+    })?;
+    // Of the 60 stabilizer states 15 have no weight on |00>, 32 have a quarter, 12 a half and
+    // one has all of it.
+    let expected = [15.0, 32.0, 12.0, 1.0];
+    counts.iter().zip(expected.iter()).for_each(|(c, e)| {
+        let e = e / 60.0 * samples as f64;
+        assert!((*c as f64 - e).abs() < 4.0 * e.sqrt() + 1.0, "{:?}", counts);
+    });
+    Ok(())
+}
+
+#[test]
+fn test_clifford_circuit_new() -> Result<(), CircuitError> {
+    let c = CliffordCircuit::new(
+        2,
+        vec![
+            CliffordGate::H(0),
+            CliffordGate::CNOT(0, 1),
+            CliffordGate::S(1),
+        ],
+    )?;
+    assert_eq!(c.n(), 2);
+    assert_eq!(c.gates().len(), 3);
+    assert_eq!(
+        c.inverse().gates(),
+        &[
+            CliffordGate::Sdag(1),
+            CliffordGate::CNOT(0, 1),
+            CliffordGate::H(0)
+        ]
+    );
+    assert!(CliffordCircuit::new(2, vec![CliffordGate::X(2)]).is_err());
+    assert!(CliffordCircuit::new(2, vec![CliffordGate::CZ(1, 1)]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_clifford_circuit_errors() -> Result<(), CircuitError> {
+    let a = CliffordCircuit::random(2);
+    let b = CliffordCircuit::random(3);
+    assert!(a.then(&b).is_err());
+    let mut builder = OpBuilder::new();
+    let r = builder.register(3)?;
+    assert!(a.apply(&mut builder, r).is_err());
+    Ok(())
+}
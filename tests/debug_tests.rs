@@ -0,0 +1,33 @@
+extern crate qip;
+
+use qip::pipeline_debug::DebugLog;
+use qip::*;
+
+#[test]
+fn test_debug_record_order_and_values() -> Result<(), CircuitError> {
+    let log = DebugLog::new();
+    let mut b = OpBuilder::new();
+    let r = b.register(2)?;
+    let r = b.not(r);
+    let r = b.debug_record(r, "ones", &log)?;
+    let (ra, rb) = b.split(r, &[0])?;
+    let ra = b.hadamard(ra);
+    let ra = b.debug_record(ra, "plus", &log)?;
+    let rb = b.debug_print(rb.unwrap(), "one")?;
+    let r = b.merge(vec![ra, rb])?;
+
+    let (state, _) = run_local::<f64>(&r)?;
+    let labels: Vec<_> = log.entries().into_iter().map(|(l, _)| l).collect();
+    assert_eq!(labels, vec!["ones", "plus"]);
+    assert_eq!(log.get("ones"), vec![vec![0.0, 0.0, 0.0, 1.0]]);
+    let plus = &log.get("plus")[0];
+    assert!((plus[0] - 0.5).abs() < 1e-10 && (plus[1] - 0.5).abs() < 1e-10);
+
+    // Recording doesn't change the state.
+    assert!((state.probability_of(&[1], 1) - 1.0).abs() < 1e-10);
+    assert!((state.probability_of(&[0], 1) - 0.5).abs() < 1e-10);
+
+    log.clear();
+    assert!(log.entries().is_empty());
+    Ok(())
+}
@@ -41,3 +41,29 @@ fn test_coding() -> Result<(), CircuitError> {
 
     Ok(())
 }
+
+#[test]
+fn test_superdense_helpers() -> Result<(), CircuitError> {
+    for bits in 0..4 {
+        let (bit_a, bit_b) = (bits & 2 == 2, bits & 1 == 1);
+        let mut b = OpBuilder::new();
+        let (epr_alice, epr_bob) = epr_pair(&mut b, 1);
+        let r_alice = superdense_encode(&mut b, epr_alice, bit_a, bit_b)?;
+        let (r, m) = superdense_decode(&mut b, r_alice, epr_bob)?;
+
+        let (_, measurements) = run_local::<f64>(&r)?;
+        let (m, p) = measurements.get_measurement(&m).unwrap();
+        assert_eq!(m, bits);
+        assert!((p - 1.0).abs() < 1e-10);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_superdense_errors() {
+    let mut b = OpBuilder::new();
+    let (epr_alice, epr_bob) = epr_pair(&mut b, 2);
+    assert!(superdense_encode(&mut b, epr_alice, true, true).is_err());
+    let q = b.qubit();
+    assert!(superdense_decode(&mut b, q, epr_bob).is_err());
+}
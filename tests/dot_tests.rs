@@ -0,0 +1,36 @@
+extern crate qip;
+
+use qip::*;
+
+#[test]
+fn test_dot_ops_and_splits() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.hadamard(q);
+    let qr = b.merge(vec![q, r])?;
+    let (q, r) = b.split(qr, &[0])?;
+    let qr = b.merge(vec![q, r.unwrap()])?;
+
+    let dot = to_dot(&qr);
+    assert!(dot.starts_with("digraph circuit {\n"));
+    assert!(dot.ends_with("}\n"));
+    assert_eq!(dot.matches("[label=\"H\"];").count(), 1);
+    assert_eq!(dot.matches(": [0, 1]\"];").count(), 2);
+    assert_eq!(dot.matches("[style=dashed];").count(), 2);
+    Ok(())
+}
+
+#[test]
+fn test_dot_measurement_deps() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let (q, m) = b.measure(q);
+    let r = b.single_register_classical_sidechannel(r, &[m], Box::new(|b, r, _| Ok(b.not(r))));
+    let qr = b.merge(vec![q, r])?;
+
+    let dot = to_dot(&qr);
+    assert_eq!(dot.matches("[style=dotted];").count(), 1);
+    Ok(())
+}
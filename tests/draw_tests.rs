@@ -0,0 +1,48 @@
+extern crate qip;
+
+use qip::*;
+
+#[test]
+fn test_draw_single_qubit_gates() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.hadamard(q);
+    let r = b.x(r);
+    let r = b.y(r);
+    let qr = b.merge(vec![q, r])?;
+
+    let diagram = draw(&qr)?;
+    assert_eq!(diagram, "q0: -[H]---------\nq1: -----[X]-[Y]-\n");
+    Ok(())
+}
+
+#[test]
+fn test_draw_control_crosses_wires() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let m = b.qubit();
+    let r = b.qubit();
+    let (q, r) = b.cy(q, r);
+    let (q, m) = b.swap(q, m)?;
+    let qmr = b.merge(vec![q, m, r])?;
+
+    let diagram = draw(&qmr)?;
+    assert_eq!(diagram, "q0: --*--x-\nq1: --|--x-\nq2: -[Y]---\n");
+    Ok(())
+}
+
+#[test]
+fn test_draw_scoped_and_measured() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    b.push_name_scope("scope");
+    let q = b.z(q);
+    b.pop_name_scope();
+    let (q, _) = b.measure(q);
+    let q = b.reset(q);
+
+    let diagram = draw(&q)?;
+    assert_eq!(diagram, "q0: -[Z]-[M]-[reset]-\n");
+    Ok(())
+}
@@ -0,0 +1,52 @@
+extern crate qip;
+
+use qip::qasm::to_qasm;
+use qip::*;
+
+fn measure_with(endianness: Endianness) -> Result<(u64, Vec<f64>), CircuitError> {
+    let mut b = OpBuilder::new();
+    b.set_endianness(endianness);
+    let q = b.qubit();
+    let r = b.register(2)?;
+    let q = b.not(q);
+    let qr = b.merge(vec![q, r])?;
+    let (qr, id) = b.stochastic_measure(qr);
+    let (qr, m) = b.measure(qr);
+    let (_, measured) = run_local::<f64>(&qr)?;
+    let probs = measured.clone_stochastic_measurements(id).unwrap();
+    Ok((measured.get_measurement(&m).unwrap().0, probs))
+}
+
+#[test]
+fn test_default_is_little_endian() -> Result<(), CircuitError> {
+    assert_eq!(OpBuilder::new().endianness(), Endianness::Little);
+    let (m, probs) = measure_with(Endianness::Little)?;
+    assert_eq!(m, 0b001);
+    assert_eq!(probs[0b001], 1.0);
+    Ok(())
+}
+
+#[test]
+fn test_big_endian_measurements() -> Result<(), CircuitError> {
+    let (m, probs) = measure_with(Endianness::Big)?;
+    assert_eq!(m, 0b100);
+    assert_eq!(probs[0b100], 1.0);
+    Ok(())
+}
+
+#[test]
+fn test_qubit_order() {
+    assert_eq!(Endianness::Little.qubit_order(&[3, 1, 2]), vec![3, 1, 2]);
+    assert_eq!(Endianness::Big.qubit_order(&[3, 1, 2]), vec![2, 1, 3]);
+}
+
+#[test]
+fn test_qasm_measurement_order() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    b.set_endianness(Endianness::Big);
+    let r = b.register(2)?;
+    let (r, _) = b.measure(r);
+    let program = to_qasm(&r)?;
+    assert!(program.contains("measure q[1] -> m1[0];\nmeasure q[0] -> m1[1];"));
+    Ok(())
+}
@@ -0,0 +1,96 @@
+extern crate qip;
+
+use qip::equivalence::{assert_circuits_equivalent, circuits_equivalent, MAX_UNITARY_QUBITS};
+use qip::rng::with_seed;
+use qip::*;
+
+/// A layer of rotations followed by a ladder of cnots, either directly or with each cnot written
+/// as `H CZ H` and an extra global phase.
+fn make_circuit(b: &mut OpBuilder, n: u64, rewrite: bool) -> Result<Register, CircuitError> {
+    let r = b.register(n)?;
+    let mut qs: Vec<Register> = b
+        .split_all(r)
+        .into_iter()
+        .enumerate()
+        .map(|(i, q)| b.ry(q, 0.3 + 0.1 * i as f64))
+        .collect();
+    for i in 0..(n as usize - 1) {
+        let c = qs.remove(i);
+        let t = qs.remove(i);
+        let (c, t) = if rewrite {
+            let t = b.hadamard(t);
+            let (c, t) = b.cz(c, t);
+            let t = b.hadamard(t);
+            let t = b.rz(t, 0.0);
+            (c, t)
+        } else {
+            b.cnot(c, t)
+        };
+        qs.insert(i, t);
+        qs.insert(i, c);
+    }
+    let r = b.merge(qs)?;
+    if rewrite {
+        Ok(b.phase(r, 0.7))
+    } else {
+        Ok(r)
+    }
+}
+
+#[test]
+fn test_equivalent_small() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let a = make_circuit(&mut b, 3, false)?;
+    let mut b = OpBuilder::new();
+    let c = make_circuit(&mut b, 3, true)?;
+    assert_circuits_equivalent(&a, &c, 1e-10);
+    Ok(())
+}
+
+#[test]
+fn test_equivalent_large() -> Result<(), CircuitError> {
+    let n = MAX_UNITARY_QUBITS + 2;
+    let mut b = OpBuilder::new();
+    let a = make_circuit(&mut b, n, false)?;
+    let mut b = OpBuilder::new();
+    let c = make_circuit(&mut b, n, true)?;
+    with_seed(3, || assert_circuits_equivalent(&a, &c, 1e-8));
+    Ok(())
+}
+
+#[test]
+fn test_not_equivalent() -> Result<(), CircuitError> {
+    for n in &[2, MAX_UNITARY_QUBITS + 1] {
+        let mut b = OpBuilder::new();
+        let a = make_circuit(&mut b, *n, false)?;
+        let mut b = OpBuilder::new();
+        let c = make_circuit(&mut b, *n, false)?;
+        let (q, c) = b.split(c, &[0])?;
+        // A relative phase is not a global phase.
+        let q = b.rz(q, 0.5);
+        let c = b.merge(vec![q, c.unwrap()])?;
+        assert!(!with_seed(5, || circuits_equivalent(&a, &c, 1e-6))?);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_equivalent_size_mismatch() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let a = b.register(2)?;
+    let c = b.register(3)?;
+    assert!(circuits_equivalent(&a, &c, 1e-10).is_err());
+    Ok(())
+}
+
+#[test]
+#[should_panic]
+fn test_assert_not_equivalent() {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let a = b.x(q);
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let c = b.z(q);
+    assert_circuits_equivalent(&a, &c, 1e-10);
+}
@@ -0,0 +1,117 @@
+extern crate num;
+extern crate qip;
+
+use qip::*;
+
+fn assert_almost_eq(a: f64, b: f64, prec: i32) {
+    let mult = 10.0f64.powi(prec);
+    let (a, b) = (a * mult, b * mult);
+    let (a, b) = (a.round(), b.round());
+    assert_eq!(a / mult, b / mult);
+}
+
+fn assert_state_almost_eq(a: &[Complex<f64>], b: &[Complex<f64>]) {
+    assert_eq!(a.len(), b.len());
+    a.iter().zip(b.iter()).for_each(|(a, b)| {
+        assert_almost_eq(a.re, b.re, 10);
+        assert_almost_eq(a.im, b.im, 10);
+    });
+}
+
+fn prepare(b: &mut OpBuilder, n: u64) -> Result<Register, CircuitError> {
+    let r = b.register(n)?;
+    let qs = b.split_all(r);
+    let qs = qs
+        .into_iter()
+        .enumerate()
+        .map(|(i, q)| {
+            let q = b.ry(q, 0.3 + 0.4 * i as f64);
+            b.rz(q, 0.7 - 0.2 * i as f64)
+        })
+        .collect();
+    b.merge(qs)
+}
+
+fn apply_pauli(b: &mut OpBuilder, r: Register, pauli: &str) -> Result<Register, CircuitError> {
+    let qs = b.split_all(r);
+    let qs = qs
+        .into_iter()
+        .zip(pauli.chars().chain(std::iter::repeat('I')))
+        .map(|(q, c)| match c {
+            'X' => b.x(q),
+            'Y' => b.y(q),
+            'Z' => b.z(q),
+            _ => q,
+        })
+        .collect();
+    b.merge(qs)
+}
+
+fn expected_state(n: u64, pauli: &str, theta: f64) -> Result<Vec<Complex<f64>>, CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = prepare(&mut b, n)?;
+    let (psi, _) = run_local::<f64>(&r)?;
+    let mut b = OpBuilder::new();
+    let r = prepare(&mut b, n)?;
+    let r = apply_pauli(&mut b, r, pauli)?;
+    let (p_psi, _) = run_local::<f64>(&r)?;
+    let (c, s) = ((theta / 2.0).cos(), (theta / 2.0).sin());
+    Ok(psi
+        .get_state(true)
+        .into_iter()
+        .zip(p_psi.get_state(true))
+        .map(|(a, pa)| a * c - Complex::new(0.0, s) * pa)
+        .collect())
+}
+
+#[test]
+fn test_exp_pauli_strings() -> Result<(), CircuitError> {
+    let theta = 0.83;
+    for pauli in &["X", "Y", "Z", "XYZ", "IYI", "ZIX", "YY", "III", "XXXX"] {
+        let n = pauli.len().max(3) as u64;
+        let mut b = OpBuilder::new();
+        let r = prepare(&mut b, n)?;
+        let r = b.exp_pauli(r, pauli, theta)?;
+        let (state, _) = run_local::<f64>(&r)?;
+        assert_state_almost_eq(&state.get_state(true), &expected_state(n, pauli, theta)?);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_exp_pauli_controlled() -> Result<(), CircuitError> {
+    let theta = 1.3;
+    for pauli in &["XZ", "II"] {
+        // With the control off nothing happens, with it on the rotation is applied, including its
+        // global phase.
+        for control in &[false, true] {
+            let mut b = OpBuilder::new();
+            let c = b.qubit();
+            let c = if *control { b.not(c) } else { c };
+            let r = prepare(&mut b, 2)?;
+            let (c, r) = try_condition(&mut b, c, r, |b, r| b.exp_pauli(r, pauli, theta))?;
+            let r = b.merge(vec![r, c])?;
+            let (state, _) = run_local::<f64>(&r)?;
+            let state = state.get_state(true);
+            let expected = if *control {
+                expected_state(2, pauli, theta)?
+            } else {
+                expected_state(2, "", 0.0)?
+            };
+            // The control was allocated first so it is the least significant qubit.
+            let offset = if *control { 1 } else { 0 };
+            let state: Vec<_> = state.into_iter().skip(offset).step_by(2).collect();
+            assert_state_almost_eq(&state, &expected);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_exp_pauli_errors() {
+    let mut b = OpBuilder::new();
+    let r = b.register(2).unwrap();
+    assert!(b.exp_pauli(r, "XYZ", 1.0).is_err());
+    let r = b.register(2).unwrap();
+    assert!(b.exp_pauli(r, "XA", 1.0).is_err());
+}
@@ -0,0 +1,98 @@
+extern crate qip;
+
+use qip::fusion::{fuse_modifiers, FusedModifier};
+use qip::pipeline::{get_opfns_and_frontier, run_local_fused};
+use qip::*;
+
+/// Layers of rotations with cnots between neighbouring qubits.
+fn make_circuit(b: &mut OpBuilder, n: u64, layers: usize) -> Result<Register, CircuitError> {
+    let r = b.register(n)?;
+    let mut qs = b.split_all(r);
+    for layer in 0..layers {
+        qs = qs
+            .into_iter()
+            .enumerate()
+            .map(|(i, q)| {
+                let q = b.hadamard(q);
+                let q = b.rz(q, 0.1 * (i + layer) as f64);
+                b.ry(q, 0.3)
+            })
+            .collect();
+        for i in (layer % 2..qs.len() - 1).step_by(2) {
+            let c = qs.remove(i);
+            let t = qs.remove(i);
+            let (c, t) = b.cnot(c, t);
+            qs.insert(i, t);
+            qs.insert(i, c);
+        }
+    }
+    b.merge(qs)
+}
+
+fn assert_states_close(a: &[Complex<f64>], b: &[Complex<f64>]) {
+    assert_eq!(a.len(), b.len());
+    a.iter()
+        .zip(b.iter())
+        .for_each(|(a, b)| assert!((a - b).norm() < 1e-10));
+}
+
+#[test]
+fn test_fused_matches_unfused() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = make_circuit(&mut b, 5, 6)?;
+    let (fused, _) = run_local_fused::<f64>(&r)?;
+    let (expected, _) = run_local::<f64>(&r)?;
+    assert_states_close(&fused.get_state(true), &expected.get_state(true));
+    Ok(())
+}
+
+#[test]
+fn test_fuse_single_qubit_runs() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.hadamard(q);
+    let r = b.not(r);
+    let q = b.rz(q, 0.4);
+    let r = b.merge(vec![q, r])?;
+    // A three qubit op can't be fused.
+    let s = b.qubit();
+    let (r, s) = b.cnot(r, s);
+    let r = b.merge(vec![r, s])?;
+
+    let (_, ops) = get_opfns_and_frontier(&r);
+    let fused = fuse_modifiers(&ops);
+    let names: Vec<&str> = fused.iter().map(|m| m.modifier().name.as_str()).collect();
+    // Runs on separate qubits are kept apart.
+    assert_eq!(names, vec!["not", "Fused(H, Rz)", "C(not)"]);
+    match &fused[2] {
+        FusedModifier::Original(_) => {}
+        FusedModifier::Fused(_) => panic!("Expected the original op"),
+    }
+    Ok(())
+}
+
+#[test]
+fn test_fusion_stops_at_measurement() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.hadamard(q);
+    let (q, m) = b.measure(q);
+    let q = b.hadamard(q);
+    let r = b.hadamard(r);
+    let (q, r) = b.cnot(q, r);
+    let r = b.merge(vec![q, r])?;
+
+    let (_, ops) = get_opfns_and_frontier(&r);
+    let fused = fuse_modifiers(&ops);
+    let names: Vec<&str> = fused.iter().map(|m| m.modifier().name.as_str()).collect();
+    assert_eq!(names, vec!["H", "measure", "Fused(H, H, C(not))"]);
+
+    let (state, measured) = run_local_fused::<f64>(&r)?;
+    let (value, _) = measured.get_measurement(&m).unwrap();
+    let p = state.peek_probabilities(&[0]);
+    assert!(p[0] > 0.0 && p[1] > 0.0);
+    assert!(value < 2);
+    Ok(())
+}
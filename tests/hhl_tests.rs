@@ -0,0 +1,115 @@
+extern crate qip;
+
+use qip::hhl::*;
+use qip::*;
+use std::f64::consts::PI;
+
+fn reals(xs: &[f64]) -> Vec<Complex<f64>> {
+    xs.iter().map(|x| Complex::new(*x, 0.0)).collect()
+}
+
+/// Check that `x` solves `A x = b`.
+fn assert_solves(matrix: &[Complex<f64>], x: &[Complex<f64>], vector: &[Complex<f64>]) {
+    let d = vector.len();
+    (0..d).for_each(|i| {
+        let ax: Complex<f64> = (0..d).map(|j| matrix[i * d + j] * x[j]).sum();
+        assert!(
+            (ax - vector[i]).norm() < 1e-6,
+            "{:?} != {:?}",
+            ax,
+            vector[i]
+        );
+    });
+}
+
+#[test]
+fn test_solve_two_by_two() -> Result<(), CircuitError> {
+    // Eigenvalues 1 and 2.
+    let matrix = reals(&[1.5, 0.5, 0.5, 1.5]);
+    let vector = reals(&[1.0, 0.0]);
+    let result = solve_linear_system(&matrix, &vector, 3, PI / 4.0)?;
+    assert_solves(&matrix, &result.solution, &vector);
+    // |b> is an even mix of the eigenvectors, each kept with probability (1/l)^2.
+    assert!((result.success_probability - 0.625).abs() < 1e-6);
+
+    let vector = reals(&[0.6, -0.8]);
+    let result = solve_linear_system(&matrix, &vector, 3, PI / 4.0)?;
+    assert_solves(&matrix, &result.solution, &vector);
+    Ok(())
+}
+
+#[test]
+fn test_solve_complex() -> Result<(), CircuitError> {
+    // Hermitian with eigenvalues 1 and 2.
+    let matrix = vec![
+        Complex::new(1.5, 0.0),
+        Complex::new(0.0, 0.5),
+        Complex::new(0.0, -0.5),
+        Complex::new(1.5, 0.0),
+    ];
+    let vector = vec![Complex::new(0.0, 1.0), Complex::new(2.0, 0.0)];
+    let result = solve_linear_system(&matrix, &vector, 3, PI / 4.0)?;
+    assert_solves(&matrix, &result.solution, &vector);
+    Ok(())
+}
+
+#[test]
+fn test_solve_negative_eigenvalues() -> Result<(), CircuitError> {
+    // (H x H) diag(1, 2, 3, -1) (H x H), with the eigenvalues all exact on 3 clock qubits.
+    let h = [
+        [1.0, 1.0, 1.0, 1.0],
+        [1.0, -1.0, 1.0, -1.0],
+        [1.0, 1.0, -1.0, -1.0],
+        [1.0, -1.0, -1.0, 1.0],
+    ];
+    let eigenvalues = [1.0, 2.0, 3.0, -1.0];
+    let matrix: Vec<f64> = (0..16)
+        .map(|index| {
+            let (i, j) = (index / 4, index % 4);
+            (0..4)
+                .map(|k| h[i][k] * eigenvalues[k] * h[k][j] / 4.0)
+                .sum()
+        })
+        .collect();
+    let matrix = reals(&matrix);
+    let vector = reals(&[1.0, 2.0, -1.0, 0.5]);
+    let result = solve_linear_system(&matrix, &vector, 3, PI / 4.0)?;
+    assert_solves(&matrix, &result.solution, &vector);
+    Ok(())
+}
+
+#[test]
+fn test_solve_approximate() -> Result<(), CircuitError> {
+    // Eigenvalues 1 and 1.9 are not exact on the clock, more qubits give a closer answer.
+    let matrix = reals(&[1.45, 0.45, 0.45, 1.45]);
+    let vector = reals(&[1.0, 0.0]);
+    let exact = reals(&[1.45 / 1.9, -0.45 / 1.9]);
+    let error = |precision: u64| -> Result<f64, CircuitError> {
+        let time = 2.0 * PI / (1 << precision) as f64;
+        let result = solve_linear_system(&matrix, &vector, precision, time)?;
+        let x = result.solution;
+        // Compare directions, the scale suffers from the clock not being uncomputed.
+        let norm = x.iter().map(|a| a.norm_sqr()).sum::<f64>().sqrt();
+        let exact_norm = exact.iter().map(|a| a.norm_sqr()).sum::<f64>().sqrt();
+        Ok(x.iter()
+            .zip(exact.iter())
+            .map(|(a, e)| (a / norm - e / exact_norm).norm())
+            .sum())
+    };
+    assert!(error(5)? < error(2)?);
+    assert!(error(5)? < 0.1);
+    Ok(())
+}
+
+#[test]
+fn test_solve_errors() {
+    let matrix = reals(&[1.5, 0.5, 0.5, 1.5]);
+    let vector = reals(&[1.0, 0.0]);
+    assert!(solve_linear_system(&matrix, &vector, 0, 1.0).is_err());
+    assert!(solve_linear_system(&matrix, &vector, 3, 0.0).is_err());
+    assert!(solve_linear_system(&matrix, &reals(&[1.0]), 3, 1.0).is_err());
+    assert!(solve_linear_system(&matrix, &reals(&[1.0, 0.0, 0.0]), 3, 1.0).is_err());
+    assert!(solve_linear_system(&matrix, &reals(&[0.0, 0.0]), 3, 1.0).is_err());
+    assert!(solve_linear_system(&reals(&[1.0, 0.5, 0.0, 1.0]), &vector, 3, 1.0).is_err());
+    assert!(solve_linear_system(&reals(&[1.0, 0.0]), &vector, 3, 1.0).is_err());
+}
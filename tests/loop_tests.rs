@@ -0,0 +1,191 @@
+extern crate qip;
+
+use qip::circuit_stats::CircuitStats;
+use qip::equivalence::assert_circuits_equivalent;
+use qip::parameters::{get_parameters, gradient, Parameter};
+use qip::qasm::{parse_qasm, to_qasm};
+use qip::*;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+/// Phase of `theta` on `|1>`.
+fn phase_matrix(theta: f64) -> Vec<Complex<f64>> {
+    let one = Complex::new(1.0, 0.0);
+    let zero = Complex::new(0.0, 0.0);
+    vec![one, zero, zero, Complex::from_polar(&1.0, &theta)]
+}
+
+/// Apply a controlled phase of `pi / 2^k` and an `Ry(0.1 k)` to `rs`.
+fn schedule_body(
+    b: &mut dyn UnitaryBuilder,
+    k: &Parameter,
+    mut rs: Vec<Register>,
+) -> Result<Vec<Register>, CircuitError> {
+    let t = rs.pop().unwrap();
+    let c = rs.pop().unwrap();
+    let mut cb = b.with_condition(c);
+    let f = Box::new(|k: f64| phase_matrix(PI / 2f64.powf(k)));
+    let t = cb.parameterized_mat("R", t, k, f)?;
+    let c = cb.release_register();
+    let c = b.parameterized_mat("Ry", c, k, Box::new(|k| ry_matrix(0.1 * k)))?;
+    Ok(vec![c, t])
+}
+
+fn ry_matrix(theta: f64) -> Vec<Complex<f64>> {
+    let (c, s) = ((theta / 2.0).cos(), (theta / 2.0).sin());
+    [c, -s, s, c]
+        .iter()
+        .map(|x| Complex::new(*x, 0.0))
+        .collect()
+}
+
+/// The same circuit as a loop of `schedule_body` over `1..4`, written out by hand.
+fn schedule_unrolled() -> Result<Register, CircuitError> {
+    let mut b = OpBuilder::new();
+    let c = b.qubit();
+    let t = b.qubit();
+    let (c, t) = (b.hadamard(c), b.hadamard(t));
+    let (c, t) = (1..4).try_fold((c, t), |(c, t), k| {
+        let k = f64::from(k);
+        let (c, t) = b.cphase(c, t, PI / 2f64.powf(k));
+        let c = b.ry(c, 0.1 * k);
+        Ok::<_, CircuitError>((c, t))
+    })?;
+    b.merge(vec![c, t])
+}
+
+fn schedule_loop() -> Result<(OpBuilder, Register), CircuitError> {
+    let k = Parameter::new("k");
+    let mut b = OpBuilder::new();
+    let c = b.qubit();
+    let t = b.qubit();
+    let (c, t) = (b.hadamard(c), b.hadamard(t));
+    let rs = b.for_loop(&k, 1..4, vec![c, t], |b, rs| schedule_body(b, &k, rs))?;
+    let r = b.merge(rs)?;
+    Ok((b, r))
+}
+
+#[test]
+fn test_loop_matches_unrolled() -> Result<(), CircuitError> {
+    let (_, r) = schedule_loop()?;
+    assert_circuits_equivalent(&r, &schedule_unrolled()?, 1e-10);
+    assert!(get_parameters(&r).is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_stats_count_iterations() -> Result<(), CircuitError> {
+    let (_, r) = schedule_loop()?;
+    // The two hadamards and two ops for each of three iterations.
+    let stats = CircuitStats::new(&r);
+    let unrolled = CircuitStats::new(&schedule_unrolled()?);
+    assert_eq!(stats.gate_counts.values().sum::<usize>(), 2 + 6);
+    assert_eq!(stats.depth, unrolled.depth);
+    Ok(())
+}
+
+#[test]
+fn test_nested_loops() -> Result<(), CircuitError> {
+    let (j, k) = (Parameter::new("j"), Parameter::new("k"));
+    let mut b = OpBuilder::new();
+    let r = b.qubit();
+    let mut rs = b.for_loop(&j, 0..2, vec![r], |b, rs| {
+        let mut rs = b.for_loop(&k, 0..3, rs, |b, mut rs| {
+            let r = rs.pop().unwrap();
+            let r = b.rx_param(r, &j)?;
+            Ok(vec![b.rz_param(r, &k)?])
+        })?;
+        Ok(vec![b.hadamard(rs.pop().unwrap())])
+    })?;
+    let looped = rs.pop().unwrap();
+
+    let mut b = OpBuilder::new();
+    let r = b.qubit();
+    let r = (0..2).fold(r, |r, j| {
+        let r = (0..3).fold(r, |r, k| {
+            let r = b.rx(r, f64::from(j));
+            b.rz(r, f64::from(k))
+        });
+        b.hadamard(r)
+    });
+    assert_circuits_equivalent(&looped, &r, 1e-10);
+    Ok(())
+}
+
+#[test]
+fn test_free_parameters() -> Result<(), CircuitError> {
+    let (k, theta) = (Parameter::new("k"), Parameter::new("theta"));
+    let mut b = OpBuilder::new();
+    let r = b.qubit();
+    let mut rs = b.for_loop(&k, 0..3, vec![r], |b, mut rs| {
+        let r = rs.pop().unwrap();
+        let r = b.ry_param(r, &theta)?;
+        Ok(vec![b.rz_param(r, &k)?])
+    })?;
+    let r = rs.pop().unwrap();
+    assert_eq!(get_parameters(&r), vec![theta.clone()]);
+
+    let mut params = HashMap::new();
+    params.insert("theta".to_string(), 0.4);
+    let (state, _) = run_local_with_parameters::<f64>(&r, &params)?;
+
+    let mut b = OpBuilder::new();
+    let s = b.qubit();
+    let s = (0..3).fold(s, |s, k| {
+        let s = b.ry(s, 0.4);
+        b.rz(s, f64::from(k))
+    });
+    let (expected, _) = run_local::<f64>(&s)?;
+    let (state, expected) = (state.get_state(true), expected.get_state(true));
+    (0..2).for_each(|i| assert!((state[i] - expected[i]).norm() < 1e-10));
+
+    // Parameters must be bound, and can't be differentiated inside the loop.
+    assert!(run_local::<f64>(&r).is_err());
+    assert!(gradient(&r, &[(1.0, "Z")], &params).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_empty_range() -> Result<(), CircuitError> {
+    let k = Parameter::new("k");
+    let mut b = OpBuilder::new();
+    let r = b.qubit();
+    let mut rs = b.for_loop(&k, 2..2, vec![r], |b, mut rs| {
+        Ok(vec![b.x(rs.pop().unwrap())])
+    })?;
+    let (state, _) = run_local::<f64>(&rs.pop().unwrap())?;
+    assert_eq!(state.get_state(true)[0].re, 1.0);
+    Ok(())
+}
+
+#[test]
+fn test_qasm_unrolls() -> Result<(), CircuitError> {
+    let (_, r) = schedule_loop()?;
+    let source = to_qasm(&r)?;
+    let mut b = OpBuilder::new();
+    let circuit = parse_qasm(&mut b, &source)?;
+    let parsed = circuit.merge_registers(&mut b)?;
+    assert_circuits_equivalent(&r, &parsed, 1e-8);
+    Ok(())
+}
+
+#[test]
+fn test_loop_errors() -> Result<(), CircuitError> {
+    let k = Parameter::new("k");
+    let mut b = OpBuilder::new();
+    let r = b.qubit();
+    assert!(b
+        .for_loop(&k, 0..2, vec![r], |b, mut rs| {
+            let (r, _) = b.measure(rs.pop().unwrap());
+            Ok(vec![r])
+        })
+        .is_err());
+    let r = b.qubit();
+    assert!(b
+        .for_loop(&k, 0..2, vec![r], |b, mut rs| {
+            let q = b.qubit();
+            Ok(vec![b.merge(vec![rs.pop().unwrap(), q])?])
+        })
+        .is_err());
+    Ok(())
+}
@@ -0,0 +1,192 @@
+extern crate qip;
+
+use qip::circuit_stats::CircuitStats;
+use qip::equivalence::assert_circuits_equivalent;
+use qip::qasm::{parse_qasm, to_qasm};
+use qip::*;
+
+fn bell(b: &mut OpBuilder, ra: Register, rb: Register) -> (Register, Register) {
+    let ra = b.hadamard(ra);
+    b.cnot(ra, rb)
+}
+
+fn define_bell(b: &mut OpBuilder) -> Result<(), CircuitError> {
+    b.define_gate("bell", &[1, 1], |b, mut rs| {
+        let rb = rs.pop().unwrap();
+        let (ra, rb) = bell(b, rs.pop().unwrap(), rb);
+        Ok(vec![ra, rb])
+    })
+}
+
+/// Apply `bell` to `(q2, q0)` and then `(q1, q2)`, using the named gate if `named`.
+fn bell_circuit(named: bool) -> Result<Register, CircuitError> {
+    let mut b = OpBuilder::new();
+    define_bell(&mut b)?;
+    let r = b.register(3)?;
+    let mut rs = b.split_all(r);
+    let (q2, q1, q0) = (rs.pop().unwrap(), rs.pop().unwrap(), rs.pop().unwrap());
+    let (q2, q0) = if named {
+        let mut rs = b.apply_gate("bell", vec![q2, q0])?;
+        let q0 = rs.pop().unwrap();
+        (rs.pop().unwrap(), q0)
+    } else {
+        bell(&mut b, q2, q0)
+    };
+    let (q1, q2) = if named {
+        let mut rs = b.apply_gate("bell", vec![q1, q2])?;
+        let q2 = rs.pop().unwrap();
+        (rs.pop().unwrap(), q2)
+    } else {
+        bell(&mut b, q1, q2)
+    };
+    b.merge(vec![q0, q1, q2])
+}
+
+#[test]
+fn test_apply_matches_definition() -> Result<(), CircuitError> {
+    assert_circuits_equivalent(&bell_circuit(true)?, &bell_circuit(false)?, 1e-10);
+    Ok(())
+}
+
+#[test]
+fn test_stats_expand_gates() -> Result<(), CircuitError> {
+    let named = CircuitStats::new(&bell_circuit(true)?);
+    let direct = CircuitStats::new(&bell_circuit(false)?);
+    assert_eq!(named.gate_counts, direct.gate_counts);
+    assert_eq!(named.depth, direct.depth);
+    Ok(())
+}
+
+#[test]
+fn test_nested_gates() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    define_bell(&mut b)?;
+    // Two bell pairs on a register of 4 qubits.
+    b.define_gate("bell_pairs", &[4], |b, mut rs| {
+        let r = rs.pop().unwrap();
+        let (ra, rb) = b.split(r, &[0, 1])?;
+        let ra = b.split_all(ra);
+        let rb = b.split_all(rb.unwrap());
+        let ra = b.apply_gate("bell", ra)?;
+        let rb = b.apply_gate("bell", rb)?;
+        let qs = ra.into_iter().chain(rb).collect();
+        Ok(vec![b.merge(qs)?])
+    })?;
+    let r = b.register(4)?;
+    let r = b.apply_gate("bell_pairs", vec![r])?.pop().unwrap();
+    let (state, _) = run_local::<f64>(&r)?;
+    let state = state.get_state(true);
+    [0b0000, 0b0011, 0b1100, 0b1111].iter().for_each(|i| {
+        assert!((state[*i].re - 0.5).abs() < 1e-10);
+    });
+    Ok(())
+}
+
+#[test]
+fn test_define_gate_errors() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    define_bell(&mut b)?;
+    assert!(define_bell(&mut b).is_err());
+    let identity = |_: &mut OpBuilder, rs: Vec<Register>| Ok(rs);
+    assert!(b.define_gate("Gate", &[1], identity).is_err());
+    assert!(b.define_gate("my-gate", &[1], identity).is_err());
+    assert!(b.define_gate("cx", &[1, 1], identity).is_err());
+    assert!(b.define_gate("empty", &[], identity).is_err());
+    assert!(b
+        .define_gate("alloc", &[1], |b, mut rs| {
+            let q = b.qubit();
+            let r = b.merge(vec![rs.pop().unwrap(), q])?;
+            Ok(vec![r])
+        })
+        .is_err());
+    assert!(b
+        .define_gate("dropped", &[2], |b, mut rs| {
+            let (r, _) = b.split(rs.pop().unwrap(), &[0])?;
+            Ok(vec![r])
+        })
+        .is_err());
+    assert!(b
+        .define_gate("measured", &[1], |b, mut rs| {
+            let (r, _) = b.measure(rs.pop().unwrap());
+            Ok(vec![r])
+        })
+        .is_err());
+    Ok(())
+}
+
+#[test]
+fn test_apply_gate_errors() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    define_bell(&mut b)?;
+    let r = b.register(2)?;
+    assert!(b.apply_gate("bell", vec![r]).is_err());
+    let ra = b.qubit();
+    let rb = b.qubit();
+    assert!(b.apply_gate("missing", vec![ra, rb]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_qasm_round_trip() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    define_bell(&mut b)?;
+    b.define_gate("rotations", &[2], |b, mut rs| {
+        let r = rs.pop().unwrap();
+        let (ra, rb) = b.split(r, &[0])?;
+        let ra = b.u3(ra, 0.3, -0.4, 1.2);
+        let (ra, rb) = b.crx(ra, rb.unwrap(), 0.7);
+        Ok(vec![b.merge(vec![ra, rb])?])
+    })?;
+    let r = b.register(3)?;
+    let mut rs = b.split_all(r);
+    let (q2, q1, q0) = (rs.pop().unwrap(), rs.pop().unwrap(), rs.pop().unwrap());
+    let rs = b.apply_gate("bell", vec![q0, q2])?;
+    let qs = b.merge(rs)?;
+    let qs = b.apply_gate("rotations", vec![qs])?.pop().unwrap();
+    let (qa, qb) = b.split(qs, &[0])?;
+    let (qa, q1) = b.cphase(qa, q1, 0.9);
+    let (q1, qa, qb) = b.cswap(q1, qa, qb.unwrap())?;
+    let q1 = b.t(q1);
+    // A controlled Y with a phase, which needs a correction on the control.
+    let phase = Complex::from_polar(&1.0, &0.4);
+    let (z, i): (Complex<f64>, Complex<f64>) = (Complex::new(0.0, 0.0), Complex::i());
+    let (q1, qb) = b.cmat("Y", q1, qb, vec![z, -i * phase, i * phase, z])?;
+    let r = b.merge(vec![qa, qb, q1])?;
+
+    let source = to_qasm(&r)?;
+    assert_eq!(source.matches("gate bell").count(), 1);
+    assert_eq!(source.matches("gate rotations").count(), 1);
+
+    let mut parsed_b = OpBuilder::new();
+    let circuit = parse_qasm(&mut parsed_b, &source)?;
+    let parsed = circuit.merge_registers(&mut parsed_b)?;
+    assert_circuits_equivalent(&r, &parsed, 1e-8);
+    Ok(())
+}
+
+#[test]
+fn test_qasm_measurements() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(2)?;
+    let r = b.hadamard(r);
+    let (r, m) = b.measure(r);
+    let source = to_qasm(&r)?;
+    let expected = format!(
+        "OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[2];\ncreg m{0}[2];\nh q[0];\nh q[1];\n\
+         measure q[0] -> m{0}[0];\nmeasure q[1] -> m{0}[1];\n",
+        m.get_id()
+    );
+    assert_eq!(source, expected);
+    Ok(())
+}
+
+#[test]
+fn test_qasm_unsupported_op() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let ra = b.qubit();
+    let rb = b.qubit();
+    let (ra, rb) = b.iswap(ra, rb)?;
+    let r = b.merge(vec![ra, rb])?;
+    assert!(to_qasm(&r).is_err());
+    Ok(())
+}
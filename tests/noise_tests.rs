@@ -0,0 +1,108 @@
+extern crate qip;
+
+use qip::density_state::run_density_local;
+use qip::density_state::DensityMatrixState;
+use qip::noise::{amplitude_damping, phase_damping, NoiseModel};
+use qip::pipeline::{run_with_noise, run_with_state_and_noise, LocalQuantumState};
+use qip::*;
+
+fn assert_almost_eq(a: f64, b: f64, prec: i32) {
+    let mult = 10.0f64.powi(prec);
+    let (a, b) = (a * mult, b * mult);
+    let (a, b) = (a.round(), b.round());
+    assert_eq!(a / mult, b / mult);
+}
+
+#[test]
+fn test_full_depolarizing() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let q = b.not(q);
+
+    let mut noise = NoiseModel::new();
+    noise.set_gate_probability("not", 1.0)?;
+    let (rho, _) = run_with_noise::<f64, DensityMatrixState<f64>>(&q, &noise)?;
+
+    assert_almost_eq(rho.get_entry(0, 0, true).re, 0.5, 10);
+    assert_almost_eq(rho.get_entry(1, 1, true).re, 0.5, 10);
+    assert_almost_eq(rho.purity(), 0.5, 10);
+    Ok(())
+}
+
+#[test]
+fn test_noiseless_model() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.hadamard(q);
+    let (q, r) = b.cnot(q, r);
+    let r = b.merge(vec![q, r])?;
+
+    let noise = NoiseModel::new();
+    let (rho, _) = run_with_noise::<f64, DensityMatrixState<f64>>(&r, &noise)?;
+    assert_almost_eq(rho.purity(), 1.0, 10);
+    assert_almost_eq(rho.get_entry(0, 3, true).re, 0.5, 10);
+    Ok(())
+}
+
+#[test]
+fn test_partial_depolarizing() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.hadamard(q);
+    let (q, r) = b.cnot(q, r);
+    let r = b.merge(vec![q, r])?;
+
+    // Only the cnot is noisy, it depolarizes both qubits.
+    let p = 0.2;
+    let mut noise = NoiseModel::new();
+    noise.set_gate_probability("C(not)", p)?;
+    let state = DensityMatrixState::<f64>::new(2);
+    let (rho, _) = run_with_state_and_noise(&r, state, &noise)?;
+
+    // Each depolarizing channel shrinks the coherence between |00> and |11> by (1-p).
+    let q = 1.0 - p;
+    assert_almost_eq(rho.get_entry(0, 3, true).re, 0.5 * q * q, 10);
+    assert_almost_eq(rho.get_entry(1, 1, true).re, (1.0 - q * q) / 4.0, 10);
+    Ok(())
+}
+
+#[test]
+fn test_noise_state_size_mismatch() {
+    let mut b = OpBuilder::new();
+    let r = b.register(2).unwrap();
+    let r = b.hadamard(r);
+
+    let noise = NoiseModel::new();
+    let state = LocalQuantumState::<f64>::new(3);
+    assert!(run_with_state_and_noise(&r, state, &noise).is_err());
+}
+
+#[test]
+fn test_amplitude_damping() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let q = b.hadamard(q);
+    let q = b.channel("T1", q, amplitude_damping(0.36)?)?;
+    let (rho, _) = run_density_local::<f64>(&q)?;
+
+    assert_almost_eq(rho.get_entry(0, 0, true).re, 0.5 + 0.5 * 0.36, 10);
+    assert_almost_eq(rho.get_entry(1, 1, true).re, 0.5 * 0.64, 10);
+    assert_almost_eq(rho.get_entry(0, 1, true).re, 0.5 * 0.8, 10);
+    Ok(())
+}
+
+#[test]
+fn test_phase_damping() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let q = b.hadamard(q);
+    let q = b.channel("T2", q, phase_damping(0.36)?)?;
+    let (rho, _) = run_density_local::<f64>(&q)?;
+
+    assert_almost_eq(rho.get_entry(0, 0, true).re, 0.5, 10);
+    assert_almost_eq(rho.get_entry(1, 1, true).re, 0.5, 10);
+    assert_almost_eq(rho.get_entry(0, 1, true).re, 0.5 * 0.8, 10);
+    Ok(())
+}
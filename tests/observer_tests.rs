@@ -0,0 +1,88 @@
+extern crate qip;
+
+use qip::density_state::DensityMatrixState;
+use qip::noise::NoiseModel;
+use qip::parameters::Parameter;
+use qip::pipeline::{
+    run_with_options, run_with_state_and_observer, LocalQuantumState, ObservedOp, RunOptions,
+};
+use qip::*;
+use std::collections::HashMap;
+
+#[test]
+fn test_observer_sees_each_op() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.not(q);
+    let (q, m) = b.measure(q);
+    let r = b.single_register_classical_sidechannel(
+        r,
+        &[m],
+        Box::new(|b, r, ms| Ok(if ms[0] == 1 { b.hadamard(r) } else { r })),
+    );
+    let r = b.merge(vec![q, r])?;
+
+    let mut seen = vec![];
+    let mut observer = |op: &ObservedOp, s: &LocalQuantumState<f64>| {
+        seen.push((op.index, op.name.to_string(), op.op.is_some()));
+        // The first qubit is |1> after the first op.
+        assert!((s.probability_of(&[0], 1) - 1.0).abs() < 1e-10);
+    };
+    let (state, _) = run_with_state_and_observer(&r, LocalQuantumState::new(2), &mut observer)?;
+    assert_eq!(
+        seen,
+        vec![
+            (0, "not".to_string(), true),
+            (1, "measure".to_string(), false),
+            (2, "H".to_string(), true),
+        ]
+    );
+    assert!((state.probability_of(&[1], 1) - 0.5).abs() < 1e-10);
+    Ok(())
+}
+
+#[test]
+fn test_observer_state_size_mismatch() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(2)?;
+    let r = b.hadamard(r);
+    let mut observer = |_: &ObservedOp, _: &LocalQuantumState<f64>| {};
+    let result = run_with_state_and_observer(&r, LocalQuantumState::new(3), &mut observer);
+    assert!(result.is_err());
+    Ok(())
+}
+
+#[test]
+fn test_combined_options() -> Result<(), CircuitError> {
+    let theta = Parameter::new("theta");
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let q = b.hadamard(q);
+    let q = b.rz_param(q, &theta)?;
+    let q = b.hadamard(q);
+
+    // Only the rotation is noisy.
+    let mut params = HashMap::new();
+    params.insert("theta".to_string(), std::f64::consts::PI);
+    let mut noise = NoiseModel::new();
+    noise.set_default_probability(0.5)?;
+    noise.set_gate_probability("H", 0.0)?;
+    let mut names = vec![];
+    let mut observer = |op: &ObservedOp, _: &DensityMatrixState<f64>| {
+        names.push(op.name.to_string());
+    };
+    let options = RunOptions {
+        noise: Some(&noise),
+        parameters: Some(&params),
+        observer: Some(&mut observer),
+        fuse: true,
+    };
+    let (rho, _) = run_with_options(&q, DensityMatrixState::new(1), options)?;
+    assert_eq!(names.len(), 3);
+    assert!((rho.get_entry(1, 1, true).re - 0.75).abs() < 1e-10);
+
+    let result = run_with_options(&q, LocalQuantumState::<f64>::new(2), RunOptions::default());
+    assert!(result.is_err());
+    Ok(())
+}
@@ -0,0 +1,195 @@
+extern crate qip;
+
+use qip::circuit_stats::CircuitStats;
+use qip::equivalence::circuits_equivalent;
+use qip::optimize::{cancel_inverses, ops_commute, reorder_commuting};
+use qip::pipeline::get_opfns_and_frontier;
+use qip::state_ops::{from_reals, UnitaryOp};
+use qip::*;
+
+fn op_names(r: &Register) -> Vec<String> {
+    let (_, ops) = get_opfns_and_frontier(r);
+    ops.iter().map(|op| op.name.clone()).collect()
+}
+
+#[test]
+fn test_cancel_nested_pairs() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.hadamard(q);
+    let q = b.x(q);
+    let (q, r) = b.cnot(q, r);
+    let (q, r) = b.cnot(q, r);
+    let q = b.x(q);
+    let q = b.hadamard(q);
+    let r = b.merge(vec![q, r])?;
+
+    let optimized = cancel_inverses(&r)?;
+    assert_eq!(optimized.indices, r.indices);
+    assert!(op_names(&optimized).is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_keep_different_pairs() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let (q, r) = b.cnot(q, r);
+    let (r, q) = b.cnot(r, q);
+    // S followed by S is Z, not the identity.
+    let q = b.s(q);
+    let q = b.s(q);
+    let r = b.merge(vec![q, r])?;
+
+    let optimized = cancel_inverses(&r)?;
+    assert_eq!(op_names(&optimized), vec!["C(not)", "C(not)", "S", "S"]);
+    assert!(circuits_equivalent(&r, &optimized, 1e-10)?);
+    Ok(())
+}
+
+#[test]
+fn test_cancel_interleaved_qubits() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.hadamard(q);
+    let r = b.ry(r, 0.2);
+    let q = b.hadamard(q);
+    let q = b.t(q);
+    let r = b.merge(vec![q, r])?;
+
+    let optimized = cancel_inverses(&r)?;
+    assert_eq!(op_names(&optimized), vec!["Ry", "T"]);
+    assert!(circuits_equivalent(&r, &optimized, 1e-10)?);
+    Ok(())
+}
+
+#[test]
+fn test_merge_rotations() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.hadamard(q);
+    let q = b.rz(q, 0.3);
+    let q = b.rz(q, 0.4);
+    let (q, r) = b.crx(q, r, 0.5);
+    let (q, r) = b.crx(q, r, -0.2);
+    let r = b.ry(r, 0.6);
+    let r = b.ry(r, -0.6);
+    let r = b.merge(vec![q, r])?;
+
+    let optimized = cancel_inverses(&r)?;
+    assert_eq!(op_names(&optimized), vec!["H", "Rz", "C(Rx)"]);
+    assert!(circuits_equivalent(&r, &optimized, 1e-10)?);
+
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.hadamard(q);
+    let q = b.rz(q, 0.7);
+    let (q, r) = b.crx(q, r, 0.3);
+    let r = b.merge(vec![q, r])?;
+    assert!(circuits_equivalent(&r, &optimized, 1e-10)?);
+    Ok(())
+}
+
+#[test]
+fn test_measurement_blocks_cancellation() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let q = b.hadamard(q);
+    let (q, m) = b.measure(q);
+    let q = b.hadamard(q);
+
+    let optimized = cancel_inverses(&q)?;
+    assert_eq!(op_names(&optimized), vec!["H", "measure", "H"]);
+    let (_, measured) = run_local::<f64>(&optimized)?;
+    assert!(measured.get_measurement(&m).is_some());
+    Ok(())
+}
+
+#[test]
+fn test_cancel_through_commuting_ops() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let r = b.not(r);
+    let q = b.rz(q, 0.3);
+    let (q, r) = b.cnot(q, r);
+    // X on the target and Rz on the control both commute with the cnot.
+    let r = b.not(r);
+    let q = b.rz(q, -0.3);
+    let r = b.merge(vec![q, r])?;
+
+    let optimized = cancel_inverses(&r)?;
+    assert_eq!(op_names(&optimized), vec!["C(not)"]);
+    assert!(circuits_equivalent(&r, &optimized, 1e-10)?);
+    Ok(())
+}
+
+#[test]
+fn test_no_cancel_through_non_commuting_ops() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.not(q);
+    let (q, r) = b.cnot(q, r);
+    let q = b.not(q);
+    let r = b.merge(vec![q, r])?;
+
+    let optimized = cancel_inverses(&r)?;
+    assert_eq!(op_names(&optimized), vec!["not", "C(not)", "not"]);
+    Ok(())
+}
+
+#[test]
+fn test_reorder_commuting() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let s = b.qubit();
+    let q = b.hadamard(q);
+    let (q, r) = b.cnot(q, r);
+    let (s, r) = b.cnot(s, r);
+    let s = b.hadamard(s);
+    let r = b.merge(vec![q, r, s])?;
+    let reordered = reorder_commuting(&r)?;
+    assert!(circuits_equivalent(&r, &reordered, 1e-10)?);
+
+    let (q, m) = b.measure(r);
+    let reordered = reorder_commuting(&q)?;
+    assert_eq!(reordered.indices, q.indices);
+    // Both cnots share a target so the second can run first, next to the first hadamard.
+    assert_eq!(CircuitStats::new(&q).depth, 5);
+    assert_eq!(CircuitStats::new(&reordered).depth, 3);
+    assert_eq!(op_names(&reordered).last().unwrap(), "measure");
+
+    let (_, measured) = run_local::<f64>(&reordered)?;
+    assert!(measured.get_measurement(&m).is_some());
+    Ok(())
+}
+
+#[test]
+fn test_ops_commute() {
+    let x = from_reals(&[0.0, 1.0, 1.0, 0.0]);
+    let h = from_reals(&[1.0, 1.0, 1.0, -1.0]);
+    let cnot = |c: u64, t: u64| {
+        UnitaryOp::Control(
+            vec![c],
+            vec![t],
+            Box::new(UnitaryOp::Matrix(vec![t], x.clone())),
+        )
+    };
+    assert!(ops_commute(&cnot(0, 1), &cnot(0, 2)));
+    assert!(ops_commute(&cnot(0, 2), &cnot(1, 2)));
+    assert!(!ops_commute(&cnot(0, 1), &cnot(1, 2)));
+    assert!(ops_commute(
+        &cnot(0, 1),
+        &UnitaryOp::Matrix(vec![2], h.clone())
+    ));
+    assert!(!ops_commute(&cnot(0, 1), &UnitaryOp::Matrix(vec![1], h)));
+    let swap = UnitaryOp::Swap(vec![0], vec![1]);
+    assert!(ops_commute(&swap, &swap));
+}
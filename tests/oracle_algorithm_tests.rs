@@ -0,0 +1,104 @@
+extern crate qip;
+
+use qip::*;
+
+fn measure(b: &mut OpBuilder, r: Register) -> Result<(u64, f64), CircuitError> {
+    let (r, m) = b.measure(r);
+    let (_, measured) = run_local::<f64>(&r)?;
+    Ok(measured.get_measurement(&m).unwrap())
+}
+
+#[test]
+fn test_bernstein_vazirani_all_secrets() -> Result<(), CircuitError> {
+    for secret in 0..32 {
+        let mut b = OpBuilder::new();
+        let (r, ancilla) = bernstein_vazirani(&mut b, secret, 5)?;
+        assert_eq!(r.n(), 5);
+        assert_eq!(ancilla.n(), 1);
+        let (value, p) = measure(&mut b, r)?;
+        assert_eq!(value, secret);
+        assert!((p - 1.0).abs() < 1e-10);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_bernstein_vazirani_ancilla_unentangled() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let (r, ancilla) = bernstein_vazirani(&mut b, 0b101, 3)?;
+    // The ancilla is left in |->, which H takes back to |1>.
+    let ancilla = b.hadamard(ancilla);
+    let r = b.merge(vec![r, ancilla])?;
+    let (value, _) = measure(&mut b, r)?;
+    assert_eq!(value, 0b1101);
+    Ok(())
+}
+
+#[test]
+fn test_bernstein_vazirani_errors() {
+    let mut b = OpBuilder::new();
+    assert!(bernstein_vazirani(&mut b, 0b1000, 3).is_err());
+    assert!(bernstein_vazirani(&mut b, 0, 0).is_err());
+}
+
+fn deutsch_jozsa_value(oracle: DeutschJozsaOracle, n: u64) -> Result<(u64, f64), CircuitError> {
+    let mut b = OpBuilder::new();
+    let (r, ancilla) = deutsch_jozsa(&mut b, oracle, n)?;
+    assert_eq!(ancilla.n(), 1);
+    measure(&mut b, r)
+}
+
+#[test]
+fn test_deutsch_jozsa_functions() -> Result<(), CircuitError> {
+    let (value, p) = deutsch_jozsa_value(DeutschJozsaOracle::Function(Box::new(|_| false)), 4)?;
+    assert_eq!(value, 0);
+    assert!((p - 1.0).abs() < 1e-10);
+    let (value, _) = deutsch_jozsa_value(DeutschJozsaOracle::Function(Box::new(|_| true)), 4)?;
+    assert_eq!(value, 0);
+
+    // Every balanced function measures anything but zero with certainty.
+    let balanced: Vec<Box<dyn Fn(u64) -> bool + Send + Sync>> = vec![
+        Box::new(|x| x & 0b100 != 0),
+        Box::new(|x| x.count_ones() & 1 == 1),
+        Box::new(|x| x < 8),
+        Box::new(|x| [1, 2, 4, 7, 8, 11, 13, 14].contains(&x)),
+    ];
+    for f in balanced {
+        let (value, _) = deutsch_jozsa_value(DeutschJozsaOracle::Function(f), 4)?;
+        assert_ne!(value, 0);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_deutsch_jozsa_circuit() -> Result<(), CircuitError> {
+    // f(x) = x0 ^ x2 as a circuit of cnots.
+    let oracle = DeutschJozsaOracle::Circuit(Box::new(|b, r, y| {
+        let mut qs = b.split_all(r);
+        let mut y = y;
+        for i in &[0, 2] {
+            let (q, t) = b.cnot(qs.remove(*i), y);
+            qs.insert(*i, q);
+            y = t;
+        }
+        Ok((b.merge(qs)?, y))
+    }));
+    let (value, _) = deutsch_jozsa_value(oracle, 3)?;
+    assert_eq!(value, 0b101);
+
+    let constant = DeutschJozsaOracle::Circuit(Box::new(|b, r, y| Ok((r, b.x(y)))));
+    let (value, _) = deutsch_jozsa_value(constant, 3)?;
+    assert_eq!(value, 0);
+    Ok(())
+}
+
+#[test]
+fn test_deutsch_jozsa_bad_oracle() {
+    let oracle = DeutschJozsaOracle::Circuit(Box::new(|b, r, y| {
+        let r = b.merge(vec![r, y])?;
+        let (r, y) = b.split(r, &[0, 1])?;
+        Ok((y.unwrap(), r))
+    }));
+    let mut b = OpBuilder::new();
+    assert!(deutsch_jozsa(&mut b, oracle, 3).is_err());
+}
@@ -0,0 +1,102 @@
+extern crate qip;
+
+use qip::pipeline::InitialState;
+use qip::*;
+
+#[test]
+fn test_classical_oracle_basis_states() -> Result<(), CircuitError> {
+    let f = |x: u64| (x * 3 + 1) % 16;
+    for x in 0..8 {
+        for y in &[0u64, 0b1010] {
+            let mut b = OpBuilder::new();
+            let r_in = b.register(3)?;
+            let r_out = b.register(4)?;
+            let inits = [
+                (r_in.indices.clone(), InitialState::Index(x)),
+                (r_out.indices.clone(), InitialState::Index(*y)),
+            ];
+            let (r_in, r_out) = classical_oracle(&mut b, r_in, r_out, f)?;
+            let r = b.merge(vec![r_in, r_out])?;
+            let (state, _) = run_local_with_init::<f64>(&r, &inits)?;
+            let expected = x | ((y ^ f(x)) << 3);
+            let state = state.get_state(true);
+            assert!((state[expected as usize].re - 1.0).abs() < 1e-10);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_classical_oracle_masks_output() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r_in = b.register(2)?;
+    let r_out = b.register(2)?;
+    let r_in = b.not(r_in);
+    let (r_in, r_out) = classical_oracle(&mut b, r_in, r_out, |x| x + 0b100)?;
+    let r = b.merge(vec![r_in, r_out])?;
+    let (state, _) = run_local::<f64>(&r)?;
+    assert!((state.get_state(true)[0b1111].re - 1.0).abs() < 1e-10);
+    Ok(())
+}
+
+#[test]
+fn test_classical_oracle_full_width_output() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r_in = b.qubit();
+    let r_out = b.register(64)?;
+    let (_, r_out) = classical_oracle(&mut b, r_in, r_out, |x| x)?;
+    assert_eq!(r_out.n(), 64);
+    Ok(())
+}
+
+#[test]
+fn test_classical_oracle_superposition() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r_in = b.register(3)?;
+    let r_out = b.qubit();
+    let r_in = b.hadamard(r_in);
+    // Parity oracle.
+    let (r_in, r_out) = classical_oracle(&mut b, r_in, r_out, |x| u64::from(x.count_ones() % 2))?;
+    let r = b.merge(vec![r_in, r_out])?;
+    let (state, _) = run_local::<f64>(&r)?;
+    let state = state.get_state(true);
+    let amp = 1.0 / 8f64.sqrt();
+    (0..8u64).for_each(|x| {
+        let parity = u64::from(x.count_ones() % 2);
+        assert!((state[(x | (parity << 3)) as usize].re - amp).abs() < 1e-10);
+    });
+    Ok(())
+}
+
+#[test]
+fn test_phase_oracle_marks_states() -> Result<(), CircuitError> {
+    let marked = |x: u64| x == 0b001 || x == 0b110;
+    let mut b = OpBuilder::new();
+    let r = b.register(3)?;
+    let r = b.hadamard(r);
+    let r = phase_oracle(&mut b, r, marked)?;
+    let (state, _) = run_local::<f64>(&r)?;
+    assert!(!state.has_arena());
+    let state = state.get_state(true);
+    let amp = 1.0 / 8f64.sqrt();
+    (0..8u64).for_each(|x| {
+        let sign = if marked(x) { -1.0 } else { 1.0 };
+        assert!((state[x as usize].re - sign * amp).abs() < 1e-10);
+    });
+    Ok(())
+}
+
+#[test]
+fn test_phase_oracle_grover_iteration() -> Result<(), CircuitError> {
+    // One grover iteration on two qubits finds the marked state exactly.
+    let mut b = OpBuilder::new();
+    let r = b.register(2)?;
+    let r = b.hadamard(r);
+    let r = phase_oracle(&mut b, r, |x| x == 0b10)?;
+    let r = b.hadamard(r);
+    let r = phase_oracle(&mut b, r, |x| x != 0)?;
+    let r = b.hadamard(r);
+    let (state, _) = run_local::<f64>(&r)?;
+    assert!((state.get_state(true)[0b10].norm() - 1.0).abs() < 1e-10);
+    Ok(())
+}
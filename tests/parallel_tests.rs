@@ -0,0 +1,73 @@
+extern crate qip;
+
+use qip::pipeline::{LocalQuantumState, ParallelConfig};
+use qip::*;
+
+fn make_circuit(b: &mut OpBuilder, n: u64) -> Result<Register, CircuitError> {
+    let r = b.register(n)?;
+    let r = b.hadamard(r);
+    let qs = b.split_all(r);
+    let qs = qs
+        .into_iter()
+        .enumerate()
+        .map(|(i, q)| b.rz(q, 0.2 * i as f64))
+        .collect();
+    let r = b.merge(qs)?;
+    let (ra, rb) = b.split(r, &[0, 1, 2])?;
+    let (ra, rb) = b.cnot(ra, rb.unwrap());
+    let (ra, rb) = b.swap(ra, rb)?;
+    b.merge(vec![ra, rb])
+}
+
+fn run_with_config(n: u64, config: ParallelConfig) -> Result<Vec<Complex<f64>>, CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = make_circuit(&mut b, n)?;
+    let mut state = LocalQuantumState::<f64>::new(n);
+    state.set_parallel_config(config)?;
+    assert_eq!(state.parallel_config(), config);
+    let (state, _) = run_with_state(&r, state)?;
+    Ok(state.get_state(true))
+}
+
+#[test]
+fn test_parallel_configs_agree() -> Result<(), CircuitError> {
+    let n = 6;
+    let expected = run_with_config(n, ParallelConfig::default())?;
+    let configs = [
+        ParallelConfig {
+            multithread: false,
+            ..Default::default()
+        },
+        ParallelConfig {
+            threshold: n + 1,
+            ..Default::default()
+        },
+        ParallelConfig {
+            num_threads: Some(2),
+            chunk_size: 1,
+            ..Default::default()
+        },
+        ParallelConfig {
+            num_threads: Some(1),
+            chunk_size: 3,
+            ..Default::default()
+        },
+    ];
+    for config in configs.iter() {
+        let state = run_with_config(n, *config)?;
+        state.iter().zip(expected.iter()).for_each(|(a, b)| {
+            assert!((a - b).norm() < 1e-10);
+        });
+    }
+    Ok(())
+}
+
+#[test]
+fn test_parallel_bad_chunk_size() {
+    let mut state = LocalQuantumState::<f64>::new(2);
+    let config = ParallelConfig {
+        chunk_size: 0,
+        ..Default::default()
+    };
+    assert!(state.set_parallel_config(config).is_err());
+}
@@ -0,0 +1,219 @@
+extern crate qip;
+
+use qip::parameters::{expectation, get_parameters, gradient, Parameter};
+use qip::*;
+use std::collections::HashMap;
+
+fn assert_almost_eq(a: f64, b: f64, prec: i32) {
+    let mult = 10.0f64.powi(prec);
+    let (a, b) = (a * mult, b * mult);
+    let (a, b) = (a.round(), b.round());
+    assert_eq!(a / mult, b / mult);
+}
+
+fn assert_states_eq(a: Vec<Complex<f64>>, b: Vec<Complex<f64>>) {
+    assert_eq!(a.len(), b.len());
+    a.into_iter().zip(b).for_each(|(a, b)| {
+        assert_almost_eq(a.re, b.re, 10);
+        assert_almost_eq(a.im, b.im, 10);
+    });
+}
+
+fn params(values: &[(&str, f64)]) -> HashMap<String, f64> {
+    values
+        .iter()
+        .map(|(name, value)| (name.to_string(), *value))
+        .collect()
+}
+
+#[test]
+fn test_parameterized_rotations() -> Result<(), CircuitError> {
+    let (alpha, beta, gamma) = (
+        Parameter::new("alpha"),
+        Parameter::new("beta"),
+        Parameter::new("gamma"),
+    );
+    let mut b = OpBuilder::new();
+    let r = b.register(2)?;
+    let r = b.hadamard(r);
+    let r = b.rx_param(r, &alpha)?;
+    let r = b.ry_param(r, &beta)?;
+    let r = b.rz_param(r, &gamma)?;
+
+    [(0.1, 0.2, 0.3), (-1.0, 2.5, 0.0), (3.0, -0.5, 1.2)]
+        .iter()
+        .try_for_each(|(a, be, g)| -> Result<(), CircuitError> {
+            let values = params(&[("alpha", *a), ("beta", *be), ("gamma", *g)]);
+            let (state, _) = run_local_with_parameters::<f64>(&r, &values)?;
+
+            let mut b = OpBuilder::new();
+            let q = b.register(2)?;
+            let q = b.hadamard(q);
+            let q = b.rx(q, *a);
+            let q = b.ry(q, *be);
+            let q = b.rz(q, *g);
+            let (expected, _) = run_local::<f64>(&q)?;
+
+            assert_states_eq(state.get_state(true), expected.get_state(true));
+            Ok(())
+        })
+}
+
+#[test]
+fn test_parameterized_condition() -> Result<(), CircuitError> {
+    let theta = Parameter::new("theta");
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.hadamard(q);
+    let (q, r) = try_condition(&mut b, q, r, |b, r| b.ry_param(r, &theta))?;
+    let r = b.merge(vec![q, r])?;
+    let (state, _) = run_local_with_parameters::<f64>(&r, &params(&[("theta", 0.7)]))?;
+
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.hadamard(q);
+    let (q, r) = condition(&mut b, q, r, |b, r| b.ry(r, 0.7));
+    let r = b.merge(vec![q, r])?;
+    let (expected, _) = run_local::<f64>(&r)?;
+
+    assert_states_eq(state.get_state(true), expected.get_state(true));
+    Ok(())
+}
+
+#[test]
+fn test_parameterized_dagger() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(2)?;
+    let r = b.rx_param(r, &Parameter::new("a"))?;
+    let r = b.rz_param(r, &Parameter::new("b"))?;
+    let r = b.dagger(
+        r,
+        Box::new(|b, r| {
+            let r = b.rx_param(r, &Parameter::new("a"))?;
+            b.rz_param(r, &Parameter::new("b"))
+        }),
+    )?;
+    let (state, _) = run_local_with_parameters::<f64>(&r, &params(&[("a", 0.4), ("b", -1.3)]))?;
+    assert_almost_eq(state.get_state(true)[0].norm(), 1.0, 10);
+    Ok(())
+}
+
+#[test]
+fn test_get_parameters() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.qubit();
+    let r = b.ry_param(r, &Parameter::new("b"))?;
+    let r = b.rx_param(r, &Parameter::new("a"))?;
+    let r = b.rz_param(r, &Parameter::new("b"))?;
+    assert_eq!(
+        get_parameters(&r),
+        vec![Parameter::new("b"), Parameter::new("a")]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_unbound_parameter() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.qubit();
+    let r = b.rx_param(r, &Parameter::new("a"))?;
+    assert!(run_local::<f64>(&r).is_err());
+    assert!(run_local_with_parameters::<f64>(&r, &params(&[("b", 1.0)])).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_parameterized_mat_size_err() {
+    let mut b = OpBuilder::new();
+    let r = b.register(2).unwrap();
+    let res = b.parameterized_mat(
+        "bad",
+        r,
+        &Parameter::new("a"),
+        Box::new(|_| vec![Complex::new(1.0, 0.0); 8]),
+    );
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_expectation() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(2)?;
+    let r = b.ry_param(r, &Parameter::new("theta"))?;
+    let theta = 0.8f64;
+    let e = expectation(
+        &r,
+        &[(0.5, "ZI"), (2.0, "ZZ")],
+        &params(&[("theta", theta)]),
+    )?;
+    assert_almost_eq(e, 0.5 * theta.cos() + 2.0 * theta.cos().powi(2), 10);
+    Ok(())
+}
+
+#[test]
+fn test_gradient_two_parameters() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let q = b.rx_param(q, &Parameter::new("a"))?;
+    let q = b.ry_param(q, &Parameter::new("b"))?;
+
+    // <Z> = cos(a) cos(b)
+    let (a, be) = (0.4f64, -1.1f64);
+    let grads = gradient(&q, &[(1.0, "Z")], &params(&[("a", a), ("b", be)]))?;
+    assert_eq!(grads.len(), 2);
+    assert_almost_eq(grads["a"], -a.sin() * be.cos(), 10);
+    assert_almost_eq(grads["b"], -a.cos() * be.sin(), 10);
+    Ok(())
+}
+
+#[test]
+fn test_gradient_shared_parameter() -> Result<(), CircuitError> {
+    let theta = Parameter::new("theta");
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let q = b.ry_param(q, &theta)?;
+    let mut rs = b.repeat(2, vec![q], |b, mut rs| {
+        Ok(vec![
+            b.ry_param(rs.pop().unwrap(), &Parameter::new("theta"))?
+        ])
+    })?;
+    let q = rs.pop().unwrap();
+
+    // <Z> = cos(3 theta)
+    let t = 0.35f64;
+    let grads = gradient(&q, &[(1.0, "Z")], &params(&[("theta", t)]))?;
+    assert_almost_eq(grads["theta"], -3.0 * (3.0 * t).sin(), 10);
+    Ok(())
+}
+
+#[test]
+fn test_gradient_matches_finite_difference() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.ry_param(q, &Parameter::new("a"))?;
+    let (q, r) = b.cnot(q, r);
+    let r = b.rx_param(r, &Parameter::new("b"))?;
+    let q = b.rz_param(q, &Parameter::new("c"))?;
+    let q = b.hadamard(q);
+    let r = b.merge(vec![q, r])?;
+
+    let observable = [(1.0, "ZZ"), (-0.5, "XI"), (0.25, "IY")];
+    let values = params(&[("a", 0.3), ("b", 1.7), ("c", -0.6)]);
+    let grads = gradient(&r, &observable, &values)?;
+    let eps = 1e-6;
+    ["a", "b", "c"]
+        .iter()
+        .try_for_each(|name| -> Result<(), CircuitError> {
+            let mut plus = values.clone();
+            *plus.get_mut(*name).unwrap() += eps;
+            let mut minus = values.clone();
+            *minus.get_mut(*name).unwrap() -= eps;
+            let fd = (expectation(&r, &observable, &plus)? - expectation(&r, &observable, &minus)?)
+                / (2.0 * eps);
+            assert_almost_eq(grads[*name], fd, 6);
+            Ok(())
+        })
+}
@@ -0,0 +1,143 @@
+extern crate num;
+extern crate qip;
+
+use num::{One, Zero};
+use qip::pipeline::make_circuit_matrix;
+use qip::*;
+
+fn assert_almost_eq(a: f64, b: f64, prec: i32) {
+    let mult = 10.0f64.powi(prec);
+    let (a, b) = (a * mult, b * mult);
+    let (a, b) = (a.round(), b.round());
+    assert_eq!(a / mult, b / mult);
+}
+
+fn assert_matrix_almost_eq(a: &[Vec<Complex<f64>>], b: &[Vec<Complex<f64>>]) {
+    assert_eq!(a.len(), b.len());
+    a.iter().zip(b.iter()).for_each(|(ra, rb)| {
+        ra.iter().zip(rb.iter()).for_each(|(a, b)| {
+            assert_almost_eq(a.re, b.re, 10);
+            assert_almost_eq(a.im, b.im, 10);
+        })
+    });
+}
+
+fn diag(entries: &[Complex<f64>]) -> Vec<Vec<Complex<f64>>> {
+    (0..entries.len())
+        .map(|row| {
+            (0..entries.len())
+                .map(|col| {
+                    if row == col {
+                        entries[row]
+                    } else {
+                        Complex::zero()
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn test_s_t_gates() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let q = b.t(q);
+    let q = b.t(q);
+    let s_from_t = make_circuit_matrix::<f64>(1, &q, false);
+
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let q = b.s(q);
+    let s = make_circuit_matrix::<f64>(1, &q, false);
+
+    let expected = diag(&[Complex::one(), Complex { re: 0.0, im: 1.0 }]);
+    assert_matrix_almost_eq(&s_from_t, &expected);
+    assert_matrix_almost_eq(&s, &expected);
+    Ok(())
+}
+
+#[test]
+fn test_daggers() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(2)?;
+    let r = b.s(r);
+    let r = b.t(r);
+    let r = b.tdagger(r);
+    let r = b.sdagger(r);
+    let mat = make_circuit_matrix::<f64>(2, &r, false);
+
+    assert_matrix_almost_eq(&mat, &diag(&[Complex::one(); 4]));
+    Ok(())
+}
+
+#[test]
+fn test_controlled_phases() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let cr = b.qubit();
+    let r = b.qubit();
+    let (cr, r) = b.ct(cr, r);
+    let (cr, r) = b.cs(cr, r);
+    let r = b.merge(vec![cr, r])?;
+    let mat = make_circuit_matrix::<f64>(2, &r, false);
+
+    // Only |11> picks up the e^{i 3pi/4} phase.
+    let phase = Complex {
+        re: 0.0,
+        im: 3.0 * std::f64::consts::FRAC_PI_4,
+    }
+    .exp();
+    let expected = diag(&[Complex::one(), Complex::one(), Complex::one(), phase]);
+    assert_matrix_almost_eq(&mat, &expected);
+    Ok(())
+}
+
+#[test]
+fn test_cphase() -> Result<(), CircuitError> {
+    let theta = 0.7;
+    let phase = Complex::from_polar(&1.0, &theta);
+    let mut b = OpBuilder::new();
+    let cr = b.qubit();
+    let r = b.qubit();
+    let (cr, r) = b.cphase(cr, r, theta);
+    let r = b.merge(vec![cr, r])?;
+    let mat = make_circuit_matrix::<f64>(2, &r, false);
+    let expected = diag(&[Complex::one(), Complex::one(), Complex::one(), phase]);
+    assert_matrix_almost_eq(&mat, &expected);
+
+    // A controlled rz differs by a phase of e^{i theta/2} on the control.
+    let mut b = OpBuilder::new();
+    let cr = b.qubit();
+    let r = b.qubit();
+    let (cr, r) = b.crz(cr, r, theta);
+    let cr = b.mat(
+        "P",
+        cr,
+        vec![
+            Complex::one(),
+            Complex::zero(),
+            Complex::zero(),
+            Complex::from_polar(&1.0, &(theta / 2.0)),
+        ],
+    )?;
+    let r = b.merge(vec![cr, r])?;
+    assert_matrix_almost_eq(&make_circuit_matrix::<f64>(2, &r, false), &expected);
+    Ok(())
+}
+
+#[test]
+fn test_cphase_broadcast() -> Result<(), CircuitError> {
+    let theta = -1.3;
+    let phase = Complex::from_polar(&1.0, &theta);
+    let mut b = OpBuilder::new();
+    let cr = b.qubit();
+    let r = b.register(2)?;
+    let (cr, r) = b.cphase(cr, r, theta);
+    let r = b.merge(vec![cr, r])?;
+    let mat = make_circuit_matrix::<f64>(3, &r, false);
+    // With the control as the highest bit, each set target bit adds a phase.
+    let o = Complex::one();
+    let expected = diag(&[o, o, o, o, o, phase, phase, phase * phase]);
+    assert_matrix_almost_eq(&mat, &expected);
+    Ok(())
+}
@@ -0,0 +1,83 @@
+extern crate qip;
+
+use qip::pipeline::{InitialState, RegisterInitialState};
+use qip::*;
+
+fn make_circuit(b: &mut OpBuilder) -> Result<Register, CircuitError> {
+    let r = b.register(4)?;
+    let r = b.hadamard(r);
+    let qs = b.split_all(r);
+    let qs = qs
+        .into_iter()
+        .enumerate()
+        .map(|(i, q)| {
+            let q = b.ry(q, 0.3 * i as f64);
+            b.rz(q, 0.1 + 0.2 * i as f64)
+        })
+        .collect();
+    let r = b.merge(qs)?;
+    let (ra, rb) = b.split(r, &[0, 1])?;
+    let (ra, rb) = b.cnot(ra, rb.unwrap());
+    let rb = qip::qfft::qfft(b, rb);
+    b.merge(vec![ra, rb])
+}
+
+#[test]
+fn test_f32_matches_f64() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = make_circuit(&mut b)?;
+    let (s32, _) = run_local::<f32>(&r)?;
+    let (s64, _) = run_local::<f64>(&r)?;
+    s32.get_state(true)
+        .into_iter()
+        .zip(s64.get_state(true))
+        .for_each(|(a, b)| {
+            assert!((f64::from(a.re) - b.re).abs() < 1e-5);
+            assert!((f64::from(a.im) - b.im).abs() < 1e-5);
+        });
+    Ok(())
+}
+
+#[test]
+fn test_f32_measurement() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.hadamard(q);
+    let (q, r) = b.cnot(q, r);
+    let (q, m) = b.measure(q);
+    let r = b.merge(vec![q, r])?;
+    let (state, measured) = run_local::<f32>(&r)?;
+    let (m, p) = measured.get_measurement(&m).unwrap();
+    assert!((p - 0.5).abs() < 1e-6);
+    let state = state.get_state(true);
+    let expected = if m == 0 { 0b00 } else { 0b11 };
+    assert!((state[expected].norm() - 1.0).abs() < 1e-6);
+    Ok(())
+}
+
+#[test]
+fn test_f32_sampling_and_expectation() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(2)?;
+    let r = b.not(r);
+    let counts = run_and_sample::<f32>(&r, 20)?;
+    assert_eq!(counts.get(&0b11), Some(&20));
+
+    let (state, _) = run_local::<f32>(&r)?;
+    let e = state.pauli_expectation("ZI")?;
+    assert!((e + 1.0).abs() < 1e-6);
+    Ok(())
+}
+
+#[test]
+fn test_f32_initial_state() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(2)?;
+    let init: RegisterInitialState<f32> = (r.indices.clone(), InitialState::Index(0b10));
+    let r = b.not(r);
+    let (state, _) = run_local_with_init::<f32>(&r, &[init])?;
+    let state = state.get_state(true);
+    assert!((state[0b01].re - 1.0).abs() < 1e-6);
+    Ok(())
+}
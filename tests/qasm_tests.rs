@@ -0,0 +1,177 @@
+extern crate qip;
+
+use qip::qasm::parse_qasm;
+use qip::*;
+
+fn assert_almost_eq(a: f64, b: f64, prec: i32) {
+    let mult = 10.0f64.powi(prec);
+    let (a, b) = (a * mult, b * mult);
+    let (a, b) = (a.round(), b.round());
+    assert_eq!(a / mult, b / mult);
+}
+
+fn assert_state_almost_eq(a: &[Complex<f64>], b: &[Complex<f64>]) {
+    assert_eq!(a.len(), b.len());
+    a.iter().zip(b.iter()).for_each(|(a, b)| {
+        assert_almost_eq(a.re, b.re, 10);
+        assert_almost_eq(a.im, b.im, 10);
+    });
+}
+
+fn run_qasm(source: &str) -> Result<Vec<Complex<f64>>, CircuitError> {
+    let mut b = OpBuilder::new();
+    let circuit = parse_qasm(&mut b, source)?;
+    let r = circuit.merge_registers(&mut b)?;
+    let (state, _) = run_local::<f64>(&r)?;
+    Ok(state.get_state(true))
+}
+
+#[test]
+fn test_matches_builder() -> Result<(), CircuitError> {
+    let state = run_qasm(
+        r#"
+        OPENQASM 2.0;
+        include "qelib1.inc";
+        qreg a[2];
+        qreg b[1];
+        h a;
+        rx(pi/3) b[0];
+        cx a[0], b[0];  // entangle
+        rz(-0.25 * 2) a[1];
+        cz b[0], a[1];
+        swap a[0], b[0];
+        "#,
+    )?;
+
+    let mut b = OpBuilder::new();
+    let ra = b.register(2)?;
+    let rb = b.qubit();
+    let ra = b.hadamard(ra);
+    let rb = b.rx(rb, std::f64::consts::PI / 3.0);
+    let (a0, a1) = b.split(ra, &[0])?;
+    let a1 = a1.unwrap();
+    let (a0, rb) = b.cnot(a0, rb);
+    let a1 = b.rz(a1, -0.5);
+    let (rb, a1) = b.cz(rb, a1);
+    let (a0, rb) = b.swap(a0, rb)?;
+    let r = b.merge(vec![a0, a1, rb])?;
+    let (expected, _) = run_local::<f64>(&r)?;
+
+    assert_state_almost_eq(&state, &expected.get_state(true));
+    Ok(())
+}
+
+#[test]
+fn test_u3_gates() -> Result<(), CircuitError> {
+    // u3(theta, -pi/2, pi/2) = rx(theta), u3(theta, 0, 0) = ry(theta).
+    let state = run_qasm(
+        r#"
+        qreg q[2];
+        u3(0.7, -pi/2, pi/2) q[0];
+        U(0.3, 0, 0) q[1];
+        "#,
+    )?;
+
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.rx(q, 0.7);
+    let r = b.ry(r, 0.3);
+    let r = b.merge(vec![q, r])?;
+    let (expected, _) = run_local::<f64>(&r)?;
+
+    assert_state_almost_eq(&state, &expected.get_state(true));
+    Ok(())
+}
+
+#[test]
+fn test_gate_definition() -> Result<(), CircuitError> {
+    let state = run_qasm(
+        r#"
+        OPENQASM 2.0;
+        gate flip(theta) a, b {
+            ry(2 * theta) a;
+            CX a, b;
+        }
+        qreg q[2];
+        flip(pi/2) q[0], q[1];
+        "#,
+    )?;
+
+    // ry(pi)|0> = |1>, then CX flips the second qubit.
+    assert_almost_eq(state[0b11].norm(), 1.0, 10);
+    Ok(())
+}
+
+#[test]
+fn test_ccx_and_reset() -> Result<(), CircuitError> {
+    let state = run_qasm(
+        r#"
+        qreg q[4];
+        x q[0];
+        x q[1];
+        ccx q[0], q[1], q[2];
+        barrier q;
+        reset q[0];
+        "#,
+    )?;
+    assert_almost_eq(state[0b0110].norm(), 1.0, 10);
+    Ok(())
+}
+
+#[test]
+fn test_measurements() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let circuit = parse_qasm(
+        &mut b,
+        r#"
+        qreg q[3];
+        creg c[3];
+        x q[1];
+        measure q -> c;
+        measure q[1] -> c[0];
+        "#,
+    )?;
+    assert_eq!(circuit.classical_registers, vec![("c".to_string(), 3)]);
+    assert_eq!(circuit.measurements.len(), 4);
+    let handles: Vec<_> = circuit
+        .measurements
+        .iter()
+        .map(|(c, i, h)| (c.clone(), *i, h.clone()))
+        .collect();
+    let r = circuit.merge_registers(&mut b)?;
+    let (_, measured) = run_local::<f64>(&r)?;
+
+    let values: Vec<_> = handles
+        .iter()
+        .map(|(_, i, h)| (*i, measured.get_measurement(h).unwrap().0))
+        .collect();
+    assert_eq!(values, vec![(0, 0), (1, 1), (2, 0), (0, 1)]);
+    Ok(())
+}
+
+#[test]
+fn test_errors() {
+    let sources = [
+        "qreg q[2]; foo q[0];",
+        "qreg q[2]; h q[2];",
+        "qreg q[2]; cx q[0], q[0];",
+        "qreg q[2]; rx q[0];",
+        "qreg q[2]; creg c[2]; if(c==1) x q[0];",
+        "qreg q[2]; qreg r[3]; cx q, r;",
+        "qreg q[2]; h r[0];",
+        "qreg q[2]; h q[0]",
+        "qreg q[2]; rx(theta) q[0];",
+    ];
+    sources.iter().for_each(|source| {
+        let mut b = OpBuilder::new();
+        assert!(parse_qasm(&mut b, source).is_err(), "{}", source);
+    });
+}
+
+#[test]
+fn test_error_line() {
+    let mut b = OpBuilder::new();
+    let err = parse_qasm(&mut b, "qreg q[1];\nh q[0];\nbad q[0];").unwrap_err();
+    assert!(format!("{}", err).contains("line 3"), "{}", err);
+}
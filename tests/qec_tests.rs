@@ -0,0 +1,70 @@
+extern crate qip;
+
+use qip::qec::measure_stabilizer;
+use qip::*;
+
+#[test]
+fn test_measure_ghz_stabilizers() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let data = b.register(3)?;
+    let ancilla = b.qubit();
+    let (q, rest) = b.split(data, &[0])?;
+    let q = b.hadamard(q);
+    let (q, rest) = b.cnot(q, rest.unwrap());
+    let data = b.merge(vec![q, rest])?;
+
+    let mut ancilla = ancilla;
+    let mut data = data;
+    let mut handles = vec![];
+    for pauli in ["XXX", "ZZ", "IZZ", "YYX", "Z"].iter() {
+        let (a, d, m) = measure_stabilizer(&mut b, ancilla, data, pauli)?;
+        ancilla = b.reset(a);
+        data = d;
+        handles.push(m);
+    }
+    let r = b.merge(vec![ancilla, data])?;
+    let (_, measured) = run_local::<f64>(&r)?;
+    let values: Vec<u64> = handles
+        .iter()
+        .map(|m| measured.get_measurement(m).unwrap().0)
+        .collect();
+    // The GHZ state is a +1 eigenstate of XXX, ZZI and IZZ, and a -1 eigenstate of YYX.
+    assert_eq!(&values[..4], &[0, 0, 0, 1]);
+    Ok(())
+}
+
+#[test]
+fn test_measure_stabilizer_projects() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let ancilla = b.qubit();
+    let (ancilla, q, m) = measure_stabilizer(&mut b, ancilla, q, "X")?;
+    let r = b.merge(vec![q, ancilla])?;
+
+    let (state, measured) = run_local::<f64>(&r)?;
+    let (value, p) = measured.get_measurement(&m).unwrap();
+    assert!((p - 0.5).abs() < 1e-10);
+    // The data is left as |+> or |-> depending on the outcome.
+    let state = state.get_state(true);
+    let sign = if value == 0 { 1.0 } else { -1.0 };
+    let a = std::f64::consts::FRAC_1_SQRT_2;
+    let offset = (value as usize) << 1;
+    assert!((state[offset].re - a).abs() < 1e-10);
+    assert!((state[offset | 1].re - sign * a).abs() < 1e-10);
+    Ok(())
+}
+
+#[test]
+fn test_measure_stabilizer_errors() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let data = b.register(2)?;
+    let ancilla = b.register(2)?;
+    assert!(measure_stabilizer(&mut b, ancilla, data, "ZZ").is_err());
+    let data = b.register(2)?;
+    let ancilla = b.qubit();
+    assert!(measure_stabilizer(&mut b, ancilla, data, "ZZZ").is_err());
+    let data = b.register(2)?;
+    let ancilla = b.qubit();
+    assert!(measure_stabilizer(&mut b, ancilla, data, "ZA").is_err());
+    Ok(())
+}
@@ -0,0 +1,88 @@
+extern crate qip;
+
+use qip::qram::qram;
+use qip::*;
+
+#[test]
+fn test_qram_classical_addresses() -> Result<(), CircuitError> {
+    let memory = [5, 0, 7, 2, 1, 6, 3, 4];
+    for a in 0..8 {
+        let mut b = OpBuilder::new();
+        let address = b.register(3)?;
+        let data = b.register(3)?;
+        let routers = b.register(8)?;
+        let handle = address.handle();
+        let (address, data, routers) = qram(&mut b, address, data, &memory, Some(routers))?;
+        let (address, ma) = b.measure(address);
+        let (data, md) = b.measure(data);
+        let (routers, mr) = b.measure(routers.unwrap());
+        let r = b.merge(vec![address, data, routers])?;
+        let (_, measured) = run_local_with_init::<f64>(&r, &[handle.make_init_from_index(a)?])?;
+        assert_eq!(measured.get_measurement(&ma).unwrap().0, a);
+        assert_eq!(measured.get_measurement(&md).unwrap().0, memory[a as usize]);
+        // The routers are returned to |0>.
+        assert_eq!(measured.get_measurement(&mr).unwrap().0, 0);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_qram_xors_data() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let address = b.register(1)?;
+    let data = b.register(2)?;
+    let data = b.not(data);
+    let handle = address.handle();
+    let (_, data, routers) = qram(&mut b, address, data, &[1], None)?;
+    assert!(routers.is_none());
+    let (data, m) = b.measure(data);
+    // Address 1 is past the end of memory and reads as 0.
+    for (a, expected) in [(0, 0b10), (1, 0b11)].iter() {
+        let init = [handle.make_init_from_index(*a)?];
+        let (_, measured) = run_local_with_init::<f64>(&data, &init)?;
+        assert_eq!(measured.get_measurement(&m).unwrap().0, *expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_qram_superposition() -> Result<(), CircuitError> {
+    let memory = [2, 3, 1, 0];
+    let mut b = OpBuilder::new();
+    let address = b.register(2)?;
+    let data = b.register(2)?;
+    let routers = b.register(5)?;
+    let address = b.hadamard(address);
+    let (address, data, routers) = qram(&mut b, address, data, &memory, Some(routers))?;
+    let routers = routers.unwrap();
+    assert_eq!(routers.n(), 5);
+    let r = b.merge(vec![address, data, routers])?;
+    let (state, _) = run_local::<f64>(&r)?;
+    let state = state.get_state_for_registers(&[&r])?;
+    // sum_a |a>|memory[a]> / 2 with the routers left as |0>.
+    state.iter().enumerate().for_each(|(index, amplitude)| {
+        let (a, d) = (index & 0b11, (index >> 2) & 0b11);
+        let expected = if index >> 4 == 0 && d as u64 == memory[a] {
+            0.5
+        } else {
+            0.0
+        };
+        assert!((amplitude - Complex::new(expected, 0.0)).norm() < 1e-10);
+    });
+    Ok(())
+}
+
+#[test]
+fn test_qram_errors() {
+    let mut b = OpBuilder::new();
+    let address = b.register(1).unwrap();
+    let data = b.register(2).unwrap();
+    assert!(qram(&mut b, address, data, &[0, 1, 2], None).is_err());
+    let address = b.register(1).unwrap();
+    let data = b.register(2).unwrap();
+    assert!(qram(&mut b, address, data, &[4], None).is_err());
+    let address = b.register(2).unwrap();
+    let data = b.register(2).unwrap();
+    let routers = b.register(3).unwrap();
+    assert!(qram(&mut b, address, data, &[1], Some(routers)).is_err());
+}
@@ -0,0 +1,135 @@
+extern crate qip;
+
+use qip::quantum_walk::*;
+use qip::*;
+use std::f64::consts::FRAC_1_SQRT_2;
+
+/// Run a circuit on a coin and position of `n` qubits, starting from `coin_value` and `start`,
+/// and get the probability of each position.
+fn position_probabilities<F>(
+    n: u64,
+    coin_value: u64,
+    start: u64,
+    f: F,
+) -> Result<Vec<f64>, CircuitError>
+where
+    F: Fn(&mut OpBuilder, Register, Register) -> Result<(Register, Register), CircuitError>,
+{
+    let mut b = OpBuilder::new();
+    let coin = b.qubit();
+    let position = b.register(n)?;
+    let coin = if coin_value == 1 { b.not(coin) } else { coin };
+    let position = (0..n).try_fold(position, |position, j| {
+        if (start >> j) & 1 == 1 {
+            let (q, rest) = b.split(position, &[j])?;
+            let q = b.not(q);
+            b.merge_with_indices(rest.unwrap(), vec![q], &[j])
+        } else {
+            Ok(position)
+        }
+    })?;
+    let (coin, position) = f(&mut b, coin, position)?;
+    let r = b.merge(vec![coin, position])?;
+    let (state, _) = run_local::<f64>(&r)?;
+    let state = state.get_state_for_registers(&[&r])?;
+    Ok((0..1 << n)
+        .map(|x| state[x << 1].norm_sqr() + state[(x << 1) | 1].norm_sqr())
+        .collect())
+}
+
+/// Classically simulate the hadamard walk on a cycle of `size` sites.
+fn hadamard_walk(size: usize, steps: usize, coin_value: usize) -> Vec<f64> {
+    // amplitudes[x][c] for position x and coin c.
+    let mut amplitudes = vec![[0.0; 2]; size];
+    amplitudes[0][coin_value] = 1.0;
+    (0..steps).for_each(|_| {
+        let mut next = vec![[0.0; 2]; size];
+        amplitudes.iter().enumerate().for_each(|(x, [a, b])| {
+            let (zero, one) = ((a + b) * FRAC_1_SQRT_2, (a - b) * FRAC_1_SQRT_2);
+            next[(x + size - 1) % size][0] += zero;
+            next[(x + 1) % size][1] += one;
+        });
+        amplitudes = next;
+    });
+    amplitudes.iter().map(|[a, b]| a * a + b * b).collect()
+}
+
+fn assert_close(a: &[f64], b: &[f64]) {
+    assert_eq!(a.len(), b.len());
+    a.iter().zip(b.iter()).for_each(|(x, y)| {
+        assert!((x - y).abs() < 1e-10, "{:?} != {:?}", a, b);
+    });
+}
+
+#[test]
+fn test_cycle_shift() -> Result<(), CircuitError> {
+    let shifted = |coin_value, start| {
+        position_probabilities(3, coin_value, start, |b, coin, position| {
+            cycle_shift(b, coin, position, 5)
+        })
+        .map(|ps| ps.iter().position(|p| (p - 1.0).abs() < 1e-10))
+    };
+    assert_eq!(shifted(1, 0)?, Some(1));
+    assert_eq!(shifted(1, 4)?, Some(0));
+    assert_eq!(shifted(0, 0)?, Some(4));
+    assert_eq!(shifted(0, 3)?, Some(2));
+    // Values outside of the cycle are left alone.
+    assert_eq!(shifted(1, 6)?, Some(6));
+    assert_eq!(shifted(0, 5)?, Some(5));
+    Ok(())
+}
+
+#[test]
+fn test_line_shift() -> Result<(), CircuitError> {
+    let ps = position_probabilities(2, 0, 0, |b, coin, position| line_shift(b, coin, position))?;
+    assert_close(&ps, &[0.0, 0.0, 0.0, 1.0]);
+    assert_eq!(line_position(3, 2), -1);
+    assert_eq!(line_position(1, 2), 1);
+    assert_eq!(line_position(0b100, 3), -4);
+    assert_eq!(line_position(0, 0), 0);
+    Ok(())
+}
+
+#[test]
+fn test_hadamard_cycle_walk() -> Result<(), CircuitError> {
+    for coin_value in 0..2 {
+        let ps = position_probabilities(2, coin_value, 0, |b, coin, position| {
+            cycle_walk(b, coin, position, 3, 5, |b, coin| Ok(b.hadamard(coin)))
+        })?;
+        let mut expected = hadamard_walk(3, 5, coin_value as usize);
+        expected.push(0.0);
+        assert_close(&ps, &expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_hadamard_line_walk() -> Result<(), CircuitError> {
+    // Five steps stay within 16 sites, so the walk matches the one on a cycle of 16.
+    let ps = position_probabilities(4, 0, 0, |b, coin, position| {
+        line_walk(b, coin, position, 5, |b, coin| Ok(b.hadamard(coin)))
+    })?;
+    assert_close(&ps, &hadamard_walk(16, 5, 0));
+    // The hadamard walk from |0> drifts towards negative positions.
+    let mean: f64 = ps
+        .iter()
+        .enumerate()
+        .map(|(x, p)| line_position(x as u64, 4) as f64 * p)
+        .sum();
+    assert!(mean < -0.5);
+    Ok(())
+}
+
+#[test]
+fn test_walk_errors() {
+    let mut b = OpBuilder::new();
+    let coin = b.register(2).unwrap();
+    let position = b.register(2).unwrap();
+    assert!(cycle_shift(&mut b, coin, position, 3).is_err());
+    let coin = b.qubit();
+    let position = b.register(2).unwrap();
+    assert!(cycle_shift(&mut b, coin, position, 5).is_err());
+    let coin = b.qubit();
+    let position = b.register(2).unwrap();
+    assert!(cycle_shift(&mut b, coin, position, 0).is_err());
+}
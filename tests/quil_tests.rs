@@ -0,0 +1,188 @@
+extern crate qip;
+
+use qip::quil::to_quil;
+use qip::state_ops::from_reals;
+use qip::*;
+
+fn assert_almost_eq(a: f64, b: f64, prec: i32) {
+    let mult = 10.0f64.powi(prec);
+    let (a, b) = (a * mult, b * mult);
+    let (a, b) = (a.round(), b.round());
+    assert_eq!(a / mult, b / mult);
+}
+
+fn lines(program: &str) -> Vec<&str> {
+    program.lines().collect()
+}
+
+#[test]
+fn test_single_qubit_gates() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let q = b.x(q);
+    let q = b.y(q);
+    let q = b.z(q);
+    let q = b.s(q);
+    let q = b.sdagger(q);
+    let q = b.t(q);
+    let q = b.tdagger(q);
+    let q = b.rx(q, 0.5);
+    let q = b.ry(q, -0.25);
+    let q = b.rz(q, 1.5);
+
+    let program = to_quil(&q)?;
+    assert_eq!(
+        lines(&program),
+        vec![
+            "X 0",
+            "Y 0",
+            "Z 0",
+            "S 0",
+            "DAGGER S 0",
+            "T 0",
+            "DAGGER T 0",
+            "RX(0.5) 0",
+            "RY(-0.25) 0",
+            "RZ(1.5) 0",
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_controlled_gates() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let ra = b.register(2)?;
+    let q = b.qubit();
+    let r = b.qubit();
+    let (ra, q) = b.cnot(ra, q);
+    let (q, r) = b.cnot(q, r);
+    let (q, r) = b.cz(q, r);
+    let (q, r) = b.cy(q, r);
+    let (q, r) = b.cs(q, r);
+    let (ra, (q, r)) = condition(&mut b, ra, (q, r), |b, (q, r)| b.swap(q, r).unwrap());
+    let phase = vec![
+        Complex::new(1.0, 0.0),
+        Complex::new(0.0, 0.0),
+        Complex::new(0.0, 0.0),
+        Complex::new(0.3f64.cos(), 0.3f64.sin()),
+    ];
+    let (q, r) = b.cmat("P", q, r, phase)?;
+    let r = b.merge(vec![ra, q, r])?;
+
+    let program = to_quil(&r)?;
+    assert_eq!(
+        lines(&program),
+        vec![
+            "CCNOT 0 1 2",
+            "CNOT 2 3",
+            "CZ 2 3",
+            "CONTROLLED Y 2 3",
+            "CONTROLLED S 2 3",
+            "CONTROLLED CONTROLLED SWAP 0 1 2 3",
+            "CPHASE(0.3) 2 3",
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_controlled_phase() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(4)?;
+    let r = qft(&mut b, r)?;
+    let program = to_quil(&r)?;
+    // R2 and R3 are the S and T gates.
+    let program_lines = lines(&program);
+    assert_eq!(
+        program_lines[..3],
+        ["H 3", "CONTROLLED S 2 3", "CONTROLLED T 1 3"]
+    );
+    let r4 = program_lines[3];
+    assert!(r4.starts_with("CPHASE(") && r4.ends_with(") 0 3"));
+    let phi: f64 = r4["CPHASE(".len()..r4.len() - ") 0 3".len()]
+        .parse()
+        .unwrap();
+    assert_almost_eq(phi, std::f64::consts::PI / 8.0, 10);
+    Ok(())
+}
+
+#[test]
+fn test_defgate() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(2)?;
+    let half = 0.5;
+    let mat = from_reals(&[
+        half, half, half, half, //
+        half, -half, half, -half, //
+        half, half, -half, -half, //
+        half, -half, -half, half,
+    ]);
+    let r = b.mat("HH", r, mat.clone())?;
+    let r = b.mat("HH", r, mat)?;
+    let program = to_quil(&r)?;
+    assert_eq!(
+        lines(&program),
+        vec![
+            "DEFGATE GATE0:",
+            "    0.5, 0.5, 0.5, 0.5",
+            "    0.5, -0.5, 0.5, -0.5",
+            "    0.5, 0.5, -0.5, -0.5",
+            "    0.5, -0.5, -0.5, 0.5",
+            "",
+            "GATE0 0 1",
+            "GATE0 0 1",
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_measure_and_reset() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let (q, mq) = b.measure_basis(q, 0.25);
+    let r = b.reset(r);
+    let (r, mr) = b.measure(r);
+    let r = b.merge(vec![q, r])?;
+
+    let program = to_quil(&r)?;
+    let mut expected = vec![
+        format!("DECLARE m{} BIT[1]", mq.get_id()),
+        format!("DECLARE m{} BIT[1]", mr.get_id()),
+        "RY(0.5) 0".to_string(),
+        format!("MEASURE 0 m{}[0]", mq.get_id()),
+        "RY(-0.5) 0".to_string(),
+        "RESET 1".to_string(),
+        format!("MEASURE 1 m{}[0]", mr.get_id()),
+    ];
+    let mut found: Vec<String> = lines(&program).into_iter().map(String::from).collect();
+    // The two qubits are independent so their relative order is not fixed.
+    expected.sort();
+    found.sort();
+    assert_eq!(found, expected);
+    Ok(())
+}
+
+#[test]
+fn test_unsupported() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let (q, _) = b.stochastic_measure(q);
+    assert!(to_quil(&q).is_err());
+
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let q = b.channel("bitflip", q, vec![from_reals(&[0.0, 1.0, 1.0, 0.0])])?;
+    assert!(to_quil(&q).is_err());
+
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let (q, m) = b.measure(q);
+    let r = b.classical_if(r, &m, Box::new(|b, r| Ok(b.not(r))));
+    let r = b.merge(vec![q, r])?;
+    assert!(to_quil(&r).is_err());
+    Ok(())
+}
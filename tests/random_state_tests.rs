@@ -0,0 +1,87 @@
+extern crate qip;
+
+use qip::pipeline::LocalQuantumState;
+use qip::rng::with_seed;
+use qip::stats::{entanglement_entropy, reduced_density_matrix};
+use qip::*;
+
+fn norm(state: &LocalQuantumState<f64>) -> f64 {
+    state.state_ref().iter().map(|c| c.norm_sqr()).sum()
+}
+
+#[test]
+fn test_haar_random_normalized() {
+    let state = LocalQuantumState::<f64>::haar_random(4);
+    assert_eq!(state.n(), 4);
+    assert_eq!(state.state_ref().len(), 16);
+    assert!((norm(&state) - 1.0).abs() < 1e-10);
+}
+
+#[test]
+fn test_random_states_seeded() {
+    let a = with_seed(1, || LocalQuantumState::<f64>::haar_random(3));
+    let b = with_seed(1, || LocalQuantumState::<f64>::haar_random(3));
+    let c = with_seed(2, || LocalQuantumState::<f64>::haar_random(3));
+    assert_eq!(a.state_ref(), b.state_ref());
+    assert_ne!(a.state_ref(), c.state_ref());
+
+    let a = with_seed(1, || LocalQuantumState::<f64>::random_product_state(3));
+    let b = with_seed(1, || LocalQuantumState::<f64>::random_product_state(3));
+    assert_eq!(a.state_ref(), b.state_ref());
+}
+
+#[test]
+fn test_random_product_state_unentangled() -> Result<(), CircuitError> {
+    let state = with_seed(3, || LocalQuantumState::<f64>::random_product_state(4));
+    assert!((norm(&state) - 1.0).abs() < 1e-10);
+    assert!(entanglement_entropy(&state, &[0])?.abs() < 1e-8);
+    assert!(entanglement_entropy(&state, &[1, 3])?.abs() < 1e-8);
+    Ok(())
+}
+
+#[test]
+fn test_haar_random_typical() -> Result<(), CircuitError> {
+    // A typical state of many qubits is close to maximally entangled with any small subsystem.
+    let state = with_seed(4, || LocalQuantumState::<f64>::haar_random(10));
+    assert!(entanglement_entropy(&state, &[0])? > 0.95);
+    let rho = reduced_density_matrix(&state, &[0])?;
+    assert!((rho[0].re - 0.5).abs() < 0.05);
+    assert!(rho[1].norm() < 0.05);
+    Ok(())
+}
+
+#[test]
+fn test_haar_random_average() -> Result<(), CircuitError> {
+    // Averaged over states <Z> is zero and |<0|psi>|^2 is 1 / 2^n.
+    let (z, p) = with_seed(5, || -> Result<(f64, f64), CircuitError> {
+        let mut z = 0.0;
+        let mut p = 0.0;
+        for _ in 0..500 {
+            let state = LocalQuantumState::<f64>::haar_random(2);
+            let rho = reduced_density_matrix(&state, &[0])?;
+            z += rho[0].re - rho[3].re;
+            p += state.state_ref()[0].norm_sqr();
+        }
+        Ok((z / 500.0, p / 500.0))
+    })?;
+    assert!(z.abs() < 0.1);
+    assert!((p - 0.25).abs() < 0.05);
+    Ok(())
+}
+
+#[test]
+fn test_run_from_random_state() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(2)?;
+    let r = b.hadamard(r);
+    let r = b.hadamard(r);
+    let state = with_seed(6, || LocalQuantumState::<f64>::haar_random(2));
+    let expected = state.state_ref().clone();
+    let (state, _) = run_with_state(&r, state)?;
+    state
+        .state_ref()
+        .iter()
+        .zip(expected.iter())
+        .for_each(|(a, b)| assert!((a - b).norm() < 1e-10));
+    Ok(())
+}
@@ -0,0 +1,134 @@
+extern crate qip;
+
+use qip::circuit_stats::CircuitStats;
+use qip::*;
+
+fn assert_almost_eq(a: f64, b: f64, prec: i32) {
+    let mult = 10.0f64.powi(prec);
+    let (a, b) = (a * mult, b * mult);
+    let (a, b) = (a.round(), b.round());
+    assert_eq!(a / mult, b / mult);
+}
+
+fn step(b: &mut OpBuilder, rs: Vec<Register>) -> Result<Vec<Register>, CircuitError> {
+    let mut rs = rs.into_iter();
+    let q = rs.next().unwrap();
+    let r = rs.next().unwrap();
+    let q = b.rx(q, 0.3);
+    let r = b.ry(r, -0.7);
+    let (q, r) = b.cnot(q, r);
+    Ok(vec![q, r])
+}
+
+#[test]
+fn test_repeat_matches_loop() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let rs = b.repeat(3, vec![q, r], step)?;
+    let r = b.merge(rs)?;
+    let (repeated, _) = run_local::<f64>(&r)?;
+
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let rs = (0..3).try_fold(vec![q, r], |rs, _| step(&mut b, rs))?;
+    let r = b.merge(rs)?;
+    let (looped, _) = run_local::<f64>(&r)?;
+
+    repeated
+        .get_state(true)
+        .into_iter()
+        .zip(looped.get_state(true))
+        .for_each(|(a, b)| {
+            assert_almost_eq(a.re, b.re, 10);
+            assert_almost_eq(a.im, b.im, 10);
+        });
+    Ok(())
+}
+
+#[test]
+fn test_repeat_stats() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let rs = b.repeat(5, vec![q, r], step)?;
+    let r = b.merge(rs)?;
+
+    let stats = CircuitStats::new(&r);
+    assert_eq!(stats.two_qubit_gates, 5);
+    assert_eq!(stats.total_gates(), 15);
+    assert_eq!(stats.depth, 10);
+    Ok(())
+}
+
+#[test]
+fn test_repeat_zero() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let rs = b.repeat(0, vec![q, r], step)?;
+    let r = b.merge(rs)?;
+    assert_eq!(CircuitStats::new(&r).total_gates(), 0);
+    Ok(())
+}
+
+#[test]
+fn test_repeat_allocating_err() {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let res = b.repeat(2, vec![q], |b, rs| {
+        let t = b.get_temp_register(1, false);
+        b.return_temp_register(t, false);
+        Ok(rs)
+    });
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_repeat_named_gate() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    b.define_gate("step", &[1, 1], step)?;
+    let q = b.qubit();
+    let r = b.qubit();
+    let rs = b.repeat(3, vec![q, r], |b, rs| b.apply_gate("step", rs))?;
+    let r = b.merge(rs)?;
+    let (repeated, _) = run_local::<f64>(&r)?;
+
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let rs = (0..3).try_fold(vec![q, r], |rs, _| step(&mut b, rs))?;
+    let r = b.merge(rs)?;
+    let (looped, _) = run_local::<f64>(&r)?;
+
+    repeated
+        .get_state(true)
+        .into_iter()
+        .zip(looped.get_state(true))
+        .for_each(|(a, b)| {
+            assert_almost_eq(a.re, b.re, 10);
+            assert_almost_eq(a.im, b.im, 10);
+        });
+    Ok(())
+}
+
+#[test]
+fn test_nested_repeat() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let rs = b.repeat(2, vec![q, r], |b, rs| {
+        let mut rs = rs.into_iter();
+        let q = rs.next().unwrap();
+        let r = b.hadamard(rs.next().unwrap());
+        // Shifted onto the second qubit of the outer repeat.
+        let mut r = b.repeat(2, vec![r], |b, mut rs| Ok(vec![b.s(rs.pop().unwrap())]))?;
+        Ok(vec![q, r.pop().unwrap()])
+    })?;
+    let r = b.merge(rs)?;
+    let (state, _) = run_local::<f64>(&r)?;
+    // (Z H)^2 |0> = -|1> on the second qubit.
+    assert_almost_eq(state.get_state(true)[0b10].re, -1.0, 10);
+    Ok(())
+}
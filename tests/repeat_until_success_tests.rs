@@ -0,0 +1,150 @@
+extern crate qip;
+
+use qip::pipeline::{run_shots, LocalQuantumState};
+use qip::repeat_until_success::repeat_until_success;
+use qip::*;
+
+/// Each attempt applies `Rz(theta)` to `r` if the flag measures `|0>`, and `Z Rz(theta)` if it
+/// measures `|1>`, which the recovery undoes.
+fn rz_attempt(
+    b: &mut dyn UnitaryBuilder,
+    r: Register,
+    flag: Register,
+    theta: f64,
+) -> Result<(Register, Register), CircuitError> {
+    let flag = b.hadamard(flag);
+    let (flag, r) = b.crz(flag, r, 2.0 * theta);
+    let flag = b.hadamard(flag);
+    Ok((r, flag))
+}
+
+#[test]
+fn test_recovers_failed_attempts() -> Result<(), CircuitError> {
+    let theta = 0.9;
+    let mut saw_failure = false;
+    for _ in 0..20 {
+        let mut b = OpBuilder::new();
+        let r = b.qubit();
+        let r = b.ry(r, 0.7);
+        let flag = b.qubit();
+        let (r, flag, rus) = repeat_until_success(
+            &mut b,
+            r,
+            flag,
+            0,
+            20,
+            move |b, r, flag| rz_attempt(b, r, flag, theta),
+            move |b, r| {
+                let r = b.rz(r, -theta);
+                Ok(b.z(r))
+            },
+        )?;
+        let r = b.merge(vec![r, flag])?;
+        let (state, measured) = run_local::<f64>(&r)?;
+        assert_eq!(measured.get_classical(&rus.succeeded()), Some(1));
+        let attempts = rus.attempts(&measured).unwrap();
+        saw_failure |= attempts > 1;
+
+        // The flag ends in |0>, leaving Rz(theta) Ry(0.7) |0> on r.
+        let state = state.get_state(true);
+        let (c, s) = ((0.35f64).cos(), (0.35f64).sin());
+        let expected = [
+            Complex::from_polar(&c, &(-theta / 2.0)),
+            Complex::from_polar(&s, &(theta / 2.0)),
+        ];
+        let overlap = expected[0].conj() * state[0] + expected[1].conj() * state[1];
+        assert!((overlap.norm() - 1.0).abs() < 1e-10);
+    }
+    // Each attempt fails with probability sin^2(theta / 2), about 0.19.
+    assert!(saw_failure);
+    Ok(())
+}
+
+#[test]
+fn test_iteration_cap() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.qubit();
+    let flag = b.qubit();
+    let (r, flag, rus) = repeat_until_success(
+        &mut b,
+        r,
+        flag,
+        1,
+        3,
+        |b, r, flag| {
+            let flag = b.hadamard(flag);
+            Ok(b.cnot(flag, r))
+        },
+        |_, r| Ok(r),
+    )?;
+    assert_eq!(rus.flags().len(), 3);
+    let r = b.merge(vec![r, flag])?;
+
+    let branches = run_shots::<f64, LocalQuantumState<f64>>(&r, 800)?;
+    let mut attempts = vec![0; 4];
+    branches.iter().for_each(|branch| {
+        let succeeded = branch.measured.get_classical(&rus.succeeded()).unwrap();
+        // Success leaves both r and the flag as |1>, giving up leaves both as |0>.
+        let expected = if succeeded == 1 { 0b11 } else { 0b00 };
+        assert_eq!(branch.counts.get(&expected), Some(&branch.shots()));
+        let n = rus.attempts(&branch.measured).unwrap();
+        let n = if succeeded == 1 { n } else { 0 };
+        attempts[n] += branch.shots();
+    });
+    // Success on attempts 1, 2, 3 or never with probabilities 1/2, 1/4, 1/8 and 1/8.
+    [400, 200, 100]
+        .iter()
+        .zip(&attempts[1..])
+        .for_each(|(expected, found)| {
+            assert!(
+                (*found as i64 - *expected as i64).abs() < 80,
+                "{:?}",
+                attempts
+            );
+        });
+    assert_eq!(attempts.iter().sum::<usize>(), 800);
+    Ok(())
+}
+
+#[test]
+fn test_multi_qubit_flags() -> Result<(), CircuitError> {
+    // Succeeds only when both flags measure |1>, failed flags are returned to |00> before the
+    // next attempt.
+    let mut b = OpBuilder::new();
+    let r = b.qubit();
+    let flags = b.register(2)?;
+    let (r, flags, rus) = repeat_until_success(
+        &mut b,
+        r,
+        flags,
+        0b11,
+        30,
+        |b, r, flags| {
+            let flags = b.hadamard(flags);
+            let (flags, r) = b.cnot(flags, r);
+            Ok((r, flags))
+        },
+        |_, r| Ok(r),
+    )?;
+    let r = b.merge(vec![r, flags])?;
+    for _ in 0..10 {
+        let (state, measured) = run_local::<f64>(&r)?;
+        if measured.get_classical(&rus.succeeded()) == Some(1) {
+            assert!((state.get_state(true)[0b111].norm() - 1.0).abs() < 1e-10);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_errors() {
+    let mut b = OpBuilder::new();
+    let r = b.qubit();
+    let flag = b.qubit();
+    let attempt = |_: &mut dyn UnitaryBuilder, r, flag| Ok((r, flag));
+    let recover = |_: &mut dyn UnitaryBuilder, r| Ok(r);
+    assert!(repeat_until_success(&mut b, r, flag, 1, 0, attempt, recover).is_err());
+    let r = b.qubit();
+    let flag = b.qubit();
+    assert!(repeat_until_success(&mut b, r, flag, 2, 5, attempt, recover).is_err());
+}
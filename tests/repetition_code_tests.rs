@@ -0,0 +1,106 @@
+extern crate qip;
+
+use qip::qec::repetition::RepetitionCode;
+use qip::*;
+
+#[test]
+fn test_errors_from_syndrome() -> Result<(), CircuitError> {
+    let code = RepetitionCode::new(3)?;
+    let cases: &[(&[bool], &[u64])] = &[
+        (&[false, false], &[]),
+        (&[true, false], &[0]),
+        (&[true, true], &[1]),
+        (&[false, true], &[2]),
+    ];
+    for (syndrome, expected) in cases {
+        assert_eq!(code.errors_from_syndrome(syndrome), expected.to_vec());
+    }
+
+    let code = RepetitionCode::new(5)?;
+    assert_eq!(
+        code.errors_from_syndrome(&[true, false, false, true]),
+        vec![0, 4]
+    );
+    assert_eq!(
+        code.errors_from_syndrome(&[false, true, false, true]),
+        vec![2, 3]
+    );
+    assert_eq!(
+        code.errors_from_syndrome(&[true, false, true, false]),
+        vec![1, 2]
+    );
+    Ok(())
+}
+
+/// Encode `ry(theta)|0>`, flip the data qubits in `errors`, then correct and decode, checking the
+/// logical qubit comes back unchanged.
+fn check_correction(n: u64, errors: &[u64]) -> Result<(), CircuitError> {
+    let theta: f64 = 1.3;
+    let code = RepetitionCode::new(n)?;
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let q = b.ry(q, theta);
+    let data = code.encode(&mut b, q)?;
+    let data = b
+        .split_all(data)
+        .into_iter()
+        .enumerate()
+        .map(|(i, q)| {
+            if errors.contains(&(i as u64)) {
+                b.not(q)
+            } else {
+                q
+            }
+        })
+        .collect();
+    let data = b.merge(data)?;
+    let ancillas = b.register(code.num_ancilla_qubits())?;
+    let (data, ancillas, syndrome) = code.measure_syndrome(&mut b, data, ancillas)?;
+    let data = code.correct(&mut b, data, &syndrome)?;
+    // A second round finds nothing left to correct.
+    let (data, ancillas, second) = code.measure_syndrome(&mut b, data, ancillas)?;
+    let (q, rest) = code.decode(&mut b, data)?;
+    let r = b.merge(vec![q, rest, ancillas])?;
+
+    let (state, measured) = run_local::<f64>(&r)?;
+    let found = code.errors_from_syndrome(&code.syndrome_values(&syndrome, &measured));
+    assert_eq!(found, errors.to_vec());
+    assert!(code.syndrome_values(&second, &measured).iter().all(|s| !s));
+    let state = state.get_state(true);
+    assert!((state[0].re - (theta / 2.0).cos()).abs() < 1e-10);
+    assert!((state[1].re - (theta / 2.0).sin()).abs() < 1e-10);
+    Ok(())
+}
+
+#[test]
+fn test_correct_single_flips() -> Result<(), CircuitError> {
+    for n in [3, 5].iter() {
+        check_correction(*n, &[])?;
+        for i in 0..*n {
+            check_correction(*n, &[i])?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_correct_double_flips() -> Result<(), CircuitError> {
+    check_correction(5, &[0, 3])?;
+    check_correction(5, &[2, 4])
+}
+
+#[test]
+fn test_repetition_errors() -> Result<(), CircuitError> {
+    assert!(RepetitionCode::new(1).is_err());
+    assert!(RepetitionCode::new(4).is_err());
+    let code = RepetitionCode::new(3)?;
+    let mut b = OpBuilder::new();
+    let r = b.register(2)?;
+    assert!(code.encode(&mut b, r).is_err());
+    let data = b.register(3)?;
+    let ancillas = b.register(3)?;
+    assert!(code.measure_syndrome(&mut b, data, ancillas).is_err());
+    let data = b.register(3)?;
+    assert!(code.correct(&mut b, data, &[]).is_err());
+    Ok(())
+}
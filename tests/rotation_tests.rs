@@ -0,0 +1,114 @@
+extern crate num;
+extern crate qip;
+
+use num::{One, Zero};
+use qip::*;
+
+fn assert_almost_eq(a: f64, b: f64, prec: i32) {
+    let mult = 10.0f64.powi(prec);
+    let (a, b) = (a * mult, b * mult);
+    let (a, b) = (a.round(), b.round());
+    assert_eq!(a / mult, b / mult);
+}
+
+fn assert_state_almost_eq(a: &[Complex<f64>], b: &[Complex<f64>]) {
+    assert_eq!(a.len(), b.len());
+    a.iter().zip(b.iter()).for_each(|(a, b)| {
+        assert_almost_eq(a.re, b.re, 10);
+        assert_almost_eq(a.im, b.im, 10);
+    });
+}
+
+#[test]
+fn test_rx_broadcast() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(2)?;
+    let r = b.rx(r, std::f64::consts::PI);
+    let (state, _) = run_local::<f64>(&r)?;
+
+    // Rx(pi) = -iX on each qubit, so |00> goes to -|11>
+    let expected = vec![
+        Complex::zero(),
+        Complex::zero(),
+        Complex::zero(),
+        Complex { re: -1.0, im: 0.0 },
+    ];
+    assert_state_almost_eq(&state.get_state(true), &expected);
+    Ok(())
+}
+
+#[test]
+fn test_ry_rz_broadcast() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(2)?;
+    let r = b.ry(r, std::f64::consts::FRAC_PI_2);
+    let r = b.rz(r, std::f64::consts::PI);
+    let (state, _) = run_local::<f64>(&r)?;
+
+    // Ry(pi/2)|0> = |+>, Rz(pi)|+> = -i|->
+    let half = Complex { re: 0.5, im: 0.0 };
+    let i = Complex { re: 0.0, im: 1.0 };
+    let single = [-i * half.sqrt(), i * half.sqrt()];
+    let expected: Vec<Complex<f64>> = (0..4)
+        .map(|indx| single[indx & 1] * single[(indx >> 1) & 1])
+        .collect();
+    assert_state_almost_eq(&state.get_state(true), &expected);
+    Ok(())
+}
+
+#[test]
+fn test_crx_off() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let cr = b.qubit();
+    let r = b.qubit();
+    let (cr, r) = b.crx(cr, r, std::f64::consts::PI);
+    let r = b.merge(vec![cr, r])?;
+    let (state, _) = run_local::<f64>(&r)?;
+
+    let expected = vec![
+        Complex::one(),
+        Complex::zero(),
+        Complex::zero(),
+        Complex::zero(),
+    ];
+    assert_state_almost_eq(&state.get_state(true), &expected);
+    Ok(())
+}
+
+#[test]
+fn test_cry_on() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let cr = b.qubit();
+    let r = b.qubit();
+    let cr = b.x(cr);
+    let (cr, r) = b.cry(cr, r, std::f64::consts::PI);
+    let (r, m) = b.measure(r);
+    let r = b.merge(vec![cr, r])?;
+    let (_, measured) = run_local::<f64>(&r)?;
+
+    let (m, p) = measured.get_measurement(&m).unwrap();
+    assert_eq!(m, 1);
+    assert_almost_eq(p, 1.0, 10);
+    Ok(())
+}
+
+#[test]
+fn test_crz_on() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let cr = b.qubit();
+    let r = b.qubit();
+    let cr = b.x(cr);
+    let (cr, r) = b.crz(cr, r, std::f64::consts::PI);
+    let r = b.merge(vec![cr, r])?;
+    let (state, _) = run_local::<f64>(&r)?;
+
+    // Control qubit is |1>, Rz(pi)|0> = -i|0>
+    let expected = vec![
+        Complex::zero(),
+        Complex { re: 0.0, im: -1.0 },
+        Complex::zero(),
+        Complex::zero(),
+    ];
+    assert_state_almost_eq(&state.get_state(true), &expected);
+    Ok(())
+}
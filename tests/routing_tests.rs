@@ -0,0 +1,135 @@
+extern crate qip;
+
+use qip::pipeline::{get_opfns_and_frontier, StateModifierType};
+use qip::routing::{route, route_with_layout, CouplingMap, RoutedCircuit};
+use qip::state_ops::{get_index, num_indices};
+use qip::*;
+
+/// Check every op on more than one qubit acts on connected physical qubits.
+fn assert_connected(routed: &RoutedCircuit, coupling: &CouplingMap) {
+    let (_, ops) = get_opfns_and_frontier(&routed.register);
+    ops.iter().for_each(|op| {
+        if let StateModifierType::UnitaryOp(op) = &op.modifier {
+            let indices: Vec<u64> = (0..num_indices(op)).map(|i| get_index(op, i)).collect();
+            assert!(indices.len() <= 2);
+            if indices.len() == 2 {
+                assert!(coupling.are_connected(indices[0], indices[1]));
+            }
+        }
+    });
+}
+
+/// Check the routed circuit prepares the same state as `r` once its qubits are moved back.
+fn assert_same_state(r: &Register, routed: &RoutedCircuit) -> Result<(), CircuitError> {
+    let (original, _) = run_local::<f64>(r)?;
+    let original = original.get_state(true);
+    let (state, _) = run_local::<f64>(&routed.register)?;
+    let state = state.get_state(true);
+    original.iter().enumerate().for_each(|(x, c)| {
+        let y: usize = routed
+            .final_layout
+            .iter()
+            .enumerate()
+            .map(|(q, p)| ((x >> q) & 1) << p)
+            .sum();
+        assert!((state[y] - c).norm() < 1e-10);
+    });
+    Ok(())
+}
+
+fn make_circuit(b: &mut OpBuilder, n: u64) -> Result<Register, CircuitError> {
+    let r = b.register(n)?;
+    let r = b.hadamard(r);
+    let mut qs: Vec<Option<Register>> = b.split_all(r).into_iter().map(Some).collect();
+    for i in 0..n as usize {
+        for j in 0..n as usize {
+            if i != j && (i + 2 * j) % 3 == 0 {
+                let (ci, cj) = b.cnot(qs[i].take().unwrap(), qs[j].take().unwrap());
+                qs[i] = Some(ci);
+                qs[j] = Some(b.rz(cj, 0.1 * (i + j) as f64));
+            }
+        }
+    }
+    b.merge(qs.into_iter().flatten().collect())
+}
+
+#[test]
+fn test_route_line() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = make_circuit(&mut b, 5)?;
+    let coupling = CouplingMap::line(5)?;
+    let routed = route(&r, &coupling)?;
+    assert!(routed.num_swaps > 0);
+    assert_eq!(routed.initial_layout, vec![0, 1, 2, 3, 4]);
+    assert_connected(&routed, &coupling);
+    assert_same_state(&r, &routed)
+}
+
+#[test]
+fn test_route_grid_with_spare_qubits() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = make_circuit(&mut b, 4)?;
+    let coupling = CouplingMap::grid(2, 3)?;
+    let routed = route_with_layout(&r, &coupling, &[5, 0, 4, 2])?;
+    assert_eq!(routed.register.n(), 6);
+    assert_connected(&routed, &coupling);
+    // The spare qubits on the device stay in the zero state.
+    assert_same_state(&r, &routed)
+}
+
+#[test]
+fn test_route_connected_circuit_unchanged() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let s = b.qubit();
+    let q = b.hadamard(q);
+    let (q, r) = b.cnot(q, r);
+    let (r, s) = b.cz(r, s);
+    let r = b.merge(vec![q, r, s])?;
+
+    let routed = route(&r, &CouplingMap::line(3)?)?;
+    assert_eq!(routed.num_swaps, 0);
+    assert_eq!(routed.final_layout, routed.initial_layout);
+    assert_same_state(&r, &routed)
+}
+
+#[test]
+fn test_route_keeps_measurements() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let s = b.qubit();
+    let q = b.not(q);
+    let (q, s) = b.cnot(q, s);
+    let (s, m) = b.measure(s);
+    let r = b.merge(vec![q, r, s])?;
+
+    let routed = route(&r, &CouplingMap::line(3)?)?;
+    let (_, measured) = run_local::<f64>(&routed.register)?;
+    assert_eq!(measured.get_measurement(&m).unwrap().0, 1);
+    Ok(())
+}
+
+#[test]
+fn test_route_errors() -> Result<(), CircuitError> {
+    assert!(CouplingMap::new(3, &[(0, 1)]).is_err());
+    assert!(CouplingMap::new(2, &[(0, 2)]).is_err());
+    assert!(CouplingMap::new(2, &[(1, 1)]).is_err());
+
+    let mut b = OpBuilder::new();
+    let r = b.register(3)?;
+    let coupling = CouplingMap::line(2)?;
+    assert!(route(&r, &coupling).is_err());
+    let coupling = CouplingMap::line(3)?;
+    assert!(route_with_layout(&r, &coupling, &[0, 1]).is_err());
+    assert!(route_with_layout(&r, &coupling, &[0, 1, 1]).is_err());
+
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.register(2)?;
+    let (r, q) = b.cnot(r, q);
+    let r = b.merge(vec![q, r])?;
+    assert!(route(&r, &coupling).is_err());
+    Ok(())
+}
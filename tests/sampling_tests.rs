@@ -0,0 +1,150 @@
+extern crate qip;
+
+use qip::parameters::Parameter;
+use qip::pipeline::{run_shots_with_parameters, LocalQuantumState};
+use qip::*;
+use std::collections::HashMap;
+
+#[test]
+fn test_sample_basis_state() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let r = b.not(r);
+    let r = b.merge(vec![q, r])?;
+
+    let counts = run_and_sample::<f64>(&r, 50)?;
+    assert_eq!(counts.len(), 1);
+    assert_eq!(counts.get(&0b10), Some(&50));
+    Ok(())
+}
+
+#[test]
+fn test_sample_bell_state() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.hadamard(q);
+    let (q, r) = b.cnot(q, r);
+    let r = b.merge(vec![q, r])?;
+
+    let shots = 1000;
+    let counts = run_and_sample::<f64>(&r, shots)?;
+    let zeros = *counts.get(&0b00).unwrap_or(&0);
+    let ones = *counts.get(&0b11).unwrap_or(&0);
+    assert_eq!(zeros + ones, shots);
+    // Far outside of any reasonable fluctuation.
+    assert!(zeros > 350 && ones > 350);
+    Ok(())
+}
+
+#[test]
+fn test_sample_subset() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.not(q);
+    let r = b.hadamard(r);
+    let qr = b.merge(vec![q, r])?;
+
+    let (state, _) = run_local::<f64>(&qr)?;
+    let counts = state.sample_measurements(&[0], 20);
+    assert_eq!(counts.get(&1), Some(&20));
+    Ok(())
+}
+
+#[test]
+fn test_shots_terminal_measurement_single_branch() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(3)?;
+    let r = b.hadamard(r);
+
+    let branches = run_local_shots::<f64>(&r, 200)?;
+    assert_eq!(branches.len(), 1);
+    assert_eq!(branches[0].shots(), 200);
+    Ok(())
+}
+
+#[test]
+fn test_shots_mid_circuit_measurement() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.hadamard(q);
+    let (q, m) = b.measure(q);
+    // Copy the measured value onto r classically.
+    let r = b.single_register_classical_sidechannel(
+        r,
+        std::slice::from_ref(&m),
+        Box::new(|b, r, ms| Ok(if ms[0] == 1 { b.not(r) } else { r })),
+    );
+    let r = b.merge(vec![q, r])?;
+
+    let shots = 1000;
+    let branches = run_local_shots::<f64>(&r, shots)?;
+    assert_eq!(branches.len(), 2);
+    assert_eq!(branches.iter().map(|b| b.shots()).sum::<usize>(), shots);
+    branches.iter().for_each(|branch| {
+        let (measured, p) = branch.measured.get_measurement(&m).unwrap();
+        assert!((p - 0.5).abs() < 1e-10);
+        let expected = if measured == 1 { 0b11 } else { 0b00 };
+        assert_eq!(branch.counts.get(&expected), Some(&branch.shots()));
+        // Far outside of any reasonable fluctuation.
+        assert!(branch.shots() > 350);
+    });
+
+    let counts = run_and_sample::<f64>(&r, shots)?;
+    let zeros = *counts.get(&0b00).unwrap_or(&0);
+    let ones = *counts.get(&0b11).unwrap_or(&0);
+    assert_eq!(zeros + ones, shots);
+    assert!(zeros > 350 && ones > 350);
+    Ok(())
+}
+
+#[test]
+fn test_peek_probabilities() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.ry(q, std::f64::consts::PI / 3.0);
+    let r = b.not(r);
+    let qr = b.merge(vec![q, r])?;
+
+    let (state, _) = run_local::<f64>(&qr)?;
+    let probs = state.peek_probabilities(&qr.indices);
+    assert!(probs[0b00].abs() < 1e-10);
+    assert!(probs[0b01].abs() < 1e-10);
+    assert!((probs[0b10] - 0.75).abs() < 1e-10);
+    assert!((probs[0b11] - 0.25).abs() < 1e-10);
+    assert!((state.probability_of(&[0], 1) - 0.25).abs() < 1e-10);
+    assert!((state.probability_of(&[1, 0], 0b01) - 0.75).abs() < 1e-10);
+
+    // Peeking leaves the state untouched.
+    let probs_again = state.peek_probabilities(&qr.indices);
+    assert_eq!(probs, probs_again);
+    Ok(())
+}
+
+#[test]
+fn test_shots_with_parameters() -> Result<(), CircuitError> {
+    let theta = Parameter::new("theta");
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.ry_param(q, &theta)?;
+    let (q, m) = b.measure(q);
+    let r = b.single_register_classical_sidechannel(
+        r,
+        std::slice::from_ref(&m),
+        Box::new(|b, r, ms| Ok(if ms[0] == 1 { b.not(r) } else { r })),
+    );
+    let r = b.merge(vec![q, r])?;
+
+    assert!(run_local_shots::<f64>(&r, 10).is_err());
+    let mut params = HashMap::new();
+    params.insert("theta".to_string(), std::f64::consts::PI);
+    let branches = run_shots_with_parameters::<f64, LocalQuantumState<f64>>(&r, 10, &params)?;
+    assert_eq!(branches.len(), 1);
+    assert_eq!(branches[0].counts.get(&0b11), Some(&10));
+    Ok(())
+}
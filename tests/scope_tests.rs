@@ -0,0 +1,97 @@
+extern crate qip;
+
+use qip::equivalence::circuits_equivalent;
+use qip::scope::Scope;
+use qip::*;
+
+fn merge_all(b: &mut OpBuilder, rs: Vec<(String, Register)>) -> Result<Register, CircuitError> {
+    b.merge(rs.into_iter().map(|(_, r)| r).collect())
+}
+
+#[test]
+fn test_matches_builder() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let mut scope = Scope::new(&mut b);
+    scope.register("a", 2).register("c", 1);
+    scope
+        .h("a")
+        .rx("c", 0.3)
+        .cnot("c", "a")
+        .cz("a", "c")
+        .t("a")
+        .apply(&["a"], |b, mut rs| {
+            let (q0, q1) = b.split(rs.pop().unwrap(), &[0])?;
+            let (q0, q1) = b.swap(q0, q1.unwrap())?;
+            Ok(vec![b.merge(vec![q0, q1])?])
+        });
+    let rs = scope.build()?;
+    assert_eq!(rs[0].0, "a");
+    assert_eq!(rs[1].0, "c");
+    let scoped = merge_all(&mut b, rs)?;
+
+    let mut b = OpBuilder::new();
+    let a = b.register(2)?;
+    let c = b.qubit();
+    let a = b.hadamard(a);
+    let c = b.rx(c, 0.3);
+    let (c, a) = b.cnot(c, a);
+    let (a, c) = b.cz(a, c);
+    let a = b.t(a);
+    let (q0, q1) = b.split(a, &[0])?;
+    let (q0, q1) = b.swap(q0, q1.unwrap())?;
+    let a = b.merge(vec![q0, q1])?;
+    let direct = b.merge(vec![a, c])?;
+
+    assert!(circuits_equivalent(&scoped, &direct, 1e-10)?);
+    Ok(())
+}
+
+#[test]
+fn test_add_existing() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let q = b.x(q);
+    let mut scope = Scope::new(&mut b);
+    scope.add("q", q).register("r", 1).cnot("q", "r");
+    let rs = scope.build()?;
+    let r = merge_all(&mut b, rs)?;
+    let (state, _) = run_local::<f64>(&r)?;
+    assert!((state.get_state(true)[0b11].re - 1.0).abs() < 1e-10);
+    Ok(())
+}
+
+#[test]
+fn test_errors_at_build() {
+    let mut b = OpBuilder::new();
+    let mut scope = Scope::new(&mut b);
+    scope.register("q", 1).cnot("q", "q");
+    assert!(scope.build().is_err());
+
+    let mut scope = Scope::new(&mut b);
+    scope.register("q", 1).h("missing").h("q");
+    assert!(scope.build().is_err());
+
+    let mut scope = Scope::new(&mut b);
+    scope.register("q", 1).register("q", 2);
+    assert!(scope.build().is_err());
+
+    let mut scope = Scope::new(&mut b);
+    scope.register("a", 1).register("b", 2).swap("a", "b");
+    assert!(scope.build().is_err());
+
+    // Ops must give back registers of the sizes they were given.
+    let mut scope = Scope::new(&mut b);
+    scope
+        .register("a", 2)
+        .apply(&["a"], |b, mut rs| Ok(b.split_all(rs.pop().unwrap())));
+    assert!(scope.build().is_err());
+}
+
+#[test]
+fn test_first_error_reported() {
+    let mut b = OpBuilder::new();
+    let mut scope = Scope::new(&mut b);
+    scope.register("q", 1).h("first").h("second");
+    let err = scope.build().unwrap_err();
+    assert!(format!("{:?}", err).contains("first"));
+}
@@ -0,0 +1,67 @@
+extern crate qip;
+
+use qip::pipeline::{run_local_with_seed, LocalQuantumState, MeasurementHandle, ParallelConfig};
+use qip::rng::with_seed;
+use qip::*;
+
+fn make_circuit(b: &mut OpBuilder) -> Result<(Register, Vec<MeasurementHandle>), CircuitError> {
+    let r = b.register(6)?;
+    let r = b.hadamard(r);
+    let qs = b.split_all(r);
+    let (qs, ms): (Vec<_>, Vec<_>) = qs.into_iter().map(|q| b.measure(q)).unzip();
+    Ok((b.merge(qs)?, ms))
+}
+
+fn measured_values(
+    measured: &pipeline::MeasuredResults<f64>,
+    ms: &[MeasurementHandle],
+) -> Vec<u64> {
+    ms.iter()
+        .map(|m| measured.get_measurement(m).unwrap().0)
+        .collect()
+}
+
+#[test]
+fn test_seeded_runs_repeat() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let (r, ms) = make_circuit(&mut b)?;
+    let (_, a) = run_local_with_seed::<f64>(&r, 3)?;
+    let (_, b) = run_local_with_seed::<f64>(&r, 3)?;
+    assert_eq!(measured_values(&a, &ms), measured_values(&b, &ms));
+
+    // Some seed must give a different set of 6 measurements.
+    let a = measured_values(&a, &ms);
+    assert!((4..20)
+        .map(|seed| run_local_with_seed::<f64>(&r, seed).unwrap().1)
+        .any(|m| measured_values(&m, &ms) != a));
+    Ok(())
+}
+
+#[test]
+fn test_seeded_sampling_repeats() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(4)?;
+    let r = b.hadamard(r);
+    let a = with_seed(11, || run_and_sample::<f64>(&r, 200))?;
+    let b = with_seed(11, || run_and_sample::<f64>(&r, 200))?;
+    assert_eq!(a, b);
+    Ok(())
+}
+
+#[test]
+fn test_seeded_thread_pool_repeats() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let (r, ms) = make_circuit(&mut b)?;
+    let run = |seed| -> Result<Vec<u64>, CircuitError> {
+        let mut state = LocalQuantumState::<f64>::new(6);
+        state.set_parallel_config(ParallelConfig {
+            num_threads: Some(2),
+            ..Default::default()
+        })?;
+        let (_, measured) = with_seed(seed, || run_with_state(&r, state))?;
+        Ok(measured_values(&measured, &ms))
+    };
+    let (_, measured) = run_local_with_seed::<f64>(&r, 5)?;
+    assert_eq!(run(5)?, measured_values(&measured, &ms));
+    Ok(())
+}
@@ -0,0 +1,72 @@
+extern crate qip;
+
+use qip::rng::with_seed;
+use qip::shadows::classical_shadow;
+use qip::*;
+
+#[test]
+fn test_shadow_product_state() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let s = b.qubit();
+    let r = b.hadamard(r);
+    let s = b.hadamard(s);
+    let s = b.s(s);
+    let r = b.merge(vec![q, r, s])?;
+
+    let shadow = with_seed(3, || classical_shadow::<f64>(&r, 3000))?;
+    assert_eq!(shadow.n(), 3);
+    assert_eq!(shadow.num_snapshots(), 3000);
+    // |0>|+>|+i> has <Z> = <X> = <Y> = 1 on its qubits.
+    assert!((shadow.estimate("Z")? - 1.0).abs() < 0.15);
+    assert!((shadow.estimate("IX")? - 1.0).abs() < 0.15);
+    assert!((shadow.estimate("IIY")? - 1.0).abs() < 0.15);
+    assert!(shadow.estimate("X")?.abs() < 0.15);
+    assert!((shadow.estimate("ZXY")? - 1.0).abs() < 0.5);
+    assert_eq!(shadow.estimate("")?, 1.0);
+    assert_eq!(shadow.estimate("III")?, 1.0);
+    Ok(())
+}
+
+#[test]
+fn test_shadow_bell_observables() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.hadamard(q);
+    let (q, r) = b.cnot(q, r);
+    let r = b.merge(vec![q, r])?;
+
+    let shadow = with_seed(5, || classical_shadow::<f64>(&r, 4000))?;
+    assert!((shadow.estimate("YY")? + 1.0).abs() < 0.2);
+    assert!((shadow.estimate_median_of_means("ZZ", 5)? - 1.0).abs() < 0.2);
+    assert!((shadow.estimate_median_of_means("XX", 4)? - 1.0).abs() < 0.2);
+    let energy = shadow.estimate_observable(&[(0.5, "ZZ"), (0.5, "XX"), (2.0, "ZI")])?;
+    assert!((energy - 1.0).abs() < 0.3);
+    Ok(())
+}
+
+#[test]
+fn test_shadow_seeded_repeats() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(2)?;
+    let r = b.hadamard(r);
+    let a = with_seed(7, || classical_shadow::<f64>(&r, 100))?;
+    let b = with_seed(7, || classical_shadow::<f64>(&r, 100))?;
+    assert_eq!(a.estimate("XZ")?, b.estimate("XZ")?);
+    Ok(())
+}
+
+#[test]
+fn test_shadow_errors() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(2)?;
+    assert!(classical_shadow::<f64>(&r, 0).is_err());
+    let shadow = classical_shadow::<f64>(&r, 10)?;
+    assert!(shadow.estimate("ZZZ").is_err());
+    assert!(shadow.estimate("ZA").is_err());
+    assert!(shadow.estimate_median_of_means("ZZ", 0).is_err());
+    assert!(shadow.estimate_median_of_means("ZZ", 11).is_err());
+    Ok(())
+}
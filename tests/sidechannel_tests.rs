@@ -1,6 +1,7 @@
 extern crate qip;
-use qip::pipeline::MeasurementHandle;
+use qip::pipeline::{run_shots, LocalQuantumState, MeasurementHandle};
 use qip::qubits::RegisterHandle;
+use qip::stabilizer_state::run_stabilizer;
 use qip::*;
 
 fn assert_almost_eq(a: f64, b: f64, prec: i32) {
@@ -86,3 +87,91 @@ fn test_cswap_sidechannel_unaligned() -> Result<(), CircuitError> {
     assert_almost_eq(p, 0.5, 10);
     Ok(())
 }
+
+#[test]
+fn test_classical_if() -> Result<(), CircuitError> {
+    for _ in 0..10 {
+        let mut b = OpBuilder::new();
+        let q = b.qubit();
+        let r = b.qubit();
+        let q = b.hadamard(q);
+        let (q, m) = b.measure(q);
+        // Reset q to |0> and put the measured value on r.
+        let q = b.classical_if(q, &m, Box::new(|b, q| Ok(b.not(q))));
+        let r = b.classical_if(r, &m, Box::new(|b, r| Ok(b.not(r))));
+        let r = b.merge(vec![q, r])?;
+
+        let (state, measured) = run_local::<f64>(&r)?;
+        let (m, p) = measured.get_measurement(&m).unwrap();
+        assert_almost_eq(p, 0.5, 10);
+        let state = state.get_state(true);
+        assert_almost_eq(state[(m << 1) as usize].norm(), 1.0, 10);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_classical_if_register() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let ra = b.register(2)?;
+    let q = b.qubit();
+    let ra = b.not(ra);
+    let (ra, m) = b.measure(ra);
+    // Fires only when both measured qubits are |1>, which they always are.
+    let q = b.classical_if(q, &m, Box::new(|b, q| Ok(b.not(q))));
+    let r = b.merge(vec![ra, q])?;
+
+    let (state, _) = run_local::<f64>(&r)?;
+    assert_almost_eq(state.get_state(true)[0b111].norm(), 1.0, 10);
+    Ok(())
+}
+
+#[test]
+fn test_classical_if_64_qubits() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let ra = b.register(64)?;
+    let q = b.qubit();
+    let ra = b.not(ra);
+    let (ra, m) = b.measure(ra);
+    let q = b.classical_if(q, &m, Box::new(|b, q| Ok(b.not(q))));
+    let (q, mq) = b.measure(q);
+    let r = b.merge(vec![ra, q])?;
+
+    let (_, measured) = run_stabilizer::<f64>(&r)?;
+    assert_eq!(measured.get_measurement(&m).unwrap().0, u64::MAX);
+    assert_eq!(measured.get_measurement(&mq).unwrap().0, 1);
+    Ok(())
+}
+
+#[test]
+fn test_c_if_value() -> Result<(), CircuitError> {
+    (0..4).try_for_each(|value| {
+        let mut b = OpBuilder::new();
+        let ra = b.register(2)?;
+        let q = b.qubit();
+        let ra = b.hadamard(ra);
+        let (ra, m) = b.measure(ra);
+        let q = b.c_if(q, &m, value, Box::new(|b, q| Ok(b.not(q))))?;
+        let r = b.merge(vec![ra, q])?;
+
+        let branches = run_shots::<f64, LocalQuantumState<f64>>(&r, 100)?;
+        assert_eq!(branches.len(), 4);
+        branches.iter().for_each(|branch| {
+            let (m, _) = branch.measured.get_measurement(&m).unwrap();
+            let flipped = if m == value { 1 } else { 0 };
+            let expected = m | (flipped << 2);
+            assert_eq!(branch.counts.get(&expected), Some(&branch.shots()));
+        });
+        Ok(())
+    })
+}
+
+#[test]
+fn test_c_if_value_too_large() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let ra = b.register(2)?;
+    let q = b.qubit();
+    let (_, m) = b.measure(ra);
+    assert!(b.c_if(q, &m, 0b100, Box::new(|_, q| Ok(q))).is_err());
+    Ok(())
+}
@@ -0,0 +1,66 @@
+extern crate qip;
+
+use qip::rng::with_seed;
+use qip::simon::*;
+use qip::*;
+
+/// A two to one function on `n` bits with period `s`.
+fn two_to_one(s: u64) -> impl Fn(u64) -> u64 + Send + Sync {
+    move |x| std::cmp::min(x, x ^ s)
+}
+
+#[test]
+fn test_simon_circuit_samples_orthogonal() -> Result<(), CircuitError> {
+    let s = 0b1011;
+    for seed in 0..10 {
+        let y = with_seed(seed, || -> Result<u64, CircuitError> {
+            let mut b = OpBuilder::new();
+            let (r, _) = simon_circuit(&mut b, two_to_one(s), 4)?;
+            let (r, m) = b.measure(r);
+            let (_, measured) = run_local::<f64>(&r)?;
+            Ok(measured.get_measurement(&m).unwrap().0)
+        })?;
+        assert_eq!((y & s).count_ones() & 1, 0, "{:?}", y);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_solve_period() {
+    assert_eq!(solve_period(&[], 1), Some(1));
+    assert_eq!(solve_period(&[], 2), None);
+    assert_eq!(solve_period(&[], 0), Some(0));
+    assert_eq!(solve_period(&[0b1], 1), Some(0));
+    assert_eq!(solve_period(&[0b0], 1), Some(1));
+    // Repeated and dependent samples do not add to the rank.
+    assert_eq!(solve_period(&[0b110, 0b110, 0b000], 3), None);
+    assert_eq!(solve_period(&[0b110, 0b011, 0b101], 3), Some(0b111));
+    assert_eq!(solve_period(&[0b110, 0b011], 3), Some(0b111));
+    assert_eq!(
+        solve_period(&[0b1100, 0b0110, 0b1001, 0b0101], 4),
+        Some(0b1111)
+    );
+    assert_eq!(solve_period(&[0b1000, 0b0010, 0b0101], 4), Some(0b0101));
+}
+
+#[test]
+fn test_simon() -> Result<(), CircuitError> {
+    for (seed, s) in (1..8).enumerate() {
+        let period = with_seed(seed as u64, || simon(two_to_one(s), 3, 100))?;
+        assert_eq!(period, s);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_simon_one_to_one() -> Result<(), CircuitError> {
+    let period = with_seed(1, || simon(|x| x ^ 0b0110, 4, 100))?;
+    assert_eq!(period, 0);
+    Ok(())
+}
+
+#[test]
+fn test_simon_errors() {
+    assert!(simon(|x| x, 0, 10).is_err());
+    assert!(simon(two_to_one(0b11), 2, 0).is_err());
+}
@@ -0,0 +1,54 @@
+extern crate qip;
+
+use qip::*;
+
+#[test]
+fn test_state_with_order_permutes_bits() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let s = b.qubit();
+    let q = b.not(q);
+    let s = b.hadamard(s);
+    let qrs = b.merge(vec![q, r, s])?;
+
+    let (state, _) = run_local::<f64>(&qrs)?;
+    let x = std::f64::consts::FRAC_1_SQRT_2;
+    // The identity order matches the natural order.
+    let natural = state.get_state_with_order(&[0, 1, 2])?;
+    assert_eq!(natural, state.clone().get_state(true));
+    assert!((natural[0b001].re - x).abs() < 1e-10);
+    assert!((natural[0b101].re - x).abs() < 1e-10);
+
+    // Reversed, q is the most significant bit.
+    let reversed = state.get_state_with_order(&[2, 1, 0])?;
+    assert!((reversed[0b100].re - x).abs() < 1e-10);
+    assert!((reversed[0b101].re - x).abs() < 1e-10);
+    Ok(())
+}
+
+#[test]
+fn test_state_for_registers() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let ra = b.register(2)?;
+    let rb = b.register(3)?;
+    let ra = b.not(ra);
+    let (ra, rb) = b.cnot(ra, rb);
+    let (state, _) = run_local::<f64>(&b.merge(vec![ra, rb])?)?;
+
+    let mut b = OpBuilder::new();
+    let ra = b.register(2)?;
+    let rb = b.register(3)?;
+    let amplitudes = state.get_state_for_registers(&[&rb, &ra])?;
+    // rb = 0b111 in the low bits, ra = 0b11 in the high bits.
+    assert_eq!(amplitudes[0b11_111], Complex::new(1.0, 0.0));
+    Ok(())
+}
+
+#[test]
+fn test_state_with_order_errors() {
+    let state = pipeline::LocalQuantumState::<f64>::new(3);
+    assert!(state.get_state_with_order(&[0, 1]).is_err());
+    assert!(state.get_state_with_order(&[0, 1, 1]).is_err());
+    assert!(state.get_state_with_order(&[0, 1, 3]).is_err());
+}
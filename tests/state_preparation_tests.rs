@@ -0,0 +1,109 @@
+extern crate num;
+extern crate qip;
+
+use num::Zero;
+use qip::*;
+
+fn assert_almost_eq(a: f64, b: f64, prec: i32) {
+    let mult = 10.0f64.powi(prec);
+    let (a, b) = (a * mult, b * mult);
+    let (a, b) = (a.round(), b.round());
+    assert_eq!(a / mult, b / mult);
+}
+
+fn assert_state_almost_eq(a: &[Complex<f64>], b: &[Complex<f64>]) {
+    assert_eq!(a.len(), b.len());
+    a.iter().zip(b.iter()).for_each(|(a, b)| {
+        assert_almost_eq(a.re, b.re, 10);
+        assert_almost_eq(a.im, b.im, 10);
+    });
+}
+
+fn normalized(vals: &[(f64, f64)]) -> Vec<Complex<f64>> {
+    let norm: f64 = vals.iter().map(|(re, im)| re * re + im * im).sum();
+    vals.iter()
+        .map(|(re, im)| Complex::new(*re, *im) / norm.sqrt())
+        .collect()
+}
+
+#[test]
+fn test_prepare_single_qubit() -> Result<(), CircuitError> {
+    let amplitudes = normalized(&[(0.6, 0.0), (0.0, 0.8)]);
+    let mut b = OpBuilder::new();
+    let r = b.qubit();
+    let r = b.prepare_state(r, &amplitudes)?;
+    let (state, _) = run_local::<f64>(&r)?;
+    assert_state_almost_eq(&state.get_state(true), &amplitudes);
+    Ok(())
+}
+
+#[test]
+fn test_prepare_register() -> Result<(), CircuitError> {
+    let amplitudes = normalized(&[
+        (1.0, 0.5),
+        (-0.3, 0.2),
+        (0.0, 0.0),
+        (0.7, -1.0),
+        (0.1, 0.1),
+        (-2.0, 0.0),
+        (0.0, 0.4),
+        (0.5, 0.5),
+    ]);
+    let mut b = OpBuilder::new();
+    let r = b.register(3)?;
+    let r = b.prepare_state(r, &amplitudes)?;
+    let (state, _) = run_local::<f64>(&r)?;
+    assert_state_almost_eq(&state.get_state(true), &amplitudes);
+    Ok(())
+}
+
+#[test]
+fn test_prepare_zero_first_amplitude() -> Result<(), CircuitError> {
+    let amplitudes = normalized(&[(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (0.0, 0.0)]);
+    let mut b = OpBuilder::new();
+    let r = b.register(2)?;
+    let r = b.prepare_state(r, &amplitudes)?;
+    let (state, _) = run_local::<f64>(&r)?;
+    assert_state_almost_eq(&state.get_state(true), &amplitudes);
+    Ok(())
+}
+
+#[test]
+fn test_prepare_basis_state() -> Result<(), CircuitError> {
+    let amplitudes = normalized(&[(0.0, 1.0), (0.0, 0.0)]);
+    let mut b = OpBuilder::new();
+    let r = b.qubit();
+    let r = b.prepare_state(r, &amplitudes)?;
+    let (state, _) = run_local::<f64>(&r)?;
+    assert_state_almost_eq(&state.get_state(true), &amplitudes);
+    Ok(())
+}
+
+#[test]
+fn test_prepare_measured_values() -> Result<(), CircuitError> {
+    // Only the value 2 has any weight, check it is measured in the same order as other values.
+    let mut amplitudes = vec![Complex::zero(); 4];
+    amplitudes[2] = Complex::new(1.0, 0.0);
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.register(2)?;
+    let r = b.prepare_state(r, &amplitudes)?;
+    let (r, m) = b.measure(r);
+    let r = b.merge(vec![q, r])?;
+    let (_, measured) = run_local::<f64>(&r)?;
+    assert_eq!(measured.get_measurement(&m).unwrap().0, 2);
+    Ok(())
+}
+
+#[test]
+fn test_prepare_errors() {
+    let mut b = OpBuilder::new();
+    let r = b.register(2).unwrap();
+    assert!(b
+        .prepare_state(r, &normalized(&[(1.0, 0.0), (1.0, 0.0)]))
+        .is_err());
+    let r = b.qubit();
+    assert!(b
+        .prepare_state(r, &[Complex::new(1.0, 0.0), Complex::new(1.0, 0.0)])
+        .is_err());
+}
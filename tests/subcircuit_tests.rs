@@ -0,0 +1,148 @@
+extern crate qip;
+
+use qip::equivalence::circuits_equivalent;
+use qip::pipeline::make_circuit_matrix;
+use qip::subcircuit::Subcircuit;
+use qip::*;
+
+fn rotations() -> Result<Subcircuit, CircuitError> {
+    Subcircuit::new(&[1, 2], |b, mut rs| {
+        let r = rs.pop().unwrap();
+        let q = b.rx(rs.pop().unwrap(), 0.3);
+        let r = b.ry(r, 0.5);
+        let (q, r) = b.cnot(q, r);
+        let q = b.t(q);
+        Ok(vec![q, r])
+    })
+}
+
+/// Apply `sub` to a fresh register split to match its widths.
+fn apply_to_register(sub: &Subcircuit, b: &mut OpBuilder) -> Result<Register, CircuitError> {
+    let widths = sub.widths();
+    let r = b.register(widths.iter().sum())?;
+    let mut start = 0;
+    let groups: Vec<Vec<u64>> = widths
+        .iter()
+        .map(|w| {
+            let group = (start..start + w).collect();
+            start += w;
+            group
+        })
+        .collect();
+    let (rs, _) = b.split_absolute_many(r, &groups)?;
+    let rs = sub.apply(b, rs)?;
+    b.merge(rs)
+}
+
+#[test]
+fn test_adjoint_undoes() -> Result<(), CircuitError> {
+    let sub = rotations()?;
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.register(2)?;
+    let rs = sub.apply(&mut b, vec![q, r])?;
+    let rs = sub.adjoint().apply(&mut b, rs)?;
+    let r = b.merge(rs)?;
+
+    let mut b = OpBuilder::new();
+    let identity = b.register(3)?;
+    assert!(circuits_equivalent(&r, &identity, 1e-10)?);
+    Ok(())
+}
+
+#[test]
+fn test_double_adjoint() -> Result<(), CircuitError> {
+    let sub = rotations()?;
+    let mut b = OpBuilder::new();
+    let r = apply_to_register(&sub, &mut b)?;
+    let mut b = OpBuilder::new();
+    let s = apply_to_register(&sub.adjoint().adjoint(), &mut b)?;
+    assert!(circuits_equivalent(&r, &s, 1e-10)?);
+    Ok(())
+}
+
+#[test]
+fn test_controlled() -> Result<(), CircuitError> {
+    let sub = rotations()?;
+    let mut b = OpBuilder::new();
+    let inner = make_circuit_matrix::<f64>(3, &apply_to_register(&sub, &mut b)?, false);
+    for controlled in [sub.controlled(), sub.adjoint().controlled().adjoint()].iter() {
+        assert_eq!(controlled.widths(), vec![1, 1, 2]);
+        let mut b = OpBuilder::new();
+        let mat = make_circuit_matrix::<f64>(4, &apply_to_register(controlled, &mut b)?, false);
+        // The control is the highest bit, the subcircuit acts on the lower three when it is set.
+        (0..16).for_each(|row| {
+            (0..16).for_each(|col| {
+                let expected = match (row >> 3, col >> 3) {
+                    (1, 1) => inner[row & 0b111][col & 0b111],
+                    (0, 0) if row == col => Complex::new(1.0, 0.0),
+                    _ => Complex::new(0.0, 0.0),
+                };
+                assert!((mat[row][col] - expected).norm() < 1e-10);
+            })
+        });
+    }
+    Ok(())
+}
+
+#[test]
+fn test_doubly_controlled() -> Result<(), CircuitError> {
+    let flip = Subcircuit::new(&[1], |b, mut rs| Ok(vec![b.x(rs.pop().unwrap())]))?;
+    let toffoli = flip.controlled().controlled();
+    assert_eq!(toffoli.widths(), vec![1, 1, 1]);
+    (0..4).try_for_each(|controls: u64| {
+        let mut b = OpBuilder::new();
+        let ca = b.qubit();
+        let cb = b.qubit();
+        let q = b.qubit();
+        let ca = if controls & 1 == 1 { b.x(ca) } else { ca };
+        let cb = if controls & 2 == 2 { b.x(cb) } else { cb };
+        let rs = toffoli.apply(&mut b, vec![ca, cb, q])?;
+        let r = b.merge(rs)?;
+        let (state, _) = run_local::<f64>(&r)?;
+        let expected = if controls == 3 { 0b111 } else { controls };
+        assert!((state.get_state(true)[expected as usize].re - 1.0).abs() < 1e-10);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_compose() -> Result<(), CircuitError> {
+    let sub = rotations()?;
+    let inner = sub.clone();
+    // Conjugate a Z on the first qubit by the subcircuit.
+    let conjugated = Subcircuit::new(&[1, 2], move |b, rs| {
+        let mut rs = inner.apply(b, rs)?;
+        let r = rs.pop().unwrap();
+        let q = b.z(rs.pop().unwrap());
+        inner.adjoint().apply(b, vec![q, r])
+    })?;
+    // Self inverse, so its adjoint is the same circuit.
+    let mut b = OpBuilder::new();
+    let r = apply_to_register(&conjugated, &mut b)?;
+    let mut b = OpBuilder::new();
+    let s = apply_to_register(&conjugated.adjoint(), &mut b)?;
+    assert!(circuits_equivalent(&r, &s, 1e-10)?);
+    Ok(())
+}
+
+#[test]
+fn test_errors() -> Result<(), CircuitError> {
+    assert!(Subcircuit::new(&[], |_, rs| Ok(rs)).is_err());
+    assert!(Subcircuit::new(&[1, 0], |_, rs| Ok(rs)).is_err());
+
+    let sub = rotations()?;
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.register(3)?;
+    assert!(sub.apply(&mut b, vec![q, r]).is_err());
+    let q = b.qubit();
+    let r = b.register(2)?;
+    assert!(sub.controlled().apply(&mut b, vec![q, r]).is_err());
+
+    let merged = Subcircuit::new(&[1, 1], |b, rs| Ok(vec![b.merge(rs)?]))?;
+    let qa = b.qubit();
+    let qb = b.qubit();
+    assert!(merged.apply(&mut b, vec![qa, qb]).is_err());
+    Ok(())
+}
@@ -0,0 +1,101 @@
+extern crate qip;
+
+use qip::qec::surface::SurfaceCode;
+use qip::*;
+
+fn overlap(a: &[u64], b: &[u64]) -> usize {
+    a.iter().filter(|q| b.contains(q)).count()
+}
+
+#[test]
+fn test_surface_layout() -> Result<(), CircuitError> {
+    for d in 2..7 {
+        let code = SurfaceCode::new(d)?;
+        assert_eq!(code.num_data_qubits(), d * d);
+        assert_eq!(code.num_ancilla_qubits(), d * d - 1);
+        let (xs, zs) = (code.x_stabilizers(), code.z_stabilizers());
+        xs.iter().chain(zs.iter()).for_each(|s| {
+            assert!(s.len() == 2 || s.len() == 4);
+        });
+        // Stabilizers commute with each other and with the logical operators.
+        xs.iter().for_each(|x| {
+            zs.iter().for_each(|z| assert_eq!(overlap(x, z) % 2, 0));
+            assert_eq!(overlap(x, &code.logical_z_qubits()) % 2, 0);
+        });
+        zs.iter().for_each(|z| {
+            assert_eq!(overlap(z, &code.logical_x_qubits()) % 2, 0);
+        });
+        // The logical operators anticommute.
+        let logical = overlap(&code.logical_x_qubits(), &code.logical_z_qubits());
+        assert_eq!(logical % 2, 1);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_surface_syndrome_detects_errors() -> Result<(), CircuitError> {
+    let code = SurfaceCode::new(3)?;
+    let error = code.data_index(1, 0);
+
+    let mut b = OpBuilder::new();
+    let (data, ancillas) = code.make_registers(&mut b)?;
+    let (data, ancillas, first) = code.measure_stabilizers(&mut b, data, ancillas)?;
+    let (q, rest) = b.split(data, &[error])?;
+    let q = b.x(q);
+    let data = b.merge(vec![q, rest.unwrap()])?;
+    // The data qubits were allocated first, so their absolute indices are their positions.
+    let (data, rest) = b.split_absolute(data, &(0..9).collect::<Vec<_>>())?;
+    assert!(rest.is_none());
+    let (data, ancillas, second) = code.measure_stabilizers(&mut b, data, ancillas)?;
+    let r = b.merge(vec![data, ancillas])?;
+
+    let (_, measured) = run_local::<f64>(&r)?;
+    let (first_x, first_z) = first.values(&measured);
+    let (second_x, second_z) = second.values(&measured);
+    assert_eq!(first_z, vec![false; 4]);
+    assert_eq!(first_x, second_x);
+    // Only the Z stabilizers on the flipped qubit see it.
+    let expected: Vec<bool> = code
+        .z_stabilizers()
+        .iter()
+        .map(|s| s.contains(&error))
+        .collect();
+    assert_eq!(expected.iter().filter(|x| **x).count(), 2);
+    assert_eq!(second_z, expected);
+    Ok(())
+}
+
+#[test]
+fn test_surface_logical_operators() -> Result<(), CircuitError> {
+    let code = SurfaceCode::new(2)?;
+    let mut b = OpBuilder::new();
+    let (data, ancillas) = code.make_registers(&mut b)?;
+    let (data, ancillas, _) = code.measure_stabilizers(&mut b, data, ancillas)?;
+    let data = code.logical_x(&mut b, data)?;
+    let data = code.logical_z(&mut b, data)?;
+    let (data, ancillas, syndrome) = code.measure_stabilizers(&mut b, data, ancillas)?;
+    // Measure logical Z as the parity of the first row.
+    let (row, rest) = b.split(data, &code.logical_z_qubits())?;
+    let (row, m) = b.measure(row);
+
+    let r = b.merge(vec![row, rest.unwrap(), ancillas])?;
+    let (_, measured) = run_local::<f64>(&r)?;
+    let (_, z) = syndrome.values(&measured);
+    assert_eq!(z, vec![false; 2]);
+    let (value, _) = measured.get_measurement(&m).unwrap();
+    assert_eq!(value.count_ones() % 2, 1);
+    Ok(())
+}
+
+#[test]
+fn test_surface_errors() -> Result<(), CircuitError> {
+    assert!(SurfaceCode::new(1).is_err());
+    let code = SurfaceCode::new(2)?;
+    let mut b = OpBuilder::new();
+    let data = b.register(3)?;
+    assert!(code.logical_x(&mut b, data).is_err());
+    let data = b.register(4)?;
+    let ancillas = b.register(4)?;
+    assert!(code.measure_stabilizers(&mut b, data, ancillas).is_err());
+    Ok(())
+}
@@ -82,3 +82,64 @@ fn test_teleport() -> Result<(), CircuitError> {
     }
     Ok(())
 }
+
+/// Prepare a state on `n` qubits with complex and entangled amplitudes.
+fn prepare(b: &mut OpBuilder, n: u64) -> Register {
+    let r = b.register(n).unwrap();
+    let qs = b.split_all(r);
+    let qs: Vec<Register> = qs
+        .into_iter()
+        .enumerate()
+        .map(|(i, q)| {
+            let q = b.ry(q, 0.3 + i as f64);
+            b.s(q)
+        })
+        .collect();
+    let r = b.merge(qs).unwrap();
+    if n > 1 {
+        let (q, rest) = b.split(r, &[0]).unwrap();
+        let (q, rest) = b.cnot(q, rest.unwrap());
+        let q = b.t(q);
+        b.merge(vec![q, rest]).unwrap()
+    } else {
+        r
+    }
+}
+
+fn expected_density_matrix(n: u64) -> Result<Vec<Complex<f64>>, CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = prepare(&mut b, n);
+    let (state, _) = run_local::<f64>(&r)?;
+    stats::reduced_density_matrix(&state, &r.indices)
+}
+
+#[test]
+fn test_teleport_helper() -> Result<(), CircuitError> {
+    for n in 1..=2 {
+        let expected = expected_density_matrix(n)?;
+        // Each seed gives different measurements, and so different corrections.
+        for seed in 0..16 {
+            let mut b = OpBuilder::new();
+            let source = prepare(&mut b, n);
+            // Each qubit needs its own pair.
+            let (epr_a, epr_b): (Vec<_>, Vec<_>) = (0..n).map(|_| epr_pair(&mut b, 1)).unzip();
+            let epr_a = b.merge(epr_a)?;
+            let epr_b = b.merge(epr_b)?;
+            let r = teleport(&mut b, source, epr_a, epr_b)?;
+            let (state, _) = rng::with_seed(seed, || run_local::<f64>(&r))?;
+            let rho = stats::reduced_density_matrix(&state, &r.indices)?;
+            rho.iter()
+                .zip(expected.iter())
+                .for_each(|(a, b)| assert!((a - b).norm() < 1e-10));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_teleport_size_mismatch() {
+    let mut b = OpBuilder::new();
+    let source = b.register(2).unwrap();
+    let (epr_a, epr_b) = epr_pair(&mut b, 1);
+    assert!(teleport(&mut b, source, epr_a, epr_b).is_err());
+}
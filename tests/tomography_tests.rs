@@ -0,0 +1,245 @@
+extern crate qip;
+
+use num::{One, Zero};
+use qip::rng::with_seed;
+use qip::state_ops::{from_reals, from_tuples};
+use qip::tomography::*;
+use qip::*;
+
+fn assert_close(rho: &[Complex<f64>], expected: &[Complex<f64>], tolerance: f64) {
+    assert_eq!(rho.len(), expected.len());
+    rho.iter().zip(expected.iter()).for_each(|(a, b)| {
+        assert!((a - b).norm() < tolerance, "{:?} != {:?}", rho, expected);
+    });
+}
+
+fn trace(rho: &[Complex<f64>]) -> Complex<f64> {
+    let dim = (rho.len() as f64).sqrt() as usize;
+    (0..dim).map(|i| rho[i * dim + i]).sum()
+}
+
+fn prepare_bell(b: &mut OpBuilder) -> Result<Register, CircuitError> {
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.hadamard(q);
+    let (q, r) = b.cnot(q, r);
+    b.merge(vec![q, r])
+}
+
+#[test]
+fn test_measurement_bases() {
+    assert_eq!(measurement_bases(0), vec![""]);
+    assert_eq!(measurement_bases(1), vec!["X", "Y", "Z"]);
+    let bases = measurement_bases(2);
+    assert_eq!(bases.len(), 9);
+    assert_eq!(bases[0], "XX");
+    assert_eq!(bases[1], "XY");
+    assert_eq!(bases[8], "ZZ");
+}
+
+#[test]
+fn test_rotate_to_basis() -> Result<(), CircuitError> {
+    // |+>|-i>|1> is the -1 eigenstate only for the last two bases.
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let s = b.qubit();
+    let q = b.hadamard(q);
+    let r = b.x(r);
+    let r = b.hadamard(r);
+    let r = b.s(r);
+    let s = b.x(s);
+    let r = b.merge(vec![q, r, s])?;
+    let r = rotate_to_basis(&mut b, r, "XYZ")?;
+    let (r, m) = b.measure(r);
+    let (_, measured) = run_local::<f64>(&r)?;
+    assert_eq!(measured.get_measurement(&m).map(|(v, _)| v), Some(0b110));
+    Ok(())
+}
+
+#[test]
+fn test_rotate_to_basis_errors() {
+    let mut b = OpBuilder::new();
+    let r = b.register(2).unwrap();
+    assert!(rotate_to_basis(&mut b, r, "X").is_err());
+    let r = b.register(2).unwrap();
+    assert!(rotate_to_basis(&mut b, r, "XI").is_err());
+}
+
+#[test]
+fn test_linear_inversion_zero() -> Result<(), CircuitError> {
+    let data = with_seed(1, || collect_tomography(|b| Ok(b.qubit()), 500))?;
+    assert_eq!(data.n, 1);
+    assert_eq!(data.counts.len(), 3);
+    let rho = linear_inversion(&data)?;
+    let expected = [
+        Complex::one(),
+        Complex::zero(),
+        Complex::zero(),
+        Complex::zero(),
+    ];
+    assert_close(&rho, &expected, 0.1);
+    Ok(())
+}
+
+#[test]
+fn test_linear_inversion_y_eigenstate() -> Result<(), CircuitError> {
+    let data = with_seed(2, || {
+        collect_tomography(
+            |b| {
+                let q = b.qubit();
+                let q = b.hadamard(q);
+                Ok(b.s(q))
+            },
+            1000,
+        )
+    })?;
+    let rho = linear_inversion(&data)?;
+    // |+i><+i| has off diagonal entries -i/2 and i/2.
+    let expected = [
+        Complex::new(0.5, 0.0),
+        Complex::new(0.0, -0.5),
+        Complex::new(0.0, 0.5),
+        Complex::new(0.5, 0.0),
+    ];
+    assert_close(&rho, &expected, 0.1);
+    Ok(())
+}
+
+#[test]
+fn test_bell_state_tomography() -> Result<(), CircuitError> {
+    let data = with_seed(3, || collect_tomography(prepare_bell, 1000))?;
+    assert_eq!(data.counts.len(), 9);
+    let h = Complex::new(0.5, 0.0);
+    let o = Complex::zero();
+    // (|00> + |11>)(<00| + <11|) / 2
+    let expected = [h, o, o, h, o, o, o, o, o, o, o, o, h, o, o, h];
+
+    let rho = linear_inversion(&data)?;
+    assert!((trace(&rho) - Complex::one()).norm() < 1e-10);
+    assert_close(&rho, &expected, 0.1);
+
+    let rho = maximum_likelihood(&data, 100)?;
+    assert!((trace(&rho) - Complex::one()).norm() < 1e-10);
+    assert_close(&rho, &expected, 0.1);
+    Ok(())
+}
+
+#[test]
+fn test_maximum_likelihood_is_positive() -> Result<(), CircuitError> {
+    // With few shots linear inversion may not give a positive matrix, but this always does.
+    let data = with_seed(4, || {
+        collect_tomography(
+            |b| {
+                let q = b.qubit();
+                Ok(b.hadamard(q))
+            },
+            20,
+        )
+    })?;
+    let rho = maximum_likelihood(&data, 200)?;
+    assert!((trace(&rho) - Complex::one()).norm() < 1e-10);
+    assert!((rho[1] - rho[2].conj()).norm() < 1e-10);
+    // A hermitian 2x2 matrix with unit trace is positive when its determinant is non-negative.
+    let det = rho[0] * rho[3] - rho[1] * rho[2];
+    assert!(det.re > -1e-10);
+    assert!(rho[0].re >= 0.0 && rho[3].re >= 0.0);
+    Ok(())
+}
+
+#[test]
+fn test_tomography_errors() {
+    assert!(collect_tomography(|b| Ok(b.qubit()), 0).is_err());
+    let mut data = with_seed(5, || collect_tomography(|b| Ok(b.qubit()), 10)).unwrap();
+    data.counts[0].0 = "XX".to_string();
+    assert!(linear_inversion(&data).is_err());
+    assert!(maximum_likelihood(&data, 10).is_err());
+}
+
+/// Get the Choi matrix of a single qubit process with kraus operators `kraus`.
+fn choi_from_kraus(kraus: &[Vec<Complex<f64>>]) -> Vec<Complex<f64>> {
+    (0..16)
+        .map(|index| {
+            let (row, col) = (index / 4, index % 4);
+            let (i, k) = (row / 2, row % 2);
+            let (j, l) = (col / 2, col % 2);
+            // <k|K|i><j|K^dagger|l>
+            kraus
+                .iter()
+                .map(|m| m[k * 2 + i] * m[l * 2 + j].conj())
+                .sum()
+        })
+        .collect()
+}
+
+#[test]
+fn test_process_tomography_identity() -> Result<(), CircuitError> {
+    let choi = with_seed(6, || process_tomography(1, |_, r| Ok(r), 1000))?;
+    let identity = vec![from_reals(&[1.0, 0.0, 0.0, 1.0])];
+    assert!((trace(&choi) - Complex::new(2.0, 0.0)).norm() < 1e-10);
+    assert_close(&choi, &choi_from_kraus(&identity), 0.15);
+    Ok(())
+}
+
+#[test]
+fn test_process_tomography_phase() -> Result<(), CircuitError> {
+    let choi = with_seed(7, || process_tomography(1, |b, r| Ok(b.s(r)), 1000))?;
+    let s = vec![from_tuples(&[
+        (1.0, 0.0),
+        (0.0, 0.0),
+        (0.0, 0.0),
+        (0.0, 1.0),
+    ])];
+    assert_close(&choi, &choi_from_kraus(&s), 0.15);
+    Ok(())
+}
+
+#[test]
+fn test_process_tomography_amplitude_damping() -> Result<(), CircuitError> {
+    let kraus = noise::amplitude_damping(0.3)?;
+    let choi = with_seed(8, || {
+        process_tomography(1, |b, r| b.channel("T1", r, kraus.clone()), 2000)
+    })?;
+    assert_close(&choi, &choi_from_kraus(&kraus), 0.15);
+    Ok(())
+}
+
+#[test]
+fn test_process_tomography_two_qubits() -> Result<(), CircuitError> {
+    let choi = with_seed(9, || {
+        process_tomography(
+            2,
+            |b, r| {
+                let mut qubits = b.split_all(r);
+                let r = qubits.pop().unwrap();
+                let q = qubits.pop().unwrap();
+                let (q, r) = b.cnot(q, r);
+                b.merge(vec![q, r])
+            },
+            200,
+        )
+    })?;
+    assert_eq!(choi.len(), 256);
+    assert!((trace(&choi) - Complex::new(4.0, 0.0)).norm() < 1e-10);
+    // The unitary cnot gives the rank one Choi matrix |v><v| for v = sum_i |i> (x) cnot|i>, with
+    // qubit 0 as the control.
+    let v = |row: usize| match row {
+        0b0000 | 0b0111 | 0b1010 | 0b1101 => 1.0,
+        _ => 0.0,
+    };
+    choi.iter().enumerate().for_each(|(index, c)| {
+        let expected = v(index / 16) * v(index % 16);
+        assert!(
+            (c - Complex::new(expected, 0.0)).norm() < 0.3,
+            "{:?}",
+            index
+        );
+    });
+    Ok(())
+}
+
+#[test]
+fn test_process_tomography_errors() {
+    assert!(process_tomography(1, |_, r| Ok(r), 0).is_err());
+    assert!(process_tomography(1, |b, r| b.channel("bad", r, vec![]), 10).is_err());
+}
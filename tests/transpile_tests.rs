@@ -0,0 +1,132 @@
+extern crate qip;
+
+use qip::equivalence::circuits_equivalent;
+use qip::parameters::Parameter;
+use qip::pipeline::get_opfns_and_frontier;
+use qip::transpile::{transpile, BasisGates};
+use qip::unitary_state::run_unitary_local;
+use qip::*;
+
+const BASES: [(BasisGates, &[&str]); 2] = [
+    (BasisGates::CnotU3, &["U3", "C(not)"]),
+    (BasisGates::CzRzSx, &["Rz", "SX", "C(Z)"]),
+];
+
+fn assert_transpiles(r: &Register) -> Result<(), CircuitError> {
+    for (basis, names) in BASES.iter() {
+        let t = transpile(r, *basis)?;
+        assert_eq!(t.indices, r.indices);
+        let (_, ops) = get_opfns_and_frontier(&t);
+        ops.iter().for_each(|op| {
+            assert!(
+                names.contains(&op.name.as_str()),
+                "{:?} not in basis",
+                op.name
+            );
+        });
+        assert!(circuits_equivalent(r, &t, 1e-8)?);
+    }
+    Ok(())
+}
+
+/// The matrix of an arbitrary circuit on `n` qubits, with the first qubit as the most significant
+/// bit.
+fn arbitrary_matrix(n: u64) -> Result<Vec<Complex<f64>>, CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(n)?;
+    let qs: Vec<Register> = b
+        .split_all(r)
+        .into_iter()
+        .enumerate()
+        .map(|(i, q)| {
+            let q = b.ry(q, 0.4 + 0.3 * i as f64);
+            b.rz(q, 1.1 - 0.2 * i as f64)
+        })
+        .collect();
+    let r = b.merge(qs)?;
+    let (q, rest) = b.split(r, &[0])?;
+    let (q, rest) = b.cnot(q, rest.unwrap());
+    let rest = b.rx(rest, 0.7);
+    let (rest, q) = b.cy(rest, q);
+    let q = b.hadamard(q);
+    let r = b.merge(vec![q, rest])?;
+    let (u, _) = run_unitary_local::<f64>(&r)?;
+    Ok(u.get_unitary(false).into_iter().flatten().collect())
+}
+
+#[test]
+fn test_transpile_standard_gates() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let s = b.qubit();
+    let q = b.hadamard(q);
+    let (q, r) = b.cnot(q, r);
+    let (r, s) = b.swap(r, s)?;
+    let s = b.rz(s, 0.3);
+    let q = b.t(q);
+    let (q, s) = b.cz(q, s);
+    let r = b.y(r);
+    let r = b.merge(vec![q, r, s])?;
+    assert_transpiles(&r)
+}
+
+#[test]
+fn test_transpile_multi_controlled() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let s = b.qubit();
+    let t = b.qubit();
+    let q = b.hadamard(q);
+    let r = b.ry(r, 0.6);
+    let qr = b.merge(vec![q, r])?;
+    // Toffoli, controlled swap, and a doubly controlled rotation.
+    let (qr, s) = b.cnot(qr, s);
+    let (q, r) = b.split(qr, &[0])?;
+    let (q, r, s) = b.cswap(q, r.unwrap(), s)?;
+    let qs = b.merge(vec![q, s])?;
+    let (qs, t) = b.cry(qs, t, 0.9);
+    let r = b.merge(vec![qs, r, t])?;
+    assert_transpiles(&r)
+}
+
+#[test]
+fn test_transpile_matrices() -> Result<(), CircuitError> {
+    for n in 2..4 {
+        let mut b = OpBuilder::new();
+        let r = b.register(n)?;
+        let r = b.hadamard(r);
+        let r = b.mat("U", r, arbitrary_matrix(n)?)?;
+        assert_transpiles(&r)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_transpile_keeps_measurements() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let r = b.qubit();
+    let q = b.not(q);
+    let (q, r) = b.cnot(q, r);
+    let r = b.merge(vec![q, r])?;
+    let (r, m) = b.measure(r);
+
+    let t = transpile(&r, BasisGates::CzRzSx)?;
+    let (_, measured) = run_local::<f64>(&t)?;
+    let (value, p) = measured.get_measurement(&m).unwrap();
+    assert_eq!(value, 0b11);
+    assert!((p - 1.0).abs() < 1e-10);
+    Ok(())
+}
+
+#[test]
+fn test_transpile_parameterized_errors() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let theta = Parameter::new("theta");
+    let q = b.rz_param(q, &theta)?;
+    assert!(transpile(&q, BasisGates::CnotU3).is_err());
+    Ok(())
+}
@@ -0,0 +1,294 @@
+extern crate num;
+extern crate qip;
+
+use num::{One, Zero};
+use qip::pipeline::make_circuit_matrix;
+use qip::*;
+use std::f64::consts::{FRAC_PI_2, PI};
+
+fn assert_matrix_almost_eq(a: &[Vec<Complex<f64>>], b: &[Vec<Complex<f64>>]) {
+    assert_eq!(a.len(), b.len());
+    a.iter().zip(b.iter()).for_each(|(ra, rb)| {
+        ra.iter().zip(rb.iter()).for_each(|(a, b)| {
+            assert!((a - b).norm() < 1e-10, "{:?} != {:?}", ra, rb);
+        })
+    });
+}
+
+/// Get the matrix of `f` applied to two qubits.
+fn two_qubit_matrix<F>(f: F) -> Result<Vec<Vec<Complex<f64>>>, CircuitError>
+where
+    F: Fn(&mut OpBuilder, Register, Register) -> Result<(Register, Register), CircuitError>,
+{
+    let mut b = OpBuilder::new();
+    let ra = b.qubit();
+    let rb = b.qubit();
+    let (ra, rb) = f(&mut b, ra, rb)?;
+    let r = b.merge(vec![ra, rb])?;
+    Ok(make_circuit_matrix::<f64>(2, &r, false))
+}
+
+/// The fermionic simulation gate, which is symmetric in its qubits.
+fn fsim_matrix(theta: f64, phi: f64) -> Vec<Vec<Complex<f64>>> {
+    let (o, z) = (Complex::one(), Complex::zero());
+    let c = Complex::new(theta.cos(), 0.0);
+    let s = Complex::new(0.0, -theta.sin());
+    let p = Complex::from_polar(&1.0, &-phi);
+    vec![
+        vec![o, z, z, z],
+        vec![z, c, s, z],
+        vec![z, s, c, z],
+        vec![z, z, z, p],
+    ]
+}
+
+/// The Givens rotation, a real rotation in the span of `|01>` and `|10>`.
+fn givens_matrix(theta: f64) -> Vec<Vec<Complex<f64>>> {
+    let (o, z) = (Complex::one(), Complex::zero());
+    let c = Complex::new(theta.cos(), 0.0);
+    let s = Complex::new(theta.sin(), 0.0);
+    vec![
+        vec![o, z, z, z],
+        vec![z, c, -s, z],
+        vec![z, s, c, z],
+        vec![z, z, z, o],
+    ]
+}
+
+#[test]
+fn test_iswap() -> Result<(), CircuitError> {
+    let (o, z, i) = (Complex::one(), Complex::zero(), Complex::i());
+    let expected = vec![
+        vec![o, z, z, z],
+        vec![z, z, i, z],
+        vec![z, i, z, z],
+        vec![z, z, z, o],
+    ];
+    assert_matrix_almost_eq(&two_qubit_matrix(|b, ra, rb| b.iswap(ra, rb))?, &expected);
+    assert_matrix_almost_eq(&fsim_matrix(-FRAC_PI_2, 0.0), &expected);
+    Ok(())
+}
+
+#[test]
+fn test_sqrt_iswap() -> Result<(), CircuitError> {
+    let mat = two_qubit_matrix(|b, ra, rb| {
+        let (ra, rb) = b.sqrt_iswap(ra, rb)?;
+        b.sqrt_iswap(ra, rb)
+    })?;
+    assert_matrix_almost_eq(&mat, &two_qubit_matrix(|b, ra, rb| b.iswap(ra, rb))?);
+    let mat = two_qubit_matrix(|b, ra, rb| b.sqrt_iswap(ra, rb))?;
+    assert_matrix_almost_eq(&mat, &fsim_matrix(-PI / 4.0, 0.0));
+    Ok(())
+}
+
+#[test]
+fn test_fsim() -> Result<(), CircuitError> {
+    let mat = two_qubit_matrix(|b, ra, rb| b.fsim(ra, rb, 0.3, 1.1))?;
+    assert_matrix_almost_eq(&mat, &fsim_matrix(0.3, 1.1));
+    // Angles add when fsim gates are composed.
+    let mat = two_qubit_matrix(|b, ra, rb| {
+        let (ra, rb) = b.fsim(ra, rb, 0.3, 1.1)?;
+        b.fsim(ra, rb, -0.8, 0.4)
+    })?;
+    assert_matrix_almost_eq(&mat, &fsim_matrix(-0.5, 1.5));
+    Ok(())
+}
+
+#[test]
+fn test_givens() -> Result<(), CircuitError> {
+    let mat = two_qubit_matrix(|b, ra, rb| b.givens(ra, rb, 0.4))?;
+    assert_matrix_almost_eq(&mat, &givens_matrix(0.4));
+    // Rotations compose, and undo each other.
+    let mat = two_qubit_matrix(|b, ra, rb| {
+        let (ra, rb) = b.givens(ra, rb, 0.4)?;
+        b.givens(ra, rb, 0.9)
+    })?;
+    assert_matrix_almost_eq(&mat, &givens_matrix(1.3));
+    let mat = two_qubit_matrix(|b, ra, rb| {
+        let (ra, rb) = b.givens(ra, rb, 0.4)?;
+        b.givens(ra, rb, -0.4)
+    })?;
+    assert_matrix_almost_eq(&mat, &givens_matrix(0.0));
+    Ok(())
+}
+
+#[test]
+fn test_givens_moves_excitation() -> Result<(), CircuitError> {
+    // A quarter turn moves a single excitation from rb to ra.
+    let mut b = OpBuilder::new();
+    let ra = b.qubit();
+    let rb = b.qubit();
+    let rb = b.not(rb);
+    let (ra, rb) = b.givens(ra, rb, FRAC_PI_2)?;
+    let r = b.merge(vec![ra, rb])?;
+    let (state, _) = run_local::<f64>(&r)?;
+    let state = state.get_state_for_registers(&[&r])?;
+    assert!((state[0b01].norm() - 1.0).abs() < 1e-10);
+    Ok(())
+}
+
+#[test]
+fn test_rzz() -> Result<(), CircuitError> {
+    let mat = two_qubit_matrix(|b, ra, rb| b.rzz(ra, rb, 0.6))?;
+    let z = Complex::zero();
+    let (p, m) = (
+        Complex::from_polar(&1.0, &-0.3),
+        Complex::from_polar(&1.0, &0.3),
+    );
+    let expected = vec![
+        vec![p, z, z, z],
+        vec![z, m, z, z],
+        vec![z, z, m, z],
+        vec![z, z, z, p],
+    ];
+    assert_matrix_almost_eq(&mat, &expected);
+    Ok(())
+}
+
+#[test]
+fn test_rxx_ryy_change_of_basis() -> Result<(), CircuitError> {
+    // XX and YY are ZZ in the bases of H and of sqrt(X).
+    let theta = 0.9;
+    let mat = two_qubit_matrix(|b, ra, rb| b.rxx(ra, rb, theta))?;
+    let expected = two_qubit_matrix(|b, ra, rb| {
+        let (ra, rb) = (b.hadamard(ra), b.hadamard(rb));
+        let (ra, rb) = b.rzz(ra, rb, theta)?;
+        Ok((b.hadamard(ra), b.hadamard(rb)))
+    })?;
+    assert_matrix_almost_eq(&mat, &expected);
+    let mat = two_qubit_matrix(|b, ra, rb| b.ryy(ra, rb, theta))?;
+    let expected = two_qubit_matrix(|b, ra, rb| {
+        let (ra, rb) = (b.rx(ra, FRAC_PI_2), b.rx(rb, FRAC_PI_2));
+        let (ra, rb) = b.rzz(ra, rb, theta)?;
+        Ok((b.rx(ra, -FRAC_PI_2), b.rx(rb, -FRAC_PI_2)))
+    })?;
+    assert_matrix_almost_eq(&mat, &expected);
+    Ok(())
+}
+
+#[test]
+fn test_ms() -> Result<(), CircuitError> {
+    let mat = two_qubit_matrix(|b, ra, rb| b.ms(ra, rb, 0.35))?;
+    assert_matrix_almost_eq(&mat, &two_qubit_matrix(|b, ra, rb| b.rxx(ra, rb, 0.7))?);
+    // A quarter turn is maximally entangling.
+    let mat = two_qubit_matrix(|b, ra, rb| b.ms(ra, rb, PI / 4.0))?;
+    let h = std::f64::consts::FRAC_1_SQRT_2;
+    assert!((mat[0][0] - Complex::new(h, 0.0)).norm() < 1e-10);
+    assert!((mat[3][0] - Complex::new(0.0, -h)).norm() < 1e-10);
+    Ok(())
+}
+
+#[test]
+fn test_controlled_two_qubit_gates() -> Result<(), CircuitError> {
+    type Matrix = Vec<Vec<Complex<f64>>>;
+    type Gate = dyn Fn(
+        &mut OpBuilder,
+        Register,
+        Register,
+        Register,
+    ) -> Result<(Register, Register, Register), CircuitError>;
+    let gates: Vec<(Box<Gate>, Matrix)> = vec![
+        (
+            Box::new(|b, cr, ra, rb| b.ciswap(cr, ra, rb)),
+            fsim_matrix(-FRAC_PI_2, 0.0),
+        ),
+        (
+            Box::new(|b, cr, ra, rb| b.csqrt_iswap(cr, ra, rb)),
+            fsim_matrix(-PI / 4.0, 0.0),
+        ),
+        (
+            Box::new(|b, cr, ra, rb| b.cfsim(cr, ra, rb, 0.7, -0.2)),
+            fsim_matrix(0.7, -0.2),
+        ),
+        (
+            Box::new(|b, cr, ra, rb| b.cgivens(cr, ra, rb, 1.2)),
+            givens_matrix(1.2),
+        ),
+    ];
+    for (gate, inner) in gates {
+        let mut b = OpBuilder::new();
+        let cr = b.qubit();
+        let ra = b.qubit();
+        let rb = b.qubit();
+        let (cr, ra, rb) = gate(&mut b, cr, ra, rb)?;
+        let r = b.merge(vec![cr, ra, rb])?;
+        let mat = make_circuit_matrix::<f64>(3, &r, false);
+        // The control is the highest bit, the gate acts on the lower two when it is set.
+        let expected: Vec<Vec<Complex<f64>>> = (0..8)
+            .map(|row| {
+                (0..8)
+                    .map(|col| match (row >> 2, col >> 2) {
+                        (1, 1) => inner[row & 0b11][col & 0b11],
+                        (0, 0) if row == col => Complex::one(),
+                        _ => Complex::zero(),
+                    })
+                    .collect()
+            })
+            .collect();
+        assert_matrix_almost_eq(&mat, &expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_two_qubit_gates_need_single_qubits() {
+    let mut b = OpBuilder::new();
+    let ra = b.register(2).unwrap();
+    let rb = b.qubit();
+    assert!(b.iswap(ra, rb).is_err());
+    let ra = b.qubit();
+    let rb = b.register(2).unwrap();
+    assert!(b.fsim(ra, rb, 0.1, 0.2).is_err());
+}
+
+/// Rows of the sparse matrix which shifts `|i>` to `|i + 1 mod 4>`.
+fn shift_sparse() -> Vec<Vec<(u64, Complex<f64>)>> {
+    (0..4u64)
+        .map(|row| vec![((row + 3) % 4, Complex::one())])
+        .collect()
+}
+
+#[test]
+fn test_sparse_mat_two_qubits_not_broadcast() -> Result<(), CircuitError> {
+    let mut b = OpBuilder::new();
+    let r = b.register(2)?;
+    let r = b.sparse_mat("shift", r, shift_sparse(), false)?;
+    let mat = make_circuit_matrix::<f64>(2, &r, false);
+
+    let (o, z) = (Complex::one(), Complex::zero());
+    let dense = (0..4)
+        .flat_map(|row| (0..4).map(move |col| if (col + 1) % 4 == row { o } else { z }))
+        .collect();
+    let mut b = OpBuilder::new();
+    let r = b.register(2)?;
+    let r = b.mat("shift", r, dense)?;
+    assert_matrix_almost_eq(&mat, &make_circuit_matrix::<f64>(2, &r, false));
+
+    // The same holds inside a condition.
+    let mut b = OpBuilder::new();
+    let cr = b.qubit();
+    let r = b.register(2)?;
+    let mut c = b.with_condition(cr);
+    let r = c.sparse_mat("shift", r, shift_sparse(), false)?;
+    assert_eq!(r.n(), 2);
+    Ok(())
+}
+
+#[test]
+fn test_sparse_mat_single_qubit_broadcasts() -> Result<(), CircuitError> {
+    let (o, z) = (Complex::one(), Complex::zero());
+    let x = vec![vec![(1, o)], vec![(0, o)]];
+    let mut b = OpBuilder::new();
+    let r = b.register(2)?;
+    let r = b.sparse_mat("x", r, x, false)?;
+    let mat = make_circuit_matrix::<f64>(2, &r, false);
+    let expected: Vec<Vec<Complex<f64>>> = (0..4)
+        .map(|row| {
+            (0..4)
+                .map(|col| if row + col == 3 { o } else { z })
+                .collect()
+        })
+        .collect();
+    assert_matrix_almost_eq(&mat, &expected);
+    Ok(())
+}
@@ -0,0 +1,100 @@
+extern crate num;
+extern crate qip;
+
+use qip::pipeline::make_circuit_matrix;
+use qip::*;
+use std::f64::consts::{FRAC_1_SQRT_2, PI};
+
+/// Get the 2x2 matrix of `f` applied to a qubit, row major.
+fn single_qubit_matrix<F: Fn(&mut OpBuilder, Register) -> Register>(f: F) -> Vec<Complex<f64>> {
+    let mut b = OpBuilder::new();
+    let q = b.qubit();
+    let q = f(&mut b, q);
+    make_circuit_matrix::<f64>(1, &q, false)
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+/// Check that `a` and `b` are equal up to a global phase.
+fn assert_equal_up_to_phase(a: &[Complex<f64>], b: &[Complex<f64>]) {
+    let (i, _) = a
+        .iter()
+        .enumerate()
+        .max_by(|(_, x), (_, y)| x.norm().partial_cmp(&y.norm()).unwrap())
+        .unwrap();
+    let phase = b[i] / a[i];
+    assert!((phase.norm() - 1.0).abs() < 1e-10, "{:?} != {:?}", a, b);
+    a.iter().zip(b.iter()).for_each(|(x, y)| {
+        assert!((x * phase - y).norm() < 1e-10, "{:?} != {:?}", a, b);
+    });
+}
+
+#[test]
+fn test_u3_is_zyz() {
+    let (theta, phi, lambda) = (0.4, -1.2, 2.5);
+    let u3 = single_qubit_matrix(|b, q| b.u3(q, theta, phi, lambda));
+    let zyz = single_qubit_matrix(|b, q| {
+        let q = b.rz(q, lambda);
+        let q = b.ry(q, theta);
+        b.rz(q, phi)
+    });
+    assert_equal_up_to_phase(&u3, &zyz);
+    let (sin, cos) = (theta / 2.0).sin_cos();
+    assert!((u3[0] - Complex::new(cos, 0.0)).norm() < 1e-10);
+    assert!((u3[2] - Complex::from_polar(&sin, &phi)).norm() < 1e-10);
+}
+
+#[test]
+fn test_u3_angles_round_trip() -> Result<(), CircuitError> {
+    let angles = [
+        (0.3, 0.5, -0.7),
+        (2.9, -3.0, 1.4),
+        (PI / 2.0, PI, -PI / 3.0),
+        (0.0, 0.4, 0.9),
+        (PI, 1.1, -0.6),
+    ];
+    for (theta, phi, lambda) in angles.iter() {
+        let mat = single_qubit_matrix(|b, q| {
+            let q = b.u3(q, *theta, *phi, *lambda);
+            b.phase(q, 0.8)
+        });
+        let (t, p, l) = u3_angles(&mat)?;
+        assert!((0.0..=PI).contains(&t));
+        assert!(p > -PI && p <= PI && l > -PI && l <= PI);
+        let rebuilt = single_qubit_matrix(|b, q| b.u3(q, t, p, l));
+        assert_equal_up_to_phase(&mat, &rebuilt);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_u3_angles_of_standard_gates() -> Result<(), CircuitError> {
+    let gates: Vec<Vec<Complex<f64>>> = vec![
+        single_qubit_matrix(|b, q| b.x(q)),
+        single_qubit_matrix(|b, q| b.y(q)),
+        single_qubit_matrix(|b, q| b.z(q)),
+        single_qubit_matrix(|b, q| b.hadamard(q)),
+        single_qubit_matrix(|b, q| b.t(q)),
+        single_qubit_matrix(|b, q| b.rx(q, 0.9)),
+    ];
+    for mat in gates {
+        let (theta, phi, lambda) = u3_angles(&mat)?;
+        let rebuilt = single_qubit_matrix(|b, q| b.u3(q, theta, phi, lambda));
+        assert_equal_up_to_phase(&mat, &rebuilt);
+    }
+    // The T gate is diagonal, so only lambda is used.
+    let (theta, phi, lambda) = u3_angles(&single_qubit_matrix(|b, q| b.t(q)))?;
+    assert!(theta.abs() < 1e-10 && phi.abs() < 1e-10);
+    assert!((lambda - PI / 4.0).abs() < 1e-10);
+    Ok(())
+}
+
+#[test]
+fn test_u3_angles_errors() {
+    let c = |re: f64| Complex::new(re, 0.0);
+    assert!(u3_angles(&[c(1.0), c(0.0), c(0.0)]).is_err());
+    assert!(u3_angles(&[c(1.0), c(1.0), c(0.0), c(1.0)]).is_err());
+    let h = FRAC_1_SQRT_2;
+    assert!(u3_angles(&[c(h), c(h), c(h), c(h)]).is_err());
+}